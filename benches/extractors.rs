@@ -0,0 +1,42 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, MaybeAuthenticated};
+use actix_web_openidconnect::test_util::{authenticate_request, AuthenticatedUserBuilder};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[get("/orders")]
+async fn orders(user: Authenticated, maybe: MaybeAuthenticated) -> HttpResponse {
+    drop(maybe);
+    HttpResponse::Ok().body(user.access.subject().to_string())
+}
+
+/// A user with about 50KB of claims, like a member of hundreds of groups.
+///
+/// Only standard claims are kept, the bulk is in localized names.
+fn large_user() -> AuthenticatedUserBuilder {
+    (0..1000).fold(AuthenticatedUserBuilder::new("alice"), |user, i| {
+        user.claim(
+            format!("name#x-{:04}", i),
+            format!("Alice, member of group {:04} of the organization", i),
+        )
+    })
+}
+
+fn extractors(c: &mut Criterion) {
+    let rt = actix_web::rt::System::new();
+    let app = rt.block_on(test::init_service(App::new().service(orders)));
+    let user = large_user().build();
+    assert!(serde_json::to_vec(&user.access).unwrap().len() > 50_000);
+
+    c.bench_function("two extractors with 50KB of claims", |b| {
+        b.iter(|| {
+            let req = authenticate_request(
+                test::TestRequest::get().uri("/orders").to_request(),
+                user.clone(),
+            );
+            rt.block_on(test::call_service(&app, req))
+        })
+    });
+}
+
+criterion_group!(benches, extractors);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+use actix_web::cookie::Cookie;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[get("/is_auth/hello")]
+async fn is_auth() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[get("/no_auth/hello")]
+async fn no_auth() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+/// Requests through the middleware, the userinfo call goes to a [`MockIdp`] on loopback.
+fn middleware(c: &mut Criterion) {
+    let rt = actix_web::rt::System::new();
+    let idp = MockIdp::start();
+    let (app, access_token) = rt.block_on(async {
+        let openid = ActixWebOpenId::builder()
+            .client_id("client")
+            .client_secret("secret")
+            .redirect_url("http://localhost/auth_callback")
+            .issuer_url(idp.issuer_url())
+            .should_auth(|req| req.path().starts_with("/is_auth"))
+            .build()
+            .await
+            .unwrap();
+        let app = test::init_service(
+            App::new()
+                .wrap(openid.get_middleware())
+                .configure(openid.configure_open_id())
+                .service(is_auth)
+                .service(no_auth),
+        )
+        .await;
+        let mut driver = FlowDriver::new(&app, &idp);
+        driver.get("/is_auth/hello").follow_login().await;
+        let access_token = driver.cookie("access_token").unwrap();
+        (app, access_token)
+    });
+
+    c.bench_function("authenticated request", |b| {
+        b.iter(|| {
+            rt.block_on(test::call_service(
+                &app,
+                test::TestRequest::get()
+                    .uri("/is_auth/hello")
+                    .cookie(Cookie::new("access_token", access_token.clone()))
+                    .to_request(),
+            ))
+        })
+    });
+    c.bench_function("anonymous request to a public path", |b| {
+        b.iter(|| {
+            rt.block_on(test::call_service(
+                &app,
+                test::TestRequest::get().uri("/no_auth/hello").to_request(),
+            ))
+        })
+    });
+}
+
+criterion_group!(benches, middleware);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+//! A CLI logging in with the device authorization grant, against the mock provider:
+//!
+//! ```sh
+//! cargo run --example device_flow --features test-util
+//! ```
+
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use openidconnect::{EmptyAdditionalClaims, Scope};
+
+#[actix_web::main]
+async fn main() -> actix_web_openidconnect::Result<()> {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    let openid = ActixWebOpenId::builder()
+        .client_id("cli")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await?;
+    let client = openid.openid_client();
+
+    let authorization = client
+        .start_device_authorization(&[Scope::new("profile".to_string())])
+        .await?;
+    println!(
+        "Open {} and enter the code {}",
+        authorization.verification_uri.as_str(),
+        authorization.user_code.secret()
+    );
+    let tokens = client
+        .poll_device_token(&authorization.device_code, authorization.interval)
+        .await?;
+
+    let user_info = client
+        .user_info::<EmptyAdditionalClaims>(tokens.access_token)
+        .await?;
+    println!("Logged in as {}", user_info.subject().as_str());
+    Ok(())
+}
@@ -0,0 +1,51 @@
+//! API keys authenticating machine clients that cannot log in with the provider.
+
+use actix_web::dev::ServiceRequest;
+use actix_web::error::ErrorUnauthorized;
+use actix_web::Error;
+
+use crate::logging::LogCategory;
+use crate::messages::MessageKey;
+use crate::openid::OpenID;
+use crate::openid_middleware::AuthenticatedUser;
+
+/// Checks the API keys of requests without a session.
+///
+/// ```ignore
+/// struct Partners(HashMap<String, String>);
+///
+/// #[async_trait::async_trait]
+/// impl ApiKeyValidator for Partners {
+///     async fn validate(&self, key: &str) -> Option<AuthenticatedUser> {
+///         let partner = self.0.get(key)?;
+///         Some(AuthenticatedUser::synthetic(format!("apikey:{}", partner)))
+///     }
+/// }
+///
+/// App::new().wrap(openid.get_middleware().api_key_validator(Partners(keys)))
+/// ```
+#[async_trait::async_trait]
+pub trait ApiKeyValidator: Send + Sync {
+    /// The user the request acts as, `None` if the key is unknown.
+    async fn validate(&self, key: &str) -> Option<AuthenticatedUser>;
+}
+
+/// The user of the request's API key.
+///
+/// A request sending a key is an API call, a rejected key answers `401 Unauthorized` rather than
+/// redirecting to the provider.
+pub(crate) async fn validate_api_key(
+    validator: &dyn ApiKeyValidator,
+    client: &OpenID,
+    req: &ServiceRequest,
+    key: &str,
+) -> Result<AuthenticatedUser, Error> {
+    validator.validate(key).await.ok_or_else(|| {
+        // The key itself is a credential, it is never logged.
+        client.log_policy().log(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("Rejected the API key sent to {}", req.path()),
+        );
+        ErrorUnauthorized(client.message(MessageKey::Unauthorized, &[]))
+    })
+}
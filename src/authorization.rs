@@ -0,0 +1,256 @@
+//! Policies deciding which authenticated users may use a route, checked by the
+//! [`RequireClaims`] middleware or the [`Authorized`] extractor.
+
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::ErrorForbidden;
+use actix_web::{Error, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use openidconnect::{AdditionalClaims, EmptyAdditionalClaims};
+use serde_json::Value;
+
+use crate::logging::LogCategory;
+use crate::messages::MessageKey;
+use crate::openid::OtherClaims;
+use crate::openid_middleware::{request_user, Authenticated, AuthenticatedUser, RegisteredClient};
+
+/// Decides whether an authenticated user may use a route.
+///
+/// Closures `Fn(&AuthenticatedUser<OtherClaims>) -> bool` are policies answering
+/// `403 Forbidden` when they return `false`, [`HasScope`] and [`HasRole`] are built in.
+pub trait Policy: Send + Sync + 'static {
+    /// `Ok` if `user` may continue, otherwise the error answered, usually [`forbidden`].
+    fn check(&self, req: &HttpRequest, user: &AuthenticatedUser<OtherClaims>) -> Result<(), Error>;
+}
+
+impl<F> Policy for F
+where
+    F: Fn(&AuthenticatedUser<OtherClaims>) -> bool + Send + Sync + 'static,
+{
+    fn check(&self, req: &HttpRequest, user: &AuthenticatedUser<OtherClaims>) -> Result<(), Error> {
+        if self(user) {
+            Ok(())
+        } else {
+            Err(forbidden(req))
+        }
+    }
+}
+
+/// `403 Forbidden`, with the request client's [`MessageKey::Forbidden`] text.
+pub fn forbidden(req: &HttpRequest) -> Error {
+    if let Some(client) = RegisteredClient::find(req) {
+        client.log_policy().log(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("The user is not allowed to access {}", req.path()),
+        );
+    }
+    ErrorForbidden(RegisteredClient::message_for(req, MessageKey::Forbidden))
+}
+
+/// Users granted `scope`, listed in their space-separated `scope` claim or their `scp` claim.
+///
+/// Access tokens carry the scopes, so users need them among their claims, e.g. with
+/// [`ValidationMode::Local`](crate::ValidationMode::Local) or a userinfo endpoint echoing them.
+#[derive(Clone, Debug)]
+pub struct HasScope(String);
+
+impl HasScope {
+    pub fn new(scope: impl Into<String>) -> Self {
+        HasScope(scope.into())
+    }
+}
+
+impl Policy for HasScope {
+    fn check(&self, req: &HttpRequest, user: &AuthenticatedUser<OtherClaims>) -> Result<(), Error> {
+        let claims = user.access.additional_claims();
+        let mut scopes = claims.get("scope").into_iter().chain(claims.get("scp"));
+        let granted = scopes.any(|scopes| match scopes {
+            Value::String(scopes) => scopes.split(' ').any(|scope| scope == self.0),
+            Value::Array(scopes) => scopes.iter().any(|scope| scope.as_str() == Some(&self.0)),
+            _ => false,
+        });
+        if granted {
+            Ok(())
+        } else {
+            Err(forbidden(req))
+        }
+    }
+}
+
+/// Users with `role` in the array at the client's `roles_claim`, or at the
+/// [`claim`](Self::claim) path.
+#[derive(Clone, Debug)]
+pub struct HasRole {
+    role: String,
+    claim: Option<String>,
+}
+
+impl HasRole {
+    pub fn new(role: impl Into<String>) -> Self {
+        HasRole {
+            role: role.into(),
+            claim: None,
+        }
+    }
+
+    /// Reads the roles at `claim` instead, names separated by dots, e.g. `realm_access.roles`.
+    pub fn claim(mut self, claim: impl Into<String>) -> Self {
+        self.claim = Some(claim.into());
+        self
+    }
+}
+
+impl Policy for HasRole {
+    fn check(&self, req: &HttpRequest, user: &AuthenticatedUser<OtherClaims>) -> Result<(), Error> {
+        let client = RegisteredClient::find(req);
+        let claim = self
+            .claim
+            .as_deref()
+            .or_else(|| client.as_ref()?.roles_claim());
+        let roles = claim.and_then(|claim| roles_at(user, claim));
+        if roles.is_some_and(|roles| roles.contains(&self.role)) {
+            Ok(())
+        } else {
+            Err(forbidden(req))
+        }
+    }
+}
+
+/// The strings in the array at `claim`, names separated by dots, `None` if there is none.
+pub(crate) fn roles_at(user: &AuthenticatedUser<OtherClaims>, claim: &str) -> Option<Vec<String>> {
+    roles_in(&serde_json::to_value(&user.access).ok()?, claim)
+}
+
+/// The strings in the array at `claim` of `claims`, see [`roles_at`].
+pub(crate) fn roles_in(claims: &Value, claim: &str) -> Option<Vec<String>> {
+    let roles = claim
+        .split('.')
+        .try_fold(claims, |claims, name| claims.get(name))?;
+    Some(
+        roles
+            .as_array()?
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Checks `policy` for the routes it wraps, inside the authentication middleware, e.g.
+/// `web::scope("/admin").wrap(RequireClaims::new(HasRole::new("admin")))` or
+/// `#[get("/orders", wrap = "RequireClaims::new(HasScope::new(\"orders:read\"))")]`.
+///
+/// Users failing the policy are answered with its error, `403 Forbidden` for the built-in
+/// ones, anonymous requests like [`Authenticated`] would be.
+pub struct RequireClaims<P: Policy> {
+    policy: Arc<P>,
+}
+
+impl<P: Policy> RequireClaims<P> {
+    pub fn new(policy: P) -> Self {
+        RequireClaims {
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+impl<S, B, P> Transform<S, ServiceRequest> for RequireClaims<P>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    P: Policy,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequireClaimsMiddleware<S, P>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireClaimsMiddleware {
+            service: Rc::new(service),
+            policy: self.policy.clone(),
+        }))
+    }
+}
+
+pub struct RequireClaimsMiddleware<S, P> {
+    service: Rc<S>,
+    policy: Arc<P>,
+}
+
+impl<S, B, P> Service<ServiceRequest> for RequireClaimsMiddleware<S, P>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    P: Policy,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let checked =
+            request_user(req.request()).and_then(|user| self.policy.check(req.request(), &user));
+        let srv = self.service.clone();
+        Box::pin(async move {
+            checked?;
+            srv.call(req).await
+        })
+    }
+}
+
+/// The logged in user once they passed `P`, with their claims read as `AC` like
+/// [`Authenticated`].
+///
+/// `P` is made with its `Default`, for policies of the application:
+///
+/// ```ignore
+/// #[derive(Default)]
+/// struct Auditors;
+///
+/// impl Policy for Auditors {
+///     fn check(&self, req: &HttpRequest, user: &AuthenticatedUser<OtherClaims>) -> Result<(), Error> {
+///         HasRole::new("auditor").check(req, user)
+///     }
+/// }
+///
+/// #[get("/audit")]
+/// async fn audit(user: Authorized<Auditors>) -> impl Responder { /* ... */ }
+/// ```
+pub struct Authorized<P: Policy + Default, AC: AdditionalClaims = EmptyAdditionalClaims>(
+    Authenticated<AC>,
+    PhantomData<fn() -> P>,
+);
+
+impl<P: Policy + Default, AC: AdditionalClaims> Authorized<P, AC> {
+    pub fn into_inner(self) -> Authenticated<AC> {
+        self.0
+    }
+}
+
+impl<P: Policy + Default, AC: AdditionalClaims> FromRequest for Authorized<P, AC> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ready(
+            request_user(req)
+                .and_then(|user| P::default().check(req, &user))
+                .and_then(|()| Authenticated::<AC>::from_request(req, payload).into_inner())
+                .map(|user| Authorized(user, PhantomData)),
+        )
+    }
+}
+
+impl<P: Policy + Default, AC: AdditionalClaims> std::ops::Deref for Authorized<P, AC> {
+    type Target = AuthenticatedUser<AC>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
@@ -0,0 +1,71 @@
+//! `awc` client forwarding the current user's access token, behind the `awc` feature.
+
+use actix_web::{web, Error, FromRequest, HttpRequest};
+use awc::error::HttpError;
+use awc::http::{Method, Uri};
+use awc::{Client, ClientRequest};
+use futures_util::future::LocalBoxFuture;
+use openidconnect::AccessToken;
+
+use crate::session_token::SessionToken;
+
+/// An `awc::Client` sending `Authorization: Bearer <token>` for the current session.
+///
+/// Extracted like [`SessionToken`], whose token it sends. The client registered as
+/// `web::Data<awc::Client>` is used if there is one.
+pub struct UserClient {
+    client: Client,
+    access_token: AccessToken,
+}
+
+impl UserClient {
+    pub fn access_token(&self) -> &AccessToken {
+        &self.access_token
+    }
+
+    pub fn request<U>(&self, method: Method, url: U) -> ClientRequest
+    where
+        Uri: TryFrom<U>,
+        <Uri as TryFrom<U>>::Error: Into<HttpError>,
+    {
+        self.client
+            .request(method, url)
+            .bearer_auth(self.access_token.secret())
+    }
+
+    pub fn get<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: TryFrom<U>,
+        <Uri as TryFrom<U>>::Error: Into<HttpError>,
+    {
+        self.request(Method::GET, url)
+    }
+
+    pub fn post<U>(&self, url: U) -> ClientRequest
+    where
+        Uri: TryFrom<U>,
+        <Uri as TryFrom<U>>::Error: Into<HttpError>,
+    {
+        self.request(Method::POST, url)
+    }
+}
+
+impl FromRequest for UserClient {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let access_token = SessionToken::extract(&req).await?.access_token().await;
+            let client = req
+                .app_data::<web::Data<Client>>()
+                .map(|client| client.get_ref().clone())
+                .unwrap_or_default();
+            Ok(UserClient {
+                client,
+                access_token,
+            })
+        })
+    }
+}
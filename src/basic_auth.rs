@@ -0,0 +1,98 @@
+//! HTTP Basic authentication for tooling that cannot log in with the provider, disabled unless
+//! enabled on the middleware.
+
+use std::sync::Arc;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::Error;
+
+use crate::credentials::{idp_unavailable, rejected};
+use crate::error::ErrorAction;
+use crate::openid::{OpenID, OtherClaims};
+use crate::openid_middleware::{AuthenticatedUser, UserTokens};
+
+/// Checks the credentials of Basic authenticated requests.
+#[async_trait::async_trait]
+pub trait BasicAuthValidator: Send + Sync {
+    /// The user of the credentials, `None` if they are wrong.
+    async fn validate(&self, username: &str, password: &str) -> Option<AuthenticatedUser>;
+}
+
+/// How Basic credentials are checked.
+#[derive(Clone)]
+pub enum BasicAuth {
+    /// Exchanged for tokens with the provider's resource owner password grant, which must be
+    /// enabled for the client. The user is then fetched with the access token, as for a session.
+    ///
+    /// Every request exchanges the credentials again.
+    PasswordGrant,
+    Validator(Arc<dyn BasicAuthValidator>),
+}
+
+impl BasicAuth {
+    pub fn validator(validator: impl BasicAuthValidator + 'static) -> Self {
+        BasicAuth::Validator(Arc::new(validator))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct BasicAuthConfig {
+    pub(crate) paths: fn(&ServiceRequest) -> bool,
+    pub(crate) method: BasicAuth,
+}
+
+impl BasicAuthConfig {
+    /// The user of the credentials.
+    ///
+    /// Failures answer `401 Unauthorized` with a Basic challenge, never a redirect to the
+    /// provider. The credentials are never logged.
+    pub(crate) async fn validate(
+        &self,
+        client: &OpenID,
+        username: &str,
+        password: &str,
+    ) -> Result<AuthenticatedUser<OtherClaims>, Error> {
+        match &self.method {
+            BasicAuth::Validator(validator) => validator
+                .validate(username, password)
+                .await
+                .map(AuthenticatedUser::from)
+                .ok_or_else(|| challenge(client, "wrong Basic credentials")),
+            BasicAuth::PasswordGrant => password_grant(client, username, password).await,
+        }
+    }
+}
+
+async fn password_grant(
+    client: &OpenID,
+    username: &str,
+    password: &str,
+) -> Result<AuthenticatedUser<OtherClaims>, Error> {
+    let user = match client.password(username, password).await {
+        Ok(tokens) => {
+            let expires_at = tokens
+                .expires_in
+                .map(|expires_in| client.now() + expires_in);
+            client
+                .user_claims(tokens.access_token.clone(), expires_at)
+                .await
+                .map(|user_info| {
+                    AuthenticatedUser::new(user_info)
+                        .with_tokens(UserTokens::new(tokens.access_token, expires_at))
+                })
+        }
+        Err(err) => Err(err),
+    };
+    user.map_err(|err| match client.error_action(&err) {
+        ErrorAction::RetryLater(retry_after) => idp_unavailable(client, err, retry_after),
+        _ => challenge(client, &format!("the provider rejected them: {}", err)),
+    })
+}
+
+fn challenge(client: &OpenID, reason: &str) -> Error {
+    rejected(
+        client,
+        &format!("Basic realm=\"{}\", charset=\"UTF-8\"", client.realm().id()),
+        &format!("Rejected Basic credentials, {}", reason),
+    )
+}
@@ -0,0 +1,61 @@
+//! `reqwest-middleware` middleware forwarding the current user's access token, behind the
+//! `reqwest-middleware` feature.
+
+use openidconnect::AccessToken;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Request, Response, StatusCode};
+use reqwest_middleware::{Error, Middleware, Next, Result};
+use task_local_extensions::Extensions;
+
+use crate::session_token::SessionToken;
+
+/// Sends `Authorization: Bearer <token>` for the [`SessionToken`] attached to the request.
+///
+/// Requests are attached the handle extracted in the handler with `with_extension`, those
+/// without one are sent unchanged. A `401` from the upstream is retried once with a refreshed
+/// token, unless the request body cannot be cloned.
+///
+/// ```ignore
+/// let client = ClientBuilder::new(reqwest::Client::new())
+///     .with(OidcBearerMiddleware)
+///     .build();
+/// client.get(url).with_extension(session_token.clone()).send().await?;
+/// ```
+pub struct OidcBearerMiddleware;
+
+#[async_trait::async_trait]
+impl Middleware for OidcBearerMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let Some(session_token) = extensions.get::<SessionToken>().cloned() else {
+            return next.run(req, extensions).await;
+        };
+        let access_token = session_token.access_token().await;
+        set_bearer(&mut req, &access_token)?;
+        let retry = req.try_clone();
+        let response = next.clone().run(req, extensions).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+        let Some(mut retry) = retry else {
+            return Ok(response);
+        };
+        let Some(access_token) = session_token.refresh_rejected(&access_token).await else {
+            return Ok(response);
+        };
+        set_bearer(&mut retry, &access_token)?;
+        next.run(retry, extensions).await
+    }
+}
+
+fn set_bearer(req: &mut Request, access_token: &AccessToken) -> Result<()> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", access_token.secret()))
+        .map_err(Error::middleware)?;
+    value.set_sensitive(true);
+    req.headers_mut().insert(AUTHORIZATION, value);
+    Ok(())
+}
@@ -0,0 +1,709 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::cookie::Key;
+use actix_web::dev::ServiceRequest;
+use actix_web::HttpResponse;
+use secrecy::SecretString;
+
+use crate::circuit_breaker::BreakerConfig;
+use crate::claims_request::ClaimsRequest;
+use crate::clock::{Clock, SystemClock};
+use crate::cookie_config::{CookieConfig, UserInfoCookie};
+use crate::error::{ErrorAction, OpenIdError, Result};
+use crate::events::{AuthEvents, NoEvents};
+#[cfg(feature = "first-party-session")]
+use crate::first_party::SessionFormat;
+use crate::forwarded::TrustedProxies;
+use crate::health::HealthThresholds;
+use crate::http_client::{PoolConfig, RetryPolicy};
+use crate::id_token::IdTokenValidation;
+use crate::logging::{LogCategory, LogPolicy};
+use crate::login_hook::LoginHook;
+use crate::messages::{EnglishMessages, Messages};
+use crate::not_before::NotBeforePolicy;
+use crate::openid::{
+    DiscoveryPolicy, IssuerValidation, OpenID, OsRandom, RandomSource, TokenHashValidation,
+    ValidationMode,
+};
+use crate::pages::{CallbackErrorHandling, DefaultPages, LoginRedirect, PageRenderer};
+use crate::payload::PayloadCodec;
+use crate::realm::Realm;
+use crate::session_limits::SessionLimits;
+use crate::session_store::SessionStore;
+use crate::should_auth::ShouldAuth;
+use crate::ActixWebOpenId;
+
+/// Configures and discovers an OpenID provider, see [`ActixWebOpenId::builder`].
+pub struct OpenIdBuilder {
+    pub(crate) client_id: Option<String>,
+    pub(crate) client_secret: Option<SecretString>,
+    pub(crate) redirect_url: Option<String>,
+    pub(crate) issuer_url: Option<String>,
+    pub(crate) discovery_url: Option<String>,
+    pub(crate) should_auth: ShouldAuth,
+    pub(crate) post_logout_redirect_url: Option<String>,
+    pub(crate) revoke_access_token: bool,
+    pub(crate) scopes: Vec<String>,
+    pub(crate) client_token_scopes: Vec<String>,
+    pub(crate) extra_auth_params: Vec<(String, String)>,
+    pub(crate) extra_token_params: Vec<(String, String)>,
+    pub(crate) form_post: bool,
+    pub(crate) claims_request: ClaimsRequest,
+    pub(crate) issuer_validation: IssuerValidation,
+    pub(crate) validation_mode: ValidationMode,
+    pub(crate) require_signed_userinfo: bool,
+    pub(crate) token_hash_validation: TokenHashValidation,
+    pub(crate) id_token_validation: IdTokenValidation,
+    pub(crate) userinfo_cache: Option<(Duration, usize)>,
+    pub(crate) negative_cache: Option<(Duration, usize)>,
+    pub(crate) userinfo_concurrency: Option<usize>,
+    pub(crate) roles_claim: Option<String>,
+    pub(crate) random: Arc<dyn RandomSource>,
+    pub(crate) strict: bool,
+    pub(crate) error_action: fn(&OpenIdError) -> ErrorAction,
+    pub(crate) callback_errors: CallbackErrorHandling,
+    pub(crate) login_redirect: Option<fn(&LoginRedirect<'_>) -> HttpResponse>,
+    pub(crate) log_policy: LogPolicy,
+    pub(crate) http_client: Option<reqwest::Client>,
+    pub(crate) pool: PoolConfig,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) discovery_policy: DiscoveryPolicy,
+    pub(crate) realm: Realm,
+    pub(crate) namespace_cookies: bool,
+    pub(crate) messages: Arc<dyn Messages>,
+    pub(crate) events: Arc<dyn AuthEvents>,
+    pub(crate) login_hook: Option<Arc<dyn LoginHook>>,
+    pub(crate) trusted_proxies: TrustedProxies,
+    pub(crate) pages: Arc<dyn PageRenderer>,
+    pub(crate) breaker: BreakerConfig,
+    pub(crate) not_before_policy: Option<Arc<dyn NotBeforePolicy>>,
+    pub(crate) keycloak_push_not_before: bool,
+    pub(crate) health_thresholds: HealthThresholds,
+    pub(crate) max_provider_staleness: Option<Duration>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) payload_codec: PayloadCodec,
+    /// `None` until set, PKCE is then only used by public clients.
+    pub(crate) pkce: Option<bool>,
+    pub(crate) login_window: Duration,
+    pub(crate) cookie_key: Option<Key>,
+    pub(crate) cookie_config: CookieConfig,
+    pub(crate) user_info_cookie: UserInfoCookie,
+    pub(crate) session_store: Option<Arc<dyn SessionStore>>,
+    pub(crate) session_limits: SessionLimits,
+    #[cfg(feature = "first-party-session")]
+    pub(crate) session_format: SessionFormat,
+    pub(crate) front_channel_logout: Option<String>,
+    pub(crate) back_channel_logout: Option<String>,
+    pub(crate) callback_path: String,
+    pub(crate) logout_path: String,
+}
+
+/// The settings given so far, the secret redacted.
+impl fmt::Debug for OpenIdBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenIdBuilder")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &self.client_secret)
+            .field("redirect_url", &self.redirect_url)
+            .field("issuer_url", &self.issuer_url)
+            .field("discovery_url", &self.discovery_url)
+            .field("post_logout_redirect_url", &self.post_logout_redirect_url)
+            .field("scopes", &self.scopes)
+            .field("realm", &self.realm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for OpenIdBuilder {
+    fn default() -> Self {
+        OpenIdBuilder {
+            client_id: None,
+            client_secret: None,
+            redirect_url: None,
+            issuer_url: None,
+            discovery_url: None,
+            should_auth: ShouldAuth::new(ShouldAuth::always()),
+            post_logout_redirect_url: None,
+            revoke_access_token: false,
+            scopes: Vec::new(),
+            client_token_scopes: Vec::new(),
+            extra_auth_params: Vec::new(),
+            extra_token_params: Vec::new(),
+            form_post: false,
+            claims_request: ClaimsRequest::default(),
+            issuer_validation: IssuerValidation::Exact,
+            validation_mode: ValidationMode::default(),
+            require_signed_userinfo: false,
+            token_hash_validation: TokenHashValidation::default(),
+            id_token_validation: IdTokenValidation::default(),
+            userinfo_cache: None,
+            negative_cache: None,
+            userinfo_concurrency: None,
+            roles_claim: None,
+            random: Arc::new(OsRandom),
+            strict: false,
+            error_action: OpenIdError::default_action,
+            callback_errors: CallbackErrorHandling::default(),
+            login_redirect: None,
+            log_policy: LogPolicy::default(),
+            http_client: None,
+            pool: PoolConfig::default(),
+            retry_policy: RetryPolicy::default(),
+            discovery_policy: DiscoveryPolicy::default(),
+            realm: Realm::default(),
+            namespace_cookies: false,
+            messages: Arc::new(EnglishMessages),
+            events: Arc::new(NoEvents),
+            login_hook: None,
+            trusted_proxies: TrustedProxies::None,
+            pages: Arc::new(DefaultPages),
+            breaker: BreakerConfig::default(),
+            not_before_policy: None,
+            keycloak_push_not_before: false,
+            health_thresholds: HealthThresholds::default(),
+            max_provider_staleness: None,
+            clock: Arc::new(SystemClock),
+            payload_codec: PayloadCodec::default(),
+            pkce: None,
+            login_window: Duration::from_secs(600),
+            cookie_key: None,
+            cookie_config: CookieConfig::default(),
+            user_info_cookie: UserInfoCookie::default(),
+            session_store: None,
+            session_limits: SessionLimits::default(),
+            #[cfg(feature = "first-party-session")]
+            session_format: SessionFormat::default(),
+            front_channel_logout: None,
+            back_channel_logout: None,
+            callback_path: "/auth_callback".to_string(),
+            logout_path: "/logout".to_string(),
+        }
+    }
+}
+
+impl OpenIdBuilder {
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    /// Left out for public clients, e.g. browser or native apps, whose token requests then only
+    /// name the client and prove the code with [`pkce`](Self::pkce).
+    pub fn client_secret(mut self, client_secret: impl Into<String>) -> Self {
+        self.client_secret = Some(SecretString::new(client_secret.into()));
+        self
+    }
+
+    /// Where the provider sends the user back, it must point to the
+    /// [callback](Self::callback_path). Given as a bare origin, e.g. `https://example.com`, the
+    /// callback path of the realm is appended.
+    pub fn redirect_url(mut self, redirect_url: impl Into<String>) -> Self {
+        self.redirect_url = Some(redirect_url.into());
+        self
+    }
+
+    /// Peers whose `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers name the scheme
+    /// and host the browser reached the app at, e.g. a TLS-terminating reverse proxy. Logins
+    /// through them return to that host instead of the one of the [redirect url](Self::redirect_url),
+    /// which must be registered at the provider as well. Defaults to [`TrustedProxies::None`].
+    pub fn trusted_proxies(mut self, trusted_proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    pub fn issuer_url(mut self, issuer_url: impl Into<String>) -> Self {
+        self.issuer_url = Some(issuer_url.into());
+        self
+    }
+
+    /// The url of the provider's discovery document, for providers not serving it at the issuer
+    /// url followed by `/.well-known/openid-configuration`, e.g. with the `p` parameter of an
+    /// Azure AD B2C policy.
+    pub fn discovery_url(mut self, discovery_url: impl Into<String>) -> Self {
+        self.discovery_url = Some(discovery_url.into());
+        self
+    }
+
+    /// Decides which requests require authentication, see [`ShouldAuth`]. Defaults to all of them.
+    pub fn should_auth(
+        mut self,
+        should_auth: impl Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.should_auth = ShouldAuth::new(should_auth);
+        self
+    }
+
+    pub fn post_logout_redirect_url(mut self, post_logout_redirect_url: impl Into<String>) -> Self {
+        self.post_logout_redirect_url = Some(post_logout_redirect_url.into());
+        self
+    }
+
+    /// Also revokes the session's access token at logout, not only its refresh token. Access
+    /// tokens outlive the revocation at providers that do not look them up, e.g. JWTs. Off by
+    /// default.
+    pub fn revoke_access_token_on_logout(mut self, enabled: bool) -> Self {
+        self.revoke_access_token = enabled;
+        self
+    }
+
+    /// Scopes requested during the authentication, in addition to `openid`.
+    pub fn scopes<S: Into<String>>(mut self, scopes: impl IntoIterator<Item = S>) -> Self {
+        self.scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Scopes of the tokens [`ClientToken`](crate::ClientToken) extracts, for the client
+    /// itself. None by default, the provider's defaults for the client.
+    pub fn client_token_scopes<S: Into<String>>(
+        mut self,
+        scopes: impl IntoIterator<Item = S>,
+    ) -> Self {
+        self.client_token_scopes = scopes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Adds a parameter to the authorization url, e.g. `audience` or `prompt`.
+    pub fn extra_auth_param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_auth_params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds a parameter to the query of the token endpoint, e.g. the `p` of an Azure AD B2C
+    /// policy, replacing one of the same name the provider advertises. Sent with every token
+    /// request, from logins and refreshes to token exchanges.
+    pub fn extra_token_param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_token_params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Asks the provider to send the code to the callback in a form it POSTs, with
+    /// `response_mode=form_post`, rather than in the query of a redirect, keeping it out of
+    /// access logs and browser histories. The cookies of the login are then `SameSite=None` and
+    /// `Secure`, for the browser to send them with the provider's cross-site POST.
+    pub fn response_mode_form_post(mut self, form_post: bool) -> Self {
+        self.form_post = form_post;
+        self
+    }
+
+    /// Claims asked for with the `claims` parameter of the authorization url, see
+    /// [`ClaimsRequest`]. Middlewares can override it.
+    pub fn claims_request(mut self, claims_request: ClaimsRequest) -> Self {
+        self.claims_request = claims_request;
+        self
+    }
+
+    /// How the `iss` claim of ID tokens is checked. Defaults to [`IssuerValidation::Exact`].
+    pub fn issuer_validation(mut self, issuer_validation: IssuerValidation) -> Self {
+        self.issuer_validation = issuer_validation;
+        self
+    }
+
+    /// How access tokens are checked, see [`ValidationMode`]. Defaults to asking the userinfo
+    /// endpoint.
+    pub fn validation_mode(mut self, validation_mode: ValidationMode) -> Self {
+        self.validation_mode = validation_mode;
+        self
+    }
+
+    /// Asks the userinfo endpoint for JWTs signed by the provider, and refuses its plain JSON
+    /// responses. Signed responses are verified either way, for providers configured with
+    /// `userinfo_signed_response_alg`.
+    pub fn require_signed_userinfo(mut self, required: bool) -> Self {
+        self.require_signed_userinfo = required;
+        self
+    }
+
+    /// Whether ID tokens issued at login must carry an `at_hash` binding them to the access
+    /// token, see [`TokenHashValidation`]. Defaults to checking it when present.
+    pub fn token_hash_validation(mut self, token_hash_validation: TokenHashValidation) -> Self {
+        self.token_hash_validation = token_hash_validation;
+        self
+    }
+
+    /// The algorithms, audiences, clock skew and `auth_time` ID tokens are checked for, see
+    /// [`IdTokenValidation`]. Defaults to RS256 tokens for the client alone, with 60 seconds of
+    /// clock skew.
+    pub fn id_token_validation(mut self, id_token_validation: IdTokenValidation) -> Self {
+        self.id_token_validation = id_token_validation;
+        self
+    }
+
+    /// Reuses the userinfo response, or the introspection response with
+    /// [`ValidationMode::Introspection`], for an access token for `ttl`, or until the token expires
+    /// if sooner, keeping at most `max_entries` of them, e.g. `(Duration::from_secs(60), 10_000)`.
+    /// Revoked tokens are only noticed once their entry expired. Off by default.
+    pub fn userinfo_cache(mut self, ttl: Duration, max_entries: usize) -> Self {
+        self.userinfo_cache = Some((ttl, max_entries));
+        self
+    }
+
+    /// Remembers the access tokens the userinfo or introspection endpoint rejected for `ttl`,
+    /// keeping at most `max_entries` of them (by SHA-256 of the token), e.g.
+    /// `(Duration::from_secs(30), 10_000)`. A client replaying a rejected token is then turned
+    /// away without asking the provider again. Off by default.
+    pub fn negative_cache(mut self, ttl: Duration, max_entries: usize) -> Self {
+        self.negative_cache = Some((ttl, max_entries));
+        self
+    }
+
+    /// Sends at most `max_calls` userinfo or introspection requests at once, the others wait
+    /// for their turn. Requests validating the same access token wait for the one asking the
+    /// provider about it, and reuse its answer when the [`userinfo_cache`](Self::userinfo_cache)
+    /// or the [`negative_cache`](Self::negative_cache) kept it. Unlimited by default.
+    pub fn userinfo_concurrency(mut self, max_calls: usize) -> Self {
+        self.userinfo_concurrency = Some(max_calls);
+        self
+    }
+
+    /// Dotted path of the claim holding the user's roles, e.g. `realm_access.roles`.
+    pub fn roles_claim(mut self, roles_claim: impl Into<String>) -> Self {
+        self.roles_claim = Some(roles_claim.into());
+        self
+    }
+
+    /// Source of the nonces sent to the provider. Defaults to the OS random number generator.
+    pub fn random_source(mut self, random: impl RandomSource + 'static) -> Self {
+        self.random = Arc::new(random);
+        self
+    }
+
+    /// Decides how the callback answers when the provider refuses the authorization code or
+    /// cannot be reached. Defaults to [`OpenIdError::default_action`].
+    pub fn error_action(mut self, error_action: fn(&OpenIdError) -> ErrorAction) -> Self {
+        self.error_action = error_action;
+        self
+    }
+
+    /// How the callback answers the provider's error redirects, e.g. when the user cancelled the
+    /// login, see [`CallbackErrorHandling`]. Defaults to rendering the error page.
+    pub fn callback_errors(mut self, callback_errors: CallbackErrorHandling) -> Self {
+        self.callback_errors = callback_errors;
+        self
+    }
+
+    /// Answers unauthenticated requests sent to the login with the response of `hook`, e.g. a
+    /// branded "Redirecting to sign-in…" page with a meta refresh, a `303 See Other`, or a
+    /// redirect without a body. The login cookies are added to its response, the callback
+    /// checks the login with them. Defaults to a `302 Found` with the not-authenticated message.
+    ///
+    /// Requests answered with the login url as JSON, e.g. from scripts, are not affected.
+    pub fn login_redirect(mut self, hook: fn(&LoginRedirect<'_>) -> HttpResponse) -> Self {
+        self.login_redirect = Some(hook);
+        self
+    }
+
+    /// Text of the responses users see, e.g. translated. Defaults to [`EnglishMessages`].
+    pub fn messages(mut self, messages: impl Messages + 'static) -> Self {
+        self.messages = Arc::new(messages);
+        self
+    }
+
+    /// Told about logins, refreshes and token validations, e.g. to count them as metrics, see
+    /// [`AuthEvents`]. Defaults to ignoring them.
+    pub fn auth_events(mut self, events: impl AuthEvents + 'static) -> Self {
+        self.events = Arc::new(events);
+        self
+    }
+
+    /// Runs `hook` for every login before its session is created, e.g. to provision the user in
+    /// the application's database, refuse deactivated accounts or store data with the session, see
+    /// [`LoginHook`].
+    pub fn on_login(mut self, hook: impl LoginHook + 'static) -> Self {
+        self.login_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Renders the HTML pages of the endpoints, e.g. with the application's templates. Defaults to
+    /// [`DefaultPages`].
+    pub fn page_renderer(mut self, pages: impl PageRenderer + 'static) -> Self {
+        self.pages = Arc::new(pages);
+        self
+    }
+
+    /// Levels at which the crate logs each [`LogCategory`]. Defaults to [`LogPolicy::default`].
+    pub fn log_policy(mut self, log_policy: LogPolicy) -> Self {
+        self.log_policy = log_policy;
+        self
+    }
+
+    /// Mounts the client as its own [`Realm`] below a scope, instead of at the root.
+    pub fn realm(mut self, realm: Realm) -> Self {
+        self.realm = realm;
+        self
+    }
+
+    /// Prefixes the cookie names with a namespace derived from the issuer url and the client id,
+    /// so several clients on one domain keep separate sessions. Ignored when a realm is set, its
+    /// id is the namespace.
+    pub fn namespace_cookies(mut self, namespace_cookies: bool) -> Self {
+        self.namespace_cookies = namespace_cookies;
+        self
+    }
+
+    /// Client sending every request to the provider, instead of the one built from the pool
+    /// settings. It should not follow redirects.
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Idle connections kept open to the provider. Defaults to no limit, ignored with
+    /// [`OpenIdBuilder::http_client`].
+    pub fn pool_max_idle_per_host(mut self, max_idle_per_host: usize) -> Self {
+        self.pool.max_idle_per_host = max_idle_per_host;
+        self
+    }
+
+    /// How long idle connections to the provider are kept open, `None` for ever. Defaults to
+    /// 90 seconds, ignored with [`OpenIdBuilder::http_client`].
+    pub fn pool_idle_timeout(mut self, idle_timeout: Option<Duration>) -> Self {
+        self.pool.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// How long connecting to the provider may take, `None` for ever. Defaults to 10 seconds,
+    /// ignored with [`OpenIdBuilder::http_client`].
+    pub fn connect_timeout(mut self, connect_timeout: Option<Duration>) -> Self {
+        self.pool.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// How long a request to the provider may take until its whole response is read, `None` for
+    /// ever. Defaults to 30 seconds, ignored with [`OpenIdBuilder::http_client`].
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool.timeout = timeout;
+        self
+    }
+
+    /// Repeats the discovery, JWKS and userinfo requests failing because the provider is
+    /// unreachable, see [`RetryPolicy`]. Applies to an injected
+    /// [`http_client`](OpenIdBuilder::http_client) too. Requests are sent once by default.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Whether `build` fails when the provider cannot be discovered, the default, or the client
+    /// starts anyway, see [`DiscoveryPolicy`].
+    pub fn discovery_policy(mut self, discovery_policy: DiscoveryPolicy) -> Self {
+        self.discovery_policy = discovery_policy;
+        self
+    }
+
+    /// Skips userinfo requests for `open_for` once the provider was unavailable `failures` times in
+    /// a row, answering as if it was unavailable. Defaults to 5 failures and 30 seconds.
+    pub fn circuit_breaker(mut self, failures: u32, open_for: Duration) -> Self {
+        self.breaker = BreakerConfig { failures, open_for };
+        self
+    }
+
+    /// Sends sessions authenticated before the user's not-before time through the login again,
+    /// see [`NotBeforePolicy`]. Its answers are reused for 30 seconds.
+    pub fn not_before_policy(mut self, policy: impl NotBeforePolicy + 'static) -> Self {
+        self.not_before_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Registers `k_push_not_before` with [`ActixWebOpenId::configure_open_id`], receiving the
+    /// not-before time Keycloak pushes for every user of the client. Sessions are then checked
+    /// like with a [`NotBeforePolicy`], which is not required.
+    pub fn keycloak_push_not_before(mut self, enabled: bool) -> Self {
+        self.keycloak_push_not_before = enabled;
+        self
+    }
+
+    /// The time token expiry, cache TTLs and the circuit breaker are checked against,
+    /// [`SystemClock`] by default. Tests can pass a `test_util::MockClock`.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How the claims of the `user_info` cookie are encoded, [`PayloadCodec::Json`] by default.
+    /// [Chunked](UserInfoCookie::Chunked) cookies compress JSON with
+    /// [`PayloadCodec::DeflateJson`].
+    pub fn payload_codec(mut self, codec: PayloadCodec) -> Self {
+        self.payload_codec = codec;
+        self
+    }
+
+    /// How long a login may take at the provider, 10 minutes by default. The cookies the callback
+    /// checks the login with expire after it, and callbacks arriving later are refused.
+    pub fn login_window(mut self, login_window: Duration) -> Self {
+        self.login_window = login_window;
+        self
+    }
+
+    /// Protects the authorization code with PKCE (RFC 7636), as required by providers such as
+    /// Okta for some clients. The verifier is kept in a cookie until the callback. Off by default,
+    /// except for public clients without a [`client_secret`](Self::client_secret).
+    pub fn pkce(mut self, pkce: bool) -> Self {
+        self.pkce = Some(pkce);
+        self
+    }
+
+    /// Encrypts the values of the auth cookies (AES-GCM) with `key`, e.g. `Key::derive_from` a
+    /// secret from the configuration. Cookies that do not decrypt, such as the plaintext cookies
+    /// of earlier sessions, count as missing and are removed. Unencrypted by default.
+    pub fn cookie_key(mut self, key: Key) -> Self {
+        self.cookie_key = Some(key);
+        self
+    }
+
+    /// The domain, path and flags of the cookies, see [`CookieConfig`].
+    pub fn cookie_config(mut self, cookie_config: CookieConfig) -> Self {
+        self.cookie_config = cookie_config;
+        self
+    }
+
+    /// Whether the callback sets the `user_info` cookie with the claims of the ID token, see
+    /// [`UserInfoCookie`]. [`Disabled`](UserInfoCookie::Disabled) by default.
+    pub fn user_info_cookie(mut self, user_info_cookie: UserInfoCookie) -> Self {
+        self.user_info_cookie = user_info_cookie;
+        self
+    }
+
+    /// Ends sessions whose user logged in longer than `lifetime` ago, whatever the lifetime of
+    /// their tokens: requests of the session are then sent through the login again and its
+    /// cookies removed. Counts from the `auth_time` of the ID token if the provider sends one,
+    /// and asks the provider to authenticate users anew past it with `max_age`: logins the
+    /// provider authenticated longer ago are refused.
+    ///
+    /// The login time is recorded in the session store, or in a cookie that requires a
+    /// [`cookie_key`](Self::cookie_key). Sessions without one must log in again.
+    pub fn session_absolute_lifetime(mut self, lifetime: Duration) -> Self {
+        self.session_limits.absolute_lifetime = Some(lifetime);
+        self
+    }
+
+    /// Ends sessions that made no request for `timeout`, like
+    /// [`session_absolute_lifetime`](Self::session_absolute_lifetime). The time of the last
+    /// request is recorded at most once a minute.
+    pub fn session_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.session_limits.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps the tokens of the sessions in `store`, the session cookie then only holds a random
+    /// id. The tokens are kept in the session cookies by default.
+    pub fn session_store(mut self, store: impl SessionStore + 'static) -> Self {
+        self.session_store = Some(Arc::new(store));
+        self
+    }
+
+    /// How the browser keeps the session, [`SessionFormat::Cookies`] by default. A
+    /// [`SessionFormat::FirstPartyJwt`] needs a [`session_store`](Self::session_store).
+    #[cfg(feature = "first-party-session")]
+    pub fn session_format(mut self, session_format: SessionFormat) -> Self {
+        self.session_format = session_format;
+        self
+    }
+
+    /// Refetches the discovery document and the JWKS once they were confirmed longer than
+    /// `max_staleness` ago, even within the `max-age` the provider sent. Only applies when they
+    /// are refreshed, see [`OpenID::refresh_provider`].
+    pub fn max_provider_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_provider_staleness = Some(max_staleness);
+        self
+    }
+
+    /// Registers `GET path`, e.g. `/logout/frontchannel`, for the provider to end the session
+    /// of the browser it loads the page in, usually in an iframe. Register the url as the
+    /// client's front-channel logout url at the provider and leave it out of `should_auth`.
+    ///
+    /// With `iss` and `sid` in the query, only the session of that provider session ends.
+    /// Browsers send the session cookies to third-party iframes only with `SameSite=None`.
+    pub fn front_channel_logout(mut self, path: impl Into<String>) -> Self {
+        self.front_channel_logout = Some(path.into());
+        self
+    }
+
+    /// Registers `POST path`, e.g. `/logout/backchannel`, where the provider posts a logout
+    /// token to end sessions it logged out, by their `sid` or `sub`. They are removed from the
+    /// [`session_store`](Self::session_store), which the build then requires. Register the url
+    /// as the client's back-channel logout url at the provider and leave it out of
+    /// `should_auth`.
+    pub fn back_channel_logout(mut self, path: impl Into<String>) -> Self {
+        self.back_channel_logout = Some(path.into());
+        self
+    }
+
+    /// Path of the callback endpoint below the realm, `/auth_callback` by default. The
+    /// middleware never requires a login for it.
+    pub fn callback_path(mut self, path: impl Into<String>) -> Self {
+        self.callback_path = path.into();
+        self
+    }
+
+    /// Path of the logout endpoint below the realm, `/logout` by default. The local logout and
+    /// the post logout callback are registered below it, at `{path}/local` and
+    /// `{path}/callback`.
+    pub fn logout_path(mut self, path: impl Into<String>) -> Self {
+        self.logout_path = path.into();
+        self
+    }
+
+    /// When [`OpenID::health`] reports the client as unhealthy, see [`HealthThresholds`].
+    pub fn health_thresholds(mut self, thresholds: HealthThresholds) -> Self {
+        self.health_thresholds = thresholds;
+        self
+    }
+
+    /// Fails the build on configuration warnings too, not only on errors, and on security findings
+    /// in release builds. See [`OpenID::validate`] and [`OpenID::security_report`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub async fn build(self) -> Result<ActixWebOpenId> {
+        self.build_validated(true).await
+    }
+
+    /// Builds the client, failing on the fatal [configuration issues](OpenID::validate) only if
+    /// `enforce`, logging them otherwise.
+    pub(crate) async fn build_validated(self, enforce: bool) -> Result<ActixWebOpenId> {
+        let should_auth = self.should_auth.clone();
+        let strict = self.strict;
+        let openid_client = OpenID::init(self).await?;
+        let mut fatal = Vec::new();
+        for issue in openid_client.validate() {
+            if enforce && issue.is_fatal(strict) {
+                fatal.push(issue.to_string());
+            } else {
+                openid_client.log_policy().log(
+                    LogCategory::ConfigWarning,
+                    format_args!("OpenID configuration {}", issue),
+                );
+            }
+        }
+        // Insecure settings are expected while developing, only release builds complain.
+        if !cfg!(debug_assertions) {
+            for finding in openid_client.security_report() {
+                if strict {
+                    fatal.push(finding.to_string());
+                } else {
+                    openid_client.log_policy().log_error(
+                        LogCategory::ConfigWarning,
+                        format_args!("insecure OpenID configuration {}", finding),
+                    );
+                }
+            }
+        }
+        if !fatal.is_empty() {
+            return Err(OpenIdError::Config(fatal.join("; ")));
+        }
+        Ok(ActixWebOpenId {
+            openid_client: Arc::new(openid_client),
+            should_auth,
+        })
+    }
+
+    pub(crate) fn required<'a, T>(value: &'a Option<T>, name: &str) -> Result<&'a T> {
+        value.as_ref().ok_or_else(|| {
+            OpenIdError::Config(format!("{} is required to build the OpenID client", name))
+        })
+    }
+}
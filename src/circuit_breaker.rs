@@ -0,0 +1,109 @@
+//! Stops calling a provider that keeps failing, letting a request through now and then to notice
+//! when it is back.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::clock::{system_time, Clock};
+use crate::logging::{LogCategory, LogPolicy};
+
+/// The state of the provider's circuit breaker, see [`crate::HealthReport`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    /// Userinfo requests are skipped.
+    Open,
+    /// The breaker was open, the next userinfo request tries the provider again.
+    HalfOpen,
+}
+
+/// When the breaker opens and for how long, see
+/// [`OpenIdBuilder::circuit_breaker`](crate::OpenIdBuilder::circuit_breaker).
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BreakerConfig {
+    pub(crate) failures: u32,
+    pub(crate) open_for: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failures: 5,
+            open_for: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BreakerState {
+    /// Consecutive failures since the last success.
+    failures: u32,
+    opened_at: Option<SystemTime>,
+}
+
+pub(crate) struct CircuitBreaker {
+    config: BreakerConfig,
+    clock: Arc<dyn Clock>,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(config: BreakerConfig, clock: Arc<dyn Clock>) -> Self {
+        CircuitBreaker {
+            config,
+            clock,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether requests to the provider are skipped. Once `open_for` elapsed the next request is
+    /// tried again, its outcome closes or reopens the breaker.
+    pub(crate) fn is_open(&self) -> bool {
+        self.state() == CircuitState::Open
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        let state = self.state.lock().unwrap();
+        let now = system_time(self.clock.as_ref());
+        match state.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at)
+                if now.duration_since(opened_at).unwrap_or_default() < self.config.open_for =>
+            {
+                CircuitState::Open
+            }
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Records the outcome of a request, `unavailable` if the provider could not answer it.
+    pub(crate) fn record(&self, unavailable: bool, log_policy: &LogPolicy) {
+        let mut state = self.state.lock().unwrap();
+        if !unavailable {
+            if state.opened_at.take().is_some() {
+                log_policy.log(
+                    LogCategory::IdpError,
+                    format_args!("The provider is available again, closing the circuit breaker"),
+                );
+            }
+            state.failures = 0;
+            return;
+        }
+        state.failures = state.failures.saturating_add(1);
+        if state.failures >= self.config.failures {
+            if state.opened_at.is_none() {
+                log_policy.log_error(
+                    LogCategory::IdpError,
+                    format_args!(
+                        "The provider failed {} times in a row, opening the circuit breaker",
+                        state.failures
+                    ),
+                );
+            }
+            state.opened_at = Some(system_time(self.clock.as_ref()));
+        }
+    }
+}
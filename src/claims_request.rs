@@ -0,0 +1,163 @@
+//! The `claims` authorization parameter, asking the provider for individual claims.
+
+use std::collections::BTreeMap;
+
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+/// Claims requested with the `claims` parameter of the authorization request, see OpenID Connect
+/// Core section 5.5. Some providers only release claims requested this way.
+///
+/// ```ignore
+/// ActixWebOpenId::builder().claims_request(
+///     ClaimsRequest::new()
+///         .userinfo("employee_id", ClaimRequest::essential())
+///         .id_token("acr", ClaimRequest::essential().values(["urn:mace:incommon:iap:silver"])),
+/// )
+/// ```
+///
+/// The callback fails with [`OpenIdError::MissingClaims`](crate::OpenIdError::MissingClaims)
+/// when the ID token or the userinfo response lacks an essential claim of its section. Only the
+/// presence of the claims is checked, providers may answer other values than the requested ones.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ClaimsRequest {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    userinfo: BTreeMap<String, ClaimRequest>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    id_token: BTreeMap<String, ClaimRequest>,
+}
+
+impl ClaimsRequest {
+    pub fn new() -> Self {
+        ClaimsRequest::default()
+    }
+
+    /// Requests `claim` from the userinfo endpoint.
+    pub fn userinfo(mut self, claim: impl Into<String>, request: ClaimRequest) -> Self {
+        self.userinfo.insert(claim.into(), request);
+        self
+    }
+
+    /// Requests `claim` in the ID token.
+    pub fn id_token(mut self, claim: impl Into<String>, request: ClaimRequest) -> Self {
+        self.id_token.insert(claim.into(), request);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.userinfo.is_empty() && self.id_token.is_empty()
+    }
+
+    /// The JSON value of the parameter, `None` when nothing is requested.
+    pub fn to_parameter(&self) -> Option<String> {
+        match self.is_empty() {
+            true => None,
+            false => serde_json::to_string(self).ok(),
+        }
+    }
+
+    pub(crate) fn essential_claims(&self) -> EssentialClaims {
+        let essential = |requests: &BTreeMap<String, ClaimRequest>| {
+            requests
+                .iter()
+                .filter(|(_, request)| request.essential)
+                .map(|(claim, _)| claim.clone())
+                .collect()
+        };
+        EssentialClaims {
+            userinfo: essential(&self.userinfo),
+            id_token: essential(&self.id_token),
+        }
+    }
+}
+
+/// The essential claims of a [`ClaimsRequest`], kept in a cookie from the redirect to the
+/// provider until the callback checks them.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct EssentialClaims {
+    userinfo: Vec<String>,
+    id_token: Vec<String>,
+}
+
+impl EssentialClaims {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.userinfo.is_empty() && self.id_token.is_empty()
+    }
+
+    pub(crate) fn has_userinfo(&self) -> bool {
+        !self.userinfo.is_empty()
+    }
+
+    /// The essential userinfo claims missing from `claims`.
+    pub(crate) fn missing_userinfo(&self, claims: &Value) -> Vec<String> {
+        missing(&self.userinfo, claims)
+    }
+
+    /// The essential ID token claims missing from `claims`.
+    pub(crate) fn missing_id_token(&self, claims: &Value) -> Vec<String> {
+        missing(&self.id_token, claims)
+    }
+}
+
+fn missing(essential: &[String], claims: &Value) -> Vec<String> {
+    essential
+        .iter()
+        .filter(|claim| claims.get(claim.as_str()).is_none_or(Value::is_null))
+        .cloned()
+        .collect()
+}
+
+/// How a claim is requested, serialized as `null` for a voluntary claim without values.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClaimRequest {
+    essential: bool,
+    value: Option<Value>,
+    values: Vec<Value>,
+}
+
+impl ClaimRequest {
+    /// A claim the provider may leave out.
+    pub fn voluntary() -> Self {
+        ClaimRequest::default()
+    }
+
+    /// A claim the login fails without.
+    pub fn essential() -> Self {
+        ClaimRequest {
+            essential: true,
+            ..ClaimRequest::default()
+        }
+    }
+
+    /// Asks for the claim to have `value`.
+    pub fn value(mut self, value: impl Into<Value>) -> Self {
+        self.value = Some(value.into());
+        self
+    }
+
+    /// Asks for the claim to have one of `values`, in order of preference.
+    pub fn values(mut self, values: impl IntoIterator<Item = impl Into<Value>>) -> Self {
+        self.values = values.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl Serialize for ClaimRequest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if *self == ClaimRequest::default() {
+            return serializer.serialize_none();
+        }
+        let mut map = serializer.serialize_map(None)?;
+        if self.essential {
+            map.serialize_entry("essential", &true)?;
+        }
+        if let Some(value) = &self.value {
+            map.serialize_entry("value", value)?;
+        }
+        if !self.values.is_empty() {
+            map.serialize_entry("values", &self.values)?;
+        }
+        map.end()
+    }
+}
@@ -0,0 +1,29 @@
+//! The time every time-based check of the crate reads, replaceable in tests.
+
+use std::time::SystemTime;
+
+use actix_web::cookie::time::OffsetDateTime;
+
+/// Tells the current time, e.g. for token expiry, cache TTLs and the circuit breaker. Set with
+/// [`OpenIdBuilder::clock`](crate::OpenIdBuilder::clock), [`SystemClock`] by default.
+///
+/// `OffsetDateTime` is the one of the `time` crate, reexported as
+/// `actix_web::cookie::time::OffsetDateTime`.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// The system's clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// The clock's time, as the crate computes with `SystemTime`.
+pub(crate) fn system_time(clock: &dyn Clock) -> SystemTime {
+    clock.now().into()
+}
@@ -0,0 +1,214 @@
+//! The attributes of the auth cookies.
+
+use std::time::Duration;
+
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::{Cookie, CookieBuilder, SameSite};
+
+use crate::realm::Realm;
+
+/// How long the browser keeps the session cookies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CookieMaxAge {
+    /// Until the browser is closed.
+    #[default]
+    Session,
+    /// As long as the access token is valid. Sessions with a refresh token keep their cookies
+    /// as long as the refresh token is valid, to be renewed when the access token expires, or
+    /// until the browser is closed when the provider does not tell with `refresh_expires_in`.
+    TokenExpiry,
+    Fixed(Duration),
+}
+
+/// Whether the claims of the ID token are kept in the `user_info` cookie for front ends to read,
+/// see [`OpenIdBuilder::user_info_cookie`]. The middleware does not read it.
+///
+/// [`OpenIdBuilder::user_info_cookie`]: crate::OpenIdBuilder::user_info_cookie
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UserInfoCookie {
+    #[default]
+    Disabled,
+    /// One `user_info` cookie encoded with the [payload codec](crate::PayloadCodec). Browsers
+    /// drop cookies above 4KB, e.g. with long group lists.
+    Plain,
+    /// Compressed and split across `user_info.0`, `user_info.1`, ... of less than 4KB each,
+    /// joined again by [`OpenID::cookie_user_info`](crate::openid::OpenID::cookie_user_info).
+    Chunked,
+}
+
+/// The attributes of the cookies the client sets, see [`OpenIdBuilder::cookie_config`].
+///
+/// Session cookies get all of them. The cookies of a login in progress and of silent login
+/// attempts are always `HttpOnly` with their own short lifetime, and `SameSite=Lax` unless
+/// `None` is configured, stricter settings would drop them on the way back from the provider.
+/// With [`response_mode_form_post`](crate::OpenIdBuilder::response_mode_form_post) they are
+/// `SameSite=None` and `Secure`, browsers only send those with a cross-site POST.
+///
+/// ```ignore
+/// // Local development over http.
+/// CookieConfig::default().secure(false)
+/// ```
+///
+/// [`OpenIdBuilder::cookie_config`]: crate::OpenIdBuilder::cookie_config
+#[derive(Clone, Debug)]
+pub struct CookieConfig {
+    prefix: String,
+    domain: Option<String>,
+    path: Option<String>,
+    same_site: SameSite,
+    secure: bool,
+    http_only: bool,
+    max_age: CookieMaxAge,
+    /// Whether the callback is a cross-site POST, see
+    /// [`response_mode_form_post`](crate::OpenIdBuilder::response_mode_form_post).
+    cross_site_logins: bool,
+}
+
+impl Default for CookieConfig {
+    /// `Secure`, `SameSite=Lax`, readable by scripts, kept until the browser is closed and sent
+    /// below the realm's path.
+    fn default() -> Self {
+        CookieConfig {
+            prefix: String::new(),
+            domain: None,
+            path: None,
+            same_site: SameSite::Lax,
+            secure: true,
+            http_only: false,
+            max_age: CookieMaxAge::Session,
+            cross_site_logins: false,
+        }
+    }
+}
+
+impl CookieConfig {
+    /// Prepended to every cookie name, before the realm's prefix, e.g. `__Host-`.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Shares the cookies with the subdomains of `domain`. Host-only by default.
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// The path the cookies are sent below, the realm's path by default.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Only sends the cookies over https. Turn off for local development over http only.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Hides the session cookies from scripts.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: CookieMaxAge) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub(crate) fn cross_site_logins(mut self, cross_site_logins: bool) -> Self {
+        self.cross_site_logins = cross_site_logins;
+        self
+    }
+
+    pub(crate) fn name_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub(crate) fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// A cookie of `realm` with the configured domain, path and `Secure` flag.
+    fn cookie(&self, realm: &Realm, name: &str, value: String) -> CookieBuilder<'static> {
+        let mut cookie = Cookie::build(name.to_string(), value)
+            .path(self.path.as_deref().unwrap_or(realm.path()).to_string())
+            .secure(self.secure);
+        if let Some(domain) = &self.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        cookie
+    }
+
+    /// A session cookie, kept for `max_age`.
+    pub(crate) fn session_cookie(
+        &self,
+        realm: &Realm,
+        name: &str,
+        value: String,
+        max_age: Option<CookieDuration>,
+    ) -> Cookie<'static> {
+        let mut cookie = self
+            .cookie(realm, name, value)
+            .same_site(self.same_site)
+            .http_only(self.http_only);
+        if let Some(max_age) = max_age {
+            cookie = cookie.max_age(max_age);
+        }
+        cookie.finish()
+    }
+
+    /// A cookie of a login in progress, kept for `max_age`.
+    pub(crate) fn login_cookie(
+        &self,
+        realm: &Realm,
+        name: &str,
+        value: String,
+        max_age: CookieDuration,
+    ) -> Cookie<'static> {
+        let mut cookie = self.cookie(realm, name, value);
+        if self.cross_site_logins {
+            cookie = cookie.same_site(SameSite::None).secure(true);
+        } else {
+            cookie = cookie.same_site(match self.same_site {
+                SameSite::None => SameSite::None,
+                _ => SameSite::Lax,
+            });
+        }
+        cookie.http_only(true).max_age(max_age).finish()
+    }
+
+    /// Removes the cookie `name`, set with the same domain and path.
+    pub(crate) fn removal(&self, realm: &Realm, name: &str) -> Cookie<'static> {
+        let mut removal = self.cookie(realm, name, String::new()).finish();
+        removal.make_removal();
+        removal
+    }
+
+    /// How long to keep the cookies of a session whose access token expires in `expires_in`, and
+    /// its refresh token, if any, in `refresh_expires_in`.
+    pub(crate) fn session_max_age(
+        &self,
+        expires_in: Option<Duration>,
+        refresh_token: bool,
+        refresh_expires_in: Option<Duration>,
+    ) -> Option<CookieDuration> {
+        let max_age = match self.max_age {
+            CookieMaxAge::Session => None,
+            CookieMaxAge::TokenExpiry if refresh_token => refresh_expires_in,
+            CookieMaxAge::TokenExpiry => expires_in,
+            CookieMaxAge::Fixed(max_age) => Some(max_age),
+        }?;
+        Some(CookieDuration::seconds(
+            i64::try_from(max_age.as_secs()).unwrap_or(i64::MAX),
+        ))
+    }
+}
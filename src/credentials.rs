@@ -0,0 +1,293 @@
+//! Where the middleware looks for credentials, and in which order.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::error::InternalError;
+use actix_web::http::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER, WWW_AUTHENTICATE,
+};
+use actix_web::{Error, HttpMessage, HttpResponse};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use openidconnect::AccessToken;
+
+use crate::api_key::{validate_api_key, ApiKeyValidator};
+use crate::basic_auth::BasicAuthConfig;
+use crate::error::{ErrorAction, OpenIdError};
+use crate::logging::LogCategory;
+use crate::messages::MessageKey;
+use crate::openid::{OpenID, OtherClaims};
+use crate::openid_middleware::{
+    request_session, token_user, AuthenticatedUser, AuthenticationRequired, RealmClient, UserTokens,
+};
+
+/// A credential found in a request, validated by the middleware according to its kind.
+#[non_exhaustive]
+pub enum Credential {
+    /// The access token of a browser session, a rejected one asks the user to log in again.
+    Session(AccessToken),
+    /// An access token sent by an API client, a rejected one answers `401 Unauthorized`.
+    Bearer(AccessToken),
+    /// Checked by the [`ApiKeyValidator`], ignored without one.
+    ApiKey(String),
+    /// Checked as configured by `basic_auth`, ignored on other paths.
+    Basic { username: String, password: String },
+    /// A user the extractor authenticated itself, e.g. from the client certificate header set by
+    /// the ingress.
+    User(Box<AuthenticatedUser>),
+    /// A first-party session token, see
+    /// [`SessionFormat::FirstPartyJwt`](crate::SessionFormat::FirstPartyJwt).
+    #[cfg(feature = "first-party-session")]
+    FirstParty(String),
+}
+
+impl Credential {
+    /// The kind of the credential, recorded as the `auth_source` of the request's span.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Credential::Session(_) => "cookie",
+            Credential::Bearer(_) => "bearer",
+            Credential::ApiKey(_) => "api_key",
+            Credential::Basic { .. } => "basic",
+            Credential::User(_) => "user",
+            #[cfg(feature = "first-party-session")]
+            Credential::FirstParty(_) => "first_party",
+        }
+    }
+}
+
+/// Finds a credential in the request.
+pub trait CredentialExtractor: Send + Sync {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential>;
+}
+
+/// The access token of the middleware realm's session, from its cookies or its
+/// [`SessionStore`](crate::SessionStore).
+pub struct SessionCookie;
+
+impl CredentialExtractor for SessionCookie {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        let client = req.extensions().get::<RealmClient>()?.0.clone();
+        let session = request_session(&client, req.request())?;
+        Some(Credential::Session(session.access_token()))
+    }
+}
+
+/// `Authorization: Bearer <access token>`.
+pub struct BearerHeader;
+
+impl CredentialExtractor for BearerHeader {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        let token = authorization(req)?.strip_prefix("Bearer ")?.trim();
+        Some(Credential::Bearer(AccessToken::new(token.to_string())))
+    }
+}
+
+/// An access token in the query, e.g. `?access_token=`. URLs end up in logs and browser
+/// histories, prefer the header.
+pub struct QueryParameter(String);
+
+impl QueryParameter {
+    pub fn new(name: impl Into<String>) -> Self {
+        QueryParameter(name.into())
+    }
+}
+
+impl CredentialExtractor for QueryParameter {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        url::form_urlencoded::parse(req.query_string().as_bytes())
+            .find(|(name, _)| *name == *self.0)
+            .map(|(_, token)| Credential::Bearer(AccessToken::new(token.into_owned())))
+    }
+}
+
+/// An API key header, `X-Api-Key` by default.
+pub struct ApiKeyHeader(HeaderName);
+
+impl ApiKeyHeader {
+    pub fn new(header: HeaderName) -> Self {
+        ApiKeyHeader(header)
+    }
+}
+
+impl Default for ApiKeyHeader {
+    fn default() -> Self {
+        ApiKeyHeader(HeaderName::from_static("x-api-key"))
+    }
+}
+
+impl CredentialExtractor for ApiKeyHeader {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        let key = req.headers().get(&self.0)?.to_str().ok()?;
+        Some(Credential::ApiKey(key.to_string()))
+    }
+}
+
+/// `Authorization: Basic <credentials>`.
+pub struct BasicHeader;
+
+impl CredentialExtractor for BasicHeader {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        let credentials = authorization(req)?.strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        Some(Credential::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+fn authorization(req: &ServiceRequest) -> Option<&str> {
+    req.headers().get(AUTHORIZATION)?.to_str().ok()
+}
+
+/// The extractors the middleware tries in order, the first credential it can validate is used.
+///
+/// ```ignore
+/// // Header then cookie, never query.
+/// openid.get_middleware().credentials(CredentialChain::new().with(BearerHeader).with(SessionCookie))
+/// ```
+#[derive(Clone, Default)]
+pub struct CredentialChain(Vec<Arc<dyn CredentialExtractor>>);
+
+impl CredentialChain {
+    pub fn new() -> Self {
+        CredentialChain::default()
+    }
+
+    pub fn with(mut self, extractor: impl CredentialExtractor + 'static) -> Self {
+        self.0.push(Arc::new(extractor));
+        self
+    }
+
+    /// The credential of `client`'s sessions: the [`SessionCookie`], or the
+    /// [`FirstPartySession`](crate::FirstPartySession) token of a client with
+    /// [`SessionFormat::FirstPartyJwt`](crate::SessionFormat::FirstPartyJwt).
+    pub(crate) fn with_sessions_of(self, client: &OpenID) -> Self {
+        #[cfg(feature = "first-party-session")]
+        if let crate::first_party::SessionFormat::FirstPartyJwt { .. } = client.session_format() {
+            return self.with(crate::first_party::FirstPartySession);
+        }
+        let _ = client;
+        self.with(SessionCookie)
+    }
+
+    /// Only the session cookie, for browser apps.
+    pub fn cookie_only() -> Self {
+        CredentialChain::new().with(SessionCookie)
+    }
+
+    /// Only `Authorization: Bearer`, for APIs and SPAs keeping the token themselves.
+    pub fn bearer_only() -> Self {
+        CredentialChain::new().with(BearerHeader)
+    }
+
+    /// The session cookie, or the bearer token of requests without one.
+    pub fn cookie_or_bearer() -> Self {
+        CredentialChain::new()
+            .with(SessionCookie)
+            .with(BearerHeader)
+    }
+}
+
+/// The chain and the validators of a middleware.
+pub(crate) struct Authenticators {
+    pub(crate) chain: CredentialChain,
+    pub(crate) api_keys: Option<Arc<dyn ApiKeyValidator>>,
+    pub(crate) basic_auth: Option<BasicAuthConfig>,
+}
+
+impl Authenticators {
+    /// The user of the first credential that can be validated, `None` if there is none.
+    ///
+    /// A rejected session is returned for the caller to decide whether to log in again, other
+    /// rejected credentials fail the request.
+    pub(crate) async fn authenticate(
+        &self,
+        client: &Arc<OpenID>,
+        req: &ServiceRequest,
+    ) -> Result<Option<Result<AuthenticatedUser<OtherClaims>, AuthenticationRequired>>, Error> {
+        for extractor in &self.chain.0 {
+            let Some(credential) = extractor.extract(req) else {
+                continue;
+            };
+            tracing::Span::current().record("auth_source", credential.kind());
+            let user = match credential {
+                Credential::Session(token) => {
+                    return Ok(Some(token_user(client, req, token).await))
+                }
+                Credential::Bearer(token) => bearer_user(client, token).await,
+                Credential::ApiKey(key) => match &self.api_keys {
+                    Some(validator) => validate_api_key(validator.as_ref(), client, req, &key)
+                        .await
+                        .map(AuthenticatedUser::from),
+                    None => continue,
+                },
+                Credential::Basic { username, password } => {
+                    let basic_auth = self.basic_auth.as_ref().filter(|basic| (basic.paths)(req));
+                    match basic_auth {
+                        Some(basic_auth) => basic_auth.validate(client, &username, &password).await,
+                        None => continue,
+                    }
+                }
+                Credential::User(user) => Ok((*user).into()),
+                #[cfg(feature = "first-party-session")]
+                Credential::FirstParty(token) => crate::first_party::session_user(client, &token),
+            };
+            return user.map(|user| Some(Ok(user)));
+        }
+        Ok(None)
+    }
+}
+
+async fn bearer_user(
+    client: &OpenID,
+    token: AccessToken,
+) -> Result<AuthenticatedUser<OtherClaims>, Error> {
+    match client.user_claims(token.clone(), None).await {
+        Ok(user_info) => {
+            Ok(AuthenticatedUser::new(user_info).with_tokens(UserTokens::new(token, None)))
+        }
+        Err(err) => Err(match client.error_action(&err) {
+            ErrorAction::RetryLater(retry_after) => idp_unavailable(client, err, retry_after),
+            _ => rejected(
+                client,
+                "Bearer error=\"invalid_token\"",
+                &format!("Rejected the bearer token: {}", err),
+            ),
+        }),
+    }
+}
+
+/// Answers `401 Unauthorized` with `challenge`, logging `reason`, which must not contain the
+/// credential.
+pub(crate) fn rejected(client: &OpenID, challenge: &str, reason: &str) -> Error {
+    client.log_policy().log(
+        LogCategory::UnauthenticatedRequest,
+        format_args!("{}", reason),
+    );
+    let mut response =
+        HttpResponse::Unauthorized().body(client.message(MessageKey::Unauthorized, &[]));
+    if let Ok(challenge) = HeaderValue::from_str(challenge) {
+        response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+    }
+    InternalError::from_response("the credentials were rejected", response).into()
+}
+
+/// Answers `503 Service Unavailable`, the credentials could not be checked.
+pub(crate) fn idp_unavailable(client: &OpenID, err: OpenIdError, retry_after: Duration) -> Error {
+    client.log_policy().log(
+        LogCategory::IdpError,
+        format_args!("Could not check the credentials: {}", err),
+    );
+    let response = HttpResponse::ServiceUnavailable()
+        .insert_header((RETRY_AFTER, retry_after.as_secs().to_string()))
+        .body(client.message(
+            MessageKey::IdpUnavailable,
+            &[("retry_after", retry_after.as_secs().to_string())],
+        ));
+    InternalError::from_response(err, response).into()
+}
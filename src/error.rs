@@ -0,0 +1,256 @@
+//! Errors returned by the OpenID client.
+
+use std::time::Duration;
+
+use actix_web::body::BoxBody;
+use actix_web::error::HttpError;
+use actix_web::http::header::{InvalidHeaderValue, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+
+use crate::id_token::IdTokenError;
+use crate::logging::{LogCategory, LogPolicy};
+use crate::session_limits::SessionLimit;
+
+use openidconnect::core::CoreErrorResponseType;
+use openidconnect::reqwest::AsyncHttpClientError as HttpClientError;
+use openidconnect::{
+    ClaimsVerificationError, DiscoveryError, RequestTokenError, StandardErrorResponse,
+    UserInfoError,
+};
+
+pub type Result<T> = std::result::Result<T, OpenIdError>;
+
+/// Underlying error kept as the [`source`](std::error::Error::source) of an [`OpenIdError`].
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OpenIdError {
+    /// The discovery document or the JWKS could not be fetched or parsed.
+    #[error("OpenID provider discovery failed: {0}")]
+    Discovery(#[source] BoxError),
+    /// The token endpoint refused the authorization code or could not be reached.
+    ///
+    /// `provider_error` is the OAuth error returned by the provider, and `status` the HTTP status
+    /// of its response, `None` if no response was received.
+    #[error("token exchange failed: {source}")]
+    TokenExchange {
+        provider_error: Option<ProviderError>,
+        status: Option<u16>,
+        #[source]
+        source: BoxError,
+    },
+    /// The provider refused the session's refresh token with `invalid_grant`: it expired, was
+    /// revoked, or was presented before. Providers rotating refresh tokens, e.g. Auth0 or
+    /// Keycloak, revoke the whole session when a replaced token is presented again, which may
+    /// mean it was stolen. The middleware ends the session rather than retrying.
+    #[error("the provider revoked the refresh token with {}", .0.error)]
+    RefreshTokenRevoked(ProviderError),
+    /// The session outlived the [absolute
+    /// lifetime](crate::OpenIdBuilder::session_absolute_lifetime) or the [idle
+    /// timeout](crate::OpenIdBuilder::session_idle_timeout) of the client's sessions. The
+    /// middleware ends it, or the callback refuses a login the provider authenticated too long
+    /// ago.
+    #[error("the session outlived its {0}")]
+    SessionExpired(SessionLimit),
+    /// The ID token is invalid, e.g. expired, for another client or with the wrong nonce.
+    #[error("ID token verification failed: {0}")]
+    Verification(#[from] ClaimsVerificationError),
+    /// The ID token failed the check the [`IdTokenError`] names, e.g. an algorithm not allowed by
+    /// the [`IdTokenValidation`](crate::IdTokenValidation).
+    #[error("ID token verification failed: {0}")]
+    IdToken(#[from] IdTokenError),
+    /// The userinfo endpoint could not be reached or returned an invalid response.
+    #[error("userinfo request failed: {0}")]
+    UserInfo(#[source] BoxError),
+    /// The provider answered with an unexpected HTTP status, e.g. 401 for a revoked token.
+    #[error("the OpenID provider answered with HTTP status {status}")]
+    Http { status: u16 },
+    /// The client is misconfigured.
+    #[error("invalid OpenID configuration: {0}")]
+    Config(String),
+    /// The ID token or the userinfo response lacks claims requested as essential, see
+    /// [`ClaimsRequest`](crate::ClaimsRequest).
+    #[error("the provider did not return the essential claims {}", .0.join(", "))]
+    MissingClaims(Vec<String>),
+    /// The [`SessionStore`](crate::SessionStore) could not read or write a session.
+    #[error("the session store failed: {0}")]
+    SessionStore(#[source] BoxError),
+    /// A cookie of the session cannot be read, e.g. an ID token edited by hand. Names the cookie.
+    #[error("the {0} cookie is malformed")]
+    MalformedCookie(String),
+    /// The provider sent the user back to the callback with an error instead of a code, e.g.
+    /// `access_denied` when the user cancelled the consent.
+    #[error("the provider refused the login with {}", .0.error)]
+    Authorization(ProviderError),
+    /// The provider does not support the grant named, e.g. token exchange: it does not advertise
+    /// it, or answered `unsupported_grant_type`.
+    #[error("the provider does not support the {0} grant")]
+    UnsupportedGrant(String),
+    /// A response header or cookie could not be built, e.g. from an url with control characters.
+    #[error("invalid response header: {0}")]
+    Header(#[source] BoxError),
+}
+
+impl From<HttpError> for OpenIdError {
+    fn from(err: HttpError) -> Self {
+        OpenIdError::Header(Box::new(err))
+    }
+}
+
+impl From<InvalidHeaderValue> for OpenIdError {
+    fn from(err: InvalidHeaderValue) -> Self {
+        OpenIdError::Header(Box::new(err))
+    }
+}
+
+impl From<DiscoveryError<HttpClientError>> for OpenIdError {
+    fn from(err: DiscoveryError<HttpClientError>) -> Self {
+        match err {
+            DiscoveryError::Response(status, _, _) => OpenIdError::Http {
+                status: status.as_u16(),
+            },
+            err => OpenIdError::Discovery(Box::new(err)),
+        }
+    }
+}
+
+/// Delay suggested to clients when the provider is unavailable.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// A standard OAuth error response, see RFC 6749 section 5.2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderError {
+    /// The error code, e.g. `invalid_grant`.
+    pub error: String,
+    pub error_description: Option<String>,
+    pub error_uri: Option<String>,
+}
+
+/// How the callback answers when talking to the provider failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorAction {
+    /// Send the user through the login again, e.g. for an expired authorization code.
+    Reauthenticate,
+    /// Answer `503 Service Unavailable` with a `Retry-After` header.
+    RetryLater(Duration),
+    /// Answer `500 Internal Server Error`, the client is misconfigured.
+    InternalError,
+    /// Answer `400 Bad Request`.
+    BadRequest,
+}
+
+impl OpenIdError {
+    pub(crate) fn token_exchange(
+        err: RequestTokenError<HttpClientError, StandardErrorResponse<CoreErrorResponseType>>,
+        status: Option<u16>,
+    ) -> Self {
+        let provider_error = match &err {
+            RequestTokenError::ServerResponse(response) => Some(ProviderError {
+                error: response.error().as_ref().to_string(),
+                error_description: response.error_description().cloned(),
+                error_uri: response.error_uri().cloned(),
+            }),
+            _ => None,
+        };
+        OpenIdError::TokenExchange {
+            provider_error,
+            status,
+            source: Box::new(err),
+        }
+    }
+
+    /// The default mapping used by the callback, see
+    /// [`OpenIdBuilder::error_action`](crate::OpenIdBuilder::error_action).
+    ///
+    /// Expired or revoked grants send the user back to the provider, an unavailable provider or
+    /// network failures ask to retry later, and errors caused by the client configuration are
+    /// reported as internal errors.
+    pub fn default_action(&self) -> ErrorAction {
+        match self {
+            OpenIdError::TokenExchange {
+                provider_error: Some(provider_error),
+                ..
+            } => match provider_error.error.as_str() {
+                "invalid_grant" | "invalid_token" => ErrorAction::Reauthenticate,
+                "temporarily_unavailable" | "server_error" => {
+                    ErrorAction::RetryLater(DEFAULT_RETRY_AFTER)
+                }
+                "invalid_client"
+                | "unauthorized_client"
+                | "unsupported_grant_type"
+                | "invalid_scope" => ErrorAction::InternalError,
+                _ => ErrorAction::BadRequest,
+            },
+            OpenIdError::TokenExchange { status: None, .. } => {
+                ErrorAction::RetryLater(DEFAULT_RETRY_AFTER)
+            }
+            OpenIdError::TokenExchange {
+                status: Some(status),
+                ..
+            }
+            | OpenIdError::Http { status } => match status {
+                500.. => ErrorAction::RetryLater(DEFAULT_RETRY_AFTER),
+                401 => ErrorAction::Reauthenticate,
+                _ => ErrorAction::BadRequest,
+            },
+            OpenIdError::Discovery(_) | OpenIdError::UserInfo(_) | OpenIdError::SessionStore(_) => {
+                ErrorAction::RetryLater(DEFAULT_RETRY_AFTER)
+            }
+            OpenIdError::RefreshTokenRevoked(_) | OpenIdError::SessionExpired(_) => {
+                ErrorAction::Reauthenticate
+            }
+            OpenIdError::Config(_) | OpenIdError::Header(_) | OpenIdError::UnsupportedGrant(_) => {
+                ErrorAction::InternalError
+            }
+            OpenIdError::Verification(_)
+            | OpenIdError::IdToken(_)
+            | OpenIdError::Authorization(_)
+            | OpenIdError::MissingClaims(_)
+            | OpenIdError::MalformedCookie(_) => ErrorAction::BadRequest,
+        }
+    }
+}
+
+impl From<UserInfoError<HttpClientError>> for OpenIdError {
+    fn from(err: UserInfoError<HttpClientError>) -> Self {
+        match err {
+            UserInfoError::Response(status, _, _) => OpenIdError::Http {
+                status: status.as_u16(),
+            },
+            err => OpenIdError::UserInfo(Box::new(err)),
+        }
+    }
+}
+
+/// Answers with the status of [`OpenIdError::default_action`] and a generic body.
+///
+/// The error itself is only logged, it may contain provider urls or token material.
+impl ResponseError for OpenIdError {
+    fn status_code(&self) -> StatusCode {
+        match self.default_action() {
+            ErrorAction::Reauthenticate => StatusCode::UNAUTHORIZED,
+            ErrorAction::RetryLater(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorAction::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorAction::BadRequest => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        // Raised from application handlers, out of reach of the client's policy.
+        let category = match self.default_action() {
+            ErrorAction::RetryLater(_) => LogCategory::IdpError,
+            _ => LogCategory::LoginFailure,
+        };
+        LogPolicy::default().log(category, format_args!("OpenID error: {}", self));
+        let mut resp = HttpResponse::build(self.status_code());
+        if let ErrorAction::RetryLater(retry_after) = self.default_action() {
+            resp.insert_header((RETRY_AFTER, retry_after.as_secs().to_string()));
+        }
+        resp.body(
+            self.status_code()
+                .canonical_reason()
+                .unwrap_or("authentication failed"),
+        )
+    }
+}
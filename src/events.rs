@@ -0,0 +1,130 @@
+//! Hooks counting what the middleware and the endpoints do, e.g. for metrics dashboards.
+
+use std::time::Duration;
+
+/// Where an [`AuthEvents`] event happened.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct EventContext<'a> {
+    /// The id of the client's [`Realm`](crate::Realm), telling apart the clients of several
+    /// providers or realms.
+    pub realm: &'a str,
+    /// The path of the request, `None` for events of the client outside of a request, e.g. a
+    /// refresh or a userinfo request.
+    pub path: Option<&'a str>,
+}
+
+/// Why the callback could not log a user in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CallbackFailure {
+    /// The provider sent the user back with an error, e.g. `access_denied`.
+    ProviderError,
+    /// The callback had neither a code nor an error.
+    MissingCode,
+    /// The nonce cookie of the login was missing, e.g. expired.
+    MissingNonce,
+    /// The login took longer than the [`login_window`](crate::OpenIdBuilder::login_window).
+    ExpiredLogin,
+    /// The state was not issued to this browser.
+    InvalidState,
+    /// The provider refused the code or could not be reached.
+    TokenExchange,
+    /// The ID token or its token hashes did not verify.
+    InvalidIdToken,
+    /// The tokens lacked an essential claim.
+    MissingClaims,
+    /// The user logged in at the provider longer than the
+    /// [`session_absolute_lifetime`](crate::OpenIdBuilder::session_absolute_lifetime) ago.
+    StaleLogin,
+    /// The [`LoginHook`](crate::LoginHook) refused the login.
+    Denied,
+    /// The session could not be stored or its cookies built.
+    Internal,
+}
+
+impl CallbackFailure {
+    /// A stable name of the failure, e.g. as a metrics label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CallbackFailure::ProviderError => "provider_error",
+            CallbackFailure::MissingCode => "missing_code",
+            CallbackFailure::MissingNonce => "missing_nonce",
+            CallbackFailure::ExpiredLogin => "expired_login",
+            CallbackFailure::InvalidState => "invalid_state",
+            CallbackFailure::TokenExchange => "token_exchange",
+            CallbackFailure::InvalidIdToken => "invalid_id_token",
+            CallbackFailure::MissingClaims => "missing_claims",
+            CallbackFailure::StaleLogin => "stale_login",
+            CallbackFailure::Denied => "denied",
+            CallbackFailure::Internal => "internal",
+        }
+    }
+}
+
+/// Where the claims of an access token came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ClaimsSource {
+    /// The userinfo endpoint of the provider.
+    UserInfo,
+    /// The userinfo cache, see
+    /// [`OpenIdBuilder::userinfo_cache`](crate::OpenIdBuilder::userinfo_cache).
+    Cache,
+    /// The access token itself, see [`ValidationMode::Local`](crate::ValidationMode::Local).
+    AccessToken,
+    /// The introspection endpoint of the provider, see
+    /// [`ValidationMode::Introspection`](crate::ValidationMode::Introspection).
+    Introspection,
+    /// The access tokens the provider rejected recently, see
+    /// [`OpenIdBuilder::negative_cache`](crate::OpenIdBuilder::negative_cache). Always rejected.
+    NegativeCache,
+}
+
+impl ClaimsSource {
+    /// A stable name of the source, e.g. as a metrics label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ClaimsSource::UserInfo => "userinfo",
+            ClaimsSource::Cache => "cache",
+            ClaimsSource::AccessToken => "access_token",
+            ClaimsSource::Introspection => "introspection",
+            ClaimsSource::NegativeCache => "negative_cache",
+        }
+    }
+}
+
+/// Told about logins, refreshes and token validations, e.g. to count them with the `metrics`
+/// or `prometheus` crates. Set with
+/// [`OpenIdBuilder::auth_events`](crate::OpenIdBuilder::auth_events), every method does nothing
+/// by default.
+///
+/// The methods are called on the request's task, they should not block.
+pub trait AuthEvents: Send + Sync {
+    /// A user was sent to the provider to log in, `context.path` being the page to return to.
+    fn on_login_redirect(&self, _context: &EventContext<'_>) {}
+
+    /// The callback logged a user in.
+    fn on_callback_success(&self, _context: &EventContext<'_>) {}
+
+    /// The callback could not log a user in.
+    fn on_callback_failure(&self, _context: &EventContext<'_>, _reason: CallbackFailure) {}
+
+    /// The claims of an access token were read from `source`, `ok` unless the token was
+    /// rejected or the source failed.
+    fn on_token_validation(&self, _context: &EventContext<'_>, _source: ClaimsSource, _ok: bool) {}
+
+    /// The userinfo endpoint answered after `duration`, `ok` unless the request failed.
+    fn on_userinfo_call(&self, _context: &EventContext<'_>, _duration: Duration, _ok: bool) {}
+
+    /// A refresh token was exchanged, `ok` unless the provider refused it or failed.
+    fn on_refresh(&self, _context: &EventContext<'_>, _ok: bool) {}
+
+    /// A user with a session logged out at `/logout` or `/logout/local`.
+    fn on_logout(&self, _context: &EventContext<'_>) {}
+}
+
+/// The default [`AuthEvents`], ignoring every event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoEvents;
+
+impl AuthEvents for NoEvents {}
@@ -0,0 +1,395 @@
+//! First-party sessions: after the login the app hands the browser its own short-lived JWT instead
+//! of session cookies, the provider's tokens stay in the session store.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{CacheControl, CacheDirective, AUTHORIZATION, RETRY_AFTER};
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ed25519_dalek::{Signer, Verifier};
+use hmac::{Hmac, Mac};
+use openidconnect::core::CoreGenderClaim;
+use openidconnect::{ClaimsVerificationError, UserInfoClaims};
+use secrecy::{ExposeSecret, SecretVec};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::authorization::roles_in;
+use crate::credentials::{rejected, Credential, CredentialExtractor};
+use crate::error::{ErrorAction, OpenIdError, Result};
+use crate::logging::LogCategory;
+use crate::openid::{OpenID, OtherClaims};
+use crate::openid_middleware::{stored_max_age, AuthenticatedUser, RealmClient, RegisteredClient};
+use crate::realm::Realm;
+
+/// Path of the refresh endpoint, below the realm's path.
+pub(crate) const REFRESH_ROUTE: &str = "/session/refresh";
+
+/// How the session of a logged in user is kept, set with
+/// [`OpenIdBuilder::session_format`](crate::OpenIdBuilder::session_format).
+#[derive(Clone, Debug, Default)]
+pub enum SessionFormat {
+    /// Session cookies, or the session id cookie with a [session store](crate::SessionStore).
+    #[default]
+    Cookies,
+    /// A JWT signed with `key` and valid for `ttl`, carrying the `sub`, `email` and roles of the
+    /// user, sent back by the app as `Authorization: Bearer`.
+    ///
+    /// The callback redirects to the page the login started at with the token in the fragment,
+    /// `#session_token=<jwt>&token_type=Bearer&expires_in=<seconds>`, and sets no session cookie.
+    /// The middleware validates the token without asking the provider; `POST /session/refresh`
+    /// answers a new one for a token that expired, as long as the session in the store lasts.
+    /// Needs a [`session_store`](crate::OpenIdBuilder::session_store) for the provider's tokens.
+    FirstPartyJwt { key: SessionKey, ttl: Duration },
+}
+
+/// The key signing the first-party session tokens.
+#[derive(Clone)]
+pub enum SessionKey {
+    /// HMAC-SHA256, only the app can verify the tokens.
+    Hs256(Arc<SecretVec<u8>>),
+    /// Ed25519, the tokens can also be verified by other services with the
+    /// [`verifying_key`](Self::verifying_key).
+    EdDsa(Arc<ed25519_dalek::SigningKey>),
+}
+
+/// The algorithm, never the key.
+impl fmt::Debug for SessionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.alg())
+    }
+}
+
+impl SessionKey {
+    /// An HMAC-SHA256 key, at least 32 random bytes.
+    pub fn hs256(key: impl Into<Vec<u8>>) -> Self {
+        SessionKey::Hs256(Arc::new(SecretVec::new(key.into())))
+    }
+
+    /// The Ed25519 key made from the 32 bytes of `secret`.
+    pub fn ed_dsa(secret: &[u8; 32]) -> Self {
+        SessionKey::EdDsa(Arc::new(ed25519_dalek::SigningKey::from_bytes(secret)))
+    }
+
+    /// The public key verifying the tokens, `None` for HMAC keys.
+    pub fn verifying_key(&self) -> Option<ed25519_dalek::VerifyingKey> {
+        match self {
+            SessionKey::Hs256(_) => None,
+            SessionKey::EdDsa(key) => Some(key.verifying_key()),
+        }
+    }
+
+    fn alg(&self) -> &'static str {
+        match self {
+            SessionKey::Hs256(_) => "HS256",
+            SessionKey::EdDsa(_) => "EdDSA",
+        }
+    }
+
+    fn mac(key: &SecretVec<u8>) -> Hmac<Sha256> {
+        // HMAC takes keys of any length.
+        Hmac::new_from_slice(key.expose_secret()).expect("HMAC accepts every key length")
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            SessionKey::Hs256(key) => {
+                let mut mac = SessionKey::mac(key);
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            SessionKey::EdDsa(key) => key.sign(message).to_bytes().to_vec(),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match self {
+            SessionKey::Hs256(key) => {
+                let mut mac = SessionKey::mac(key);
+                mac.update(message);
+                mac.verify_slice(signature).is_ok()
+            }
+            SessionKey::EdDsa(key) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verifying_key().verify(message, &signature).is_ok()),
+        }
+    }
+}
+
+/// The claims of a first-party session token.
+#[derive(Debug, Deserialize, Serialize)]
+struct SessionClaims {
+    /// The realm of the client that issued the token.
+    aud: String,
+    sub: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    roles: Option<Vec<String>>,
+    /// The id of the session in the store, for the refresh endpoint.
+    sid: String,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+}
+
+/// A token for the session stored under `session_id`, with the user from the ID token `claims`
+/// the session was logged in with.
+pub(crate) fn issue(
+    client: &OpenID,
+    key: &SessionKey,
+    ttl: Duration,
+    session_id: &str,
+    claims: &Value,
+) -> Result<String> {
+    let invalid = |reason: &str| {
+        OpenIdError::from(ClaimsVerificationError::Other(format!(
+            "cannot issue a session token: {}",
+            reason
+        )))
+    };
+    let now = unix_time(client.now());
+    let session_claims = SessionClaims {
+        aud: client.realm().id().to_string(),
+        sub: claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid("the ID token has no subject"))?
+            .to_string(),
+        email: claims
+            .get("email")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        roles: client
+            .roles_claim()
+            .and_then(|roles_claim| roles_in(claims, roles_claim)),
+        sid: session_id.to_string(),
+        iat: now,
+        exp: now + ttl.as_secs(),
+    };
+    let header = json!({ "alg": key.alg(), "typ": "JWT" });
+    let payload = serde_json::to_vec(&session_claims).map_err(|err| invalid(&err.to_string()))?;
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(payload)
+    );
+    let signature = URL_SAFE_NO_PAD.encode(key.sign(message.as_bytes()));
+    Ok(format!("{}.{}", message, signature))
+}
+
+/// The claims of `token` if `key` signed it for `client`'s realm, whether it expired or not.
+fn verify(client: &OpenID, key: &SessionKey, token: &str) -> Option<SessionClaims> {
+    let (message, signature) = token.rsplit_once('.')?;
+    let (header, payload) = message.split_once('.')?;
+    let header: Header = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+    // The algorithm of the key, never the one the token names.
+    if header.alg != key.alg()
+        || !key.verify(message.as_bytes(), &URL_SAFE_NO_PAD.decode(signature).ok()?)
+    {
+        return None;
+    }
+    let claims: SessionClaims =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+    (claims.aud == client.realm().id()).then_some(claims)
+}
+
+/// The user of a first-party session `token`, checked without the provider.
+pub(crate) fn session_user(
+    client: &OpenID,
+    token: &str,
+) -> std::result::Result<AuthenticatedUser<OtherClaims>, Error> {
+    let invalid = |reason: &str| {
+        rejected(
+            client,
+            "Bearer error=\"invalid_token\"",
+            &format!("Rejected the session token: {}", reason),
+        )
+    };
+    let SessionFormat::FirstPartyJwt { key, .. } = client.session_format() else {
+        return Err(invalid("first-party sessions are not enabled"));
+    };
+    tracing::Span::current().record("validation", "first_party");
+    let claims = verify(client, key, token).ok_or_else(|| invalid("invalid signature"))?;
+    if claims.exp <= unix_time(client.now()) {
+        return Err(invalid("expired"));
+    }
+    let mut user = json!({ "sub": claims.sub });
+    if let Some(email) = claims.email {
+        user["email"] = Value::String(email);
+    }
+    if let (Some(roles), Some(roles_claim)) = (claims.roles, client.roles_claim()) {
+        insert_at(&mut user, roles_claim, json!(roles));
+    }
+    let user = serde_json::to_vec(&user).map_err(|err| invalid(&err.to_string()))?;
+    let access =
+        UserInfoClaims::<OtherClaims, CoreGenderClaim>::from_json::<serde_json::Error>(&user, None)
+            .map_err(|err| invalid(&err.to_string()))?;
+    Ok(AuthenticatedUser::new(access))
+}
+
+/// Sets the claim at `path`, names separated by dots, creating the objects on the way.
+fn insert_at(claims: &mut Value, path: &str, value: Value) {
+    let mut claims = claims;
+    let mut names = path.split('.').peekable();
+    while let Some(name) = names.next() {
+        let Value::Object(object) = claims else {
+            return;
+        };
+        if names.peek().is_none() {
+            object.insert(name.to_string(), value);
+            return;
+        }
+        claims = object
+            .entry(name.to_string())
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+}
+
+/// `Authorization: Bearer <session token>`, the default credential of clients with
+/// [`SessionFormat::FirstPartyJwt`]. Left to the refresh endpoint on its path, where it may have
+/// expired.
+pub struct FirstPartySession;
+
+impl CredentialExtractor for FirstPartySession {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        let client = req.extensions().get::<RealmClient>()?.0.clone();
+        if req.path() == refresh_path(client.realm()) {
+            return None;
+        }
+        let token = bearer(req.request())?;
+        Some(Credential::FirstParty(token.to_string()))
+    }
+}
+
+/// The id of the stored session of the session token in the `Authorization` header, expired or
+/// not, for the logout to end it: first-party sessions have no session id cookie.
+pub(crate) fn session_id(client: &OpenID, req: &HttpRequest) -> Option<String> {
+    let SessionFormat::FirstPartyJwt { key, .. } = client.session_format() else {
+        return None;
+    };
+    Some(verify(client, key, bearer(req)?)?.sid)
+}
+
+fn refresh_path(realm: &Realm) -> String {
+    format!("{}{}", realm.path().trim_end_matches('/'), REFRESH_ROUTE)
+}
+
+fn bearer(req: &HttpRequest) -> Option<&str> {
+    let token = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    Some(token.strip_prefix("Bearer ")?.trim())
+}
+
+#[derive(Serialize)]
+struct RefreshResponse {
+    session_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+}
+
+/// Answers a new session token for the expired or expiring one in the `Authorization` header,
+/// renewing the stored session with its refresh token. The stored session ends when the
+/// provider refuses.
+pub(crate) async fn refresh_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+) -> actix_web::Result<HttpResponse> {
+    let client = &open_id_client.0;
+    let (SessionFormat::FirstPartyJwt { key, ttl }, Some(store)) =
+        (client.session_format(), client.session_store())
+    else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let rejected = |reason: &str| {
+        client.log_policy().log(
+            LogCategory::Refresh,
+            format_args!("Refused to renew the session token: {}", reason),
+        );
+        Ok(HttpResponse::Unauthorized()
+            .insert_header(("WWW-Authenticate", "Bearer error=\"invalid_token\""))
+            .finish())
+    };
+    let Some(claims) = bearer(&req).and_then(|token| verify(client, key, token)) else {
+        return rejected("invalid session token");
+    };
+    let now = client.now();
+    let session = store
+        .get(&claims.sid)
+        .await?
+        .filter(|session| session.expires_at().is_none_or(|at| now < at));
+    let Some(session) = session else {
+        return rejected("the session ended");
+    };
+    let session = match session.refresh_token() {
+        Some(refresh_token) => match client.refresh(&refresh_token).await {
+            Ok(tokens) => {
+                let max_age = client.cookie_config().session_max_age(
+                    tokens.expires_in,
+                    true,
+                    tokens.refresh_expires_in,
+                );
+                let session = session.refreshed(&tokens, now, stored_max_age(max_age));
+                store.insert(&claims.sid, session.clone()).await?;
+                session
+            }
+            Err(err) => {
+                if let ErrorAction::RetryLater(retry_after) = client.error_action(&err) {
+                    client.log_policy().log(
+                        LogCategory::IdpError,
+                        format_args!("Could not renew the session: {}", err),
+                    );
+                    return Ok(HttpResponse::ServiceUnavailable()
+                        .insert_header((RETRY_AFTER, retry_after.as_secs().to_string()))
+                        .finish());
+                }
+                store.remove(&claims.sid).await?;
+                return rejected(&format!("the provider refused the refresh token: {}", err));
+            }
+        },
+        // Without a refresh token the session lasts as long as its access token.
+        None if session
+            .access_token_expires_at()
+            .is_none_or(|expires_at| now < expires_at) =>
+        {
+            session
+        }
+        None => return rejected("the access token expired"),
+    };
+    let session_token = issue(client, key, *ttl, &claims.sid, session.claims())?;
+    client.log_policy().log(
+        LogCategory::Refresh,
+        format_args!("Renewed the session token of {}", claims.sub),
+    );
+    Ok(HttpResponse::Ok()
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(RefreshResponse {
+            session_token,
+            token_type: "Bearer",
+            expires_in: ttl.as_secs(),
+        }))
+}
+
+/// Where the callback sends the user with a new session token: `return_path` with the token in
+/// the fragment, which browsers never send to servers.
+pub(crate) fn login_redirect(return_path: &str, session_token: &str, ttl: Duration) -> String {
+    format!(
+        "{}#session_token={}&token_type=Bearer&expires_in={}",
+        return_path,
+        session_token,
+        ttl.as_secs()
+    )
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
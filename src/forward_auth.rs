@@ -0,0 +1,112 @@
+//! An endpoint deciding about the requests of other apps for the ingress, e.g. Traefik's
+//! ForwardAuth or nginx's `auth_request`.
+
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{error, web, HttpRequest, HttpResponse, ResponseError};
+use url::Url;
+
+use crate::identity_headers::IdentityHeaders;
+use crate::logging::LogCategory;
+use crate::openid_middleware::{session_user, AuthenticationRequired, RegisteredClient};
+
+/// The configuration of the `/forward_auth` endpoint, registered with
+/// [`configure_forward_auth`](crate::ActixWebOpenId::configure_forward_auth).
+///
+/// The ingress calls the endpoint with the cookies of the original request, and describes it
+/// with `X-Forwarded-Proto` (`https` if absent), `X-Forwarded-Host` and `X-Forwarded-Uri`. Users
+/// with a session get `200 OK` and the identity headers, others `401 Unauthorized` with the
+/// login URL as `Location` and the nonce cookie. After the login, the callback sends them back
+/// to the original URL, so the client's redirect URL must be served on a host sharing the
+/// cookies with the protected apps.
+#[derive(Clone, Debug)]
+pub struct ForwardAuth {
+    hosts: Vec<String>,
+    identity_headers: IdentityHeaders,
+}
+
+impl ForwardAuth {
+    /// Decides for the apps at `hosts`, e.g. `app.example.com` or `localhost:8080`. Requests for
+    /// other hosts answer `400 Bad Request`, the login must not redirect anywhere else.
+    pub fn new(hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        ForwardAuth {
+            hosts: hosts
+                .into_iter()
+                .map(|host| host.into().to_ascii_lowercase())
+                .collect(),
+            identity_headers: IdentityHeaders::default(),
+        }
+    }
+
+    /// The headers answered for users with a session, [`IdentityHeaders::default`] by default.
+    pub fn identity_headers(mut self, identity_headers: IdentityHeaders) -> Self {
+        self.identity_headers = identity_headers;
+        self
+    }
+
+    /// The URL of the request the ingress asks about, if it is for one of the hosts.
+    fn original_url(&self, req: &HttpRequest) -> Option<String> {
+        let header = |name: &str| req.headers().get(name)?.to_str().ok();
+        let proto = header("x-forwarded-proto").unwrap_or("https");
+        let host = header("x-forwarded-host")?.to_ascii_lowercase();
+        let uri = header("x-forwarded-uri").unwrap_or("/");
+        let known = self.hosts.contains(&host);
+        (known && matches!(proto, "http" | "https") && uri.starts_with('/'))
+            .then(|| format!("{}://{}{}", proto, host, uri))
+    }
+
+    /// Whether the callback may send the user back to `url`, an URL of one of the hosts.
+    pub(crate) fn is_app_url(&self, url: &str) -> bool {
+        let Ok(url) = Url::parse(url) else {
+            return false;
+        };
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return false,
+        };
+        matches!(url.scheme(), "http" | "https") && self.hosts.contains(&host)
+    }
+}
+
+pub(crate) async fn forward_auth_endpoint(
+    req: HttpRequest,
+    client: RegisteredClient,
+    forward_auth: web::Data<ForwardAuth>,
+) -> HttpResponse {
+    let Some(original_url) = forward_auth.original_url(&req) else {
+        client.log_policy().log(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("Forward auth for an unknown original URL"),
+        );
+        return error::ErrorBadRequest("unknown original URL").error_response();
+    };
+    let client = client.0;
+    let user = match session_user(&client, &ServiceRequest::from_request(req)).await {
+        Ok(user) => user,
+        Err(err) => return err.error_response(),
+    };
+    let required = match user {
+        Some(Ok(user)) => {
+            let mut response = HttpResponse::Ok();
+            for header in forward_auth.identity_headers.values(&client, &user) {
+                response.insert_header(header);
+            }
+            return response.finish();
+        }
+        Some(Err(required)) => required.returning_to(&original_url),
+        None => {
+            client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("No session for {}, answering forward auth", original_url),
+            );
+            AuthenticationRequired::new(&client, &original_url, None)
+        }
+    };
+    // The ingress expects a 401, the login URL stays in `Location` for it to send the user to.
+    let mut response = required.error_response();
+    if response.status() == StatusCode::FOUND {
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+    }
+    response
+}
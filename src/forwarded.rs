@@ -0,0 +1,94 @@
+//! The scheme and host browsers reach the app at through a reverse proxy.
+
+use std::net::IpAddr;
+
+use actix_web::http::header::HeaderMap;
+use actix_web::HttpRequest;
+use url::Url;
+
+/// The peers whose `Forwarded`, `X-Forwarded-Proto` and `X-Forwarded-Host` headers are
+/// believed, see [`OpenIdBuilder::trusted_proxies`](crate::OpenIdBuilder::trusted_proxies).
+///
+/// Anyone reaching the app directly can send these headers, only trust the peers that are
+/// proxies setting them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TrustedProxies {
+    /// No peer, the configured redirect url is used as is.
+    #[default]
+    None,
+    /// Every peer, for apps only reachable through the proxy.
+    Any,
+    /// The peers with these addresses.
+    Peers(Vec<IpAddr>),
+}
+
+impl TrustedProxies {
+    fn trusts(&self, req: &HttpRequest) -> bool {
+        match self {
+            TrustedProxies::None => false,
+            TrustedProxies::Any => true,
+            TrustedProxies::Peers(peers) => req
+                .peer_addr()
+                .is_some_and(|peer| peers.contains(&peer.ip())),
+        }
+    }
+
+    /// Whether `req` reached the proxy in front of it over https, `false` if the peer is not
+    /// trusted or forwarded no scheme.
+    pub(crate) fn forwarded_https(&self, req: &HttpRequest) -> bool {
+        self.trusts(req) && forwarded(req.headers()).0 == Some("https")
+    }
+
+    /// `url` at the scheme and host the proxy in front of `req` was reached at, `None` if the
+    /// peer is not trusted or forwarded neither.
+    pub(crate) fn external_url(&self, req: &HttpRequest, url: &Url) -> Option<Url> {
+        if !self.trusts(req) {
+            return None;
+        }
+        let (proto, host) = forwarded(req.headers());
+        if proto.is_none() && host.is_none() {
+            return None;
+        }
+        let proto = proto.unwrap_or(url.scheme());
+        let host = match host {
+            Some(host) => host.to_string(),
+            None => url[url::Position::BeforeHost..url::Position::AfterPort].to_string(),
+        };
+        // Only a scheme and an authority, not a path or credentials smuggled in.
+        if !matches!(proto, "http" | "https")
+            || host.is_empty()
+            || host.contains(['/', '?', '#', '@', '\\', ' '])
+        {
+            return None;
+        }
+        let mut external = Url::parse(&format!("{}://{}", proto, host)).ok()?;
+        external.set_path(url.path());
+        external.set_query(url.query());
+        Some(external)
+    }
+}
+
+/// The scheme and host of the last hop, from `Forwarded` (RFC 7239) or the `X-Forwarded-Proto`
+/// and `X-Forwarded-Host` headers. The trusted peer appends its entry, the ones before it come
+/// from the client or proxies nobody vouches for.
+fn forwarded(headers: &HeaderMap) -> (Option<&str>, Option<&str>) {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next_back())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    if let Some(last) = header("forwarded") {
+        let pair = |name: &str| {
+            last.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case(name)
+                    .then(|| value.trim_matches('"'))
+            })
+        };
+        return (pair("proto"), pair("host"));
+    }
+    (header("x-forwarded-proto"), header("x-forwarded-host"))
+}
@@ -0,0 +1,187 @@
+//! Whether the provider can be reached well enough to authenticate users, for readiness probes.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::{Serialize, Serializer};
+
+use crate::circuit_breaker::CircuitState;
+use crate::clock::{system_time, Clock};
+use crate::error::Result;
+use crate::openid_middleware::RegisteredClient;
+
+/// When [`OpenID::health`](crate::openid::OpenID::health) reports the client as unhealthy,
+/// besides an open circuit breaker or a JWKS without keys.
+///
+/// The documents are only as old as the last
+/// [`refresh_provider`](crate::openid::OpenID::refresh_provider) or
+/// [`probe`](crate::openid::OpenID::probe) that reached the provider, limits only make sense
+/// when one of them runs periodically. There is no limit by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HealthThresholds {
+    max_discovery_age: Option<Duration>,
+    max_jwks_age: Option<Duration>,
+    max_failing_for: Option<Duration>,
+}
+
+impl HealthThresholds {
+    pub fn max_discovery_age(mut self, max_age: Duration) -> Self {
+        self.max_discovery_age = Some(max_age);
+        self
+    }
+
+    pub fn max_jwks_age(mut self, max_age: Duration) -> Self {
+        self.max_jwks_age = Some(max_age);
+        self
+    }
+
+    /// Unhealthy once every request to the provider failed for longer than `duration`, counting
+    /// from the first failure after the last success.
+    pub fn max_failing_for(mut self, duration: Duration) -> Self {
+        self.max_failing_for = Some(duration);
+        self
+    }
+}
+
+/// The state of the client's connection to the provider, from what it last saw. Durations are
+/// serialized as seconds, times as unix seconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    /// Time since the provider last sent or confirmed its discovery document.
+    #[serde(serialize_with = "as_secs")]
+    pub discovery_age: Option<Duration>,
+    #[serde(serialize_with = "as_secs")]
+    pub jwks_age: Option<Duration>,
+    /// Why the last refresh of the documents failed, `None` once one succeeded.
+    pub last_error: Option<String>,
+    pub breaker_state: CircuitState,
+    /// When a request to the provider, e.g. for a token, userinfo or the JWKS, was last answered.
+    /// Answers with a client error, e.g. a refused refresh token, count as answered.
+    #[serde(serialize_with = "as_unix_secs")]
+    pub last_success: Option<SystemTime>,
+    /// When a request to the provider last went unanswered or was answered with `429` or a
+    /// server error.
+    #[serde(serialize_with = "as_unix_secs")]
+    pub last_failure: Option<SystemTime>,
+    /// Why that request failed.
+    pub last_failure_error: Option<String>,
+}
+
+fn as_secs<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    duration
+        .map(|duration| duration.as_secs())
+        .serialize(serializer)
+}
+
+fn as_unix_secs<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    time.map(|time| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    })
+    .serialize(serializer)
+}
+
+/// The outcomes of the requests to the provider.
+#[derive(Default)]
+struct ProviderCalls {
+    last_success: Option<SystemTime>,
+    last_failure: Option<(SystemTime, String)>,
+    /// The first failure after the last success.
+    failing_since: Option<SystemTime>,
+}
+
+/// What the client keeps between reports.
+pub(crate) struct Health {
+    thresholds: HealthThresholds,
+    clock: Arc<dyn Clock>,
+    last_error: Mutex<Option<String>>,
+    calls: Mutex<ProviderCalls>,
+}
+
+impl Health {
+    pub(crate) fn new(thresholds: HealthThresholds, clock: Arc<dyn Clock>) -> Self {
+        Health {
+            thresholds,
+            clock,
+            last_error: Mutex::default(),
+            calls: Mutex::default(),
+        }
+    }
+
+    /// Records the outcome of a refresh of the documents.
+    pub(crate) fn record<T>(&self, result: &Result<T>) {
+        *self.last_error.lock().unwrap() = result.as_ref().err().map(ToString::to_string);
+    }
+
+    /// Records the outcome of a request to the provider, the error of a failed one.
+    pub(crate) fn record_call(&self, outcome: std::result::Result<(), String>) {
+        let now = system_time(self.clock.as_ref());
+        let mut calls = self.calls.lock().unwrap();
+        match outcome {
+            Ok(()) => {
+                calls.last_success = Some(now);
+                calls.failing_since = None;
+            }
+            Err(err) => {
+                calls.last_failure = Some((now, err));
+                calls.failing_since.get_or_insert(now);
+            }
+        }
+    }
+
+    pub(crate) fn report(
+        &self,
+        discovery_age: Option<Duration>,
+        jwks_age: Option<Duration>,
+        has_keys: bool,
+        breaker_state: CircuitState,
+    ) -> HealthReport {
+        let within = |age: Option<Duration>, max_age: Option<Duration>| match (age, max_age) {
+            (_, None) => true,
+            (Some(age), Some(max_age)) => age <= max_age,
+            (None, Some(_)) => false,
+        };
+        let calls = self.calls.lock().unwrap();
+        let failing_for = calls.failing_since.map(|since| {
+            system_time(self.clock.as_ref())
+                .duration_since(since)
+                .unwrap_or_default()
+        });
+        HealthReport {
+            healthy: has_keys
+                && breaker_state != CircuitState::Open
+                && within(discovery_age, self.thresholds.max_discovery_age)
+                && within(jwks_age, self.thresholds.max_jwks_age)
+                && failing_for.is_none_or(|failing_for| {
+                    within(Some(failing_for), self.thresholds.max_failing_for)
+                }),
+            discovery_age,
+            jwks_age,
+            last_error: self.last_error.lock().unwrap().clone(),
+            breaker_state,
+            last_success: calls.last_success,
+            last_failure: calls.last_failure.as_ref().map(|(at, _)| *at),
+            last_failure_error: calls.last_failure.as_ref().map(|(_, err)| err.clone()),
+        }
+    }
+}
+
+/// Answers the client's [`HealthReport`], with `503 Service Unavailable` when it is unhealthy.
+pub(crate) async fn health_endpoint(client: RegisteredClient) -> HttpResponse {
+    let report = client.health();
+    let status = match report.healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    HttpResponse::build(status).json(report)
+}
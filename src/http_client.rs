@@ -0,0 +1,224 @@
+//! The pooled HTTP client every request to the provider goes through.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use openidconnect::http::{Method, StatusCode};
+use openidconnect::reqwest::{AsyncHttpClientError, Error};
+use openidconnect::{HttpRequest, HttpResponse};
+use tracing::Instrument;
+
+use crate::health::Health;
+
+/// Settings of the client built when none is injected.
+#[derive(Clone, Debug)]
+pub(crate) struct PoolConfig {
+    pub(crate) max_idle_per_host: usize,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    /// reqwest's pool defaults, with timeouts so an unreachable provider does not stall requests.
+    fn default() -> Self {
+        PoolConfig {
+            max_idle_per_host: usize::MAX,
+            idle_timeout: Some(Duration::from_secs(90)),
+            connect_timeout: Some(Duration::from_secs(10)),
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+/// How often requests that change nothing at the provider are repeated when it is unreachable
+/// or answers `502`, `503` or `504`: the discovery document, the JWKS and userinfo. The delay
+/// doubles after every attempt, up to the maximum backoff.
+///
+/// ```ignore
+/// ActixWebOpenId::builder().retry_policy(RetryPolicy::exponential(3))
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Sends every request once, the default.
+    pub fn none() -> Self {
+        RetryPolicy::exponential(0)
+    }
+
+    /// Repeats a request up to `max_retries` times, after 200 milliseconds at first and at most
+    /// 5 seconds apart.
+    pub fn exponential(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// The delay before the retry `attempt`, counting from 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
+
+/// A `reqwest::Client` shared by the clones of an [`OpenID`](crate::openid::OpenID), so
+/// discovery, token, userinfo and JWKS requests reuse its connections.
+#[derive(Clone)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    retry: RetryPolicy,
+    health: Arc<Health>,
+}
+
+impl HttpClient {
+    /// Uses the injected `client` as is, or builds one from `pool`. Either retries with `retry`
+    /// and records the outcome of every request in `health`.
+    pub(crate) fn new(
+        client: Option<reqwest::Client>,
+        pool: &PoolConfig,
+        retry: RetryPolicy,
+        health: Arc<Health>,
+    ) -> Result<Self, AsyncHttpClientError> {
+        let client = match client {
+            Some(client) => client,
+            None => {
+                let mut builder = reqwest::Client::builder()
+                    // Following redirects opens the client up to SSRF, as in oauth2's client.
+                    .redirect(reqwest::redirect::Policy::none())
+                    .pool_max_idle_per_host(pool.max_idle_per_host)
+                    .pool_idle_timeout(pool.idle_timeout);
+                if let Some(connect_timeout) = pool.connect_timeout {
+                    builder = builder.connect_timeout(connect_timeout);
+                }
+                if let Some(timeout) = pool.timeout {
+                    builder = builder.timeout(timeout);
+                }
+                builder.build().map_err(Error::Reqwest)?
+            }
+        };
+        Ok(HttpClient {
+            client,
+            retry,
+            health,
+        })
+    }
+
+    /// Sends `request`, the drop-in for oauth2's `async_http_client`. GET requests are repeated
+    /// as the [`RetryPolicy`] says, the others are sent once: a token request may have been
+    /// handled before the connection broke.
+    ///
+    /// Runs in a `provider_request` span with the method and the URL without its query, recording
+    /// the status, the attempts and the latency once answered. The outcome is recorded for the
+    /// [`health`](crate::openid::OpenID::health) report, without waiting for a probe.
+    pub(crate) async fn execute(
+        &self,
+        request: HttpRequest,
+    ) -> Result<HttpResponse, AsyncHttpClientError> {
+        let mut url = request.url.clone();
+        url.set_query(None);
+        let span = tracing::debug_span!(
+            "provider_request",
+            method = %request.method,
+            url = %url,
+            status = tracing::field::Empty,
+            attempts = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+        );
+        let started = Instant::now();
+        let (response, attempts) = self
+            .execute_with_retries(&request)
+            .instrument(span.clone())
+            .await;
+        span.record("attempts", attempts);
+        span.record("latency_ms", started.elapsed().as_millis() as u64);
+        if let Ok(response) = &response {
+            span.record("status", response.status_code.as_u16());
+        }
+        self.health.record_call(match &response {
+            Ok(response)
+                if response.status_code.is_server_error()
+                    || response.status_code == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                Err(format!(
+                    "{} {} answered {}",
+                    request.method, url, response.status_code
+                ))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("{} {}: {}", request.method, url, err)),
+        });
+        response
+    }
+
+    async fn execute_with_retries(
+        &self,
+        request: &HttpRequest,
+    ) -> (Result<HttpResponse, AsyncHttpClientError>, u32) {
+        let retries = match request.method {
+            Method::GET => self.retry.max_retries,
+            _ => 0,
+        };
+        let mut attempt = 0;
+        loop {
+            let response = self.send(request).await;
+            let retryable = match &response {
+                Ok(response) => matches!(
+                    response.status_code,
+                    StatusCode::BAD_GATEWAY
+                        | StatusCode::SERVICE_UNAVAILABLE
+                        | StatusCode::GATEWAY_TIMEOUT
+                ),
+                Err(_) => true,
+            };
+            if !retryable || attempt >= retries {
+                return (response, attempt + 1);
+            }
+            attempt += 1;
+            tokio::time::sleep(self.retry.backoff(attempt)).await;
+        }
+    }
+
+    async fn send(&self, request: &HttpRequest) -> Result<HttpResponse, AsyncHttpClientError> {
+        let mut request_builder = self
+            .client
+            .request(request.method.clone(), request.url.as_str())
+            .body(request.body.clone());
+        for (name, value) in &request.headers {
+            request_builder = request_builder.header(name.as_str(), value.as_bytes());
+        }
+        let request = request_builder.build().map_err(Error::Reqwest)?;
+        let response = self.client.execute(request).await.map_err(Error::Reqwest)?;
+        let status_code = response.status();
+        let headers = response.headers().to_owned();
+        let body = response.bytes().await.map_err(Error::Reqwest)?;
+        Ok(HttpResponse {
+            status_code,
+            headers,
+            body: body.to_vec(),
+        })
+    }
+}
@@ -0,0 +1,250 @@
+//! The checks ID tokens go through besides their signature, see [`IdTokenValidation`].
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openidconnect::core::{CoreIdTokenVerifier, CoreJwsSigningAlgorithm};
+use openidconnect::{ClaimsVerificationError, SignatureVerificationError};
+use serde_json::Value;
+
+/// How ID tokens are verified, see [`OpenIdBuilder::id_token_validation`].
+///
+/// ```ignore
+/// // A provider signing with ES256 and adding its API to the audiences.
+/// IdTokenValidation::default()
+///     .signing_algs([CoreJwsSigningAlgorithm::EcdsaP256Sha256])
+///     .multiple_audiences(true)
+/// ```
+///
+/// [`OpenIdBuilder::id_token_validation`]: crate::OpenIdBuilder::id_token_validation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdTokenValidation {
+    signing_algs: Vec<CoreJwsSigningAlgorithm>,
+    multiple_audiences: bool,
+    clock_skew: Duration,
+    require_auth_time: bool,
+}
+
+impl Default for IdTokenValidation {
+    /// RS256 only, the client as the only audience, 60 seconds of clock skew and `auth_time`
+    /// optional.
+    fn default() -> Self {
+        IdTokenValidation {
+            signing_algs: vec![CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256],
+            multiple_audiences: false,
+            clock_skew: Duration::from_secs(60),
+            require_auth_time: false,
+        }
+    }
+}
+
+impl IdTokenValidation {
+    /// The algorithms ID tokens may be signed with, e.g. RS256, ES256 or PS256.
+    pub fn signing_algs(mut self, algs: impl IntoIterator<Item = CoreJwsSigningAlgorithm>) -> Self {
+        self.signing_algs = algs.into_iter().collect();
+        self
+    }
+
+    /// Accepts ID tokens for other audiences besides the client, as long as their `azp` names
+    /// the client. Off by default, such tokens are rejected.
+    pub fn multiple_audiences(mut self, multiple_audiences: bool) -> Self {
+        self.multiple_audiences = multiple_audiences;
+        self
+    }
+
+    /// How far the provider's clock may be off, for the `exp`, `iat` and `nbf` claims.
+    pub fn clock_skew(mut self, clock_skew: Duration) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// Rejects ID tokens without an `auth_time` claim, e.g. when enforcing a `max_age`.
+    pub fn require_auth_time(mut self, require_auth_time: bool) -> Self {
+        self.require_auth_time = require_auth_time;
+        self
+    }
+
+    pub(crate) fn allowed_algs(&self) -> &[CoreJwsSigningAlgorithm] {
+        &self.signing_algs
+    }
+
+    /// `verifier` with these checks at `now`, `None` to accept expired tokens, e.g. of a session.
+    pub(crate) fn verifier<'a>(
+        &self,
+        verifier: CoreIdTokenVerifier<'a>,
+        now: Option<SystemTime>,
+    ) -> CoreIdTokenVerifier<'a> {
+        let multiple_audiences = self.multiple_audiences;
+        let verifier = verifier
+            .set_allowed_algs(self.signing_algs.clone())
+            .set_other_audience_verifier_fn(move |_| multiple_audiences);
+        let Some(now) = now else {
+            return verifier.set_time_fn(|| UNIX_EPOCH.into());
+        };
+        let (earliest, latest) = (now - self.clock_skew, now + self.clock_skew);
+        let verifier = verifier
+            .set_time_fn(move || earliest.into())
+            .set_issue_time_verifier_fn(move |issued_at| {
+                match SystemTime::from(issued_at) > latest {
+                    true => Err("issued in the future".to_string()),
+                    false => Ok(()),
+                }
+            });
+        match self.require_auth_time {
+            true => verifier.set_auth_time_verifier_fn(|auth_time| match auth_time {
+                Some(_) => Ok(()),
+                None => Err("missing".to_string()),
+            }),
+            false => verifier,
+        }
+    }
+
+    /// The checks the verifier of the openidconnect crate does not make, on the `claims` of a
+    /// token it verified.
+    pub(crate) fn check(
+        &self,
+        claims: &Value,
+        client_id: &str,
+        now: Option<SystemTime>,
+    ) -> Result<(), IdTokenError> {
+        if audiences(claims).len() > 1 {
+            let azp = claims.get("azp").and_then(Value::as_str);
+            if azp != Some(client_id) {
+                return Err(IdTokenError::AuthorizedParty(azp.map(str::to_string)));
+            }
+        }
+        if let (Some(now), Some(not_before)) = (now, claims.get("nbf").and_then(Value::as_i64)) {
+            if not_before > unix_time(now + self.clock_skew) {
+                return Err(IdTokenError::NotYetValid {
+                    not_before,
+                    now: unix_time(now),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// What `err` of the openidconnect crate's verifier means for the token with `claims` and
+    /// signed with `alg`.
+    pub(crate) fn error(
+        &self,
+        err: ClaimsVerificationError,
+        claims: &Value,
+        alg: Option<&CoreJwsSigningAlgorithm>,
+        client_id: &str,
+        now: Option<SystemTime>,
+    ) -> IdTokenError {
+        let string = |name| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        let time = |name| claims.get(name).and_then(Value::as_i64).unwrap_or_default();
+        match err {
+            ClaimsVerificationError::SignatureVerification(
+                SignatureVerificationError::DisallowedAlg(_),
+            ) => IdTokenError::DisallowedAlgorithm {
+                alg: alg.map(alg_name).unwrap_or_default(),
+                allowed: self.signing_algs.iter().map(alg_name).collect(),
+            },
+            ClaimsVerificationError::SignatureVerification(err) => {
+                IdTokenError::Signature(err.to_string())
+            }
+            ClaimsVerificationError::NoSignature => IdTokenError::Signature(err.to_string()),
+            ClaimsVerificationError::InvalidIssuer(_) => {
+                IdTokenError::Issuer(string("iss").unwrap_or_default())
+            }
+            ClaimsVerificationError::InvalidAudience(_) => {
+                let audiences = audiences(claims);
+                match audiences.iter().any(|audience| audience == client_id) {
+                    true => IdTokenError::UntrustedAudience(
+                        audiences
+                            .into_iter()
+                            .find(|audience| audience != client_id)
+                            .unwrap_or_default(),
+                    ),
+                    false => IdTokenError::Audience(audiences),
+                }
+            }
+            ClaimsVerificationError::Expired(_) => {
+                let now = now.unwrap_or(UNIX_EPOCH);
+                let expired_at = time("exp");
+                match expired_at <= unix_time(now - self.clock_skew) {
+                    true => IdTokenError::Expired {
+                        expired_at,
+                        now: unix_time(now),
+                    },
+                    false => IdTokenError::IssuedInFuture {
+                        issued_at: time("iat"),
+                        now: unix_time(now),
+                    },
+                }
+            }
+            ClaimsVerificationError::InvalidAuthTime(_) => IdTokenError::MissingAuthTime,
+            ClaimsVerificationError::InvalidNonce(reason) => IdTokenError::Nonce(reason),
+            err => IdTokenError::Malformed(err.to_string()),
+        }
+    }
+}
+
+/// Which check an ID token failed, in [`OpenIdError::IdToken`](crate::OpenIdError::IdToken).
+///
+/// Times are seconds since the Unix epoch, `now` including no clock skew.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum IdTokenError {
+    /// Signed with an algorithm not in [`IdTokenValidation::signing_algs`].
+    #[error("signed with {alg}, allowed are {}", .allowed.join(", "))]
+    DisallowedAlgorithm { alg: String, allowed: Vec<String> },
+    /// The signature does not verify, or the provider has no key with the token's key id.
+    #[error("invalid signature: {0}")]
+    Signature(String),
+    /// Issued by another issuer than the provider, see [`IssuerValidation`](crate::IssuerValidation).
+    #[error("issued by `{0}`")]
+    Issuer(String),
+    /// The audiences, which do not include the client.
+    #[error("issued for {:?}, not for the client", .0)]
+    Audience(Vec<String>),
+    /// Also issued for this audience, see [`IdTokenValidation::multiple_audiences`].
+    #[error("also issued for `{0}`, multiple audiences are not allowed")]
+    UntrustedAudience(String),
+    /// The `azp` of a token for several audiences, which must name the client.
+    #[error("authorized party {:?} is not the client", .0)]
+    AuthorizedParty(Option<String>),
+    #[error("expired at {expired_at}, now is {now}")]
+    Expired { expired_at: i64, now: i64 },
+    #[error("issued in the future at {issued_at}, now is {now}")]
+    IssuedInFuture { issued_at: i64, now: i64 },
+    /// The `nbf` claim is in the future.
+    #[error("not valid before {not_before}, now is {now}")]
+    NotYetValid { not_before: i64, now: i64 },
+    /// See [`IdTokenValidation::require_auth_time`].
+    #[error("no auth_time")]
+    MissingAuthTime,
+    /// The nonce is not the one of the login.
+    #[error("invalid nonce: {0}")]
+    Nonce(String),
+    /// The token could not be parsed or lacks required claims.
+    #[error("malformed: {0}")]
+    Malformed(String),
+}
+
+/// The `aud` claim, a string or an array.
+fn audiences(claims: &Value) -> Vec<String> {
+    match claims.get("aud") {
+        Some(Value::String(audience)) => vec![audience.clone()],
+        Some(Value::Array(audiences)) => audiences
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn alg_name(alg: &CoreJwsSigningAlgorithm) -> String {
+    serde_json::to_value(alg)
+        .ok()
+        .and_then(|alg| alg.as_str().map(str::to_string))
+        .unwrap_or_else(|| format!("{:?}", alg))
+}
+
+fn unix_time(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() as i64)
+}
@@ -0,0 +1,280 @@
+//! Request headers telling the services behind the middleware who the user is, so they do not
+//! have to validate tokens themselves.
+
+use std::fmt;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::dev::{Payload, ServiceRequest};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretVec};
+use sha2::Sha256;
+
+use crate::authorization::roles_at;
+use crate::openid::{OpenID, OtherClaims};
+use crate::openid_middleware::AuthenticatedUser;
+
+/// Signs the identity headers when a key is set, see [`IdentityHeaders::sign`].
+pub const IDENTITY_SIGNATURE: HeaderName = HeaderName::from_static("x-auth-signature");
+
+/// What an identity header carries.
+#[derive(Clone, Copy, Debug)]
+pub enum IdentityClaim {
+    Subject,
+    Email,
+    /// The roles at the client's `roles_claim`, separated by commas.
+    Roles,
+    /// Any value of the user, made of all their claims, a header is only set when it is `Some`.
+    Custom(fn(&AuthenticatedUser<OtherClaims>) -> Option<String>),
+}
+
+/// The headers the middleware sets for the inner service, configured with
+/// [`identity_headers`](crate::openid_middleware::AuthenticateMiddlewareFactory::identity_headers).
+///
+/// Client-supplied headers of the same names are always removed, for authenticated and
+/// anonymous requests alike, so the inner service can trust them.
+///
+/// ```ignore
+/// openid.get_middleware().identity_headers(
+///     IdentityHeaders::default()
+///         .header(HeaderName::from_static("x-auth-name"), IdentityClaim::Custom(|user| {
+///             Some(user.access.name()?.get(None)?.to_string())
+///         }))
+///         .sign(key),
+/// )
+/// ```
+#[derive(Clone)]
+pub struct IdentityHeaders {
+    headers: Vec<(HeaderName, IdentityClaim)>,
+    key: Option<Arc<SecretVec<u8>>>,
+}
+
+/// The headers and whether they are signed, never the key.
+impl fmt::Debug for IdentityHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityHeaders")
+            .field("headers", &self.headers)
+            .field("signed", &self.key.is_some())
+            .finish()
+    }
+}
+
+/// `X-Auth-Subject`, `X-Auth-Email` and `X-Auth-Roles`, unsigned.
+impl Default for IdentityHeaders {
+    fn default() -> Self {
+        IdentityHeaders::new()
+            .header(
+                HeaderName::from_static("x-auth-subject"),
+                IdentityClaim::Subject,
+            )
+            .header(
+                HeaderName::from_static("x-auth-email"),
+                IdentityClaim::Email,
+            )
+            .header(
+                HeaderName::from_static("x-auth-roles"),
+                IdentityClaim::Roles,
+            )
+    }
+}
+
+impl IdentityHeaders {
+    /// No headers, add them with [`header`](Self::header).
+    pub fn new() -> Self {
+        IdentityHeaders {
+            headers: Vec::new(),
+            key: None,
+        }
+    }
+
+    /// Sets `name` to `claim`, replacing what the header carried before.
+    pub fn header(mut self, name: HeaderName, claim: IdentityClaim) -> Self {
+        self.headers.retain(|(header, _)| *header != name);
+        self.headers.push((name, claim));
+        self
+    }
+
+    /// Adds an [`IDENTITY_SIGNATURE`] header, `t=<unix time>,v1=<hex HMAC-SHA256>`, signing
+    /// the time and every configured header, see [`verify`](Self::verify).
+    pub fn sign(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(Arc::new(SecretVec::new(key.into())));
+        self
+    }
+
+    /// Whether `headers` carry a valid signature made at most `max_age` ago, for services
+    /// receiving the headers. Always `false` without a key.
+    pub fn verify(&self, headers: &HeaderMap, max_age: Duration) -> bool {
+        let Some(signature) = headers
+            .get(IDENTITY_SIGNATURE)
+            .and_then(|signature| signature.to_str().ok())
+        else {
+            return false;
+        };
+        let Some((timestamp, digest)) = signature
+            .strip_prefix("t=")
+            .and_then(|signature| signature.split_once(",v1="))
+        else {
+            return false;
+        };
+        let (Ok(timestamp), Some(digest)) = (timestamp.parse::<u64>(), decode_hex(digest)) else {
+            return false;
+        };
+        let age = unix_time(SystemTime::now()).abs_diff(timestamp);
+        if age > max_age.as_secs() {
+            return false;
+        }
+        let values = self
+            .headers
+            .iter()
+            .map(|(name, _)| (name, headers.get(name)));
+        self.mac(timestamp, values)
+            .is_some_and(|mac| mac.verify_slice(&digest).is_ok())
+    }
+
+    /// The headers for `user`, with the signature when a key is set.
+    pub(crate) fn values(
+        &self,
+        client: &OpenID,
+        user: &AuthenticatedUser<OtherClaims>,
+    ) -> Vec<(HeaderName, HeaderValue)> {
+        let mut values: Vec<_> = self
+            .headers
+            .iter()
+            .filter_map(|(name, claim)| {
+                let value = claim_value(client, user, *claim)?;
+                // Values that cannot be sent, e.g. with line breaks, are left out.
+                Some((name.clone(), HeaderValue::from_str(&value).ok()?))
+            })
+            .collect();
+        let timestamp = unix_time(client.now());
+        let signed = self.headers.iter().map(|(name, _)| {
+            let value = values
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value);
+            (name, value)
+        });
+        if let Some(mac) = self.mac(timestamp, signed) {
+            let digest: String = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            if let Ok(signature) = HeaderValue::from_str(&format!("t={},v1={}", timestamp, digest))
+            {
+                values.push((IDENTITY_SIGNATURE, signature));
+            }
+        }
+        values
+    }
+
+    /// Removes the configured headers and the signature sent by the client.
+    pub(crate) fn strip(&self, req: &mut ServiceRequest) {
+        let headers = req.headers_mut();
+        for (name, _) in &self.headers {
+            headers.remove(name);
+        }
+        headers.remove(IDENTITY_SIGNATURE);
+    }
+
+    /// Sets the headers of `user` on the request, and keeps them for [`ForwardedIdentity`].
+    pub(crate) fn forward(
+        &self,
+        client: &OpenID,
+        req: &mut ServiceRequest,
+        user: &AuthenticatedUser<OtherClaims>,
+    ) {
+        let values = self.values(client, user);
+        let headers = req.headers_mut();
+        for (name, value) in &values {
+            headers.insert(name.clone(), value.clone());
+        }
+        req.extensions_mut().insert(ForwardedIdentity(values));
+    }
+
+    /// The MAC of `timestamp` and the `name:value` lines of the headers, absent ones being empty.
+    fn mac<'a>(
+        &self,
+        timestamp: u64,
+        values: impl Iterator<Item = (&'a HeaderName, Option<&'a HeaderValue>)>,
+    ) -> Option<Hmac<Sha256>> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_deref()?.expose_secret()).ok()?;
+        mac.update(timestamp.to_string().as_bytes());
+        for (name, value) in values {
+            mac.update(b"\n");
+            mac.update(name.as_str().as_bytes());
+            mac.update(b":");
+            mac.update(value.map(HeaderValue::as_bytes).unwrap_or_default());
+        }
+        Some(mac)
+    }
+}
+
+fn claim_value(
+    client: &OpenID,
+    user: &AuthenticatedUser<OtherClaims>,
+    claim: IdentityClaim,
+) -> Option<String> {
+    match claim {
+        IdentityClaim::Subject => Some(user.access.subject().to_string()),
+        IdentityClaim::Email => Some(user.access.email()?.to_string()),
+        IdentityClaim::Roles => Some(roles_at(user, client.roles_claim()?)?.join(",")),
+        IdentityClaim::Custom(value) => value(user),
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// The identity headers the middleware set on the request, to send along with requests proxied
+/// to upstream services. Empty for anonymous requests and without
+/// [`identity_headers`](crate::openid_middleware::AuthenticateMiddlewareFactory::identity_headers).
+///
+/// ```ignore
+/// #[get("/orders")]
+/// async fn orders(identity: ForwardedIdentity, client: web::Data<awc::Client>) -> impl Responder {
+///     let mut request = client.get("https://orders.internal/api/orders");
+///     for (name, value) in identity.headers() {
+///         request = request.insert_header((name.clone(), value.clone()));
+///     }
+///     // ...
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ForwardedIdentity(Vec<(HeaderName, HeaderValue)>);
+
+impl ForwardedIdentity {
+    pub fn headers(&self) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+        self.0.iter().map(|(name, value)| (name, value))
+    }
+}
+
+impl FromRequest for ForwardedIdentity {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req
+            .extensions()
+            .get::<ForwardedIdentity>()
+            .cloned()
+            .unwrap_or_default()))
+    }
+}
@@ -0,0 +1,83 @@
+//! Token introspection (RFC 7662), for providers issuing opaque access tokens.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openidconnect::core::CoreGenderClaim;
+use openidconnect::{ClaimsVerificationError, UserInfoClaims};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::Result;
+use crate::openid::OtherClaims;
+
+/// What the provider's introspection endpoint tells about a token, see
+/// [`OpenID::introspect`](crate::openid::OpenID::introspect).
+///
+/// Inactive tokens, e.g. expired, revoked or unknown ones, carry no other field.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct IntrospectionResult {
+    pub active: bool,
+    /// The space-separated scopes of the token, see [`scopes`](Self::scopes).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    /// When the token expires, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    /// The audience, a string or an array of strings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// The other fields of the response, e.g. the user's claims.
+    #[serde(flatten)]
+    pub claims: Map<String, Value>,
+}
+
+impl IntrospectionResult {
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.as_deref().unwrap_or_default().split_whitespace()
+    }
+
+    /// When the token expires, `None` without an `exp` or one past what the clock can tell.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.exp
+            .and_then(|exp| UNIX_EPOCH.checked_add(Duration::from_secs(exp)))
+    }
+
+    /// The claims of an active token, with the `username` or else the `client_id` as the `sub`
+    /// when the provider sent none, e.g. for client credentials.
+    pub(crate) fn into_claims(self) -> Result<UserInfoClaims<OtherClaims, CoreGenderClaim>> {
+        if !self.active {
+            return Err(ClaimsVerificationError::Expired(
+                "the access token is not active".to_string(),
+            )
+            .into());
+        }
+        let sub = self.sub.clone().or_else(|| self.username.clone());
+        let sub = sub.or_else(|| self.client_id.clone()).ok_or_else(|| {
+            ClaimsVerificationError::Other("the introspection response names no subject".into())
+        })?;
+        let mut claims = serde_json::to_value(&self).map_err(|err| {
+            ClaimsVerificationError::Other(format!("invalid introspection response: {}", err))
+        })?;
+        claims["sub"] = Value::String(sub);
+        let claims = serde_json::to_vec(&claims).map_err(|err| {
+            ClaimsVerificationError::Other(format!("invalid introspection response: {}", err))
+        })?;
+        Ok(
+            UserInfoClaims::from_json::<serde_json::Error>(&claims, None).map_err(|err| {
+                ClaimsVerificationError::Other(format!("invalid introspection claims: {}", err))
+            })?,
+        )
+    }
+}
@@ -12,15 +12,154 @@ use actix_web::dev::ServiceRequest;
 use actix_web::web;
 use actix_web::web::ServiceConfig;
 
+pub use crate::api_key::ApiKeyValidator;
+pub use crate::authorization::{
+    forbidden, Authorized, HasRole, HasScope, Policy, RequireClaims, RequireClaimsMiddleware,
+};
+pub use crate::basic_auth::{BasicAuth, BasicAuthValidator};
+pub use crate::builder::OpenIdBuilder;
+pub use crate::circuit_breaker::CircuitState;
+pub use crate::claims_request::{ClaimRequest, ClaimsRequest};
+pub use crate::clock::{Clock, SystemClock};
+pub use crate::cookie_config::{CookieConfig, CookieMaxAge, UserInfoCookie};
+pub use crate::credentials::{
+    ApiKeyHeader, BasicHeader, BearerHeader, Credential, CredentialChain, CredentialExtractor,
+    QueryParameter, SessionCookie,
+};
+pub use crate::error::{ErrorAction, OpenIdError, ProviderError, Result};
+pub use crate::events::{AuthEvents, CallbackFailure, ClaimsSource, EventContext, NoEvents};
+#[cfg(feature = "first-party-session")]
+pub use crate::first_party::{FirstPartySession, SessionFormat, SessionKey};
+pub use crate::forward_auth::ForwardAuth;
+pub use crate::forwarded::TrustedProxies;
+pub use crate::health::{HealthReport, HealthThresholds};
+pub use crate::http_client::RetryPolicy;
+pub use crate::id_token::{IdTokenError, IdTokenValidation};
+pub use crate::identity_headers::{
+    ForwardedIdentity, IdentityClaim, IdentityHeaders, IDENTITY_SIGNATURE,
+};
+pub use crate::introspection::IntrospectionResult;
+pub use crate::logging::{LogCategory, LogPolicy};
+pub use crate::login_hook::{LoginDecision, LoginHook};
+pub use crate::login_params::LoginParams;
+pub use crate::messages::{EnglishMessages, MessageKey, Messages};
+pub use crate::not_before::NotBeforePolicy;
+pub use crate::openid::{
+    DeviceAuthorization, DiscoveryPolicy, IdToken, IssuerValidation, OpenIDTokens, OsRandom,
+    OtherClaims, RandomSource, TokenHashValidation, TokenTypeHint, UserInfo, ValidationMode,
+};
+pub use crate::pages::{
+    CallbackErrorHandling, DefaultPages, LoginRedirect, Page, PageContext, PageKind, PageRenderer,
+};
+pub use crate::payload::{PayloadCodec, PayloadError};
+pub use crate::pre_auth::PreAuthDecision;
+pub use crate::provider_registry::{
+    ProviderKey, ProviderRegistry, ProviderRegistryMiddleware, ProviderRegistryMiddlewareFactory,
+};
+pub use crate::providers::{
+    LoginProviders, LoginProvidersMiddleware, LoginProvidersMiddlewareFactory, ProviderEntry,
+    ProviderLink,
+};
+pub use crate::realm::Realm;
+pub use crate::security::{Finding, SecurityCheck};
+pub use crate::session_limits::SessionLimit;
+pub use crate::session_store::{InMemorySessionStore, SessionStore, StoredSession};
+pub use crate::session_token::SessionToken;
+pub use crate::should_auth::{Public, RequireAuth, ShouldAuth};
+pub use crate::tasks::{Shutdown, TaskSet};
+pub use crate::token_exchange::ExchangedToken;
+pub use crate::token_provider::{ClientToken, TokenProvider, TokenSource, TokenStatus};
+pub use crate::validation::{ConfigIssue, Severity};
+
 use crate::openid::OpenID;
 
-mod openid;
+mod api_key;
+mod authorization;
+#[cfg(feature = "awc")]
+pub mod awc_client;
+mod basic_auth;
+#[cfg(feature = "reqwest-middleware")]
+pub mod bearer_middleware;
+mod builder;
+mod circuit_breaker;
+mod claims_request;
+mod clock;
+mod cookie_config;
+mod credentials;
+mod error;
+mod events;
+#[cfg(feature = "first-party-session")]
+mod first_party;
+mod forward_auth;
+mod forwarded;
+mod health;
+mod http_client;
+mod id_token;
+mod identity_headers;
+mod introspection;
+mod logging;
+mod login_hook;
+mod login_params;
+mod messages;
+mod not_before;
+pub mod openid;
 pub mod openid_middleware;
+mod pages;
+mod payload;
+mod pre_auth;
+pub mod prelude;
+mod presets;
+mod provider_cache;
+mod provider_logout;
+mod provider_registry;
+mod providers;
+mod realm;
+mod refresh_lock;
+mod security;
+mod session_limits;
+mod session_store;
+mod session_token;
+mod should_auth;
+mod tasks;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod token_exchange;
+mod token_provider;
+mod userinfo_cache;
+mod userinfo_limiter;
+mod validation;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct ActixWebOpenId {
     openid_client: Arc<OpenID>,
-    should_auth: fn(&ServiceRequest) -> bool,
+    should_auth: ShouldAuth,
+}
+
+/// Registers the endpoints of `client` at the paths of its realm, within its scope.
+pub(crate) fn configure_endpoints(cfg: &mut ServiceConfig, client: &OpenID) {
+    if client.not_before().accepts_pushes() {
+        cfg.service(not_before::keycloak_push_not_before);
+    }
+    if let Some(path) = client.front_channel_logout() {
+        cfg.route(
+            path,
+            web::get().to(provider_logout::front_channel_logout_endpoint),
+        );
+    }
+    if let Some(path) = client.back_channel_logout() {
+        cfg.route(
+            path,
+            web::post().to(provider_logout::back_channel_logout_endpoint),
+        );
+    }
+    #[cfg(feature = "first-party-session")]
+    if let first_party::SessionFormat::FirstPartyJwt { .. } = client.session_format() {
+        cfg.route(
+            first_party::REFRESH_ROUTE,
+            web::post().to(first_party::refresh_endpoint),
+        );
+    }
+    openid_middleware::configure_endpoints(cfg, client.realm());
 }
 
 impl ActixWebOpenId {
@@ -29,40 +168,85 @@ impl ActixWebOpenId {
         client_secret: String,
         redirect_url: String,
         issuer_url: String,
-        should_auth: fn(&ServiceRequest) -> bool,
+        should_auth: impl Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
         post_logout_redirect_url: Option<String>,
         scopes: Vec<String>,
     ) -> Self {
-        ActixWebOpenId {
-            openid_client: Arc::new(
-                OpenID::init(
-                    client_id,
-                    client_secret,
-                    redirect_url,
-                    issuer_url,
-                    post_logout_redirect_url,
-                    scopes,
-                )
-                .await
-                .unwrap(),
-            ),
-            should_auth,
+        let mut builder = Self::builder()
+            .client_id(client_id)
+            .client_secret(client_secret)
+            .redirect_url(redirect_url)
+            .issuer_url(issuer_url)
+            .should_auth(should_auth)
+            .scopes(scopes);
+        if let Some(post_logout_redirect_url) = post_logout_redirect_url {
+            builder = builder.post_logout_redirect_url(post_logout_redirect_url);
         }
+        // Predates the configuration checks, which must not break its callers.
+        builder.build_validated(false).await.unwrap()
+    }
+
+    pub fn builder() -> OpenIdBuilder {
+        OpenIdBuilder::default()
+    }
+
+    /// The OpenID client shared by the middleware and the endpoints.
+    pub fn openid_client(&self) -> &Arc<OpenID> {
+        &self.openid_client
+    }
+
+    /// Cross-checks the configuration against the provider, see [`OpenID::validate`].
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        self.openid_client.validate()
+    }
+
+    /// Lists the dangerous settings currently active, see [`OpenID::security_report`].
+    pub fn security_report(&self) -> Vec<Finding> {
+        self.openid_client.security_report()
     }
 
     pub fn configure_open_id(&self) -> impl Fn(&mut ServiceConfig) {
         let client = self.openid_client.clone();
         move |cfg: &mut ServiceConfig| {
-            cfg.service(openid_middleware::auth_endpoint)
-                .service(openid_middleware::logout_endpoint)
+            configure_endpoints(cfg, &client);
+            cfg.app_data(web::Data::from(client.clone()))
+                // Handlers extracting `web::Data<Arc<OpenID>>` keep working until the next release.
                 .app_data(web::Data::new(client.clone()));
         }
     }
 
+    /// Registers `GET /forward_auth`, deciding about the requests of other apps for the ingress,
+    /// see [`ForwardAuth`]. The callback is registered by [`configure_open_id`](Self::configure_open_id).
+    pub fn configure_forward_auth(&self, forward_auth: ForwardAuth) -> impl Fn(&mut ServiceConfig) {
+        let client = self.openid_client.clone();
+        let forward_auth = web::Data::new(forward_auth);
+        move |cfg: &mut ServiceConfig| {
+            // Registered for the whole app, the callback sends users back to the apps' hosts.
+            cfg.app_data(forward_auth.clone()).service(
+                web::resource("/forward_auth")
+                    .app_data(web::Data::from(client.clone()))
+                    .route(web::get().to(forward_auth::forward_auth_endpoint)),
+            );
+        }
+    }
+
+    /// Registers `GET /auth/health`, answering the [`OpenID::health`] report as JSON, with
+    /// `503 Service Unavailable` when it is unhealthy. Meant for readiness probes.
+    pub fn configure_health(&self) -> impl Fn(&mut ServiceConfig) {
+        let client = self.openid_client.clone();
+        move |cfg: &mut ServiceConfig| {
+            cfg.service(
+                web::resource("/auth/health")
+                    .app_data(web::Data::from(client.clone()))
+                    .route(web::get().to(health::health_endpoint)),
+            );
+        }
+    }
+
     pub fn get_middleware(&self) -> openid_middleware::AuthenticateMiddlewareFactory {
-        openid_middleware::AuthenticateMiddlewareFactory::new(
+        openid_middleware::AuthenticateMiddlewareFactory::with_should_auth(
             self.openid_client.clone(),
-            self.should_auth,
+            self.should_auth.clone(),
         )
     }
 }
@@ -0,0 +1,139 @@
+//! Per category log levels, everything the crate logs goes through [`LogPolicy::log`].
+//!
+//! Records are `tracing` events, within the span of the request the middleware handles. With
+//! the `log` feature, on by default, they are also logged through `log` while no `tracing`
+//! subscriber is set.
+
+use std::fmt::Arguments;
+
+use log::Level;
+
+/// What a log record is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LogCategory {
+    /// A request without a usable session, e.g. from a bot probing protected pages.
+    UnauthenticatedRequest,
+    LoginSuccess,
+    /// A callback that could not complete the login, e.g. without nonce or with an invalid token.
+    LoginFailure,
+    Refresh,
+    /// The provider could not be reached or answered with a server error.
+    IdpError,
+    /// Suspicious or insecure settings found while building the client.
+    ConfigWarning,
+}
+
+impl LogCategory {
+    /// Log target of the category, e.g. `actix_web_openidconnect::login_failure`.
+    pub fn target(&self) -> &'static str {
+        match self {
+            LogCategory::UnauthenticatedRequest => UNAUTHENTICATED_REQUEST,
+            LogCategory::LoginSuccess => LOGIN_SUCCESS,
+            LogCategory::LoginFailure => LOGIN_FAILURE,
+            LogCategory::Refresh => REFRESH,
+            LogCategory::IdpError => IDP_ERROR,
+            LogCategory::ConfigWarning => CONFIG_WARNING,
+        }
+    }
+}
+
+const UNAUTHENTICATED_REQUEST: &str = "actix_web_openidconnect::unauthenticated_request";
+const LOGIN_SUCCESS: &str = "actix_web_openidconnect::login_success";
+const LOGIN_FAILURE: &str = "actix_web_openidconnect::login_failure";
+const REFRESH: &str = "actix_web_openidconnect::refresh";
+const IDP_ERROR: &str = "actix_web_openidconnect::idp_error";
+const CONFIG_WARNING: &str = "actix_web_openidconnect::config_warning";
+
+/// A `tracing` event with the `target` at `level`, both of which its callsite needs as constants.
+macro_rules! event_at {
+    ($target:expr, $level:expr, $args:expr) => {
+        match $level {
+            Level::Error => tracing::event!(target: $target, tracing::Level::ERROR, "{}", $args),
+            Level::Warn => tracing::event!(target: $target, tracing::Level::WARN, "{}", $args),
+            Level::Info => tracing::event!(target: $target, tracing::Level::INFO, "{}", $args),
+            Level::Debug => tracing::event!(target: $target, tracing::Level::DEBUG, "{}", $args),
+            Level::Trace => tracing::event!(target: $target, tracing::Level::TRACE, "{}", $args),
+        }
+    };
+}
+
+fn event(category: LogCategory, level: Level, args: Arguments<'_>) {
+    match category {
+        LogCategory::UnauthenticatedRequest => event_at!(UNAUTHENTICATED_REQUEST, level, args),
+        LogCategory::LoginSuccess => event_at!(LOGIN_SUCCESS, level, args),
+        LogCategory::LoginFailure => event_at!(LOGIN_FAILURE, level, args),
+        LogCategory::Refresh => event_at!(REFRESH, level, args),
+        LogCategory::IdpError => event_at!(IDP_ERROR, level, args),
+        LogCategory::ConfigWarning => event_at!(CONFIG_WARNING, level, args),
+    }
+}
+
+/// The level each [`LogCategory`] is logged at, `None` turning it off.
+///
+/// Unauthenticated requests are only logged at debug level by default, they are mostly noise.
+/// Log records never contain tokens, codes or nonces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogPolicy {
+    pub unauthenticated_request: Option<Level>,
+    pub login_success: Option<Level>,
+    pub login_failure: Option<Level>,
+    pub refresh: Option<Level>,
+    pub idp_error: Option<Level>,
+    pub config_warning: Option<Level>,
+}
+
+impl Default for LogPolicy {
+    fn default() -> Self {
+        LogPolicy {
+            unauthenticated_request: Some(Level::Debug),
+            login_success: Some(Level::Info),
+            login_failure: Some(Level::Warn),
+            refresh: Some(Level::Info),
+            idp_error: Some(Level::Error),
+            config_warning: Some(Level::Warn),
+        }
+    }
+}
+
+impl LogPolicy {
+    /// Sets the level of `category`, or turns it off with `None`.
+    pub fn level(mut self, category: LogCategory, level: Option<Level>) -> Self {
+        *self.level_mut(category) = level;
+        self
+    }
+
+    pub fn level_of(&self, category: LogCategory) -> Option<Level> {
+        match category {
+            LogCategory::UnauthenticatedRequest => self.unauthenticated_request,
+            LogCategory::LoginSuccess => self.login_success,
+            LogCategory::LoginFailure => self.login_failure,
+            LogCategory::Refresh => self.refresh,
+            LogCategory::IdpError => self.idp_error,
+            LogCategory::ConfigWarning => self.config_warning,
+        }
+    }
+
+    fn level_mut(&mut self, category: LogCategory) -> &mut Option<Level> {
+        match category {
+            LogCategory::UnauthenticatedRequest => &mut self.unauthenticated_request,
+            LogCategory::LoginSuccess => &mut self.login_success,
+            LogCategory::LoginFailure => &mut self.login_failure,
+            LogCategory::Refresh => &mut self.refresh,
+            LogCategory::IdpError => &mut self.idp_error,
+            LogCategory::ConfigWarning => &mut self.config_warning,
+        }
+    }
+
+    pub(crate) fn log(&self, category: LogCategory, args: Arguments<'_>) {
+        if let Some(level) = self.level_of(category) {
+            event(category, level, args);
+        }
+    }
+
+    /// Logs at error level unless `category` is turned off, for findings that must stand out.
+    pub(crate) fn log_error(&self, category: LogCategory, args: Arguments<'_>) {
+        if self.level_of(category).is_some() {
+            event(category, Level::Error, args);
+        }
+    }
+}
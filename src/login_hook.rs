@@ -0,0 +1,54 @@
+//! A hook run by the callback once a login verified, e.g. to provision the user in the
+//! application's database.
+
+use openidconnect::core::CoreGenderClaim;
+use openidconnect::IdTokenClaims;
+use serde_json::Value;
+
+use crate::openid::{OpenIDTokens, OtherClaims};
+
+/// Told about every login before its session is created, able to refuse it or to store data with
+/// the session. Set with [`OpenIdBuilder::on_login`](crate::OpenIdBuilder::on_login).
+///
+/// ```ignore
+/// struct Provisioning(PgPool);
+///
+/// #[async_trait::async_trait]
+/// impl LoginHook for Provisioning {
+///     async fn on_login(
+///         &self,
+///         claims: &IdTokenClaims<OtherClaims, CoreGenderClaim>,
+///         _tokens: &OpenIDTokens,
+///     ) -> LoginDecision {
+///         match users::find_or_create(&self.0, claims.subject().as_str()).await {
+///             Ok(user) if user.deactivated => LoginDecision::Deny("Your account is deactivated".into()),
+///             Ok(user) => LoginDecision::AllowWith(json!({ "user_id": user.id })),
+///             Err(_) => LoginDecision::Deny("Please try again later".into()),
+///         }
+///     }
+/// }
+/// ```
+#[async_trait::async_trait]
+pub trait LoginHook: Send + Sync {
+    /// Decides about the login of the user with the verified ID token `claims`, issued with
+    /// `tokens`.
+    async fn on_login(
+        &self,
+        claims: &IdTokenClaims<OtherClaims, CoreGenderClaim>,
+        tokens: &OpenIDTokens,
+    ) -> LoginDecision;
+}
+
+/// The decision of a [`LoginHook`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoginDecision {
+    /// Create the session.
+    Allow,
+    /// Create the session, with the data returned by
+    /// [`AuthenticatedUser::session_data`](crate::openid_middleware::AuthenticatedUser::session_data)
+    /// for as long as it lasts. Without a [session store](crate::OpenIdBuilder::session_store)
+    /// it is kept in a cookie and should stay small, e.g. the user's id in the application.
+    AllowWith(Value),
+    /// Refuse the login, answered with `403 Forbidden` and the message.
+    Deny(String),
+}
@@ -0,0 +1,99 @@
+//! Scopes and parameters added to the authorization request of a single login.
+
+use actix_web::http::header::{HeaderMap, ACCEPT_LANGUAGE};
+use openidconnect::Scope;
+
+/// Added to the client's [`scopes`](crate::OpenIdBuilder::scopes) and
+/// [`extra_auth_param`](crate::OpenIdBuilder::extra_auth_param)s for one login, e.g. the step-up
+/// scopes of a route, see
+/// [`login_params`](crate::openid_middleware::AuthenticateMiddlewareFactory::login_params) and
+/// [`AuthenticationRequired::step_up`](crate::openid_middleware::AuthenticationRequired::step_up).
+///
+/// The middleware also picks up the parameters an earlier middleware put into the request
+/// extensions, after those of its hook:
+///
+/// ```ignore
+/// req.extensions_mut().insert(LoginParams::new().ui_locales_from(req.headers()));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoginParams {
+    scopes: Vec<Scope>,
+    extra_params: Vec<(String, String)>,
+}
+
+impl LoginParams {
+    pub fn new() -> Self {
+        LoginParams::default()
+    }
+
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(Scope::new(scope.into()));
+        self
+    }
+
+    /// Adds `name=value` to the authorization url, e.g. `prompt=consent` or `acr_values`,
+    /// replacing an earlier value of `name`.
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        self.extra_params.retain(|(param, _)| *param != name);
+        self.extra_params.push((name, value.into()));
+        self
+    }
+
+    /// The user's email or name, filled in on the provider's login page.
+    pub fn login_hint(self, hint: impl Into<String>) -> Self {
+        self.param("login_hint", hint)
+    }
+
+    /// E.g. `login` to ask for the password again, or `select_account` for a "switch account"
+    /// link.
+    pub fn prompt(self, prompt: impl Into<String>) -> Self {
+        self.param("prompt", prompt)
+    }
+
+    /// The languages of the provider's pages, space separated by preference, e.g. `de en`.
+    pub fn ui_locales(self, locales: impl Into<String>) -> Self {
+        self.param("ui_locales", locales)
+    }
+
+    /// The [`ui_locales`](Self::ui_locales) of an `Accept-Language` header, unchanged without
+    /// one.
+    pub fn ui_locales_from(self, headers: &HeaderMap) -> Self {
+        let mut languages: Vec<(&str, f32)> = headers
+            .get_all(ACCEPT_LANGUAGE)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|language| {
+                let mut parts = language.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .find_map(|part| part.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |quality| quality.trim().parse().ok())?;
+                (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        if languages.is_empty() {
+            return self;
+        }
+        languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        let locales: Vec<&str> = languages.into_iter().map(|(tag, _)| tag).collect();
+        self.ui_locales(locales.join(" "))
+    }
+
+    /// These parameters with the scopes of `other` added and its parameters taking precedence.
+    pub(crate) fn merged(mut self, other: LoginParams) -> Self {
+        self.scopes.extend(other.scopes);
+        other
+            .extra_params
+            .into_iter()
+            .fold(self, |params, (name, value)| params.param(name, value))
+    }
+
+    pub(crate) fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub(crate) fn extra_params(&self) -> &[(String, String)] {
+        &self.extra_params
+    }
+}
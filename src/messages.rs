@@ -0,0 +1,141 @@
+//! The text of the responses users see, translatable by applications.
+
+/// Identifies a user-facing message.
+///
+/// The [`id`](MessageKey::id)s are stable, translation catalogs (fluent, gettext) can be keyed on
+/// them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MessageKey {
+    /// Body of the redirect to the provider.
+    NotAuthenticated,
+    /// A failure whose details are only logged.
+    InternalError,
+    /// Logout without an ID token.
+    MissingIdToken,
+    /// An ID token that cannot be parsed or verified.
+    InvalidIdToken,
+    /// Callback without the nonce cookie set before the redirect to the provider.
+    MissingNonce,
+    /// The provider cannot be reached, with the `retry_after` argument in seconds.
+    IdpUnavailable,
+    /// The provider refuses the client's configuration.
+    Misconfigured,
+    /// The provider refuses the authorization code.
+    AuthenticationFailed,
+    /// An extractor needing a user on a route without the middleware.
+    Unauthorized,
+    /// A session without an access token.
+    NoAccessToken,
+    /// An endpoint or extractor without a registered client.
+    ClientNotRegistered,
+    /// The page shown after logging out, when there is no page to send the user back to.
+    LoggedOut,
+    /// Heading of the provider chooser.
+    ChooseProvider,
+    /// The provider left out claims requested as essential.
+    MissingClaims,
+    /// Callback whose `state` was not issued to this browser, e.g. a forged login link.
+    InvalidState,
+    /// A user failing the policy of the route, see [`RequireClaims`](crate::RequireClaims).
+    Forbidden,
+    /// The user cancelled the login at the provider, which answered `access_denied`.
+    LoginCancelled,
+    /// Callback of a login started longer than the
+    /// [`login_window`](crate::OpenIdBuilder::login_window) ago.
+    LoginExpired,
+    /// Callback of a user who logged in at the provider longer than the
+    /// [`session_absolute_lifetime`](crate::OpenIdBuilder::session_absolute_lifetime) ago.
+    StaleLogin,
+}
+
+impl MessageKey {
+    pub const ALL: &'static [MessageKey] = &[
+        MessageKey::NotAuthenticated,
+        MessageKey::InternalError,
+        MessageKey::MissingIdToken,
+        MessageKey::InvalidIdToken,
+        MessageKey::MissingNonce,
+        MessageKey::IdpUnavailable,
+        MessageKey::Misconfigured,
+        MessageKey::AuthenticationFailed,
+        MessageKey::Unauthorized,
+        MessageKey::NoAccessToken,
+        MessageKey::ClientNotRegistered,
+        MessageKey::LoggedOut,
+        MessageKey::ChooseProvider,
+        MessageKey::MissingClaims,
+        MessageKey::InvalidState,
+        MessageKey::Forbidden,
+        MessageKey::LoginCancelled,
+        MessageKey::LoginExpired,
+        MessageKey::StaleLogin,
+    ];
+
+    pub const fn id(&self) -> &'static str {
+        match self {
+            MessageKey::NotAuthenticated => "not-authenticated",
+            MessageKey::InternalError => "internal-error",
+            MessageKey::MissingIdToken => "missing-id-token",
+            MessageKey::InvalidIdToken => "invalid-id-token",
+            MessageKey::MissingNonce => "missing-nonce",
+            MessageKey::IdpUnavailable => "idp-unavailable",
+            MessageKey::Misconfigured => "misconfigured",
+            MessageKey::AuthenticationFailed => "authentication-failed",
+            MessageKey::Unauthorized => "unauthorized",
+            MessageKey::NoAccessToken => "no-access-token",
+            MessageKey::ClientNotRegistered => "client-not-registered",
+            MessageKey::LoggedOut => "logged-out",
+            MessageKey::ChooseProvider => "choose-provider",
+            MessageKey::MissingClaims => "missing-claims",
+            MessageKey::InvalidState => "invalid-state",
+            MessageKey::Forbidden => "forbidden",
+            MessageKey::LoginCancelled => "login-cancelled",
+            MessageKey::LoginExpired => "login-expired",
+            MessageKey::StaleLogin => "stale-login",
+        }
+    }
+}
+
+/// Renders user-facing messages, e.g. in the user's language.
+///
+/// `args` are the named values a message can include, listed on its [`MessageKey`]. Responses
+/// built without a client, e.g. when none is registered, use [`EnglishMessages`], and an
+/// [`OpenIdError`](crate::OpenIdError) returned by a handler answers with the HTTP reason phrase.
+pub trait Messages: Send + Sync {
+    fn message(&self, key: MessageKey, args: &[(&str, String)]) -> String;
+}
+
+/// The built-in English messages.
+pub struct EnglishMessages;
+
+impl Messages for EnglishMessages {
+    fn message(&self, key: MessageKey, _args: &[(&str, String)]) -> String {
+        match key {
+            MessageKey::NotAuthenticated => "Not authenticated",
+            MessageKey::InternalError => "internal error",
+            MessageKey::MissingIdToken => "missing id token",
+            MessageKey::InvalidIdToken => "invalid id token",
+            MessageKey::MissingNonce => "No nonce",
+            MessageKey::IdpUnavailable => "the identity provider is unavailable",
+            MessageKey::Misconfigured => "the authentication is misconfigured",
+            MessageKey::AuthenticationFailed => "authentication failed",
+            MessageKey::Unauthorized => "Unauthorized",
+            MessageKey::NoAccessToken => "the session has no access token",
+            MessageKey::ClientNotRegistered => "configure_open_id is not registered",
+            MessageKey::LoggedOut => "You are logged out",
+            MessageKey::ChooseProvider => "Log in with",
+            MessageKey::MissingClaims => {
+                "the identity provider did not share the required information"
+            }
+            MessageKey::InvalidState => "the login was not started here, please log in again",
+            MessageKey::Forbidden => "you are not allowed to access this page",
+            MessageKey::LoginCancelled => "the login was cancelled",
+            MessageKey::LoginExpired => "the login took too long, please log in again",
+            MessageKey::StaleLogin => {
+                "your login at the identity provider is too old, please enter your credentials again"
+            }
+        }
+        .to_string()
+    }
+}
@@ -0,0 +1,190 @@
+//! Sessions that must log in again, e.g. after a password reset: users' "not-before" times, and
+//! the one pushed by Keycloak for every user of the client.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{post, HttpResponse};
+use serde::Deserialize;
+
+use crate::clock::{system_time, Clock};
+use crate::error::OpenIdError;
+use crate::logging::LogCategory;
+use crate::openid_middleware::RegisteredClient;
+
+/// When users last logged out everywhere, e.g. after resetting their password.
+///
+/// ```ignore
+/// struct PasswordResets(PgPool);
+///
+/// #[async_trait::async_trait]
+/// impl NotBeforePolicy for PasswordResets {
+///     async fn not_before(&self, subject: &str) -> Option<SystemTime> {
+///         sqlx::query_scalar("SELECT reset_at FROM users WHERE sub = $1")
+///             .bind(subject)
+///             .fetch_optional(&self.0)
+///             .await
+///             .ok()
+///             .flatten()
+///     }
+/// }
+///
+/// ActixWebOpenId::builder().not_before_policy(PasswordResets(pool))
+/// ```
+#[async_trait::async_trait]
+pub trait NotBeforePolicy: Send + Sync {
+    /// Sessions of `subject` authenticated before the returned time must log in again, `None` if
+    /// all are valid.
+    async fn not_before(&self, subject: &str) -> Option<SystemTime>;
+}
+
+/// How long the answers of the [`NotBeforePolicy`] are reused.
+const CACHE_TTL: Duration = Duration::from_secs(30);
+/// Cached answers kept before expired ones are dropped.
+const CACHE_SIZE: usize = 10_000;
+
+struct Cached {
+    not_before: Option<SystemTime>,
+    fetched_at: SystemTime,
+}
+
+pub(crate) struct NotBefore {
+    policy: Option<Arc<dyn NotBeforePolicy>>,
+    clock: Arc<dyn Clock>,
+    cache: Mutex<HashMap<String, Cached>>,
+    /// Unix time pushed by Keycloak, for every user of the client, 0 if none.
+    pushed: AtomicU64,
+    accepts_pushes: bool,
+}
+
+impl NotBefore {
+    pub(crate) fn new(
+        policy: Option<Arc<dyn NotBeforePolicy>>,
+        accepts_pushes: bool,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        NotBefore {
+            policy,
+            accepts_pushes,
+            clock,
+            cache: Mutex::default(),
+            pushed: AtomicU64::default(),
+        }
+    }
+
+    /// Whether sessions are checked at all.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.policy.is_some() || self.accepts_pushes
+    }
+
+    pub(crate) fn accepts_pushes(&self) -> bool {
+        self.accepts_pushes
+    }
+
+    /// Whether the session of `subject`, who logged in at `authenticated_at`, must log in again.
+    pub(crate) async fn predates(&self, subject: &str, authenticated_at: SystemTime) -> bool {
+        let pushed = UNIX_EPOCH + Duration::from_secs(self.pushed.load(Ordering::Relaxed));
+        if authenticated_at < pushed {
+            return true;
+        }
+        let Some(policy) = &self.policy else {
+            return false;
+        };
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(subject)
+            .filter(|cached| !self.is_stale(cached))
+            .map(|cached| cached.not_before);
+        let not_before = match cached {
+            Some(not_before) => not_before,
+            None => {
+                let not_before = policy.not_before(subject).await;
+                let mut cache = self.cache.lock().unwrap();
+                if cache.len() >= CACHE_SIZE {
+                    cache.retain(|_, cached| !self.is_stale(cached));
+                }
+                cache.insert(
+                    subject.to_string(),
+                    Cached {
+                        not_before,
+                        fetched_at: system_time(self.clock.as_ref()),
+                    },
+                );
+                not_before
+            }
+        };
+        not_before.is_some_and(|not_before| authenticated_at < not_before)
+    }
+
+    fn push(&self, not_before: u64) {
+        self.pushed.fetch_max(not_before, Ordering::Relaxed);
+    }
+
+    fn is_stale(&self, cached: &Cached) -> bool {
+        let now = system_time(self.clock.as_ref());
+        now.duration_since(cached.fetched_at).unwrap_or_default() >= CACHE_TTL
+    }
+}
+
+/// The `PUSH_NOT_BEFORE` admin event of Keycloak.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PushNotBefore {
+    action: String,
+    /// Client the event is meant for.
+    resource: String,
+    /// Unix time after which the event must be ignored.
+    expiration: u64,
+    not_before: u64,
+}
+
+/// Receives Keycloak's "Push" of the client's not-before time, registered with
+/// [`OpenIdBuilder::keycloak_push_not_before`](crate::OpenIdBuilder::keycloak_push_not_before).
+/// The client's admin URL must point to the realm path.
+#[post("/k_push_not_before")]
+pub(crate) async fn keycloak_push_not_before(
+    open_id_client: RegisteredClient,
+    body: String,
+) -> HttpResponse {
+    let event = open_id_client.verify_jws(body.trim()).and_then(|payload| {
+        serde_json::from_value::<PushNotBefore>(payload)
+            .map_err(|err| OpenIdError::Config(format!("invalid admin event: {}", err)))
+    });
+    let now = open_id_client
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    match event {
+        Ok(event)
+            if event.action == "PUSH_NOT_BEFORE"
+                && event.resource == open_id_client.client_id()
+                && event.expiration > now =>
+        {
+            open_id_client.log_policy().log(
+                LogCategory::LoginSuccess,
+                format_args!("Keycloak pushed the not-before time {}", event.not_before),
+            );
+            open_id_client.not_before().push(event.not_before);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(_) => {
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Ignored a Keycloak admin event for another client or expired"),
+            );
+            HttpResponse::Unauthorized().finish()
+        }
+        Err(err) => {
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Rejected a Keycloak admin event: {}", err),
+            );
+            HttpResponse::Unauthorized().finish()
+        }
+    }
+}
@@ -1,44 +1,441 @@
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures_util::future::BoxFuture;
 use openidconnect::core::{
-    CoreAuthDisplay, CoreAuthenticationFlow, CoreClaimName, CoreClaimType, CoreClient,
-    CoreClientAuthMethod, CoreGenderClaim, CoreGrantType, CoreJsonWebKey, CoreJsonWebKeyType,
-    CoreJsonWebKeyUse, CoreJweContentEncryptionAlgorithm, CoreJweKeyManagementAlgorithm,
-    CoreJwsSigningAlgorithm, CoreResponseMode, CoreResponseType, CoreSubjectIdentifierType,
+    CoreAuthDisplay, CoreAuthPrompt, CoreAuthenticationFlow, CoreClaimName, CoreClaimType,
+    CoreClientAuthMethod, CoreErrorResponseType, CoreGenderClaim, CoreGrantType, CoreJsonWebKey,
+    CoreJsonWebKeyType, CoreJsonWebKeyUse, CoreJweContentEncryptionAlgorithm,
+    CoreJweKeyManagementAlgorithm, CoreJwsSigningAlgorithm, CoreResponseMode, CoreResponseType,
+    CoreRevocableToken, CoreRevocationErrorResponse, CoreSubjectIdentifierType,
+    CoreTokenIntrospectionResponse, CoreTokenType,
 };
-use openidconnect::reqwest::async_http_client;
+use openidconnect::reqwest::AsyncHttpClientError;
 use openidconnect::{
-    AccessToken, AdditionalProviderMetadata, AuthorizationCode, ClaimsVerificationError, ClientId,
-    ClientSecret, CsrfToken, EmptyAdditionalClaims, EndSessionUrl, IdTokenClaims, IssuerUrl,
-    LogoutRequest, Nonce, OAuth2TokenResponse, PostLogoutRedirectUrl, ProviderMetadata,
-    RedirectUrl, RefreshToken, Scope, TokenResponse, UserInfoClaims,
+    AccessToken, AccessTokenHash, AdditionalClaims, AdditionalProviderMetadata, AuthorizationCode,
+    AuthorizationCodeHash, ClaimsVerificationError, Client, ClientId, ClientSecret, CsrfToken,
+    DeviceCode, EmptyAdditionalClaims, EndSessionUrl, EndUserVerificationUrl, ExtraTokenFields,
+    HttpRequest, HttpResponse, IdTokenClaims, IssuerUrl, JsonWebKey, JsonWebKeyId, LogoutRequest,
+    Nonce, NonceVerifier, OAuth2TokenResponse, PkceCodeChallenge, PkceCodeVerifier,
+    PostLogoutRedirectUrl, ProviderMetadata, RedirectUrl, RefreshToken, ResourceOwnerPassword,
+    ResourceOwnerUsername, Scope, StandardErrorResponse, StandardTokenResponse, TokenResponse,
+    TokenUrl, UserCode, UserInfoClaims, UserInfoError, UserInfoResponseType,
+    VerificationUriComplete,
 };
+use secrecy::{ExposeSecret, SecretString};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::builder::OpenIdBuilder;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::claims_request::{ClaimsRequest, EssentialClaims};
+use crate::clock::{system_time, Clock};
+use crate::cookie_config::{CookieConfig, UserInfoCookie};
+use crate::error::{ErrorAction, OpenIdError, ProviderError, Result};
+use crate::events::{AuthEvents, ClaimsSource, EventContext};
+#[cfg(feature = "first-party-session")]
+use crate::first_party::SessionFormat;
+use crate::forwarded::TrustedProxies;
+use crate::health::{Health, HealthReport};
+use crate::http_client::HttpClient;
+use crate::id_token::{IdTokenError, IdTokenValidation};
+use crate::introspection::IntrospectionResult;
+use crate::logging::{LogCategory, LogPolicy};
+use crate::login_hook::LoginHook;
+use crate::login_params::LoginParams;
+use crate::messages::{MessageKey, Messages};
+use crate::not_before::NotBefore;
+use crate::openid_middleware::{login_cookie_name, user_info_chunk, AuthCookies};
+use crate::pages::{CallbackErrorHandling, LoginRedirect, PageRenderer};
+use crate::payload::PayloadCodec;
+use crate::provider_cache::{self, ProviderDocuments};
+use crate::realm::Realm;
+use crate::refresh_lock::RefreshLock;
+use crate::session_limits::SessionLimits;
+use crate::session_store::SessionStore;
+use crate::tasks::TaskSet;
+use crate::token_exchange::ExchangeCache;
+use crate::token_provider::TokenCache;
+use crate::userinfo_cache::{NegativeCache, UserInfoCache};
+use crate::userinfo_limiter::UserInfoLimiter;
+
+/// Generates the random values sent to the provider, such as nonces.
+pub trait RandomSource: Send + Sync {
+    /// Returns a new URL-safe random token.
+    fn random_token(&self) -> String;
+}
+
+/// Draws tokens from the operating system's random number generator.
+pub struct OsRandom;
+
+impl RandomSource for OsRandom {
+    fn random_token(&self) -> String {
+        CsrfToken::new_random().secret().to_string()
+    }
+}
+
+/// How the `iss` claim of ID tokens is checked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IssuerValidation {
+    /// The claim must equal the issuer url.
+    Exact,
+    /// The claim, and the issuer the discovery document advertises, must equal this value
+    /// instead of the issuer url, for providers advertising another issuer than the url they
+    /// are discovered at, such as Azure AD B2C.
+    Expected(String),
+    /// The claim must be one of these values, for providers using several spellings.
+    OneOf(Vec<String>),
+    /// The claim is not checked, e.g. for multi-tenant endpoints.
+    Skip,
+}
+
+/// How the middleware checks access tokens and gets the user's claims.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ValidationMode {
+    /// Asks the provider's userinfo endpoint on every request.
+    #[default]
+    UserInfo,
+    /// Verifies access tokens issued as JWTs against the provider's JWKS, with their expiry,
+    /// audience and issuer, and takes the claims from the token. The provider is only asked
+    /// again for keys it rotated in.
+    Local,
+    /// Asks the provider's introspection endpoint (RFC 7662) whether the token is active, for
+    /// opaque access tokens, and takes the claims from its answer, e.g. the `scope`. Cached like
+    /// userinfo responses, see [`OpenIdBuilder::userinfo_cache`](crate::OpenIdBuilder::userinfo_cache).
+    Introspection,
+}
+
+/// How strictly the `at_hash` of an ID token issued at login must bind it to the access token.
+/// A `c_hash` is checked against the authorization code in both modes, when the provider sent
+/// one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TokenHashValidation {
+    /// Checks the `at_hash` when the provider sent one, many only do in the implicit flow.
+    #[default]
+    IfPresent,
+    /// Rejects ID tokens without an `at_hash`.
+    Strict,
+}
+
+/// What `build` does when the provider cannot be discovered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DiscoveryPolicy {
+    /// Fails, the service does not start without its provider.
+    #[default]
+    FailFast,
+    /// Starts anyway and completes the discovery on first use, trying again at most once per
+    /// `backoff`. Until then the middleware answers `503 Service Unavailable`, mount health
+    /// checks outside of it. Settings checked against the discovery document are only
+    /// reported once it arrived.
+    RetryForever { backoff: Duration },
+}
+
+/// What a token given to [`OpenID::revoke_token`] is, a hint for the provider's lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenTypeHint {
+    AccessToken,
+    RefreshToken,
+}
+
+impl TokenTypeHint {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            TokenTypeHint::AccessToken => "access_token",
+            TokenTypeHint::RefreshToken => "refresh_token",
+        }
+    }
+}
+
+/// Tokens signed with an unknown key refetch the JWKS at most this often, forged key ids must
+/// not send every request to the provider.
+const MIN_JWKS_AGE: Duration = Duration::from_secs(60);
+
 #[derive(Clone)]
 pub struct OpenID {
-    client: CoreClient,
-    provider_metadata: ExtendedProviderMetadata,
-    post_logout_redirect_url: Option<String>,
+    client_id: ClientId,
+    client_secret: Option<SecretString>,
+    issuer_url: IssuerUrl,
+    /// Shared by clones, so refreshing the documents reaches them all.
+    provider: Arc<RwLock<Provider>>,
+    http: HttpClient,
+    discovery_policy: DiscoveryPolicy,
+    /// When [`DiscoveryPolicy::RetryForever`] may try to discover the provider again.
+    next_discovery: Arc<Mutex<Option<SystemTime>>>,
+    redirect_url: Url,
+    post_logout_redirect_url: Option<PostLogoutRedirectUrl>,
+    revoke_access_token: bool,
     scopes: Vec<Scope>,
+    client_token_scopes: Vec<Scope>,
+    /// The tokens of [`client_credentials_token`](Self::client_credentials_token) by scopes,
+    /// shared by clones.
+    client_tokens: Arc<RwLock<HashMap<Vec<String>, Arc<TokenCache>>>>,
+    extra_auth_params: Vec<(String, String)>,
+    extra_token_params: Vec<(String, String)>,
+    claims_request: ClaimsRequest,
+    issuer_validation: IssuerValidation,
+    validation_mode: ValidationMode,
+    require_signed_userinfo: bool,
+    token_hash_validation: TokenHashValidation,
+    id_token_validation: IdTokenValidation,
+    roles_claim: Option<String>,
+    random: Arc<dyn RandomSource>,
+    error_action: fn(&OpenIdError) -> ErrorAction,
+    callback_errors: CallbackErrorHandling,
+    login_redirect: Option<fn(&LoginRedirect<'_>) -> actix_web::HttpResponse>,
+    log_policy: LogPolicy,
+    realm: Realm,
+    messages: Arc<dyn Messages>,
+    events: Arc<dyn AuthEvents>,
+    login_hook: Option<Arc<dyn LoginHook>>,
+    trusted_proxies: TrustedProxies,
+    pages: Arc<dyn PageRenderer>,
+    /// Shared by clones, like the provider documents.
+    breaker: Arc<CircuitBreaker>,
+    not_before: Arc<NotBefore>,
+    userinfo_cache: Option<Arc<UserInfoCache>>,
+    negative_cache: Option<Arc<NegativeCache>>,
+    userinfo_limiter: Option<Arc<UserInfoLimiter>>,
+    refresh_lock: Arc<RefreshLock>,
+    exchange_cache: Arc<ExchangeCache>,
+    max_provider_staleness: Option<Duration>,
+    health: Arc<Health>,
+    tasks: TaskSet,
+    clock: Arc<dyn Clock>,
+    payload_codec: PayloadCodec,
+    pkce: bool,
+    login_window: Duration,
+    cookie_key: Option<Key>,
+    cookie_config: CookieConfig,
+    user_info_cookie: UserInfoCookie,
+    session_store: Option<Arc<dyn SessionStore>>,
+    session_limits: SessionLimits,
+    #[cfg(feature = "first-party-session")]
+    session_format: SessionFormat,
+    front_channel_logout: Option<String>,
+    back_channel_logout: Option<String>,
 }
 
+/// The client's identity, the secret redacted.
+impl Debug for OpenID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenID")
+            .field("client_id", &self.client_id.as_str())
+            .field("client_secret", &self.client_secret)
+            .field("issuer_url", &self.issuer_url.as_str())
+            .field("redirect_url", &self.redirect_url.as_str())
+            .field("realm", &self.realm)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Provider {
+    client: Arc<ProviderClientType>,
+    metadata: Arc<ExtendedProviderMetadata>,
+    documents: ProviderDocuments,
+    /// Whether the documents came from the provider, or are placeholders until it is reachable.
+    discovered: bool,
+}
+
+impl Provider {
+    fn new(documents: ProviderDocuments, openid: ProviderClient<'_>) -> Self {
+        let mut metadata = documents.metadata.clone();
+        if let Some(token_url) = metadata
+            .token_endpoint()
+            .filter(|_| !openid.extra_token_params.is_empty())
+        {
+            let mut url = token_url.url().clone();
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(name, _)| {
+                    !openid
+                        .extra_token_params
+                        .iter()
+                        .any(|(param, _)| param == name)
+                })
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .chain(openid.extra_token_params.iter().cloned())
+                .collect();
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+            metadata = metadata.set_token_endpoint(Some(TokenUrl::from_url(url)));
+        }
+        let client = ProviderClientType::from_provider_metadata(
+            metadata.clone(),
+            openid.client_id.clone(),
+            openid
+                .client_secret
+                .map(|secret| ClientSecret::new(secret.expose_secret().clone())),
+        )
+        .set_redirect_uri(openid.redirect_url.clone());
+        Provider {
+            client: Arc::new(client),
+            metadata: Arc::new(metadata),
+            documents,
+            discovered: true,
+        }
+    }
+
+    /// Placeholders standing in for the documents of a provider yet to be discovered.
+    fn pending(issuer_url: &IssuerUrl, discovery_url: Url, openid: ProviderClient<'_>) -> Self {
+        Provider {
+            discovered: false,
+            ..Provider::new(
+                ProviderDocuments::pending(issuer_url, discovery_url),
+                openid,
+            )
+        }
+    }
+}
+
+/// The client settings the provider's client is built with.
+struct ProviderClient<'a> {
+    client_id: &'a ClientId,
+    client_secret: Option<&'a SecretString>,
+    redirect_url: &'a RedirectUrl,
+    extra_token_params: &'a [(String, String)],
+}
+
+/// The tokens of a login. Its `Debug` output leaves them out.
+#[derive(Deserialize, Serialize)]
 pub struct OpenIDTokens {
     pub access_token: AccessToken,
     pub id_token: IdToken,
     pub refresh_token: Option<RefreshToken>,
+    /// Lifetime of the access token, if the provider told.
+    pub expires_in: Option<Duration>,
+    /// Lifetime of the refresh token, if the provider told with `refresh_expires_in` like
+    /// Keycloak does.
+    pub refresh_expires_in: Option<Duration>,
+}
+
+/// Never the tokens.
+impl Debug for OpenIDTokens {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpenIDTokens")
+            .field("expires_in", &self.expires_in)
+            .field("refresh_expires_in", &self.refresh_expires_in)
+            .finish_non_exhaustive()
+    }
+}
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// How much longer to wait between polls after a `slow_down`, RFC 8628 section 3.5.
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+
+/// A device authorization [started](OpenID::start_device_authorization) at the provider: the
+/// user opens `verification_uri` on another device and enters `user_code`, while the device
+/// [polls](OpenID::poll_device_token) for the tokens with `device_code`.
+#[derive(Clone, Debug)]
+pub struct DeviceAuthorization {
+    pub device_code: DeviceCode,
+    pub user_code: UserCode,
+    pub verification_uri: EndUserVerificationUrl,
+    /// `verification_uri` with the user code in it, e.g. for a QR code, if the provider sends
+    /// one.
+    pub verification_uri_complete: Option<VerificationUriComplete>,
+    /// Lifetime of the device code.
+    pub expires_in: Duration,
+    /// How long to wait between polls, 5 seconds unless the provider tells.
+    pub interval: Duration,
+}
+
+#[derive(Deserialize)]
+struct DeviceAuthorizationResponse {
+    device_code: DeviceCode,
+    user_code: UserCode,
+    // Google names it `verification_url`.
+    #[serde(alias = "verification_url")]
+    verification_uri: EndUserVerificationUrl,
+    verification_uri_complete: Option<VerificationUriComplete>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+impl From<DeviceAuthorizationResponse> for DeviceAuthorization {
+    fn from(response: DeviceAuthorizationResponse) -> Self {
+        DeviceAuthorization {
+            device_code: response.device_code,
+            user_code: response.user_code,
+            verification_uri: response.verification_uri,
+            verification_uri_complete: response.verification_uri_complete,
+            expires_in: Duration::from_secs(response.expires_in),
+            interval: Duration::from_secs(response.interval.unwrap_or(5)),
+        }
+    }
+}
+
+/// Tokens issued for a refresh token, providers may not issue a new ID or refresh token.
+#[derive(Clone)]
+pub struct RefreshedTokens {
+    pub access_token: AccessToken,
+    pub id_token: Option<IdToken>,
+    pub refresh_token: Option<RefreshToken>,
+    pub expires_in: Option<Duration>,
+    pub refresh_expires_in: Option<Duration>,
 }
 
 pub struct AuthorizationUrl {
     pub url: Url,
+    /// The CSRF token and the path to return to, separated by `|`.
+    pub state: CsrfToken,
+    /// The CSRF token of `state`, which the callback expects in the `oauth_state` cookie of the
+    /// [login attempt](Self::login_attempt).
+    pub csrf_token: CsrfToken,
     pub nonce: Nonce,
+    /// The PKCE verifier of the challenge sent, `None` unless the client uses PKCE.
+    pub pkce_verifier: Option<PkceCodeVerifier>,
+}
+
+impl AuthorizationUrl {
+    /// The id suffixing the names of the cookies the callback checks this login with, e.g.
+    /// `nonce.<id>`, so logins started in parallel, e.g. in several tabs, keep their own.
+    pub fn login_attempt(&self) -> &str {
+        login_attempt(self.csrf_token.secret())
+    }
+}
+
+/// Separates the CSRF token from the return path in the `state` parameter, random tokens are
+/// URL-safe and never contain it.
+const STATE_SEPARATOR: char = '|';
+
+/// Characters of the CSRF token naming its login attempt.
+const LOGIN_ATTEMPT_LEN: usize = 8;
+
+/// The id of the login attempt of `csrf_token`, the start of the token, see
+/// [`AuthorizationUrl::login_attempt`].
+pub(crate) fn login_attempt(csrf_token: &str) -> &str {
+    csrf_token.get(..LOGIN_ATTEMPT_LEN).unwrap_or(csrf_token)
+}
+
+fn login_state(csrf_token: &str, path: &str) -> String {
+    format!("{}{}{}", csrf_token, STATE_SEPARATOR, path)
+}
+
+/// The CSRF token and the return path of a `state` parameter, `None` if it has no token.
+pub(crate) fn split_login_state(state: &str) -> Option<(&str, &str)> {
+    state.split_once(STATE_SEPARATOR)
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AdditionalMetadata {
     end_session_endpoint: Option<EndSessionUrl>,
+    #[serde(default)]
+    frontchannel_logout_supported: bool,
+    #[serde(default)]
+    backchannel_logout_supported: bool,
+    /// RFC 7009 token revocation, from OAuth 2.0 Authorization Server Metadata (RFC 8414).
+    revocation_endpoint: Option<Url>,
+    /// RFC 7662 token introspection, see [`OpenID::introspect`].
+    introspection_endpoint: Option<Url>,
+    /// RFC 8628 device authorization, see [`OpenID::start_device_authorization`].
+    device_authorization_endpoint: Option<Url>,
 }
 
 impl AdditionalProviderMetadata for AdditionalMetadata {}
@@ -61,116 +458,1752 @@ pub type ExtendedProviderMetadata = ProviderMetadata<
     CoreSubjectIdentifierType,
 >;
 
-pub(crate) type IdToken = openidconnect::IdToken<
+/// An ID token signed with the algorithms of the core profile, the one of
+/// [`AuthenticatedUser::id_token`](crate::openid_middleware::AuthenticatedUser::id_token).
+pub type IdToken<AC = EmptyAdditionalClaims> = openidconnect::IdToken<
+    AC,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+>;
+
+/// The claims of a userinfo response, those of `AC` included, e.g. the
+/// [`access`](crate::openid_middleware::AuthenticatedUser::access) of a user.
+pub type UserInfo<AC = EmptyAdditionalClaims> = UserInfoClaims<AC, CoreGenderClaim>;
+
+/// Every claim of a token or userinfo response by name, for claims without a type of their own.
+///
+/// The middleware keeps the users' claims this way, [`Authenticated`] turns them into the
+/// additional claims type of the handler.
+///
+/// [`Authenticated`]: crate::openid_middleware::Authenticated
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct OtherClaims {
+    #[serde(flatten)]
+    claims: serde_json::Map<String, serde_json::Value>,
+}
+
+impl OtherClaims {
+    pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+        self.claims.get(name)
+    }
+
+    pub fn claims(&self) -> &serde_json::Map<String, serde_json::Value> {
+        &self.claims
+    }
+}
+
+impl AdditionalClaims for OtherClaims {}
+
+#[derive(Deserialize)]
+struct JwsHeader {
+    alg: CoreJwsSigningAlgorithm,
+    kid: Option<JsonWebKeyId>,
+}
+
+/// The id of the key a compact JWS names in its header, before checking anything.
+fn jws_key_id(jws: &str) -> Option<JsonWebKeyId> {
+    let header = URL_SAFE_NO_PAD.decode(jws.split('.').next()?).ok()?;
+    serde_json::from_slice::<JwsHeader>(&header).ok()?.kid
+}
+
+/// The claims of a JWT whose signature was checked before.
+pub(crate) fn jwt_payload(jwt: &str) -> Result<serde_json::Value> {
+    let invalid =
+        |reason: String| ClaimsVerificationError::Other(format!("invalid JWT: {}", reason));
+    let payload = jwt
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| invalid("not a compact JWS".to_string()))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|err| invalid(err.to_string()))?;
+    Ok(serde_json::from_slice(&payload).map_err(|err| invalid(err.to_string()))?)
+}
+
+/// The fields of token responses beyond the standard ones.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct TokenFields {
+    /// Seconds the refresh token is valid, `0` for refresh tokens that do not expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_expires_in: Option<u64>,
+}
+
+impl ExtraTokenFields for TokenFields {}
+
+type ProviderTokenResponse = StandardTokenResponse<
+    openidconnect::IdTokenFields<
+        EmptyAdditionalClaims,
+        TokenFields,
+        CoreGenderClaim,
+        CoreJweContentEncryptionAlgorithm,
+        CoreJwsSigningAlgorithm,
+        CoreJsonWebKeyType,
+    >,
+    CoreTokenType,
+>;
+
+/// `CoreClient` reading [`TokenFields`] from the token responses.
+type ProviderClientType = Client<
     EmptyAdditionalClaims,
+    CoreAuthDisplay,
     CoreGenderClaim,
     CoreJweContentEncryptionAlgorithm,
     CoreJwsSigningAlgorithm,
     CoreJsonWebKeyType,
+    CoreJsonWebKeyUse,
+    CoreJsonWebKey,
+    CoreAuthPrompt,
+    StandardErrorResponse<CoreErrorResponseType>,
+    ProviderTokenResponse,
+    CoreTokenType,
+    CoreTokenIntrospectionResponse,
+    CoreRevocableToken,
+    CoreRevocationErrorResponse,
 >;
 
+fn refresh_expires_in(token_response: &ProviderTokenResponse) -> Option<Duration> {
+    token_response
+        .extra_fields()
+        .extra_fields()
+        .refresh_expires_in
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs)
+}
+
+impl From<&ProviderTokenResponse> for RefreshedTokens {
+    fn from(token_response: &ProviderTokenResponse) -> Self {
+        RefreshedTokens {
+            access_token: token_response.access_token().clone(),
+            id_token: token_response.id_token().cloned(),
+            refresh_token: token_response.refresh_token().cloned(),
+            expires_in: token_response.expires_in(),
+            refresh_expires_in: refresh_expires_in(token_response),
+        }
+    }
+}
+
+impl TryFrom<&ProviderTokenResponse> for OpenIDTokens {
+    type Error = OpenIdError;
+
+    fn try_from(token_response: &ProviderTokenResponse) -> Result<Self> {
+        let id_token = token_response.id_token().cloned().ok_or_else(|| {
+            ClaimsVerificationError::Other("the token response has no ID token".to_string())
+        })?;
+        Ok(OpenIDTokens {
+            access_token: token_response.access_token().clone(),
+            id_token,
+            refresh_token: token_response.refresh_token().cloned(),
+            expires_in: token_response.expires_in(),
+            refresh_expires_in: refresh_expires_in(token_response),
+        })
+    }
+}
+
+/// The OAuth error of an error response, if it has one.
+pub(crate) fn provider_error(body: &[u8]) -> Option<ProviderError> {
+    let body = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+    Some(ProviderError {
+        error: body.get("error")?.as_str()?.to_string(),
+        error_description: body
+            .get("error_description")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string),
+        error_uri: body
+            .get("error_uri")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+/// An http client keeping the status of the response, oauth2 drops it from error responses but
+/// it tells outages from refusals.
+fn status_recording_client<'a>(
+    http: &'a HttpClient,
+    status: &'a mut Option<u16>,
+) -> impl FnOnce(HttpRequest) -> BoxFuture<'a, std::result::Result<HttpResponse, AsyncHttpClientError>>
+       + 'a {
+    move |request| {
+        Box::pin(async move {
+            let response = http.execute(request).await?;
+            *status = Some(response.status_code.as_u16());
+            Ok(response)
+        })
+    }
+}
+
 impl OpenID {
-    pub(crate) async fn init(
-        client_id: String,
-        client_secret: String,
-        redirect_uri: String,
-        issuer_url: String,
-        post_logout_redirect_url: Option<String>,
-        scopes: Vec<String>,
-    ) -> Result<Self> {
-        let provider_metadata = ExtendedProviderMetadata::discover_async(
-            IssuerUrl::new(issuer_url)?,
-            async_http_client,
+    pub(crate) async fn init(config: OpenIdBuilder) -> Result<Self> {
+        let client_id = OpenIdBuilder::required(&config.client_id, "client_id")?;
+        let redirect_uri = OpenIdBuilder::required(&config.redirect_url, "redirect_url")?;
+        let issuer_url = OpenIdBuilder::required(&config.issuer_url, "issuer_url")?;
+        let issuer_url = IssuerUrl::new(issuer_url.to_string())
+            .map_err(|err| OpenIdError::Config(format!("invalid issuer url: {}", err)))?;
+        let health = Arc::new(Health::new(config.health_thresholds, config.clock.clone()));
+        let http = HttpClient::new(
+            config.http_client,
+            &config.pool,
+            config.retry_policy,
+            health.clone(),
         )
-        .await
-        .expect("Failed to discover OpenID Provider");
-        let client = CoreClient::from_provider_metadata(
-            provider_metadata.clone(),
-            ClientId::new(client_id.to_string()),
-            Some(ClientSecret::new(client_secret.to_string())),
+        .map_err(|err| OpenIdError::Config(format!("invalid http client: {}", err)))?;
+        let discovery_url = match &config.discovery_url {
+            Some(discovery_url) => Url::parse(discovery_url)
+                .map_err(|err| OpenIdError::Config(format!("invalid discovery url: {}", err)))?,
+            None => provider_cache::discovery_url(&issuer_url)?,
+        };
+        let documents = match ProviderDocuments::discover(
+            &http,
+            config.clock.as_ref(),
+            &discovery_url,
+            &issuer_url,
+            &config.issuer_validation,
         )
-        .set_redirect_uri(
-            RedirectUrl::new(redirect_uri.to_string()).expect("Invalid redirect URL"),
-        );
+        .await
+        {
+            Ok(documents) => Some(documents),
+            Err(err)
+                if matches!(
+                    config.discovery_policy,
+                    DiscoveryPolicy::RetryForever { .. }
+                ) =>
+            {
+                config.log_policy.log_error(
+                    LogCategory::IdpError,
+                    format_args!("Could not discover the provider, retrying on use: {}", err),
+                );
+                None
+            }
+            Err(err) => return Err(err),
+        };
+        // Times the browser could edit would not limit anything.
+        if config.session_limits.is_enabled()
+            && config.cookie_key.is_none()
+            && config.session_store.is_none()
+        {
+            return Err(OpenIdError::Config(
+                "session limits need a cookie key or a session store to record the session times"
+                    .to_string(),
+            ));
+        }
+        for (name, path) in [
+            ("callback", &config.callback_path),
+            ("logout", &config.logout_path),
+        ] {
+            if !path.starts_with('/') || path.len() == 1 {
+                return Err(OpenIdError::Config(format!(
+                    "the {} path {} does not start with / or is the root",
+                    name, path
+                )));
+            }
+        }
+        let realm = if config.namespace_cookies && config.realm == Realm::default() {
+            Realm::namespaced(issuer_url.as_str(), client_id)
+        } else {
+            config.realm
+        }
+        .with_cookie_prefix(config.cookie_config.name_prefix())
+        .with_routes(config.callback_path, config.logout_path);
+        let mut redirect_uri = Url::parse(redirect_uri)
+            .map_err(|err| OpenIdError::Config(format!("invalid redirect url: {}", err)))?;
+        // A bare origin follows the callback path.
+        if redirect_uri.path() == "/" {
+            redirect_uri.set_path(&realm.callback_path());
+        }
+        let redirect_url = RedirectUrl::from_url(redirect_uri);
+        let client_id = ClientId::new(client_id.to_string());
+        let client_secret = config.client_secret;
+        // Public clients have nothing else proving that the code was issued to them.
+        let pkce = config.pkce.unwrap_or(client_secret.is_none());
+        let provider_client = ProviderClient {
+            client_id: &client_id,
+            client_secret: client_secret.as_ref(),
+            redirect_url: &redirect_url,
+            extra_token_params: &config.extra_token_params,
+        };
+        let provider = match documents {
+            Some(documents) => Provider::new(documents, provider_client),
+            None => Provider::pending(&issuer_url, discovery_url, provider_client),
+        };
+        let mut extra_auth_params = config.extra_auth_params;
+        if config.form_post {
+            extra_auth_params.push(("response_mode".to_string(), "form_post".to_string()));
+        }
+        let post_logout_redirect_url = config
+            .post_logout_redirect_url
+            .map(PostLogoutRedirectUrl::new)
+            .transpose()
+            .map_err(|err| {
+                OpenIdError::Config(format!("invalid post logout redirect url: {}", err))
+            })?;
         Ok(Self {
-            client,
-            provider_metadata,
+            client_id,
+            client_secret,
+            issuer_url,
+            provider: Arc::new(RwLock::new(provider)),
+            http,
+            discovery_policy: config.discovery_policy,
+            next_discovery: Arc::default(),
+            redirect_url: redirect_url.url().clone(),
             post_logout_redirect_url,
-            scopes: scopes.iter().map(|s| Scope::new(s.to_string())).collect(),
+            revoke_access_token: config.revoke_access_token,
+            scopes: config
+                .scopes
+                .iter()
+                .map(|s| Scope::new(s.to_string()))
+                .collect(),
+            client_token_scopes: config
+                .client_token_scopes
+                .into_iter()
+                .map(Scope::new)
+                .collect(),
+            client_tokens: Arc::default(),
+            extra_auth_params,
+            extra_token_params: config.extra_token_params,
+            claims_request: config.claims_request,
+            issuer_validation: config.issuer_validation,
+            validation_mode: config.validation_mode,
+            require_signed_userinfo: config.require_signed_userinfo,
+            token_hash_validation: config.token_hash_validation,
+            id_token_validation: config.id_token_validation,
+            roles_claim: config.roles_claim,
+            random: config.random,
+            error_action: config.error_action,
+            callback_errors: config.callback_errors,
+            login_redirect: config.login_redirect,
+            log_policy: config.log_policy,
+            realm,
+            messages: config.messages,
+            events: config.events,
+            login_hook: config.login_hook,
+            trusted_proxies: config.trusted_proxies,
+            pages: config.pages,
+            breaker: Arc::new(CircuitBreaker::new(config.breaker, config.clock.clone())),
+            not_before: Arc::new(NotBefore::new(
+                config.not_before_policy,
+                config.keycloak_push_not_before,
+                config.clock.clone(),
+            )),
+            userinfo_cache: config.userinfo_cache.map(|(ttl, max_entries)| {
+                Arc::new(UserInfoCache::new(ttl, max_entries, config.clock.clone()))
+            }),
+            negative_cache: config.negative_cache.map(|(ttl, max_entries)| {
+                Arc::new(NegativeCache::new(ttl, max_entries, config.clock.clone()))
+            }),
+            userinfo_limiter: config
+                .userinfo_concurrency
+                .map(|max_calls| Arc::new(UserInfoLimiter::new(max_calls))),
+            refresh_lock: Arc::new(RefreshLock::new(config.clock.clone())),
+            exchange_cache: Arc::new(ExchangeCache::new(config.clock.clone())),
+            max_provider_staleness: config.max_provider_staleness,
+            health,
+            tasks: TaskSet::new(),
+            clock: config.clock,
+            payload_codec: config.payload_codec,
+            pkce,
+            login_window: config.login_window,
+            cookie_key: config.cookie_key,
+            cookie_config: config.cookie_config.cross_site_logins(config.form_post),
+            user_info_cookie: config.user_info_cookie,
+            session_store: config.session_store,
+            session_limits: config.session_limits,
+            #[cfg(feature = "first-party-session")]
+            session_format: config.session_format,
+            front_channel_logout: config.front_channel_logout,
+            back_channel_logout: config.back_channel_logout,
         })
     }
 
-    pub(crate) fn get_authorization_url(&self, path: String) -> AuthorizationUrl {
-        let authorize_url_builder = self
-            .client
+    pub(crate) fn client_id(&self) -> &str {
+        self.client_id.as_str()
+    }
+
+    /// The current time of the client's [`Clock`].
+    pub(crate) fn now(&self) -> SystemTime {
+        system_time(self.clock.as_ref())
+    }
+
+    pub(crate) fn tasks(&self) -> &TaskSet {
+        &self.tasks
+    }
+
+    pub(crate) fn not_before(&self) -> &NotBefore {
+        &self.not_before
+    }
+
+    /// Dotted path of the claim holding the user's roles, e.g. `realm_access.roles`.
+    pub fn roles_claim(&self) -> Option<&str> {
+        self.roles_claim.as_deref()
+    }
+
+    /// The user-facing text for `key`, see [`Messages`].
+    pub fn message(&self, key: MessageKey, args: &[(&str, String)]) -> String {
+        self.messages.message(key, args)
+    }
+
+    pub(crate) fn events(&self) -> &dyn AuthEvents {
+        self.events.as_ref()
+    }
+
+    pub(crate) fn login_hook(&self) -> Option<&dyn LoginHook> {
+        self.login_hook.as_deref()
+    }
+
+    /// The context of an event of this client at `path`, see [`AuthEvents`].
+    pub(crate) fn event_context<'a>(&'a self, path: Option<&'a str>) -> EventContext<'a> {
+        EventContext {
+            realm: self.realm.id(),
+            path,
+        }
+    }
+
+    /// Where the client is mounted and how its cookies are named.
+    pub fn realm(&self) -> &Realm {
+        &self.realm
+    }
+
+    /// The attributes of the cookies the client sets.
+    pub fn cookie_config(&self) -> &CookieConfig {
+        &self.cookie_config
+    }
+
+    pub fn user_info_cookie(&self) -> UserInfoCookie {
+        self.user_info_cookie
+    }
+
+    /// The claims of the `user_info` cookie of `req`, whether [plain](UserInfoCookie::Plain) or
+    /// [chunked](UserInfoCookie::Chunked). `None` without the cookie or when it does not
+    /// decode.
+    pub fn cookie_user_info(&self, req: &actix_web::HttpRequest) -> Option<OtherClaims> {
+        let payload = match self.auth_cookie(req, AuthCookies::UserInfo) {
+            Some(payload) => payload,
+            None => {
+                let name = self.realm.cookie_name(AuthCookies::UserInfo);
+                let chunks = (0..)
+                    .map_while(|index| req.cookie(&user_info_chunk(name, index)))
+                    .map(|chunk| self.open_cookie(chunk))
+                    .collect::<Option<Vec<_>>>()?;
+                if chunks.is_empty() {
+                    return None;
+                }
+                chunks.concat()
+            }
+        };
+        PayloadCodec::decode(&payload).ok()
+    }
+
+    /// Where the sessions are kept, `None` if they are kept in the session cookies.
+    pub(crate) fn session_store(&self) -> Option<&dyn SessionStore> {
+        self.session_store.as_deref()
+    }
+
+    pub(crate) fn session_limits(&self) -> &SessionLimits {
+        &self.session_limits
+    }
+
+    /// How the browser keeps the session, see [`SessionFormat`].
+    #[cfg(feature = "first-party-session")]
+    pub(crate) fn session_format(&self) -> &SessionFormat {
+        &self.session_format
+    }
+
+    /// Path of the front-channel logout endpoint, `None` unless enabled.
+    pub(crate) fn front_channel_logout(&self) -> Option<&str> {
+        self.front_channel_logout.as_deref()
+    }
+
+    /// Path of the back-channel logout endpoint, `None` unless enabled.
+    pub(crate) fn back_channel_logout(&self) -> Option<&str> {
+        self.back_channel_logout.as_deref()
+    }
+
+    /// How the claims the client serializes are encoded.
+    pub fn payload_codec(&self) -> PayloadCodec {
+        self.payload_codec
+    }
+
+    /// How long a login may take at the provider, see
+    /// [`OpenIdBuilder::login_window`](crate::OpenIdBuilder::login_window).
+    pub(crate) fn login_window(&self) -> Duration {
+        self.login_window
+    }
+
+    /// Whether the client has no secret, e.g. a native app, see
+    /// [`OpenIdBuilder::client_secret`].
+    pub fn is_public_client(&self) -> bool {
+        self.client_secret.is_none()
+    }
+
+    /// Whether authorization urls carry a PKCE challenge, see [`OpenIdBuilder::pkce`].
+    pub fn pkce(&self) -> bool {
+        self.pkce
+    }
+
+    /// The value of `cookie` in `req`, `None` if it is missing or does not decrypt with the
+    /// cookie key.
+    pub(crate) fn auth_cookie(
+        &self,
+        req: &actix_web::HttpRequest,
+        cookie: AuthCookies,
+    ) -> Option<String> {
+        self.open_cookie(req.cookie(self.realm.cookie_name(cookie))?)
+    }
+
+    /// The value of the login cookie `cookie` of the login attempt `attempt`, or the unsuffixed
+    /// one of a login started before the cookies were named after their attempt.
+    pub(crate) fn login_cookie(
+        &self,
+        req: &actix_web::HttpRequest,
+        cookie: AuthCookies,
+        attempt: &str,
+    ) -> Option<String> {
+        match req.cookie(&login_cookie_name(self.realm.cookie_name(cookie), attempt)) {
+            Some(value) => self.open_cookie(value),
+            None => self.auth_cookie(req, cookie),
+        }
+    }
+
+    /// The value of `cookie`, decrypted with the cookie key.
+    fn open_cookie(&self, cookie: Cookie<'static>) -> Option<String> {
+        match &self.cookie_key {
+            Some(key) => CookieJar::new()
+                .private(key)
+                .decrypt(cookie)
+                .map(|cookie| cookie.value().to_string()),
+            None => Some(cookie.value().to_string()),
+        }
+    }
+
+    /// The auth cookies of `req` that do not decrypt with the cookie key.
+    pub(crate) fn unreadable_cookies(&self, req: &actix_web::HttpRequest) -> Vec<AuthCookies> {
+        if self.cookie_key.is_none() {
+            return Vec::new();
+        }
+        AuthCookies::ALL
+            .into_iter()
+            .filter(|&cookie| {
+                req.cookie(self.realm.cookie_name(cookie)).is_some()
+                    && self.auth_cookie(req, cookie).is_none()
+            })
+            .collect()
+    }
+
+    /// `cookie` with its value encrypted with the cookie key, unchanged without one.
+    pub(crate) fn seal_cookie(&self, cookie: Cookie<'_>) -> Cookie<'static> {
+        let cookie = cookie.into_owned();
+        let Some(key) = &self.cookie_key else {
+            return cookie;
+        };
+        let mut jar = CookieJar::new();
+        jar.private_mut(key).add(cookie.clone());
+        jar.get(cookie.name()).cloned().unwrap_or(cookie)
+    }
+
+    /// The claims asked for with the authorization url.
+    pub fn claims_request(&self) -> &ClaimsRequest {
+        &self.claims_request
+    }
+
+    /// A clone asking for `claims_request` instead, sharing the provider documents.
+    pub(crate) fn with_claims_request(&self, claims_request: ClaimsRequest) -> Self {
+        OpenID {
+            claims_request,
+            ..self.clone()
+        }
+    }
+
+    pub fn validation_mode(&self) -> ValidationMode {
+        self.validation_mode
+    }
+
+    pub fn issuer_validation(&self) -> &IssuerValidation {
+        &self.issuer_validation
+    }
+
+    /// Builds the URL sending the user to the provider, coming back to `path` once logged in.
+    ///
+    /// The returned state and nonce are the ones embedded in the URL.
+    pub fn get_authorization_url(&self, path: String) -> AuthorizationUrl {
+        self.authorization_url(path, &LoginParams::default(), false, None)
+    }
+
+    /// Like [`get_authorization_url`](Self::get_authorization_url), with the scopes of `params`
+    /// added to the client's, and its parameters replacing the client's of the same name.
+    pub fn get_authorization_url_with(
+        &self,
+        path: String,
+        params: &LoginParams,
+    ) -> AuthorizationUrl {
+        self.authorization_url(path, params, false, None)
+    }
+
+    /// An authorization url, with `prompt=none` if `silent`: the provider answers without showing
+    /// a page, with an error if the user has no session there. Sends the user back to
+    /// `redirect_url` instead of the configured redirect url, see
+    /// [`forwarded_redirect_url`](Self::forwarded_redirect_url).
+    pub(crate) fn authorization_url(
+        &self,
+        path: String,
+        params: &LoginParams,
+        silent: bool,
+        redirect_url: Option<&Url>,
+    ) -> AuthorizationUrl {
+        let random = self.random.clone();
+        let csrf_token = self.random.random_token();
+        let state = login_state(&csrf_token, &path);
+        let client = self.client();
+        let mut authorize_url_builder = client
             .authorize_url(
                 CoreAuthenticationFlow::AuthorizationCode,
-                move || CsrfToken::new(path.clone()),
-                Nonce::new_random,
+                move || CsrfToken::new(state.clone()),
+                move || Nonce::new(random.random_token()),
             )
-            .add_scopes(self.scopes.clone());
-        let (auth_url, _csrf_token, nonce) = authorize_url_builder.url();
+            .add_scopes(self.scopes.clone())
+            .add_scopes(params.scopes().iter().cloned());
+        let overridden = |name: &str| params.extra_params().iter().any(|(param, _)| param == name);
+        for (name, value) in self
+            .extra_auth_params
+            .iter()
+            .filter(|(name, _)| !overridden(name))
+        {
+            authorize_url_builder = authorize_url_builder.add_extra_param(name, value);
+        }
+        for (name, value) in params.extra_params() {
+            authorize_url_builder = authorize_url_builder.add_extra_param(name, value);
+        }
+        if let Some(claims) = self.claims_request.to_parameter() {
+            authorize_url_builder = authorize_url_builder.add_extra_param("claims", claims);
+        }
+        // Users the provider authenticated longer ago enter their credentials again, rather
+        // than the callback refusing them.
+        let max_age_given = overridden("max_age")
+            || self
+                .extra_auth_params
+                .iter()
+                .any(|(name, _)| name == "max_age");
+        if let Some(lifetime) = self
+            .session_limits
+            .absolute_lifetime
+            .filter(|_| !max_age_given)
+        {
+            authorize_url_builder = authorize_url_builder.set_max_age(lifetime);
+        }
+        if silent {
+            authorize_url_builder = authorize_url_builder.add_prompt(CoreAuthPrompt::None);
+        }
+        if let Some(redirect_url) = redirect_url {
+            authorize_url_builder = authorize_url_builder
+                .set_redirect_uri(Cow::Owned(RedirectUrl::from_url(redirect_url.clone())));
+        }
+        let pkce_verifier = self.pkce.then(|| self.pkce_verifier());
+        if let Some(verifier) = &pkce_verifier {
+            authorize_url_builder = authorize_url_builder
+                .set_pkce_challenge(PkceCodeChallenge::from_code_verifier_sha256(verifier));
+        }
+        let (url, state, nonce) = authorize_url_builder.url();
 
         AuthorizationUrl {
-            url: auth_url,
+            url,
+            state,
+            csrf_token: CsrfToken::new(csrf_token),
             nonce,
+            pkce_verifier,
+        }
+    }
+
+    /// A verifier of 43 to 128 characters, the length RFC 7636 requires, from the random source.
+    fn pkce_verifier(&self) -> PkceCodeVerifier {
+        let mut verifier = String::new();
+        while verifier.len() < 43 {
+            verifier.push_str(&self.random.random_token());
         }
+        verifier.truncate(128);
+        PkceCodeVerifier::new(verifier)
     }
 
-    pub(crate) async fn get_token(
+    /// Exchanges the authorization code received by the callback for tokens.
+    pub async fn get_token(&self, authorization_code: AuthorizationCode) -> Result<OpenIDTokens> {
+        self.exchange_code(authorization_code, None).await
+    }
+
+    /// Exchanges the authorization code, proving the PKCE challenge of the authorization url
+    /// with `pkce_verifier`.
+    pub async fn exchange_code(
         &self,
         authorization_code: AuthorizationCode,
+        pkce_verifier: Option<PkceCodeVerifier>,
     ) -> Result<OpenIDTokens> {
+        self.exchange_code_at(authorization_code, pkce_verifier, None)
+            .await
+    }
+
+    /// Exchanges the authorization code of a login that was sent back to `redirect_url` instead
+    /// of the configured redirect url, which the token request must name again.
+    pub(crate) async fn exchange_code_at(
+        &self,
+        authorization_code: AuthorizationCode,
+        pkce_verifier: Option<PkceCodeVerifier>,
+        redirect_url: Option<&Url>,
+    ) -> Result<OpenIDTokens> {
+        let mut status = None;
+        let client = self.client();
+        let mut request = client.exchange_code(authorization_code);
+        if let Some(pkce_verifier) = pkce_verifier {
+            request = request.set_pkce_verifier(pkce_verifier);
+        }
+        if let Some(redirect_url) = redirect_url {
+            request =
+                request.set_redirect_uri(Cow::Owned(RedirectUrl::from_url(redirect_url.clone())));
+        }
+        let token_response = request
+            .request_async(status_recording_client(&self.http, &mut status))
+            .await
+            .map_err(|err| OpenIdError::token_exchange(err, status))?;
+        OpenIDTokens::try_from(&token_response)
+    }
+
+    /// Exchanges a refresh token for a new access token.
+    ///
+    /// Concurrent refreshes of a token make one exchange, and refreshes of a token replaced
+    /// moments ago get the tokens that replaced it: providers rotating refresh tokens only
+    /// accept them once. Fails with [`OpenIdError::RefreshTokenRevoked`] when the provider
+    /// answers `invalid_grant`.
+    pub async fn refresh(&self, refresh_token: &RefreshToken) -> Result<RefreshedTokens> {
+        self.refresh_lock
+            .refresh(refresh_token, || self.exchange_refresh_token(refresh_token))
+            .await
+    }
+
+    async fn exchange_refresh_token(
+        &self,
+        refresh_token: &RefreshToken,
+    ) -> Result<RefreshedTokens> {
+        let mut status = None;
         let token_response = self
-            .client
-            .exchange_code(authorization_code)
-            .request_async(async_http_client)
-            .await?;
-        let id_token = token_response.id_token().cloned();
-        Ok(OpenIDTokens {
-            access_token: token_response.access_token().clone(),
-            id_token: id_token.unwrap(),
-            refresh_token: token_response.refresh_token().cloned(),
+            .client()
+            .exchange_refresh_token(refresh_token)
+            .request_async(status_recording_client(&self.http, &mut status))
+            .await
+            .map_err(|err| match OpenIdError::token_exchange(err, status) {
+                OpenIdError::TokenExchange {
+                    provider_error: Some(provider_error),
+                    ..
+                } if provider_error.error == "invalid_grant" => {
+                    OpenIdError::RefreshTokenRevoked(provider_error)
+                }
+                err => err,
+            });
+        self.events
+            .on_refresh(&self.event_context(None), token_response.is_ok());
+        Ok(RefreshedTokens::from(&token_response?))
+    }
+
+    /// Requests a token for the client itself with the client credentials grant.
+    pub(crate) async fn client_credentials(&self, scopes: &[Scope]) -> Result<RefreshedTokens> {
+        let mut status = None;
+        let token_response = self
+            .client()
+            .exchange_client_credentials()
+            .add_scopes(scopes.iter().cloned())
+            .request_async(status_recording_client(&self.http, &mut status))
+            .await
+            .map_err(|err| OpenIdError::token_exchange(err, status))?;
+        Ok(RefreshedTokens::from(&token_response))
+    }
+
+    /// Exchanges a user's credentials for tokens with the resource owner password grant.
+    pub(crate) async fn password(&self, username: &str, password: &str) -> Result<RefreshedTokens> {
+        let username = ResourceOwnerUsername::new(username.to_string());
+        let password = ResourceOwnerPassword::new(password.to_string());
+        let mut status = None;
+        let token_response = self
+            .client()
+            .exchange_password(&username, &password)
+            .add_scopes(self.scopes.iter().cloned())
+            .request_async(status_recording_client(&self.http, &mut status))
+            .await
+            .map_err(|err| OpenIdError::token_exchange(err, status))?;
+        Ok(RefreshedTokens::from(&token_response))
+    }
+
+    /// Revokes `token` at the provider's revocation endpoint (RFC 7009), e.g. the refresh tokens
+    /// of a user who changed their password. Authenticates like the token requests.
+    ///
+    /// Returns `false` without asking when the provider advertises no revocation endpoint.
+    /// Revoking a token the provider does not know, such as an expired one, succeeds.
+    pub async fn revoke_token(&self, token: &str, hint: TokenTypeHint) -> Result<bool> {
+        let Some(endpoint) = self
+            .provider_metadata()
+            .additional_metadata()
+            .revocation_endpoint
+            .clone()
+        else {
+            return Ok(false);
+        };
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("token", token)
+            .append_pair("token_type_hint", hint.as_str());
+        let response = self.post_form(endpoint, form).await?;
+        let status = response.status_code.as_u16();
+        if response.status_code.is_success() {
+            return Ok(true);
+        }
+        Err(OpenIdError::TokenExchange {
+            provider_error: provider_error(&response.body),
+            status: Some(status),
+            source: format!(
+                "the revocation endpoint answered with HTTP status {}",
+                status
+            )
+            .into(),
         })
     }
 
-    pub(crate) async fn user_info(
+    /// Asks the provider's introspection endpoint (RFC 7662) about the access token `token`,
+    /// authenticated like the token requests. Inactive tokens are not an error, their result
+    /// says so.
+    ///
+    /// Fails with [`OpenIdError::Config`] when the provider advertises no introspection endpoint.
+    pub async fn introspect(&self, token: &str) -> Result<IntrospectionResult> {
+        let Some(endpoint) = self
+            .provider_metadata()
+            .additional_metadata()
+            .introspection_endpoint
+            .clone()
+        else {
+            return Err(OpenIdError::Config(
+                "the provider advertises no introspection endpoint".to_string(),
+            ));
+        };
+        if self.breaker.is_open() {
+            return Err(OpenIdError::UserInfo("the circuit breaker is open".into()));
+        }
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("token", token)
+            .append_pair("token_type_hint", TokenTypeHint::AccessToken.as_str());
+        let result = match self.post_form(endpoint, form).await {
+            Ok(response) if response.status_code.is_success() => {
+                serde_json::from_slice::<IntrospectionResult>(&response.body)
+                    .map_err(|err| OpenIdError::UserInfo(Box::new(err)))
+            }
+            Ok(response) => Err(OpenIdError::Http {
+                status: response.status_code.as_u16(),
+            }),
+            Err(err) => Err(err),
+        };
+        let unavailable = result
+            .as_ref()
+            .is_err_and(|err| matches!(self.error_action(err), ErrorAction::RetryLater(_)));
+        self.breaker.record(unavailable, &self.log_policy);
+        result
+    }
+
+    /// Starts the device authorization grant (RFC 8628) for a device without a browser, e.g. a
+    /// CLI. Show the user the [`DeviceAuthorization`]'s code and verification URI, then wait for
+    /// the tokens with [`poll_device_token`](Self::poll_device_token).
+    ///
+    /// Requests the `openid` scope besides `scopes`. Fails with [`OpenIdError::Config`] when the
+    /// provider advertises no `device_authorization_endpoint`.
+    pub async fn start_device_authorization(
+        &self,
+        scopes: &[Scope],
+    ) -> Result<DeviceAuthorization> {
+        let Some(endpoint) = self
+            .provider_metadata()
+            .additional_metadata()
+            .device_authorization_endpoint
+            .clone()
+        else {
+            return Err(OpenIdError::Config(
+                "the provider advertises no device authorization endpoint".to_string(),
+            ));
+        };
+        let mut scope = vec!["openid"];
+        scope.extend(
+            scopes
+                .iter()
+                .map(|scope| scope.as_str())
+                .filter(|scope| *scope != "openid"),
+        );
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("scope", &scope.join(" "));
+        let response = self.post_form(endpoint, form).await?;
+        let status = response.status_code.as_u16();
+        if !response.status_code.is_success() {
+            return Err(OpenIdError::TokenExchange {
+                provider_error: provider_error(&response.body),
+                status: Some(status),
+                source: format!(
+                    "the device authorization endpoint answered with HTTP status {}",
+                    status
+                )
+                .into(),
+            });
+        }
+        serde_json::from_slice::<DeviceAuthorizationResponse>(&response.body)
+            .map(DeviceAuthorization::from)
+            .map_err(|err| OpenIdError::TokenExchange {
+                provider_error: None,
+                status: Some(status),
+                source: Box::new(err),
+            })
+    }
+
+    /// Polls the token endpoint for the tokens of a [device
+    /// authorization](Self::start_device_authorization) every `interval`, until the user
+    /// approved it. Waits 5 more seconds between polls each time the provider answers
+    /// `slow_down`.
+    ///
+    /// Fails with the provider's error when the user denied the request or the device code
+    /// expired, and when the provider cannot be reached: poll again to keep waiting.
+    pub async fn poll_device_token(
+        &self,
+        device_code: &DeviceCode,
+        interval: Duration,
+    ) -> Result<OpenIDTokens> {
+        let token_url = self
+            .provider_metadata()
+            .token_endpoint()
+            .map(|token_url| token_url.url().clone())
+            .ok_or_else(|| {
+                OpenIdError::Config("the provider advertises no token endpoint".to_string())
+            })?;
+        let mut interval = interval;
+        loop {
+            let mut form = url::form_urlencoded::Serializer::new(String::new());
+            form.append_pair("grant_type", DEVICE_CODE_GRANT_TYPE)
+                .append_pair("device_code", device_code.secret());
+            let response = self.post_form(token_url.clone(), form).await?;
+            let status = response.status_code.as_u16();
+            if response.status_code.is_success() {
+                let token_response = serde_json::from_slice::<ProviderTokenResponse>(
+                    &response.body,
+                )
+                .map_err(|err| OpenIdError::TokenExchange {
+                    provider_error: None,
+                    status: Some(status),
+                    source: Box::new(err),
+                })?;
+                return OpenIDTokens::try_from(&token_response);
+            }
+            let provider_error = provider_error(&response.body);
+            match provider_error.as_ref().map(|err| err.error.as_str()) {
+                Some("authorization_pending") => {}
+                Some("slow_down") => interval += SLOW_DOWN_INCREMENT,
+                _ => {
+                    return Err(OpenIdError::TokenExchange {
+                        provider_error,
+                        status: Some(status),
+                        source: format!("the token endpoint answered with HTTP status {}", status)
+                            .into(),
+                    })
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Posts `form` to an endpoint of the provider, authenticated like the token requests.
+    pub(crate) async fn post_form(
+        &self,
+        url: Url,
+        mut form: url::form_urlencoded::Serializer<'_, String>,
+    ) -> Result<HttpResponse> {
+        let mut headers = openidconnect::http::HeaderMap::new();
+        headers.insert(
+            openidconnect::http::header::CONTENT_TYPE,
+            openidconnect::http::HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        headers.insert(
+            openidconnect::http::header::ACCEPT,
+            openidconnect::http::HeaderValue::from_static("application/json"),
+        );
+        match &self.client_secret {
+            // Basic authentication as RFC 6749 section 2.3.1 encodes it, like oauth2 does.
+            Some(secret) => {
+                let encode = |value: &str| {
+                    url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>()
+                };
+                let credentials = format!(
+                    "{}:{}",
+                    encode(self.client_id()),
+                    encode(secret.expose_secret())
+                );
+                let authorization = format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode(credentials)
+                );
+                headers.insert(
+                    openidconnect::http::header::AUTHORIZATION,
+                    openidconnect::http::HeaderValue::from_str(&authorization)
+                        .map_err(|err| OpenIdError::Header(Box::new(err)))?,
+                );
+            }
+            None => {
+                form.append_pair("client_id", self.client_id());
+            }
+        }
+        let request = HttpRequest {
+            url,
+            method: openidconnect::http::Method::POST,
+            headers,
+            body: form.finish().into_bytes(),
+        };
+        self.http
+            .execute(request)
+            .await
+            .map_err(|err| OpenIdError::TokenExchange {
+                provider_error: None,
+                status: None,
+                source: Box::new(err),
+            })
+    }
+
+    /// Fetches the user's claims from the userinfo endpoint, those of `AC` included.
+    pub async fn user_info<AC: AdditionalClaims>(
         &self,
         access_token: AccessToken,
-    ) -> Result<UserInfoClaims<EmptyAdditionalClaims, CoreGenderClaim>> {
-        Ok(self
-            .client
-            .user_info(access_token, None)?
-            .request_async(async_http_client)
-            .await?)
+    ) -> Result<UserInfoClaims<AC, CoreGenderClaim>> {
+        if self.breaker.is_open() {
+            return Err(OpenIdError::UserInfo("the circuit breaker is open".into()));
+        }
+        let started = Instant::now();
+        let user_info = self.request_user_info(access_token).await;
+        self.events.on_userinfo_call(
+            &self.event_context(None),
+            started.elapsed(),
+            user_info.is_ok(),
+        );
+        let unavailable = user_info
+            .as_ref()
+            .is_err_and(|err| matches!(self.error_action(err), ErrorAction::RetryLater(_)));
+        self.breaker.record(unavailable, &self.log_policy);
+        user_info
     }
 
-    pub(crate) async fn verify_id_token<'a>(
+    /// Asks the userinfo endpoint for the claims of `access_token`, answered as JSON or as a JWT
+    /// signed by the provider, whose issuer is checked like that of ID tokens. A JWT signed with
+    /// a key the client does not know yet refetches the JWKS and is asked for again.
+    async fn request_user_info<AC: AdditionalClaims>(
         &self,
-        id_token: &'a IdToken,
-        nonce: String,
-    ) -> Result<&'a IdTokenClaims<EmptyAdditionalClaims, CoreGenderClaim>, ClaimsVerificationError>
-    {
-        id_token.claims(&self.client.id_token_verifier(), &Nonce::new(nonce))
-    }
-
-    pub(crate) fn get_logout_uri(&self, id_token: &IdToken) -> Url {
-        let mut logout_request = LogoutRequest::from(
-            self.provider_metadata
-                .additional_metadata()
-                .end_session_endpoint
-                .clone()
-                .unwrap(),
+        access_token: AccessToken,
+    ) -> Result<UserInfoClaims<AC, CoreGenderClaim>> {
+        let mut jwt = None;
+        let mut user_info = self
+            .send_user_info_request(access_token.clone(), &mut jwt)
+            .await?;
+        if let (Err(UserInfoError::ClaimsVerification(_)), Some(signed)) = (&user_info, &jwt) {
+            if self.refetch_unknown_key(signed).await? {
+                jwt = None;
+                user_info = self.send_user_info_request(access_token, &mut jwt).await?;
+            }
+        }
+        let user_info = user_info?;
+        if let Some(jwt) = jwt {
+            self.check_issuer(&jwt_payload(&jwt)?)?;
+        }
+        Ok(user_info)
+    }
+
+    /// Sends a userinfo request, keeping the JWT of a signed response in `jwt`.
+    async fn send_user_info_request<AC: AdditionalClaims>(
+        &self,
+        access_token: AccessToken,
+        jwt: &mut Option<String>,
+    ) -> Result<
+        std::result::Result<
+            UserInfoClaims<AC, CoreGenderClaim>,
+            UserInfoError<AsyncHttpClientError>,
+        >,
+    > {
+        let client = self.client();
+        let mut request = client
+            .user_info(access_token, None)
+            .map_err(|err| OpenIdError::Config(err.to_string()))?
+            // Checked by `check_issuer`, which knows the configured issuer validation.
+            .require_issuer_match(false);
+        if self.require_signed_userinfo {
+            request = request
+                .require_signed_response(true)
+                .set_response_type(UserInfoResponseType::Jwt);
+        }
+        Ok(request
+            .request_async(|request| async {
+                let response = self.http.execute(request).await?;
+                let signed = response
+                    .headers
+                    .get(openidconnect::http::header::CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .is_some_and(|content_type| content_type.starts_with("application/jwt"));
+                if signed {
+                    *jwt = String::from_utf8(response.body.clone()).ok();
+                }
+                Ok(response)
+            })
+            .await)
+    }
+
+    /// The user's claims for `access_token`, from the userinfo endpoint, the token itself or the
+    /// introspection endpoint depending on the [`ValidationMode`]. Userinfo and introspection
+    /// responses are cached, see [`OpenIdBuilder::userinfo_cache`], at most until `expires_at`
+    /// or the expiry the introspection returned.
+    pub async fn user_claims(
+        &self,
+        access_token: AccessToken,
+        expires_at: Option<SystemTime>,
+    ) -> Result<UserInfoClaims<OtherClaims, CoreGenderClaim>> {
+        let validated = |source: ClaimsSource, claims: Result<_>| {
+            tracing::Span::current().record("validation", source.as_str());
+            self.events
+                .on_token_validation(&self.event_context(None), source, claims.is_ok());
+            claims
+        };
+        if self.validation_mode == ValidationMode::Local {
+            let claims = self.verify_access_token(&access_token).await;
+            return validated(ClaimsSource::AccessToken, claims);
+        }
+        let introspection = self.validation_mode == ValidationMode::Introspection;
+        if let Some((source, claims)) = self.cached_user_claims(&access_token) {
+            return validated(source, claims);
+        }
+        let _token = match &self.userinfo_limiter {
+            Some(limiter) => {
+                let token = limiter.lock_token(&access_token).await;
+                // The request validating the token meanwhile may have left its outcome.
+                if let Some((source, claims)) = self.cached_user_claims(&access_token) {
+                    return validated(source, claims);
+                }
+                Some(token)
+            }
+            None => None,
+        };
+        let _call = match &self.userinfo_limiter {
+            Some(limiter) => Some(limiter.acquire_call().await),
+            None => None,
+        };
+        let (source, claims, expires_at) = match introspection {
+            true => match self.introspect(access_token.secret()).await {
+                Ok(result) => {
+                    let expires_at = match (expires_at, result.expires_at()) {
+                        (Some(session), Some(token)) => Some(session.min(token)),
+                        (session, token) => session.or(token),
+                    };
+                    (
+                        ClaimsSource::Introspection,
+                        result.into_claims(),
+                        expires_at,
+                    )
+                }
+                Err(err) => (ClaimsSource::Introspection, Err(err), expires_at),
+            },
+            false => (
+                ClaimsSource::UserInfo,
+                self.user_info(access_token.clone()).await,
+                expires_at,
+            ),
+        };
+        if let Some(cache) = &self.userinfo_cache {
+            match &claims {
+                Ok(claims) => cache.insert(&access_token, claims.clone(), expires_at),
+                Err(_) => cache.remove(&access_token),
+            }
+        }
+        if let (Some(cache), Err(err)) = (&self.negative_cache, &claims) {
+            // Outages and misconfigurations say nothing about the token.
+            if matches!(
+                self.error_action(err),
+                ErrorAction::Reauthenticate | ErrorAction::BadRequest
+            ) {
+                cache.insert(&access_token);
+            }
+        }
+        validated(source, claims)
+    }
+
+    /// The claims the [`userinfo_cache`](crate::OpenIdBuilder::userinfo_cache) kept for
+    /// `access_token`, or its rejection the
+    /// [`negative_cache`](crate::OpenIdBuilder::negative_cache) kept.
+    fn cached_user_claims(
+        &self,
+        access_token: &AccessToken,
+    ) -> Option<(
+        ClaimsSource,
+        Result<UserInfoClaims<OtherClaims, CoreGenderClaim>>,
+    )> {
+        if let Some(claims) = self
+            .userinfo_cache
+            .as_ref()
+            .and_then(|cache| cache.get(access_token))
+        {
+            return Some((ClaimsSource::Cache, Ok(claims)));
+        }
+        if self
+            .negative_cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains(access_token))
+        {
+            let rejected = ClaimsVerificationError::Other(
+                "the provider rejected the access token moments ago".to_string(),
+            );
+            return Some((ClaimsSource::NegativeCache, Err(rejected.into())));
+        }
+        None
+    }
+
+    /// Drops the cached userinfo response for `access_token`, once its session ended or failed
+    /// another check.
+    pub(crate) fn forget_user_claims(&self, access_token: &AccessToken) {
+        if let Some(cache) = &self.userinfo_cache {
+            cache.remove(access_token);
+        }
+    }
+
+    /// Refetches the JWKS if `jwt` names a key id it does not have, at most once a minute.
+    /// Whether the keys were refetched.
+    pub(crate) async fn refetch_unknown_key(&self, jwt: &str) -> Result<bool> {
+        let Some(key_id) = jws_key_id(jwt) else {
+            return Ok(false);
+        };
+        let known = self
+            .provider_metadata()
+            .jwks()
+            .keys()
+            .iter()
+            .any(|key| key.key_id() == Some(&key_id));
+        let jwks_age = self.provider.read().unwrap().documents.jwks_age(self.now());
+        if known || jwks_age.is_some_and(|age| age < MIN_JWKS_AGE) {
+            return Ok(false);
+        }
+        self.log_policy.log(
+            LogCategory::Refresh,
+            format_args!(
+                "Refetching the provider's keys for the unknown key {}",
+                key_id.as_str()
+            ),
+        );
+        self.update_provider(true).await?;
+        Ok(true)
+    }
+
+    /// Fails unless the `iss` of JWT `claims` passes the configured issuer validation.
+    pub(crate) fn check_issuer(&self, claims: &serde_json::Value) -> Result<()> {
+        let issuer = claims.get("iss").and_then(serde_json::Value::as_str);
+        let issuer_valid = match &self.issuer_validation {
+            IssuerValidation::Exact => issuer == Some(self.provider_metadata().issuer().as_str()),
+            IssuerValidation::Expected(expected) => issuer == Some(expected.as_str()),
+            IssuerValidation::OneOf(issuers) => {
+                issuer.is_some_and(|issuer| issuers.iter().any(|valid| valid == issuer))
+            }
+            IssuerValidation::Skip => true,
+        };
+        if !issuer_valid {
+            return Err(ClaimsVerificationError::InvalidIssuer(format!(
+                "unexpected issuer `{}`",
+                issuer.unwrap_or_default()
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The claims of a JWT access token signed with one of the provider's keys, issued by the
+    /// provider for this client and not expired. Unknown keys refetch the JWKS first.
+    async fn verify_access_token(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<UserInfoClaims<OtherClaims, CoreGenderClaim>> {
+        let jwt = access_token.secret();
+        self.refetch_unknown_key(jwt).await?;
+        let claims = self.verify_jws(jwt)?;
+
+        let now = self.now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let expires_at = claims.get("exp").and_then(serde_json::Value::as_u64);
+        if expires_at.is_none_or(|expires_at| expires_at <= now.as_secs()) {
+            return Err(ClaimsVerificationError::Expired(
+                "the access token expired or has no `exp`".to_string(),
+            )
+            .into());
+        }
+        self.check_issuer(&claims)?;
+        // Providers such as Keycloak issue access tokens for other audiences, naming the client
+        // in `azp`.
+        let client_id = self.client_id();
+        let audience = match claims.get("aud") {
+            Some(serde_json::Value::String(audience)) => audience == client_id,
+            Some(serde_json::Value::Array(audiences)) => audiences
+                .iter()
+                .any(|audience| audience.as_str() == Some(client_id)),
+            _ => false,
+        };
+        if !audience && claims.get("azp").and_then(serde_json::Value::as_str) != Some(client_id) {
+            return Err(ClaimsVerificationError::InvalidAudience(format!(
+                "the access token was not issued for `{}`",
+                client_id
+            ))
+            .into());
+        }
+        let claims = serde_json::to_vec(&claims).map_err(|err| {
+            ClaimsVerificationError::Other(format!("invalid access token: {}", err))
+        })?;
+        Ok(
+            UserInfoClaims::from_json::<serde_json::Error>(&claims, None).map_err(|err| {
+                ClaimsVerificationError::Other(format!("invalid access token claims: {}", err))
+            })?,
         )
-        .set_id_token_hint(id_token);
-        match &self.post_logout_redirect_url {
+    }
+
+    /// Fails with [`OpenIdError::MissingClaims`] when the tokens of a login lack `essential`
+    /// claims. The userinfo endpoint is only asked for essential userinfo claims.
+    pub(crate) async fn check_essential_claims(
+        &self,
+        essential: &EssentialClaims,
+        tokens: &OpenIDTokens,
+    ) -> Result<()> {
+        let id_token = tokens.id_token.to_string();
+        let mut missing = essential.missing_id_token(&jwt_payload(&id_token)?);
+        if essential.has_userinfo() {
+            let user_info: UserInfoClaims<OtherClaims, CoreGenderClaim> =
+                self.request_user_info(tokens.access_token.clone()).await?;
+            let user_info = serde_json::to_value(&user_info)
+                .map_err(|err| OpenIdError::UserInfo(Box::new(err)))?;
+            missing.extend(essential.missing_userinfo(&user_info));
+        }
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(OpenIdError::MissingClaims(missing)),
+        }
+    }
+
+    /// Whether the provider keeps failing and userinfo requests are skipped for now, see
+    /// [`OpenIdBuilder::circuit_breaker`](crate::OpenIdBuilder::circuit_breaker). Meant for
+    /// metrics and readiness probes.
+    pub fn is_circuit_open(&self) -> bool {
+        self.breaker.is_open()
+    }
+
+    /// Whether the provider can be reached well enough to authenticate users, from the cached
+    /// documents and the circuit breaker. Nothing is sent to the provider, see
+    /// [`probe`](Self::probe) for that.
+    pub fn health(&self) -> HealthReport {
+        let provider = self.provider.read().unwrap();
+        let has_keys = !provider.metadata.jwks().keys().is_empty();
+        let now = self.now();
+        self.health.report(
+            provider.documents.discovery_age(now),
+            provider.documents.jwks_age(now),
+            has_keys,
+            self.breaker.state(),
+        )
+    }
+
+    /// Refetches the discovery document and the JWKS, whatever their `max-age`, and reports the
+    /// [`health`](Self::health) afterwards.
+    pub async fn probe(&self) -> HealthReport {
+        // The outcome ends up in the report.
+        let _ = self.update_provider(true).await;
+        self.health()
+    }
+
+    /// Verifies the ID token against the provider keys, the configured issuer and
+    /// [ID token validation](crate::OpenIdBuilder::id_token_validation) and the `nonce` sent in
+    /// the authorization request. Fails with [`OpenIdError::IdToken`] naming the failed check.
+    ///
+    /// A token signed with a key the client does not know yet refetches the JWKS and is verified
+    /// again, like access tokens are.
+    pub async fn verify_id_token<'a, AC: AdditionalClaims>(
+        &self,
+        id_token: &'a IdToken<AC>,
+        nonce: String,
+    ) -> Result<&'a IdTokenClaims<AC, CoreGenderClaim>> {
+        let nonce = Nonce::new(nonce);
+        match self.verified_claims(id_token, &nonce, false) {
+            Err(OpenIdError::IdToken(IdTokenError::Signature(_)))
+                if self.refetch_unknown_key(&id_token.to_string()).await? =>
+            {
+                self.verified_claims(id_token, &nonce, false)
+            }
+            verified => verified,
+        }
+    }
+
+    /// Checks the `at_hash` of an ID token against the `access_token` issued with it, as the
+    /// [`TokenHashValidation`] says, and its `c_hash` against the authorization `code` it was
+    /// exchanged for. The `claims` are those [`verify_id_token`](Self::verify_id_token) returned.
+    pub fn verify_token_hashes<AC: AdditionalClaims>(
+        &self,
+        id_token: &IdToken<AC>,
+        claims: &IdTokenClaims<AC, CoreGenderClaim>,
+        access_token: &AccessToken,
+        code: Option<&AuthorizationCode>,
+    ) -> Result<()> {
+        let invalid =
+            |reason: String| -> OpenIdError { ClaimsVerificationError::Other(reason).into() };
+        let alg = || {
+            id_token
+                .signing_alg()
+                .map_err(|err| invalid(format!("unsupported ID token algorithm: {}", err)))
+        };
+        match claims.access_token_hash() {
+            Some(expected) => {
+                let actual = AccessTokenHash::from_token(access_token, &alg()?)
+                    .map_err(|err| invalid(format!("could not hash the access token: {}", err)))?;
+                if actual != *expected {
+                    return Err(invalid(
+                        "the at_hash does not match the access token".to_string(),
+                    ));
+                }
+            }
+            None if self.token_hash_validation == TokenHashValidation::Strict => {
+                return Err(invalid("the ID token has no at_hash".to_string()));
+            }
             None => {}
-            Some(uri) => {
-                logout_request = logout_request.set_post_logout_redirect_uri(
-                    PostLogoutRedirectUrl::new(uri.to_string()).unwrap(),
-                );
+        }
+        if let (Some(expected), Some(code)) = (claims.code_hash(), code) {
+            let actual = AuthorizationCodeHash::from_code(code, &alg()?)
+                .map_err(|err| invalid(format!("could not hash the code: {}", err)))?;
+            if actual != *expected {
+                return Err(invalid(
+                    "the c_hash does not match the authorization code".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The subject of a session's ID token and when the user logged in, its `auth_time` or else
+    /// its `iat`. The token may have expired since the login, only its signature, audience and
+    /// issuer are checked.
+    pub(crate) fn authenticated_at(&self, id_token: &IdToken) -> Result<(String, SystemTime)> {
+        let claims = self.verified_claims(id_token, |_: Option<&Nonce>| Ok(()), true)?;
+        let authenticated_at = claims.auth_time().unwrap_or_else(|| claims.issue_time());
+        Ok((claims.subject().to_string(), authenticated_at.into()))
+    }
+
+    /// Every claim of a session's ID token, which may have expired since the login like with
+    /// [`authenticated_at`](Self::authenticated_at).
+    pub(crate) fn id_token_claims(
+        &self,
+        id_token: &str,
+    ) -> Result<IdTokenClaims<OtherClaims, CoreGenderClaim>> {
+        let id_token: IdToken<OtherClaims> = id_token
+            .parse()
+            .map_err(|err| ClaimsVerificationError::Other(format!("invalid ID token: {}", err)))?;
+        let claims = self.verified_claims(&id_token, |_: Option<&Nonce>| Ok(()), true)?;
+        Ok(claims.clone())
+    }
+
+    /// The payload of a compact JWS signed with one of the provider's keys, e.g. a Keycloak
+    /// admin event.
+    pub(crate) fn verify_jws(&self, jws: &str) -> Result<serde_json::Value> {
+        let invalid =
+            |reason: String| ClaimsVerificationError::Other(format!("invalid JWS: {}", reason));
+        let decode = |part: &str| {
+            URL_SAFE_NO_PAD
+                .decode(part)
+                .map_err(|err| invalid(err.to_string()))
+        };
+        let mut parts = jws.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("not a compact JWS".to_string()).into());
+        };
+        let parsed: JwsHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|err| invalid(err.to_string()))?;
+        let metadata = self.provider_metadata();
+        let supported = metadata.id_token_signing_alg_values_supported();
+        if !supported.contains(&parsed.alg) {
+            return Err(invalid(format!("unsupported algorithm {:?}", parsed.alg)).into());
+        }
+        let key = metadata
+            .jwks()
+            .keys()
+            .iter()
+            .find(|key| parsed.kid.is_none() || key.key_id() == parsed.kid.as_ref())
+            .ok_or_else(|| invalid("signed with an unknown key".to_string()))?;
+        key.verify_signature(
+            &parsed.alg,
+            format!("{}.{}", header, payload).as_bytes(),
+            &decode(signature)?,
+        )
+        .map_err(|err| invalid(err.to_string()))?;
+        Ok(serde_json::from_slice(&decode(payload)?).map_err(|err| invalid(err.to_string()))?)
+    }
+
+    fn verified_claims<'a, AC: AdditionalClaims>(
+        &self,
+        id_token: &'a IdToken<AC>,
+        nonce_verifier: impl NonceVerifier,
+        allow_expired: bool,
+    ) -> Result<&'a IdTokenClaims<AC, CoreGenderClaim>> {
+        let client = self.client();
+        let verifier = match self.issuer_validation {
+            IssuerValidation::Exact => client.id_token_verifier(),
+            _ => client.id_token_verifier().require_issuer_match(false),
+        };
+        let now = (!allow_expired).then(|| self.now());
+        let validation = &self.id_token_validation;
+        let verifier = validation.verifier(verifier, now);
+        let jwt = id_token.to_string();
+        let payload = || jwt_payload(&jwt).unwrap_or_default();
+        let claims = id_token.claims(&verifier, nonce_verifier).map_err(|err| {
+            let alg = id_token.signing_alg().ok();
+            validation.error(err, &payload(), alg.as_ref(), self.client_id(), now)
+        })?;
+        let issuer_valid = match &self.issuer_validation {
+            IssuerValidation::Expected(expected) => expected == claims.issuer().as_str(),
+            IssuerValidation::OneOf(issuers) => issuers
+                .iter()
+                .any(|issuer| issuer == claims.issuer().as_str()),
+            IssuerValidation::Exact | IssuerValidation::Skip => true,
+        };
+        if !issuer_valid {
+            return Err(IdTokenError::Issuer(claims.issuer().to_string()).into());
+        }
+        validation.check(&payload(), self.client_id(), now)?;
+        Ok(claims)
+    }
+
+    /// The provider's end session url sending the user on to `redirect` afterwards, `None` if
+    /// the provider has no logout endpoint.
+    ///
+    /// With a [post logout redirect url](crate::OpenIdBuilder::post_logout_redirect_url) the
+    /// provider returns there with `redirect` as the `state`, `/logout/callback` then redirects
+    /// to it. Without one, `redirect` resolved against the redirect url is the post logout
+    /// redirect uri, the provider must allow it.
+    pub fn get_logout_uri_with_redirect(
+        &self,
+        id_token: &IdToken,
+        redirect: Option<&str>,
+    ) -> Option<Url> {
+        let end_session_endpoint = self
+            .provider_metadata()
+            .additional_metadata()
+            .end_session_endpoint
+            .clone()?;
+        let mut logout_request =
+            LogoutRequest::from(end_session_endpoint).set_id_token_hint(id_token);
+        match (&self.post_logout_redirect_url, redirect) {
+            (Some(uri), redirect) => {
+                logout_request = logout_request.set_post_logout_redirect_uri(uri.clone());
+                if let Some(redirect) = redirect {
+                    logout_request = logout_request.set_state(CsrfToken::new(redirect.to_string()));
+                }
+            }
+            (None, Some(redirect)) => {
+                if let Ok(uri) = self.redirect_url.join(redirect) {
+                    logout_request = logout_request
+                        .set_post_logout_redirect_uri(PostLogoutRedirectUrl::from_url(uri));
+                }
+            }
+            (None, None) => {}
+        }
+        Some(logout_request.http_get_url())
+    }
+
+    pub(crate) fn post_logout_redirect_url(&self) -> Option<&str> {
+        self.post_logout_redirect_url
+            .as_ref()
+            .map(|url| url.as_str())
+    }
+
+    pub(crate) fn callback_errors(&self) -> &CallbackErrorHandling {
+        &self.callback_errors
+    }
+
+    pub(crate) fn login_redirect(
+        &self,
+    ) -> Option<fn(&LoginRedirect<'_>) -> actix_web::HttpResponse> {
+        self.login_redirect
+    }
+
+    pub(crate) fn pages(&self) -> &dyn PageRenderer {
+        self.pages.as_ref()
+    }
+
+    pub(crate) fn random_token(&self) -> String {
+        self.random.random_token()
+    }
+
+    pub(crate) fn log_policy(&self) -> &LogPolicy {
+        &self.log_policy
+    }
+
+    pub(crate) fn error_action(&self, err: &OpenIdError) -> ErrorAction {
+        (self.error_action)(err)
+    }
+
+    pub(crate) fn redirect_url(&self) -> &Url {
+        &self.redirect_url
+    }
+
+    pub(crate) fn id_token_validation(&self) -> &IdTokenValidation {
+        &self.id_token_validation
+    }
+
+    /// The redirect url at the scheme and host `req` reached the app at through a
+    /// [trusted proxy](crate::OpenIdBuilder::trusted_proxies), `None` to use the configured one.
+    pub(crate) fn forwarded_redirect_url(&self, req: &actix_web::HttpRequest) -> Option<Url> {
+        self.trusted_proxies
+            .external_url(req, &self.redirect_url)
+            .filter(|url| *url != self.redirect_url)
+    }
+
+    /// Whether `req` reached the app over https through a [trusted
+    /// proxy](crate::OpenIdBuilder::trusted_proxies), its cookies are `Secure` even with
+    /// [`CookieConfig::secure`](crate::CookieConfig::secure) off.
+    pub(crate) fn forwarded_https(&self, req: &actix_web::HttpRequest) -> bool {
+        self.trusted_proxies.forwarded_https(req)
+    }
+
+    pub(crate) fn scopes(&self) -> &[Scope] {
+        &self.scopes
+    }
+
+    pub(crate) fn provider_metadata(&self) -> Arc<ExtendedProviderMetadata> {
+        self.provider.read().unwrap().metadata.clone()
+    }
+
+    fn client(&self) -> Arc<ProviderClientType> {
+        self.provider.read().unwrap().client.clone()
+    }
+
+    /// Refetches the discovery document and the JWKS once their `max-age` elapsed.
+    ///
+    /// Requests are conditional on the `ETag` and `Last-Modified` the provider sent, a
+    /// `304 Not Modified` keeps the cached documents.
+    pub async fn refresh_provider(&self) -> Result<()> {
+        self.update_provider(false).await
+    }
+
+    async fn update_provider(&self, force: bool) -> Result<()> {
+        let mut documents = self.provider.read().unwrap().documents.clone();
+        let refreshed = documents
+            .refresh(
+                &self.http,
+                self.clock.as_ref(),
+                &self.issuer_url,
+                &self.issuer_validation,
+                self.max_provider_staleness,
+                force,
+            )
+            .await;
+        self.health.record(&refreshed);
+        let refreshed = refreshed?;
+        if refreshed.keys_changed {
+            self.log_policy.log(
+                LogCategory::Refresh,
+                format_args!("The provider's signing keys changed"),
+            );
+        }
+        let provider = if refreshed.metadata_changed || refreshed.keys_changed {
+            let redirect_url = RedirectUrl::from_url(self.redirect_url.clone());
+            Provider::new(
+                documents,
+                ProviderClient {
+                    client_id: &self.client_id,
+                    client_secret: self.client_secret.as_ref(),
+                    redirect_url: &redirect_url,
+                    extra_token_params: &self.extra_token_params,
+                },
+            )
+        } else {
+            let current = self.provider.read().unwrap();
+            Provider {
+                client: current.client.clone(),
+                metadata: current.metadata.clone(),
+                documents,
+                discovered: current.discovered,
             }
         };
-        logout_request.http_get_url()
+        *self.provider.write().unwrap() = provider;
+        Ok(())
+    }
+
+    pub(crate) fn client_token_scopes(&self) -> &[Scope] {
+        &self.client_token_scopes
+    }
+
+    pub(crate) fn exchange_cache(&self) -> &ExchangeCache {
+        &self.exchange_cache
+    }
+
+    pub(crate) fn client_tokens(&self) -> &RwLock<HashMap<Vec<String>, Arc<TokenCache>>> {
+        &self.client_tokens
+    }
+
+    /// Whether the provider's documents were fetched, see [`DiscoveryPolicy::RetryForever`].
+    pub fn is_discovered(&self) -> bool {
+        self.provider.read().unwrap().discovered
+    }
+
+    /// Completes the discovery postponed by [`DiscoveryPolicy::RetryForever`], asking the
+    /// provider at most once per backoff. Fails with the discovery error until it succeeds.
+    pub(crate) async fn ensure_discovered(&self) -> Result<()> {
+        if self.is_discovered() {
+            return Ok(());
+        }
+        let backoff = self.discovery_backoff();
+        let now = self.now();
+        {
+            let mut next_discovery = self.next_discovery.lock().unwrap();
+            if next_discovery.is_some_and(|next_discovery| now < next_discovery) {
+                return Err(OpenIdError::Discovery(
+                    "the provider was not discovered yet".into(),
+                ));
+            }
+            *next_discovery = Some(now + backoff);
+        }
+        self.update_provider(true).await?;
+        self.log_policy.log(
+            LogCategory::Refresh,
+            format_args!("Discovered the provider"),
+        );
+        for issue in self.validate() {
+            self.log_policy.log(
+                LogCategory::ConfigWarning,
+                format_args!("OpenID configuration {}", issue),
+            );
+        }
+        Ok(())
+    }
+
+    /// How long requests are told to wait for the discovery, see [`DiscoveryPolicy`].
+    pub(crate) fn discovery_backoff(&self) -> Duration {
+        match self.discovery_policy {
+            DiscoveryPolicy::RetryForever { backoff } => backoff,
+            DiscoveryPolicy::FailFast => Duration::ZERO,
+        }
+    }
+
+    /// Whether logouts revoke the access token too, see
+    /// [`OpenIdBuilder::revoke_access_token_on_logout`].
+    pub(crate) fn revokes_access_token(&self) -> bool {
+        self.revoke_access_token
+    }
+
+    pub(crate) fn has_introspection_endpoint(&self) -> bool {
+        self.provider_metadata()
+            .additional_metadata()
+            .introspection_endpoint
+            .is_some()
+    }
+
+    pub(crate) fn has_end_session_endpoint(&self) -> bool {
+        self.provider_metadata()
+            .additional_metadata()
+            .end_session_endpoint
+            .is_some()
+    }
+
+    /// Whether the provider advertises front- and back-channel logout.
+    pub(crate) fn provider_logout_supported(&self) -> (bool, bool) {
+        let metadata = self.provider_metadata();
+        let additional = metadata.additional_metadata();
+        (
+            additional.frontchannel_logout_supported,
+            additional.backchannel_logout_supported,
+        )
     }
 }
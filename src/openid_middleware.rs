@@ -1,356 +1,3450 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::future::{ready, Ready};
 use std::rc::Rc;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Once};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use actix_web::body::BoxBody;
-use actix_web::cookie::{Cookie, SameSite};
+use actix_web::cookie::time::Duration as CookieDuration;
+use actix_web::cookie::Cookie;
 use actix_web::dev::forward_ready;
-use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
-use actix_web::error::ErrorUnauthorized;
-use actix_web::http::header::LOCATION;
-use actix_web::http::StatusCode;
-use actix_web::{error, get, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
+use actix_web::dev::{Extensions, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::error::{ErrorInternalServerError, ErrorUnauthorized};
+use actix_web::http::header::{
+    HeaderName, ACCEPT, CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION, RETRY_AFTER, SET_COOKIE,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{error, web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse};
 use futures_util::future::LocalBoxFuture;
 use openidconnect::core::CoreGenderClaim;
 use openidconnect::http::HeaderValue;
-use openidconnect::{AccessToken, AuthorizationCode, EmptyAdditionalClaims, UserInfoClaims};
-use serde::Deserialize;
+use openidconnect::{
+    AccessToken, AdditionalClaims, AuthorizationCode, ClaimsVerificationError,
+    EmptyAdditionalClaims, IdTokenClaims, PkceCodeVerifier, RefreshToken, StandardClaims,
+    SubjectIdentifier, UserInfoClaims,
+};
+use serde::de::DeserializeOwned;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing::Instrument;
+use url::{form_urlencoded, Url};
 
-use crate::openid::{IdToken, OpenID};
+use crate::api_key::ApiKeyValidator;
+use crate::authorization::Policy;
+use crate::basic_auth::{BasicAuth, BasicAuthConfig};
+use crate::claims_request::{ClaimsRequest, EssentialClaims};
+use crate::cookie_config::UserInfoCookie;
+use crate::credentials::{self, ApiKeyHeader, Authenticators, BasicHeader, CredentialChain};
+use crate::error::{ErrorAction, OpenIdError, ProviderError};
+use crate::events::CallbackFailure;
+#[cfg(feature = "first-party-session")]
+use crate::first_party::{self, SessionFormat};
+use crate::forward_auth::ForwardAuth;
+use crate::identity_headers::IdentityHeaders;
+use crate::logging::{LogCategory, LogPolicy};
+use crate::login_hook::LoginDecision;
+use crate::login_params::LoginParams;
+use crate::messages::{EnglishMessages, MessageKey, Messages};
+use crate::openid::{
+    jwt_payload, login_attempt, split_login_state, IdToken, OpenID, OtherClaims, RefreshedTokens,
+    TokenTypeHint,
+};
+use crate::pages::{
+    default_content_security_policy, CallbackErrorHandling, LoginRedirect, PageContext, PageKind,
+};
+use crate::payload::{PayloadCodec, PayloadError};
+use crate::pre_auth::PreAuthDecision;
+use crate::realm::Realm;
+use crate::session_limits::SessionTimes;
+use crate::session_store::{from_unix_secs, unix_secs, SessionStore, StoredSession};
+use crate::session_token::{SessionToken, REFRESH_WINDOW};
+use crate::should_auth::ShouldAuth;
+use crate::tasks::TaskSet;
 
-enum AuthCookies {
+#[derive(Clone, Copy)]
+pub(crate) enum AuthCookies {
     AccessToken,
     IdToken,
     RefreshToken,
     UserInfo,
     Nonce,
+    /// Unix time at which the access token expires.
+    ExpiresAt,
+    /// Set once a silent login found no session at the provider, see
+    /// [`AuthenticateMiddlewareFactory::eager_sso`].
+    SsoChecked,
+    /// The essential claims the login asked for, checked by the callback.
+    EssentialClaims,
+    /// The PKCE verifier of the login, sent with the authorization code.
+    PkceVerifier,
+    /// The CSRF token of the login, matched against the `state` the provider sends back.
+    State,
+    /// The id of the session in the client's [`SessionStore`], instead of the token cookies.
+    SessionId,
+    /// The data the [`LoginHook`](crate::LoginHook) stored with a session kept in the cookies.
+    SessionData,
+    /// When the user of a session kept in the cookies logged in and was last seen, for the
+    /// session limits.
+    SessionTimes,
 }
 
-impl Display for AuthCookies {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+impl AuthCookies {
+    pub(crate) const ALL: [AuthCookies; 13] = [
+        AuthCookies::AccessToken,
+        AuthCookies::IdToken,
+        AuthCookies::RefreshToken,
+        AuthCookies::UserInfo,
+        AuthCookies::Nonce,
+        AuthCookies::ExpiresAt,
+        AuthCookies::SsoChecked,
+        AuthCookies::EssentialClaims,
+        AuthCookies::PkceVerifier,
+        AuthCookies::State,
+        AuthCookies::SessionId,
+        AuthCookies::SessionData,
+        AuthCookies::SessionTimes,
+    ];
+
+    /// The cookie's name in the default realm, other realms prefix it.
+    pub(crate) const fn name(&self) -> &'static str {
         match self {
-            AuthCookies::AccessToken => {
-                write!(f, "access_token")
+            AuthCookies::AccessToken => "access_token",
+            AuthCookies::IdToken => "id_token",
+            AuthCookies::RefreshToken => "refresh_token",
+            AuthCookies::UserInfo => "user_info",
+            AuthCookies::Nonce => "nonce",
+            AuthCookies::ExpiresAt => "access_token_expires_at",
+            AuthCookies::SsoChecked => "sso_checked",
+            AuthCookies::EssentialClaims => "essential_claims",
+            AuthCookies::PkceVerifier => "pkce_verifier",
+            AuthCookies::State => "oauth_state",
+            AuthCookies::SessionId => "session_id",
+            AuthCookies::SessionData => "session_data",
+            AuthCookies::SessionTimes => "session_times",
+        }
+    }
+}
+
+/// A logged in user and their claims, those of `AC` included, e.g. the roles of
+/// `AuthenticatedUser<MyClaims>`, and the tokens they were authenticated with.
+///
+/// Serialized with its tokens, e.g. for a background job to call services on behalf of the user:
+/// the serialized user is as secret as them. Its `Debug` output leaves them out.
+#[derive(Clone)]
+pub struct AuthenticatedUser<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    pub access: UserInfoClaims<AC, CoreGenderClaim>,
+    tokens: Option<Arc<UserTokens>>,
+    /// The claims `AC` leaves out of a user built with all of them, e.g. by
+    /// `AuthenticatedUserBuilder`, kept for the policies reading them as [`OtherClaims`].
+    other_claims: Option<Arc<OtherClaims>>,
+}
+
+/// Never the tokens.
+impl<AC: AdditionalClaims> fmt::Debug for AuthenticatedUser<AC> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthenticatedUser")
+            .field("access", &self.access)
+            .field("expires_at", &self.expires_at())
+            .finish_non_exhaustive()
+    }
+}
+
+/// How an [`AuthenticatedUser`] is serialized, claims through a `Value` for the claims flattened
+/// twice to appear once, times as Unix seconds.
+#[derive(Serialize, Deserialize)]
+struct UserRecord<T> {
+    access: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    access_token: Option<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id_token: Option<T>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id_token_claims: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session_data: Option<serde_json::Value>,
+}
+
+impl<AC: AdditionalClaims> Serialize for AuthenticatedUser<AC> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tokens = self.tokens.as_deref();
+        let id_token = self.id_token().map(|id_token| id_token.to_string());
+        UserRecord {
+            access: serde_json::to_value(&self.access).map_err(S::Error::custom)?,
+            access_token: self.access_token().map(|token| token.secret().as_str()),
+            id_token: id_token.as_deref(),
+            id_token_claims: self
+                .id_token_claims()
+                .map(serde_json::to_value)
+                .transpose()
+                .map_err(S::Error::custom)?,
+            expires_at: unix_secs(self.expires_at()),
+            session_data: tokens.and_then(|tokens| tokens.session_data.clone()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de, AC: AdditionalClaims> Deserialize<'de> for AuthenticatedUser<AC> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = UserRecord::<String>::deserialize(deserializer)?;
+        let access = serde_json::to_vec(&record.access).map_err(D::Error::custom)?;
+        let access = UserInfoClaims::from_json::<serde_json::Error>(&access, None)
+            .map_err(D::Error::custom)?;
+        let Some(access_token) = record.access_token else {
+            return Ok(AuthenticatedUser::new(access));
+        };
+        let tokens = UserTokens {
+            access_token: AccessToken::new(access_token),
+            id_token: record
+                .id_token
+                .map(|id_token| IdToken::from_str(&id_token))
+                .transpose()
+                .map_err(D::Error::custom)?,
+            id_token_claims: record
+                .id_token_claims
+                .map(serde_json::from_value)
+                .transpose()
+                .map_err(D::Error::custom)?,
+            expires_at: from_unix_secs(record.expires_at)?,
+            session_data: record.session_data,
+        };
+        Ok(AuthenticatedUser::new(access).with_tokens(tokens))
+    }
+}
+
+/// The tokens a user was authenticated with, shared by the clones of the user.
+pub(crate) struct UserTokens {
+    access_token: AccessToken,
+    id_token: Option<IdToken>,
+    id_token_claims: Option<IdTokenClaims<OtherClaims, CoreGenderClaim>>,
+    expires_at: Option<SystemTime>,
+    session_data: Option<serde_json::Value>,
+}
+
+impl UserTokens {
+    pub(crate) fn new(access_token: AccessToken, expires_at: Option<SystemTime>) -> Self {
+        UserTokens {
+            access_token,
+            id_token: None,
+            id_token_claims: None,
+            expires_at,
+            session_data: None,
+        }
+    }
+
+    /// Adds the data the [`LoginHook`](crate::LoginHook) stored with the `session`.
+    fn with_session_data(mut self, session: Option<&Arc<StoredSession>>) -> Self {
+        self.session_data = session.and_then(|session| session.data().cloned());
+        self
+    }
+
+    /// Adds the session's `id_token`, its claims only if its signature verifies.
+    fn with_id_token(mut self, client: &OpenID, id_token: Option<&str>) -> Self {
+        let Some(id_token) = id_token else {
+            return self;
+        };
+        self.id_token_claims = client
+            .id_token_claims(id_token)
+            .inspect_err(|err| {
+                client.log_policy().log(
+                    LogCategory::UnauthenticatedRequest,
+                    format_args!("Leaving out the claims of the session's ID token: {}", err),
+                );
+            })
+            .ok();
+        self.id_token = IdToken::from_str(id_token).ok();
+        self
+    }
+}
+
+impl<AC: AdditionalClaims> AuthenticatedUser<AC> {
+    /// A user with the `access` claims and no tokens, e.g. for an [`ApiKeyValidator`].
+    pub fn new(access: UserInfoClaims<AC, CoreGenderClaim>) -> Self {
+        AuthenticatedUser {
+            access,
+            tokens: None,
+            other_claims: None,
+        }
+    }
+
+    pub(crate) fn with_tokens(mut self, tokens: UserTokens) -> Self {
+        self.tokens = Some(Arc::new(tokens));
+        self
+    }
+
+    #[cfg(feature = "test-util")]
+    pub(crate) fn with_other_claims(mut self, other_claims: OtherClaims) -> Self {
+        self.other_claims = Some(Arc::new(other_claims));
+        self
+    }
+
+    /// The access token the request was authenticated with, the renewed one if the middleware
+    /// refreshed the session. `None` for users authenticated without one, e.g. by an API key.
+    ///
+    /// [`SessionToken`] also refreshes it when it is about to expire.
+    pub fn access_token(&self) -> Option<&AccessToken> {
+        self.tokens.as_ref().map(|tokens| &tokens.access_token)
+    }
+
+    /// The ID token of the session, `None` for bearer tokens and other credentials.
+    pub fn id_token(&self) -> Option<&IdToken> {
+        self.tokens.as_ref()?.id_token.as_ref()
+    }
+
+    /// The claims of the session's ID token, e.g. the `auth_time` or a `sid` the userinfo
+    /// response lacks. Their signature is checked, not their expiry: ID tokens usually expire
+    /// long before the session.
+    pub fn id_token_claims(&self) -> Option<&IdTokenClaims<OtherClaims, CoreGenderClaim>> {
+        self.tokens.as_ref()?.id_token_claims.as_ref()
+    }
+
+    /// When the [`access_token`](Self::access_token) expires, if the provider told.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.tokens.as_ref()?.expires_at
+    }
+
+    /// The user's `sub` claim.
+    pub fn subject(&self) -> &str {
+        self.access.subject().as_str()
+    }
+
+    pub fn email(&self) -> Option<&str> {
+        self.access.email().map(|email| email.as_str())
+    }
+
+    /// The user's full name, the one without a language tag if the provider sent several.
+    pub fn name(&self) -> Option<&str> {
+        let name = self.access.name()?;
+        name.get(None)
+            .or_else(|| name.iter().next().map(|(_, name)| name))
+            .map(|name| name.as_str())
+    }
+
+    pub fn preferred_username(&self) -> Option<&str> {
+        self.access
+            .preferred_username()
+            .map(|username| username.as_str())
+    }
+
+    /// The data the [`LoginHook`](crate::LoginHook) stored with the session, e.g. the user's id
+    /// in the application. `None` without a session or when the hook stored none.
+    pub fn session_data(&self) -> Option<&serde_json::Value> {
+        self.tokens.as_ref()?.session_data.as_ref()
+    }
+
+    /// The same user with their claims read as `T`, failing when they lack claims `T` requires.
+    pub fn with_claims<T: AdditionalClaims>(&self) -> Result<AuthenticatedUser<T>, OpenIdError> {
+        let invalid = |reason: String| {
+            ClaimsVerificationError::Other(format!("the claims do not fit: {}", reason))
+        };
+        // Through a `Value`, claims serialized twice by flattened maps become one.
+        let mut claims =
+            serde_json::to_value(&self.access).map_err(|err| invalid(err.to_string()))?;
+        if let (serde_json::Value::Object(claims), Some(other_claims)) =
+            (&mut claims, &self.other_claims)
+        {
+            for (name, value) in other_claims.claims() {
+                claims.entry(name).or_insert_with(|| value.clone());
+            }
+        }
+        let claims = serde_json::to_vec(&claims).map_err(|err| invalid(err.to_string()))?;
+        let access = UserInfoClaims::from_json::<serde_json::Error>(&claims, None)
+            .map_err(|err| invalid(err.to_string()))?;
+        Ok(AuthenticatedUser {
+            access,
+            tokens: self.tokens.clone(),
+            other_claims: self.other_claims.clone(),
+        })
+    }
+
+    /// The claims of the ID token and the userinfo response, which take precedence, read as
+    /// `T`, e.g. an app's own user struct. Fails naming the claim `T` requires but the user
+    /// lacks.
+    pub fn claims_as<T: DeserializeOwned>(&self) -> Result<T, OpenIdError> {
+        let invalid = |err: serde_json::Error| {
+            ClaimsVerificationError::Other(format!("the claims do not fit: {}", err))
+        };
+        let mut claims = match self.id_token_claims() {
+            Some(id_token_claims) => serde_json::to_value(id_token_claims).map_err(invalid)?,
+            None => serde_json::Value::Object(Default::default()),
+        };
+        if let (serde_json::Value::Object(claims), serde_json::Value::Object(access)) = (
+            &mut claims,
+            serde_json::to_value(&self.access).map_err(invalid)?,
+        ) {
+            claims.extend(access);
+        }
+        Ok(serde_json::from_value(claims).map_err(invalid)?)
+    }
+}
+
+/// Users found by validators, with the other claims they were built with, if any.
+impl From<AuthenticatedUser> for AuthenticatedUser<OtherClaims> {
+    fn from(user: AuthenticatedUser) -> Self {
+        let other_claims = user.other_claims.as_deref().cloned().unwrap_or_default();
+        AuthenticatedUser {
+            access: UserInfoClaims::new(user.access.standard_claims().clone(), other_claims),
+            tokens: user.tokens,
+            other_claims: user.other_claims,
+        }
+    }
+}
+
+impl AuthenticatedUser {
+    /// A user with only a subject, for clients authenticated without the provider, e.g. with an
+    /// API key.
+    pub fn synthetic(subject: impl Into<String>) -> Self {
+        AuthenticatedUser::new(UserInfoClaims::new(
+            StandardClaims::new(SubjectIdentifier::new(subject.into())),
+            EmptyAdditionalClaims {},
+        ))
+    }
+}
+
+/// The request needs a logged in user, answered with a redirect to the provider.
+///
+/// Custom error handlers can recognize it with `err.as_error::<AuthenticationRequired>()`, its
+/// response carries the redirect and the nonce cookie needed to complete the login. Both are only
+/// generated with the response, requests that never answer with it do not pay for them.
+#[derive(Clone, thiserror::Error)]
+#[error("Not authenticated")]
+pub struct AuthenticationRequired {
+    client: Arc<OpenID>,
+    path: String,
+    /// Path of the provider chooser the login starts at, instead of `client`'s provider.
+    chooser: Option<Arc<str>>,
+    /// Whether the login is a silent attempt with `prompt=none`.
+    silent: bool,
+    /// Status answered with the login url instead of redirecting to it, see
+    /// [`answering`](Self::answering).
+    answer: Option<StatusCode>,
+    /// Cookies of the request that do not decrypt, removed by the response.
+    unreadable_cookies: Vec<AuthCookies>,
+    /// Added to the authorization request, see [`LoginParams`].
+    login_params: Option<LoginParams>,
+    /// Where the provider sends the user back instead of the configured redirect url, see
+    /// [`TrustedProxies`](crate::TrustedProxies).
+    redirect_url: Option<Arc<Url>>,
+    /// Whether the request reached the app over https through a trusted proxy.
+    forwarded_https: bool,
+    #[source]
+    reason: Option<Arc<OpenIdError>>,
+}
+
+impl AuthenticationRequired {
+    pub(crate) fn new(client: &Arc<OpenID>, path: &str, reason: Option<OpenIdError>) -> Self {
+        AuthenticationRequired {
+            client: client.clone(),
+            path: path.to_string(),
+            chooser: None,
+            silent: false,
+            answer: None,
+            unreadable_cookies: Vec::new(),
+            login_params: None,
+            redirect_url: None,
+            forwarded_https: false,
+            reason: reason.map(Arc::new),
+        }
+    }
+
+    /// Tries to log the user in without showing a page, for users still signed in at the
+    /// provider. The attempt is only made once in a while, see [`SSO_CHECK_TTL`].
+    pub(crate) fn silent(client: &Arc<OpenID>, path: &str) -> Self {
+        AuthenticationRequired {
+            silent: true,
+            ..AuthenticationRequired::new(client, path, None)
+        }
+    }
+
+    /// Sends the user to the chooser at `chooser` to pick a provider, its messages come from
+    /// `client`.
+    pub(crate) fn choose_provider(client: &Arc<OpenID>, chooser: &Arc<str>, path: &str) -> Self {
+        AuthenticationRequired {
+            chooser: Some(chooser.clone()),
+            ..AuthenticationRequired::new(client, path, None)
+        }
+    }
+
+    /// Sends the user through the login again with `params`, e.g. from a handler needing
+    /// scopes the session was not granted. Returns to the request's path afterwards.
+    ///
+    /// ```ignore
+    /// #[post("/payments")]
+    /// async fn pay(req: HttpRequest, user: Authenticated<OtherClaims>) -> actix_web::Result<HttpResponse> {
+    ///     if HasScope::new("payments").check(&req, &user).is_err() {
+    ///         return Err(AuthenticationRequired::step_up(&req, LoginParams::new().scope("payments"))?.into());
+    ///     }
+    ///     // ...
+    /// }
+    /// ```
+    pub fn step_up(req: &HttpRequest, params: LoginParams) -> Result<Self, Error> {
+        let client = RegisteredClient::get(req)?;
+        Ok(
+            AuthenticationRequired::new(&client.0, request_target(req), None)
+                .with_login_params(Some(params))
+                .reached_through(req),
+        )
+    }
+
+    /// Adds `params` to the authorization request, unless the login is silent.
+    pub(crate) fn with_login_params(mut self, params: Option<LoginParams>) -> Self {
+        self.login_params = params;
+        self
+    }
+
+    /// Has the provider send the user back to the host `req` reached the app at, if it came
+    /// through a [trusted proxy](crate::TrustedProxies).
+    pub(crate) fn reached_through(mut self, req: &HttpRequest) -> Self {
+        self.redirect_url = self.client.forwarded_redirect_url(req).map(Arc::new);
+        self.forwarded_https = self.client.forwarded_https(req);
+        self
+    }
+
+    /// Returns to `path` after the login instead of the request's path.
+    pub(crate) fn returning_to(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Answers `status` with a JSON body naming the login url instead of redirecting to it, for
+    /// scripts that cannot follow a redirect to the provider and for methods the browser would
+    /// replay as a `GET` after the login.
+    pub(crate) fn answering(mut self, status: Option<StatusCode>) -> Self {
+        self.answer = status;
+        self
+    }
+
+    /// Removes `cookies` with the response, before the login cookies are set.
+    pub(crate) fn removing(mut self, cookies: Vec<AuthCookies>) -> Self {
+        self.unreadable_cookies = cookies;
+        self
+    }
+
+    /// Sends the user to `login_url`, with the client's [login
+    /// redirect](crate::OpenIdBuilder::login_redirect) if it has one, or names it in the body of
+    /// an [answer](Self::answering).
+    fn login_response(&self, login_url: &str) -> Result<HttpResponse, OpenIdError> {
+        let mut response = match (self.answer, self.client.login_redirect()) {
+            (Some(status), _) => HttpResponse::build(status).json(serde_json::json!({
+                "error": "unauthenticated",
+                "login_url": login_url,
+            })),
+            (None, Some(login_redirect)) => login_redirect(&LoginRedirect {
+                url: login_url,
+                path: &self.path,
+                silent: self.silent,
+            }),
+            (None, None) => HttpResponse::Found()
+                .insert_header((LOCATION, HeaderValue::from_str(login_url)?))
+                .body(self.client.message(MessageKey::NotAuthenticated, &[])),
+        };
+        for &cookie in &self.unreadable_cookies {
+            response.add_cookie(&removal_cookie(&self.client, cookie))?;
+        }
+        Ok(response)
+    }
+
+    /// A link starting the login and returning to the request afterwards, at the realm's
+    /// [login path](Realm::login_path) or at the provider chooser. Unlike the provider's
+    /// authorization url, it can be rendered into pages: the login cookies are set when it is
+    /// followed.
+    pub fn login_url(&self) -> String {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("next", &self.path)
+            .finish();
+        match &self.chooser {
+            Some(chooser) => format!("{}?{}", chooser, query),
+            None => format!("{}?{}", self.client.realm().login_path(), query),
+        }
+    }
+
+    /// Why the session could not be used, `None` if the request had no session.
+    pub fn reason(&self) -> Option<&OpenIdError> {
+        self.reason.as_deref()
+    }
+}
+
+impl fmt::Debug for AuthenticationRequired {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthenticationRequired")
+            .field("reason", &self.reason)
+            .finish_non_exhaustive()
+    }
+}
+
+impl error::ResponseError for AuthenticationRequired {
+    fn status_code(&self) -> StatusCode {
+        self.answer.unwrap_or(StatusCode::FOUND)
+    }
+
+    fn error_response(&self) -> HttpResponse<BoxBody> {
+        let mut resp = self.login_redirect();
+        secure_forwarded_cookies(&self.client, self.forwarded_https, &mut resp);
+        resp
+    }
+}
+
+impl AuthenticationRequired {
+    /// The response sending the user to the provider, with the cookies of the login attempt.
+    fn login_redirect(&self) -> HttpResponse<BoxBody> {
+        if self.chooser.is_some() {
+            return self
+                .login_response(&self.login_url())
+                .unwrap_or_else(|err| {
+                    internal_error(&self.client, "the chooser url is not a valid header", err)
+                });
+        }
+        let default_params = LoginParams::default();
+        let url = self.client.authorization_url(
+            self.path.clone(),
+            match &self.login_params {
+                Some(params) if !self.silent => params,
+                _ => &default_params,
+            },
+            self.silent,
+            self.redirect_url.as_deref(),
+        );
+        let mut resp = match self.login_response(url.url.as_str()) {
+            Ok(resp) => resp,
+            Err(err) => {
+                return internal_error(
+                    &self.client,
+                    "the authorization url is not a valid header",
+                    err,
+                )
             }
-            AuthCookies::IdToken => {
-                write!(f, "id_token")
+        };
+        // Named after the attempt, logins started in other tabs meanwhile keep their own.
+        let attempt = url.login_attempt();
+        let nonce = login_cookie(
+            &self.client,
+            AuthCookies::Nonce,
+            attempt,
+            &nonce_cookie_value(self.client.now(), url.nonce.secret()),
+        );
+        if let Err(err) = resp.add_cookie(&nonce) {
+            return internal_error(&self.client, "the nonce is not a valid cookie value", err);
+        }
+        let state = login_cookie(
+            &self.client,
+            AuthCookies::State,
+            attempt,
+            url.csrf_token.secret(),
+        );
+        if let Err(err) = resp.add_cookie(&state) {
+            return internal_error(&self.client, "cannot set the oauth_state cookie", err);
+        }
+        if let Some(verifier) = &url.pkce_verifier {
+            let cookie = login_cookie(
+                &self.client,
+                AuthCookies::PkceVerifier,
+                attempt,
+                verifier.secret(),
+            );
+            if let Err(err) = resp.add_cookie(&cookie) {
+                return internal_error(&self.client, "cannot set the pkce_verifier cookie", err);
             }
-            AuthCookies::RefreshToken => {
-                write!(f, "refresh_token")
+        }
+        let essential = self.client.claims_request().essential_claims();
+        if !essential.is_empty() {
+            let value = match PayloadCodec::DeflateJson.encode(&essential) {
+                Ok(value) => value,
+                Err(err) => {
+                    return internal_error(&self.client, "cannot encode the essential claims", err)
+                }
+            };
+            let cookie = login_cookie(&self.client, AuthCookies::EssentialClaims, attempt, &value);
+            if let Err(err) = resp.add_cookie(&cookie) {
+                return internal_error(&self.client, "cannot set the essential_claims cookie", err);
             }
-            AuthCookies::UserInfo => {
-                write!(f, "user_info")
+        }
+        self.client
+            .events()
+            .on_login_redirect(&self.client.event_context(Some(&self.path)));
+        if self.silent {
+            // Set before the attempt, a provider that never answers cannot cause a loop either.
+            if let Err(err) = resp.add_cookie(&sso_checked_cookie(&self.client)) {
+                return internal_error(&self.client, "cannot set the sso_checked cookie", err);
             }
-            AuthCookies::Nonce => {
-                write!(f, "nonce")
+        }
+        resp
+    }
+}
+
+/// The cookies the callback checks a login with.
+const LOGIN_COOKIES: [AuthCookies; 4] = [
+    AuthCookies::Nonce,
+    AuthCookies::State,
+    AuthCookies::PkceVerifier,
+    AuthCookies::EssentialClaims,
+];
+
+/// Removes the [`LOGIN_COOKIES`] of the login attempt `attempt` once the callback is done with
+/// them, and the unsuffixed ones of `req` from logins started before they were named after their
+/// attempt. The cookies of other attempts stay for their callbacks.
+fn login_cookie_removals<'a>(
+    client: &'a OpenID,
+    req: &'a HttpRequest,
+    attempt: Option<&'a str>,
+) -> impl Iterator<Item = Cookie<'static>> + 'a {
+    let realm = client.realm();
+    LOGIN_COOKIES.into_iter().flat_map(move |cookie| {
+        let name = realm.cookie_name(cookie);
+        let attempt = attempt.map(|attempt| {
+            client
+                .cookie_config()
+                .removal(realm, &login_cookie_name(name, attempt))
+        });
+        let unsuffixed = req.cookie(name).map(|_| removal_cookie(client, cookie));
+        attempt.into_iter().chain(unsuffixed)
+    })
+}
+
+/// Marks the cookies of `client` that `response` sets `Secure` if the request reached the app
+/// over https through a trusted proxy, while the app itself may be served over http.
+pub(crate) fn secure_forwarded_cookies<B>(
+    client: &OpenID,
+    forwarded_https: bool,
+    response: &mut HttpResponse<B>,
+) {
+    if !forwarded_https || client.cookie_config().is_secure() {
+        return;
+    }
+    let realm = client.realm();
+    let cookies: Vec<_> = response.headers_mut().remove(SET_COOKIE).collect();
+    for value in cookies {
+        let secured = value
+            .to_str()
+            .ok()
+            .and_then(|value| Cookie::parse(value).ok())
+            .filter(|cookie| realm.owns_cookie(cookie.name()))
+            .and_then(|mut cookie| {
+                cookie.set_secure(true);
+                HeaderValue::from_str(&cookie.to_string()).ok()
+            });
+        response
+            .headers_mut()
+            .append(SET_COOKIE, secured.unwrap_or(value));
+    }
+}
+
+fn removal_cookie(client: &OpenID, cookie: AuthCookies) -> Cookie<'static> {
+    let realm = client.realm();
+    client
+        .cookie_config()
+        .removal(realm, realm.cookie_name(cookie))
+}
+
+/// Removes the `unreadable` cookies of the request with its response, unless the response
+/// replaces them, e.g. plaintext cookies from before the cookie key was set.
+fn remove_unreadable_cookies<B>(
+    client: &OpenID,
+    unreadable: Vec<AuthCookies>,
+    result: Result<ServiceResponse<B>, Error>,
+) -> Result<ServiceResponse<B>, Error> {
+    let mut res = match result {
+        Ok(res) => res,
+        Err(err) => {
+            return match err.as_error::<AuthenticationRequired>() {
+                Some(required) => Err(required.clone().removing(unreadable).into()),
+                None => Err(err),
             }
         }
+    };
+    let realm = client.realm();
+    for cookie in unreadable {
+        let name = realm.cookie_name(cookie);
+        if res.response().cookies().any(|set| set.name() == name) {
+            continue;
+        }
+        if let Err(err) = res
+            .response_mut()
+            .add_cookie(&removal_cookie(client, cookie))
+        {
+            client.log_policy().log_error(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Could not remove the unreadable {} cookie: {}", name, err),
+            );
+        }
+    }
+    Ok(res)
+}
+
+/// The name of the login cookie `name` of the login attempt `attempt`.
+pub(crate) fn login_cookie_name(name: &str, attempt: &str) -> String {
+    format!("{}.{}", name, attempt)
+}
+
+/// A cookie the callback checks the login attempt `attempt` with, unreadable by scripts.
+fn login_cookie(client: &OpenID, name: AuthCookies, attempt: &str, value: &str) -> Cookie<'static> {
+    let realm = client.realm();
+    client.seal_cookie(client.cookie_config().login_cookie(
+        realm,
+        &login_cookie_name(realm.cookie_name(name), attempt),
+        value.to_string(),
+        CookieDuration::try_from(client.login_window()).unwrap_or(CookieDuration::MAX),
+    ))
+}
+
+/// The value of the nonce cookie of a login started at `issued`, the time in unix seconds before
+/// the nonce. Sealed with the cookie key, neither can be changed without the other.
+fn nonce_cookie_value(issued: SystemTime, nonce: &str) -> String {
+    let issued = issued
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("{}.{}", issued, nonce)
+}
+
+/// The start and the nonce of a login from its nonce cookie, `None` for a cookie without the
+/// start bound into it: unsealed, dropping it would escape the login window.
+fn parse_nonce_cookie(value: &str) -> Option<(SystemTime, &str)> {
+    let (issued, nonce) = value.split_once('.')?;
+    if issued.is_empty() || !issued.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Out of range, the start is too far off to be in the login window.
+    let issued = issued
+        .parse()
+        .ok()
+        .and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+        .unwrap_or(UNIX_EPOCH);
+    Some((issued, nonce))
+}
+
+/// How long anonymous visitors are not sent through a silent login again.
+const SSO_CHECK_TTL: CookieDuration = CookieDuration::minutes(10);
+
+fn sso_checked_cookie(client: &OpenID) -> Cookie<'static> {
+    let realm = client.realm();
+    client.seal_cookie(client.cookie_config().login_cookie(
+        realm,
+        realm.cookie_name(AuthCookies::SsoChecked),
+        "1".to_string(),
+        SSO_CHECK_TTL,
+    ))
+}
+
+/// Whether an anonymous request is a page navigation worth a silent login attempt: a `GET` the
+/// browser sends for a page, from a visitor not checked recently.
+fn wants_silent_login(client: &OpenID, req: &ServiceRequest) -> bool {
+    req.method() == Method::GET
+        && req
+            .headers()
+            .get("sec-fetch-mode")
+            .is_some_and(|mode| mode == "navigate")
+        && client
+            .auth_cookie(req.request(), AuthCookies::SsoChecked)
+            .is_none()
+        && req.path() != client.realm().callback_path()
+}
+
+/// Path and query of `req`, where the login returns to.
+pub(crate) fn request_target(req: &HttpRequest) -> &str {
+    req.uri()
+        .path_and_query()
+        .map_or(req.path(), |target| target.as_str())
+}
+
+/// Whether the login can return to `req`: browsers replay the redirects of other methods as a
+/// `GET`, whose handler may not exist and that loses the body.
+pub(crate) fn returns_after_login(req: &ServiceRequest) -> bool {
+    matches!(*req.method(), Method::GET | Method::HEAD)
+}
+
+/// What answers `req` when it needs a login: `401 Unauthorized` for `api` requests,
+/// `unsafe_method` for those the login cannot return to, a redirect to the provider otherwise.
+fn login_answer(req: &ServiceRequest, api: bool, unsafe_method: StatusCode) -> Option<StatusCode> {
+    match api {
+        true => Some(StatusCode::UNAUTHORIZED),
+        false if !returns_after_login(req) => Some(unsafe_method),
+        false => None,
+    }
+}
+
+/// Whether `req` comes from a script rather than a page navigation: it accepts JSON but not
+/// HTML, or is sent with `X-Requested-With: XMLHttpRequest`.
+pub fn is_api_request(req: &ServiceRequest) -> bool {
+    let accepts = |media_type: &str| {
+        req.headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .is_some_and(|accept| accept.contains(media_type))
+    };
+    let xhr = req
+        .headers()
+        .get("x-requested-with")
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"XMLHttpRequest"));
+    xhr || (accepts("application/json") && !accepts("text/html"))
+}
+
+/// Answers `500 Internal Server Error`, logging `cause` instead of leaking it to the client.
+pub(crate) fn internal_error(client: &OpenID, context: &str, cause: impl Display) -> HttpResponse {
+    client.log_policy().log_error(
+        LogCategory::LoginFailure,
+        format_args!("{}: {}", context, cause),
+    );
+    HttpResponse::InternalServerError().body(client.message(MessageKey::InternalError, &[]))
+}
+
+pub struct OpenIdMiddleware<S> {
+    openid_client: Arc<OpenID>,
+    service: Rc<S>,
+    should_auth: ShouldAuth,
+    authenticators: Arc<Authenticators>,
+    pre_auth: Option<fn(&ServiceRequest) -> PreAuthDecision>,
+    login_params: Option<fn(&ServiceRequest) -> Option<LoginParams>>,
+    always_authenticate: fn(&ServiceRequest) -> bool,
+    degradable: fn(&ServiceRequest) -> bool,
+    eager_sso: bool,
+    api_request: fn(&ServiceRequest) -> bool,
+    unsafe_method_status: StatusCode,
+    identity_headers: Option<Arc<IdentityHeaders>>,
+    policies: Arc<[Arc<dyn Policy>]>,
+}
+
+impl<S> OpenIdMiddleware<S> {}
+
+/// Checks the [`require`](AuthenticateMiddlewareFactory::require)d policies for the user of
+/// `req`, anonymous requests are sent to the login.
+fn check_policies(policies: &[Arc<dyn Policy>], req: &ServiceRequest) -> Result<(), Error> {
+    if policies.is_empty() {
+        return Ok(());
+    }
+    let user = request_user(req.request())?;
+    policies
+        .iter()
+        .try_for_each(|policy| policy.check(req.request(), &user))
+}
+
+/// Set on the responses of requests served anonymously while the provider is unavailable.
+const AUTH_DEGRADED: HeaderName = HeaderName::from_static("x-auth-degraded");
+
+impl<S, B> Service<ServiceRequest> for OpenIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let srv = self.service.clone();
+        let client = self.openid_client.clone();
+        let span = tracing::debug_span!(
+            "openid_auth",
+            realm = client.realm().id(),
+            path = req.path(),
+            auth_source = tracing::field::Empty,
+            validation = tracing::field::Empty,
+        );
+        let _entered = span.enter();
+        let should_auth = self.should_auth.clone();
+        // The callback completes the login, it never requires one.
+        let callback = req.path() == client.realm().callback_path();
+        let authenticators = self.authenticators.clone();
+        let degradable = (self.degradable)(&req);
+        let eager_sso = self.eager_sso;
+        let answer = login_answer(&req, (self.api_request)(&req), self.unsafe_method_status);
+        let login_params = match (
+            self.login_params.and_then(|hook| hook(&req)),
+            req.extensions().get::<LoginParams>().cloned(),
+        ) {
+            (Some(params), Some(inserted)) => Some(params.merged(inserted)),
+            (params, inserted) => params.or(inserted),
+        };
+        // For the login endpoint, which starts logins itself.
+        if let Some(params) = &login_params {
+            req.extensions_mut().insert(params.clone());
+        }
+        let identity_headers = self.identity_headers.clone();
+        // The callback completes the login the policies ask for.
+        let policies = match callback {
+            true => Arc::from([]),
+            false => self.policies.clone(),
+        };
+        if let Some(identity_headers) = &identity_headers {
+            identity_headers.strip(&mut req);
+        }
+        let pre_auth = self
+            .pre_auth
+            .filter(|_| !(self.always_authenticate)(&req))
+            .map(|pre_auth| pre_auth(&req));
+        let unreadable_cookies = client.unreadable_cookies(req.request());
+        if !unreadable_cookies.is_empty() {
+            client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Removing auth cookies that do not decrypt with the cookie key"),
+            );
+        }
+
+        let response = Box::pin(async move {
+            // Nested realms overwrite the outer one, the route belongs to the innermost.
+            req.extensions_mut().insert(RealmClient(client.clone()));
+            // Started with DiscoveryPolicy::RetryForever, nothing works before the provider does.
+            if let Err(err) = client.ensure_discovered().await {
+                let retry_after = client.discovery_backoff().max(Duration::from_secs(1));
+                return Err(credentials::idp_unavailable(&client, err, retry_after));
+            }
+            match pre_auth {
+                None | Some(PreAuthDecision::Continue) => {}
+                Some(PreAuthDecision::Allow(user)) => {
+                    client.log_policy().log(
+                        LogCategory::UnauthenticatedRequest,
+                        format_args!("The pre_auth hook allowed {}", req.path()),
+                    );
+                    let auth_user = user.map(|user| (*user).into()).ok_or_else(|| {
+                        AuthenticationRequired::new(&client, request_target(req.request()), None)
+                            .answering(answer)
+                            .with_login_params(login_params.clone())
+                            .reached_through(req.request())
+                    });
+                    forward_identity(identity_headers.as_deref(), &client, &mut req, &auth_user);
+                    insert_auth_result(&mut req.extensions_mut(), auth_user);
+                    check_policies(&policies, &req)?;
+                    return srv.call(req).await;
+                }
+                Some(PreAuthDecision::Deny(status)) => {
+                    client.log_policy().log(
+                        LogCategory::UnauthenticatedRequest,
+                        format_args!("The pre_auth hook denied {} with {}", req.path(), status),
+                    );
+                    let response = HttpResponse::build(status).finish();
+                    return Err(error::InternalError::from_response(
+                        "denied by pre_auth",
+                        response,
+                    )
+                    .into());
+                }
+            }
+            let degraded = degradable && client.is_circuit_open();
+            let auth_user = if degraded {
+                client.log_policy().log(
+                    LogCategory::UnauthenticatedRequest,
+                    format_args!(
+                        "The provider is unavailable, serving {} anonymously",
+                        req.path()
+                    ),
+                );
+                Err(
+                    AuthenticationRequired::new(&client, request_target(req.request()), None)
+                        .answering(answer)
+                        .with_login_params(login_params.clone())
+                        .reached_through(req.request()),
+                )
+            } else {
+                load_session(&client, req.request()).await?;
+                match authenticators.authenticate(&client, &req).await? {
+                    None => {
+                        if !callback && should_auth.check(&req) {
+                            client.log_policy().log(
+                                LogCategory::UnauthenticatedRequest,
+                                format_args!("No session for {}, redirecting to auth", req.path()),
+                            );
+                            // Auth is not optional
+                            return Err(AuthenticationRequired::new(
+                                &client,
+                                request_target(req.request()),
+                                None,
+                            )
+                            .answering(answer)
+                            .with_login_params(login_params.clone())
+                            .reached_through(req.request())
+                            .into());
+                        } else if eager_sso && wants_silent_login(&client, &req) {
+                            client.log_policy().log(
+                                LogCategory::UnauthenticatedRequest,
+                                format_args!(
+                                    "No session for {}, trying a silent login",
+                                    req.path()
+                                ),
+                            );
+                            return Err(AuthenticationRequired::silent(
+                                &client,
+                                request_target(req.request()),
+                            )
+                            .reached_through(req.request())
+                            .into());
+                        } else {
+                            Err(AuthenticationRequired::new(
+                                &client,
+                                request_target(req.request()),
+                                None,
+                            )
+                            .answering(answer)
+                            .with_login_params(login_params.clone())
+                            .reached_through(req.request()))
+                        }
+                    }
+                    Some(Err(err)) if !callback && should_auth.check(&req) => {
+                        return Err(err
+                            .answering(answer)
+                            .with_login_params(login_params.clone())
+                            .reached_through(req.request())
+                            .into())
+                    }
+                    Some(auth_user) => auth_user.map_err(|err| {
+                        err.answering(answer)
+                            .with_login_params(login_params.clone())
+                            .reached_through(req.request())
+                    }),
+                }
+            };
+            forward_identity(identity_headers.as_deref(), &client, &mut req, &auth_user);
+            insert_auth_result(&mut req.extensions_mut(), auth_user);
+            // The session may have been refreshed already, errors carry its cookies too.
+            let update = SessionUpdate::of(&client, req.request());
+            let result = match check_policies(&policies, &req) {
+                Ok(()) => srv.call(req).await,
+                Err(err) => Err(err),
+            };
+            let mut res = match result {
+                Ok(res) => res,
+                Err(err) => return Err(update.apply_to_error(&client, err).await),
+            };
+            if degraded {
+                res.headers_mut()
+                    .insert(AUTH_DEGRADED, HeaderValue::from_static("true"));
+            }
+            let update = SessionUpdate::of(&client, res.request());
+            update.apply(&client, res.response_mut()).await;
+            let forwarded_https = client.forwarded_https(res.request());
+            secure_forwarded_cookies(&client, forwarded_https, res.response_mut());
+            Ok(res)
+        });
+        if unreadable_cookies.is_empty() {
+            return Box::pin(response.instrument(span.clone()));
+        }
+        let client = self.openid_client.clone();
+        Box::pin(
+            async move { remove_unreadable_cookies(&client, unreadable_cookies, response.await) }
+                .instrument(span.clone()),
+        )
+    }
+}
+
+fn forward_identity(
+    identity_headers: Option<&IdentityHeaders>,
+    client: &OpenID,
+    req: &mut ServiceRequest,
+    auth_user: &Result<AuthenticatedUser<OtherClaims>, AuthenticationRequired>,
+) {
+    if let (Some(identity_headers), Ok(user)) = (identity_headers, auth_user) {
+        identity_headers.forward(client, req, user);
+    }
+}
+
+/// The user of `client`'s session, `None` if the request has none.
+pub(crate) async fn session_user(
+    client: &Arc<OpenID>,
+    req: &ServiceRequest,
+) -> Result<Option<Result<AuthenticatedUser<OtherClaims>, AuthenticationRequired>>, OpenIdError> {
+    load_session(client, req.request()).await?;
+    let Some(session) = request_session(client, req.request()) else {
+        return Ok(None);
+    };
+    Ok(Some(token_user(client, req, session.access_token()).await))
+}
+
+/// The sessions read from the store for the request by their id, `None` for ids naming none.
+#[derive(Default)]
+struct LoadedSessions(HashMap<String, Option<Arc<StoredSession>>>);
+
+/// Reads the session the request's session id names from `client`'s [`SessionStore`], for
+/// [`request_session`] to find. Does nothing for clients without a store.
+pub(crate) async fn load_session(client: &OpenID, req: &HttpRequest) -> Result<(), OpenIdError> {
+    let (Some(store), Some(session_id)) = (
+        client.session_store(),
+        client.auth_cookie(req, AuthCookies::SessionId),
+    ) else {
+        return Ok(());
+    };
+    let loaded = req
+        .extensions()
+        .get::<LoadedSessions>()
+        .is_some_and(|loaded| loaded.0.contains_key(&session_id));
+    if loaded {
+        return Ok(());
+    }
+    let session = store.get(&session_id).await.inspect_err(|err| {
+        client.log_policy().log_error(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("Could not read the session: {}", err),
+        );
+    })?;
+    let now = client.now();
+    let session = session.filter(|session| session.expires_at().is_none_or(|at| now < at));
+    if session.is_none() {
+        client.log_policy().log(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("The session id names no stored session"),
+        );
+    }
+    let mut extensions = req.extensions_mut();
+    if !extensions.contains::<LoadedSessions>() {
+        extensions.insert(LoadedSessions::default());
+    }
+    if let Some(loaded) = extensions.get_mut::<LoadedSessions>() {
+        loaded.0.insert(session_id, session.map(Arc::new));
+    }
+    Ok(())
+}
+
+/// The tokens of the request's session: those [`load_session`] found in `client`'s store, or else
+/// those of its session cookies.
+pub(crate) fn request_session(client: &OpenID, req: &HttpRequest) -> Option<Arc<StoredSession>> {
+    if client.session_store().is_some() {
+        let session_id = client.auth_cookie(req, AuthCookies::SessionId)?;
+        return req
+            .extensions()
+            .get::<LoadedSessions>()?
+            .0
+            .get(&session_id)?
+            .clone();
+    }
+    let access_token = client.auth_cookie(req, AuthCookies::AccessToken)?;
+    let expires_at = client
+        .auth_cookie(req, AuthCookies::ExpiresAt)
+        .and_then(|expires_at| expires_at.parse::<u64>().ok())
+        // A time past what the clock can tell is forged, the token counts as expired.
+        .map(|secs| {
+            UNIX_EPOCH
+                .checked_add(Duration::from_secs(secs))
+                .unwrap_or(UNIX_EPOCH)
+        });
+    let data = client
+        .auth_cookie(req, AuthCookies::SessionData)
+        .and_then(|data| PayloadCodec::decode(&data).ok());
+    let times = client
+        .auth_cookie(req, AuthCookies::SessionTimes)
+        .and_then(|times| SessionTimes::parse(&times));
+    Some(Arc::new(
+        StoredSession::from_cookies(
+            access_token,
+            client.auth_cookie(req, AuthCookies::IdToken),
+            client.auth_cookie(req, AuthCookies::RefreshToken),
+            expires_at,
+            data,
+        )
+        .with_times(times),
+    ))
+}
+
+/// The user of the session's access token.
+pub(crate) async fn token_user(
+    client: &Arc<OpenID>,
+    req: &ServiceRequest,
+    access_token: AccessToken,
+) -> Result<AuthenticatedUser<OtherClaims>, AuthenticationRequired> {
+    if client.session_limits().is_enabled() {
+        check_session_limits(client, req).await?;
+    }
+    if client.not_before().is_enabled() {
+        if let Err(err) = check_not_before(client, req).await {
+            client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("The session must log in again: {}", err),
+            );
+            client.forget_user_claims(&access_token);
+            return Err(AuthenticationRequired::new(
+                client,
+                request_target(req.request()),
+                Some(err),
+            ));
+        }
+    }
+    let session = request_session(client, req.request());
+    let refresh_token = session.as_ref().and_then(|session| session.refresh_token());
+    let expires_at = session
+        .as_ref()
+        .and_then(|session| session.access_token_expires_at());
+    let now = client.now();
+    let expired = expires_at.is_some_and(|expires_at| expires_at <= now);
+    let expiring = expires_at.is_some_and(|expires_at| expires_at <= now + REFRESH_WINDOW);
+    let user = match refresh_token {
+        // A token about to expire is refreshed without asking the provider about it first.
+        Some(refresh_token) if expiring => {
+            client.log_policy().log(
+                LogCategory::Refresh,
+                format_args!("Renewing the session, its access token expires soon"),
+            );
+            refresh_session(client, req, refresh_token).await
+        }
+        // Nothing renews it, the user logs in again rather than the provider refusing it.
+        None if expired => {
+            Err(ClaimsVerificationError::Expired("the access token expired".to_string()).into())
+        }
+        refresh_token => match client.user_claims(access_token.clone(), expires_at).await {
+            Ok(user_info) => Ok(AuthenticatedUser::new(user_info).with_tokens(
+                UserTokens::new(access_token, expires_at)
+                    .with_id_token(
+                        client,
+                        session.as_ref().and_then(|session| session.id_token()),
+                    )
+                    .with_session_data(session.as_ref()),
+            )),
+            Err(err) => match refresh_token {
+                Some(refresh_token)
+                    if !matches!(client.error_action(&err), ErrorAction::RetryLater(_)) =>
+                {
+                    client.log_policy().log(
+                        LogCategory::Refresh,
+                        format_args!(
+                            "Renewing the session, its access token was rejected: {}",
+                            err
+                        ),
+                    );
+                    refresh_session(client, req, refresh_token).await
+                }
+                _ => Err(err),
+            },
+        },
+    };
+    user.map_err(|err| {
+        let category = match client.error_action(&err) {
+            ErrorAction::RetryLater(_) => LogCategory::IdpError,
+            _ => LogCategory::UnauthenticatedRequest,
+        };
+        client.log_policy().log(
+            category,
+            format_args!("Could not fetch the user info, asking to log in: {}", err),
+        );
+        let revoked = matches!(err, OpenIdError::RefreshTokenRevoked(_));
+        let required =
+            AuthenticationRequired::new(client, request_target(req.request()), Some(err));
+        if revoked {
+            required.removing(AuthCookies::ALL.to_vec())
+        } else {
+            required
+        }
+    })
+}
+
+/// Renews the session with `refresh_token`, for the request to use the new tokens and the
+/// middleware to store them in the session cookies.
+async fn refresh_session(
+    client: &Arc<OpenID>,
+    req: &ServiceRequest,
+    refresh_token: RefreshToken,
+) -> Result<AuthenticatedUser<OtherClaims>, OpenIdError> {
+    let tokens = match client.refresh(&refresh_token).await {
+        Ok(tokens) => tokens,
+        Err(err @ OpenIdError::RefreshTokenRevoked(_)) => {
+            client.log_policy().log_error(
+                LogCategory::Refresh,
+                format_args!("Ending the session, {}", err),
+            );
+            end_session(client, req, LogCategory::Refresh).await;
+            req.extensions_mut().insert(EndedSession);
+            return Err(err);
+        }
+        Err(err) => {
+            client.log_policy().log(
+                LogCategory::Refresh,
+                format_args!("Could not renew the session: {}", err),
+            );
+            return Err(err);
+        }
+    };
+    let expires_at = tokens
+        .expires_in
+        .map(|expires_in| client.now() + expires_in);
+    let user_info = client
+        .user_claims(tokens.access_token.clone(), expires_at)
+        .await?;
+    client.log_policy().log(
+        LogCategory::Refresh,
+        format_args!("Renewed the session of {}", user_info.subject().as_str()),
+    );
+    let session = request_session(client, req.request());
+    let id_token = match &tokens.id_token {
+        Some(id_token) => Some(id_token.to_string()),
+        None => session
+            .as_ref()
+            .and_then(|session| session.id_token())
+            .map(str::to_string),
+    };
+    let user_tokens = UserTokens::new(tokens.access_token.clone(), expires_at)
+        .with_id_token(client, id_token.as_deref())
+        .with_session_data(session.as_ref());
+    req.extensions_mut().insert(SessionToken::refreshed(
+        client.clone(),
+        tokens,
+        refresh_token,
+    ));
+    Ok(AuthenticatedUser::new(user_info).with_tokens(user_tokens))
+}
+
+/// Marks requests whose session ended, because the provider revoked its refresh token or the
+/// session outlived a limit, for the response to remove the session cookies.
+struct EndedSession;
+
+/// Marks requests recording when a session kept in the cookies was last seen, for the response to
+/// set the times cookie.
+struct SeenSession(SessionTimes);
+
+/// Removes the stored session of a request whose session ended, and the claims cached for its
+/// access token.
+async fn end_session(client: &OpenID, req: &ServiceRequest, category: LogCategory) {
+    if let Some(session) = request_session(client, req.request()) {
+        client.forget_user_claims(&session.access_token());
+    }
+    let (Some(store), Some(session_id)) = (
+        client.session_store(),
+        client.auth_cookie(req.request(), AuthCookies::SessionId),
+    ) else {
+        return;
+    };
+    if let Err(err) = store.remove(&session_id).await {
+        client.log_policy().log_error(
+            category,
+            format_args!("Could not remove the session: {}", err),
+        );
+    }
+}
+
+/// Ends sessions that outlived a session limit, and records when the others were last seen.
+async fn check_session_limits(
+    client: &Arc<OpenID>,
+    req: &ServiceRequest,
+) -> Result<(), AuthenticationRequired> {
+    let limits = client.session_limits();
+    let session = request_session(client, req.request());
+    let times = session
+        .as_ref()
+        .and_then(|session| session.times())
+        .copied();
+    let now = client.now();
+    if let Some(limit) = limits.exceeded(times.as_ref(), now) {
+        client.log_policy().log(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("Ending the session, it outlived its {}", limit),
+        );
+        end_session(client, req, LogCategory::UnauthenticatedRequest).await;
+        req.extensions_mut().insert(EndedSession);
+        return Err(AuthenticationRequired::new(
+            client,
+            request_target(req.request()),
+            Some(OpenIdError::SessionExpired(limit)),
+        )
+        .removing(AuthCookies::ALL.to_vec()));
+    }
+    let (Some(session), Some(seen)) = (session, times.and_then(|times| limits.seen(&times, now)))
+    else {
+        return Ok(());
+    };
+    let (Some(store), Some(session_id)) = (
+        client.session_store(),
+        client.auth_cookie(req.request(), AuthCookies::SessionId),
+    ) else {
+        req.extensions_mut().insert(SeenSession(seen));
+        return Ok(());
+    };
+    let session = StoredSession::clone(&session).with_times(Some(seen));
+    if let Err(err) = store.insert(&session_id, session.clone()).await {
+        client.log_policy().log_error(
+            LogCategory::UnauthenticatedRequest,
+            format_args!("Could not record when the session was seen: {}", err),
+        );
+        return Ok(());
+    }
+    // A refresh later in the request keeps the new times.
+    if let Some(loaded) = req.extensions_mut().get_mut::<LoadedSessions>() {
+        loaded.0.insert(session_id, Some(Arc::new(session)));
+    }
+    Ok(())
+}
+
+/// Fails for sessions authenticated before the user's not-before time, or whose ID token tells
+/// nothing about the login.
+async fn check_not_before(client: &OpenID, req: &ServiceRequest) -> Result<(), OpenIdError> {
+    let session = request_session(client, req.request());
+    let id_token = session
+        .as_ref()
+        .and_then(|session| session.id_token())
+        .ok_or_else(|| ClaimsVerificationError::Other("the session has no ID token".to_string()))?;
+    let id_token = IdToken::from_str(id_token)
+        .map_err(|err| ClaimsVerificationError::Other(format!("invalid ID token: {}", err)))?;
+    let (subject, authenticated_at) = client.authenticated_at(&id_token)?;
+    if client
+        .not_before()
+        .predates(&subject, authenticated_at)
+        .await
+    {
+        return Err(ClaimsVerificationError::Expired(format!(
+            "{} logged in before their not-before time",
+            subject
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// The updates of a request's session cookies its response carries: the removal of a session that
+/// ended, when it was last seen, and the tokens refreshed for it.
+///
+/// Read from the request once the inner services answered, or before they take the request, for
+/// their errors to carry the updates of the middleware.
+pub(crate) struct SessionUpdate {
+    ended: bool,
+    seen: Option<SessionTimes>,
+    session_token: Option<SessionToken>,
+    session_id: Option<String>,
+    session: Option<Arc<StoredSession>>,
+    refresh_token: Option<String>,
+    session_data: Option<String>,
+}
+
+impl SessionUpdate {
+    pub(crate) fn of(client: &OpenID, req: &HttpRequest) -> Self {
+        let extensions = req.extensions();
+        let session_token = extensions.get::<SessionToken>().cloned();
+        let mut update = SessionUpdate {
+            ended: extensions.contains::<EndedSession>(),
+            seen: extensions.get::<SeenSession>().map(|seen| seen.0),
+            session_token,
+            session_id: None,
+            session: None,
+            refresh_token: None,
+            session_data: None,
+        };
+        drop(extensions);
+        // Only sessions with a token can have been refreshed.
+        if update.session_token.is_some() {
+            update.session_id = client.auth_cookie(req, AuthCookies::SessionId);
+            update.session = request_session(client, req);
+            update.refresh_token = client.auth_cookie(req, AuthCookies::RefreshToken);
+            update.session_data = client.auth_cookie(req, AuthCookies::SessionData);
+        }
+        update
+    }
+
+    /// Stores the refreshed tokens in `client`'s session cookies or store, or removes the cookies
+    /// of a session that ended.
+    ///
+    /// Only `Set-Cookie` headers are added to the `response`, its body, e.g. a stream, is left
+    /// alone.
+    pub(crate) async fn apply<B>(self, client: &OpenID, response: &mut HttpResponse<B>) {
+        if self.ended {
+            let realm = client.realm();
+            for cookie in AuthCookies::ALL {
+                let name = realm.cookie_name(cookie);
+                if response.cookies().any(|set| set.name() == name) {
+                    continue;
+                }
+                if let Err(err) = response.add_cookie(&removal_cookie(client, cookie)) {
+                    client.log_policy().log_error(
+                        LogCategory::Refresh,
+                        format_args!("Could not remove the {} cookie: {}", name, err),
+                    );
+                }
+            }
+            return;
+        }
+        if let Some(times) = self.seen {
+            if let Err(err) = response.add_cookie(&session_times_cookie(client, &times)) {
+                client.log_policy().log_error(
+                    LogCategory::UnauthenticatedRequest,
+                    format_args!("Could not record when the session was seen: {}", err),
+                );
+            }
+        }
+        let refreshed = match &self.session_token {
+            Some(session_token) => session_token.take_refreshed().await,
+            None => None,
+        };
+        let Some(tokens) = refreshed else {
+            return;
+        };
+        let stored = match client.session_store() {
+            Some(store) => match self.store_refreshed_session(client, store, &tokens).await {
+                Ok(Some(cookie)) => response.add_cookie(&cookie).map_err(|err| err.to_string()),
+                Ok(None) => Ok(()),
+                Err(err) => Err(err.to_string()),
+            },
+            None => set_refreshed_cookies(
+                response,
+                client,
+                &tokens,
+                self.refresh_token,
+                self.session_data,
+            )
+            .map_err(|err| err.to_string()),
+        };
+        if let Err(err) = stored {
+            client.log_policy().log_error(
+                LogCategory::Refresh,
+                format_args!("Could not store the refreshed tokens: {}", err),
+            );
+        }
+    }
+
+    /// The error answering the request, its response carrying the updates, e.g. for a session
+    /// refreshed before an inner service failed. Errors asking to log in are left alone, the login
+    /// replaces the session.
+    pub(crate) async fn apply_to_error(self, client: &OpenID, err: Error) -> Error {
+        if err.as_error::<AuthenticationRequired>().is_some() {
+            return err;
+        }
+        let mut response = err.error_response();
+        let cookies = response.headers().get_all(SET_COOKIE).count();
+        self.apply(client, &mut response).await;
+        if response.headers().get_all(SET_COOKIE).count() == cookies {
+            return err;
+        }
+        error::InternalError::from_response(err, response).into()
+    }
+
+    /// Replaces the stored session with the refreshed `tokens`, returning the session cookie kept
+    /// for as long as the session.
+    async fn store_refreshed_session(
+        &self,
+        client: &OpenID,
+        store: &dyn SessionStore,
+        tokens: &RefreshedTokens,
+    ) -> Result<Option<Cookie<'static>>, OpenIdError> {
+        let (Some(session_id), Some(session)) = (&self.session_id, &self.session) else {
+            return Ok(None);
+        };
+        // Only sessions with a refresh token are refreshed.
+        let max_age = client.cookie_config().session_max_age(
+            tokens.expires_in,
+            true,
+            tokens.refresh_expires_in,
+        );
+        let session = session.refreshed(tokens, client.now(), stored_max_age(max_age));
+        store.insert(session_id, session).await?;
+        Ok(Some(token_cookie(
+            client,
+            AuthCookies::SessionId,
+            session_id.clone(),
+            max_age,
+        )))
+    }
+}
+
+/// How long the store keeps a session whose cookie is kept for `max_age`.
+pub(crate) fn stored_max_age(max_age: Option<CookieDuration>) -> Option<Duration> {
+    max_age.and_then(|max_age| max_age.try_into().ok())
+}
+
+/// The client of the realm whose middleware handled the request.
+#[derive(Clone)]
+pub(crate) struct RealmClient(pub(crate) Arc<OpenID>);
+
+/// What the middleware found out about the request, stored in its extensions for the
+/// extractors and for code of other crates, e.g. `req.extensions().get::<AuthContext>()`. Absent
+/// on routes the middleware does not wrap.
+#[derive(Clone)]
+pub enum AuthContext {
+    /// The user is shared behind an `Arc`, extractors do not copy the claims.
+    Authenticated(Arc<AuthenticatedUser<OtherClaims>>),
+    Unauthenticated {
+        /// A link starting the login, see [`AuthenticationRequired::login_url`].
+        login_url: String,
+        /// Answers requests whose handler requires the login.
+        required: AuthenticationRequired,
+    },
+}
+
+impl AuthContext {
+    /// The user, `None` if the request is not authenticated.
+    pub fn user(&self) -> Option<&Arc<AuthenticatedUser<OtherClaims>>> {
+        match self {
+            AuthContext::Authenticated(user) => Some(user),
+            AuthContext::Unauthenticated { .. } => None,
+        }
+    }
+}
+
+/// Answered by [`Authenticated`] on routes without the middleware, a setup error rather than a
+/// missing login.
+const MIDDLEWARE_MISSING: &str = "OpenID middleware not registered for this scope";
+
+/// Stores the outcome of authentication where the extractors look for it.
+///
+/// Everything that makes a user available to `Authenticated`/`MaybeAuthenticated` goes through
+/// here, so the middleware and `test_util` cannot drift apart.
+pub(crate) fn insert_auth_result(
+    extensions: &mut Extensions,
+    auth_result: Result<AuthenticatedUser<OtherClaims>, AuthenticationRequired>,
+) {
+    extensions.insert(match auth_result {
+        Ok(user) => AuthContext::Authenticated(Arc::new(user)),
+        Err(required) => AuthContext::Unauthenticated {
+            login_url: required.login_url(),
+            required,
+        },
+    });
+}
+
+/// Authenticates the requests of the app or of the scope it wraps.
+///
+/// Factories are cheap to clone, every clone shares the client: its discovered documents, JWKS
+/// and caches, cookie configuration and background tasks. Wrap scopes of the app with
+/// [`scoped`](Self::scoped) factories answering differently, and register the endpoints once for
+/// the whole app:
+///
+/// ```ignore
+/// let auth = openid.get_middleware();
+/// App::new()
+///     .configure(openid.configure_open_id())
+///     .service(
+///         web::scope("/admin")
+///             .wrap(auth.scoped().require(HasRole::new("admin")))
+///             .service(admin),
+///     )
+///     .service(web::scope("/app").wrap(auth.scoped()).service(app))
+///     .service(
+///         web::scope("")
+///             .wrap(auth.scoped().should_auth(|_| false))
+///             .service(home),
+///     )
+/// ```
+#[derive(Clone)]
+pub struct AuthenticateMiddlewareFactory {
+    client: Arc<OpenID>,
+    should_auth: ShouldAuth,
+    credentials: Option<CredentialChain>,
+    api_key_header: HeaderName,
+    api_keys: Option<Arc<dyn ApiKeyValidator>>,
+    basic_auth: Option<BasicAuthConfig>,
+    pre_auth: Option<fn(&ServiceRequest) -> PreAuthDecision>,
+    login_params: Option<fn(&ServiceRequest) -> Option<LoginParams>>,
+    always_authenticate: fn(&ServiceRequest) -> bool,
+    degradable: fn(&ServiceRequest) -> bool,
+    eager_sso: bool,
+    api_request: fn(&ServiceRequest) -> bool,
+    unsafe_method_status: StatusCode,
+    identity_headers: Option<Arc<IdentityHeaders>>,
+    policies: Vec<Arc<dyn Policy>>,
+}
+
+impl AuthenticateMiddlewareFactory {
+    /// Accepts the client as `OpenID`, `Arc<OpenID>` or `web::Data<OpenID>::into_inner()`.
+    pub fn new(
+        client: impl Into<Arc<OpenID>>,
+        should_auth: impl Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        AuthenticateMiddlewareFactory::with_should_auth(client, ShouldAuth::new(should_auth))
+    }
+
+    pub(crate) fn with_should_auth(
+        client: impl Into<Arc<OpenID>>,
+        should_auth: ShouldAuth,
+    ) -> Self {
+        AuthenticateMiddlewareFactory {
+            client: client.into(),
+            should_auth,
+            credentials: None,
+            api_key_header: HeaderName::from_static("x-api-key"),
+            api_keys: None,
+            basic_auth: None,
+            pre_auth: None,
+            login_params: None,
+            always_authenticate: |_| false,
+            degradable: |_| false,
+            eager_sso: false,
+            api_request: is_api_request,
+            unsafe_method_status: StatusCode::UNAUTHORIZED,
+            identity_headers: None,
+            policies: Vec::new(),
+        }
+    }
+
+    /// A copy of this factory sharing its client, to configure for one scope of the app with the
+    /// setters below, e.g. [`should_auth`](Self::should_auth) and [`require`](Self::require).
+    /// See [`AuthenticateMiddlewareFactory`].
+    pub fn scoped(&self) -> Self {
+        self.clone()
+    }
+
+    /// Decides which requests require a login instead of the builder's
+    /// [`should_auth`](crate::OpenIdBuilder::should_auth), e.g. `|_| false` for a scope where
+    /// the login is optional.
+    pub fn should_auth(
+        mut self,
+        should_auth: impl Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.should_auth = ShouldAuth::new(should_auth);
+        self
+    }
+
+    /// Checks `policy` for every request, once it is authenticated, like
+    /// [`RequireClaims`](crate::RequireClaims) does. Anonymous requests are sent to the login,
+    /// even where [`should_auth`](Self::should_auth) does not require one, users failing the
+    /// policy are answered with its error. Policies required several times must all pass.
+    pub fn require(mut self, policy: impl Policy) -> Self {
+        self.policies.push(Arc::new(policy));
+        self
+    }
+
+    /// Where to look for credentials and in which order, the session cookie, Basic credentials
+    /// and then the API key header by default.
+    pub fn credentials(mut self, credentials: CredentialChain) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Authenticates requests without a session by their API key, see [`ApiKeyValidator`].
+    pub fn api_key_validator(mut self, validator: impl ApiKeyValidator + 'static) -> Self {
+        self.api_keys = Some(Arc::new(validator));
+        self
+    }
+
+    /// Authenticates requests without a session to `paths` by their `Authorization: Basic`
+    /// credentials, checked with `method`.
+    pub fn basic_auth(mut self, paths: fn(&ServiceRequest) -> bool, method: BasicAuth) -> Self {
+        self.basic_auth = Some(BasicAuthConfig { paths, method });
+        self
+    }
+
+    /// Decides about requests before their credentials are looked at, see [`PreAuthDecision`].
+    /// Decisions other than `Continue` are logged at debug level.
+    pub fn pre_auth(mut self, hook: fn(&ServiceRequest) -> PreAuthDecision) -> Self {
+        self.pre_auth = Some(hook);
+        self
+    }
+
+    /// Scopes and parameters added to the logins started from a request, e.g. step-up scopes for
+    /// the routes below `/payments`. `None` logs in with the client's alone.
+    pub fn login_params(mut self, hook: fn(&ServiceRequest) -> Option<LoginParams>) -> Self {
+        self.login_params = Some(hook);
+        self
+    }
+
+    /// Routes the [`pre_auth`](Self::pre_auth) hook is never asked about, they are always
+    /// authenticated as usual.
+    pub fn always_authenticate(mut self, paths: fn(&ServiceRequest) -> bool) -> Self {
+        self.always_authenticate = paths;
+        self
+    }
+
+    /// Routes served anonymously while the provider's circuit breaker is open, see
+    /// [`OpenID::is_circuit_open`]. Their responses carry `X-Auth-Degraded: true`, other routes
+    /// keep failing as usual.
+    pub fn degradable(mut self, paths: fn(&ServiceRequest) -> bool) -> Self {
+        self.degradable = paths;
+        self
+    }
+
+    /// Sends anonymous visitors of public pages through a silent login with `prompt=none`, so
+    /// users signed in at the provider are recognized without clicking anything. Visitors
+    /// without a session at the provider come back anonymously, and are not sent again for
+    /// 10 minutes. Only page navigations (`Sec-Fetch-Mode: navigate`) are redirected.
+    ///
+    /// Off by default, it costs a redirect to the provider.
+    pub fn eager_sso(mut self, eager_sso: bool) -> Self {
+        self.eager_sso = eager_sso;
+        self
+    }
+
+    /// Requests answered `401 Unauthorized` with a JSON body naming the login url when they need
+    /// a login, instead of a redirect to the provider that `fetch()` cannot follow across origins.
+    /// [`is_api_request`] by default.
+    pub fn api_request(mut self, api_request: fn(&ServiceRequest) -> bool) -> Self {
+        self.api_request = api_request;
+        self
+    }
+
+    /// Status answered, with a JSON body naming the login url, to requests other than `GET` and
+    /// `HEAD` that need a login, e.g. a form posted after the session expired: the browser would
+    /// replay them as a `GET` after the login, without their body. `401 Unauthorized` by default,
+    /// e.g. `409 Conflict` for clients telling it apart from an API's answer.
+    pub fn unsafe_method_status(mut self, status: StatusCode) -> Self {
+        self.unsafe_method_status = status;
+        self
+    }
+
+    /// Asks for `claims_request` instead of the client's when this middleware sends users to the
+    /// provider, see [`ClaimsRequest`].
+    pub fn claims_request(mut self, claims_request: ClaimsRequest) -> Self {
+        self.client = Arc::new(self.client.with_claims_request(claims_request));
+        self
+    }
+
+    /// Tells the inner service who the user is with request headers, see [`IdentityHeaders`].
+    /// Client-supplied headers of the same names are removed from every request.
+    pub fn identity_headers(mut self, identity_headers: IdentityHeaders) -> Self {
+        self.identity_headers = Some(Arc::new(identity_headers));
+        self
+    }
+
+    /// The background tasks of the client, started when the first worker builds the middleware.
+    /// Keep a clone to [`shutdown`](TaskSet::shutdown) them once the server stopped.
+    pub fn tasks(&self) -> &TaskSet {
+        self.client.tasks()
+    }
+
+    /// Refreshes the provider's documents every `interval` in the background, see
+    /// [`OpenID::refresh_provider`]. Failures are logged, the cached documents are kept.
+    pub fn refresh_provider_every(self, interval: Duration) -> Self {
+        let client = Arc::downgrade(&self.client);
+        self.client
+            .tasks()
+            .add("refresh_provider", move |mut shutdown| async move {
+                loop {
+                    let stopping = shutdown.requested();
+                    if tokio::time::timeout(interval, stopping).await.is_ok() {
+                        return;
+                    }
+                    let Some(client) = client.upgrade() else {
+                        return;
+                    };
+                    if let Err(err) = client.refresh_provider().await {
+                        client.log_policy().log(
+                            LogCategory::IdpError,
+                            format_args!("Could not refresh the provider's documents: {}", err),
+                        );
+                    }
+                }
+            });
+        self
+    }
+
+    /// Header carrying the API key in the default [`credentials`](Self::credentials), `X-Api-Key`
+    /// by default.
+    pub fn api_key_header(mut self, header: HeaderName) -> Self {
+        self.api_key_header = header;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AuthenticateMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = OpenIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        self.client.tasks().start();
+        let chain = self.credentials.clone().unwrap_or_else(|| {
+            CredentialChain::new()
+                .with_sessions_of(&self.client)
+                .with(BasicHeader)
+                .with(ApiKeyHeader::new(self.api_key_header.clone()))
+        });
+        ready(Ok(OpenIdMiddleware {
+            openid_client: self.client.clone(),
+            service: Rc::new(service),
+            should_auth: self.should_auth.clone(),
+            authenticators: Arc::new(Authenticators {
+                chain,
+                api_keys: self.api_keys.clone(),
+                basic_auth: self.basic_auth.clone(),
+            }),
+            pre_auth: self.pre_auth,
+            login_params: self.login_params,
+            always_authenticate: self.always_authenticate,
+            degradable: self.degradable,
+            eager_sso: self.eager_sso,
+            api_request: self.api_request,
+            unsafe_method_status: self.unsafe_method_status,
+            identity_headers: self.identity_headers.clone(),
+            policies: self.policies.iter().cloned().collect(),
+        }))
+    }
+}
+
+/// The client of the realm the request belongs to: the one stamped by its middleware, or else
+/// the one registered by [`configure_open_id`](crate::ActixWebOpenId::configure_open_id) as
+/// `web::Data<OpenID>`.
+///
+/// `web::Data<Arc<OpenID>>`, registered before, is still accepted until the next release.
+pub(crate) struct RegisteredClient(pub(crate) Arc<OpenID>);
+
+impl RegisteredClient {
+    pub(crate) fn get(req: &HttpRequest) -> Result<Self, Error> {
+        if let Some(client) = RegisteredClient::find(req) {
+            return Ok(client);
+        }
+        // Every request fails the same way, once is enough to find the missing registration.
+        static REPORTED: Once = Once::new();
+        REPORTED.call_once(|| {
+            LogPolicy::default().log_error(
+                LogCategory::ConfigWarning,
+                format_args!(
+                    "No OpenID client is registered, register it as web::Data<OpenID> with \
+                     App::configure(openid.configure_open_id())"
+                ),
+            )
+        });
+        Err(ErrorInternalServerError(
+            EnglishMessages.message(MessageKey::ClientNotRegistered, &[]),
+        ))
+    }
+
+    pub(crate) fn find(req: &HttpRequest) -> Option<Self> {
+        if let Some(RealmClient(client)) = req.extensions().get::<RealmClient>() {
+            return Some(RegisteredClient(client.clone()));
+        }
+        if let Some(client) = req.app_data::<web::Data<OpenID>>() {
+            return Some(RegisteredClient(client.clone().into_inner()));
+        }
+        req.app_data::<web::Data<Arc<OpenID>>>()
+            .map(|client| RegisteredClient(client.get_ref().clone()))
+    }
+
+    /// The text for `key` from the request's client, the English one without a client.
+    pub(crate) fn message_for(req: &HttpRequest, key: MessageKey) -> String {
+        match RegisteredClient::find(req) {
+            Some(client) => client.message(key, &[]),
+            None => EnglishMessages.message(key, &[]),
+        }
+    }
+}
+
+impl FromRequest for RegisteredClient {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(RegisteredClient::get(req))
+    }
+}
+
+impl std::ops::Deref for RegisteredClient {
+    type Target = OpenID;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AuthQuery {
+    code: Option<String>,
+    // Providers may leave it out of error redirects.
+    state: Option<String>,
+    /// Set by the provider instead of the code when the login failed.
+    error: Option<String>,
+    error_description: Option<String>,
+    error_uri: Option<String>,
+}
+
+/// Errors answering a `prompt=none` login of a user the provider would have to show a page.
+const SILENT_LOGIN_ERRORS: [&str; 4] = [
+    "login_required",
+    "interaction_required",
+    "consent_required",
+    "account_selection_required",
+];
+
+/// The query of the logout endpoints: where to send the user after the logout, and the
+/// `state` the provider returns it in.
+#[derive(Default, Deserialize)]
+struct LogoutQuery {
+    redirect: Option<String>,
+    state: Option<String>,
+}
+
+impl LogoutQuery {
+    /// The query of `req`, empty if it is malformed.
+    fn of(req: &HttpRequest) -> Self {
+        web::Query::<LogoutQuery>::from_query(req.query_string())
+            .map(web::Query::into_inner)
+            .unwrap_or_default()
+    }
+}
+
+/// Registers the callback, login and logout endpoints at the paths of `realm`, within its scope.
+pub(crate) fn configure_endpoints(cfg: &mut web::ServiceConfig, realm: &Realm) {
+    let logout = realm.logout_route();
+    cfg.route(realm.callback_route(), web::get().to(auth_endpoint))
+        .route(realm.callback_route(), web::post().to(auth_form_endpoint))
+        .route("/login", web::get().to(login_endpoint))
+        .route(logout, web::get().to(logout_endpoint))
+        .route(
+            &format!("{}/local", logout),
+            web::get().to(local_logout_endpoint),
+        )
+        .route(
+            &format!("{}/callback", logout),
+            web::get().to(post_logout_endpoint),
+        );
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginQuery {
+    next: Option<String>,
+    hint: Option<String>,
+    prompt: Option<String>,
+}
+
+/// The prompts a link to the login endpoint may ask for, `none` is the middleware's to send.
+const LOGIN_PROMPTS: [&str; 3] = ["login", "consent", "select_account"];
+
+/// Starts the login, for links on pages where it is optional, see
+/// [`AuthenticationRequired::login_url`]. Returns to the local path `?next=` afterwards, `/`
+/// without one, and redirects there right away if the user is logged in, unless `?prompt=`
+/// asks for another login, e.g. `prompt=select_account` to switch accounts.
+///
+/// `?hint=` is passed on as the `login_hint`, and the `Accept-Language` header as the
+/// `ui_locales`, after the [`LoginParams`] of the middleware.
+pub(crate) async fn login_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+    query: web::Query<LoginQuery>,
+) -> HttpResponse {
+    let next = query.next.as_deref().and_then(local_path).unwrap_or("/");
+    let prompt = query
+        .prompt
+        .as_deref()
+        .filter(|prompt| LOGIN_PROMPTS.contains(prompt));
+    let authenticated = matches!(
+        req.extensions().get::<AuthContext>(),
+        Some(AuthContext::Authenticated(_))
+    );
+    if authenticated && prompt.is_none() {
+        return HttpResponse::Found()
+            .insert_header((LOCATION, next.to_string()))
+            .finish();
+    }
+    let mut params = LoginParams::new().ui_locales_from(req.headers());
+    if let Some(inserted) = req.extensions().get::<LoginParams>() {
+        params = params.merged(inserted.clone());
+    }
+    if let Some(hint) = &query.hint {
+        params = params.login_hint(hint);
+    }
+    if let Some(prompt) = prompt {
+        params = params.prompt(prompt);
+    }
+    error::ResponseError::error_response(
+        &AuthenticationRequired::new(&open_id_client.0, next, None)
+            .with_login_params(Some(params))
+            .reached_through(&req),
+    )
+}
+
+/// `target` of a logout query if it is a local path or an app URL of [`ForwardAuth`], other
+/// targets are ignored.
+fn logout_target(req: &HttpRequest, target: Option<&str>) -> Option<String> {
+    return_target(req, target?).map(str::to_string)
+}
+
+/// Ends the session of this app and of the provider. `?redirect=/path` sends the user there
+/// afterwards, see [`OpenID::get_logout_uri_with_redirect`].
+pub(crate) async fn logout_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+) -> actix_web::Result<HttpResponse> {
+    let redirect = logout_target(&req, LogoutQuery::of(&req).redirect.as_deref());
+    load_session(&open_id_client, &req).await?;
+    let session = request_session(&open_id_client, &req);
+    if let Some(session) = &session {
+        open_id_client
+            .events()
+            .on_logout(&open_id_client.event_context(Some(req.path())));
+        revoke_session_tokens(&open_id_client, session).await;
+    }
+    let id_token = match session.as_ref().and_then(|session| session.id_token()) {
+        None => {
+            open_id_client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Logout without id token"),
+            );
+            let mut response = HttpResponse::BadRequest()
+                .body(open_id_client.message(MessageKey::MissingIdToken, &[]));
+            remove_session_cookies(&open_id_client, &req, &mut response).await?;
+            return Ok(response);
+        }
+        Some(id_token) => id_token,
+    };
+    let id_token = match IdToken::from_str(id_token) {
+        Ok(id_token) => id_token,
+        Err(err) => {
+            let malformed = OpenIdError::MalformedCookie(
+                open_id_client
+                    .realm()
+                    .cookie_name(AuthCookies::IdToken)
+                    .to_string(),
+            );
+            open_id_client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Logout failed, {}: {}", malformed, err),
+            );
+            let mut response = HttpResponse::BadRequest()
+                .body(open_id_client.message(MessageKey::InvalidIdToken, &[]));
+            remove_session_cookies(&open_id_client, &req, &mut response).await?;
+            return Ok(response);
+        }
+    };
+    let logout_uri =
+        match open_id_client.get_logout_uri_with_redirect(&id_token, redirect.as_deref()) {
+            Some(uri) => Some(uri.to_string()),
+            // The provider cannot end its own session, send the user straight back.
+            None => redirect.or_else(|| {
+                open_id_client
+                    .post_logout_redirect_url()
+                    .map(str::to_string)
+            }),
+        };
+    let mut response = match logout_uri {
+        Some(logout_uri) => HttpResponse::Found()
+            .append_header((LOCATION, logout_uri))
+            .finish(),
+        None => logged_out_page(&open_id_client),
+    };
+    // Removed before the provider is asked, the session must not outlive a failed logout there.
+    remove_session_cookies(&open_id_client, &req, &mut response).await?;
+    Ok(response)
+}
+
+/// Revokes the refresh token of the ending `session`, and its access token if configured, for
+/// intercepted tokens not to outlive the logout. Failures are logged, the logout goes on.
+async fn revoke_session_tokens(client: &OpenID, session: &StoredSession) {
+    let refresh_token = session.refresh_token();
+    let access_token = client
+        .revokes_access_token()
+        .then(|| session.access_token());
+    let tokens = [
+        refresh_token.map(|token| (token.secret().clone(), TokenTypeHint::RefreshToken)),
+        access_token.map(|token| (token.secret().clone(), TokenTypeHint::AccessToken)),
+    ];
+    for (token, hint) in tokens.into_iter().flatten() {
+        if let Err(err) = client.revoke_token(&token, hint).await {
+            client.log_policy().log_error(
+                LogCategory::IdpError,
+                format_args!("Could not revoke the {} at logout: {}", hint.as_str(), err),
+            );
+        }
     }
 }
 
-#[derive(Clone)]
-pub struct AuthenticatedUser {
-    pub access: UserInfoClaims<EmptyAdditionalClaims, CoreGenderClaim>,
+/// Ends the session of this app only, the user stays signed in at the provider.
+pub(crate) async fn local_logout_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+) -> actix_web::Result<HttpResponse> {
+    load_session(&open_id_client, &req).await?;
+    if request_session(&open_id_client, &req).is_some() {
+        open_id_client
+            .events()
+            .on_logout(&open_id_client.event_context(Some(req.path())));
+    }
+    let mut response = match open_id_client.post_logout_redirect_url() {
+        Some(url) => HttpResponse::Found()
+            .append_header((LOCATION, url.to_string()))
+            .finish(),
+        None => logged_out_page(&open_id_client),
+    };
+    remove_session_cookies(&open_id_client, &req, &mut response).await?;
+    Ok(response)
 }
 
-#[derive(Clone, Debug, derive_more::Error)]
-enum AuthError {
-    NotAuthenticated { issuer_url: String, nonce: String },
+/// Where the provider can send the user back after ending its session, set the post logout
+/// redirect url to it. Removes what is left of the session, then redirects to the target the
+/// logout was asked for in the `state`, or shows the logged out page.
+pub(crate) async fn post_logout_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+) -> actix_web::Result<HttpResponse> {
+    let mut response = match logout_target(&req, LogoutQuery::of(&req).state.as_deref()) {
+        Some(target) => HttpResponse::Found()
+            .append_header((LOCATION, target))
+            .finish(),
+        None => logged_out_page(&open_id_client),
+    };
+    remove_session_cookies(&open_id_client, &req, &mut response).await?;
+    Ok(response)
 }
 
-impl Display for AuthError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AuthError::NotAuthenticated {
-                issuer_url: _issuer_url,
-                nonce: _nonce,
-            } => {
-                write!(f, "Not authenticated")
-            }
-        }
-    }
+fn logged_out_page(client: &OpenID) -> HttpResponse {
+    render_page(
+        client,
+        PageKind::LoggedOut,
+        StatusCode::OK,
+        &client.message(MessageKey::LoggedOut, &[]),
+        Some("/"),
+        None,
+    )
 }
 
-impl error::ResponseError for AuthError {
-    fn status_code(&self) -> StatusCode {
-        match *self {
-            AuthError::NotAuthenticated { .. } => StatusCode::FOUND,
-        }
+/// Removes every cookie of the client, the stored session and the session's cached claims. Only
+/// this realm's session ends, other clients on the domain keep theirs.
+pub(crate) async fn remove_session_cookies(
+    client: &OpenID,
+    req: &HttpRequest,
+    response: &mut HttpResponse,
+) -> actix_web::Result<()> {
+    load_session(client, req).await?;
+    if let Some(session) = request_session(client, req) {
+        client.forget_user_claims(&session.access_token());
     }
-
-    fn error_response(&self) -> HttpResponse<BoxBody> {
-        let mut resp = HttpResponse::build(self.status_code()).body(self.to_string());
-        match self {
-            AuthError::NotAuthenticated { issuer_url, nonce } => {
-                resp.add_cookie(&Cookie::build(AuthCookies::Nonce.to_string(), nonce)
-                    .path("/")
-                    .finish()
-                ).unwrap();
-                resp.headers_mut()
-                    .insert(LOCATION, HeaderValue::from_str(issuer_url).unwrap());
-                resp
-            }
+    let session_id = client.auth_cookie(req, AuthCookies::SessionId);
+    #[cfg(feature = "first-party-session")]
+    let session_id = session_id.or_else(|| first_party::session_id(client, req));
+    if let (Some(store), Some(session_id)) = (client.session_store(), session_id) {
+        store.remove(&session_id).await.inspect_err(|err| {
+            client.log_policy().log_error(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Could not remove the session: {}", err),
+            );
+        })?;
+    }
+    for cookie in AuthCookies::ALL {
+        response
+            .add_cookie(&removal_cookie(client, cookie))
+            .map_err(OpenIdError::from)?;
+    }
+    let realm = client.realm();
+    for chunk in user_info_cookie_names(client, req) {
+        if chunk != realm.cookie_name(AuthCookies::UserInfo) {
+            response
+                .add_cookie(&client.cookie_config().removal(realm, &chunk))
+                .map_err(OpenIdError::from)?;
         }
     }
+    Ok(())
 }
 
-pub struct OpenIdMiddleware<S> {
-    openid_client: Arc<OpenID>,
-    service: Rc<S>,
-    should_auth: fn(&ServiceRequest) -> bool,
+/// `state` as the target of a page, if it is a path on this host; it comes from the query, a
+/// link to anywhere else would be an open redirect. Browsers drop tabs and newlines from URLs,
+/// `/\t/evil.com` would become `//evil.com`, so whitespace and control characters are refused too.
+pub(crate) fn local_path(state: &str) -> Option<&str> {
+    let unsafe_char = |c: char| c == '\\' || c.is_whitespace() || c.is_control();
+    (state.starts_with('/') && !state.starts_with("//") && !state.contains(unsafe_char))
+        .then_some(state)
 }
 
-impl<S> OpenIdMiddleware<S> {}
-
-impl<S, B> Service<ServiceRequest> for OpenIdMiddleware<S>
-where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
-{
-    type Response = ServiceResponse<B>;
-    type Error = Error;
-    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
-
-    forward_ready!(service);
-
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        let srv = self.service.clone();
-        let client = self.openid_client.clone();
-        let client2 = self.openid_client.clone();
-        let should_auth = self.should_auth;
-        let path = req.path().to_string();
-
-        let redirect_to_auth = move || -> AuthError {
-            let url = client2.get_authorization_url(path.clone());
-            AuthError::NotAuthenticated {
-                issuer_url: url.url.to_string(),
-                nonce: url.nonce.secret().to_string(),
-            }
-        };
+/// Where a `state` parameter returns to, with or without its CSRF token: a local path, or an
+/// URL of the apps the [`ForwardAuth`] endpoint decides for.
+fn return_target<'a>(req: &HttpRequest, state: &'a str) -> Option<&'a str> {
+    let target = split_login_state(state).map_or(state, |(_, target)| target);
+    let app_url = req
+        .app_data::<web::Data<ForwardAuth>>()
+        .is_some_and(|forward_auth| forward_auth.is_app_url(target));
+    local_path(target).or(app_url.then_some(target))
+}
 
-        Box::pin(async move {
-            let auth_user = match req.cookie(AuthCookies::AccessToken.to_string().as_str()) {
-                None => if should_auth(&req) {
-                    // Auth is not optional
-                    return Err(redirect_to_auth().into())
-                } else {
-                    Err(redirect_to_auth())
-                },
-                Some(token) => {
-                    let auth_user = client
-                        .user_info(AccessToken::new(token.value().to_string()))
-                        .await
-                        .map_err(|_| redirect_to_auth())
-                        .map(|user_info| AuthenticatedUser { access: user_info });
-                    if auth_user.is_err() && should_auth(&req) {
-                        return Err(redirect_to_auth().into());
-                    }
+/// Compares secrets without revealing through timing how much of them matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
 
-                    auth_user
-                }
-            };
-            req.extensions_mut().insert(auth_user);
-            srv.call(req).await
-        })
-    }
+/// Renders the page `kind` with the client's [`PageRenderer`](crate::PageRenderer).
+pub(crate) fn render_page(
+    client: &OpenID,
+    kind: PageKind,
+    status: StatusCode,
+    message: &str,
+    target_url: Option<&str>,
+    error: Option<&OpenIdError>,
+) -> HttpResponse {
+    let csp_nonce = client.random_token();
+    page_response(
+        client,
+        kind,
+        &PageContext {
+            status,
+            message,
+            target_url,
+            error,
+            providers: &[],
+            csp_nonce: &csp_nonce,
+        },
+    )
 }
 
-pub struct AuthenticateMiddlewareFactory {
-    client: Arc<OpenID>,
-    should_auth: fn(&ServiceRequest) -> bool,
+/// Renders the page `kind` showing `context`, whose nonce must be fresh.
+pub(crate) fn page_response(
+    client: &OpenID,
+    kind: PageKind,
+    context: &PageContext<'_>,
+) -> HttpResponse {
+    let page = client.pages().render(kind, context);
+    let content_security_policy = page
+        .content_security_policy
+        .unwrap_or_else(|| default_content_security_policy(context.csp_nonce));
+    HttpResponse::build(context.status)
+        .insert_header((CONTENT_TYPE, page.content_type))
+        .insert_header((CONTENT_SECURITY_POLICY, content_security_policy))
+        .body(page.body)
 }
 
-impl AuthenticateMiddlewareFactory {
-    pub(crate) fn new(client: Arc<OpenID>, should_auth: fn(&ServiceRequest) -> bool) -> Self {
-        AuthenticateMiddlewareFactory {
+/// Answers the provider sending the user back with an error, as the client's
+/// [`CallbackErrorHandling`] says.
+fn provider_error_response(
+    req: &HttpRequest,
+    client: &OpenID,
+    provider_error: ProviderError,
+    return_path: Option<&str>,
+) -> HttpResponse {
+    // Users cancelling the login is no failure worth a warning.
+    let (category, message) = match provider_error.error.as_str() {
+        "access_denied" => (
+            LogCategory::UnauthenticatedRequest,
+            MessageKey::LoginCancelled,
+        ),
+        _ => (LogCategory::LoginFailure, MessageKey::AuthenticationFailed),
+    };
+    client.log_policy().log(
+        category,
+        format_args!(
+            "The provider answered the login with {}",
+            provider_error.error
+        ),
+    );
+    match client.callback_errors() {
+        CallbackErrorHandling::Page => render_page(
             client,
-            should_auth,
+            PageKind::CallbackError,
+            StatusCode::BAD_REQUEST,
+            &client.message(message, &[]),
+            return_path,
+            Some(&OpenIdError::Authorization(provider_error)),
+        ),
+        CallbackErrorHandling::Redirect(route) => {
+            let mut query = url::form_urlencoded::Serializer::new(String::new());
+            query.append_pair("error", &provider_error.error);
+            if let Some(description) = &provider_error.error_description {
+                query.append_pair("error_description", description);
+            }
+            if let Some(uri) = &provider_error.error_uri {
+                query.append_pair("error_uri", uri);
+            }
+            if let Some(return_path) = return_path {
+                query.append_pair("next", return_path);
+            }
+            let separator = if route.contains('?') { '&' } else { '?' };
+            HttpResponse::Found()
+                .append_header((
+                    LOCATION,
+                    format!("{}{}{}", route, separator, query.finish()),
+                ))
+                .finish()
         }
+        CallbackErrorHandling::Handler(handler) => handler(req, &provider_error),
     }
 }
 
-impl<S, B> Transform<S, ServiceRequest> for AuthenticateMiddlewareFactory
-where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
-{
-    type Response = ServiceResponse<B>;
-    type Error = Error;
-    type Transform = OpenIdMiddleware<S>;
-    type InitError = ();
-    type Future = Ready<Result<Self::Transform, Self::InitError>>;
-
-    fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(OpenIdMiddleware {
-            openid_client: self.client.clone(),
-            service: Rc::new(service),
-            should_auth: self.should_auth,
-        }))
-    }
+pub(crate) async fn auth_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+    query: web::Query<AuthQuery>,
+) -> actix_web::Result<HttpResponse> {
+    complete_login(req, open_id_client, query.into_inner()).await
 }
 
-#[derive(Deserialize)]
-struct AuthQuery {
-    code: String,
-    state: String,
+/// The callback of logins with [`response_mode_form_post`](crate::OpenIdBuilder::response_mode_form_post),
+/// the provider POSTs the code in a form.
+pub(crate) async fn auth_form_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+    form: web::Form<AuthQuery>,
+) -> actix_web::Result<HttpResponse> {
+    complete_login(req, open_id_client, form.into_inner()).await
 }
 
-#[get("/logout")]
-async fn logout_endpoint(
+/// Completes the login the provider sent the user back from with `query`. Whether it succeeded
+/// or not, the login is over and the cookies it was checked with are removed, they are
+/// single-use.
+async fn complete_login(
     req: HttpRequest,
-    open_id_client: web::Data<Arc<OpenID>>,
+    open_id_client: RegisteredClient,
+    query: AuthQuery,
 ) -> actix_web::Result<HttpResponse> {
-    let id_token = match req.cookie(AuthCookies::IdToken.to_string().as_str()) {
-        None => {
-            log::debug!("No id token, redirecting to auth");
-            return Err(error::ErrorBadRequest("missing id token"));
-        }
-        Some(id) => id.value().to_string(),
+    let attempt = query
+        .state
+        .as_deref()
+        .and_then(split_login_state)
+        .map(|(csrf_token, _)| login_attempt(csrf_token).to_string());
+    let client = open_id_client.0.clone();
+    // Errors too, e.g. the redirect of a login started again, carry the removals.
+    let mut response = match check_login(req.clone(), open_id_client, query).await {
+        Ok(response) => response,
+        Err(err) => err.error_response(),
     };
-    let logout_uri = open_id_client.get_logout_uri(&IdToken::from_str(id_token.as_str()).unwrap());
-    let mut response = HttpResponse::Found();
-    response.append_header((LOCATION, logout_uri.to_string()));
-    Ok(response.finish())
+    for removal in login_cookie_removals(&client, &req, attempt.as_deref()) {
+        if response.cookies().any(|set| set.name() == removal.name()) {
+            continue;
+        }
+        response.add_cookie(&removal).map_err(OpenIdError::from)?;
+    }
+    Ok(response)
 }
 
-#[get("/auth_callback")]
-async fn auth_endpoint(
+/// The response to the callback of the login the provider sent the user back from with `query`.
+async fn check_login(
     req: HttpRequest,
-    open_id_client: web::Data<Arc<OpenID>>,
-    query: web::Query<AuthQuery>,
+    open_id_client: RegisteredClient,
+    query: AuthQuery,
 ) -> actix_web::Result<HttpResponse> {
-    let nonce = match req.cookie(AuthCookies::Nonce.to_string().as_str()) {
-        None => {
-            log::debug!("No nonce, redirecting to auth");
-            return Err(error::ErrorBadRequest("No nonce"));
+    let state = query.state.as_deref().unwrap_or_default();
+    let return_path = return_target(&req, state);
+    let context = open_id_client.event_context(return_path);
+    let failed = |reason| {
+        open_id_client
+            .events()
+            .on_callback_failure(&context, reason)
+    };
+    let code = match (&query.code, query.error.as_deref()) {
+        (Some(code), _) => code,
+        (None, Some(error)) if SILENT_LOGIN_ERRORS.contains(&error) => {
+            open_id_client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Silent login failed with {}, continuing anonymously", error),
+            );
+            return Ok(HttpResponse::Found()
+                .append_header((LOCATION, return_path.unwrap_or("/")))
+                .cookie(sso_checked_cookie(&open_id_client))
+                .finish());
+        }
+        (None, Some(error)) => {
+            let provider_error = ProviderError {
+                error: error.to_string(),
+                error_description: query.error_description.clone(),
+                error_uri: query.error_uri.clone(),
+            };
+            failed(CallbackFailure::ProviderError);
+            return Ok(provider_error_response(
+                &req,
+                &open_id_client,
+                provider_error,
+                return_path,
+            ));
+        }
+        (None, None) => {
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Callback without code"),
+            );
+            failed(CallbackFailure::MissingCode);
+            return Ok(render_page(
+                &open_id_client,
+                PageKind::CallbackError,
+                StatusCode::BAD_REQUEST,
+                &open_id_client.message(MessageKey::AuthenticationFailed, &[]),
+                return_path,
+                None,
+            ));
+        }
+    };
+    // The login cookies are named after the attempt, the start of the state's CSRF token.
+    let attempt = split_login_state(state).map(|(csrf_token, _)| login_attempt(csrf_token));
+    let login_cookie =
+        |cookie| attempt.and_then(|attempt| open_id_client.login_cookie(&req, cookie, attempt));
+    let nonce = login_cookie(AuthCookies::Nonce);
+    let nonce = nonce.as_deref().and_then(parse_nonce_cookie);
+    let nonce = match nonce {
+        Some((started, nonce)) if !nonce.is_empty() => {
+            let taken = open_id_client
+                .now()
+                .duration_since(started)
+                .unwrap_or_default();
+            if taken > open_id_client.login_window() {
+                open_id_client.log_policy().log(
+                    LogCategory::LoginFailure,
+                    format_args!("Callback of a login started {}s ago", taken.as_secs()),
+                );
+                failed(CallbackFailure::ExpiredLogin);
+                return Ok(render_page(
+                    &open_id_client,
+                    PageKind::CallbackError,
+                    StatusCode::BAD_REQUEST,
+                    &open_id_client.message(MessageKey::LoginExpired, &[]),
+                    return_path,
+                    None,
+                ));
+            }
+            nonce.to_string()
         }
-        Some(n) => n.value().to_string(),
+        _ => {
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Callback without nonce"),
+            );
+            failed(CallbackFailure::MissingNonce);
+            return Ok(render_page(
+                &open_id_client,
+                PageKind::CallbackError,
+                StatusCode::BAD_REQUEST,
+                &open_id_client.message(MessageKey::MissingNonce, &[]),
+                return_path,
+                None,
+            ));
+        }
+    };
+    let csrf_token = login_cookie(AuthCookies::State);
+    let state_matches = match (&csrf_token, split_login_state(state)) {
+        (Some(expected), Some((csrf_token, _))) => {
+            constant_time_eq(expected.as_bytes(), csrf_token.as_bytes())
+        }
+        _ => false,
     };
+    if !state_matches {
+        open_id_client.log_policy().log(
+            LogCategory::LoginFailure,
+            format_args!("Callback with a state not issued to this browser"),
+        );
+        failed(CallbackFailure::InvalidState);
+        return Ok(render_page(
+            &open_id_client,
+            PageKind::CallbackError,
+            StatusCode::BAD_REQUEST,
+            &open_id_client.message(MessageKey::InvalidState, &[]),
+            None,
+            None,
+        ));
+    }
 
+    let pkce_verifier = login_cookie(AuthCookies::PkceVerifier).map(PkceCodeVerifier::new);
     let tkn = match open_id_client
-        .get_token(AuthorizationCode::new(query.code.to_string()))
+        .exchange_code_at(
+            AuthorizationCode::new(code.to_string()),
+            pkce_verifier,
+            open_id_client.forwarded_redirect_url(&req).as_ref(),
+        )
         .await
     {
         Ok(tkn) => tkn,
         Err(e) => {
-            log::warn!("Error getting token: {}", e);
-            return Ok(HttpResponse::BadRequest().body(e.to_string()));
+            let action = open_id_client.error_action(&e);
+            let category = match action {
+                ErrorAction::RetryLater(_) => LogCategory::IdpError,
+                _ => LogCategory::LoginFailure,
+            };
+            open_id_client
+                .log_policy()
+                .log(category, format_args!("Error getting token: {}", e));
+            failed(CallbackFailure::TokenExchange);
+            return match action {
+                ErrorAction::Reauthenticate => Err(AuthenticationRequired::new(
+                    &open_id_client.0,
+                    return_path.unwrap_or("/"),
+                    Some(e),
+                )
+                .into()),
+                ErrorAction::RetryLater(retry_after) => {
+                    let mut response = render_page(
+                        &open_id_client,
+                        PageKind::CallbackError,
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        &open_id_client.message(
+                            MessageKey::IdpUnavailable,
+                            &[("retry_after", retry_after.as_secs().to_string())],
+                        ),
+                        return_path,
+                        Some(&e),
+                    );
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+                    Ok(response)
+                }
+                ErrorAction::InternalError => Ok(render_page(
+                    &open_id_client,
+                    PageKind::CallbackError,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &open_id_client.message(MessageKey::Misconfigured, &[]),
+                    None,
+                    Some(&e),
+                )),
+                ErrorAction::BadRequest => Ok(render_page(
+                    &open_id_client,
+                    PageKind::CallbackError,
+                    StatusCode::BAD_REQUEST,
+                    &open_id_client.message(MessageKey::AuthenticationFailed, &[]),
+                    return_path,
+                    Some(&e),
+                )),
+            };
         }
     };
     let claim = match open_id_client.verify_id_token(&tkn.id_token, nonce).await {
         Ok(claim) => claim,
         Err(e) => {
-            log::warn!("Error verifying id token: {}", e);
-            return Err(error::ErrorInternalServerError("invalid id token"));
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Error verifying id token: {}", e),
+            );
+            failed(CallbackFailure::InvalidIdToken);
+            return Ok(render_page(
+                &open_id_client,
+                PageKind::CallbackError,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &open_id_client.message(MessageKey::InvalidIdToken, &[]),
+                None,
+                Some(&e),
+            ));
         }
     };
-    let mut response = HttpResponse::Found();
-    response
-        .append_header((LOCATION, query.state.to_string()))
-        .cookie(
-            Cookie::build(
-                AuthCookies::AccessToken.to_string(),
-                tkn.access_token.secret(),
-            )
-            .same_site(SameSite::Lax)
-            .secure(true)
-            .finish(),
+    let code = AuthorizationCode::new(code.to_string());
+    if let Err(e) =
+        open_id_client.verify_token_hashes(&tkn.id_token, claim, &tkn.access_token, Some(&code))
+    {
+        open_id_client.log_policy().log(
+            LogCategory::LoginFailure,
+            format_args!("Error verifying the token hashes: {}", e),
+        );
+        failed(CallbackFailure::InvalidIdToken);
+        return Ok(render_page(
+            &open_id_client,
+            PageKind::CallbackError,
+            StatusCode::BAD_REQUEST,
+            &open_id_client.message(MessageKey::InvalidIdToken, &[]),
+            None,
+            Some(&e),
+        ));
+    }
+    let session_limits = open_id_client.session_limits();
+    let session_times = session_limits.is_enabled().then(|| {
+        SessionTimes::login(
+            claim.auth_time().map(SystemTime::from),
+            open_id_client.now(),
         )
-        .cookie(
-            Cookie::build::<String, String>(
-                AuthCookies::UserInfo.to_string(),
-                serde_json::to_string(claim).unwrap(),
-            )
-            .same_site(SameSite::Lax)
-            .finish(),
+    });
+    if let Some(limit) =
+        session_times.and_then(|times| session_limits.exceeded(Some(&times), open_id_client.now()))
+    {
+        let e = OpenIdError::SessionExpired(limit);
+        open_id_client.log_policy().log(
+            LogCategory::LoginFailure,
+            format_args!(
+                "Refusing the login of subject {}: {}",
+                claim.subject().as_str(),
+                e
+            ),
+        );
+        failed(CallbackFailure::StaleLogin);
+        return Ok(render_page(
+            &open_id_client,
+            PageKind::CallbackError,
+            StatusCode::UNAUTHORIZED,
+            &open_id_client.message(MessageKey::StaleLogin, &[]),
+            return_path,
+            Some(&e),
+        ));
+    }
+    let essential = login_cookie(AuthCookies::EssentialClaims)
+        .and_then(|claims| PayloadCodec::decode::<EssentialClaims>(&claims).ok())
+        .unwrap_or_else(|| open_id_client.claims_request().essential_claims());
+    if let Err(e) = open_id_client
+        .check_essential_claims(&essential, &tkn)
+        .await
+    {
+        open_id_client.log_policy().log(
+            LogCategory::LoginFailure,
+            format_args!("Error checking the essential claims: {}", e),
+        );
+        failed(CallbackFailure::MissingClaims);
+        let (status, message, args) = match (&e, open_id_client.error_action(&e)) {
+            (OpenIdError::MissingClaims(_), _) => {
+                (StatusCode::FORBIDDEN, MessageKey::MissingClaims, Vec::new())
+            }
+            (_, ErrorAction::RetryLater(retry_after)) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                MessageKey::IdpUnavailable,
+                vec![("retry_after", retry_after.as_secs().to_string())],
+            ),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                MessageKey::InternalError,
+                Vec::new(),
+            ),
+        };
+        return Ok(render_page(
+            &open_id_client,
+            PageKind::CallbackError,
+            status,
+            &open_id_client.message(message, &args),
+            None,
+            Some(&e),
+        ));
+    }
+    let subject = claim.subject().to_string();
+    let session_data = match open_id_client.login_hook() {
+        Some(hook) => {
+            let claims = match open_id_client.id_token_claims(&tkn.id_token.to_string()) {
+                Ok(claims) => claims,
+                Err(err) => {
+                    failed(CallbackFailure::Internal);
+                    return Ok(internal_error(
+                        &open_id_client,
+                        "cannot read the claims of the ID token",
+                        err,
+                    ));
+                }
+            };
+            match hook.on_login(&claims, &tkn).await {
+                LoginDecision::Allow => None,
+                LoginDecision::AllowWith(data) => Some(data),
+                LoginDecision::Deny(message) => {
+                    open_id_client.log_policy().log(
+                        LogCategory::LoginFailure,
+                        format_args!("The login hook refused the login of subject {}", subject),
+                    );
+                    failed(CallbackFailure::Denied);
+                    return Ok(render_page(
+                        &open_id_client,
+                        PageKind::CallbackError,
+                        StatusCode::FORBIDDEN,
+                        &message,
+                        None,
+                        None,
+                    ));
+                }
+            }
+        }
+        None => None,
+    };
+    let max_age = open_id_client.cookie_config().session_max_age(
+        tkn.expires_in,
+        tkn.refresh_token.is_some(),
+        tkn.refresh_expires_in,
+    );
+    let mut response = HttpResponse::Found();
+    response.append_header((LOCATION, return_path.unwrap_or("/")));
+    if let Some(store) = open_id_client.session_store() {
+        // Every claim of the verified token, also those the verifier has no type for, e.g. `sid`.
+        let claims = match jwt_payload(&tkn.id_token.to_string()) {
+            Ok(claims) => claims,
+            Err(err) => {
+                failed(CallbackFailure::Internal);
+                return Ok(internal_error(
+                    &open_id_client,
+                    "cannot read the claims of the ID token",
+                    err,
+                ));
+            }
+        };
+        let session = StoredSession::new(
+            &tkn,
+            claims,
+            session_data,
+            open_id_client.now(),
+            stored_max_age(max_age),
         )
-        .cookie(
-            Cookie::build::<String, String>(
-                AuthCookies::IdToken.to_string(),
-                tkn.id_token.to_string(),
-            )
-            .same_site(SameSite::Lax)
-            .secure(true)
-            .finish(),
+        .with_times(session_times);
+        // Every login gets a new id, an id planted in the browser before never names a session.
+        let session_id = open_id_client.random_token();
+        #[cfg(feature = "first-party-session")]
+        let first_party_redirect = match open_id_client.session_format() {
+            SessionFormat::FirstPartyJwt { key, ttl } => {
+                match first_party::issue(&open_id_client, key, *ttl, &session_id, session.claims())
+                {
+                    Ok(token) => Some(first_party::login_redirect(
+                        return_path.unwrap_or("/"),
+                        &token,
+                        *ttl,
+                    )),
+                    Err(err) => {
+                        failed(CallbackFailure::Internal);
+                        return Ok(internal_error(
+                            &open_id_client,
+                            "cannot issue the session token",
+                            err,
+                        ));
+                    }
+                }
+            }
+            SessionFormat::Cookies => None,
+        };
+        if let Err(err) = store.insert(&session_id, session).await {
+            failed(CallbackFailure::Internal);
+            return Ok(internal_error(
+                &open_id_client,
+                "cannot store the session",
+                err,
+            ));
+        }
+        if let Some(previous) = open_id_client.auth_cookie(&req, AuthCookies::SessionId) {
+            if let Err(err) = store.remove(&previous).await {
+                open_id_client.log_policy().log_error(
+                    LogCategory::LoginSuccess,
+                    format_args!("Could not remove the previous session: {}", err),
+                );
+            }
+        }
+        open_id_client.log_policy().log(
+            LogCategory::LoginSuccess,
+            format_args!("Login succeeded for subject {}", subject),
         );
+        open_id_client.events().on_callback_success(&context);
+        // The front end keeps the session token, the browser gets no session cookie.
+        #[cfg(feature = "first-party-session")]
+        if let Some(location) = first_party_redirect {
+            response.insert_header((LOCATION, location));
+            return Ok(response.finish());
+        }
+        response.cookie(token_cookie(
+            &open_id_client,
+            AuthCookies::SessionId,
+            session_id,
+            max_age,
+        ));
+        return Ok(response.finish());
+    }
+    let user_info = match user_info_cookies(&open_id_client, &req, claim, max_age) {
+        Ok(user_info) => user_info,
+        Err(err) => {
+            failed(CallbackFailure::Internal);
+            return Ok(internal_error(
+                &open_id_client,
+                "cannot serialize the user info",
+                err,
+            ));
+        }
+    };
+    let session_data = match session_data
+        .map(|data| open_id_client.payload_codec().encode(&data))
+        .transpose()
+    {
+        Ok(session_data) => session_data,
+        Err(err) => {
+            failed(CallbackFailure::Internal);
+            return Ok(internal_error(
+                &open_id_client,
+                "cannot serialize the session data",
+                err,
+            ));
+        }
+    };
+    open_id_client.log_policy().log(
+        LogCategory::LoginSuccess,
+        format_args!("Login succeeded for subject {}", subject),
+    );
+    open_id_client.events().on_callback_success(&context);
+    response
+        .cookie(token_cookie(
+            &open_id_client,
+            AuthCookies::AccessToken,
+            tkn.access_token.secret().to_string(),
+            max_age,
+        ))
+        .cookie(token_cookie(
+            &open_id_client,
+            AuthCookies::IdToken,
+            tkn.id_token.to_string(),
+            max_age,
+        ));
+    for cookie in user_info {
+        response.cookie(cookie);
+    }
+    match session_data {
+        Some(session_data) => {
+            response.cookie(token_cookie(
+                &open_id_client,
+                AuthCookies::SessionData,
+                session_data,
+                max_age,
+            ));
+        }
+        // The data of an earlier login is not the user's anymore.
+        None if req
+            .cookie(open_id_client.realm().cookie_name(AuthCookies::SessionData))
+            .is_some() =>
+        {
+            response.cookie(removal_cookie(&open_id_client, AuthCookies::SessionData));
+        }
+        None => {}
+    }
+    // Neither are the expiry and refresh token of an earlier login, when this one has none.
+    let earlier = |cookie: AuthCookies| {
+        req.cookie(open_id_client.realm().cookie_name(cookie))
+            .is_some()
+    };
+    match tkn.expires_in {
+        Some(expires_in) => {
+            response.cookie(expires_at_cookie(
+                &open_id_client,
+                open_id_client.now() + expires_in,
+                max_age,
+            ));
+        }
+        None if earlier(AuthCookies::ExpiresAt) => {
+            response.cookie(removal_cookie(&open_id_client, AuthCookies::ExpiresAt));
+        }
+        None => {}
+    }
     match tkn.refresh_token {
-        Some(refresh_token) => Ok(response
-            .cookie(
-                Cookie::build(
-                    AuthCookies::RefreshToken.to_string(),
-                    refresh_token.secret(),
-                )
-                .same_site(SameSite::Lax)
-                .secure(true)
-                .finish(),
-            )
-            .finish()),
-        None => Ok(response.finish()),
+        Some(refresh_token) => {
+            response.cookie(token_cookie(
+                &open_id_client,
+                AuthCookies::RefreshToken,
+                refresh_token.secret().to_string(),
+                max_age,
+            ));
+        }
+        None if earlier(AuthCookies::RefreshToken) => {
+            response.cookie(removal_cookie(&open_id_client, AuthCookies::RefreshToken));
+        }
+        None => {}
+    }
+    if let Some(times) = session_times {
+        response.cookie(session_times_cookie(&open_id_client, &times));
+    }
+    Ok(response.finish())
+}
+
+/// Bytes of a chunk of the `user_info` cookie, below 4KB with the attributes also when sealed.
+const USER_INFO_CHUNK: usize = 2800;
+
+/// The name of the `index`th chunk of the `user_info` cookie `name`.
+pub(crate) fn user_info_chunk(name: &str, index: usize) -> String {
+    format!("{}.{}", name, index)
+}
+
+/// The `user_info` cookies of a login with `claims` as configured with
+/// [`OpenIdBuilder::user_info_cookie`](crate::OpenIdBuilder::user_info_cookie), and the
+/// removals of those of `req` they do not replace.
+fn user_info_cookies(
+    client: &OpenID,
+    req: &HttpRequest,
+    claims: &impl Serialize,
+    max_age: Option<CookieDuration>,
+) -> Result<Vec<Cookie<'static>>, PayloadError> {
+    let realm = client.realm();
+    let name = realm.cookie_name(AuthCookies::UserInfo);
+    let mut cookies = match client.user_info_cookie() {
+        UserInfoCookie::Disabled => Vec::new(),
+        UserInfoCookie::Plain => vec![token_cookie(
+            client,
+            AuthCookies::UserInfo,
+            client.payload_codec().encode(claims)?,
+            max_age,
+        )],
+        UserInfoCookie::Chunked => {
+            let codec = match client.payload_codec() {
+                PayloadCodec::Json => PayloadCodec::DeflateJson,
+                codec => codec,
+            };
+            // Payloads are ASCII, they split anywhere.
+            let payload = codec.encode(claims)?;
+            payload
+                .as_bytes()
+                .chunks(USER_INFO_CHUNK)
+                .enumerate()
+                .map(|(index, chunk)| {
+                    client.seal_cookie(client.cookie_config().session_cookie(
+                        realm,
+                        &user_info_chunk(name, index),
+                        String::from_utf8_lossy(chunk).into_owned(),
+                        max_age,
+                    ))
+                })
+                .collect()
+        }
+    };
+    // Chunks left from a larger earlier login would be joined to the new ones.
+    let stale = user_info_cookie_names(client, req)
+        .filter(|stale| cookies.iter().all(|cookie| cookie.name() != stale))
+        .map(|stale| client.cookie_config().removal(realm, &stale))
+        .collect::<Vec<_>>();
+    cookies.extend(stale);
+    Ok(cookies)
+}
+
+/// The names of the `user_info` cookies `req` carries, plain and chunked.
+fn user_info_cookie_names<'a>(
+    client: &'a OpenID,
+    req: &HttpRequest,
+) -> impl Iterator<Item = String> + 'a {
+    let name = client.realm().cookie_name(AuthCookies::UserInfo);
+    let cookies = req
+        .cookies()
+        .map(|cookies| cookies.clone())
+        .unwrap_or_default();
+    cookies
+        .into_iter()
+        .map(|cookie| cookie.name().to_string())
+        .filter(move |cookie| {
+            cookie == name
+                || cookie
+                    .strip_prefix(name)
+                    .and_then(|index| index.strip_prefix('.'))
+                    .is_some_and(|index| index.parse::<usize>().is_ok())
+        })
+}
+
+/// A session cookie, kept by the browser for `max_age` or until it is closed.
+fn token_cookie(
+    client: &OpenID,
+    name: AuthCookies,
+    value: String,
+    max_age: Option<CookieDuration>,
+) -> Cookie<'static> {
+    let realm = client.realm();
+    client.seal_cookie(client.cookie_config().session_cookie(
+        realm,
+        realm.cookie_name(name),
+        value,
+        max_age,
+    ))
+}
+
+/// The cookie recording the session's `times`, kept until the session outlives a limit.
+fn session_times_cookie(client: &OpenID, times: &SessionTimes) -> Cookie<'static> {
+    let max_age = client.session_limits().cookie_max_age(times, client.now());
+    token_cookie(
+        client,
+        AuthCookies::SessionTimes,
+        times.cookie_value(),
+        CookieDuration::try_from(max_age).ok(),
+    )
+}
+
+fn expires_at_cookie(
+    client: &OpenID,
+    expires_at: SystemTime,
+    max_age: Option<CookieDuration>,
+) -> Cookie<'static> {
+    let expires_at = expires_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    token_cookie(
+        client,
+        AuthCookies::ExpiresAt,
+        expires_at.as_secs().to_string(),
+        max_age,
+    )
+}
+
+/// Replaces the session cookies with tokens refreshed while handling the request, keeping the
+/// `session_data` for as long as them.
+fn set_refreshed_cookies<B>(
+    response: &mut HttpResponse<B>,
+    client: &OpenID,
+    tokens: &RefreshedTokens,
+    previous_refresh_token: Option<String>,
+    session_data: Option<String>,
+) -> Result<(), OpenIdError> {
+    // Only sessions with a refresh token are refreshed.
+    let max_age =
+        client
+            .cookie_config()
+            .session_max_age(tokens.expires_in, true, tokens.refresh_expires_in);
+    response.add_cookie(&token_cookie(
+        client,
+        AuthCookies::AccessToken,
+        tokens.access_token.secret().to_string(),
+        max_age,
+    ))?;
+    if let Some(id_token) = &tokens.id_token {
+        response.add_cookie(&token_cookie(
+            client,
+            AuthCookies::IdToken,
+            id_token.to_string(),
+            max_age,
+        ))?;
+    }
+    // Without a new refresh token the previous one is kept, for as long as the other cookies.
+    let refresh_token = tokens
+        .refresh_token
+        .as_ref()
+        .map(|refresh_token| refresh_token.secret().to_string())
+        .or(previous_refresh_token);
+    if let Some(refresh_token) = refresh_token {
+        response.add_cookie(&token_cookie(
+            client,
+            AuthCookies::RefreshToken,
+            refresh_token,
+            max_age,
+        ))?;
+    }
+    if let Some(expires_in) = tokens.expires_in {
+        response.add_cookie(&expires_at_cookie(
+            client,
+            client.now() + expires_in,
+            max_age,
+        ))?;
+    }
+    if let Some(session_data) = session_data {
+        response.add_cookie(&token_cookie(
+            client,
+            AuthCookies::SessionData,
+            session_data,
+            max_age,
+        ))?;
     }
+    Ok(())
 }
 
-pub struct Authenticated(AuthenticatedUser);
+/// The logged in user with their claims read as `AC`, e.g. `Authenticated<MyClaims>` for
+/// `user.access.additional_claims().roles`. Requests whose user lacks claims `AC` requires are
+/// answered with `403 Forbidden`.
+///
+/// The user is read once per request and claims type, then shared with the request extensions
+/// rather than copied out of them.
+pub struct Authenticated<AC: AdditionalClaims = EmptyAdditionalClaims>(Arc<AuthenticatedUser<AC>>);
 
-impl FromRequest for Authenticated {
+impl<AC: AdditionalClaims> Clone for Authenticated<AC> {
+    fn clone(&self) -> Self {
+        Authenticated(self.0.clone())
+    }
+}
+
+impl<AC: AdditionalClaims + Clone> Authenticated<AC> {
+    /// The shared user, e.g. to keep it past the request.
+    pub fn into_inner(self) -> Arc<AuthenticatedUser<AC>> {
+        self.0
+    }
+
+    /// A copy of the user and all of their claims.
+    pub fn to_owned(&self) -> AuthenticatedUser<AC> {
+        AuthenticatedUser::clone(&self.0)
+    }
+}
+
+/// The user of the request read as `AC`, kept for the next extractor asking for the same type.
+struct TypedUser<AC: AdditionalClaims>(Arc<AuthenticatedUser<AC>>);
+
+fn typed_user<AC: AdditionalClaims>(
+    req: &HttpRequest,
+    user: &AuthenticatedUser<OtherClaims>,
+) -> Result<Arc<AuthenticatedUser<AC>>, Error> {
+    if let Some(TypedUser(user)) = req.extensions().get::<TypedUser<AC>>() {
+        return Ok(user.clone());
+    }
+    let typed = user.with_claims::<AC>().map_err(|err| {
+        if let Some(client) = RegisteredClient::find(req) {
+            client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("The user lacks claims the handler requires: {}", err),
+            );
+        }
+        error::ErrorForbidden(RegisteredClient::message_for(
+            req,
+            MessageKey::MissingClaims,
+        ))
+    })?;
+    let typed = Arc::new(typed);
+    req.extensions_mut().insert(TypedUser(typed.clone()));
+    Ok(typed)
+}
+
+impl<AC: AdditionalClaims> FromRequest for Authenticated<AC> {
     type Error = Error;
     type Future = Ready<Result<Self, Self::Error>>;
 
-    fn from_request(
-        req: &HttpRequest,
-        _payload: &mut actix_web::dev::Payload,
-    ) -> Self::Future {
-        let value = req.extensions().get::<Result<AuthenticatedUser, AuthError>>().cloned();
-        ready(match value {
-            Some(Ok(v)) => Ok(Authenticated(v)),
-            Some(Err(e)) => Err(e.into()),
-            None => Err(ErrorUnauthorized("Unauthorized")),
-        })
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        ready(request_user(req).and_then(|user| typed_user(req, &user).map(Authenticated)))
+    }
+}
+
+/// The user the middleware found for the request, failing like [`Authenticated`] without one.
+pub(crate) fn request_user(
+    req: &HttpRequest,
+) -> Result<Arc<AuthenticatedUser<OtherClaims>>, Error> {
+    match req.extensions().get::<AuthContext>().cloned() {
+        Some(AuthContext::Authenticated(user)) => Ok(user),
+        Some(AuthContext::Unauthenticated { required, .. }) => Err(required.into()),
+        None => {
+            if let Some(client) = RegisteredClient::find(req) {
+                client.log_policy().log_error(
+                    LogCategory::UnauthenticatedRequest,
+                    format_args!("{}: {}", MIDDLEWARE_MISSING, req.path()),
+                );
+            }
+            Err(ErrorUnauthorized(MIDDLEWARE_MISSING))
+        }
+    }
+}
+
+impl<AC: AdditionalClaims> std::ops::Deref for Authenticated<AC> {
+    type Target = AuthenticatedUser<AC>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// The logged in user's claims read as `T`, an app's own type, see
+/// [`AuthenticatedUser::claims_as`]. Users lacking a claim `T` requires are answered
+/// `500 Internal Server Error` naming it, the provider is not sending what the app expects.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct MyUser {
+///     #[serde(rename = "sub")]
+///     id: String,
+///     email: String,
+///     #[serde(rename = "name")]
+///     display_name: Option<String>,
+///     #[serde(default)]
+///     roles: Vec<String>,
+/// }
+///
+/// #[get("/profile")]
+/// async fn profile(user: AuthenticatedAs<MyUser>) -> HttpResponse {
+///     HttpResponse::Ok().body(user.email.clone())
+/// }
+/// ```
+pub struct AuthenticatedAs<T>(Arc<T>);
+
+impl<T> AuthenticatedAs<T> {
+    pub fn into_inner(self) -> Arc<T> {
+        self.0
+    }
+}
+
+impl<T> Clone for AuthenticatedAs<T> {
+    fn clone(&self) -> Self {
+        AuthenticatedAs(self.0.clone())
     }
 }
 
-impl std::ops::Deref for Authenticated {
-    type Target = AuthenticatedUser;
+impl<T> std::ops::Deref for AuthenticatedAs<T> {
+    type Target = T;
 
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 
-pub struct MaybeAuthenticated(Result<AuthenticatedUser, AuthError>);
+/// The claims of the request's user read as `T`, kept for the next extractor asking for them.
+struct MappedUser<T>(Arc<T>);
+
+impl<T: DeserializeOwned + 'static> FromRequest for AuthenticatedAs<T> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        if let Some(MappedUser(user)) = req.extensions().get::<MappedUser<T>>() {
+            return ready(Ok(AuthenticatedAs(user.clone())));
+        }
+        let mapped = request_user(req).and_then(|user| {
+            user.claims_as::<T>().map_err(|err| {
+                if let Some(client) = RegisteredClient::find(req) {
+                    client.log_policy().log_error(
+                        LogCategory::ConfigWarning,
+                        format_args!(
+                            "The user's claims do not fit {}: {}",
+                            std::any::type_name::<T>(),
+                            err
+                        ),
+                    );
+                }
+                error::ErrorInternalServerError(err.to_string())
+            })
+        });
+        ready(mapped.map(|user| {
+            let user = Arc::new(user);
+            req.extensions_mut().insert(MappedUser(user.clone()));
+            AuthenticatedAs(user)
+        }))
+    }
+}
+
+/// The logged in user if there is one, with their claims read as `AC` like [`Authenticated`].
+/// Extracting it never fails for a missing login, also on routes without the middleware, nor
+/// for a user lacking claims `AC` requires, who has no [`user`](Self::user) then.
+///
+/// ```ignore
+/// #[get("/")]
+/// async fn home(user: MaybeAuthenticated) -> HttpResponse {
+///     match user.user() {
+///         Some(user) => HttpResponse::Ok().body(format!("hello {}", user.access.subject().as_str())),
+///         None => HttpResponse::Ok().body(format!("<a href=\"{}\">log in</a>", user.login_url().unwrap_or_default())),
+///     }
+/// }
+/// ```
+pub struct MaybeAuthenticated<AC: AdditionalClaims = EmptyAdditionalClaims> {
+    user: Option<Arc<AuthenticatedUser<AC>>>,
+    /// Why there is no user, `None` without a client to log in with.
+    required: Option<AuthenticationRequired>,
+}
+
+impl<AC: AdditionalClaims> MaybeAuthenticated<AC> {
+    pub fn user(&self) -> Option<&AuthenticatedUser<AC>> {
+        self.user.as_deref()
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.user.is_some()
+    }
+
+    /// A link logging the user in and returning to this request, `None` if the user is logged
+    /// in, see [`AuthenticationRequired::login_url`].
+    pub fn login_url(&self) -> Option<String> {
+        match self.user {
+            Some(_) => None,
+            None => self
+                .required
+                .as_ref()
+                .map(AuthenticationRequired::login_url),
+        }
+    }
+
+    /// Why the request is not authenticated, `None` if it is.
+    pub fn reason(&self) -> Option<&AuthenticationRequired> {
+        self.required.as_ref().filter(|_| self.user.is_none())
+    }
+
+    /// The shared user, `None` if the request is not authenticated.
+    pub fn into_inner(self) -> Option<Arc<AuthenticatedUser<AC>>> {
+        self.user
+    }
+}
+
+impl<AC: AdditionalClaims> std::ops::Deref for MaybeAuthenticated<AC> {
+    type Target = Option<Arc<AuthenticatedUser<AC>>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
 
-impl FromRequest for MaybeAuthenticated {
+impl<AC: AdditionalClaims> FromRequest for MaybeAuthenticated<AC> {
     type Error = Error;
     type Future = Ready<Result<Self, Self::Error>>;
 
-    fn from_request(
-        req: &HttpRequest,
-        _payload: &mut actix_web::dev::Payload,
-    ) -> Self::Future {
-        let value = req.extensions().get::<Result<AuthenticatedUser, AuthError>>().cloned();
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let value = req.extensions().get::<AuthContext>().cloned();
         ready(match value {
-            Some(v) => Ok(MaybeAuthenticated(v)),
-            _ => Err(ErrorUnauthorized("Unauthorized")),
+            // Logging in again would not bring the missing claims, there is no login to offer.
+            Some(AuthContext::Authenticated(user)) => Ok(MaybeAuthenticated {
+                user: typed_user(req, &user).ok(),
+                required: None,
+            }),
+            Some(AuthContext::Unauthenticated { required, .. }) => Ok(MaybeAuthenticated {
+                user: None,
+                required: Some(required),
+            }),
+            // Not behind the middleware, the login can still start from the app's client.
+            None => Ok(MaybeAuthenticated {
+                user: None,
+                required: RegisteredClient::find(req).map(|client| {
+                    AuthenticationRequired::new(&client.0, request_target(req), None)
+                }),
+            }),
         })
     }
 }
 
-impl<'a> Into<Option<&'a AuthenticatedUser>> for &'a MaybeAuthenticated {
-    fn into(self) -> Option<&'a AuthenticatedUser> {
-        match &self.0 {
-            Ok(v) => Some(v),
-            _ => None,
-        }
+/// Deprecated, use [`MaybeAuthenticated::user`]. Trait impls cannot carry `#[deprecated]`,
+/// it is kept for existing handlers and removed in the next major release.
+impl<'a, AC: AdditionalClaims> From<&'a MaybeAuthenticated<AC>>
+    for Option<&'a AuthenticatedUser<AC>>
+{
+    fn from(value: &'a MaybeAuthenticated<AC>) -> Self {
+        value.user()
     }
 }
 
-impl<'a> TryInto<&'a AuthenticatedUser> for &'a MaybeAuthenticated {
+/// Deprecated, use [`MaybeAuthenticated::user`] or the [`Authenticated`] extractor. Kept for
+/// existing handlers and removed in the next major release.
+impl<'a, AC: AdditionalClaims> TryInto<&'a AuthenticatedUser<AC>> for &'a MaybeAuthenticated<AC> {
     type Error = Error;
 
-    fn try_into(self) -> Result<&'a AuthenticatedUser, Self::Error> {
-        match &self.0 {
-            Ok(v) => Ok(v),
-            Err(e) => Err(e.clone().into()),
+    fn try_into(self) -> Result<&'a AuthenticatedUser<AC>, Self::Error> {
+        match (self.user(), &self.required) {
+            (Some(user), _) => Ok(user),
+            (None, Some(required)) => Err(required.clone().into()),
+            (None, None) => Err(ErrorUnauthorized(
+                EnglishMessages.message(MessageKey::Unauthorized, &[]),
+            )),
         }
     }
 }
@@ -0,0 +1,163 @@
+//! The HTML pages the endpoints answer with, renderable by applications.
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+
+use crate::error::{OpenIdError, ProviderError};
+use crate::providers::ProviderLink;
+
+/// Which page is rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PageKind {
+    /// The callback could not complete the login.
+    CallbackError,
+    /// The session ended and there is no page to send the user back to.
+    LoggedOut,
+    /// The list of providers to log in with, see [`LoginProviders`](crate::LoginProviders).
+    ProviderChooser,
+}
+
+/// What a page shows.
+pub struct PageContext<'a> {
+    pub status: StatusCode,
+    /// The user-facing text of the page, see [`Messages`](crate::Messages).
+    pub message: &'a str,
+    /// Where the page leads the user next, if anywhere.
+    pub target_url: Option<&'a str>,
+    /// Why the page is shown, for [`PageKind::CallbackError`] caused by the provider.
+    pub error: Option<&'a OpenIdError>,
+    /// The providers of [`PageKind::ProviderChooser`], in display order.
+    pub providers: &'a [ProviderLink<'a>],
+    /// Nonce allowed by the page's `Content-Security-Policy` for inline scripts and styles.
+    pub csp_nonce: &'a str,
+}
+
+/// A rendered page.
+pub struct Page {
+    pub content_type: String,
+    pub body: String,
+    /// Replaces the default `Content-Security-Policy`, which only allows inline scripts and styles
+    /// carrying the nonce.
+    pub content_security_policy: Option<String>,
+}
+
+impl Page {
+    pub fn html(body: impl Into<String>) -> Self {
+        Page {
+            content_type: "text/html; charset=utf-8".to_string(),
+            body: body.into(),
+            content_security_policy: None,
+        }
+    }
+}
+
+/// How the callback answers the provider sending the user back with an `error` instead of a
+/// code, e.g. `access_denied` when the user cancelled the consent.
+///
+/// The error's fields come from the query of the callback, anyone can send them: show or
+/// forward them escaped.
+#[derive(Clone, Debug, Default)]
+pub enum CallbackErrorHandling {
+    /// Renders [`PageKind::CallbackError`] with `400 Bad Request`, the error as
+    /// [`OpenIdError::Authorization`].
+    #[default]
+    Page,
+    /// Redirects to a route of the application, e.g. `/login-failed`, with the `error`,
+    /// `error_description` and `error_uri` of the provider and the page the user was logging in
+    /// for as `next` in the query.
+    Redirect(String),
+    /// Answers with the response of the handler.
+    Handler(fn(&HttpRequest, &ProviderError) -> HttpResponse),
+}
+
+/// An unauthenticated request sent to the login, answered by the hook of
+/// [`login_redirect`](crate::OpenIdBuilder::login_redirect).
+#[derive(Clone, Copy, Debug)]
+pub struct LoginRedirect<'a> {
+    /// Where the user logs in: the provider's authorization url, or the provider chooser. Escape
+    /// it when rendering it into HTML.
+    pub url: &'a str,
+    /// The path and query the user returns to after the login.
+    pub path: &'a str,
+    /// Whether the login is a silent attempt with `prompt=none`.
+    pub silent: bool,
+}
+
+/// Renders the pages, e.g. through a template engine with the application's branding.
+pub trait PageRenderer: Send + Sync {
+    fn render(&self, kind: PageKind, context: &PageContext<'_>) -> Page;
+}
+
+/// Bare HTML pages showing the message, with a link to the target url if there is one.
+pub struct DefaultPages;
+
+impl PageRenderer for DefaultPages {
+    fn render(&self, kind: PageKind, context: &PageContext<'_>) -> Page {
+        let message = escape(context.message);
+        if kind == PageKind::ProviderChooser {
+            return Page::html(format!(
+                "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title></head>\
+                 <body><h1 id=\"providers\">{}</h1><nav aria-labelledby=\"providers\"><ul>{}</ul>\
+                 </nav></body></html>",
+                message,
+                message,
+                context
+                    .providers
+                    .iter()
+                    .map(provider_item)
+                    .collect::<String>()
+            ));
+        }
+        let link = match context.target_url {
+            Some(url) => format!("<p><a href=\"{}\">{}</a></p>", escape(url), escape(url)),
+            None => String::new(),
+        };
+        Page::html(format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title></head>\
+             <body><p>{}</p>{}</body></html>",
+            message, message, link
+        ))
+    }
+}
+
+fn provider_item(link: &ProviderLink<'_>) -> String {
+    // The name follows as text, the icon is decorative.
+    let icon = match &link.entry.icon_url {
+        Some(url) => format!(
+            "<img src=\"{}\" alt=\"\" width=\"24\" height=\"24\"> ",
+            escape(url)
+        ),
+        None => String::new(),
+    };
+    format!(
+        "<li><a href=\"{}\">{}{}</a></li>",
+        escape(&link.href),
+        icon,
+        escape(&link.entry.display_name)
+    )
+}
+
+/// The policy of pages not setting their own.
+pub(crate) fn default_content_security_policy(csp_nonce: &str) -> String {
+    format!(
+        "default-src 'none'; script-src 'nonce-{0}'; style-src 'nonce-{0}'; \
+         img-src 'self' https: data:; base-uri 'none'; form-action 'self'; frame-ancestors 'none'",
+        csp_nonce
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,107 @@
+//! How the crate serializes claims into cookies, e.g. the `user_info` cookie.
+
+use std::io::{Read, Write};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Prefix of [`PayloadCodec::MessagePack`] payloads.
+const MESSAGE_PACK: &str = "m1.";
+/// Prefix of [`PayloadCodec::DeflateJson`] payloads.
+const DEFLATE_JSON: &str = "d1.";
+
+/// Encoding of the claims the crate serializes, [`Json`](Self::Json) by default.
+///
+/// Binary payloads are base64url encoded behind a prefix naming the codec, so
+/// [`decode`](Self::decode) reads payloads of every codec whatever the configured one, e.g. while
+/// a deploy switching codecs rolls out. Large claims, such as Azure AD group lists, may not fit
+/// the 4KB cookie limit as JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PayloadCodec {
+    /// Plain JSON, readable by front ends as is. Non-ASCII characters are escaped.
+    #[default]
+    Json,
+    /// MessagePack with the field names, compressed with deflate. Usually the smallest.
+    MessagePack,
+    /// JSON compressed with deflate.
+    DeflateJson,
+}
+
+/// A payload could not be encoded or decoded.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid payload: {0}")]
+pub struct PayloadError(#[source] Box<dyn std::error::Error + Send + Sync>);
+
+impl PayloadError {
+    fn new(err: impl std::error::Error + Send + Sync + 'static) -> Self {
+        PayloadError(Box::new(err))
+    }
+}
+
+impl PayloadCodec {
+    /// Encodes `value` as an ASCII string, valid as a cookie value.
+    pub fn encode(self, value: &impl Serialize) -> Result<String, PayloadError> {
+        match self {
+            PayloadCodec::Json => to_ascii_json(value),
+            PayloadCodec::MessagePack => {
+                let bytes = rmp_serde::to_vec_named(value).map_err(PayloadError::new)?;
+                deflate(MESSAGE_PACK, &bytes)
+            }
+            PayloadCodec::DeflateJson => {
+                let json = serde_json::to_vec(value).map_err(PayloadError::new)?;
+                deflate(DEFLATE_JSON, &json)
+            }
+        }
+    }
+
+    /// Decodes a payload encoded with any codec.
+    pub fn decode<T: DeserializeOwned>(payload: &str) -> Result<T, PayloadError> {
+        if let Some(encoded) = payload.strip_prefix(MESSAGE_PACK) {
+            rmp_serde::from_slice(&inflate(encoded)?).map_err(PayloadError::new)
+        } else if let Some(encoded) = payload.strip_prefix(DEFLATE_JSON) {
+            serde_json::from_slice(&inflate(encoded)?).map_err(PayloadError::new)
+        } else {
+            serde_json::from_str(payload).map_err(PayloadError::new)
+        }
+    }
+}
+
+/// Compresses `bytes`, base64url encoded behind `prefix`.
+fn deflate(prefix: &str, bytes: &[u8]) -> Result<String, PayloadError> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(bytes).map_err(PayloadError::new)?;
+    let compressed = encoder.finish().map_err(PayloadError::new)?;
+    Ok(format!("{}{}", prefix, URL_SAFE_NO_PAD.encode(compressed)))
+}
+
+fn inflate(encoded: &str) -> Result<Vec<u8>, PayloadError> {
+    let compressed = URL_SAFE_NO_PAD.decode(encoded).map_err(PayloadError::new)?;
+    let mut bytes = Vec::new();
+    DeflateDecoder::new(compressed.as_slice())
+        .read_to_end(&mut bytes)
+        .map_err(PayloadError::new)?;
+    Ok(bytes)
+}
+
+/// Serializes `value` to JSON escaping every non-ASCII character, cookie values must be ASCII.
+fn to_ascii_json(value: &impl Serialize) -> Result<String, PayloadError> {
+    let json = serde_json::to_string(value).map_err(PayloadError::new)?;
+    let mut escaped = String::with_capacity(json.len());
+    for c in json.chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            // Non-ASCII characters can only appear inside JSON strings, where escapes are valid.
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+    }
+    Ok(escaped)
+}
@@ -0,0 +1,30 @@
+//! A hook deciding about requests before the middleware looks at their credentials, e.g. letting
+//! the office VPN in on a few routes.
+
+use actix_web::http::StatusCode;
+
+use crate::openid_middleware::AuthenticatedUser;
+
+/// The decision of the `pre_auth` hook.
+///
+/// ```ignore
+/// fn office_vpn(req: &ServiceRequest) -> PreAuthDecision {
+///     match req.headers().get("x-office-vpn") {
+///         Some(_) if req.path().starts_with("/status") => {
+///             PreAuthDecision::Allow(Some(Box::new(AuthenticatedUser::synthetic("vpn"))))
+///         }
+///         _ => PreAuthDecision::Continue,
+///     }
+/// }
+///
+/// openid.get_middleware().pre_auth(office_vpn).always_authenticate(|req| req.path().starts_with("/admin"))
+/// ```
+pub enum PreAuthDecision {
+    /// Authenticate the request as usual.
+    Continue,
+    /// Let the request in without looking at its credentials, as the user if one is given.
+    /// Without one, `Authenticated` rejects the request and `MaybeAuthenticated` is `None`.
+    Allow(Option<Box<AuthenticatedUser>>),
+    /// Answer with the status, e.g. `403 Forbidden`.
+    Deny(StatusCode),
+}
@@ -0,0 +1,23 @@
+//! The types of the crate handlers and hooks name most, with those of `openidconnect` they are
+//! made of, for apps not to depend on the exact `openidconnect` version the crate uses:
+//!
+//! ```
+//! use actix_web_openidconnect::prelude::*;
+//!
+//! fn greeting(user: &AuthenticatedUser) -> String {
+//!     format!("Hello {}", user.name().unwrap_or(user.subject()))
+//! }
+//! ```
+
+pub use openidconnect::core::CoreGenderClaim;
+pub use openidconnect::{
+    AccessToken, AdditionalClaims, EmptyAdditionalClaims, EndUserEmail, EndUserName,
+    EndUserUsername, IdTokenClaims, LanguageTag, LocalizedClaim, RefreshToken, Scope,
+    StandardClaims, SubjectIdentifier, UserInfoClaims,
+};
+
+pub use crate::openid::{IdToken, OpenID, OpenIDTokens, OtherClaims, UserInfo};
+pub use crate::openid_middleware::{
+    Authenticated, AuthenticatedUser, AuthenticationRequired, MaybeAuthenticated,
+};
+pub use crate::{ActixWebOpenId, OpenIdBuilder, OpenIdError, SessionToken};
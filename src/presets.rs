@@ -0,0 +1,117 @@
+//! Builders preconfigured for the quirks of common providers.
+//!
+//! Each preset only fills in the provider specific parts, the client secret, redirect url and
+//! `should_auth` still have to be set on the returned [`OpenIdBuilder`].
+
+use crate::builder::OpenIdBuilder;
+use crate::openid::{IssuerValidation, OpenID};
+
+impl OpenID {
+    /// Keycloak realm served under `base_url`, e.g. `https://sso.example.com`.
+    ///
+    /// Roles are read from the realm roles. A specific identity provider can be preselected by
+    /// adding a `kc_idp_hint` parameter.
+    pub fn keycloak(
+        base_url: impl AsRef<str>,
+        realm: impl AsRef<str>,
+        client_id: impl Into<String>,
+    ) -> OpenIdBuilder {
+        OpenIdBuilder::default()
+            .issuer_url(format!(
+                "{}/realms/{}",
+                base_url.as_ref().trim_end_matches('/'),
+                realm.as_ref()
+            ))
+            .client_id(client_id)
+            .roles_claim("realm_access.roles")
+    }
+
+    /// Auth0 tenant at `domain`, e.g. `example.eu.auth0.com`.
+    ///
+    /// Auth0 only issues JWT access tokens for the API named by `audience`, and only returns a
+    /// refresh token when `offline_access` is requested.
+    pub fn auth0(
+        domain: impl AsRef<str>,
+        client_id: impl Into<String>,
+        audience: impl Into<String>,
+    ) -> OpenIdBuilder {
+        OpenIdBuilder::default()
+            .issuer_url(format!(
+                "https://{}/",
+                domain.as_ref().trim_end_matches('/')
+            ))
+            .client_id(client_id)
+            .scopes(["profile", "email", "offline_access"])
+            .extra_auth_param("audience", audience)
+    }
+
+    /// Azure AD (Microsoft Entra ID) v2.0 endpoint of `tenant`, a tenant id or domain.
+    ///
+    /// The multi-tenant `common`, `organizations` and `consumers` endpoints advertise a templated
+    /// issuer, so the issuer of their ID tokens is not checked: the application has to check the
+    /// `tid` claim itself.
+    pub fn azure_ad(tenant: impl AsRef<str>, client_id: impl Into<String>) -> OpenIdBuilder {
+        let tenant = tenant.as_ref();
+        let issuer_validation = match tenant {
+            "common" | "organizations" | "consumers" => IssuerValidation::Skip,
+            _ => IssuerValidation::Exact,
+        };
+        OpenIdBuilder::default()
+            .issuer_url(format!("https://login.microsoftonline.com/{}/v2.0", tenant))
+            .client_id(client_id)
+            .scopes(["profile", "email", "offline_access"])
+            .issuer_validation(issuer_validation)
+            .roles_claim("roles")
+    }
+
+    /// Azure AD B2C user flow or custom `policy`, e.g. `B2C_1_signin`, of the tenant named
+    /// `tenant` at `<tenant>.b2clogin.com`, whose id is `tenant_id`.
+    ///
+    /// B2C advertises an issuer with the tenant id rather than the url it is discovered at, and
+    /// needs the policy with every request to the discovery document, the authorization url and
+    /// the token endpoint.
+    pub fn azure_ad_b2c(
+        tenant: impl AsRef<str>,
+        tenant_id: impl AsRef<str>,
+        policy: impl AsRef<str>,
+        client_id: impl Into<String>,
+    ) -> OpenIdBuilder {
+        let (tenant, policy) = (tenant.as_ref(), policy.as_ref());
+        let issuer_url = format!(
+            "https://{}.b2clogin.com/{}.onmicrosoft.com/v2.0/",
+            tenant, tenant
+        );
+        OpenIdBuilder::default()
+            .discovery_url(format!(
+                "{}.well-known/openid-configuration?p={}",
+                issuer_url, policy
+            ))
+            .issuer_url(issuer_url)
+            .client_id(client_id)
+            .scopes(["offline_access"])
+            .extra_auth_param("p", policy)
+            .extra_token_param("p", policy)
+            .issuer_validation(IssuerValidation::Expected(format!(
+                "https://{}.b2clogin.com/{}/v2.0/",
+                tenant,
+                tenant_id.as_ref()
+            )))
+    }
+
+    /// Google.
+    ///
+    /// Google only issues refresh tokens with `access_type=offline` and has no end session
+    /// endpoint, so logging out only ends the local session.
+    pub fn google(client_id: impl Into<String>) -> OpenIdBuilder {
+        OpenIdBuilder::default()
+            .issuer_url("https://accounts.google.com")
+            .client_id(client_id)
+            .scopes(["profile", "email"])
+            .extra_auth_param("access_type", "offline")
+            .extra_auth_param("prompt", "consent")
+            .issuer_validation(IssuerValidation::OneOf(vec![
+                "https://accounts.google.com".to_string(),
+                "accounts.google.com".to_string(),
+            ]))
+    }
+}
@@ -0,0 +1,282 @@
+//! The discovery document and JWKS, kept with the HTTP validators the provider sent.
+
+use std::time::{Duration, SystemTime};
+
+use openidconnect::core::CoreJsonWebKeySet;
+use openidconnect::http::header::{
+    HeaderMap, HeaderValue, ACCEPT, CACHE_CONTROL, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED,
+};
+use openidconnect::http::{Method, StatusCode};
+use openidconnect::{HttpRequest, IssuerUrl};
+use serde_json::json;
+use url::Url;
+
+use crate::clock::{system_time, Clock};
+use crate::error::{OpenIdError, Result};
+use crate::http_client::HttpClient;
+use crate::openid::{ExtendedProviderMetadata, IssuerValidation};
+
+/// How a cached document can be revalidated, and how long it is fresh.
+#[derive(Clone, Debug, Default)]
+struct CacheValidators {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    /// End of the provider's `max-age`, the document is not refetched before.
+    fresh_until: Option<SystemTime>,
+    /// When the provider last confirmed the document, by sending or revalidating it.
+    checked_at: Option<SystemTime>,
+}
+
+impl CacheValidators {
+    fn from_headers(headers: &HeaderMap, now: SystemTime) -> Self {
+        let max_age = headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(|directive| directive.trim().strip_prefix("max-age=")?.parse().ok());
+        CacheValidators {
+            etag: headers.get(ETAG).cloned(),
+            last_modified: headers.get(LAST_MODIFIED).cloned(),
+            fresh_until: max_age.map(|secs| now + Duration::from_secs(secs)),
+            checked_at: Some(now),
+        }
+    }
+
+    /// The validators after a `304 Not Modified`, which may not repeat all of them.
+    fn revalidated(&self, headers: &HeaderMap, now: SystemTime) -> Self {
+        let sent = CacheValidators::from_headers(headers, now);
+        CacheValidators {
+            etag: sent.etag.or_else(|| self.etag.clone()),
+            last_modified: sent.last_modified.or_else(|| self.last_modified.clone()),
+            fresh_until: sent.fresh_until,
+            checked_at: sent.checked_at,
+        }
+    }
+
+    /// Within the provider's `max-age`, and confirmed at most `max_staleness` ago.
+    fn is_fresh(&self, now: SystemTime, max_staleness: Option<Duration>) -> bool {
+        let within_staleness = match (self.checked_at, max_staleness) {
+            (Some(checked_at), Some(max_staleness)) => now < checked_at + max_staleness,
+            _ => true,
+        };
+        within_staleness
+            && self
+                .fresh_until
+                .is_some_and(|fresh_until| now < fresh_until)
+    }
+
+    fn age(&self, now: SystemTime) -> Option<Duration> {
+        Some(now.duration_since(self.checked_at?).unwrap_or_default())
+    }
+}
+
+struct Fetched {
+    /// `None` if the provider answered `304 Not Modified`.
+    body: Option<Vec<u8>>,
+    validators: CacheValidators,
+}
+
+/// GETs the JSON document at `url`, revalidating the cached one if there are `validators`.
+async fn fetch(
+    http: &HttpClient,
+    clock: &dyn Clock,
+    url: Url,
+    validators: Option<&CacheValidators>,
+) -> Result<Fetched> {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    if let Some(validators) = validators {
+        if let Some(etag) = &validators.etag {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+    let response = http
+        .execute(HttpRequest {
+            url,
+            method: Method::GET,
+            headers,
+            body: Vec::new(),
+        })
+        .await
+        .map_err(|err| OpenIdError::Discovery(Box::new(err)))?;
+    match (response.status_code, validators) {
+        (StatusCode::NOT_MODIFIED, Some(validators)) => Ok(Fetched {
+            body: None,
+            validators: validators.revalidated(&response.headers, system_time(clock)),
+        }),
+        (StatusCode::OK, _) => Ok(Fetched {
+            validators: CacheValidators::from_headers(&response.headers, system_time(clock)),
+            body: Some(response.body),
+        }),
+        (status, _) => Err(OpenIdError::Http {
+            status: status.as_u16(),
+        }),
+    }
+}
+
+/// What [`ProviderDocuments::refresh`] found out.
+#[derive(Debug, Default)]
+pub(crate) struct Refreshed {
+    pub(crate) metadata_changed: bool,
+    pub(crate) keys_changed: bool,
+}
+
+/// The provider's discovery document, with its signing keys set, and how to revalidate both.
+#[derive(Clone)]
+pub(crate) struct ProviderDocuments {
+    pub(crate) metadata: ExtendedProviderMetadata,
+    discovery_url: Url,
+    discovery: CacheValidators,
+    jwks: CacheValidators,
+}
+
+impl ProviderDocuments {
+    /// Fetches the discovery document of `issuer_url` at `discovery_url`, and its JWKS.
+    ///
+    /// Only [`IssuerValidation::Exact`] and [`IssuerValidation::Expected`] check the advertised
+    /// issuer, multi-tenant endpoints such as Azure AD's `common` advertise a templated issuer.
+    pub(crate) async fn discover(
+        http: &HttpClient,
+        clock: &dyn Clock,
+        discovery_url: &Url,
+        issuer_url: &IssuerUrl,
+        issuer_validation: &IssuerValidation,
+    ) -> Result<Self> {
+        let discovery = fetch(http, clock, discovery_url.clone(), None).await?;
+        let metadata = parse_metadata(
+            &discovery.body.unwrap_or_default(),
+            issuer_url,
+            issuer_validation,
+        )?;
+        let jwks = fetch(http, clock, metadata.jwks_uri().url().clone(), None).await?;
+        let keys = parse_jwks(&jwks.body.unwrap_or_default())?;
+        Ok(ProviderDocuments {
+            metadata: metadata.set_jwks(keys),
+            discovery_url: discovery_url.clone(),
+            discovery: discovery.validators,
+            jwks: jwks.validators,
+        })
+    }
+
+    /// Documents advertising nothing but `issuer_url`, to be replaced by the provider's on the
+    /// next [`refresh`](Self::refresh) from `discovery_url`. Nothing verifies against their
+    /// empty key set.
+    pub(crate) fn pending(issuer_url: &IssuerUrl, discovery_url: Url) -> Self {
+        let metadata = serde_json::from_value(json!({
+            "issuer": issuer_url,
+            "authorization_endpoint": issuer_url,
+            "jwks_uri": issuer_url,
+            "response_types_supported": ["code"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": ["RS256"],
+        }))
+        .expect("the placeholder metadata is valid");
+        ProviderDocuments {
+            metadata,
+            discovery_url,
+            discovery: CacheValidators::default(),
+            jwks: CacheValidators::default(),
+        }
+    }
+
+    /// Revalidates the documents whose `max-age` elapsed or that were confirmed longer than
+    /// `max_staleness` ago, or both if `force`.
+    pub(crate) async fn refresh(
+        &mut self,
+        http: &HttpClient,
+        clock: &dyn Clock,
+        issuer_url: &IssuerUrl,
+        issuer_validation: &IssuerValidation,
+        max_staleness: Option<Duration>,
+        force: bool,
+    ) -> Result<Refreshed> {
+        let mut refreshed = Refreshed::default();
+        if force || !self.discovery.is_fresh(system_time(clock), max_staleness) {
+            let discovery = fetch(
+                http,
+                clock,
+                self.discovery_url.clone(),
+                Some(&self.discovery),
+            )
+            .await?;
+            if let Some(body) = discovery.body {
+                let metadata = parse_metadata(&body, issuer_url, issuer_validation)?;
+                let jwks_uri_changed = metadata.jwks_uri() != self.metadata.jwks_uri();
+                self.metadata = metadata.set_jwks(self.metadata.jwks().clone());
+                if jwks_uri_changed {
+                    self.jwks = CacheValidators::default();
+                }
+                refreshed.metadata_changed = true;
+            }
+            self.discovery = discovery.validators;
+        }
+        if force || !self.jwks.is_fresh(system_time(clock), max_staleness) {
+            let revalidate = self.jwks.etag.is_some() || self.jwks.last_modified.is_some();
+            let jwks = fetch(
+                http,
+                clock,
+                self.metadata.jwks_uri().url().clone(),
+                revalidate.then_some(&self.jwks),
+            )
+            .await?;
+            if let Some(body) = jwks.body {
+                let keys = parse_jwks(&body)?;
+                refreshed.keys_changed = &keys != self.metadata.jwks();
+                self.metadata = self.metadata.clone().set_jwks(keys);
+            }
+            self.jwks = jwks.validators;
+        }
+        Ok(refreshed)
+    }
+
+    /// Time since the provider last confirmed the discovery document.
+    pub(crate) fn discovery_age(&self, now: SystemTime) -> Option<Duration> {
+        self.discovery.age(now)
+    }
+
+    /// Time since the provider last confirmed the JWKS, `None` if it has to be fetched again.
+    pub(crate) fn jwks_age(&self, now: SystemTime) -> Option<Duration> {
+        self.jwks.age(now)
+    }
+}
+
+/// Where the discovery document of `issuer_url` is served by default.
+pub(crate) fn discovery_url(issuer_url: &IssuerUrl) -> Result<Url> {
+    issuer_url
+        .join(".well-known/openid-configuration")
+        .map_err(|err| OpenIdError::Config(format!("invalid issuer url: {}", err)))
+}
+
+fn parse_metadata(
+    body: &[u8],
+    issuer_url: &IssuerUrl,
+    issuer_validation: &IssuerValidation,
+) -> Result<ExtendedProviderMetadata> {
+    let metadata: ExtendedProviderMetadata =
+        serde_json::from_slice(body).map_err(|err| OpenIdError::Discovery(Box::new(err)))?;
+    let expected = match issuer_validation {
+        IssuerValidation::Exact => Some(issuer_url.as_str()),
+        IssuerValidation::Expected(expected) => Some(expected.as_str()),
+        IssuerValidation::OneOf(_) | IssuerValidation::Skip => None,
+    };
+    if let Some(expected) = expected.filter(|expected| metadata.issuer().as_str() != *expected) {
+        return Err(OpenIdError::Discovery(
+            format!(
+                "the provider advertises the issuer {} instead of {}",
+                metadata.issuer().as_str(),
+                expected
+            )
+            .into(),
+        ));
+    }
+    Ok(metadata)
+}
+
+fn parse_jwks(body: &[u8]) -> Result<CoreJsonWebKeySet> {
+    serde_json::from_slice(body).map_err(|err| OpenIdError::Discovery(Box::new(err)))
+}
@@ -0,0 +1,202 @@
+//! Logouts started at the provider: front-channel, loading a page of the app in the browser,
+//! and back-channel, posting a logout token to the app.
+
+use std::time::UNIX_EPOCH;
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{web, HttpRequest, HttpResponse};
+use openidconnect::ClaimsVerificationError;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::Result;
+use crate::logging::LogCategory;
+use crate::openid::OpenID;
+use crate::openid_middleware::{
+    load_session, remove_session_cookies, request_session, RegisteredClient,
+};
+
+/// The member of the `events` claim of a logout token, see OpenID Connect Back-Channel Logout
+/// section 2.4.
+const BACK_CHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// The provider session the front-channel logout ends, sent when the client registered the
+/// front-channel logout url with `frontchannel_logout_session_required`.
+#[derive(Deserialize)]
+pub(crate) struct FrontChannelLogout {
+    iss: Option<String>,
+    sid: Option<String>,
+}
+
+/// Ends the browser's session, unless `sid` names another provider session than the one it
+/// logged in with. Registered with
+/// [`OpenIdBuilder::front_channel_logout`](crate::OpenIdBuilder::front_channel_logout).
+pub(crate) async fn front_channel_logout_endpoint(
+    req: HttpRequest,
+    open_id_client: RegisteredClient,
+    query: web::Query<FrontChannelLogout>,
+) -> actix_web::Result<HttpResponse> {
+    let mut response = HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoCache,
+            CacheDirective::NoStore,
+        ]))
+        .finish();
+    if let Some(issuer) = &query.iss {
+        if issuer != open_id_client.provider_metadata().issuer().as_str() {
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Ignored a front-channel logout of the issuer {}", issuer),
+            );
+            return Ok(HttpResponse::BadRequest().finish());
+        }
+    }
+    if let Some(sid) = &query.sid {
+        load_session(&open_id_client, &req).await?;
+        let session = request_session(&open_id_client, &req);
+        let session_sid = session
+            .as_ref()
+            .and_then(|session| session.id_token())
+            .and_then(|id_token| open_id_client.id_token_claims(id_token).ok())
+            .and_then(|claims| claims.additional_claims().get("sid").cloned());
+        if session_sid.as_ref().and_then(Value::as_str) != Some(sid.as_str()) {
+            open_id_client.log_policy().log(
+                LogCategory::UnauthenticatedRequest,
+                format_args!("Front-channel logout of another provider session, kept the session"),
+            );
+            return Ok(response);
+        }
+    }
+    open_id_client.log_policy().log(
+        LogCategory::LoginSuccess,
+        format_args!("The provider ended the session through the front channel"),
+    );
+    remove_session_cookies(&open_id_client, &req, &mut response).await?;
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BackChannelLogout {
+    logout_token: String,
+}
+
+/// The sessions a verified logout token ends, at least one of both is set.
+pub(crate) struct LogoutToken {
+    pub(crate) subject: Option<String>,
+    pub(crate) sid: Option<String>,
+}
+
+impl OpenID {
+    /// Verifies a logout token as OpenID Connect Back-Channel Logout section 2.6 requires: it is
+    /// signed with one of the provider's keys, from the validated issuer, for this client, names
+    /// the back-channel logout event and a `sub` or `sid`, and has no `nonce`.
+    pub(crate) async fn verify_logout_token(&self, logout_token: &str) -> Result<LogoutToken> {
+        let invalid = |reason: &str| {
+            ClaimsVerificationError::Other(format!("invalid logout token: {}", reason))
+        };
+        self.refetch_unknown_key(logout_token).await?;
+        let claims = self.verify_jws(logout_token)?;
+        self.check_issuer(&claims)?;
+        let client_id = self.client_id();
+        let audience = match claims.get("aud") {
+            Some(Value::String(audience)) => audience == client_id,
+            Some(Value::Array(audiences)) => audiences
+                .iter()
+                .any(|audience| audience.as_str() == Some(client_id)),
+            _ => false,
+        };
+        if !audience {
+            return Err(ClaimsVerificationError::InvalidAudience(format!(
+                "the logout token was not issued for `{}`",
+                client_id
+            ))
+            .into());
+        }
+        if claims.get("iat").and_then(Value::as_u64).is_none() {
+            return Err(invalid("no `iat`").into());
+        }
+        let now = self.now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        if let Some(expires_at) = claims.get("exp") {
+            if expires_at
+                .as_u64()
+                .is_none_or(|expires_at| expires_at <= now.as_secs())
+            {
+                return Err(ClaimsVerificationError::Expired(
+                    "the logout token expired".to_string(),
+                )
+                .into());
+            }
+        }
+        let event = claims
+            .get("events")
+            .and_then(|events| events.get(BACK_CHANNEL_LOGOUT_EVENT));
+        if !event.is_some_and(Value::is_object) {
+            return Err(invalid("no back-channel logout event").into());
+        }
+        // Keeps ID tokens from being replayed as logout tokens.
+        if claims.get("nonce").is_some() {
+            return Err(invalid("a `nonce` is not allowed").into());
+        }
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_string);
+        let token = LogoutToken {
+            subject: claim("sub"),
+            sid: claim("sid"),
+        };
+        if token.subject.is_none() && token.sid.is_none() {
+            return Err(invalid("neither `sub` nor `sid`").into());
+        }
+        Ok(token)
+    }
+}
+
+/// Removes the stored sessions a logout token posted by the provider ends. Registered with
+/// [`OpenIdBuilder::back_channel_logout`](crate::OpenIdBuilder::back_channel_logout).
+pub(crate) async fn back_channel_logout_endpoint(
+    open_id_client: RegisteredClient,
+    form: web::Form<BackChannelLogout>,
+) -> HttpResponse {
+    let no_store = CacheControl(vec![CacheDirective::NoStore]);
+    let logout_token = match open_id_client.verify_logout_token(&form.logout_token).await {
+        Ok(logout_token) => logout_token,
+        Err(err) => {
+            open_id_client.log_policy().log(
+                LogCategory::LoginFailure,
+                format_args!("Rejected a back-channel logout: {}", err),
+            );
+            return HttpResponse::BadRequest()
+                .insert_header(no_store)
+                .json(serde_json::json!({ "error": "invalid_request" }));
+        }
+    };
+    // The build fails without a store.
+    let Some(store) = open_id_client.session_store() else {
+        return HttpResponse::NotImplemented()
+            .insert_header(no_store)
+            .finish();
+    };
+    match store
+        .remove_logged_out(logout_token.subject.as_deref(), logout_token.sid.as_deref())
+        .await
+    {
+        Ok(removed) => {
+            open_id_client.log_policy().log(
+                LogCategory::LoginSuccess,
+                format_args!(
+                    "The provider ended {} sessions through the back channel",
+                    removed
+                ),
+            );
+            HttpResponse::Ok().insert_header(no_store).finish()
+        }
+        Err(err) => {
+            open_id_client.log_policy().log_error(
+                LogCategory::IdpError,
+                format_args!(
+                    "Could not remove the sessions of a back-channel logout: {}",
+                    err
+                ),
+            );
+            HttpResponse::BadRequest().insert_header(no_store).finish()
+        }
+    }
+}
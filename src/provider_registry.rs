@@ -0,0 +1,307 @@
+//! Clients of several providers serving one app, picked per request, e.g. one tenant per domain.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::HOST;
+use actix_web::web::ServiceConfig;
+use actix_web::Error;
+use futures_util::future::LocalBoxFuture;
+
+use crate::error::OpenIdError;
+use crate::openid::OpenID;
+use crate::openid_middleware::{AuthCookies, OpenIdMiddleware};
+use crate::ActixWebOpenId;
+
+/// Names the client of a request in a [`ProviderRegistry`], e.g. the domain of a tenant.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProviderKey(String);
+
+impl ProviderKey {
+    pub fn new(key: impl Into<String>) -> Self {
+        ProviderKey(key.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The host the request was sent to, lowercase and without the port. It is read from the
+    /// `Host` header, not from `Forwarded` headers a client could set, write your own for a
+    /// proxy sending the host elsewhere.
+    pub fn host(req: &ServiceRequest) -> ProviderKey {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri().host())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+                name.to_string()
+            }
+            _ => host,
+        };
+        ProviderKey(host)
+    }
+
+    /// The first segment of the request's path, e.g. `acme` for `/acme/orders`.
+    pub fn path_prefix(req: &ServiceRequest) -> ProviderKey {
+        let segment = req.path().trim_start_matches('/').split('/').next();
+        ProviderKey(segment.unwrap_or_default().to_string())
+    }
+}
+
+impl From<&str> for ProviderKey {
+    fn from(key: &str) -> Self {
+        ProviderKey::new(key)
+    }
+}
+
+impl From<String> for ProviderKey {
+    fn from(key: String) -> Self {
+        ProviderKey(key)
+    }
+}
+
+impl fmt::Display for ProviderKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Clients of several providers, each request handled by the one its [`ProviderKey`] names:
+///
+/// ```ignore
+/// let acme = ActixWebOpenId::builder()
+///     .issuer_url("https://login.microsoftonline.com/<acme tenant>/v2.0")
+///     .redirect_url("https://acme.example.com/auth_callback")
+///     .namespace_cookies(true)
+///     // ...
+///     .build()
+///     .await?;
+/// let registry = ProviderRegistry::new(
+///     ProviderKey::host,
+///     [("acme.example.com".into(), acme), ("globex.example.com".into(), globex)],
+/// )?;
+/// App::new()
+///     .wrap(registry.get_middleware())
+///     .configure(registry.configure_open_id())
+/// ```
+///
+/// Each client authenticates like its own middleware would, with its `should_auth`. Requests
+/// with a key no client is registered for are answered `404 Not Found`.
+///
+/// The callback completes the login with the client whose [callback
+/// path](crate::OpenIdBuilder::callback_path) it was sent to when the clients' paths differ, and
+/// with the client of its key otherwise: keys read from the path, like
+/// [`ProviderKey::path_prefix`], need a callback path per client. The clients' cookies must be
+/// kept apart, with [`namespace_cookies`](crate::OpenIdBuilder::namespace_cookies) or a
+/// [`Realm`](crate::Realm) each, so that sessions do not cross to another provider.
+#[derive(Clone)]
+pub struct ProviderRegistry {
+    key: fn(&ServiceRequest) -> ProviderKey,
+    clients: Arc<HashMap<ProviderKey, ActixWebOpenId>>,
+    /// The callback paths used by a single client.
+    callbacks: Arc<HashMap<String, ProviderKey>>,
+}
+
+impl ProviderRegistry {
+    /// Fails if two clients share a key or their cookies, see [`ProviderRegistry`].
+    pub fn new(
+        key: fn(&ServiceRequest) -> ProviderKey,
+        clients: impl IntoIterator<Item = (ProviderKey, ActixWebOpenId)>,
+    ) -> crate::Result<Self> {
+        let mut registered: HashMap<ProviderKey, ActixWebOpenId> = HashMap::new();
+        for (provider_key, openid) in clients {
+            let session_cookie = |openid: &ActixWebOpenId| {
+                openid
+                    .openid_client()
+                    .realm()
+                    .cookie_name(AuthCookies::SessionId)
+                    .to_string()
+            };
+            if let Some((other, _)) = registered
+                .iter()
+                .find(|(_, other)| session_cookie(other) == session_cookie(&openid))
+            {
+                return Err(OpenIdError::Config(format!(
+                    "the providers {} and {} share their cookies, their sessions would mix",
+                    other, provider_key
+                )));
+            }
+            if registered.insert(provider_key.clone(), openid).is_some() {
+                return Err(OpenIdError::Config(format!(
+                    "the provider key {} is used twice",
+                    provider_key
+                )));
+            }
+        }
+        if registered.is_empty() {
+            return Err(OpenIdError::Config("no providers".to_string()));
+        }
+        let mut callbacks = HashMap::new();
+        let mut shared = HashSet::new();
+        for (provider_key, openid) in &registered {
+            let path = openid.openid_client().realm().callback_path();
+            if callbacks
+                .insert(path.clone(), provider_key.clone())
+                .is_some()
+            {
+                shared.insert(path);
+            }
+        }
+        callbacks.retain(|path, _| !shared.contains(path));
+        Ok(ProviderRegistry {
+            key,
+            clients: Arc::new(registered),
+            callbacks: Arc::new(callbacks),
+        })
+    }
+
+    /// The client registered for `key`.
+    pub fn get(&self, key: &ProviderKey) -> Option<&ActixWebOpenId> {
+        self.clients.get(key)
+    }
+
+    /// The client handling `req`, e.g. for a middleware of the app running before the
+    /// registry's.
+    pub fn client_for(&self, req: &ServiceRequest) -> Option<&Arc<OpenID>> {
+        self.get(&self.resolve(req))
+            .map(ActixWebOpenId::openid_client)
+    }
+
+    /// Registers the endpoints of every client, once for the clients sharing their paths.
+    pub fn configure_open_id(&self) -> impl Fn(&mut ServiceConfig) {
+        let clients = self.clients.clone();
+        move |cfg: &mut ServiceConfig| {
+            let mut registered = HashSet::new();
+            for openid in clients.values() {
+                let client = openid.openid_client();
+                let realm = client.realm();
+                let paths = (
+                    realm.callback_path(),
+                    realm.logout_path(),
+                    client.front_channel_logout().map(str::to_string),
+                    client.back_channel_logout().map(str::to_string),
+                    client.not_before().accepts_pushes(),
+                );
+                if registered.insert(paths) {
+                    crate::configure_endpoints(cfg, client);
+                }
+            }
+        }
+    }
+
+    pub fn get_middleware(&self) -> ProviderRegistryMiddlewareFactory {
+        ProviderRegistryMiddlewareFactory {
+            registry: self.clone(),
+        }
+    }
+
+    /// The key of the client handling `req`.
+    fn resolve(&self, req: &ServiceRequest) -> ProviderKey {
+        match self.callbacks.get(req.path()) {
+            Some(provider_key) => provider_key.clone(),
+            None => (self.key)(req),
+        }
+    }
+}
+
+pub struct ProviderRegistryMiddlewareFactory {
+    registry: ProviderRegistry,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ProviderRegistryMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ProviderRegistryMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let service = Rc::new(service);
+        let clients = self
+            .registry
+            .clients
+            .iter()
+            .filter_map(|(provider_key, openid)| {
+                let middleware = openid
+                    .get_middleware()
+                    .new_transform(Shared(service.clone()))
+                    .into_inner()
+                    .ok()?;
+                Some((provider_key.clone(), middleware))
+            })
+            .collect();
+        ready(Ok(ProviderRegistryMiddleware {
+            registry: self.registry.clone(),
+            clients,
+            service,
+        }))
+    }
+}
+
+/// Hands each request to the middleware of the client its key names.
+pub struct ProviderRegistryMiddleware<S> {
+    registry: ProviderRegistry,
+    clients: HashMap<ProviderKey, OpenIdMiddleware<Shared<S>>>,
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ProviderRegistryMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let provider_key = self.registry.resolve(&req);
+        match self.clients.get(&provider_key) {
+            Some(middleware) => middleware.call(req),
+            None => {
+                let err =
+                    actix_web::error::ErrorNotFound(format!("no provider for {}", provider_key));
+                Box::pin(async move { Err(err) })
+            }
+        }
+    }
+}
+
+/// The app's service, shared by the middlewares of the clients.
+struct Shared<S>(Rc<S>);
+
+impl<S: Service<ServiceRequest>> Service<ServiceRequest> for Shared<S> {
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        self.0.call(req)
+    }
+}
+
+impl fmt::Debug for ProviderRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderRegistry")
+            .field("providers", &self.clients.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
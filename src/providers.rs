@@ -0,0 +1,449 @@
+//! Several providers to log in with, picked by the user from a generated chooser.
+
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_web::cookie::{time::Duration as CookieDuration, Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::web::ServiceConfig;
+use actix_web::{get, web, Error, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+use url::form_urlencoded;
+
+use crate::error::OpenIdError;
+use crate::logging::LogCategory;
+use crate::messages::MessageKey;
+use crate::openid::OpenID;
+use crate::openid_middleware::{
+    auth_endpoint, auth_form_endpoint, insert_auth_result, internal_error, local_path,
+    logout_endpoint, page_response, request_target, returns_after_login, secure_forwarded_cookies,
+    session_user, AuthCookies, AuthenticationRequired, RealmClient, SessionUpdate,
+};
+use crate::pages::{PageContext, PageKind};
+use crate::should_auth::ShouldAuth;
+use crate::ActixWebOpenId;
+
+/// How a provider is listed in the chooser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProviderEntry {
+    /// Names the provider in `/login?provider=` and in its redirect url.
+    pub id: String,
+    pub display_name: String,
+    pub icon_url: Option<String>,
+    /// Providers are listed by ascending sort order, then in the order they were added.
+    pub sort_order: i32,
+}
+
+impl ProviderEntry {
+    pub fn new(id: impl Into<String>, display_name: impl Into<String>) -> Self {
+        ProviderEntry {
+            id: id.into(),
+            display_name: display_name.into(),
+            icon_url: None,
+            sort_order: 0,
+        }
+    }
+
+    pub fn icon_url(mut self, icon_url: impl Into<String>) -> Self {
+        self.icon_url = Some(icon_url.into());
+        self
+    }
+
+    pub fn sort_order(mut self, sort_order: i32) -> Self {
+        self.sort_order = sort_order;
+        self
+    }
+}
+
+/// A provider as rendered in the chooser, `href` starts the login with it.
+pub struct ProviderLink<'a> {
+    pub entry: &'a ProviderEntry,
+    pub href: String,
+}
+
+/// Names the provider unauthenticated requests are sent to instead of the chooser.
+const PREFERRED_PROVIDER: &str = "preferred_provider";
+const PREFERRED_PROVIDER_MAX_AGE: CookieDuration = CookieDuration::days(365);
+
+struct Provider {
+    entry: ProviderEntry,
+    client: Arc<OpenID>,
+}
+
+/// Clients of several providers mounted together, users pick one at `/login`.
+///
+/// Each client keeps its session in its own [`Realm`](crate::Realm), all mounted at the same
+/// path, and its redirect url names it with a `provider` query parameter:
+///
+/// ```ignore
+/// let google = ActixWebOpenId::builder()
+///     .realm(Realm::new("google", "/"))
+///     .redirect_url("https://example.com/auth_callback?provider=google")
+///     // ...
+///     .build()
+///     .await?;
+/// let providers = LoginProviders::new(
+///     |req| req.path() != "/auth_callback",
+///     [(ProviderEntry::new("google", "Google"), google), (ProviderEntry::new("azure", "Azure AD"), azure)],
+/// )?;
+/// App::new()
+///     .wrap(providers.get_middleware())
+///     .configure(providers.configure_open_id())
+/// ```
+///
+/// The chooser is rendered by the [`PageRenderer`](crate::PageRenderer) of the first provider
+/// listed, with its messages. It never requires a login and sets no cookies,
+/// `/login?provider=<id>` skips it and redirects straight to that provider.
+///
+/// Once logged in, the `preferred_provider` cookie remembers the provider and later logins go
+/// straight to it, until the user visits `/login?choose=1` to pick another one.
+#[derive(Clone)]
+pub struct LoginProviders {
+    providers: Arc<[Provider]>,
+    chooser_path: Arc<str>,
+    callback_path: Arc<str>,
+    should_auth: ShouldAuth,
+}
+
+impl LoginProviders {
+    /// Fails if the providers are not set up to be told apart, see [`LoginProviders`].
+    pub fn new(
+        should_auth: impl Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+        providers: impl IntoIterator<Item = (ProviderEntry, ActixWebOpenId)>,
+    ) -> crate::Result<Self> {
+        let mut providers: Vec<_> = providers
+            .into_iter()
+            .map(|(entry, openid)| Provider {
+                entry,
+                client: openid.openid_client().clone(),
+            })
+            .collect();
+        providers.sort_by_key(|provider| provider.entry.sort_order);
+        let first = providers
+            .first()
+            .ok_or_else(|| OpenIdError::Config("no login providers".to_string()))?;
+        let realm = first.client.realm().clone();
+        for (index, provider) in providers.iter().enumerate() {
+            let id = &provider.entry.id;
+            if id.is_empty()
+                || !id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(OpenIdError::Config(format!(
+                    "the provider id {:?} is not made of letters, digits, - and _",
+                    id
+                )));
+            }
+            let client_realm = provider.client.realm();
+            if client_realm.path() != realm.path() {
+                return Err(OpenIdError::Config(format!(
+                    "the provider {} is mounted at {} instead of {}",
+                    id,
+                    client_realm.path(),
+                    realm.path()
+                )));
+            }
+            // The endpoints are registered once, for all providers.
+            if client_realm.callback_path() != realm.callback_path()
+                || client_realm.logout_path() != realm.logout_path()
+            {
+                return Err(OpenIdError::Config(format!(
+                    "the provider {} has other callback or logout paths than {}",
+                    id, first.entry.id
+                )));
+            }
+            for other in &providers[..index] {
+                if other.entry.id == *id {
+                    return Err(OpenIdError::Config(format!(
+                        "the provider id {} is used twice",
+                        id
+                    )));
+                }
+                if other.client.realm().id() == client_realm.id() {
+                    return Err(OpenIdError::Config(format!(
+                        "the providers {} and {} share the realm {}, their sessions would mix",
+                        other.entry.id,
+                        id,
+                        client_realm.id()
+                    )));
+                }
+            }
+            let names_provider = provider
+                .client
+                .redirect_url()
+                .query_pairs()
+                .any(|(name, value)| name == "provider" && value == id.as_str());
+            if !names_provider {
+                return Err(OpenIdError::Config(format!(
+                    "the redirect url {} does not end with ?provider={}",
+                    provider.client.redirect_url(),
+                    id
+                )));
+            }
+        }
+        Ok(LoginProviders {
+            providers: providers.into(),
+            chooser_path: realm.login_path().into(),
+            callback_path: realm.callback_path().into(),
+            should_auth: ShouldAuth::new(should_auth),
+        })
+    }
+
+    pub fn configure_open_id(&self) -> impl Fn(&mut ServiceConfig) {
+        let providers = self.clone();
+        move |cfg: &mut ServiceConfig| {
+            let realm = providers.default_client().realm();
+            cfg.route(realm.callback_route(), web::get().to(auth_endpoint))
+                .route(realm.callback_route(), web::post().to(auth_form_endpoint))
+                .route(realm.logout_route(), web::get().to(logout_endpoint))
+                .service(login_endpoint)
+                .app_data(web::Data::new(providers.clone()));
+        }
+    }
+
+    pub fn get_middleware(&self) -> LoginProvidersMiddlewareFactory {
+        LoginProvidersMiddlewareFactory {
+            providers: self.clone(),
+        }
+    }
+
+    fn find(&self, id: &str) -> Option<&Provider> {
+        self.providers
+            .iter()
+            .find(|provider| provider.entry.id == id)
+    }
+
+    /// The client rendering the chooser and the responses of requests without a provider.
+    fn default_client(&self) -> &Arc<OpenID> {
+        &self.providers[0].client
+    }
+
+    /// The provider the user last logged in with, if it is still configured.
+    fn preferred_provider(&self, req: &ServiceRequest) -> Option<&Provider> {
+        self.find(req.cookie(PREFERRED_PROVIDER)?.value())
+    }
+
+    fn preferred_provider_cookie(&self, id: String) -> Cookie<'static> {
+        Cookie::build(PREFERRED_PROVIDER, id)
+            .path(self.providers[0].client.realm().path().to_string())
+            .max_age(PREFERRED_PROVIDER_MAX_AGE)
+            .same_site(SameSite::Lax)
+            .secure(true)
+            .http_only(true)
+            .finish()
+    }
+
+    fn login_href(&self, id: &str, next: &str) -> String {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("provider", id)
+            .append_pair("next", next)
+            .finish();
+        format!("{}?{}", self.chooser_path, query)
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginQuery {
+    provider: Option<String>,
+    next: Option<String>,
+    /// `1` forgets the preferred provider, e.g. to switch accounts.
+    choose: Option<String>,
+}
+
+#[get("/login")]
+async fn login_endpoint(
+    req: HttpRequest,
+    providers: web::Data<LoginProviders>,
+    query: web::Query<LoginQuery>,
+) -> HttpResponse {
+    let next = query.next.as_deref().and_then(local_path).unwrap_or("/");
+    if let Some(provider) = query.provider.as_deref().and_then(|id| providers.find(id)) {
+        return AuthenticationRequired::new(&provider.client, next, None)
+            .reached_through(&req)
+            .error_response();
+    }
+    let links: Vec<_> = providers
+        .providers
+        .iter()
+        .map(|provider| ProviderLink {
+            entry: &provider.entry,
+            href: providers.login_href(&provider.entry.id, next),
+        })
+        .collect();
+    let client = providers.default_client();
+    let csp_nonce = client.random_token();
+    let mut response = page_response(
+        client,
+        PageKind::ProviderChooser,
+        &PageContext {
+            status: StatusCode::OK,
+            message: &client.message(MessageKey::ChooseProvider, &[]),
+            target_url: Some(next),
+            error: None,
+            providers: &links,
+            csp_nonce: &csp_nonce,
+        },
+    );
+    if matches!(query.choose.as_deref(), Some("1" | "true")) {
+        let mut removal = providers.preferred_provider_cookie(String::new());
+        removal.make_removal();
+        if let Err(err) = response.add_cookie(&removal) {
+            return internal_error(client, "cannot clear the preferred provider", err);
+        }
+    }
+    response
+}
+
+pub struct LoginProvidersMiddlewareFactory {
+    providers: LoginProviders,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LoginProvidersMiddlewareFactory
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = LoginProvidersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LoginProvidersMiddleware {
+            providers: self.providers.clone(),
+            service: Rc::new(service),
+        }))
+    }
+}
+
+/// Authenticates with the session of whichever provider the user logged in with.
+pub struct LoginProvidersMiddleware<S> {
+    providers: LoginProviders,
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for LoginProvidersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let srv = self.service.clone();
+        let providers = self.providers.clone();
+
+        Box::pin(async move {
+            let mut session = None;
+            for provider in providers.providers.iter() {
+                if let Some(auth_user) = session_user(&provider.client, &req).await? {
+                    session = Some((provider.client.clone(), auth_user));
+                    break;
+                }
+            }
+            let should_auth = req.path() != &*providers.chooser_path
+                && req.path() != &*providers.callback_path
+                && providers.should_auth.check(&req);
+            // The login cannot return to a form posted after the session expired.
+            let answer = (!returns_after_login(&req)).then_some(StatusCode::UNAUTHORIZED);
+            let (client, auth_user) = match session {
+                None => {
+                    let client = providers.default_client();
+                    let target = request_target(req.request());
+                    let required = match providers.preferred_provider(&req) {
+                        Some(provider) => {
+                            AuthenticationRequired::new(&provider.client, target, None)
+                        }
+                        None => AuthenticationRequired::choose_provider(
+                            client,
+                            &providers.chooser_path,
+                            target,
+                        ),
+                    };
+                    let required = required.answering(answer).reached_through(req.request());
+                    if should_auth {
+                        client.log_policy().log(
+                            LogCategory::UnauthenticatedRequest,
+                            format_args!("No session for {}, redirecting to login", req.path()),
+                        );
+                        return Err(required.into());
+                    }
+                    (None, Err(required))
+                }
+                Some((_, Err(err))) if should_auth => {
+                    return Err(err.answering(answer).reached_through(req.request()).into())
+                }
+                Some((client, auth_user)) => (Some(client), auth_user),
+            };
+            // The callback completes the login with the provider its redirect url names.
+            let callback_provider = match req.path() == &*providers.callback_path {
+                true => web::Query::<LoginQuery>::from_query(req.query_string())
+                    .ok()
+                    .and_then(|query| providers.find(query.provider.as_deref()?)),
+                false => None,
+            };
+            let realm_client = callback_provider
+                .map(|provider| &provider.client)
+                .or(client.as_ref());
+            if let Some(realm_client) = realm_client {
+                req.extensions_mut()
+                    .insert(RealmClient(realm_client.clone()));
+            }
+            insert_auth_result(&mut req.extensions_mut(), auth_user);
+            let update = client
+                .as_ref()
+                .map(|client| SessionUpdate::of(client, req.request()));
+            let mut res = match (srv.call(req).await, &client, update) {
+                (Ok(res), _, _) => res,
+                (Err(err), Some(client), Some(update)) => {
+                    return Err(update.apply_to_error(client, err).await)
+                }
+                (Err(err), _, _) => return Err(err),
+            };
+            if let Some(client) = client {
+                let update = SessionUpdate::of(&client, res.request());
+                update.apply(&client, res.response_mut()).await;
+                let forwarded_https = client.forwarded_https(res.request());
+                secure_forwarded_cookies(&client, forwarded_https, res.response_mut());
+            }
+            if let Some(provider) = callback_provider {
+                remember_provider(&providers, provider, &mut res);
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// Prefers the provider of a callback that logged the user in, the session cookies show it did.
+fn remember_provider<B>(
+    providers: &LoginProviders,
+    provider: &Provider,
+    res: &mut ServiceResponse<B>,
+) {
+    let realm = provider.client.realm();
+    let session_cookies = [
+        realm.cookie_name(AuthCookies::AccessToken),
+        realm.cookie_name(AuthCookies::SessionId),
+    ];
+    let logged_in = res
+        .response()
+        .cookies()
+        .any(|cookie| session_cookies.contains(&cookie.name()) && !cookie.value().is_empty());
+    if !logged_in {
+        return;
+    }
+    let cookie = providers.preferred_provider_cookie(provider.entry.id.clone());
+    if let Err(err) = res.response_mut().add_cookie(&cookie) {
+        provider.client.log_policy().log_error(
+            LogCategory::LoginSuccess,
+            format_args!("Could not store the preferred provider: {}", err),
+        );
+    }
+}
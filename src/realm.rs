@@ -0,0 +1,149 @@
+//! Realms, isolating OpenID clients mounted under different scopes of one app.
+
+use crate::openid_middleware::AuthCookies;
+
+/// Where an OpenID client is mounted, and how its cookies are kept apart from other realms'.
+///
+/// Each realm has its own middleware and endpoints, mounted below its path:
+///
+/// ```ignore
+/// App::new().service(
+///     web::scope("/internal")
+///         .wrap(internal.get_middleware())
+///         .configure(internal.configure_open_id())
+///         .service(dashboard),
+/// )
+/// ```
+///
+/// Its cookies are prefixed with the realm id and only sent below the path, and the redirect url
+/// must point to the callback below it, e.g. `https://example.com/internal/auth_callback`. The
+/// endpoints' paths are set with [`OpenIdBuilder::callback_path`](crate::OpenIdBuilder::callback_path)
+/// and [`OpenIdBuilder::logout_path`](crate::OpenIdBuilder::logout_path).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Realm {
+    id: String,
+    path: String,
+    /// Indexed by [`AuthCookies`], built once so requests only borrow them.
+    cookie_names: Vec<String>,
+    /// Paths of the endpoints, relative to `path`.
+    callback_route: String,
+    logout_route: String,
+}
+
+impl Realm {
+    /// The realm `id` mounted at `path`, e.g. `Realm::new("internal", "/internal")`.
+    pub fn new(id: impl Into<String>, path: impl Into<String>) -> Self {
+        let id = id.into();
+        let prefix = format!("{}_", id);
+        Realm::with_prefix(id, path.into(), &prefix)
+    }
+
+    fn with_prefix(id: String, path: String, prefix: &str) -> Self {
+        let cookie_names = AuthCookies::ALL
+            .iter()
+            .map(|cookie| format!("{}{}", prefix, cookie.name()))
+            .collect();
+        let path = match path.trim_end_matches('/') {
+            "" => "/".to_string(),
+            path => path.to_string(),
+        };
+        Realm {
+            id,
+            path,
+            cookie_names,
+            callback_route: "/auth_callback".to_string(),
+            logout_route: "/logout".to_string(),
+        }
+    }
+
+    /// The realm at the root with an id derived from the client, so that clients sharing a
+    /// domain, e.g. services on different ports, keep separate sessions.
+    pub(crate) fn namespaced(issuer_url: &str, client_id: &str) -> Self {
+        Realm::new(
+            format!("oidc{:08x}", namespace_hash(issuer_url, client_id)),
+            "/",
+        )
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Mount point of the realm, the path of its cookies.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Path of the realm's callback endpoint.
+    pub fn callback_path(&self) -> String {
+        format!("{}{}", self.path.trim_end_matches('/'), self.callback_route)
+    }
+
+    /// Path of the realm's login endpoint, starting the login for links on pages where it is
+    /// optional.
+    pub fn login_path(&self) -> String {
+        format!("{}/login", self.path.trim_end_matches('/'))
+    }
+
+    /// Path of the realm's logout endpoint.
+    pub fn logout_path(&self) -> String {
+        format!("{}{}", self.path.trim_end_matches('/'), self.logout_route)
+    }
+
+    /// Path of the callback endpoint within the realm's scope.
+    pub(crate) fn callback_route(&self) -> &str {
+        &self.callback_route
+    }
+
+    /// Path of the logout endpoint within the realm's scope.
+    pub(crate) fn logout_route(&self) -> &str {
+        &self.logout_route
+    }
+
+    /// The realm with its endpoints at `callback` and `logout`.
+    pub(crate) fn with_routes(mut self, callback: String, logout: String) -> Self {
+        self.callback_route = callback;
+        self.logout_route = logout;
+        self
+    }
+
+    /// The realm with `prefix` prepended to its cookie names.
+    pub(crate) fn with_cookie_prefix(mut self, prefix: &str) -> Self {
+        if !prefix.is_empty() {
+            for name in &mut self.cookie_names {
+                name.insert_str(0, prefix);
+            }
+        }
+        self
+    }
+
+    pub(crate) fn cookie_name(&self, cookie: AuthCookies) -> &str {
+        &self.cookie_names[cookie as usize]
+    }
+
+    /// Whether `name` is one of the realm's cookies, or a login attempt's or chunk of one.
+    pub(crate) fn owns_cookie(&self, name: &str) -> bool {
+        self.cookie_names.iter().any(|own| {
+            name.strip_prefix(own.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    }
+}
+
+impl Default for Realm {
+    /// The realm of a client mounted at the root, with unprefixed cookie names.
+    fn default() -> Self {
+        Realm::with_prefix("default".to_string(), "/".to_string(), "")
+    }
+}
+
+/// FNV-1a, stable across releases and platforms unlike `DefaultHasher`, cookie names must not
+/// change with the compiler.
+fn namespace_hash(issuer_url: &str, client_id: &str) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in issuer_url.bytes().chain([0]).chain(client_id.bytes()) {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
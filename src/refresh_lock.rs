@@ -0,0 +1,85 @@
+//! Serializes the refreshes of a refresh token, the requests that waited reuse the tokens the
+//! first one got.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use openidconnect::RefreshToken;
+use sha2::{Digest, Sha256};
+
+use crate::clock::{system_time, Clock};
+use crate::error::Result;
+use crate::openid::RefreshedTokens;
+
+/// How long the tokens of a refresh are handed to requests still presenting the refresh token
+/// they replaced, e.g. requests the browser sent before it got the new cookies. Providers
+/// rotating refresh tokens would take the replaced token being sent again for a theft.
+const REUSE_WINDOW: Duration = Duration::from_secs(30);
+
+struct Refreshed {
+    tokens: RefreshedTokens,
+    at: SystemTime,
+}
+
+/// Held while a refresh is in flight, then holding its tokens.
+type Refresh = Arc<tokio::sync::Mutex<Option<Refreshed>>>;
+
+/// The refreshes by the SHA-256 of the refresh token they exchange, which is not kept.
+pub(crate) struct RefreshLock {
+    clock: Arc<dyn Clock>,
+    /// Dropped with their last waiter, or once their outcome left the reuse window.
+    refreshes: Mutex<HashMap<[u8; 32], Refresh>>,
+}
+
+impl RefreshLock {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        RefreshLock {
+            clock,
+            refreshes: Mutex::default(),
+        }
+    }
+
+    /// Runs `exchange` for `refresh_token` unless another request is already doing so, or did
+    /// within the reuse window: those wait for the other request and get its tokens. Failed
+    /// exchanges are not shared, the next request in line tries again.
+    pub(crate) async fn refresh<F, Fut>(
+        &self,
+        refresh_token: &RefreshToken,
+        exchange: F,
+    ) -> Result<RefreshedTokens>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<RefreshedTokens>>,
+    {
+        let key: [u8; 32] = Sha256::digest(refresh_token.secret()).into();
+        let lock = {
+            let now = system_time(self.clock.as_ref());
+            let mut refreshes = self.refreshes.lock().unwrap();
+            refreshes.retain(|_, lock| {
+                Arc::strong_count(lock) > 1
+                    || lock.try_lock().is_ok_and(|refreshed| {
+                        refreshed
+                            .as_ref()
+                            .is_some_and(|refreshed| now < refreshed.at + REUSE_WINDOW)
+                    })
+            });
+            refreshes.entry(key).or_default().clone()
+        };
+        let mut refreshed = lock.lock().await;
+        let now = system_time(self.clock.as_ref());
+        if let Some(refreshed) = refreshed
+            .as_ref()
+            .filter(|refreshed| now < refreshed.at + REUSE_WINDOW)
+        {
+            return Ok(refreshed.tokens.clone());
+        }
+        let tokens = exchange().await?;
+        *refreshed = Some(Refreshed {
+            tokens: tokens.clone(),
+            at: system_time(self.clock.as_ref()),
+        });
+        Ok(tokens)
+    }
+}
@@ -0,0 +1,75 @@
+//! Known-dangerous settings, reported at build time in release builds.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::openid::{IssuerValidation, OpenID};
+
+/// The dangerous setting a [`Finding`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SecurityCheck {
+    /// The provider is reached over plain http, tokens travel unencrypted.
+    HttpIssuer,
+    /// The `iss` claim of ID tokens is not checked.
+    IssuerNotValidated,
+    /// The auth cookies are also sent over plain http.
+    InsecureCookies,
+    /// A client without a secret exchanges authorization codes without PKCE.
+    PublicClientWithoutPkce,
+}
+
+/// A dangerous setting found by [`OpenID::security_report`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub check: SecurityCheck,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.check, self.message)
+    }
+}
+
+impl OpenID {
+    /// Lists the dangerous settings currently active.
+    ///
+    /// Release builds log each finding at error level when the client is built, or fail the build
+    /// in strict mode. Debug builds only report them here.
+    pub fn security_report(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        let provider_metadata = self.provider_metadata();
+        let issuer = provider_metadata.issuer();
+        if issuer.url().scheme() == "http" {
+            findings.push(Finding {
+                check: SecurityCheck::HttpIssuer,
+                message: format!("the issuer {} is reached over http", issuer.as_str()),
+            });
+        }
+
+        if self.issuer_validation() == &IssuerValidation::Skip {
+            findings.push(Finding {
+                check: SecurityCheck::IssuerNotValidated,
+                message: "ID tokens are accepted from any issuer".to_string(),
+            });
+        }
+
+        if !self.cookie_config().is_secure() {
+            findings.push(Finding {
+                check: SecurityCheck::InsecureCookies,
+                message: "the auth cookies are sent without the Secure flag".to_string(),
+            });
+        }
+
+        if self.is_public_client() && !self.pkce() {
+            findings.push(Finding {
+                check: SecurityCheck::PublicClientWithoutPkce,
+                message: "the public client exchanges authorization codes without PKCE".to_string(),
+            });
+        }
+
+        findings
+    }
+}
@@ -0,0 +1,138 @@
+//! Sessions ending after an absolute lifetime or a time without requests, whatever the lifetimes
+//! of their tokens, see [`OpenIdBuilder::session_absolute_lifetime`] and
+//! [`OpenIdBuilder::session_idle_timeout`].
+//!
+//! [`OpenIdBuilder::session_absolute_lifetime`]: crate::OpenIdBuilder::session_absolute_lifetime
+//! [`OpenIdBuilder::session_idle_timeout`]: crate::OpenIdBuilder::session_idle_timeout
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long after the recorded last-seen time a request records it again, requests in between
+/// do not write the session.
+const SEEN_GRANULARITY: Duration = Duration::from_secs(60);
+
+/// The limit a session outlived, see [`OpenIdError::SessionExpired`](crate::OpenIdError::SessionExpired).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionLimit {
+    /// The user logged in, at the provider, longer than the absolute lifetime ago. Also sessions
+    /// without a recorded login time, e.g. logged in before the limits were set.
+    Absolute,
+    /// The session made no request for longer than the idle timeout.
+    Idle,
+}
+
+impl fmt::Display for SessionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SessionLimit::Absolute => "absolute lifetime",
+            SessionLimit::Idle => "idle timeout",
+        })
+    }
+}
+
+/// When a session's user logged in, and when the session last made a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SessionTimes {
+    pub(crate) authenticated_at: SystemTime,
+    pub(crate) last_seen: SystemTime,
+}
+
+impl SessionTimes {
+    /// The times of a login at `now`, at the provider's `auth_time` if it sent one: a session
+    /// the provider kept for longer counts from when the user entered their credentials.
+    pub(crate) fn login(auth_time: Option<SystemTime>, now: SystemTime) -> Self {
+        SessionTimes {
+            authenticated_at: auth_time.map_or(now, |auth_time| auth_time.min(now)),
+            last_seen: now,
+        }
+    }
+
+    /// The times of a cookie, as Unix seconds separated by a dot. `None` also for times past
+    /// what the clock can tell.
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let (authenticated_at, last_seen) = value.split_once('.')?;
+        let time = |secs: &str| UNIX_EPOCH.checked_add(Duration::from_secs(secs.parse().ok()?));
+        Some(SessionTimes {
+            authenticated_at: time(authenticated_at)?,
+            last_seen: time(last_seen)?,
+        })
+    }
+
+    pub(crate) fn cookie_value(&self) -> String {
+        format!(
+            "{}.{}",
+            unix_secs(self.authenticated_at),
+            unix_secs(self.last_seen)
+        )
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The limits of the sessions, none by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SessionLimits {
+    pub(crate) absolute_lifetime: Option<Duration>,
+    pub(crate) idle_timeout: Option<Duration>,
+}
+
+impl SessionLimits {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.absolute_lifetime.is_some() || self.idle_timeout.is_some()
+    }
+
+    /// The limit a session with `times` outlived at `now`, `None` while it lasts.
+    pub(crate) fn exceeded(
+        &self,
+        times: Option<&SessionTimes>,
+        now: SystemTime,
+    ) -> Option<SessionLimit> {
+        let Some(times) = times else {
+            return Some(SessionLimit::Absolute);
+        };
+        // A limit ending past what the clock can tell counts as outlived, the times are bogus.
+        let outlived = |since: SystemTime, limit: Option<Duration>| {
+            limit.is_some_and(|limit| since.checked_add(limit).is_none_or(|end| end <= now))
+        };
+        if outlived(times.authenticated_at, self.absolute_lifetime) {
+            Some(SessionLimit::Absolute)
+        } else if outlived(times.last_seen, self.idle_timeout) {
+            Some(SessionLimit::Idle)
+        } else {
+            None
+        }
+    }
+
+    /// The times to record for a request of the session at `now`, `None` if the recorded ones
+    /// are recent enough or nothing slides.
+    pub(crate) fn seen(&self, times: &SessionTimes, now: SystemTime) -> Option<SessionTimes> {
+        let stale = now.duration_since(times.last_seen).unwrap_or_default() >= SEEN_GRANULARITY;
+        (self.idle_timeout.is_some() && stale).then_some(SessionTimes {
+            last_seen: now,
+            ..*times
+        })
+    }
+
+    /// How long the cookie recording `times` is kept from `now`: until the session outlives
+    /// either limit, unless a request slides the idle one first.
+    pub(crate) fn cookie_max_age(&self, times: &SessionTimes, now: SystemTime) -> Duration {
+        let remaining = |since: SystemTime, limit: Duration| {
+            since
+                .checked_add(limit)
+                .and_then(|end| end.duration_since(now).ok())
+                .unwrap_or_default()
+        };
+        let absolute = self
+            .absolute_lifetime
+            .map(|limit| remaining(times.authenticated_at, limit));
+        let idle = self
+            .idle_timeout
+            .map(|limit| remaining(times.last_seen, limit));
+        absolute.into_iter().chain(idle).min().unwrap_or_default()
+    }
+}
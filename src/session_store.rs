@@ -0,0 +1,405 @@
+//! Server-side sessions, the session cookie then only holds a random id.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use openidconnect::{AccessToken, RefreshToken};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::error::{OpenIdError, Result};
+use crate::openid::{OpenIDTokens, RefreshedTokens};
+use crate::session_limits::SessionTimes;
+
+/// Keeps the tokens of the sessions by their id instead of the session cookies, which carry a
+/// few kilobytes of tokens with every request otherwise. Selected with
+/// [`session_store`](crate::OpenIdBuilder::session_store).
+///
+/// Stores shared by several instances, e.g. in Redis, can keep the [`StoredSession`]s in any
+/// serde format, and fail with [`OpenIdError::SessionStore`](crate::OpenIdError::SessionStore).
+///
+/// ```ignore
+/// #[async_trait::async_trait]
+/// impl SessionStore for Redis {
+///     async fn get(&self, session_id: &str) -> actix_web_openidconnect::Result<Option<StoredSession>> {
+///         let value: Option<String> = self.pool.get(session_id).await.map_err(store_error)?;
+///         value.map(|value| PayloadCodec::decode(&value)).transpose().map_err(store_error)
+///     }
+///
+///     async fn insert(&self, session_id: &str, session: StoredSession) -> actix_web_openidconnect::Result<()> {
+///         let value = PayloadCodec::MessagePack.encode(&session).map_err(store_error)?;
+///         self.pool.set(session_id, value, session.expires_at()).await.map_err(store_error)
+///     }
+///
+///     async fn remove(&self, session_id: &str) -> actix_web_openidconnect::Result<()> {
+///         self.pool.del(session_id).await.map_err(store_error)
+///     }
+/// }
+/// ```
+#[async_trait::async_trait]
+pub trait SessionStore: Send + Sync {
+    /// The session stored under `session_id`, `None` if there is none.
+    async fn get(&self, session_id: &str) -> Result<Option<StoredSession>>;
+
+    /// Stores `session` under `session_id`, replacing the one stored before. It is not used past
+    /// its [`expires_at`](StoredSession::expires_at), the store may drop it then.
+    async fn insert(&self, session_id: &str, session: StoredSession) -> Result<()>;
+
+    async fn remove(&self, session_id: &str) -> Result<()>;
+
+    /// Removes the sessions a [back-channel logout](crate::OpenIdBuilder::back_channel_logout)
+    /// of the provider ended: those whose [`claims`](StoredSession::claims) carry the `sid` and
+    /// the `sub` equal to `subject`, either may be `None`. Answers how many were removed.
+    ///
+    /// Stores have to find the sessions by their claims for it, e.g. keeping the session ids by
+    /// `sid` and `sub`; the default fails.
+    async fn remove_logged_out(&self, subject: Option<&str>, sid: Option<&str>) -> Result<usize> {
+        let _ = (subject, sid);
+        Err(OpenIdError::SessionStore(
+            "the session store cannot find sessions by their claims".into(),
+        ))
+    }
+}
+
+/// A store shared with the application, e.g. to end the sessions of a user it locked.
+#[async_trait::async_trait]
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    async fn get(&self, session_id: &str) -> Result<Option<StoredSession>> {
+        (**self).get(session_id).await
+    }
+
+    async fn insert(&self, session_id: &str, session: StoredSession) -> Result<()> {
+        (**self).insert(session_id, session).await
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<()> {
+        (**self).remove(session_id).await
+    }
+
+    async fn remove_logged_out(&self, subject: Option<&str>, sid: Option<&str>) -> Result<usize> {
+        (**self).remove_logged_out(subject, sid).await
+    }
+}
+
+/// The tokens of a logged in session and the claims of its ID token.
+///
+/// The tokens are serialized as they are, for the store to keep them; they are zeroed when
+/// dropped and left out of the `Debug` output.
+pub struct StoredSession {
+    access_token: SecretString,
+    id_token: Option<SecretString>,
+    refresh_token: Option<SecretString>,
+    access_token_expires_at: Option<SystemTime>,
+    expires_at: Option<SystemTime>,
+    claims: Value,
+    data: Option<Value>,
+    /// Recorded for the [session limits](crate::OpenIdBuilder::session_idle_timeout).
+    times: Option<SessionTimes>,
+}
+
+impl StoredSession {
+    /// The session of a login, kept for `max_age` or, without one, until it is removed.
+    pub(crate) fn new(
+        tokens: &OpenIDTokens,
+        claims: Value,
+        data: Option<Value>,
+        now: SystemTime,
+        max_age: Option<Duration>,
+    ) -> Self {
+        StoredSession {
+            access_token: SecretString::new(tokens.access_token.secret().clone()),
+            id_token: Some(SecretString::new(tokens.id_token.to_string())),
+            refresh_token: tokens
+                .refresh_token
+                .as_ref()
+                .map(|token| SecretString::new(token.secret().clone())),
+            access_token_expires_at: tokens.expires_in.map(|expires_in| now + expires_in),
+            expires_at: max_age.map(|max_age| now + max_age),
+            claims,
+            data,
+            times: None,
+        }
+    }
+
+    /// The session read from the cookies of a client without a store.
+    pub(crate) fn from_cookies(
+        access_token: String,
+        id_token: Option<String>,
+        refresh_token: Option<String>,
+        access_token_expires_at: Option<SystemTime>,
+        data: Option<Value>,
+    ) -> Self {
+        StoredSession {
+            access_token: SecretString::new(access_token),
+            id_token: id_token.map(SecretString::new),
+            refresh_token: refresh_token.map(SecretString::new),
+            access_token_expires_at,
+            expires_at: None,
+            claims: Value::Null,
+            data,
+            times: None,
+        }
+    }
+
+    /// The session recording `times`.
+    pub(crate) fn with_times(mut self, times: Option<SessionTimes>) -> Self {
+        self.times = times;
+        self
+    }
+
+    /// The session with the `refreshed` tokens, keeping the ID and refresh token the provider did
+    /// not replace.
+    pub(crate) fn refreshed(
+        &self,
+        refreshed: &RefreshedTokens,
+        now: SystemTime,
+        max_age: Option<Duration>,
+    ) -> Self {
+        let secret = |token: &String| SecretString::new(token.clone());
+        StoredSession {
+            access_token: secret(refreshed.access_token.secret()),
+            id_token: match &refreshed.id_token {
+                Some(id_token) => Some(SecretString::new(id_token.to_string())),
+                None => self
+                    .id_token
+                    .as_ref()
+                    .map(|token| secret(token.expose_secret())),
+            },
+            refresh_token: match &refreshed.refresh_token {
+                Some(refresh_token) => Some(secret(refresh_token.secret())),
+                None => self
+                    .refresh_token
+                    .as_ref()
+                    .map(|token| secret(token.expose_secret())),
+            },
+            access_token_expires_at: refreshed.expires_in.map(|expires_in| now + expires_in),
+            expires_at: max_age.map(|max_age| now + max_age),
+            claims: self.claims.clone(),
+            data: self.data.clone(),
+            times: self.times,
+        }
+    }
+
+    pub(crate) fn access_token(&self) -> AccessToken {
+        AccessToken::new(self.access_token.expose_secret().clone())
+    }
+
+    pub(crate) fn id_token(&self) -> Option<&str> {
+        self.id_token
+            .as_ref()
+            .map(|token| token.expose_secret().as_str())
+    }
+
+    pub(crate) fn refresh_token(&self) -> Option<RefreshToken> {
+        self.refresh_token
+            .as_ref()
+            .map(|token| RefreshToken::new(token.expose_secret().clone()))
+    }
+
+    pub(crate) fn access_token_expires_at(&self) -> Option<SystemTime> {
+        self.access_token_expires_at
+    }
+
+    /// When the user logged in and the session was last seen, if recorded.
+    pub(crate) fn times(&self) -> Option<&SessionTimes> {
+        self.times.as_ref()
+    }
+
+    /// Until when the session is used, `None` until it is removed.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at
+    }
+
+    /// The claims of the ID token the user logged in with.
+    pub fn claims(&self) -> &Value {
+        &self.claims
+    }
+
+    /// The data the [`LoginHook`](crate::LoginHook) stored with the session, if any.
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
+}
+
+impl Clone for StoredSession {
+    fn clone(&self) -> Self {
+        let secret = |token: &SecretString| SecretString::new(token.expose_secret().clone());
+        StoredSession {
+            access_token: secret(&self.access_token),
+            id_token: self.id_token.as_ref().map(secret),
+            refresh_token: self.refresh_token.as_ref().map(secret),
+            access_token_expires_at: self.access_token_expires_at,
+            expires_at: self.expires_at,
+            claims: self.claims.clone(),
+            data: self.data.clone(),
+            times: self.times,
+        }
+    }
+}
+
+/// Never the tokens or claims.
+impl fmt::Debug for StoredSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredSession")
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// How a [`StoredSession`] is serialized, times as Unix seconds.
+#[derive(Serialize, Deserialize)]
+struct Record<T> {
+    access_token: T,
+    id_token: Option<T>,
+    refresh_token: Option<T>,
+    access_token_expires_at: Option<u64>,
+    expires_at: Option<u64>,
+    claims: Value,
+    /// Absent in sessions stored before there was a login hook.
+    #[serde(default)]
+    data: Option<Value>,
+    /// Absent in sessions stored without session limits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    authenticated_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_seen: Option<u64>,
+}
+
+pub(crate) fn unix_secs(time: Option<SystemTime>) -> Option<u64> {
+    time.map(|time| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    })
+}
+
+/// The time `secs` after the Unix epoch, an error if it is past what the clock can tell.
+pub(crate) fn from_unix_secs<E: de::Error>(
+    secs: Option<u64>,
+) -> std::result::Result<Option<SystemTime>, E> {
+    secs.map(|secs| {
+        UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(|| E::custom(format!("time out of range: {}", secs)))
+    })
+    .transpose()
+}
+
+impl Serialize for StoredSession {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        Record {
+            access_token: self.access_token.expose_secret().as_str(),
+            id_token: self.id_token(),
+            refresh_token: self
+                .refresh_token
+                .as_ref()
+                .map(|token| token.expose_secret().as_str()),
+            access_token_expires_at: unix_secs(self.access_token_expires_at),
+            expires_at: unix_secs(self.expires_at),
+            claims: self.claims.clone(),
+            data: self.data.clone(),
+            authenticated_at: unix_secs(self.times.map(|times| times.authenticated_at)),
+            last_seen: unix_secs(self.times.map(|times| times.last_seen)),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for StoredSession {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let record = Record::<String>::deserialize(deserializer)?;
+        Ok(StoredSession {
+            access_token: SecretString::new(record.access_token),
+            id_token: record.id_token.map(SecretString::new),
+            refresh_token: record.refresh_token.map(SecretString::new),
+            access_token_expires_at: from_unix_secs(record.access_token_expires_at)?,
+            expires_at: from_unix_secs(record.expires_at)?,
+            claims: record.claims,
+            data: record.data,
+            times: from_unix_secs(record.authenticated_at)?
+                .zip(from_unix_secs(record.last_seen)?)
+                .map(|(authenticated_at, last_seen)| SessionTimes {
+                    authenticated_at,
+                    last_seen,
+                }),
+        })
+    }
+}
+
+/// Keeps the sessions in the memory of the process: they are lost when it restarts and not
+/// shared with other instances. Holds at most `max_entries` sessions, the expired ones and then
+/// those expiring soonest make room for new ones.
+pub struct InMemorySessionStore {
+    max_entries: usize,
+    sessions: Mutex<HashMap<String, StoredSession>>,
+}
+
+impl InMemorySessionStore {
+    pub fn new(max_entries: usize) -> Self {
+        InMemorySessionStore {
+            max_entries,
+            sessions: Mutex::default(),
+        }
+    }
+}
+
+/// Room for 10 000 sessions.
+impl Default for InMemorySessionStore {
+    fn default() -> Self {
+        InMemorySessionStore::new(10_000)
+    }
+}
+
+#[async_trait::async_trait]
+impl SessionStore for InMemorySessionStore {
+    async fn get(&self, session_id: &str) -> Result<Option<StoredSession>> {
+        Ok(self.sessions.lock().unwrap().get(session_id).cloned())
+    }
+
+    async fn insert(&self, session_id: &str, session: StoredSession) -> Result<()> {
+        if self.max_entries == 0 {
+            return Ok(());
+        }
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= self.max_entries && !sessions.contains_key(session_id) {
+            let now = SystemTime::now();
+            sessions.retain(|_, session| session.expires_at.is_none_or(|at| now < at));
+        }
+        if sessions.len() >= self.max_entries && !sessions.contains_key(session_id) {
+            // Sessions without an expiry last longest.
+            let soonest = sessions
+                .iter()
+                .min_by_key(|(_, session)| (session.expires_at.is_none(), session.expires_at))
+                .map(|(id, _)| id.clone());
+            if let Some(soonest) = soonest {
+                sessions.remove(&soonest);
+            }
+        }
+        sessions.insert(session_id.to_string(), session);
+        Ok(())
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<()> {
+        self.sessions.lock().unwrap().remove(session_id);
+        Ok(())
+    }
+
+    async fn remove_logged_out(&self, subject: Option<&str>, sid: Option<&str>) -> Result<usize> {
+        if subject.is_none() && sid.is_none() {
+            return Ok(0);
+        }
+        let claim_matches = |session: &StoredSession, name: &str, value: Option<&str>| {
+            value
+                .is_none_or(|value| session.claims.get(name).and_then(Value::as_str) == Some(value))
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| {
+            !(claim_matches(session, "sub", subject) && claim_matches(session, "sid", sid))
+        });
+        Ok(before - sessions.len())
+    }
+}
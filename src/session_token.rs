@@ -0,0 +1,179 @@
+//! The session's access token for calls to other services, refreshed on demand.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use actix_web::error::ErrorInternalServerError;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use futures_util::lock::Mutex;
+use openidconnect::{AccessToken, RefreshToken};
+use secrecy::{ExposeSecret, SecretString};
+
+use crate::logging::LogCategory;
+use crate::messages::MessageKey;
+use crate::openid::{OpenID, RefreshedTokens};
+use crate::openid_middleware::{request_session, Authenticated, RegisteredClient};
+
+/// Access tokens expiring within this window are refreshed before being handed out, or before
+/// the middleware authenticates a request with them.
+pub(crate) const REFRESH_WINDOW: Duration = Duration::from_secs(60);
+
+/// The current session's access token, for calling other services on behalf of the user.
+///
+/// Extracted in handlers behind the middleware, it fails like [`Authenticated`] when the request
+/// has no session. Clones share the token and are `Send`, they can be moved into spawned tasks.
+/// Tokens refreshed before the response is sent are stored in the session cookies by the
+/// middleware.
+#[derive(Clone)]
+pub struct SessionToken {
+    client: Arc<OpenID>,
+    state: Arc<Mutex<TokenState>>,
+}
+
+/// Never the tokens.
+impl fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionToken").finish_non_exhaustive()
+    }
+}
+
+struct TokenState {
+    access_token: SecretString,
+    refresh_token: Option<SecretString>,
+    expires_at: Option<SystemTime>,
+    /// Tokens not yet stored in the session cookies.
+    refreshed: Option<RefreshedTokens>,
+}
+
+impl TokenState {
+    fn access_token(&self) -> AccessToken {
+        AccessToken::new(self.access_token.expose_secret().clone())
+    }
+}
+
+impl SessionToken {
+    /// The access token, refreshed first when it expires within a minute and the session has a
+    /// refresh token.
+    pub async fn access_token(&self) -> AccessToken {
+        let mut state = self.state.lock().await;
+        let expiring = state
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= self.client.now() + REFRESH_WINDOW);
+        if expiring {
+            self.refresh(&mut state).await;
+        }
+        state.access_token()
+    }
+
+    /// Refreshes `rejected` after a service refused it, unless another clone already did.
+    ///
+    /// `None` if the session has no refresh token or the provider refused to refresh.
+    pub async fn refresh_rejected(&self, rejected: &AccessToken) -> Option<AccessToken> {
+        let mut state = self.state.lock().await;
+        if state.access_token.expose_secret() != rejected.secret() {
+            return Some(state.access_token());
+        }
+        self.refresh(&mut state).await
+    }
+
+    /// Takes the tokens refreshed since the last call, for the middleware to store.
+    pub(crate) async fn take_refreshed(&self) -> Option<RefreshedTokens> {
+        self.state.lock().await.refreshed.take()
+    }
+
+    async fn refresh(&self, state: &mut TokenState) -> Option<AccessToken> {
+        let refresh_token =
+            RefreshToken::new(state.refresh_token.as_ref()?.expose_secret().clone());
+        let mut tokens = match self.client.refresh(&refresh_token).await {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                self.client.log_policy().log(
+                    LogCategory::Refresh,
+                    format_args!("Could not refresh the access token: {}", err),
+                );
+                return None;
+            }
+        };
+        self.client.log_policy().log(
+            LogCategory::Refresh,
+            format_args!("Refreshed the access token"),
+        );
+        if let Some(previous) = state.refreshed.take() {
+            tokens.id_token = tokens.id_token.or(previous.id_token);
+            tokens.refresh_token = tokens.refresh_token.or(previous.refresh_token);
+        }
+        state.access_token = SecretString::new(tokens.access_token.secret().clone());
+        if let Some(refresh_token) = &tokens.refresh_token {
+            state.refresh_token = Some(SecretString::new(refresh_token.secret().clone()));
+        }
+        state.expires_at = tokens
+            .expires_in
+            .map(|expires_in| self.client.now() + expires_in);
+        state.refreshed = Some(tokens);
+        Some(state.access_token())
+    }
+
+    /// The token of a session the middleware renewed with `refresh_token`, its cookies are
+    /// replaced with `tokens` once the response is sent.
+    pub(crate) fn refreshed(
+        client: Arc<OpenID>,
+        tokens: RefreshedTokens,
+        refresh_token: RefreshToken,
+    ) -> Self {
+        let refresh_token = tokens.refresh_token.as_ref().unwrap_or(&refresh_token);
+        let expires_at = tokens
+            .expires_in
+            .map(|expires_in| client.now() + expires_in);
+        SessionToken {
+            state: Arc::new(Mutex::new(TokenState {
+                access_token: SecretString::new(tokens.access_token.secret().clone()),
+                refresh_token: Some(SecretString::new(refresh_token.secret().clone())),
+                expires_at,
+                refreshed: Some(tokens),
+            })),
+            client,
+        }
+    }
+
+    fn from_session(client: Arc<OpenID>, req: &HttpRequest) -> Option<Self> {
+        let session = request_session(&client, req)?;
+        Some(SessionToken {
+            client,
+            state: Arc::new(Mutex::new(TokenState {
+                access_token: SecretString::new(session.access_token().secret().clone()),
+                refresh_token: session
+                    .refresh_token()
+                    .map(|token| SecretString::new(token.secret().clone())),
+                expires_at: session.access_token_expires_at(),
+                refreshed: None,
+            })),
+        })
+    }
+}
+
+impl FromRequest for SessionToken {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            <Authenticated>::extract(&req).await?;
+            // Every extraction shares one token, so refreshes are seen by the middleware.
+            if let Some(token) = req.extensions().get::<SessionToken>() {
+                return Ok(token.clone());
+            }
+            let client = RegisteredClient::get(&req)?;
+            let token = SessionToken::from_session(client.0, &req).ok_or_else(|| {
+                ErrorInternalServerError(RegisteredClient::message_for(
+                    &req,
+                    MessageKey::NoAccessToken,
+                ))
+            })?;
+            req.extensions_mut().insert(token.clone());
+            Ok(token)
+        })
+    }
+}
@@ -0,0 +1,112 @@
+//! The predicate deciding which requests require a login.
+
+use std::fmt;
+use std::sync::Arc;
+
+use actix_web::dev::ServiceRequest;
+use actix_web::{web, Resource};
+
+/// Decides which requests require authentication, set with
+/// [`should_auth`](crate::OpenIdBuilder::should_auth) from any closure or
+/// `fn(&ServiceRequest) -> bool`, e.g. one capturing paths read from a configuration file, or
+/// from the predicates below.
+#[derive(Clone)]
+pub struct ShouldAuth(Arc<dyn Fn(&ServiceRequest) -> bool + Send + Sync>);
+
+impl fmt::Debug for ShouldAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ShouldAuth(..)")
+    }
+}
+
+impl ShouldAuth {
+    pub(crate) fn new(
+        should_auth: impl Fn(&ServiceRequest) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        ShouldAuth(Arc::new(should_auth))
+    }
+
+    /// Whether `req` requires authentication: its resource's [`Public`] or [`RequireAuth`]
+    /// marker decides, the predicate otherwise.
+    pub(crate) fn check(&self, req: &ServiceRequest) -> bool {
+        match req.match_name() {
+            Some(name) if name.starts_with(Public::PREFIX) => false,
+            Some(name) if name.starts_with(RequireAuth::PREFIX) => true,
+            _ => (self.0)(req),
+        }
+    }
+
+    /// Every request, the default.
+    pub fn always() -> impl Fn(&ServiceRequest) -> bool + Clone + Send + Sync + 'static {
+        |_| true
+    }
+
+    /// Every request but those below one of `prefixes`, e.g. `["/health", "/public"]`. Prefixes
+    /// match whole path segments, `/public` does not match `/publications`.
+    pub fn except_prefixes(
+        prefixes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> impl Fn(&ServiceRequest) -> bool + Clone + Send + Sync + 'static {
+        let prefixes: Arc<[String]> = prefixes
+            .into_iter()
+            .map(|prefix| prefix.into().trim_end_matches('/').to_string())
+            .collect();
+        move |req| {
+            let path = req.path();
+            !prefixes.iter().any(|prefix| {
+                path.strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+        }
+    }
+}
+
+/// Marks a resource whose requests are authenticated when they have a session and go through
+/// without one, whatever [`should_auth`](crate::OpenIdBuilder::should_auth) says, e.g. a pricing
+/// page of an app requiring logins everywhere else:
+///
+/// ```ignore
+/// App::new()
+///     .wrap(openid.get_middleware())
+///     .service(Public::resource("/pricing").route(web::get().to(pricing)))
+/// ```
+///
+/// The middleware runs before the router picks the route, it reads the marker from the
+/// [name](actix_web::Resource::name) of the resource matching the path, which works for
+/// resources nested in scopes too. Resources renamed afterwards lose it; name them with
+/// [`Public::name`] instead, or `#[get("/pricing", name = "openid:public:pricing")]`.
+pub struct Public;
+
+impl Public {
+    /// Prefix of the names of public resources.
+    pub const PREFIX: &'static str = "openid:public:";
+
+    /// A resource at `path` marked public, named after its path.
+    pub fn resource(path: &str) -> Resource {
+        web::resource(path).name(&Self::name(path))
+    }
+
+    /// The resource name `name` marked public, e.g. to find the resource with `url_for`.
+    pub fn name(name: &str) -> String {
+        format!("{}{}", Self::PREFIX, name)
+    }
+}
+
+/// Marks a resource whose requests require authentication, whatever
+/// [`should_auth`](crate::OpenIdBuilder::should_auth) says, e.g. an account page in an app
+/// where logins are optional. Like [`Public`], the marker is the resource's name.
+pub struct RequireAuth;
+
+impl RequireAuth {
+    /// Prefix of the names of resources requiring authentication.
+    pub const PREFIX: &'static str = "openid:require_auth:";
+
+    /// A resource at `path` requiring authentication, named after its path.
+    pub fn resource(path: &str) -> Resource {
+        web::resource(path).name(&Self::name(path))
+    }
+
+    /// The resource name `name` marked as requiring authentication.
+    pub fn name(name: &str) -> String {
+        format!("{}{}", Self::PREFIX, name)
+    }
+}
@@ -0,0 +1,162 @@
+//! Background tasks of the crate, started once and stopped together when the server stops.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::future::{abortable, AbortHandle, BoxFuture};
+use futures_util::FutureExt;
+use tokio::sync::watch;
+
+/// How long [`TaskSet::shutdown`] waits for the tasks to stop before aborting them.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Tells a task the set is shutting down. Tasks finish the work at hand, e.g. a write to a
+/// store, and return.
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the shutdown is requested, e.g. to race a timer against.
+    pub async fn requested(&mut self) {
+        // The sender lives as long as the set, which outlives its tasks.
+        let _ = self.0.wait_for(|requested| *requested).await;
+    }
+}
+
+type Task = Box<dyn FnOnce(Shutdown) -> BoxFuture<'static, ()> + Send>;
+
+/// The crate's background tasks, e.g. the periodic refresh of the provider's documents.
+///
+/// Shared by the clones of an [`OpenID`](crate::openid::OpenID) client, and so by every worker's
+/// middleware. Tasks are named and each name is only added once, the middleware starts them on
+/// the actix runtime when the first worker builds it. Applications without one take them with
+/// [`futures`](Self::futures) and spawn them themselves.
+///
+/// ```ignore
+/// let tasks = openid.get_middleware().tasks().clone();
+/// HttpServer::new(move || App::new().wrap(openid.get_middleware().refresh_provider_every(hour)))
+///     .run()
+///     .await?;
+/// tasks.shutdown().await;
+/// ```
+#[derive(Clone, Default)]
+pub struct TaskSet(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+    shutdown: watch::Sender<bool>,
+    /// Tasks started and not yet completed.
+    running: watch::Sender<usize>,
+}
+
+#[derive(Default)]
+struct State {
+    names: HashSet<&'static str>,
+    pending: Vec<Task>,
+    aborts: Vec<AbortHandle>,
+    started: bool,
+}
+
+impl TaskSet {
+    pub fn new() -> Self {
+        TaskSet::default()
+    }
+
+    /// Adds `task` unless one named `name` was added before. Once the set is started, the task is
+    /// spawned right away.
+    pub fn add<F>(&self, name: &'static str, task: impl FnOnce(Shutdown) -> F + Send + 'static)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let mut state = self.0.state.lock().unwrap();
+        if self.is_shut_down() || !state.names.insert(name) {
+            return;
+        }
+        let task: Task = Box::new(move |shutdown| task(shutdown).boxed());
+        if state.started {
+            let future = self.track(&mut state, task);
+            actix_web::rt::spawn(future);
+        } else {
+            state.pending.push(task);
+        }
+    }
+
+    /// Spawns the tasks on the current actix runtime, `false` without one. Later calls only
+    /// spawn the tasks added since.
+    pub fn start(&self) -> bool {
+        if actix_web::rt::System::try_current().is_none() {
+            return false;
+        }
+        for future in self.take(true) {
+            actix_web::rt::spawn(future);
+        }
+        true
+    }
+
+    /// Takes the tasks not started yet, for applications spawning them on their own runtime.
+    /// They are still stopped by [`shutdown`](Self::shutdown).
+    pub fn futures(&self) -> Vec<BoxFuture<'static, ()>> {
+        self.take(false)
+    }
+
+    /// Asks the tasks to stop and waits for them, aborting those still running after 10 seconds.
+    pub async fn shutdown(&self) {
+        self.shutdown_within(SHUTDOWN_GRACE).await
+    }
+
+    /// Like [`shutdown`](Self::shutdown), aborting the tasks still running after `grace`.
+    pub async fn shutdown_within(&self, grace: Duration) {
+        self.0.shutdown.send_replace(true);
+        let mut running = self.0.running.subscribe();
+        let stopped = tokio::time::timeout(grace, running.wait_for(|running| *running == 0)).await;
+        if stopped.is_err() {
+            for abort in self.0.state.lock().unwrap().aborts.drain(..) {
+                abort.abort();
+            }
+        }
+    }
+
+    fn is_shut_down(&self) -> bool {
+        *self.0.shutdown.borrow()
+    }
+
+    fn take(&self, start: bool) -> Vec<BoxFuture<'static, ()>> {
+        let mut state = self.0.state.lock().unwrap();
+        if self.is_shut_down() {
+            return Vec::new();
+        }
+        state.started |= start;
+        let pending = std::mem::take(&mut state.pending);
+        pending
+            .into_iter()
+            .map(|task| self.track(&mut state, task))
+            .collect()
+    }
+
+    /// Runs `task`, counted as running until it completes, is aborted or dropped.
+    fn track(&self, state: &mut State, task: Task) -> BoxFuture<'static, ()> {
+        let (future, abort) = abortable(task(Shutdown(self.0.shutdown.subscribe())));
+        state.aborts.push(abort);
+        self.0.running.send_modify(|running| *running += 1);
+        let running = Running(self.0.clone());
+        async move {
+            let _running = running;
+            let _ = future.await;
+        }
+        .boxed()
+    }
+}
+
+struct Running(Arc<Inner>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.running.send_modify(|running| *running -= 1);
+    }
+}
@@ -0,0 +1,179 @@
+//! Helpers for testing handlers that use the authentication extractors.
+//!
+//! Enabled with the `test-util` feature.
+//!
+//! ```ignore
+//! let req = test_util::authenticate_request(
+//!     test::TestRequest::get().uri("/orders").to_request(),
+//!     AuthenticatedUserBuilder::new("sub").email("a@b.c").role("admin"),
+//! );
+//! let resp = test::call_service(&app, req).await;
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::cookie::time::OffsetDateTime;
+use actix_web::HttpMessage;
+use openidconnect::{AccessToken, AdditionalClaims, UserInfoClaims};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{Map, Value};
+
+use crate::openid_middleware::{insert_auth_result, AuthenticatedUser, UserTokens};
+use crate::{Clock, OtherClaims, RandomSource};
+
+pub use flow_driver::{FlowDriver, FlowRequest, FlowResponse};
+pub use mock_idp::{MockIdp, MockIdpFailure, TokenRequest};
+
+mod flow_driver;
+mod mock_idp;
+
+/// Builds an [`AuthenticatedUser`] without going through the OpenID provider.
+///
+/// Only the subject is required. Every setter writes the claim the provider's userinfo endpoint
+/// would have returned, so the resulting user looks exactly like one produced by the middleware.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUserBuilder {
+    claims: Map<String, Value>,
+    access_token: Option<String>,
+}
+
+impl AuthenticatedUserBuilder {
+    pub fn new(subject: impl Into<String>) -> Self {
+        let mut claims = Map::new();
+        claims.insert("sub".to_string(), Value::String(subject.into()));
+        AuthenticatedUserBuilder {
+            claims,
+            access_token: None,
+        }
+    }
+
+    pub fn email(self, email: impl Into<String>) -> Self {
+        self.claim("email", email.into())
+            .claim("email_verified", true)
+    }
+
+    pub fn name(self, name: impl Into<String>) -> Self {
+        self.claim("name", name.into())
+    }
+
+    pub fn preferred_username(self, preferred_username: impl Into<String>) -> Self {
+        self.claim("preferred_username", preferred_username.into())
+    }
+
+    /// Appends `role` to the `roles` claim.
+    pub fn role(mut self, role: impl Into<String>) -> Self {
+        let roles = self
+            .claims
+            .entry("roles")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(roles) = roles {
+            roles.push(Value::String(role.into()));
+        }
+        self
+    }
+
+    /// Sets an arbitrary claim, overriding any previous value.
+    pub fn claim(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.claims.insert(name.into(), value.into());
+        self
+    }
+
+    /// The access token the user was authenticated with, handed to handlers by
+    /// [`AuthenticatedUser::access_token`].
+    pub fn access_token(mut self, access_token: impl Into<String>) -> Self {
+        self.access_token = Some(access_token.into());
+        self
+    }
+
+    pub fn build(self) -> AuthenticatedUser {
+        self.into()
+    }
+}
+
+/// The user with the claims read as `AC`, e.g. `let user: AuthenticatedUser<MyClaims> =
+/// builder.into()`.
+impl<AC: AdditionalClaims> From<AuthenticatedUserBuilder> for AuthenticatedUser<AC> {
+    fn from(builder: AuthenticatedUserBuilder) -> Self {
+        let json = serde_json::to_vec(&Value::Object(builder.claims.clone())).unwrap();
+        let access = UserInfoClaims::from_json::<serde_json::Error>(&json, None)
+            .expect("claims set on AuthenticatedUserBuilder are not valid userinfo claims");
+        // Kept for the policies, whatever claims `AC` reads.
+        let other_claims = serde_json::from_value(Value::Object(builder.claims)).unwrap();
+        let user = AuthenticatedUser::new(access).with_other_claims(other_claims);
+        match builder.access_token {
+            Some(access_token) => {
+                user.with_tokens(UserTokens::new(AccessToken::new(access_token), None))
+            }
+            None => user,
+        }
+    }
+}
+
+/// Marks `req` as authenticated as `user`, the same way the middleware would.
+///
+/// Works with anything carrying request extensions, e.g. the results of
+/// `TestRequest::to_request`, `to_http_request` or `to_srv_request`. Claims set on an [`AuthenticatedUserBuilder`] reach the extractors whatever their claims type.
+pub fn authenticate_request<R: HttpMessage>(
+    req: R,
+    user: impl Into<AuthenticatedUser<OtherClaims>>,
+) -> R {
+    insert_auth_result(&mut req.extensions_mut(), Ok(user.into()));
+    req
+}
+
+/// A [`RandomSource`] producing the same sequence of tokens for the same seed.
+///
+/// Lets tests predict the nonces the client sends to the provider.
+pub struct SeededRandom(Mutex<StdRng>);
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn random_token(&self) -> String {
+        let bytes: [u8; 16] = self.0.lock().unwrap().gen();
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// A [`Clock`] that only moves when told to, starting at the current time.
+///
+/// Clones share the time, so a test keeps one to advance after handing another to
+/// [`OpenIdBuilder::clock`](crate::OpenIdBuilder::clock).
+#[derive(Clone)]
+pub struct MockClock(Arc<Mutex<OffsetDateTime>>);
+
+impl MockClock {
+    pub fn new() -> Self {
+        MockClock::at(OffsetDateTime::now_utc())
+    }
+
+    pub fn at(now: OffsetDateTime) -> Self {
+        MockClock(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+
+    pub fn set(&self, now: OffsetDateTime) {
+        *self.0.lock().unwrap() = now;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.0.lock().unwrap()
+    }
+}
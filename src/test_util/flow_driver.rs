@@ -0,0 +1,347 @@
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::cookie::{Cookie, CookieJar};
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::header::{
+    HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, COOKIE, LOCATION, SET_COOKIE,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::web::Bytes;
+use actix_web::{test, Error};
+use url::Url;
+
+use super::MockIdp;
+use crate::openid_middleware::AuthCookies;
+
+/// Maximum number of redirects followed by [`FlowRequest::follow_login`].
+const MAX_REDIRECTS: usize = 10;
+
+/// Drives an `actix_web::test` app through login flows against a [`MockIdp`].
+///
+/// Cookies set by the app are kept in a jar and sent along with every following request, like a
+/// browser would.
+///
+/// ```ignore
+/// let mut driver = FlowDriver::new(app, &idp);
+/// let resp = driver.get("/protected").follow_login().await;
+/// assert_eq!(resp.status(), 200);
+/// driver.assert_authenticated();
+/// ```
+pub struct FlowDriver<S> {
+    app: S,
+    issuer_urls: Vec<String>,
+    jar: CookieJar,
+    fragment: Option<String>,
+}
+
+/// A response received by the [`FlowDriver`], from the app or from the provider.
+#[derive(Debug)]
+pub struct FlowResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl FlowResponse {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// Returns the `Location` header of a redirect.
+    pub fn location(&self) -> Option<&str> {
+        self.headers
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// Returns the cookies set by the response, including removal cookies.
+    pub fn cookies(&self) -> impl Iterator<Item = Cookie<'static>> + '_ {
+        self.headers
+            .get_all(SET_COOKIE)
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| Cookie::parse_encoded(value.to_string()).ok())
+    }
+}
+
+/// A request being prepared by [`FlowDriver::get`] or [`FlowDriver::request`].
+pub struct FlowRequest<'a, S> {
+    driver: &'a mut FlowDriver<S>,
+    request: test::TestRequest,
+}
+
+impl<S, B> FlowDriver<S>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    pub fn new(app: S, idp: &MockIdp) -> Self {
+        FlowDriver {
+            app,
+            issuer_urls: vec![idp.issuer_url()],
+            jar: CookieJar::new(),
+            fragment: None,
+        }
+    }
+
+    /// Also follows logins through `idp`, for apps with several realms.
+    pub fn with_idp(mut self, idp: &MockIdp) -> Self {
+        self.issuer_urls.push(idp.issuer_url());
+        self
+    }
+
+    /// Continues against `app` with this driver's cookies and providers, like a browser visiting
+    /// another service on the same domain.
+    pub fn with_app<T>(self, app: T) -> FlowDriver<T> {
+        FlowDriver {
+            app,
+            issuer_urls: self.issuer_urls,
+            jar: self.jar,
+            fragment: self.fragment,
+        }
+    }
+
+    pub fn get(&mut self, path: &str) -> FlowRequest<'_, S> {
+        self.request(Method::GET, path)
+    }
+
+    pub fn request(&mut self, method: Method, path: &str) -> FlowRequest<'_, S> {
+        FlowRequest {
+            driver: self,
+            request: test::TestRequest::default().method(method).uri(path),
+        }
+    }
+
+    /// Returns the value of a cookie currently held in the jar.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.jar.get(name).map(|cookie| cookie.value().to_string())
+    }
+
+    /// Returns the value of the login cookie `name`, e.g. `nonce`, of a login in progress, whose
+    /// name is suffixed with its [login attempt](crate::openid::AuthorizationUrl::login_attempt).
+    pub fn login_cookie(&self, name: &str) -> Option<String> {
+        let prefix = format!("{}.", name);
+        self.jar
+            .iter()
+            .find(|cookie| cookie.name().starts_with(&prefix))
+            .map(|cookie| cookie.value().to_string())
+    }
+
+    /// The fragment of the last redirect followed into the app, which browsers keep for the page
+    /// instead of sending it, e.g. the token of a first-party session.
+    pub fn fragment(&self) -> Option<&str> {
+        self.fragment.as_deref()
+    }
+
+    /// The cookies currently held in the jar.
+    pub fn cookies(&self) -> Vec<Cookie<'static>> {
+        self.jar.iter().cloned().collect()
+    }
+
+    /// Adds a cookie to the jar, e.g. to simulate a tampered session.
+    pub fn set_cookie(&mut self, cookie: Cookie<'static>) {
+        self.jar.add(cookie);
+    }
+
+    /// Panics unless the jar holds an access token or a session id.
+    pub fn assert_authenticated(&self) {
+        assert!(
+            self.has_session(),
+            "expected the session to be authenticated, cookies: {:?}",
+            self.jar.iter().collect::<Vec<_>>()
+        );
+    }
+
+    /// Panics if the jar holds an access token or a session id.
+    pub fn assert_unauthenticated(&self) {
+        assert!(
+            !self.has_session(),
+            "expected the session to be unauthenticated"
+        );
+    }
+
+    fn has_session(&self) -> bool {
+        [AuthCookies::AccessToken, AuthCookies::SessionId]
+            .iter()
+            .any(|cookie| self.cookie(cookie.name()).is_some())
+    }
+
+    async fn send(&mut self, mut request: test::TestRequest) -> FlowResponse {
+        let cookies = self
+            .jar
+            .iter()
+            .map(|cookie| cookie.stripped().encoded().to_string())
+            .collect::<Vec<_>>();
+        if !cookies.is_empty() {
+            request = request.insert_header((COOKIE, cookies.join("; ")));
+        }
+        let resp = match test::try_call_service(&self.app, request.to_request()).await {
+            Ok(resp) => {
+                let (_, resp) = resp.into_parts();
+                let (head, body) = resp.into_parts();
+                let body = actix_web::body::to_bytes(body)
+                    .await
+                    .map_err(Into::into)
+                    .expect("failed to read the response body");
+                FlowResponse {
+                    status: head.status(),
+                    headers: head.headers().clone(),
+                    body,
+                }
+            }
+            Err(err) => {
+                let resp = err.error_response();
+                let status = resp.status();
+                let headers = resp.headers().clone();
+                let body = actix_web::body::to_bytes(resp.into_body())
+                    .await
+                    .expect("failed to read the error response body");
+                FlowResponse {
+                    status,
+                    headers,
+                    body,
+                }
+            }
+        };
+        for cookie in resp.cookies() {
+            if cookie.max_age().is_some_and(|max_age| max_age.is_zero()) {
+                self.jar.force_remove(&cookie);
+            } else {
+                self.jar.add(cookie);
+            }
+        }
+        resp
+    }
+
+    async fn send_to_idp(&self, url: &str) -> FlowResponse {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let resp = client
+            .get(url)
+            .send()
+            .await
+            .expect("failed to call the mock IdP");
+        let status = StatusCode::from_u16(resp.status().as_u16()).unwrap();
+        let mut headers = HeaderMap::new();
+        for (name, value) in resp.headers() {
+            headers.append(
+                HeaderName::from_bytes(name.as_str().as_bytes()).unwrap(),
+                HeaderValue::from_bytes(value.as_bytes()).unwrap(),
+            );
+        }
+        let body = resp.bytes().await.unwrap();
+        FlowResponse {
+            status,
+            headers,
+            body: Bytes::from(body.to_vec()),
+        }
+    }
+}
+
+impl<S, B> FlowRequest<'_, S>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.request = self.request.insert_header((name, value));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.request = self.request.set_payload(body.into());
+        self
+    }
+
+    /// Sends the request to the app and returns its response as is.
+    pub async fn send(self) -> FlowResponse {
+        self.driver.send(self.request).await
+    }
+
+    /// Sends the request and follows redirects, through the provider and back to the app, until
+    /// a response that is not a redirect is reached.
+    pub async fn follow_login(self) -> FlowResponse {
+        let driver = self.driver;
+        let mut resp = driver.send(self.request).await;
+        for _ in 0..MAX_REDIRECTS {
+            if let Some((action, form)) = form_post(&resp) {
+                resp = driver
+                    .send(
+                        test::TestRequest::post()
+                            .uri(&app_path(&action))
+                            .insert_header((CONTENT_TYPE, "application/x-www-form-urlencoded"))
+                            .set_payload(form),
+                    )
+                    .await;
+                continue;
+            }
+            if !resp.status().is_redirection() {
+                return resp;
+            }
+            let location = resp
+                .location()
+                .expect("redirect without location")
+                .to_string();
+            let to_idp = driver
+                .issuer_urls
+                .iter()
+                .any(|issuer_url| location.starts_with(issuer_url));
+            resp = if to_idp {
+                driver.send_to_idp(&location).await
+            } else {
+                driver.fragment = location
+                    .split_once('#')
+                    .map(|(_, fragment)| fragment.to_string());
+                driver
+                    .send(test::TestRequest::get().uri(&app_path(&location)))
+                    .await
+            };
+        }
+        panic!("more than {} redirects", MAX_REDIRECTS);
+    }
+}
+
+/// The target and the urlencoded body of the form a `response_mode=form_post` page of the mock
+/// provider submits, as a browser would.
+fn form_post(resp: &FlowResponse) -> Option<(String, String)> {
+    let page = std::str::from_utf8(resp.body()).ok()?;
+    let (_, rest) = page.split_once(r#"<form method="post" action=""#)?;
+    let (action, mut rest) = rest.split_once('"')?;
+    let mut form = url::form_urlencoded::Serializer::new(String::new());
+    while let Some((_, input)) = rest.split_once(r#"<input type="hidden" name=""#) {
+        let (name, input) = input.split_once(r#"" value=""#)?;
+        let (value, input) = input.split_once('"')?;
+        form.append_pair(name, &html_unescape(value));
+        rest = input;
+    }
+    Some((html_unescape(action), form.finish()))
+}
+
+fn html_unescape(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Turns a redirect target, relative or absolute, into a path and query the app can be called with.
+fn app_path(location: &str) -> String {
+    match Url::parse(location) {
+        Ok(url) => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        },
+        // Browsers never send the fragment.
+        Err(_) => location.split('#').next().unwrap_or_default().to_string(),
+    }
+}
@@ -0,0 +1,1144 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServerHandle;
+use actix_web::http::header::{AUTHORIZATION, CONTENT_TYPE, LOCATION};
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, HttpRequest, HttpResponse, HttpServer};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openidconnect::core::{
+    CoreGenderClaim, CoreJsonWebKeySet, CoreJsonWebKeyType, CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm, CoreRsaPrivateSigningKey,
+};
+use openidconnect::{
+    AccessToken, AdditionalClaims, AuthorizationCode, CsrfToken, IdToken, IdTokenClaims,
+    JsonWebKeyId, PrivateSigningKey,
+};
+use rsa::pkcs1::{EncodeRsaPrivateKey, LineEnding};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::{AuthenticatedUserBuilder, FlowDriver};
+use crate::ActixWebOpenId;
+
+const KEY_ID: &str = "mock-idp";
+const DEFAULT_TOKEN_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Misbehaviour the [`MockIdp`] can be told to exhibit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MockIdpFailure {
+    /// Issued ID tokens are already expired.
+    ExpiredIdToken,
+    /// Issued ID tokens carry a nonce other than the one sent in the authorization request.
+    WrongNonce,
+    /// Issued ID tokens carry the `at_hash` and `c_hash` of another access token and code.
+    WrongTokenHashes,
+    /// Issued ID tokens carry neither an `at_hash` nor a `c_hash`.
+    NoTokenHashes,
+    /// The token and userinfo endpoints answer with `500 Internal Server Error`.
+    ServerError,
+    /// The token endpoint answers with `status` and the OAuth error code `error`.
+    TokenError { status: u16, error: &'static str },
+    /// The discovery document and the JWKS answer with `503 Service Unavailable`.
+    DocumentsUnavailable,
+    /// The authorization endpoint redirects back with the OAuth error code `error` instead of a
+    /// code, e.g. `access_denied` for a user cancelling the consent.
+    AuthorizationError { error: &'static str },
+}
+
+/// A request the token endpoint of the [`MockIdp`] received, see [`MockIdp::token_requests`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenRequest {
+    pub grant_type: String,
+    pub client_id: Option<String>,
+    /// Sent as `client_secret` in the body or with Basic authentication.
+    pub client_secret: Option<String>,
+    /// The redirect url a code was exchanged for.
+    pub redirect_uri: Option<String>,
+}
+
+/// A tiny OpenID provider running in-process on a random local port.
+///
+/// It serves a discovery document, a JWKS, an authorization endpoint that immediately redirects
+/// back with a code, a token endpoint issuing RS256-signed ID tokens and rotating refresh tokens
+/// (or client credentials, password and device grant tokens), a userinfo endpoint, a revocation
+/// endpoint, a device authorization endpoint and an end-session endpoint. The provider is shut
+/// down when the value is dropped.
+///
+/// ```ignore
+/// let idp = MockIdp::start();
+/// idp.login_as(AuthenticatedUserBuilder::new("alice").email("alice@example.com"));
+/// let openid = ActixWebOpenId::init(/* ... */ idp.issuer_url(), /* ... */).await;
+/// ```
+pub struct MockIdp {
+    issuer_url: String,
+    state: Arc<Mutex<MockIdpState>>,
+    server: ServerHandle,
+    thread: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+struct MockIdpState {
+    issuer_url: String,
+    user: Map<String, Value>,
+    failure: Option<MockIdpFailure>,
+    end_session_endpoint: bool,
+    revocation_endpoint: bool,
+    device_authorization_endpoint: bool,
+    /// Whether the token exchange grant is advertised and accepted.
+    token_exchange: bool,
+    /// Audiences tokens are exchanged for, any when `None`.
+    token_exchange_audiences: Option<Vec<String>>,
+    /// Token endpoint advertised instead of the provider's own.
+    token_endpoint: Option<String>,
+    /// Issuer advertised and put into the tokens instead of the issuer url.
+    issuer: Option<String>,
+    /// The `p` query parameter the discovery document and the token endpoint require, like the
+    /// policies of Azure AD B2C.
+    policy: Option<String>,
+    token_lifetime: Duration,
+    /// Sent as `refresh_expires_in`, refresh tokens do not expire.
+    refresh_token_lifetime: Option<Duration>,
+    /// Whether access tokens are signed JWTs rather than opaque strings.
+    jwt_access_tokens: bool,
+    /// How often the signing key was rotated.
+    key_generation: usize,
+    codes: HashMap<String, PendingCode>,
+    access_tokens: HashMap<String, Map<String, Value>>,
+    /// Client each access token of a login or a refresh was issued to.
+    access_token_clients: HashMap<String, String>,
+    /// Whether userinfo is answered with a signed JWT rather than JSON.
+    signed_userinfo: bool,
+    /// Client and user each refresh token was issued to.
+    refresh_tokens: HashMap<String, (String, Map<String, Value>)>,
+    /// Whether logins issue a refresh token.
+    login_refresh_tokens: bool,
+    /// Whether refresh tokens are replaced on use, otherwise refreshes issue no new one.
+    rotate_refresh_tokens: bool,
+    /// Credentials accepted by the password grant.
+    passwords: HashMap<String, String>,
+    /// Client of each device code, and how many polls are answered `authorization_pending`.
+    device_codes: HashMap<String, (String, usize)>,
+    /// Polls of new device codes answered `authorization_pending` before the user approves.
+    device_approval_polls: usize,
+    /// Whether the user has a session at the provider, answering `prompt=none` requests.
+    signed_in: bool,
+    connections: usize,
+    token_requests: Vec<TokenRequest>,
+    /// Requests to the documents and userinfo still answered with `503 Service Unavailable`.
+    unavailable_for: usize,
+    userinfo_delay: Duration,
+    /// Algorithm the ID tokens are signed with, with the RSA key.
+    id_token_alg: CoreJwsSigningAlgorithm,
+    /// Claims of the ID tokens replacing the standard ones, e.g. `aud`.
+    id_token_claims: Map<String, Value>,
+    introspections: usize,
+}
+
+impl MockIdpState {
+    fn issuer(&self) -> String {
+        self.issuer
+            .clone()
+            .unwrap_or_else(|| self.issuer_url.clone())
+    }
+
+    /// Whether `req` carries the policy, if one is required.
+    fn has_policy(&self, req: &HttpRequest) -> bool {
+        let Some(policy) = &self.policy else {
+            return true;
+        };
+        url::form_urlencoded::parse(req.query_string().as_bytes())
+            .any(|(name, value)| name == "p" && value == policy.as_str())
+    }
+
+    /// Whether the request is one of those answered while the provider is briefly unavailable.
+    fn take_unavailable(&mut self) -> bool {
+        let unavailable = self.unavailable_for > 0;
+        self.unavailable_for = self.unavailable_for.saturating_sub(1);
+        unavailable
+    }
+}
+
+struct PendingCode {
+    client_id: String,
+    nonce: Option<String>,
+    /// The S256 PKCE challenge the code must be redeemed with.
+    code_challenge: Option<String>,
+}
+
+impl MockIdp {
+    /// Starts a provider logging everyone in as `mock-user`.
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockIdpState {
+            issuer_url: String::new(),
+            user: default_user().claims,
+            failure: None,
+            end_session_endpoint: true,
+            revocation_endpoint: true,
+            device_authorization_endpoint: true,
+            token_exchange: true,
+            token_exchange_audiences: None,
+            token_endpoint: None,
+            issuer: None,
+            policy: None,
+            token_lifetime: DEFAULT_TOKEN_LIFETIME,
+            refresh_token_lifetime: None,
+            jwt_access_tokens: false,
+            key_generation: 0,
+            codes: HashMap::new(),
+            access_tokens: HashMap::new(),
+            access_token_clients: HashMap::new(),
+            signed_userinfo: false,
+            refresh_tokens: HashMap::new(),
+            rotate_refresh_tokens: true,
+            login_refresh_tokens: true,
+            passwords: HashMap::new(),
+            device_codes: HashMap::new(),
+            device_approval_polls: 0,
+            signed_in: true,
+            connections: 0,
+            token_requests: Vec::new(),
+            unavailable_for: 0,
+            userinfo_delay: Duration::ZERO,
+            id_token_alg: CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            id_token_claims: Map::new(),
+            introspections: 0,
+        }));
+        let (tx, rx) = mpsc::channel();
+        let server_state = state.clone();
+        let connection_state = state.clone();
+        let thread = thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let data = web::Data::from(server_state.clone());
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(data.clone())
+                        .route(
+                            "/.well-known/openid-configuration",
+                            web::get().to(discovery),
+                        )
+                        .route("/jwks", web::get().to(jwks))
+                        .route("/authorize", web::get().to(authorize))
+                        .route("/token", web::post().to(token))
+                        .route("/userinfo", web::get().to(userinfo))
+                        .route("/logout", web::get().to(logout))
+                        .route("/revoke", web::post().to(revoke))
+                        .route("/introspect", web::post().to(introspect))
+                        .route("/device", web::post().to(device_authorization))
+                })
+                .on_connect(move |_, _| connection_state.lock().unwrap().connections += 1)
+                .workers(1)
+                .disable_signals()
+                .bind(("127.0.0.1", 0))
+                .expect("failed to bind the mock IdP");
+                let issuer_url = format!("http://{}", server.addrs()[0]);
+                server_state.lock().unwrap().issuer_url = issuer_url.clone();
+                let server = server.run();
+                tx.send((issuer_url, server.handle())).unwrap();
+                server.await
+            })
+        });
+        let (issuer_url, server) = rx.recv().expect("the mock IdP failed to start");
+        MockIdp {
+            issuer_url,
+            state,
+            server,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn issuer_url(&self) -> String {
+        self.issuer_url.clone()
+    }
+
+    /// Sets the user whose claims are put into subsequently issued tokens and userinfo responses.
+    pub fn login_as(&self, user: AuthenticatedUserBuilder) {
+        self.state.lock().unwrap().user = user.claims;
+    }
+
+    /// Logs `user` in to `openid` and returns the session cookies the callback set, to send with
+    /// `actix_web::test::TestRequest::cookie` to an app using the same client:
+    ///
+    /// ```ignore
+    /// let cookies = idp
+    ///     .issue_session_cookies(&openid, AuthenticatedUserBuilder::new("alice").role("admin"))
+    ///     .await;
+    /// let req = cookies
+    ///     .into_iter()
+    ///     .fold(test::TestRequest::get().uri("/orders"), |req, cookie| req.cookie(cookie));
+    /// ```
+    ///
+    /// The login goes through the client's endpoints and this provider like a browser's would,
+    /// `user` stays logged in for later logins.
+    pub async fn issue_session_cookies(
+        &self,
+        openid: &ActixWebOpenId,
+        user: AuthenticatedUserBuilder,
+    ) -> Vec<Cookie<'static>> {
+        self.login_as(user);
+        let app = test::init_service(
+            App::new()
+                .wrap(openid.get_middleware())
+                .configure(openid.configure_open_id()),
+        )
+        .await;
+        let mut driver = FlowDriver::new(app, self);
+        let login_path = openid.openid_client().realm().login_path();
+        driver.get(&login_path).follow_login().await;
+        driver.assert_authenticated();
+        driver.cookies()
+    }
+
+    /// Makes the provider misbehave from now on, or behave again with `None`.
+    pub fn set_failure(&self, failure: Option<MockIdpFailure>) {
+        self.state.lock().unwrap().failure = failure;
+    }
+
+    /// Answers the next `requests` requests to the discovery document, the JWKS and userinfo
+    /// with `503 Service Unavailable`, like a provider that is restarting.
+    pub fn set_unavailable_for(&self, requests: usize) {
+        self.state.lock().unwrap().unavailable_for = requests;
+    }
+
+    /// Delays the answers of the userinfo endpoint, like an overloaded provider.
+    pub fn set_userinfo_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().userinfo_delay = delay;
+    }
+
+    /// Advertises the end-session endpoint in the discovery document, or not, like Google.
+    ///
+    /// Only affects clients discovering the provider afterwards.
+    pub fn set_end_session_endpoint(&self, enabled: bool) {
+        self.state.lock().unwrap().end_session_endpoint = enabled;
+    }
+
+    /// Advertises the revocation endpoint in the discovery document, or not.
+    ///
+    /// Only affects clients discovering the provider afterwards.
+    pub fn set_revocation_endpoint(&self, enabled: bool) {
+        self.state.lock().unwrap().revocation_endpoint = enabled;
+    }
+
+    /// Advertises the device authorization endpoint in the discovery document, or not.
+    ///
+    /// Only affects clients discovering the provider afterwards.
+    pub fn set_device_authorization_endpoint(&self, enabled: bool) {
+        self.state.lock().unwrap().device_authorization_endpoint = enabled;
+    }
+
+    /// Advertises and accepts the token exchange grant (RFC 8693), or answers it with
+    /// `unsupported_grant_type`. Enabled by default.
+    ///
+    /// Only affects the discovery document of clients discovering the provider afterwards.
+    pub fn set_token_exchange(&self, enabled: bool) {
+        self.state.lock().unwrap().token_exchange = enabled;
+    }
+
+    /// Exchanges tokens only for `audiences`, others are refused with `invalid_target`. Tokens are
+    /// exchanged for any audience by default.
+    pub fn set_token_exchange_audiences(&self, audiences: &[&str]) {
+        self.state.lock().unwrap().token_exchange_audiences = Some(
+            audiences
+                .iter()
+                .map(|audience| audience.to_string())
+                .collect(),
+        );
+    }
+
+    /// Answers `polls` polls of subsequent device authorizations with `authorization_pending`
+    /// before the user approves them, `0` by default. The tokens belong to the logged in user.
+    pub fn set_device_approval_polls(&self, polls: usize) {
+        self.state.lock().unwrap().device_approval_polls = polls;
+    }
+
+    /// Advertises `url` as the token endpoint, e.g. an unreachable one like
+    /// `http://127.0.0.1:1/token`. Unlike dropping the provider, no other provider started by a
+    /// concurrent test can take its place.
+    ///
+    /// Only affects clients discovering the provider afterwards.
+    pub fn set_token_endpoint(&self, url: impl Into<String>) {
+        self.state.lock().unwrap().token_endpoint = Some(url.into());
+    }
+
+    /// Advertises `issuer` and puts it into the tokens, rather than the issuer url the provider
+    /// is discovered at, like Azure AD B2C.
+    pub fn set_issuer(&self, issuer: impl Into<String>) {
+        self.state.lock().unwrap().issuer = Some(issuer.into());
+    }
+
+    /// Only serves the discovery document and the token endpoint to requests with
+    /// `p=<policy>` in the query, like Azure AD B2C. The advertised endpoints do not carry it.
+    pub fn set_policy(&self, policy: impl Into<String>) {
+        self.state.lock().unwrap().policy = Some(policy.into());
+    }
+
+    /// Accepts `password` for `username` in the password grant, whose tokens belong to the
+    /// logged in user with `username` as the subject.
+    pub fn set_password(&self, username: impl Into<String>, password: impl Into<String>) {
+        self.state
+            .lock()
+            .unwrap()
+            .passwords
+            .insert(username.into(), password.into());
+    }
+
+    /// Whether the user has a session at the provider, `true` by default. Without one,
+    /// `prompt=none` authorization requests are answered with `error=login_required`, other
+    /// requests still log the user in.
+    pub fn set_signed_in(&self, signed_in: bool) {
+        self.state.lock().unwrap().signed_in = signed_in;
+    }
+
+    /// Sets the lifetime of subsequently issued tokens, 5 minutes by default.
+    pub fn set_token_lifetime(&self, lifetime: Duration) {
+        self.state.lock().unwrap().token_lifetime = lifetime;
+    }
+
+    /// Tells the lifetime of subsequently issued refresh tokens with `refresh_expires_in`, like
+    /// Keycloak. Not sent by default.
+    pub fn set_refresh_token_lifetime(&self, lifetime: Duration) {
+        self.state.lock().unwrap().refresh_token_lifetime = Some(lifetime);
+    }
+
+    /// Issues access tokens as JWTs signed like the ID tokens, with the user's claims, rather than
+    /// opaque strings. The userinfo endpoint still accepts them.
+    pub fn set_jwt_access_tokens(&self, enabled: bool) {
+        self.state.lock().unwrap().jwt_access_tokens = enabled;
+    }
+
+    /// Answers userinfo requests with a JWT signed like the ID tokens, as `application/jwt`, like
+    /// a provider configured with `userinfo_signed_response_alg=RS256`.
+    pub fn set_signed_userinfo(&self, enabled: bool) {
+        self.state.lock().unwrap().signed_userinfo = enabled;
+    }
+
+    /// Signs everything from now on with a new key under a new key id, the JWKS only lists the
+    /// new key.
+    pub fn rotate_signing_key(&self) {
+        self.state.lock().unwrap().key_generation += 1;
+    }
+
+    /// Revokes the access tokens issued so far, the userinfo endpoint rejects them from now on.
+    pub fn revoke_access_tokens(&self) {
+        self.state.lock().unwrap().access_tokens.clear();
+    }
+
+    /// Keeps refresh tokens valid after use, refreshes then answer without a new refresh token.
+    /// By default refresh tokens are rotated: each is replaced on use, and refused afterwards.
+    pub fn set_rotate_refresh_tokens(&self, enabled: bool) {
+        self.state.lock().unwrap().rotate_refresh_tokens = enabled;
+    }
+
+    /// Answers subsequent logins without a refresh token, by default each login issues one.
+    pub fn set_login_refresh_tokens(&self, enabled: bool) {
+        self.state.lock().unwrap().login_refresh_tokens = enabled;
+    }
+
+    /// Revokes the refresh tokens issued so far, the token endpoint rejects them from now on.
+    pub fn revoke_refresh_tokens(&self) {
+        self.state.lock().unwrap().refresh_tokens.clear();
+    }
+
+    /// Signs subsequently issued ID tokens with `alg`, RS256 by default. The provider has an RSA
+    /// key, `alg` must be one of RS256, RS384, RS512, PS256, PS384 or PS512.
+    pub fn set_id_token_alg(&self, alg: CoreJwsSigningAlgorithm) {
+        self.state.lock().unwrap().id_token_alg = alg;
+    }
+
+    /// Sets `name` to `value` in subsequently issued ID tokens, replacing the standard claim of
+    /// that name, e.g. `aud`, `iat` or `nbf`.
+    pub fn set_id_token_claim(&self, name: impl Into<String>, value: Value) {
+        self.state
+            .lock()
+            .unwrap()
+            .id_token_claims
+            .insert(name.into(), value);
+    }
+
+    /// Signs `payload` with the provider's key as a compact JWS, e.g. a Keycloak admin event.
+    pub fn sign(&self, payload: &Value) -> String {
+        sign(self.state.lock().unwrap().key_generation, payload)
+    }
+
+    /// Requests of the introspection endpoint so far.
+    pub fn introspection_count(&self) -> usize {
+        self.state.lock().unwrap().introspections
+    }
+
+    /// Connections accepted so far, to check that clients reuse them.
+    /// The requests of the token endpoint so far, oldest first.
+    pub fn token_requests(&self) -> Vec<TokenRequest> {
+        self.state.lock().unwrap().token_requests.clone()
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+}
+
+impl Drop for MockIdp {
+    fn drop(&mut self) {
+        // Waiting for the server thread closes the connections clients keep alive, requests after
+        // the drop find the provider unreachable.
+        drop(self.server.stop(false));
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn default_user() -> AuthenticatedUserBuilder {
+    AuthenticatedUserBuilder::new("mock-user")
+        .preferred_username("mock-user")
+        .email("mock-user@example.com")
+}
+
+/// The signing keys are generated once per process, RSA key generation is slow in debug builds.
+fn signing_key_pem(generation: usize) -> String {
+    static PEMS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+    let mut pems = PEMS.get_or_init(Mutex::default).lock().unwrap();
+    while pems.len() <= generation {
+        let key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 2048)
+            .expect("failed to generate the mock IdP signing key");
+        pems.push(key.to_pkcs1_pem(LineEnding::LF).unwrap().to_string());
+    }
+    pems[generation].clone()
+}
+
+fn key_id(generation: usize) -> String {
+    match generation {
+        0 => KEY_ID.to_string(),
+        generation => format!("{}-{}", KEY_ID, generation),
+    }
+}
+
+fn signing_key(generation: usize) -> CoreRsaPrivateSigningKey {
+    CoreRsaPrivateSigningKey::from_pem(
+        &signing_key_pem(generation),
+        Some(JsonWebKeyId::new(key_id(generation))),
+    )
+    .unwrap()
+}
+
+fn sign(generation: usize, payload: &Value) -> String {
+    let header = json!({ "alg": "RS256", "typ": "JWT", "kid": key_id(generation) });
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(payload.to_string())
+    );
+    let signature = signing_key(generation)
+        .sign(
+            &CoreJwsSigningAlgorithm::RsaSsaPkcs1V15Sha256,
+            signing_input.as_bytes(),
+        )
+        .unwrap();
+    format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn random_string() -> String {
+    CsrfToken::new_random().secret().to_string()
+}
+
+/// Keeps claims the crate does not know about in issued ID tokens.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct MockClaims {
+    #[serde(flatten)]
+    claims: Map<String, Value>,
+}
+
+impl AdditionalClaims for MockClaims {}
+
+type MockIdToken = IdToken<
+    MockClaims,
+    CoreGenderClaim,
+    CoreJweContentEncryptionAlgorithm,
+    CoreJwsSigningAlgorithm,
+    CoreJsonWebKeyType,
+>;
+
+async fn discovery(req: HttpRequest, state: web::Data<Mutex<MockIdpState>>) -> HttpResponse {
+    let (unavailable, has_policy) = {
+        let mut state = state.lock().unwrap();
+        (
+            state.take_unavailable() || state.failure == Some(MockIdpFailure::DocumentsUnavailable),
+            state.has_policy(&req),
+        )
+    };
+    if unavailable {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    if !has_policy {
+        return HttpResponse::NotFound().finish();
+    }
+    let (
+        advertised_issuer,
+        issuer,
+        end_session_endpoint,
+        revocation_endpoint,
+        device_endpoint,
+        token_endpoint,
+        token_exchange,
+    ) = {
+        let state = state.lock().unwrap();
+        let token_endpoint = state
+            .token_endpoint
+            .clone()
+            .unwrap_or_else(|| format!("{}/token", state.issuer_url));
+        (
+            state.issuer(),
+            state.issuer_url.clone(),
+            state.end_session_endpoint,
+            state.revocation_endpoint,
+            state.device_authorization_endpoint,
+            token_endpoint,
+            state.token_exchange,
+        )
+    };
+    let mut metadata = json!({
+        "issuer": advertised_issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": token_endpoint,
+        "userinfo_endpoint": format!("{issuer}/userinfo"),
+        "introspection_endpoint": format!("{issuer}/introspect"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "scopes_supported": ["openid", "profile", "email", "offline_access"],
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256", "PS256"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+        "grant_types_supported": [
+            "authorization_code",
+            "refresh_token",
+            "urn:ietf:params:oauth:grant-type:device_code",
+        ],
+        "code_challenge_methods_supported": ["S256"],
+    });
+    if end_session_endpoint {
+        metadata["end_session_endpoint"] = Value::String(format!("{issuer}/logout"));
+    }
+    if revocation_endpoint {
+        metadata["revocation_endpoint"] = Value::String(format!("{issuer}/revoke"));
+    }
+    if token_exchange {
+        metadata["grant_types_supported"]
+            .as_array_mut()
+            .unwrap()
+            .push(json!("urn:ietf:params:oauth:grant-type:token-exchange"));
+    }
+    if device_endpoint {
+        metadata["device_authorization_endpoint"] = Value::String(format!("{issuer}/device"));
+    }
+    HttpResponse::Ok().json(metadata)
+}
+
+async fn jwks(state: web::Data<Mutex<MockIdpState>>) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    if state.take_unavailable() || state.failure == Some(MockIdpFailure::DocumentsUnavailable) {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    HttpResponse::Ok().json(CoreJsonWebKeySet::new(vec![signing_key(
+        state.key_generation,
+    )
+    .as_verification_key()]))
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    prompt: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    response_mode: Option<String>,
+}
+
+async fn authorize(
+    state: web::Data<Mutex<MockIdpState>>,
+    query: web::Query<AuthorizeQuery>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    let Ok(mut redirect) = Url::parse(&query.redirect_uri) else {
+        return HttpResponse::BadRequest().body("invalid redirect_uri");
+    };
+    if query.code_challenge.is_some() && query.code_challenge_method.as_deref() != Some("S256") {
+        return HttpResponse::BadRequest().body("unsupported code_challenge_method");
+    }
+    let mut state = state.lock().unwrap();
+    let mut params = Vec::new();
+    if let Some(MockIdpFailure::AuthorizationError { error }) = state.failure {
+        params.push(("error", error.to_string()));
+        params.push(("error_description", format!("mock {}", error)));
+    } else if query.prompt.as_deref() == Some("none") && !state.signed_in {
+        params.push(("error", "login_required".to_string()));
+    } else {
+        let code = random_string();
+        state.codes.insert(
+            code.clone(),
+            PendingCode {
+                client_id: query.client_id,
+                nonce: query.nonce,
+                code_challenge: query.code_challenge,
+            },
+        );
+        params.push(("code", code));
+    }
+    if let Some(csrf_state) = query.state {
+        params.push(("state", csrf_state));
+    }
+    if query.response_mode.as_deref() == Some("form_post") {
+        // The page a browser submits right away, posting the parameters to the callback.
+        let inputs = params
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    r#"<input type="hidden" name="{}" value="{}">"#,
+                    name,
+                    html_escape(value)
+                )
+            })
+            .collect::<String>();
+        return HttpResponse::Ok().content_type("text/html").body(format!(
+            r#"<html><body onload="document.forms[0].submit()"><form method="post" action="{}">{}</form></body></html>"#,
+            html_escape(redirect.as_str()),
+            inputs
+        ));
+    }
+    redirect.query_pairs_mut().extend_pairs(params);
+    HttpResponse::Found()
+        .insert_header((LOCATION, redirect.to_string()))
+        .finish()
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    grant_type: String,
+    code: Option<String>,
+    code_verifier: Option<String>,
+    redirect_uri: Option<String>,
+    refresh_token: Option<String>,
+    device_code: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    subject_token: Option<String>,
+    subject_token_type: Option<String>,
+    audience: Option<String>,
+    scope: Option<String>,
+}
+
+async fn token(
+    req: HttpRequest,
+    state: web::Data<Mutex<MockIdpState>>,
+    form: web::Form<TokenForm>,
+) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    if !state.has_policy(&req) {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "invalid_request",
+            "error_description": "the policy is missing",
+        }));
+    }
+    let basic = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok()?.strip_prefix("Basic "))
+        .and_then(|credentials| {
+            base64::engine::general_purpose::STANDARD
+                .decode(credentials)
+                .ok()
+        })
+        .and_then(|credentials| String::from_utf8(credentials).ok());
+    let (basic_id, basic_secret) = match basic.as_deref().and_then(|basic| basic.split_once(':')) {
+        Some((id, secret)) => (Some(id.to_string()), Some(secret.to_string())),
+        None => (None, None),
+    };
+    state.token_requests.push(TokenRequest {
+        grant_type: form.grant_type.clone(),
+        client_id: form.client_id.clone().or(basic_id),
+        client_secret: form.client_secret.clone().or(basic_secret),
+        redirect_uri: form.redirect_uri.clone(),
+    });
+    match state.failure {
+        Some(MockIdpFailure::ServerError) => return HttpResponse::InternalServerError().finish(),
+        Some(MockIdpFailure::TokenError { status, error }) => {
+            return HttpResponse::build(StatusCode::from_u16(status).unwrap()).json(json!({
+                "error": error,
+                "error_description": format!("mock {}", error),
+            }))
+        }
+        _ => {}
+    }
+    match form.grant_type.as_str() {
+        "authorization_code" => {
+            let Some(pending) = form.code.as_ref().and_then(|code| state.codes.remove(code)) else {
+                return oauth_error("invalid_grant");
+            };
+            let verified = match (&pending.code_challenge, &form.code_verifier) {
+                (None, _) => true,
+                (Some(challenge), Some(verifier)) => {
+                    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier)) == *challenge
+                }
+                (Some(_), None) => false,
+            };
+            if !verified {
+                return oauth_error("invalid_grant");
+            }
+            let code = AuthorizationCode::new(form.code.clone().unwrap());
+            let (user, refresh_token) = (state.user.clone(), state.login_refresh_tokens);
+            issue_tokens(
+                &mut state,
+                pending.client_id,
+                user,
+                pending.nonce,
+                Some(&code),
+                refresh_token,
+            )
+        }
+        "refresh_token" => {
+            let rotate = state.rotate_refresh_tokens;
+            let issued = form.refresh_token.as_ref().and_then(|token| match rotate {
+                true => state.refresh_tokens.remove(token),
+                false => state.refresh_tokens.get(token).cloned(),
+            });
+            // Refreshes go on for the user the refresh token was issued to.
+            match issued {
+                Some((client_id, user)) => {
+                    issue_tokens(&mut state, client_id, user, None, None, rotate)
+                }
+                None => oauth_error("invalid_grant"),
+            }
+        }
+        "urn:ietf:params:oauth:grant-type:device_code" => {
+            let device_code = form.device_code.clone().unwrap_or_default();
+            match state.device_codes.get_mut(&device_code) {
+                Some((_, pending)) if *pending > 0 => {
+                    *pending -= 1;
+                    oauth_error("authorization_pending")
+                }
+                Some(_) => {
+                    let (client_id, _) = state.device_codes.remove(&device_code).unwrap();
+                    let user = state.user.clone();
+                    issue_tokens(&mut state, client_id, user, None, None, true)
+                }
+                None => oauth_error("expired_token"),
+            }
+        }
+        "client_credentials" => {
+            let access_token = random_string();
+            state.access_tokens.insert(access_token.clone(), Map::new());
+            HttpResponse::Ok().json(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": state.token_lifetime.as_secs(),
+            }))
+        }
+        "urn:ietf:params:oauth:grant-type:token-exchange" if state.token_exchange => {
+            let user = form
+                .subject_token
+                .as_ref()
+                .filter(|_| {
+                    form.subject_token_type.as_deref()
+                        == Some("urn:ietf:params:oauth:token-type:access_token")
+                })
+                .and_then(|token| state.access_tokens.get(token))
+                .cloned();
+            let Some(user) = user else {
+                return oauth_error("invalid_grant");
+            };
+            let audience = form.audience.clone().unwrap_or_default();
+            if state
+                .token_exchange_audiences
+                .as_ref()
+                .is_some_and(|audiences| !audiences.contains(&audience))
+            {
+                return oauth_error("invalid_target");
+            }
+            let access_token = random_string();
+            state.access_tokens.insert(access_token.clone(), user);
+            let mut response = json!({
+                "access_token": access_token,
+                "issued_token_type": "urn:ietf:params:oauth:token-type:access_token",
+                "token_type": "Bearer",
+                "expires_in": state.token_lifetime.as_secs(),
+            });
+            if let Some(scope) = &form.scope {
+                response["scope"] = json!(scope);
+            }
+            HttpResponse::Ok().json(response)
+        }
+        "password" => {
+            let known = match (&form.username, &form.password) {
+                (Some(username), Some(password)) => state.passwords.get(username) == Some(password),
+                _ => false,
+            };
+            if !known {
+                return oauth_error("invalid_grant");
+            }
+            let mut user = state.user.clone();
+            user.insert("sub".to_string(), json!(form.username));
+            let access_token = random_string();
+            state.access_tokens.insert(access_token.clone(), user);
+            HttpResponse::Ok().json(json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": state.token_lifetime.as_secs(),
+            }))
+        }
+        _ => oauth_error("unsupported_grant_type"),
+    }
+}
+
+fn issue_tokens(
+    state: &mut MockIdpState,
+    client_id: String,
+    user: Map<String, Value>,
+    nonce: Option<String>,
+    code: Option<&AuthorizationCode>,
+    refresh_token: bool,
+) -> HttpResponse {
+    let lifetime = state.token_lifetime.as_secs();
+    let issued_at = now();
+    let access_token = match state.jwt_access_tokens {
+        true => {
+            let mut claims = user.clone();
+            claims.insert("iss".to_string(), json!(state.issuer()));
+            claims.insert("aud".to_string(), json!([client_id]));
+            claims.insert("azp".to_string(), json!(client_id));
+            claims.insert("iat".to_string(), json!(issued_at));
+            claims.insert("exp".to_string(), json!(issued_at + lifetime));
+            claims.insert("jti".to_string(), json!(random_string()));
+            AccessToken::new(sign(state.key_generation, &Value::Object(claims)))
+        }
+        false => AccessToken::new(random_string()),
+    };
+
+    let expires_at = match state.failure {
+        Some(MockIdpFailure::ExpiredIdToken) => issued_at - 60,
+        _ => issued_at + lifetime,
+    };
+    let nonce = match state.failure {
+        Some(MockIdpFailure::WrongNonce) => Some(random_string()),
+        _ => nonce,
+    };
+    let mut claims = user.clone();
+    claims.insert("iss".to_string(), json!(state.issuer()));
+    claims.insert("aud".to_string(), json!([client_id]));
+    claims.insert("iat".to_string(), json!(issued_at));
+    claims.insert("exp".to_string(), json!(expires_at));
+    if let Some(nonce) = nonce {
+        claims.insert("nonce".to_string(), json!(nonce));
+    }
+    claims.extend(state.id_token_claims.clone());
+    let claims: IdTokenClaims<MockClaims, CoreGenderClaim> =
+        serde_json::from_value(Value::Object(claims)).expect("invalid mock IdP user claims");
+    let (other_access_token, other_code) = (
+        AccessToken::new(random_string()),
+        AuthorizationCode::new(random_string()),
+    );
+    let (hashed_access_token, hashed_code) = match state.failure {
+        Some(MockIdpFailure::WrongTokenHashes) => (Some(&other_access_token), Some(&other_code)),
+        Some(MockIdpFailure::NoTokenHashes) => (None, None),
+        _ => (Some(&access_token), code),
+    };
+    let id_token = MockIdToken::new(
+        claims,
+        &signing_key(state.key_generation),
+        state.id_token_alg.clone(),
+        hashed_access_token,
+        hashed_code,
+    )
+    .unwrap();
+
+    state
+        .access_tokens
+        .insert(access_token.secret().to_string(), user.clone());
+    state
+        .access_token_clients
+        .insert(access_token.secret().to_string(), client_id.clone());
+    let mut response = json!({
+        "access_token": access_token.secret(),
+        "token_type": "Bearer",
+        "expires_in": lifetime,
+        "id_token": id_token.to_string(),
+    });
+    if refresh_token {
+        let refresh_token = random_string();
+        state
+            .refresh_tokens
+            .insert(refresh_token.clone(), (client_id, user));
+        response["refresh_token"] = json!(refresh_token);
+    }
+    if let Some(refresh_lifetime) = state.refresh_token_lifetime {
+        response["refresh_expires_in"] = json!(refresh_lifetime.as_secs());
+    }
+    HttpResponse::Ok().json(response)
+}
+
+fn oauth_error(error: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "error": error }))
+}
+
+async fn userinfo(state: web::Data<Mutex<MockIdpState>>, req: HttpRequest) -> HttpResponse {
+    let delay = state.lock().unwrap().userinfo_delay;
+    actix_web::rt::time::sleep(delay).await;
+    let mut state = state.lock().unwrap();
+    if state.take_unavailable() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    if state.failure == Some(MockIdpFailure::ServerError) {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    match state.access_tokens.get(token) {
+        Some(user) if state.signed_userinfo => {
+            let mut claims = user.clone();
+            claims.insert("iss".to_string(), json!(state.issuer()));
+            if let Some(client_id) = state.access_token_clients.get(token) {
+                claims.insert("aud".to_string(), json!(client_id));
+            }
+            HttpResponse::Ok()
+                .insert_header((CONTENT_TYPE, "application/jwt"))
+                .body(sign(state.key_generation, &Value::Object(claims)))
+        }
+        Some(user) => HttpResponse::Ok()
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .json(user),
+        None => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+struct LogoutQuery {
+    post_logout_redirect_uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RevocationForm {
+    token: String,
+    client_id: Option<String>,
+}
+
+/// Revokes the access and refresh tokens of authenticated clients. Unknown tokens succeed, as
+/// RFC 7009 section 2.2 asks.
+async fn revoke(
+    req: HttpRequest,
+    state: web::Data<Mutex<MockIdpState>>,
+    form: web::Form<RevocationForm>,
+) -> HttpResponse {
+    if !req.headers().contains_key(AUTHORIZATION) && form.client_id.is_none() {
+        return HttpResponse::Unauthorized().json(json!({ "error": "invalid_client" }));
+    }
+    let mut state = state.lock().unwrap();
+    state.access_tokens.remove(&form.token);
+    state.refresh_tokens.remove(&form.token);
+    HttpResponse::Ok().finish()
+}
+
+/// Tells authenticated clients whether an access token is active (RFC 7662), with the claims of
+/// its user and the scopes `openid profile email`. Refresh tokens and unknown tokens are inactive.
+async fn introspect(
+    req: HttpRequest,
+    state: web::Data<Mutex<MockIdpState>>,
+    form: web::Form<RevocationForm>,
+) -> HttpResponse {
+    if !req.headers().contains_key(AUTHORIZATION) && form.client_id.is_none() {
+        return HttpResponse::Unauthorized().json(json!({ "error": "invalid_client" }));
+    }
+    let mut state = state.lock().unwrap();
+    state.introspections += 1;
+    if state.failure == Some(MockIdpFailure::ServerError) {
+        return HttpResponse::InternalServerError().finish();
+    }
+    let Some(user) = state.access_tokens.get(&form.token) else {
+        return HttpResponse::Ok().json(json!({ "active": false }));
+    };
+    let mut response = user.clone();
+    response.insert("active".to_string(), json!(true));
+    response.insert("scope".to_string(), json!("openid profile email"));
+    response.insert("token_type".to_string(), json!("Bearer"));
+    response.insert(
+        "exp".to_string(),
+        json!(now() + state.token_lifetime.as_secs()),
+    );
+    HttpResponse::Ok().json(response)
+}
+
+#[derive(Deserialize)]
+struct DeviceAuthorizationForm {
+    client_id: Option<String>,
+}
+
+async fn device_authorization(
+    req: HttpRequest,
+    state: web::Data<Mutex<MockIdpState>>,
+    form: web::Form<DeviceAuthorizationForm>,
+) -> HttpResponse {
+    let basic_id = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok()?.strip_prefix("Basic "))
+        .and_then(|credentials| {
+            base64::engine::general_purpose::STANDARD
+                .decode(credentials)
+                .ok()
+        })
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| Some(credentials.split_once(':')?.0.to_string()));
+    let Some(client_id) = form.client_id.clone().or(basic_id) else {
+        return HttpResponse::Unauthorized().json(json!({ "error": "invalid_client" }));
+    };
+    let mut state = state.lock().unwrap();
+    let device_code = random_string();
+    let user_code = random_string()[..8].to_ascii_uppercase();
+    let pending = state.device_approval_polls;
+    state
+        .device_codes
+        .insert(device_code.clone(), (client_id, pending));
+    let verification_uri = format!("{}/device/verify", state.issuer_url);
+    HttpResponse::Ok().json(json!({
+        "device_code": device_code,
+        "user_code": user_code,
+        "verification_uri": verification_uri,
+        "verification_uri_complete": format!("{}?user_code={}", verification_uri, user_code),
+        "expires_in": 600,
+        "interval": 5,
+    }))
+}
+
+async fn logout(query: web::Query<LogoutQuery>) -> HttpResponse {
+    match &query.post_logout_redirect_uri {
+        Some(uri) => HttpResponse::Found()
+            .insert_header((LOCATION, uri.as_str()))
+            .finish(),
+        None => HttpResponse::Ok().body("logged out"),
+    }
+}
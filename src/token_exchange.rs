@@ -0,0 +1,238 @@
+//! Token exchange (RFC 8693), trading a user's access token for one meant for another service.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use openidconnect::{AccessToken, Scope};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::clock::{system_time, Clock};
+use crate::error::{OpenIdError, Result};
+use crate::openid::{provider_error, OpenID};
+use crate::openid_middleware::AuthenticatedUser;
+use crate::session_token::REFRESH_WINDOW;
+
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Exchanged tokens kept at most, those expiring first make room.
+const MAX_ENTRIES: usize = 1024;
+
+/// A token the provider issued for another audience, see [`OpenID::exchange_token`].
+#[derive(Clone)]
+pub struct ExchangedToken {
+    pub access_token: AccessToken,
+    /// The type of the token, e.g. `urn:ietf:params:oauth:token-type:access_token`.
+    pub issued_token_type: String,
+    pub expires_in: Option<Duration>,
+    /// The scopes granted, `None` when they are those asked for.
+    pub scopes: Option<Vec<Scope>>,
+}
+
+/// Never the token.
+impl fmt::Debug for ExchangedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExchangedToken")
+            .field("issued_token_type", &self.issued_token_type)
+            .field("expires_in", &self.expires_in)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct ExchangeResponse {
+    access_token: String,
+    issued_token_type: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+    #[serde(default)]
+    scope: Option<String>,
+}
+
+struct Cached {
+    token: ExchangedToken,
+    renew_at: SystemTime,
+}
+
+/// Exchanged tokens by the SHA-256 of the subject token, audience and scopes, until a minute
+/// before they expire. Tokens the provider gives no lifetime are not kept.
+pub(crate) struct ExchangeCache {
+    clock: Arc<dyn Clock>,
+    entries: Mutex<HashMap<[u8; 32], Cached>>,
+}
+
+impl ExchangeCache {
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        ExchangeCache {
+            clock,
+            entries: Mutex::default(),
+        }
+    }
+
+    fn get(&self, key: &[u8; 32]) -> Option<ExchangedToken> {
+        let now = system_time(self.clock.as_ref());
+        self.entries
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|cached| now < cached.renew_at)
+            .map(|cached| cached.token.clone())
+    }
+
+    fn insert(&self, key: [u8; 32], token: &ExchangedToken) {
+        let Some(expires_in) = token.expires_in else {
+            return;
+        };
+        let now = system_time(self.clock.as_ref());
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, cached| now < cached.renew_at);
+        }
+        if entries.len() >= MAX_ENTRIES {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.renew_at)
+                .map(|(key, _)| *key);
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(
+            key,
+            Cached {
+                token: token.clone(),
+                renew_at: now + expires_in.saturating_sub(REFRESH_WINDOW),
+            },
+        );
+    }
+}
+
+fn cache_key(subject_token: &AccessToken, audience: &str, scopes: &[Scope]) -> [u8; 32] {
+    let mut scopes: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+    scopes.sort_unstable();
+    scopes.dedup();
+    let mut hasher = Sha256::new();
+    for part in [subject_token.secret().as_str(), audience]
+        .into_iter()
+        .chain(scopes)
+    {
+        // Prefixed with their length, the parts cannot run into each other.
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+impl OpenID {
+    /// Exchanges the user's `subject_token` for an access token for `audience` with `scopes`,
+    /// with the token exchange grant (RFC 8693), e.g. to call an internal service on behalf of
+    /// the user. The token request is authenticated like the others.
+    ///
+    /// Tokens are cached by the client and its clones for the subject token, audience and scopes,
+    /// until a minute before they expire. Fails with [`OpenIdError::UnsupportedGrant`] when the
+    /// provider does not support token exchange, and with [`OpenIdError::TokenExchange`] carrying
+    /// the provider's error, e.g. `invalid_target` or `access_denied`, when it refuses this one.
+    pub async fn exchange_token(
+        &self,
+        subject_token: &AccessToken,
+        audience: &str,
+        scopes: &[Scope],
+    ) -> Result<ExchangedToken> {
+        let key = cache_key(subject_token, audience, scopes);
+        if let Some(token) = self.exchange_cache().get(&key) {
+            return Ok(token);
+        }
+        let metadata = self.provider_metadata();
+        let advertised = metadata.grant_types_supported().map(|grant_types| {
+            grant_types
+                .iter()
+                .any(|grant_type| grant_type.as_ref() == TOKEN_EXCHANGE_GRANT_TYPE)
+        });
+        if advertised == Some(false) {
+            return Err(OpenIdError::UnsupportedGrant(
+                TOKEN_EXCHANGE_GRANT_TYPE.to_string(),
+            ));
+        }
+        let token_url = metadata
+            .token_endpoint()
+            .map(|token_url| token_url.url().clone())
+            .ok_or_else(|| {
+                OpenIdError::Config("the provider advertises no token endpoint".to_string())
+            })?;
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", TOKEN_EXCHANGE_GRANT_TYPE)
+            .append_pair("subject_token", subject_token.secret())
+            .append_pair("subject_token_type", ACCESS_TOKEN_TYPE)
+            .append_pair("requested_token_type", ACCESS_TOKEN_TYPE)
+            .append_pair("audience", audience);
+        if !scopes.is_empty() {
+            let scopes: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+            form.append_pair("scope", &scopes.join(" "));
+        }
+        let response = self.post_form(token_url, form).await?;
+        let status = response.status_code.as_u16();
+        if !response.status_code.is_success() {
+            let provider_error = provider_error(&response.body);
+            if provider_error
+                .as_ref()
+                .is_some_and(|err| err.error == "unsupported_grant_type")
+            {
+                return Err(OpenIdError::UnsupportedGrant(
+                    TOKEN_EXCHANGE_GRANT_TYPE.to_string(),
+                ));
+            }
+            return Err(OpenIdError::TokenExchange {
+                provider_error,
+                status: Some(status),
+                source: format!("the token endpoint answered with HTTP status {}", status).into(),
+            });
+        }
+        let response =
+            serde_json::from_slice::<ExchangeResponse>(&response.body).map_err(|err| {
+                OpenIdError::TokenExchange {
+                    provider_error: None,
+                    status: Some(status),
+                    source: Box::new(err),
+                }
+            })?;
+        let token = ExchangedToken {
+            access_token: AccessToken::new(response.access_token),
+            issued_token_type: response.issued_token_type,
+            expires_in: response.expires_in.map(Duration::from_secs),
+            scopes: response.scope.map(|scope| {
+                scope
+                    .split_whitespace()
+                    .map(|scope| Scope::new(scope.to_string()))
+                    .collect()
+            }),
+        };
+        self.exchange_cache().insert(key, &token);
+        Ok(token)
+    }
+}
+
+impl<AC: openidconnect::AdditionalClaims> AuthenticatedUser<AC> {
+    /// Exchanges the user's access token for one for `audience`, see
+    /// [`OpenID::exchange_token`]:
+    ///
+    /// ```ignore
+    /// #[get("/stock")]
+    /// async fn stock(user: Authenticated, openid: web::Data<OpenID>) -> actix_web::Result<String> {
+    ///     let token = user.token_for("api://inventory", &openid).await?;
+    ///     // ...
+    /// }
+    /// ```
+    ///
+    /// Fails with [`OpenIdError::Config`] for users authenticated without an access token, e.g.
+    /// by an API key.
+    pub async fn token_for(&self, audience: &str, client: &OpenID) -> Result<ExchangedToken> {
+        let access_token = self.access_token().ok_or_else(|| {
+            OpenIdError::Config("the user was not authenticated with an access token".to_string())
+        })?;
+        client.exchange_token(access_token, audience, &[]).await
+    }
+}
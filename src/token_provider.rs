@@ -0,0 +1,292 @@
+//! Tokens for background jobs, which have no incoming request to take a session from.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use actix_web::dev::Payload;
+use actix_web::{error, Error, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use futures_util::lock::Mutex;
+use openidconnect::{AccessToken, RefreshToken, Scope};
+use secrecy::{ExposeSecret, SecretString};
+use tokio::sync::watch;
+
+use crate::credentials;
+use crate::error::{ErrorAction, Result};
+use crate::logging::LogCategory;
+use crate::messages::MessageKey;
+use crate::openid::OpenID;
+use crate::openid_middleware::RegisteredClient;
+use crate::session_token::REFRESH_WINDOW;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What a [`TokenProvider`] gets its tokens with.
+#[derive(Clone, Debug)]
+pub enum TokenSource {
+    /// Tokens for the client itself, from the client credentials grant with these scopes.
+    ClientCredentials(Vec<String>),
+    /// Tokens on behalf of a user, from a refresh token stored earlier. Rotated refresh tokens
+    /// replace it.
+    RefreshToken(RefreshToken),
+}
+
+/// Whether a [`TokenProvider`] can renew its token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenStatus {
+    Healthy,
+    /// The last `attempts` renewals failed, the last one with `error`.
+    Failing {
+        error: String,
+        attempts: u32,
+    },
+}
+
+/// Hands out access tokens to background jobs, renewing them before they expire.
+///
+/// Created with [`OpenID::token_provider`]. Clones share the cached token and `get_token` can be
+/// called from any tokio task. Temporary renewal failures are retried with backoff, jobs can
+/// watch [`TokenProvider::status`] to pause meanwhile.
+#[derive(Clone)]
+pub struct TokenProvider {
+    client: OpenID,
+    cache: Arc<TokenCache>,
+}
+
+/// The token of a [`TokenProvider`], apart from the client so that the client can keep those of
+/// [`OpenID::client_credentials_token`].
+pub(crate) struct TokenCache {
+    state: Mutex<ProviderState>,
+    status: watch::Sender<TokenStatus>,
+}
+
+impl TokenCache {
+    fn new(grant: Grant) -> Self {
+        TokenCache {
+            state: Mutex::new(ProviderState {
+                grant,
+                access_token: None,
+                renew_at: None,
+            }),
+            status: watch::Sender::new(TokenStatus::Healthy),
+        }
+    }
+}
+
+/// The token status, never the tokens.
+impl fmt::Debug for TokenProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenProvider")
+            .field("status", &*self.cache.status.borrow())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The [`TokenSource`], its refresh token kept as a secret.
+enum Grant {
+    ClientCredentials(Vec<Scope>),
+    RefreshToken(SecretString),
+}
+
+struct ProviderState {
+    grant: Grant,
+    access_token: Option<SecretString>,
+    /// When the cached token is about to expire, `None` if the provider did not tell.
+    renew_at: Option<SystemTime>,
+}
+
+impl TokenProvider {
+    /// The cached access token, renewed first if it is about to expire.
+    ///
+    /// Waits while the provider is unavailable, fails if it refuses to issue a token.
+    pub async fn get_token(&self) -> Result<AccessToken> {
+        self.token(true).await
+    }
+
+    /// The cached access token, renewed by the first of concurrent callers while the others
+    /// wait for its token. Renewals failing for a while are retried if `retry`.
+    async fn token(&self, retry: bool) -> Result<AccessToken> {
+        let mut state = self.cache.state.lock().await;
+        if let Some(access_token) = &state.access_token {
+            if state.renew_at.is_none_or(|at| self.client.now() < at) {
+                return Ok(AccessToken::new(access_token.expose_secret().clone()));
+            }
+        }
+        let log_policy = self.client.log_policy();
+        let mut attempts = 0;
+        loop {
+            let err = match self.renew(&mut state).await {
+                Ok(access_token) => {
+                    log_policy.log(LogCategory::Refresh, format_args!("Renewed the job token"));
+                    self.cache.status.send_replace(TokenStatus::Healthy);
+                    return Ok(access_token);
+                }
+                Err(err) => err,
+            };
+            attempts += 1;
+            log_policy.log(
+                LogCategory::Refresh,
+                format_args!(
+                    "Could not renew the job token ({} attempts): {}",
+                    attempts, err
+                ),
+            );
+            self.cache.status.send_replace(TokenStatus::Failing {
+                error: err.to_string(),
+                attempts,
+            });
+            if !retry || !matches!(self.client.error_action(&err), ErrorAction::RetryLater(_)) {
+                return Err(err);
+            }
+            tokio::time::sleep(backoff(attempts)).await;
+        }
+    }
+
+    /// Follows the renewal status, e.g. to pause a job while the provider is failing.
+    pub fn status(&self) -> watch::Receiver<TokenStatus> {
+        self.cache.status.subscribe()
+    }
+
+    async fn renew(&self, state: &mut ProviderState) -> Result<AccessToken> {
+        let tokens = match &state.grant {
+            Grant::ClientCredentials(scopes) => self.client.client_credentials(scopes).await?,
+            Grant::RefreshToken(refresh_token) => {
+                let refresh_token = RefreshToken::new(refresh_token.expose_secret().clone());
+                self.client.refresh(&refresh_token).await?
+            }
+        };
+        if let (Grant::RefreshToken(current), Some(rotated)) =
+            (&mut state.grant, tokens.refresh_token)
+        {
+            *current = SecretString::new(rotated.secret().clone());
+        }
+        state.renew_at = tokens
+            .expires_in
+            .map(|expires_in| self.client.now() + expires_in.saturating_sub(REFRESH_WINDOW));
+        state.access_token = Some(SecretString::new(tokens.access_token.secret().clone()));
+        Ok(tokens.access_token)
+    }
+}
+
+fn backoff(attempts: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+impl OpenID {
+    /// Creates a [`TokenProvider`] for background jobs getting its tokens from `source`.
+    pub fn token_provider(&self, source: TokenSource) -> TokenProvider {
+        let grant = match source {
+            TokenSource::ClientCredentials(scopes) => {
+                Grant::ClientCredentials(scopes.into_iter().map(Scope::new).collect())
+            }
+            TokenSource::RefreshToken(refresh_token) => {
+                Grant::RefreshToken(SecretString::new(refresh_token.secret().clone()))
+            }
+        };
+        TokenProvider {
+            client: self.clone(),
+            cache: Arc::new(TokenCache::new(grant)),
+        }
+    }
+
+    /// A token of the client itself for `scopes`, from the client credentials grant, e.g. to call
+    /// another API as the service rather than for a user. The token is cached by the client and
+    /// its clones, and renewed a minute before it expires; concurrent callers wait for a single
+    /// renewal. Fails right away when the provider does not issue one, unlike
+    /// [`TokenProvider::get_token`].
+    pub async fn client_credentials_token(&self, scopes: &[Scope]) -> Result<AccessToken> {
+        let mut names: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+        names.sort();
+        names.dedup();
+        let cached = self.client_tokens().read().unwrap().get(&names).cloned();
+        let cache = match cached {
+            Some(cache) => cache,
+            None => self
+                .client_tokens()
+                .write()
+                .unwrap()
+                .entry(names)
+                .or_insert_with(|| {
+                    Arc::new(TokenCache::new(Grant::ClientCredentials(scopes.to_vec())))
+                })
+                .clone(),
+        };
+        TokenProvider {
+            client: self.clone(),
+            cache,
+        }
+        .token(false)
+        .await
+    }
+}
+
+/// A token of the client itself, see [`OpenID::client_credentials_token`], for the scopes of
+/// [`client_token_scopes`](crate::OpenIdBuilder::client_token_scopes):
+///
+/// ```ignore
+/// #[get("/report")]
+/// async fn report(token: ClientToken) -> actix_web::Result<HttpResponse> {
+///     let report = reqwest::Client::new()
+///         .get("https://reports.internal/api/report")
+///         .bearer_auth(token.access_token().secret())
+///         .send()
+///         .await;
+///     // ...
+/// }
+/// ```
+///
+/// Answers `503 Service Unavailable` while the provider is unavailable, and `500 Internal
+/// Server Error` when it refuses to issue the token.
+pub struct ClientToken(AccessToken);
+
+impl ClientToken {
+    pub fn access_token(&self) -> &AccessToken {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> AccessToken {
+        self.0
+    }
+}
+
+/// Never shows the token.
+impl fmt::Debug for ClientToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientToken").finish_non_exhaustive()
+    }
+}
+
+impl FromRequest for ClientToken {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let client = RegisteredClient::get(req);
+        Box::pin(async move {
+            let client = client?.0;
+            let scopes = client.client_token_scopes().to_vec();
+            client
+                .client_credentials_token(&scopes)
+                .await
+                .map(ClientToken)
+                .map_err(|err| match client.error_action(&err) {
+                    ErrorAction::RetryLater(retry_after) => {
+                        credentials::idp_unavailable(&client, err, retry_after)
+                    }
+                    _ => {
+                        client.log_policy().log_error(
+                            LogCategory::IdpError,
+                            format_args!("Could not get a client token: {}", err),
+                        );
+                        error::ErrorInternalServerError(
+                            client.message(MessageKey::InternalError, &[]),
+                        )
+                    }
+                })
+        })
+    }
+}
@@ -0,0 +1,141 @@
+//! The claims the userinfo endpoint returned for access tokens, reused until they expire, and
+//! the access tokens it rejected, not sent again for a while.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use openidconnect::core::CoreGenderClaim;
+use openidconnect::{AccessToken, UserInfoClaims};
+use sha2::{Digest, Sha256};
+
+use crate::clock::{system_time, Clock};
+use crate::openid::OtherClaims;
+
+type Claims = UserInfoClaims<OtherClaims, CoreGenderClaim>;
+
+struct Cached {
+    claims: Claims,
+    expires_at: SystemTime,
+}
+
+/// Claims by the SHA-256 of the access token, the tokens themselves are not kept.
+pub(crate) struct UserInfoCache {
+    ttl: Duration,
+    max_entries: usize,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<HashMap<[u8; 32], Cached>>,
+}
+
+impl UserInfoCache {
+    pub(crate) fn new(ttl: Duration, max_entries: usize, clock: Arc<dyn Clock>) -> Self {
+        UserInfoCache {
+            ttl,
+            max_entries,
+            clock,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// The claims cached for `access_token`, unless they expired.
+    pub(crate) fn get(&self, access_token: &AccessToken) -> Option<Claims> {
+        let now = system_time(self.clock.as_ref());
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&key(access_token))
+            .filter(|cached| now < cached.expires_at)
+            .map(|cached| cached.claims.clone())
+    }
+
+    /// Caches `claims` for the TTL, or until `token_expires_at` if that is sooner.
+    pub(crate) fn insert(
+        &self,
+        access_token: &AccessToken,
+        claims: Claims,
+        token_expires_at: Option<SystemTime>,
+    ) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let now = system_time(self.clock.as_ref());
+        let expires_at = match token_expires_at {
+            Some(token_expires_at) => token_expires_at.min(now + self.ttl),
+            None => now + self.ttl,
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, cached| now < cached.expires_at);
+        }
+        if entries.len() >= self.max_entries {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.expires_at)
+                .map(|(key, _)| *key);
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key(access_token), Cached { claims, expires_at });
+    }
+
+    /// Drops the claims of `access_token`, e.g. once the session ended.
+    pub(crate) fn remove(&self, access_token: &AccessToken) {
+        self.entries.lock().unwrap().remove(&key(access_token));
+    }
+}
+
+/// The access tokens the provider rejected recently by their SHA-256, with when they may be
+/// sent to it again. The oldest rejection makes room once `max_entries` are kept.
+pub(crate) struct NegativeCache {
+    ttl: Duration,
+    max_entries: usize,
+    clock: Arc<dyn Clock>,
+    entries: Mutex<HashMap<[u8; 32], SystemTime>>,
+}
+
+impl NegativeCache {
+    pub(crate) fn new(ttl: Duration, max_entries: usize, clock: Arc<dyn Clock>) -> Self {
+        NegativeCache {
+            ttl,
+            max_entries,
+            clock,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Whether the provider rejected `access_token` less than the TTL ago.
+    pub(crate) fn contains(&self, access_token: &AccessToken) -> bool {
+        let now = system_time(self.clock.as_ref());
+        self.entries
+            .lock()
+            .unwrap()
+            .get(&key(access_token))
+            .is_some_and(|expires_at| now < *expires_at)
+    }
+
+    pub(crate) fn insert(&self, access_token: &AccessToken) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let now = system_time(self.clock.as_ref());
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, expires_at| now < *expires_at);
+        }
+        if entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, expires_at)| **expires_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key(access_token), now + self.ttl);
+    }
+}
+
+fn key(access_token: &AccessToken) -> [u8; 32] {
+    Sha256::digest(access_token.secret()).into()
+}
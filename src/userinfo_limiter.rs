@@ -0,0 +1,63 @@
+//! Bounds the userinfo and introspection requests in flight, one at a time per access token.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use openidconnect::AccessToken;
+use sha2::{Digest, Sha256};
+use tokio::sync::{OwnedMutexGuard, Semaphore, SemaphorePermit};
+
+/// See [`OpenIdBuilder::userinfo_concurrency`](crate::OpenIdBuilder::userinfo_concurrency).
+pub(crate) struct UserInfoLimiter {
+    calls: Semaphore,
+    /// The locks of the access tokens being validated by their SHA-256, dropped with their last
+    /// waiter.
+    tokens: Mutex<HashMap<[u8; 32], Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Allows one request to the provider about an access token, held until it was answered.
+pub(crate) struct TokenPermit<'a> {
+    limiter: &'a UserInfoLimiter,
+    key: [u8; 32],
+    token: Option<OwnedMutexGuard<()>>,
+}
+
+impl UserInfoLimiter {
+    pub(crate) fn new(max_calls: usize) -> Self {
+        UserInfoLimiter {
+            calls: Semaphore::new(max_calls.clamp(1, Semaphore::MAX_PERMITS)),
+            tokens: Mutex::default(),
+        }
+    }
+
+    /// Waits until no other request validates `access_token`. Those waiting for the same token
+    /// find its outcome cached once they get their turn.
+    pub(crate) async fn lock_token(&self, access_token: &AccessToken) -> TokenPermit<'_> {
+        let key: [u8; 32] = Sha256::digest(access_token.secret()).into();
+        let lock = self.tokens.lock().unwrap().entry(key).or_default().clone();
+        TokenPermit {
+            limiter: self,
+            key,
+            token: Some(lock.lock_owned().await),
+        }
+    }
+
+    /// Waits until fewer than the maximum of requests are in flight.
+    pub(crate) async fn acquire_call(&self) -> SemaphorePermit<'_> {
+        // The semaphore is never closed.
+        self.calls.acquire().await.unwrap()
+    }
+}
+
+impl Drop for TokenPermit<'_> {
+    fn drop(&mut self) {
+        let mut tokens = self.limiter.tokens.lock().unwrap();
+        self.token.take();
+        if tokens
+            .get(&self.key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            tokens.remove(&self.key);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Startup checks cross-checking the configuration against the provider's discovery document.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use openidconnect::Scope;
+use url::Host;
+
+use crate::openid::{OpenID, ValidationMode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The configuration works, but probably not the way it was meant to.
+    Warning,
+    /// The login flow cannot succeed with this configuration.
+    Error,
+}
+
+/// A problem found by [`OpenID::validate`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn warning(message: impl Into<String>) -> Self {
+        ConfigIssue {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        ConfigIssue {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    /// Whether the issue prevents the client from being built, warnings only do in strict mode.
+    pub fn is_fatal(&self, strict: bool) -> bool {
+        strict || self.severity == Severity::Error
+    }
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+impl OpenID {
+    /// Cross-checks the configuration against the discovery document.
+    ///
+    /// Run by [`OpenIdBuilder::build`](crate::OpenIdBuilder::build), which fails on errors, and
+    /// on warnings too when built with `strict(true)`.
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let redirect_url = self.redirect_url();
+
+        // The callback endpoint mounted by `configure_open_id`, below the realm's scope.
+        let callback_path = self.realm().callback_path();
+        if !redirect_url.path().ends_with(&callback_path) {
+            issues.push(ConfigIssue::error(format!(
+                "redirect url {} does not point to the {} endpoint",
+                redirect_url, callback_path
+            )));
+        }
+
+        let loopback = match redirect_url.host() {
+            Some(Host::Domain(domain)) => domain == "localhost",
+            Some(Host::Ipv4(ip)) => ip.is_loopback(),
+            Some(Host::Ipv6(ip)) => ip.is_loopback(),
+            None => false,
+        };
+        if redirect_url.scheme() == "http" && !loopback && self.cookie_config().is_secure() {
+            issues.push(ConfigIssue::warning(format!(
+                "redirect url {} uses http, browsers drop the secure session cookies",
+                redirect_url
+            )));
+        }
+
+        // The placeholders of a provider yet to be discovered advertise nothing.
+        let discovered = self.is_discovered();
+        if discovered && !self.has_end_session_endpoint() {
+            issues.push(ConfigIssue::warning(format!(
+                "the provider has no end_session_endpoint, {} redirects to {} without ending \
+                 the provider session",
+                self.realm().logout_path(),
+                self.post_logout_redirect_url().unwrap_or("/")
+            )));
+        }
+
+        if discovered
+            && self.validation_mode() == ValidationMode::Introspection
+            && !self.has_introspection_endpoint()
+        {
+            issues.push(ConfigIssue::error(
+                "introspection validation is enabled, but the provider advertises no \
+                 introspection_endpoint"
+                    .to_string(),
+            ));
+        }
+
+        let (frontchannel_supported, backchannel_supported) = self.provider_logout_supported();
+        if discovered && self.front_channel_logout().is_some() && !frontchannel_supported {
+            issues.push(ConfigIssue::warning(
+                "front-channel logout is enabled, but the provider does not advertise \
+                 frontchannel_logout_supported"
+                    .to_string(),
+            ));
+        }
+        if self.back_channel_logout().is_some() {
+            if self.session_store().is_none() {
+                issues.push(ConfigIssue::error(
+                    "back-channel logout needs a session_store to end the sessions".to_string(),
+                ));
+            }
+            if discovered && !backchannel_supported {
+                issues.push(ConfigIssue::warning(
+                    "back-channel logout is enabled, but the provider does not advertise \
+                     backchannel_logout_supported"
+                        .to_string(),
+                ));
+            }
+        }
+
+        #[cfg(feature = "first-party-session")]
+        if let crate::first_party::SessionFormat::FirstPartyJwt { .. } = self.session_format() {
+            if self.session_store().is_none() {
+                issues.push(ConfigIssue::error(
+                    "first-party sessions need a session_store for the provider's tokens"
+                        .to_string(),
+                ));
+            }
+        }
+
+        let metadata = self.provider_metadata();
+        let algs_supported = metadata.id_token_signing_alg_values_supported();
+        let allowed_algs = self.id_token_validation().allowed_algs();
+        if discovered && !allowed_algs.iter().any(|alg| algs_supported.contains(alg)) {
+            issues.push(ConfigIssue::error(format!(
+                "the provider signs ID tokens with {:?}, none of the allowed {:?}",
+                algs_supported, allowed_algs
+            )));
+        }
+
+        if let Some(supported) = self
+            .provider_metadata()
+            .scopes_supported()
+            .filter(|_| discovered)
+        {
+            for scope in self.scopes() {
+                if !supported.contains(scope) {
+                    issues.push(ConfigIssue::warning(format!(
+                        "scope {} is not in the provider's scopes_supported",
+                        scope.as_str()
+                    )));
+                }
+            }
+            let offline_access = |scope: &Scope| scope.as_str() == "offline_access";
+            if supported.iter().any(offline_access) && !self.scopes().iter().any(offline_access) {
+                issues.push(ConfigIssue::warning(
+                    "the provider supports the offline_access scope, but it is not requested: \
+                     logins may come without a refresh token, ending the session with the \
+                     access token"
+                        .to_string(),
+                ));
+            }
+        }
+
+        issues
+    }
+}
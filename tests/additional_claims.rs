@@ -0,0 +1,180 @@
+use actix_web::{get, test, App, HttpRequest, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, MaybeAuthenticated};
+use actix_web_openidconnect::test_util::{self, AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, IdentityHeaders, OtherClaims};
+use openidconnect::AdditionalClaims;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct RoleClaims {
+    roles: Vec<String>,
+}
+
+impl AdditionalClaims for RoleClaims {}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct DepartmentClaims {
+    department: String,
+}
+
+impl AdditionalClaims for DepartmentClaims {}
+
+#[get("/roles")]
+async fn roles(user: Authenticated<RoleClaims>) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.additional_claims().roles.join(","))
+}
+
+#[get("/department")]
+async fn department(user: Authenticated<DepartmentClaims>) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.additional_claims().department.clone())
+}
+
+#[get("/groups")]
+async fn groups(user: MaybeAuthenticated<OtherClaims>) -> HttpResponse {
+    let groups = user
+        .into_inner()
+        .and_then(|user| user.access.additional_claims().get("groups").cloned());
+    HttpResponse::Ok().body(groups.map(|groups| groups.to_string()).unwrap_or_default())
+}
+
+#[get("/maybe_department")]
+async fn maybe_department(user: MaybeAuthenticated<DepartmentClaims>) -> HttpResponse {
+    match user.user() {
+        Some(user) => HttpResponse::Ok().body(user.access.additional_claims().department.clone()),
+        None => HttpResponse::Ok().body(format!("anonymous {}", user.login_url().is_none())),
+    }
+}
+
+#[get("/subject")]
+async fn subject(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().to_string())
+}
+
+#[get("/no_auth/roles_header")]
+async fn roles_header(req: HttpRequest) -> HttpResponse {
+    let header = req.headers().get("x-auth-roles").cloned();
+    HttpResponse::Ok().body(
+        header
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default(),
+    )
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .role("admin")
+            .role("auditor")
+            .claim("groups", vec!["staff"]),
+    );
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+        .roles_claim("roles")
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn handlers_read_the_claims_of_their_type() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(roles)
+            .service(groups)
+            .service(subject),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/roles").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "admin,auditor");
+    assert_eq!(driver.get("/groups").send().await.body(), "[\"staff\"]");
+    assert_eq!(driver.get("/subject").send().await.body(), "alice");
+}
+
+#[actix_web::test]
+async fn users_lacking_required_claims_are_forbidden() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(department),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/department").follow_login().await;
+
+    assert_eq!(resp.status(), 403);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn users_lacking_optional_claims_are_no_user() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(roles)
+            .service(maybe_department),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/roles").follow_login().await;
+
+    let resp = driver.get("/maybe_department").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "anonymous true");
+}
+
+#[actix_web::test]
+async fn test_users_carry_their_claims() {
+    let app = test::init_service(App::new().service(roles)).await;
+    let req = test_util::authenticate_request(
+        test::TestRequest::get().uri("/roles").to_request(),
+        AuthenticatedUserBuilder::new("bob").role("viewer"),
+    );
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(test::read_body(resp).await, "viewer");
+}
+
+#[actix_web::test]
+async fn identity_headers_carry_the_roles() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .identity_headers(IdentityHeaders::default()),
+            )
+            .configure(openid.configure_open_id())
+            .service(roles)
+            .service(roles_header),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/roles").follow_login().await;
+
+    let resp = driver.get("/no_auth/roles_header").send().await;
+
+    assert_eq!(resp.body(), "admin,auditor");
+}
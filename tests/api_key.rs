@@ -0,0 +1,128 @@
+use actix_web::http::header::HeaderName;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, AuthenticatedUser};
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{ActixWebOpenId, ApiKeyValidator};
+
+#[get("/no_auth/whoami")]
+async fn whoami(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+#[get("/orders")]
+async fn orders(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(format!("orders of {}", user.access.subject().as_str()))
+}
+
+struct Partners;
+
+#[async_trait::async_trait]
+impl ApiKeyValidator for Partners {
+    async fn validate(&self, key: &str) -> Option<AuthenticatedUser> {
+        (key == "partner-x-key").then(|| AuthenticatedUser::synthetic("apikey:partner-x"))
+    }
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn api_keys_authenticate_as_their_user() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().api_key_validator(Partners))
+            .service(orders),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/orders")
+            .insert_header(("X-Api-Key", "partner-x-key"))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "orders of apikey:partner-x");
+}
+
+#[actix_web::test]
+async fn rejected_keys_are_not_redirected_to_the_provider() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().api_key_validator(Partners))
+            .service(orders)
+            .service(whoami),
+    )
+    .await;
+
+    for path in ["/orders", "/no_auth/whoami"] {
+        let err = test::try_call_service(
+            &app,
+            test::TestRequest::get()
+                .uri(path)
+                .insert_header(("X-Api-Key", "guessed"))
+                .to_request(),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        let resp = err.error_response();
+        assert_eq!(resp.status(), 401, "{}", path);
+        assert!(resp.headers().get("location").is_none());
+    }
+}
+
+#[actix_web::test]
+async fn the_key_header_is_configurable() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .api_key_validator(Partners)
+                    .api_key_header(HeaderName::from_static("x-partner-key")),
+            )
+            .service(whoami),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/no_auth/whoami")
+            .insert_header(("X-Partner-Key", "partner-x-key"))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(test::read_body(resp).await, "apikey:partner-x");
+
+    // Other headers are not looked at.
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/no_auth/whoami")
+            .insert_header(("X-Api-Key", "partner-x-key"))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), 302);
+}
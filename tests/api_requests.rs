@@ -0,0 +1,84 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use serde_json::Value;
+
+#[get("/is_auth/orders")]
+async fn orders(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn scripts_get_the_login_url_instead_of_a_redirect() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(orders),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+
+    for (name, value) in [
+        ("accept", "application/json"),
+        ("x-requested-with", "XMLHttpRequest"),
+    ] {
+        let resp = driver
+            .get("/is_auth/orders")
+            .header(name, value)
+            .send()
+            .await;
+        assert_eq!(resp.status(), 401, "{}", name);
+        assert!(resp.location().is_none());
+        let body: Value = serde_json::from_slice(resp.body()).unwrap();
+        assert_eq!(body["error"], "unauthenticated");
+        let login_url = body["login_url"].as_str().unwrap();
+        assert!(login_url.starts_with(&idp.issuer_url()), "{}", login_url);
+    }
+
+    let resp = driver
+        .get("/is_auth/orders")
+        .header("accept", "text/html,application/json;q=0.9")
+        .send()
+        .await;
+    assert_eq!(resp.status(), 302);
+}
+
+#[actix_web::test]
+async fn the_predicate_is_configurable() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .api_request(|req| req.path().starts_with("/is_auth/")),
+            )
+            .configure(openid.configure_open_id())
+            .service(orders),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+
+    let resp = driver.get("/is_auth/orders").send().await;
+
+    assert_eq!(resp.status(), 401);
+    // The front end navigates to the login url, the callback needs the nonce set meanwhile.
+    assert!(driver.login_cookie("nonce").is_some());
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use actix_web::cookie::Cookie;
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::openid::OpenID;
+use actix_web_openidconnect::test_util::{authenticate_request, AuthenticatedUserBuilder, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, SessionToken};
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[get("/client")]
+async fn client(_openid: web::Data<OpenID>) -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[get("/legacy/client")]
+async fn legacy_client(_openid: web::Data<Arc<OpenID>>) -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+#[get("/token")]
+async fn token(token: SessionToken) -> HttpResponse {
+    HttpResponse::Ok().body(token.access_token().await.secret().to_string())
+}
+
+fn token_request() -> actix_http::Request {
+    authenticate_request(
+        test::TestRequest::get()
+            .uri("/token")
+            .cookie(Cookie::new("access_token", "token"))
+            .to_request(),
+        AuthenticatedUserBuilder::new("alice"),
+    )
+}
+
+#[actix_web::test]
+async fn configure_open_id_registers_the_client_as_data() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .configure(openid.configure_open_id())
+            .service(client)
+            .service(legacy_client),
+    )
+    .await;
+
+    for uri in ["/client", "/legacy/client"] {
+        let resp = test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        assert_eq!(resp.status(), 200, "{}", uri);
+    }
+}
+
+#[actix_web::test]
+async fn extractors_accept_the_client_registered_either_way() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(openid.openid_client().clone()))
+            .service(token),
+    )
+    .await;
+    let legacy_app = test::init_service(
+        App::new()
+            .app_data(web::Data::new(openid.openid_client().clone()))
+            .service(token),
+    )
+    .await;
+
+    let resp = test::call_service(&app, token_request()).await;
+    assert_eq!(test::read_body(resp).await, "token");
+    let resp = test::call_service(&legacy_app, token_request()).await;
+    assert_eq!(test::read_body(resp).await, "token");
+}
+
+#[actix_web::test]
+async fn extractors_fail_without_a_registered_client() {
+    let app = test::init_service(App::new().service(token)).await;
+
+    let resp = test::call_service(&app, token_request()).await;
+
+    assert_eq!(resp.status(), 500);
+}
@@ -0,0 +1,91 @@
+use actix_web::{get, test, App, HttpMessage, HttpRequest, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{AuthContext, Authenticated};
+use actix_web_openidconnect::test_util::{self, AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+
+#[get("/is_auth/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+/// Reads the context like code of another crate would, without the extractors.
+#[get("/context")]
+async fn context(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().body(match req.extensions().get::<AuthContext>() {
+        Some(AuthContext::Authenticated(user)) => user.access.subject().as_str().to_string(),
+        Some(AuthContext::Unauthenticated { login_url, .. }) => login_url.clone(),
+        None => "no middleware".to_string(),
+    })
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn the_middleware_stores_the_auth_context() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(me)
+            .service(context),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let anonymous = driver.get("/context").send().await;
+    assert_eq!(anonymous.body(), "/login?next=%2Fcontext");
+
+    driver.get("/is_auth/me").follow_login().await;
+    assert_eq!(driver.get("/context").send().await.body(), "alice");
+}
+
+#[actix_web::test]
+async fn routes_without_the_middleware_name_the_setup_error() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .configure(openid.configure_open_id())
+            .service(me)
+            .service(context),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/is_auth/me").to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(
+        test::read_body(resp).await,
+        "OpenID middleware not registered for this scope"
+    );
+    let body =
+        test::call_and_read_body(&app, test::TestRequest::get().uri("/context").to_request()).await;
+    assert_eq!(body, "no middleware");
+}
+
+#[actix_web::test]
+async fn test_users_are_stored_as_the_auth_context() {
+    let app = test::init_service(App::new().service(context)).await;
+    let req = test_util::authenticate_request(
+        test::TestRequest::get().uri("/context").to_request(),
+        AuthenticatedUserBuilder::new("bob"),
+    );
+
+    assert_eq!(test::call_and_read_body(&app, req).await, "bob");
+}
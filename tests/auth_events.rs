@@ -0,0 +1,129 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, AuthEvents, CallbackFailure, ClaimsSource, EventContext, OpenIdBuilder,
+};
+
+mod mock_auth_api;
+
+/// Records every event as `name realm path`.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl Recorder {
+    fn record(&self, name: &str, context: &EventContext<'_>) {
+        self.0.lock().unwrap().push(format!(
+            "{} {} {}",
+            name,
+            context.realm,
+            context.path.unwrap_or("-")
+        ));
+    }
+
+    fn events(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl AuthEvents for Recorder {
+    fn on_login_redirect(&self, context: &EventContext<'_>) {
+        self.record("login_redirect", context);
+    }
+
+    fn on_callback_success(&self, context: &EventContext<'_>) {
+        self.record("callback_success", context);
+    }
+
+    fn on_callback_failure(&self, context: &EventContext<'_>, reason: CallbackFailure) {
+        self.record(&format!("callback_failure:{}", reason.as_str()), context);
+    }
+
+    fn on_token_validation(&self, context: &EventContext<'_>, source: ClaimsSource, ok: bool) {
+        self.record(&format!("validation:{}:{}", source.as_str(), ok), context);
+    }
+
+    fn on_userinfo_call(&self, context: &EventContext<'_>, _: Duration, ok: bool) {
+        self.record(&format!("userinfo:{}", ok), context);
+    }
+
+    fn on_logout(&self, context: &EventContext<'_>) {
+        self.record("logout", context);
+    }
+}
+
+fn builder(idp: &MockIdp, recorder: &Recorder) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .auth_events(recorder.clone())
+}
+
+#[actix_web::test]
+async fn logins_and_logouts_are_reported() {
+    let idp = MockIdp::start();
+    let recorder = Recorder::default();
+    let openid = builder(&idp, &recorder).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.get("/logout").send().await;
+
+    assert_eq!(
+        recorder.events(),
+        [
+            "login_redirect default /is_auth/hello",
+            "callback_success default /is_auth/hello",
+            "userinfo:true default -",
+            "validation:userinfo:true default -",
+            // The middleware checks the session of the logout request as well.
+            "userinfo:true default -",
+            "validation:userinfo:true default -",
+            "logout default /logout",
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn cached_claims_are_reported_as_such() {
+    let idp = MockIdp::start();
+    let recorder = Recorder::default();
+    let openid = builder(&idp, &recorder)
+        .userinfo_cache(Duration::from_secs(60), 10)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(
+        recorder.events().last().unwrap(),
+        "validation:cache:true default -"
+    );
+}
+
+#[actix_web::test]
+async fn callback_failures_name_the_reason() {
+    let idp = MockIdp::start();
+    let recorder = Recorder::default();
+    let openid = builder(&idp, &recorder).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver
+        .get("/auth_callback?code=code&state=csrf%7C%2Fis_auth%2Fhello")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 400);
+    assert_eq!(
+        recorder.events(),
+        ["callback_failure:missing_nonce default /is_auth/hello"]
+    );
+}
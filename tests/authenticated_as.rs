@@ -0,0 +1,125 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::AuthenticatedAs;
+use actix_web_openidconnect::test_util::{self, AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MyUser {
+    #[serde(rename = "sub")]
+    id: String,
+    email: String,
+    #[serde(rename = "name")]
+    display_name: Option<String>,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Only the ID token names the issuer.
+#[derive(Deserialize)]
+struct Issued {
+    iss: String,
+}
+
+#[derive(Deserialize)]
+struct WithPhone {
+    #[allow(dead_code)]
+    phone_number: String,
+}
+
+#[get("/profile")]
+async fn profile(user: AuthenticatedAs<MyUser>, again: AuthenticatedAs<MyUser>) -> HttpResponse {
+    assert_eq!(user.id, again.id);
+    HttpResponse::Ok().json(&*user)
+}
+
+#[get("/issuer")]
+async fn issuer(user: AuthenticatedAs<Issued>) -> HttpResponse {
+    HttpResponse::Ok().body(user.iss.clone())
+}
+
+#[get("/phone")]
+async fn phone(_user: AuthenticatedAs<WithPhone>) -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .email("alice@example.com")
+            .name("Alice")
+            .role("admin"),
+    );
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn handlers_read_the_claims_into_their_type() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(profile)
+            .service(issuer),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/profile").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let user: MyUser = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(user.id, "alice");
+    assert_eq!(user.email, "alice@example.com");
+    assert_eq!(user.display_name.as_deref(), Some("Alice"));
+    assert_eq!(user.roles, ["admin"]);
+    assert_eq!(
+        driver.get("/issuer").send().await.body(),
+        idp.issuer_url().as_str()
+    );
+}
+
+#[actix_web::test]
+async fn missing_claims_are_named() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(phone),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/phone").follow_login().await;
+
+    assert_eq!(resp.status(), 500);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(body.contains("missing field `phone_number`"), "{}", body);
+}
+
+#[actix_web::test]
+async fn test_users_are_read_into_the_type() {
+    let app = test::init_service(App::new().service(profile)).await;
+    let req = test_util::authenticate_request(
+        test::TestRequest::get().uri("/profile").to_request(),
+        AuthenticatedUserBuilder::new("bob").email("bob@example.com"),
+    );
+
+    let user: MyUser = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(user.id, "bob");
+    assert_eq!(user.display_name, None);
+    assert!(user.roles.is_empty());
+}
@@ -0,0 +1,168 @@
+use actix_web::{get, test, web, App, Error, HttpRequest, HttpResponse};
+use actix_web_openidconnect::openid_middleware::AuthenticatedUser;
+use actix_web_openidconnect::test_util::{self, AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, Authorized, HasRole, HasScope, OtherClaims, Policy, RequireClaims,
+};
+use serde_json::json;
+
+async fn hello() -> HttpResponse {
+    HttpResponse::Ok().body("hello")
+}
+
+#[get("/orders", wrap = "RequireClaims::new(HasScope::new(\"orders:read\"))")]
+async fn orders() -> HttpResponse {
+    HttpResponse::Ok().body("orders")
+}
+
+#[derive(Default)]
+struct Auditors;
+
+impl Policy for Auditors {
+    fn check(&self, req: &HttpRequest, user: &AuthenticatedUser<OtherClaims>) -> Result<(), Error> {
+        HasRole::new("auditor")
+            .claim("realm_access.roles")
+            .check(req, user)
+    }
+}
+
+#[get("/audit")]
+async fn audit(user: Authorized<Auditors>) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().to_string())
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .role("admin")
+            .claim("email_verified", true),
+    );
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+        .roles_claim("roles")
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn scopes_require_the_roles_of_their_policy() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(
+                web::scope("/admin")
+                    .wrap(RequireClaims::new(HasRole::new("admin")))
+                    .route("/hello", web::get().to(hello)),
+            )
+            .service(
+                web::scope("/billing")
+                    .wrap(RequireClaims::new(HasRole::new("billing")))
+                    .route("/hello", web::get().to(hello)),
+            ),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/admin/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+
+    let resp = driver.get("/billing/hello").send().await;
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.location(), None);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn closures_are_policies() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let verified =
+        |user: &AuthenticatedUser<OtherClaims>| user.access.email_verified() == Some(true);
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(
+                web::resource("/verified")
+                    .wrap(RequireClaims::new(verified))
+                    .to(hello),
+            )
+            .service(
+                web::resource("/nobody")
+                    .wrap(RequireClaims::new(|_: &AuthenticatedUser<OtherClaims>| {
+                        false
+                    }))
+                    .to(hello),
+            ),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    assert_eq!(driver.get("/verified").follow_login().await.status(), 200);
+    assert_eq!(driver.get("/nobody").send().await.status(), 403);
+}
+
+#[actix_web::test]
+async fn anonymous_requests_are_not_forbidden() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(
+                web::scope("/no_auth/admin")
+                    .wrap(RequireClaims::new(HasRole::new("admin")))
+                    .route("/hello", web::get().to(hello)),
+            ),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/no_auth/admin/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn routes_require_their_scope() {
+    let app = test::init_service(App::new().service(orders)).await;
+    let request = |scope: &str| {
+        test_util::authenticate_request(
+            test::TestRequest::get().uri("/orders").to_request(),
+            AuthenticatedUserBuilder::new("bob").claim("scope", scope),
+        )
+    };
+
+    let granted = test::call_service(&app, request("openid orders:read")).await;
+    let denied = test::try_call_service(&app, request("openid orders:write")).await;
+
+    assert_eq!(granted.status(), 200);
+    assert_eq!(denied.unwrap_err().error_response().status(), 403);
+}
+
+#[actix_web::test]
+async fn extractors_check_their_policy() {
+    let app = test::init_service(App::new().service(audit)).await;
+    let request = |roles: Vec<&str>| {
+        test_util::authenticate_request(
+            test::TestRequest::get().uri("/audit").to_request(),
+            AuthenticatedUserBuilder::new("carol").claim("realm_access", json!({ "roles": roles })),
+        )
+    };
+
+    let granted = test::call_service(&app, request(vec!["auditor"])).await;
+    let denied = test::call_service(&app, request(vec!["viewer"])).await;
+
+    assert_eq!(test::read_body(granted).await, "carol");
+    assert_eq!(denied.status(), 403);
+}
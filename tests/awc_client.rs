@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::awc_client::UserClient;
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use httpmock::prelude::*;
+
+/// Calls the upstream with the user's token, answering with the token that was sent.
+#[get("/is_auth/upstream")]
+async fn upstream(user: UserClient, upstream_url: web::Data<String>) -> HttpResponse {
+    let resp = user.get(upstream_url.as_str()).send().await.unwrap();
+    HttpResponse::build(resp.status()).body(user.access_token().secret().to_string())
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn sends_the_session_access_token() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let upstream_server = MockServer::start();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .app_data(web::Data::new(upstream_server.url("/orders")))
+            .service(upstream),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+    driver.get("/is_auth/upstream").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let orders = upstream_server.mock(|when, then| {
+        when.path("/orders")
+            .header("authorization", format!("Bearer {}", access_token));
+        then.status(200);
+    });
+
+    let resp = driver.get("/is_auth/upstream").send().await;
+
+    assert_eq!(resp.status(), 200);
+    orders.assert();
+}
+
+#[actix_web::test]
+async fn refreshes_an_expiring_token_before_sending() {
+    let idp = MockIdp::start();
+    idp.set_token_lifetime(Duration::from_secs(10));
+    let openid = openid(&idp).await;
+    let upstream_server = MockServer::start();
+    let orders = upstream_server.mock(|when, then| {
+        when.path("/orders").header_exists("authorization");
+        then.status(200);
+    });
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .app_data(web::Data::new(upstream_server.url("/orders")))
+            .service(upstream),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+
+    let first = driver.get("/is_auth/upstream").follow_login().await;
+    let first_token = String::from_utf8(first.body().to_vec()).unwrap();
+    assert_eq!(driver.cookie("access_token").unwrap(), first_token);
+    let second = driver.get("/is_auth/upstream").send().await;
+
+    // Each call refreshed with the rotated refresh token stored by the previous one.
+    assert_eq!(second.status(), 200);
+    let second_token = String::from_utf8(second.body().to_vec()).unwrap();
+    assert_ne!(second_token, first_token);
+    assert_eq!(driver.cookie("access_token").unwrap(), second_token);
+    orders.assert_hits(2);
+}
+
+#[actix_web::test]
+async fn requires_a_session() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .app_data(web::Data::new("http://localhost/orders".to_string()))
+            .service(upstream),
+    )
+    .await;
+
+    let resp = test::try_call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/is_auth/upstream")
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.err().unwrap().error_response().status(), 302);
+}
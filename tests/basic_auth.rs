@@ -0,0 +1,176 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{AUTHORIZATION, LOCATION, WWW_AUTHENTICATE};
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, AuthenticatedUser};
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{ActixWebOpenId, BasicAuth, BasicAuthValidator};
+
+#[get("/metrics")]
+async fn metrics(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+#[get("/dashboard")]
+async fn dashboard(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn tooling(req: &ServiceRequest) -> bool {
+    req.path() == "/metrics"
+}
+
+/// `Authorization: Basic` for `username:password`.
+fn basic(credentials: &str) -> (actix_web::http::header::HeaderName, String) {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+    (AUTHORIZATION, format!("Basic {}", encoded))
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn credentials_are_exchanged_with_the_password_grant() {
+    let idp = MockIdp::start();
+    idp.set_password("monitoring", "hunter2");
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .basic_auth(tooling, BasicAuth::PasswordGrant),
+            )
+            .service(metrics),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/metrics")
+            .insert_header(basic("monitoring:hunter2"))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "monitoring");
+}
+
+#[actix_web::test]
+async fn wrong_passwords_are_challenged_not_redirected() {
+    let idp = MockIdp::start();
+    idp.set_password("monitoring", "hunter2");
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .basic_auth(tooling, BasicAuth::PasswordGrant),
+            )
+            .service(metrics),
+    )
+    .await;
+
+    let resp = test::try_call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/metrics")
+            .insert_header(basic("monitoring:guess"))
+            .to_request(),
+    )
+    .await
+    .err()
+    .unwrap()
+    .error_response();
+
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().get(LOCATION).is_none());
+    assert!(resp
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("Basic realm="));
+}
+
+struct Monitoring;
+
+#[async_trait::async_trait]
+impl BasicAuthValidator for Monitoring {
+    async fn validate(&self, username: &str, password: &str) -> Option<AuthenticatedUser> {
+        (username == "nagios" && password == "check")
+            .then(|| AuthenticatedUser::synthetic("nagios"))
+    }
+}
+
+#[actix_web::test]
+async fn validators_only_check_the_matched_paths() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .basic_auth(tooling, BasicAuth::validator(Monitoring)),
+            )
+            .service(metrics)
+            .service(dashboard),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/metrics")
+            .insert_header(basic("nagios:check"))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(test::read_body(resp).await, "nagios");
+
+    let err = test::try_call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/dashboard")
+            .insert_header(basic("nagios:check"))
+            .to_request(),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(err.error_response().status(), 302);
+}
+
+#[actix_web::test]
+async fn basic_auth_is_disabled_by_default() {
+    let idp = MockIdp::start();
+    idp.set_password("monitoring", "hunter2");
+    let openid = openid(&idp).await;
+    let app = test::init_service(App::new().wrap(openid.get_middleware()).service(metrics)).await;
+
+    let err = test::try_call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/metrics")
+            .insert_header(basic("monitoring:hunter2"))
+            .to_request(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    assert_eq!(err.error_response().status(), 302);
+}
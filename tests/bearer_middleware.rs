@@ -0,0 +1,118 @@
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::bearer_middleware::OidcBearerMiddleware;
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, SessionToken};
+use httpmock::prelude::*;
+use reqwest_middleware::ClientBuilder;
+
+/// Calls the upstream on behalf of the user from a spawned task, answering with its status.
+#[get("/is_auth/upstream")]
+async fn upstream(token: SessionToken, upstream_url: web::Data<String>) -> HttpResponse {
+    let client = ClientBuilder::new(reqwest::Client::new())
+        .with(OidcBearerMiddleware)
+        .build();
+    let request = client.get(upstream_url.as_str()).with_extension(token);
+    let status = actix_web::rt::spawn(async move { request.send().await.unwrap().status() })
+        .await
+        .unwrap();
+    HttpResponse::build(status.as_u16().try_into().unwrap()).finish()
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn sends_the_session_access_token() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let upstream_server = MockServer::start();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .app_data(web::Data::new(upstream_server.url("/orders")))
+            .service(upstream),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+    driver.get("/is_auth/upstream").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let orders = upstream_server.mock(|when, then| {
+        when.path("/orders")
+            .header("authorization", format!("Bearer {}", access_token));
+        then.status(200);
+    });
+
+    let resp = driver.get("/is_auth/upstream").send().await;
+
+    assert_eq!(resp.status(), 200);
+    orders.assert();
+}
+
+#[actix_web::test]
+async fn retries_once_after_refreshing_a_rejected_token() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let upstream_server = MockServer::start();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .app_data(web::Data::new(upstream_server.url("/orders")))
+            .service(upstream),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+    driver.get("/is_auth/upstream").follow_login().await;
+    let revoked_token = driver.cookie("access_token").unwrap();
+    let rejected = upstream_server.mock(|when, then| {
+        when.path("/orders")
+            .header("authorization", format!("Bearer {}", revoked_token));
+        then.status(401);
+    });
+    let accepted = upstream_server.mock(|when, then| {
+        when.path("/orders").header_exists("authorization");
+        then.status(200);
+    });
+
+    let resp = driver.get("/is_auth/upstream").send().await;
+
+    assert_eq!(resp.status(), 200);
+    rejected.assert();
+    accepted.assert();
+    assert_ne!(driver.cookie("access_token").unwrap(), revoked_token);
+}
+
+#[actix_web::test]
+async fn gives_up_after_one_retry() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let upstream_server = MockServer::start();
+    let orders = upstream_server.mock(|when, then| {
+        when.path("/orders");
+        then.status(401);
+    });
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .app_data(web::Data::new(upstream_server.url("/orders")))
+            .service(upstream),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+
+    let resp = driver.get("/is_auth/upstream").follow_login().await;
+
+    assert_eq!(resp.status(), 401);
+    orders.assert_hits(2);
+}
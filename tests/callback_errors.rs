@@ -0,0 +1,102 @@
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{
+    ActixWebOpenId, CallbackErrorHandling, OpenIdBuilder, ProviderError,
+};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+fn cancel(idp: &MockIdp) {
+    idp.set_failure(Some(MockIdpFailure::AuthorizationError {
+        error: "access_denied",
+    }));
+}
+
+#[actix_web::test]
+async fn cancelled_logins_render_the_error_page() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    cancel(&idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 400);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(body.contains("the login was cancelled"), "{}", body);
+    assert!(body.contains("href=\"/is_auth/hello\""), "{}", body);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn error_callbacks_can_redirect_to_an_error_route() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .callback_errors(CallbackErrorHandling::Redirect(
+            "/no_auth/login-failed".to_string(),
+        ))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    idp.set_failure(Some(MockIdpFailure::AuthorizationError {
+        error: "temporarily_unavailable",
+    }));
+    let login = driver.get("/is_auth/hello").send().await;
+    let authorize = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(login.location().unwrap())
+        .send()
+        .await
+        .unwrap();
+    let callback = authorize.headers()["location"].to_str().unwrap();
+
+    let resp = driver
+        .get(callback.trim_start_matches("http://localhost"))
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 302);
+    assert_eq!(
+        resp.location(),
+        Some(
+            "/no_auth/login-failed?error=temporarily_unavailable\
+             &error_description=mock+temporarily_unavailable&next=%2Fis_auth%2Fhello"
+        )
+    );
+}
+
+fn cancelled(_: &HttpRequest, error: &ProviderError) -> HttpResponse {
+    HttpResponse::Ok().body(format!("handled {}", error.error))
+}
+
+#[actix_web::test]
+async fn error_callbacks_can_be_answered_by_a_handler() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .callback_errors(CallbackErrorHandling::Handler(cancelled))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    cancel(&idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "handled access_denied");
+}
@@ -0,0 +1,127 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, ClaimRequest, ClaimsRequest, OpenIdBuilder, OpenIdError,
+};
+
+mod mock_auth_api;
+
+#[get("/is_auth/hello")]
+async fn hello() -> HttpResponse {
+    HttpResponse::Ok().body("hello")
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+/// The raw, still encoded, `claims` parameter of `url`.
+fn claims_parameter(url: &str) -> &str {
+    url.split(['?', '&'])
+        .find_map(|pair| pair.strip_prefix("claims="))
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn the_authorization_url_carries_the_claims_parameter() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .claims_request(
+            ClaimsRequest::new()
+                .userinfo("employee_id", ClaimRequest::essential())
+                .userinfo("cost_center", ClaimRequest::voluntary())
+                .id_token(
+                    "acr",
+                    ClaimRequest::essential().values(["urn:mace:incommon:iap:silver"]),
+                ),
+        )
+        .build()
+        .await
+        .unwrap();
+
+    let url = openid
+        .openid_client()
+        .get_authorization_url("/".to_string())
+        .url;
+
+    assert_eq!(
+        claims_parameter(url.as_str()),
+        "%7B%22userinfo%22%3A%7B%22cost_center%22%3Anull%2C%22employee_id%22%3A%7B%22essential\
+         %22%3Atrue%7D%7D%2C%22id_token%22%3A%7B%22acr%22%3A%7B%22essential%22%3Atrue%2C%22values\
+         %22%3A%5B%22urn%3Amace%3Aincommon%3Aiap%3Asilver%22%5D%7D%7D%7D"
+    );
+    let plain = builder(&idp).build().await.unwrap();
+    let url = plain
+        .openid_client()
+        .get_authorization_url("/".to_string())
+        .url;
+    assert!(!url.as_str().contains("claims="));
+}
+
+#[actix_web::test]
+async fn logins_without_essential_userinfo_claims_fail() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .claims_request(ClaimsRequest::new().userinfo("employee_id", ClaimRequest::essential()))
+        .build()
+        .await
+        .unwrap();
+
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 403);
+    driver.assert_unauthenticated();
+
+    idp.login_as(
+        AuthenticatedUserBuilder::new("bob")
+            .preferred_username("bob")
+            .claim("employee_id", "42"),
+    );
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn middlewares_override_the_claims_request() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let openid = builder(&idp).build().await.unwrap();
+    let app =
+        test::init_service(
+            App::new()
+                .wrap(openid.get_middleware().claims_request(
+                    ClaimsRequest::new().id_token("acr", ClaimRequest::essential()),
+                ))
+                .configure(openid.configure_open_id())
+                .service(hello),
+        )
+        .await;
+    let mut driver = FlowDriver::new(app, &idp);
+
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert_eq!(
+        claims_parameter(resp.location().unwrap()),
+        "%7B%22id_token%22%3A%7B%22acr%22%3A%7B%22essential%22%3Atrue%7D%7D%7D"
+    );
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 403);
+    driver.assert_unauthenticated();
+}
+
+#[::core::prelude::v1::test]
+fn missing_claims_are_named_in_the_error() {
+    let err = OpenIdError::MissingClaims(vec!["employee_id".to_string(), "acr".to_string()]);
+
+    assert_eq!(
+        err.to_string(),
+        "the provider did not return the essential claims employee_id, acr"
+    );
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::test_util::{MockClock, MockIdp, MockIdpFailure};
+use actix_web_openidconnect::{ActixWebOpenId, ClientToken, OpenIdBuilder};
+use futures_util::future::join_all;
+use openidconnect::Scope;
+
+#[get("/report")]
+async fn report(token: ClientToken) -> HttpResponse {
+    HttpResponse::Ok().body(token.access_token().secret().clone())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/report")
+}
+
+fn scopes() -> Vec<Scope> {
+    vec![Scope::new("reports".to_string())]
+}
+
+fn token_requests(idp: &MockIdp) -> usize {
+    idp.token_requests()
+        .iter()
+        .filter(|request| request.grant_type == "client_credentials")
+        .count()
+}
+
+#[actix_web::test]
+async fn clones_of_the_client_share_its_tokens() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let client = openid.openid_client();
+
+    let first = client.client_credentials_token(&scopes()).await.unwrap();
+    let second = (**client)
+        .clone()
+        .client_credentials_token(&scopes())
+        .await
+        .unwrap();
+    let other = client.client_credentials_token(&[]).await.unwrap();
+
+    assert_eq!(first.secret(), second.secret());
+    assert_ne!(first.secret(), other.secret());
+    assert_eq!(token_requests(&idp), 2);
+}
+
+#[actix_web::test]
+async fn concurrent_callers_wait_for_one_request() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let scopes = scopes();
+
+    let tokens =
+        join_all((0..5).map(|_| openid.openid_client().client_credentials_token(&scopes))).await;
+
+    assert!(tokens.iter().all(|token| token.is_ok()));
+    assert_eq!(token_requests(&idp), 1);
+}
+
+#[actix_web::test]
+async fn tokens_are_renewed_shortly_before_they_expire() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp).clock(clock.clone()).build().await.unwrap();
+    let client = openid.openid_client();
+
+    let first = client.client_credentials_token(&scopes()).await.unwrap();
+    clock.advance(Duration::from_secs(3 * 60));
+    let cached = client.client_credentials_token(&scopes()).await.unwrap();
+    clock.advance(Duration::from_secs(60 + 1));
+    let renewed = client.client_credentials_token(&scopes()).await.unwrap();
+
+    assert_eq!(first.secret(), cached.secret());
+    assert_ne!(first.secret(), renewed.secret());
+}
+
+#[actix_web::test]
+async fn handlers_extract_the_client_token() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .client_token_scopes(["reports"])
+        .build()
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(report),
+    )
+    .await;
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/report").to_request()).await;
+    assert_eq!(resp.status(), 200);
+    let token = test::read_body(resp).await;
+    let cached = openid
+        .openid_client()
+        .client_credentials_token(&scopes())
+        .await
+        .unwrap();
+
+    assert_eq!(token, cached.secret().as_str());
+}
+
+#[actix_web::test]
+async fn unavailable_providers_answer_service_unavailable() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(report),
+    )
+    .await;
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/report").to_request()).await;
+
+    assert_eq!(resp.status(), 503);
+}
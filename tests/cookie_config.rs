@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use actix_web::cookie::SameSite;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, FlowResponse, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, CookieConfig, CookieMaxAge};
+
+mod mock_auth_api;
+
+async fn openid(idp: &MockIdp, cookie_config: CookieConfig) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .cookie_config(cookie_config)
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Logs in at the provider from `login`, and returns the response of the callback.
+async fn callback<S>(driver: &mut FlowDriver<S>, login: &FlowResponse) -> FlowResponse
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let resp = client.get(login.location().unwrap()).send().await.unwrap();
+    let callback = resp.headers()["location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("http://localhost")
+        .unwrap()
+        .to_string();
+    driver.get(&callback).send().await
+}
+
+#[actix_web::test]
+async fn session_cookies_are_secure_and_lax_by_default() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, CookieConfig::default()).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    let nonce = login
+        .cookies()
+        .find(|c| c.name().starts_with("nonce."))
+        .unwrap();
+    assert_eq!(nonce.http_only(), Some(true));
+    assert_eq!(nonce.max_age().map(|age| age.whole_minutes()), Some(10));
+    let resp = callback(&mut driver, &login).await;
+
+    let access_token = resp.cookies().find(|c| c.name() == "access_token").unwrap();
+    assert_eq!(access_token.secure(), Some(true));
+    assert_eq!(access_token.same_site(), Some(SameSite::Lax));
+    assert_eq!(access_token.path(), Some("/"));
+    assert_eq!(access_token.http_only(), None);
+    assert_eq!(access_token.max_age(), None);
+}
+
+#[actix_web::test]
+async fn configured_attributes_apply_to_every_cookie() {
+    let idp = MockIdp::start();
+    let cookie_config = CookieConfig::default()
+        .prefix("shop_")
+        .domain("example.com")
+        .same_site(SameSite::Strict)
+        .secure(false)
+        .http_only(true)
+        .max_age(CookieMaxAge::Fixed(Duration::from_secs(3600)));
+    let openid = openid(&idp, cookie_config).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    let state = login
+        .cookies()
+        .find(|c| c.name().starts_with("shop_oauth_state."))
+        .unwrap();
+    assert_eq!(state.domain(), Some("example.com"));
+    assert_eq!(state.same_site(), Some(SameSite::Lax));
+    assert_eq!(state.secure(), None);
+    let resp = callback(&mut driver, &login).await;
+
+    assert_eq!(resp.status(), 302);
+    for name in ["shop_access_token", "shop_id_token", "shop_refresh_token"] {
+        let cookie = resp.cookies().find(|c| c.name() == name).unwrap();
+        assert_eq!(cookie.domain(), Some("example.com"), "{}", name);
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict), "{}", name);
+        assert_eq!(cookie.secure(), None, "{}", name);
+        assert_eq!(cookie.http_only(), Some(true), "{}", name);
+        assert_eq!(cookie.max_age().map(|age| age.whole_hours()), Some(1));
+    }
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+
+    let resp = driver.get("/logout").send().await;
+    let removal = resp
+        .cookies()
+        .find(|c| c.name() == "shop_access_token")
+        .unwrap();
+    assert_eq!(removal.domain(), Some("example.com"));
+    assert_eq!(removal.value(), "");
+}
+
+#[actix_web::test]
+async fn token_expiry_keeps_renewable_sessions() {
+    let idp = MockIdp::start();
+    let openid = openid(
+        &idp,
+        CookieConfig::default().max_age(CookieMaxAge::TokenExpiry),
+    )
+    .await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    let resp = callback(&mut driver, &login).await;
+
+    let refresh_token = resp
+        .cookies()
+        .find(|c| c.name() == "refresh_token")
+        .unwrap();
+    assert_eq!(refresh_token.max_age(), None);
+}
+
+#[actix_web::test]
+async fn token_expiry_keeps_sessions_as_long_as_the_refresh_token() {
+    let idp = MockIdp::start();
+    idp.set_refresh_token_lifetime(Duration::from_secs(30 * 60));
+    let openid = openid(
+        &idp,
+        CookieConfig::default().max_age(CookieMaxAge::TokenExpiry),
+    )
+    .await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    let resp = callback(&mut driver, &login).await;
+
+    for name in ["access_token", "refresh_token", "access_token_expires_at"] {
+        let cookie = resp.cookies().find(|c| c.name() == name).unwrap();
+        assert_eq!(
+            cookie.max_age().map(|age| age.whole_minutes()),
+            Some(30),
+            "{}",
+            name
+        );
+    }
+}
+
+#[actix_web::test]
+async fn insecure_cookies_are_a_security_finding() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, CookieConfig::default().secure(false)).await;
+
+    let findings = openid.openid_client().security_report();
+
+    assert!(findings
+        .iter()
+        .any(|finding| finding.check == actix_web_openidconnect::SecurityCheck::InsecureCookies));
+}
@@ -0,0 +1,89 @@
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder, UserInfoCookie};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+fn key() -> Key {
+    Key::derive_from(&[7; 32])
+}
+
+fn decrypt(name: &str, value: String) -> Option<String> {
+    CookieJar::new()
+        .private(&key())
+        .decrypt(Cookie::new(name.to_string(), value))
+        .map(|cookie| cookie.value().to_string())
+}
+
+#[actix_web::test]
+async fn sessions_are_stored_encrypted() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .cookie_key(key())
+        .user_info_cookie(UserInfoCookie::Plain)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    for name in ["access_token", "id_token", "refresh_token", "user_info"] {
+        let sealed = driver.cookie(name).unwrap();
+        let value = decrypt(name, sealed.clone()).unwrap();
+        assert_ne!(sealed, value, "{}", name);
+    }
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+}
+
+#[actix_web::test]
+async fn tampered_sessions_log_in_again() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).cookie_key(key()).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let mut sealed = driver.cookie("access_token").unwrap();
+    sealed.replace_range(..4, "AAAA");
+
+    driver.set_cookie(Cookie::new("access_token", sealed));
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn plaintext_sessions_are_removed_after_the_upgrade() {
+    let idp = MockIdp::start();
+    let before = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&before).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let after = builder(&idp).cookie_key(key()).build().await.unwrap();
+    let mut driver = driver.with_app(mock_auth_api::get_mock_auth_api(&after).await);
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+    driver.assert_unauthenticated();
+    assert!(driver.cookie("refresh_token").is_none());
+    let nonce = driver
+        .cookies()
+        .into_iter()
+        .find(|cookie| cookie.name().starts_with("nonce."))
+        .unwrap();
+    assert!(decrypt(nonce.name(), nonce.value().to_string()).is_some());
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+}
@@ -0,0 +1,224 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, AuthenticatedUser};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, BearerHeader, Credential, CredentialChain, CredentialExtractor, QueryParameter,
+    SessionCookie,
+};
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap()
+}
+
+fn header_then_cookie() -> CredentialChain {
+    CredentialChain::new()
+        .with(BearerHeader)
+        .with(SessionCookie)
+}
+
+#[actix_web::test]
+async fn bearer_tokens_authenticate_api_clients() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().credentials(header_then_cookie()))
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/me").follow_login().await;
+    let token = driver.cookie("access_token").unwrap();
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "alice");
+}
+
+#[actix_web::test]
+async fn rejected_bearer_tokens_are_challenged() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().credentials(header_then_cookie()))
+            .service(me),
+    )
+    .await;
+
+    let err = test::try_call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, "Bearer forged"))
+            .to_request(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    let resp = err.error_response();
+    assert_eq!(resp.status(), 401);
+    assert_eq!(
+        resp.headers().get(WWW_AUTHENTICATE).unwrap(),
+        "Bearer error=\"invalid_token\""
+    );
+}
+
+#[actix_web::test]
+async fn only_the_configured_extractors_are_looked_at() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let openid = openid(&idp).await;
+    let chain = |query: bool| {
+        let chain = header_then_cookie();
+        if query {
+            chain.with(QueryParameter::new("access_token"))
+        } else {
+            chain
+        }
+    };
+    let with_query = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().credentials(chain(true)))
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let without_query = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().credentials(chain(false)))
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&with_query, &idp);
+    driver.get("/me").follow_login().await;
+    let uri = format!(
+        "/me?access_token={}",
+        driver.cookie("access_token").unwrap()
+    );
+
+    let resp =
+        test::call_service(&with_query, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(test::read_body(resp).await, "alice");
+
+    let err = test::try_call_service(
+        &without_query,
+        test::TestRequest::get().uri(&uri).to_request(),
+    )
+    .await
+    .err()
+    .unwrap();
+    assert_eq!(err.error_response().status(), 302);
+}
+
+/// Trusts the client certificate subject forwarded by the ingress.
+struct ClientCertificate;
+
+impl CredentialExtractor for ClientCertificate {
+    fn extract(&self, req: &ServiceRequest) -> Option<Credential> {
+        let subject = req.headers().get("x-client-cert-subject")?.to_str().ok()?;
+        Some(Credential::User(Box::new(AuthenticatedUser::synthetic(
+            format!("cert:{}", subject),
+        ))))
+    }
+}
+
+#[actix_web::test]
+async fn custom_extractors_can_authenticate_users_themselves() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .credentials(CredentialChain::new().with(ClientCertificate)),
+            )
+            .service(me),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("X-Client-Cert-Subject", "billing-service"))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(test::read_body(resp).await, "cert:billing-service");
+}
+
+#[actix_web::test]
+async fn presets_choose_between_the_cookie_and_the_header() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let openid = openid(&idp).await;
+    let login = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&login, &idp);
+    driver.get("/me").follow_login().await;
+    let token = driver.cookie("access_token").unwrap();
+    let with_cookie = || {
+        test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Cookie", format!("access_token={}", token)))
+    };
+    let with_header = || {
+        test::TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)))
+    };
+
+    for (chain, cookie, header) in [
+        (CredentialChain::cookie_only(), 200, 302),
+        (CredentialChain::bearer_only(), 302, 200),
+        (CredentialChain::cookie_or_bearer(), 200, 200),
+    ] {
+        let app = test::init_service(
+            App::new()
+                .wrap(openid.get_middleware().credentials(chain))
+                .service(me),
+        )
+        .await;
+        for (request, expected) in [(with_cookie(), cookie), (with_header(), header)] {
+            let status = match test::try_call_service(&app, request.to_request()).await {
+                Ok(resp) => resp.status(),
+                Err(err) => err.error_response().status(),
+            };
+            assert_eq!(status, expected);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, MaybeAuthenticated};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::ActixWebOpenId;
+
+#[get("/news")]
+async fn news(user: MaybeAuthenticated) -> HttpResponse {
+    HttpResponse::Ok().body(match user.into_inner() {
+        Some(user) => format!("news for {}", user.access.subject().as_str()),
+        None => "news".to_string(),
+    })
+}
+
+#[get("/account")]
+async fn account(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+const OPEN_FOR: Duration = Duration::from_secs(30);
+
+async fn openid(idp: &MockIdp, clock: &MockClock) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .circuit_breaker(2, OPEN_FOR)
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn degradable_routes_are_served_anonymously_while_the_provider_is_down() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let clock = MockClock::new();
+    let openid = openid(&idp, &clock).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .degradable(|req| req.path() == "/news"),
+            )
+            .configure(openid.configure_open_id())
+            .service(news)
+            .service(account),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/news").follow_login().await;
+    driver.assert_authenticated();
+
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+    for _ in 0..2 {
+        assert!(driver.get("/news").send().await.status().is_redirection());
+    }
+    assert!(openid.openid_client().is_circuit_open());
+
+    let resp = driver.get("/news").send().await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "news");
+    assert_eq!(resp.headers().get("x-auth-degraded").unwrap(), "true");
+    assert!(driver
+        .get("/account")
+        .send()
+        .await
+        .status()
+        .is_redirection());
+
+    idp.set_failure(None);
+    clock.advance(OPEN_FOR);
+    assert!(!openid.openid_client().is_circuit_open());
+
+    let resp = driver.get("/news").send().await;
+    assert_eq!(resp.body(), "news for alice");
+    assert!(resp.headers().get("x-auth-degraded").is_none());
+}
+
+#[actix_web::test]
+async fn routes_are_not_degraded_by_default() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let openid = openid(&idp, &MockClock::new()).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(news),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/news").follow_login().await;
+
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+    for _ in 0..3 {
+        assert!(driver.get("/news").send().await.status().is_redirection());
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, MockIdp, MockIdpFailure};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdError};
+use openidconnect::{EmptyAdditionalClaims, Scope};
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("cli")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn devices_get_the_tokens_once_the_user_approves() {
+    let idp = MockIdp::start();
+    idp.set_device_approval_polls(2);
+    let openid = openid(&idp).await;
+    let client = openid.openid_client();
+
+    let authorization = client
+        .start_device_authorization(&[Scope::new("profile".to_string())])
+        .await
+        .unwrap();
+    let tokens = client
+        .poll_device_token(&authorization.device_code, Duration::from_millis(10))
+        .await
+        .unwrap();
+
+    assert!(!authorization.user_code.secret().is_empty());
+    assert_eq!(
+        authorization.verification_uri.as_str(),
+        format!("{}/device/verify", idp.issuer_url())
+    );
+    assert_eq!(authorization.interval, Duration::from_secs(5));
+    let device_polls = idp
+        .token_requests()
+        .into_iter()
+        .filter(|request| request.grant_type == "urn:ietf:params:oauth:grant-type:device_code")
+        .inspect(|request| assert_eq!(request.client_id.as_deref(), Some("cli")))
+        .count();
+    assert_eq!(device_polls, 3);
+    let user_info = client
+        .user_info::<EmptyAdditionalClaims>(tokens.access_token)
+        .await
+        .unwrap();
+    assert_eq!(user_info.subject().as_str(), "alice");
+    assert!(tokens.refresh_token.is_some());
+}
+
+#[actix_web::test]
+async fn denied_device_authorizations_fail() {
+    let idp = MockIdp::start();
+    idp.set_device_approval_polls(1);
+    let openid = openid(&idp).await;
+    let client = openid.openid_client();
+    let authorization = client.start_device_authorization(&[]).await.unwrap();
+    idp.set_failure(Some(MockIdpFailure::TokenError {
+        status: 400,
+        error: "access_denied",
+    }));
+
+    let err = client
+        .poll_device_token(&authorization.device_code, Duration::from_millis(10))
+        .await
+        .err()
+        .unwrap();
+
+    match err {
+        OpenIdError::TokenExchange {
+            provider_error: Some(provider_error),
+            status: Some(400),
+            ..
+        } => assert_eq!(provider_error.error, "access_denied"),
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[actix_web::test]
+async fn providers_without_a_device_authorization_endpoint_are_not_asked() {
+    let idp = MockIdp::start();
+    idp.set_device_authorization_endpoint(false);
+    let openid = openid(&idp).await;
+
+    let err = openid
+        .openid_client()
+        .start_device_authorization(&[])
+        .await
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, OpenIdError::Config(_)), "{:?}", err);
+}
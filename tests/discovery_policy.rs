@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{ActixWebOpenId, DiscoveryPolicy, OpenIdBuilder, OpenIdError};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+#[actix_web::test]
+async fn unreachable_providers_fail_the_build_by_default() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::DocumentsUnavailable));
+
+    let err = builder(&idp).build().await.unwrap_err();
+
+    assert!(
+        matches!(err, OpenIdError::Http { status: 503 }),
+        "{:?}",
+        err
+    );
+}
+
+#[actix_web::test]
+async fn clients_start_before_their_provider() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::DocumentsUnavailable));
+    let openid = builder(&idp)
+        .discovery_policy(DiscoveryPolicy::RetryForever {
+            backoff: Duration::ZERO,
+        })
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let unavailable = driver.get("/no_auth/hello").send().await;
+    assert!(!openid.openid_client().is_discovered());
+    idp.set_failure(None);
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(unavailable.status(), 503);
+    assert!(unavailable.headers().contains_key("retry-after"));
+    assert!(openid.openid_client().is_discovered());
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn discovery_is_retried_at_most_once_per_backoff() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::DocumentsUnavailable));
+    let openid = builder(&idp)
+        .discovery_policy(DiscoveryPolicy::RetryForever {
+            backoff: Duration::from_secs(3600),
+        })
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    driver.get("/no_auth/hello").send().await;
+    idp.set_failure(None);
+    let resp = driver.get("/no_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 503);
+    assert!(!openid.openid_client().is_discovered());
+    assert!(openid.openid_client().probe().await.healthy);
+    assert!(openid.openid_client().is_discovered());
+}
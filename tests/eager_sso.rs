@@ -0,0 +1,117 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::MaybeAuthenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+
+#[get("/")]
+async fn home(user: MaybeAuthenticated) -> HttpResponse {
+    HttpResponse::Ok().body(match user.into_inner() {
+        Some(user) => format!("hello {}", user.access.subject().as_str()),
+        None => "hello".to_string(),
+    })
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/account"))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn visitors_signed_in_at_the_provider_are_recognized() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().eager_sso(true))
+            .configure(openid.configure_open_id())
+            .service(home),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver
+        .get("/")
+        .header("Sec-Fetch-Mode", "navigate")
+        .follow_login()
+        .await;
+
+    assert_eq!(resp.body(), "hello alice");
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn anonymous_visitors_are_only_checked_once() {
+    let idp = MockIdp::start();
+    idp.set_signed_in(false);
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().eager_sso(true))
+            .configure(openid.configure_open_id())
+            .service(home),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver
+        .get("/")
+        .header("Sec-Fetch-Mode", "navigate")
+        .follow_login()
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "hello");
+    driver.assert_unauthenticated();
+    assert!(driver.cookie("sso_checked").is_some());
+
+    let resp = driver
+        .get("/")
+        .header("Sec-Fetch-Mode", "navigate")
+        .send()
+        .await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn only_page_navigations_are_redirected_and_only_when_enabled() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let eager = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().eager_sso(true))
+            .service(home),
+    )
+    .await;
+    let default = test::init_service(App::new().wrap(openid.get_middleware()).service(home)).await;
+
+    let fetch = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Sec-Fetch-Mode", "cors"))
+        .to_request();
+    assert_eq!(test::call_service(&eager, fetch).await.status(), 200);
+
+    let navigation = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Sec-Fetch-Mode", "navigate"))
+        .to_request();
+    assert_eq!(test::call_service(&default, navigation).await.status(), 200);
+
+    let navigation = test::TestRequest::get()
+        .uri("/")
+        .insert_header(("Sec-Fetch-Mode", "navigate"))
+        .to_request();
+    let err = test::try_call_service(&eager, navigation)
+        .await
+        .err()
+        .unwrap();
+    let resp = err.error_response();
+    let location = resp.headers().get("location").unwrap().to_str().unwrap();
+    assert!(location.contains("prompt=none"));
+}
@@ -0,0 +1,233 @@
+use std::error::Error;
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::HeaderValue;
+use actix_web::http::StatusCode;
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::{get, test, web, App, HttpResponse, Responder, ResponseError};
+use actix_web_openidconnect::openid::OpenID;
+use actix_web_openidconnect::openid_middleware::{Authenticated, AuthenticationRequired};
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdError};
+use httpmock::Method::GET;
+use httpmock::MockServer;
+use openidconnect::{AccessToken, EmptyAdditionalClaims};
+
+mod mock_auth_api;
+
+async fn build(issuer_url: String) -> Result<ActixWebOpenId, OpenIdError> {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(issuer_url)
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+        .build()
+        .await
+}
+
+#[actix_web::test]
+async fn provider_errors_keep_their_status() {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(GET).path("/.well-known/openid-configuration");
+        then.status(503);
+    });
+
+    let err = build(server.base_url()).await.err().unwrap();
+
+    assert!(
+        matches!(err, OpenIdError::Http { status: 503 }),
+        "{:?}",
+        err
+    );
+}
+
+#[actix_web::test]
+async fn unreachable_provider_is_a_discovery_error_with_a_source() {
+    // Nothing listens on the discard port.
+    let err = build("http://127.0.0.1:9".to_string()).await.err().unwrap();
+
+    assert!(matches!(err, OpenIdError::Discovery(_)), "{:?}", err);
+    assert!(err.source().is_some());
+}
+
+#[actix_web::test]
+async fn missing_settings_are_configuration_errors() {
+    let err = ActixWebOpenId::builder()
+        .client_secret("secret")
+        .build()
+        .await
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, OpenIdError::Config(_)), "{:?}", err);
+    assert_eq!(
+        err.to_string(),
+        "invalid OpenID configuration: client_id is required to build the OpenID client"
+    );
+}
+
+#[actix_web::test]
+async fn revoked_access_token_asks_for_login_again() {
+    let idp = MockIdp::start();
+    let openid = build(idp.issuer_url()).await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.set_cookie(Cookie::new("access_token", "revoked"));
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp
+        .location()
+        .unwrap()
+        .starts_with(&format!("{}/authorize?", idp.issuer_url())));
+}
+
+#[get("/api/me")]
+async fn me(user: Authenticated) -> impl Responder {
+    HttpResponse::Ok().body(user.access.subject().to_string())
+}
+
+/// Renders a problem+json body instead of redirecting API clients to the provider.
+fn problem_json<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let required = res
+        .response()
+        .error()
+        .and_then(|err| err.as_error::<AuthenticationRequired>())
+        .is_some();
+    if !required {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+    let (req, _) = res.into_parts();
+    let resp = HttpResponse::Unauthorized()
+        .content_type("application/problem+json")
+        .body(r#"{"title":"authentication required","status":401}"#);
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, resp).map_into_right_body(),
+    ))
+}
+
+#[actix_web::test]
+async fn authentication_required_can_be_handled_by_the_application() {
+    let idp = MockIdp::start();
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/api") && req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(ErrorHandlers::new().handler(StatusCode::FOUND, problem_json))
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await;
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/api/me").to_request()).await;
+
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().get("location").is_none());
+    assert!(resp.headers().get("set-cookie").is_none());
+    assert_eq!(
+        test::read_body(resp).await,
+        r#"{"title":"authentication required","status":401}"#
+    );
+}
+
+#[actix_web::test]
+async fn authentication_required_exposes_why_the_session_was_rejected() {
+    let idp = MockIdp::start();
+    let openid = build(idp.issuer_url()).await.unwrap();
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+
+    let err = test::try_call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/is_auth/hello")
+            .cookie(Cookie::new("access_token", "revoked"))
+            .to_request(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    let required = err.as_error::<AuthenticationRequired>().unwrap();
+    assert!(matches!(
+        required.reason(),
+        Some(OpenIdError::Http { status: 401 })
+    ));
+    assert!(!format!("{:?}", required).contains("nonce"));
+}
+
+#[get("/custom/userinfo")]
+async fn custom_user_info(
+    openid: web::Data<OpenID>,
+) -> actix_web_openidconnect::Result<HttpResponse> {
+    let user_info = openid
+        .user_info::<EmptyAdditionalClaims>(AccessToken::new("revoked-secret-token".to_string()))
+        .await?;
+    Ok(HttpResponse::Ok().body(user_info.subject().to_string()))
+}
+
+#[actix_web::test]
+async fn openid_errors_convert_into_responses_without_details() {
+    let idp = MockIdp::start();
+    let openid = build(idp.issuer_url()).await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(openid.openid_client().clone()))
+            .service(custom_user_info),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/custom/userinfo")
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 401);
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(!body.contains("revoked-secret-token"));
+    assert!(!body.contains(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn unavailable_provider_converts_into_a_retriable_response() {
+    let resp = OpenIdError::Http { status: 502 }.error_response();
+
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "30");
+}
+
+#[actix_web::test]
+async fn configuration_errors_convert_into_internal_errors() {
+    let err = OpenIdError::Config("client_secret=hunter2 is wrong".to_string());
+
+    let resp = actix_web::Error::from(err).error_response();
+
+    assert_eq!(resp.status(), 500);
+    let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+    assert_eq!(body, "Internal Server Error");
+}
+
+#[actix_web::test]
+async fn malformed_cookies_are_bad_requests_and_invalid_headers_internal_errors() {
+    let malformed = OpenIdError::MalformedCookie("id_token".to_string());
+    let header = OpenIdError::from(HeaderValue::from_str("https://idp\n/login").unwrap_err());
+
+    assert_eq!(malformed.status_code(), StatusCode::BAD_REQUEST);
+    assert_eq!(malformed.to_string(), "the id_token cookie is malformed");
+    assert_eq!(header.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(header.source().is_some());
+}
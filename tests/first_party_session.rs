@@ -0,0 +1,275 @@
+use std::time::Duration;
+
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::http::Method;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, FlowResponse, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{
+    ActixWebOpenId, HasRole, InMemorySessionStore, OpenIdBuilder, RequireClaims, SessionFormat,
+    SessionKey,
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::Value;
+
+const TTL: Duration = Duration::from_secs(300);
+
+#[get("/app")]
+async fn app_page() -> HttpResponse {
+    HttpResponse::Ok().body("app")
+}
+
+#[get("/api/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().json(&user.access)
+}
+
+#[get("/api/admin", wrap = "RequireClaims::new(HasRole::new(\"admin\"))")]
+async fn admin() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+fn builder(idp: &MockIdp, key: SessionKey, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .email("alice@example.com")
+            .role("admin"),
+    );
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/api"))
+        .roles_claim("roles")
+        .clock(clock.clone())
+        .session_store(InMemorySessionStore::default())
+        .session_format(SessionFormat::FirstPartyJwt { key, ttl: TTL })
+}
+
+async fn driver(
+    openid: &ActixWebOpenId,
+    idp: &MockIdp,
+) -> FlowDriver<
+    impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+> {
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .service(app_page)
+            .service(me)
+            .service(admin)
+            .configure(openid.configure_open_id()),
+    )
+    .await;
+    FlowDriver::new(app, idp)
+}
+
+/// Logs in from the SPA's page and returns the session token handed to it.
+async fn login<S>(driver: &mut FlowDriver<S>) -> String
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    let resp = driver.get("/login?next=/app").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    let fragment = driver.fragment().unwrap();
+    url::form_urlencoded::parse(fragment.as_bytes())
+        .find(|(name, _)| name == "session_token")
+        .map(|(_, token)| token.into_owned())
+        .unwrap()
+}
+
+/// Sends `token` the way the SPA does, in the `Authorization` header.
+async fn call<S>(
+    driver: &mut FlowDriver<S>,
+    method: Method,
+    path: &str,
+    token: &str,
+) -> FlowResponse
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    driver
+        .request(method, path)
+        .header(AUTHORIZATION.as_str(), &format!("Bearer {}", token))
+        .send()
+        .await
+}
+
+#[actix_web::test]
+async fn logins_hand_the_front_end_a_session_token() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, SessionKey::hs256([7; 32]), &MockClock::new())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = driver(&openid, &idp).await;
+
+    let token = login(&mut driver).await;
+
+    assert!(driver.cookies().iter().all(|cookie| {
+        !["session_id", "access_token", "refresh_token", "id_token"].contains(&cookie.name())
+    }));
+    let fragment = driver.fragment().unwrap();
+    assert!(
+        fragment.contains("token_type=Bearer&expires_in=300"),
+        "{}",
+        fragment
+    );
+    let resp = call(&mut driver, Method::GET, "/api/me", &token).await;
+    assert_eq!(resp.status(), 200);
+    let user: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(user["sub"], "alice");
+    assert_eq!(user["email"], "alice@example.com");
+    let resp = call(&mut driver, Method::GET, "/api/admin", &token).await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn session_tokens_are_checked_without_the_provider() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, SessionKey::hs256([7; 32]), &MockClock::new())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = driver(&openid, &idp).await;
+    let token = login(&mut driver).await;
+
+    idp.revoke_access_tokens();
+    let resp = call(&mut driver, Method::GET, "/api/me", &token).await;
+    assert_eq!(resp.status(), 200);
+
+    let (header, rest) = token.split_once('.').unwrap();
+    let (_, signature) = rest.split_once('.').unwrap();
+    let forged = URL_SAFE_NO_PAD
+        .encode(br#"{"aud":"x","sub":"mallory","sid":"x","iat":0,"exp":99999999999}"#);
+    let forged = format!("{}.{}.{}", header, forged, signature);
+    let resp = call(&mut driver, Method::GET, "/api/me", &forged).await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn expired_session_tokens_are_renewed_by_the_refresh_endpoint() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, SessionKey::hs256([7; 32]), &clock)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = driver(&openid, &idp).await;
+    let token = login(&mut driver).await;
+
+    clock.advance(TTL + Duration::from_secs(1));
+    let resp = call(&mut driver, Method::GET, "/api/me", &token).await;
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().contains_key(WWW_AUTHENTICATE));
+
+    let resp = call(&mut driver, Method::POST, "/session/refresh", &token).await;
+    assert_eq!(resp.status(), 200);
+    let renewed: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(renewed["token_type"], "Bearer");
+    assert_eq!(renewed["expires_in"], 300);
+    let renewed = renewed["session_token"].as_str().unwrap();
+    let resp = call(&mut driver, Method::GET, "/api/me", renewed).await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn sessions_end_when_the_provider_refuses_the_refresh() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, SessionKey::hs256([7; 32]), &MockClock::new())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = driver(&openid, &idp).await;
+    let token = login(&mut driver).await;
+
+    idp.revoke_refresh_tokens();
+    let resp = call(&mut driver, Method::POST, "/session/refresh", &token).await;
+    assert_eq!(resp.status(), 401);
+
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let resp = call(&mut driver, Method::POST, "/session/refresh", &token).await;
+    assert_eq!(resp.status(), 401);
+    let resp = call(&mut driver, Method::POST, "/session/refresh", "not.a.token").await;
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn logouts_end_the_stored_session() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, SessionKey::hs256([7; 32]), &clock)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = driver(&openid, &idp).await;
+
+    for logout in ["/logout", "/logout/local"] {
+        let token = login(&mut driver).await;
+        call(&mut driver, Method::GET, logout, &token).await;
+
+        let resp = call(&mut driver, Method::POST, "/session/refresh", &token).await;
+        assert_eq!(resp.status(), 401, "{}", logout);
+    }
+}
+
+#[actix_web::test]
+async fn ed_dsa_keys_sign_tokens_others_can_verify() {
+    let idp = MockIdp::start();
+    let key = SessionKey::ed_dsa(&[9; 32]);
+    let verifying_key = key.verifying_key().unwrap();
+    let openid = builder(&idp, key, &MockClock::new()).build().await.unwrap();
+    let mut driver = driver(&openid, &idp).await;
+
+    let token = login(&mut driver).await;
+
+    let (message, signature) = token.rsplit_once('.').unwrap();
+    let header: Value = serde_json::from_slice(
+        &URL_SAFE_NO_PAD
+            .decode(message.split('.').next().unwrap())
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(header["alg"], "EdDSA");
+    let signature =
+        ed25519_dalek::Signature::from_slice(&URL_SAFE_NO_PAD.decode(signature).unwrap()).unwrap();
+    verifying_key
+        .verify_strict(message.as_bytes(), &signature)
+        .unwrap();
+    let resp = call(&mut driver, Method::GET, "/api/me", &token).await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn first_party_sessions_need_a_session_store() {
+    let idp = MockIdp::start();
+    let result = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .session_format(SessionFormat::FirstPartyJwt {
+            key: SessionKey::hs256([7; 32]),
+            ttl: TTL,
+        })
+        .build()
+        .await;
+
+    assert!(result.is_err());
+}
@@ -0,0 +1,92 @@
+use actix_web::cookie::SameSite;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{ActixWebOpenId, CookieConfig, OpenIdBuilder};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .response_mode_form_post(true)
+}
+
+#[actix_web::test]
+async fn the_provider_posts_the_code_to_the_callback() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    assert!(login
+        .location()
+        .unwrap()
+        .contains("response_mode=form_post"));
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert!(String::from_utf8_lossy(resp.body()).contains("alice"));
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn login_cookies_are_sent_with_cross_site_posts() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .cookie_config(CookieConfig::default().secure(false))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+
+    for prefix in ["nonce.", "oauth_state."] {
+        let cookie = login
+            .cookies()
+            .find(|cookie| cookie.name().starts_with(prefix))
+            .unwrap();
+        assert_eq!(cookie.same_site(), Some(SameSite::None), "{}", prefix);
+        assert_eq!(cookie.secure(), Some(true), "{}", prefix);
+    }
+}
+
+#[actix_web::test]
+async fn posted_errors_render_the_error_page() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    idp.set_failure(Some(MockIdpFailure::AuthorizationError {
+        error: "access_denied",
+    }));
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 400);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(body.contains("the login was cancelled"), "{}", body);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn posted_callbacks_check_the_state() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver
+        .request(actix_web::http::Method::POST, "/auth_callback")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("code=forged&state=forged")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 400);
+    driver.assert_unauthenticated();
+}
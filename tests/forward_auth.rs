@@ -0,0 +1,120 @@
+use actix_web::{test, App};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, ForwardAuth};
+use url::Url;
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("https://app.example.com/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+/// The path the login returns to, from the state of the provider URL in `location`.
+fn state(location: &str) -> String {
+    Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "state")
+        .unwrap()
+        .1
+        .split_once('|')
+        .unwrap()
+        .1
+        .to_string()
+}
+
+#[actix_web::test]
+async fn the_ingress_learns_who_the_user_is() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice").email("alice@example.com"));
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .configure(openid.configure_open_id())
+            .configure(openid.configure_forward_auth(ForwardAuth::new(["app.example.com"]))),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    // Traefik's ForwardAuth describes the original request with these headers.
+    let resp = driver
+        .get("/forward_auth")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", "app.example.com")
+        .header("X-Forwarded-Uri", "/orders?page=2")
+        .send()
+        .await;
+    assert_eq!(resp.status(), 401);
+    let login_url = resp.location().unwrap().to_string();
+    assert!(login_url.starts_with(&idp.issuer_url()));
+    assert_eq!(state(&login_url), "https://app.example.com/orders?page=2");
+    assert!(driver.login_cookie("nonce").is_some());
+
+    // The user logs in and the callback sends them back to the app.
+    let resp = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(&login_url)
+        .send()
+        .await
+        .unwrap();
+    let callback = Url::parse(resp.headers()["location"].to_str().unwrap()).unwrap();
+    let resp = driver
+        .get(&format!(
+            "{}?{}",
+            callback.path(),
+            callback.query().unwrap()
+        ))
+        .send()
+        .await;
+    assert_eq!(
+        resp.location(),
+        Some("https://app.example.com/orders?page=2")
+    );
+
+    let resp = driver
+        .get("/forward_auth")
+        .header("X-Forwarded-Host", "app.example.com")
+        .header("X-Forwarded-Uri", "/orders?page=2")
+        .send()
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("x-auth-subject").unwrap(), "alice");
+    assert_eq!(
+        resp.headers().get("x-auth-email").unwrap(),
+        "alice@example.com"
+    );
+}
+
+#[actix_web::test]
+async fn unknown_hosts_are_rejected() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .configure(openid.configure_open_id())
+            .configure(openid.configure_forward_auth(ForwardAuth::new(["app.example.com"]))),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    for (host, uri) in [
+        (Some("evil.example"), "/"),
+        (None, "/"),
+        (Some("app.example.com"), "https://evil.example/"),
+    ] {
+        let mut request = driver.get("/forward_auth").header("X-Forwarded-Uri", uri);
+        if let Some(host) = host {
+            request = request.header("X-Forwarded-Host", host);
+        }
+        let resp = request.send().await;
+        assert_eq!(resp.status(), 400, "{:?} {}", host, uri);
+        assert!(resp.location().is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::{Duration, SystemTime};
+
+use actix_web::{test, App};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{ActixWebOpenId, CircuitState, Clock, HealthThresholds};
+use serde_json::Value;
+
+mod mock_auth_api;
+
+async fn openid(idp: &MockIdp, thresholds: HealthThresholds, clock: &MockClock) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .health_thresholds(thresholds)
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn the_endpoint_answers_the_report() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, HealthThresholds::default(), &MockClock::new()).await;
+    let app = test::init_service(App::new().configure(openid.configure_health())).await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/auth/health").to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    let report: Value = test::read_body_json(resp).await;
+    assert_eq!(report["healthy"], true);
+    assert_eq!(report["discovery_age"], 0);
+    assert_eq!(report["jwks_age"], 0);
+    assert_eq!(report["last_error"], Value::Null);
+    assert_eq!(report["breaker_state"], "closed");
+    // The discovery when building the client reached the provider.
+    assert!(report["last_success"].is_u64());
+    assert_eq!(report["last_failure"], Value::Null);
+}
+
+#[actix_web::test]
+async fn documents_older_than_the_thresholds_are_unhealthy() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(
+        &idp,
+        HealthThresholds::default().max_jwks_age(Duration::from_secs(60)),
+        &clock,
+    )
+    .await;
+    let app = test::init_service(App::new().configure(openid.configure_health())).await;
+    let client = openid.openid_client();
+    assert!(client.health().healthy);
+
+    clock.advance(Duration::from_secs(61));
+    assert!(!client.health().healthy);
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/auth/health").to_request(),
+    )
+    .await;
+    assert_eq!(resp.status(), 503);
+
+    // A refresh reaching the provider makes it healthy again.
+    client.refresh_provider().await.unwrap();
+    assert!(client.health().healthy);
+}
+
+#[actix_web::test]
+async fn probes_report_the_provider_failing() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, HealthThresholds::default(), &MockClock::new()).await;
+    let client = openid.openid_client();
+
+    idp.set_failure(Some(MockIdpFailure::DocumentsUnavailable));
+    let report = client.probe().await;
+    // The cached documents are still usable.
+    assert!(report.healthy);
+    assert!(report.last_error.unwrap().contains("503"));
+    assert_eq!(report.breaker_state, CircuitState::Closed);
+
+    idp.set_failure(None);
+    assert!(client.probe().await.last_error.is_none());
+}
+
+#[actix_web::test]
+async fn calls_made_for_logins_are_recorded() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(&idp, HealthThresholds::default(), &clock).await;
+    let client = openid.openid_client();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    clock.advance(Duration::from_secs(30));
+    driver.get("/is_auth/hello").follow_login().await;
+
+    driver.assert_authenticated();
+    let report = client.health();
+    assert_eq!(report.last_success, Some(SystemTime::from(clock.now())));
+    assert_eq!(report.last_failure, None);
+    assert_eq!(report.last_failure_error, None);
+}
+
+#[actix_web::test]
+async fn failed_calls_are_recorded_with_their_error() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(&idp, HealthThresholds::default(), &clock).await;
+    let client = openid.openid_client();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let report = client.health();
+    assert_eq!(report.last_failure, Some(SystemTime::from(clock.now())));
+    let error = report.last_failure_error.unwrap();
+    assert!(error.starts_with("POST "), "{}", error);
+    assert!(error.contains("500"), "{}", error);
+    // Without a threshold, failures alone leave the client healthy.
+    assert!(report.healthy);
+}
+
+#[actix_web::test]
+async fn failing_for_longer_than_the_threshold_is_unhealthy() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(
+        &idp,
+        HealthThresholds::default().max_failing_for(Duration::from_secs(60)),
+        &clock,
+    )
+    .await;
+    let client = openid.openid_client();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+    driver.get("/is_auth/hello").follow_login().await;
+    assert!(client.health().healthy);
+    clock.advance(Duration::from_secs(61));
+    assert!(!client.health().healthy);
+
+    // The next answer from the provider makes it healthy again.
+    idp.set_failure(None);
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.assert_authenticated();
+    assert!(client.health().healthy);
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{
+    ActixWebOpenId, OpenIdBuilder, OpenIdError, RetryPolicy, TokenSource,
+};
+use openidconnect::EmptyAdditionalClaims;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+}
+
+/// Requests tokens that expire within the refresh window, so every call reaches the provider.
+async fn request_tokens(openid: &ActixWebOpenId, times: usize) {
+    let provider = openid
+        .openid_client()
+        .token_provider(TokenSource::ClientCredentials(Vec::new()));
+    for _ in 0..times {
+        provider.get_token().await.unwrap();
+    }
+}
+
+#[actix_web::test]
+async fn reuses_connections_to_the_provider() {
+    let idp = MockIdp::start();
+    idp.set_token_lifetime(Duration::from_secs(10));
+    let openid = builder(&idp).build().await.unwrap();
+
+    request_tokens(&openid, 3).await;
+    openid.openid_client().refresh_provider().await.unwrap();
+
+    // Discovery, JWKS, token and refetched documents all went over the first connection.
+    assert_eq!(idp.connection_count(), 1);
+}
+
+#[actix_web::test]
+async fn uses_the_injected_client() {
+    let idp = MockIdp::start();
+    idp.set_token_lifetime(Duration::from_secs(10));
+    let http_client = reqwest::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+    let openid = builder(&idp)
+        .http_client(http_client)
+        .build()
+        .await
+        .unwrap();
+
+    request_tokens(&openid, 3).await;
+
+    // Without idle connections, discovery, JWKS and each token request open their own.
+    assert_eq!(idp.connection_count(), 5);
+}
+
+#[actix_web::test]
+async fn retries_documents_while_the_provider_is_unavailable() {
+    let idp = MockIdp::start();
+    idp.set_unavailable_for(3);
+
+    let openid = builder(&idp)
+        .retry_policy(RetryPolicy::exponential(3).initial_backoff(Duration::from_millis(10)))
+        .build()
+        .await;
+
+    assert!(openid.is_ok(), "{:?}", openid.err());
+}
+
+#[actix_web::test]
+async fn sends_requests_once_by_default() {
+    let idp = MockIdp::start();
+    idp.set_unavailable_for(1);
+
+    let err = builder(&idp).build().await.unwrap_err();
+
+    assert!(
+        matches!(err, OpenIdError::Http { status: 503 }),
+        "{:?}",
+        err
+    );
+}
+
+#[actix_web::test]
+async fn slow_userinfo_requests_time_out() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .request_timeout(Some(Duration::from_millis(200)))
+        .build()
+        .await
+        .unwrap();
+    let access_token = openid
+        .openid_client()
+        .token_provider(TokenSource::ClientCredentials(Vec::new()))
+        .get_token()
+        .await
+        .unwrap();
+    idp.set_userinfo_delay(Duration::from_secs(5));
+
+    let started = Instant::now();
+    let user_info = openid
+        .openid_client()
+        .user_info::<EmptyAdditionalClaims>(access_token)
+        .await;
+
+    assert!(
+        matches!(user_info, Err(OpenIdError::UserInfo(_))),
+        "{:?}",
+        user_info.err()
+    );
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,226 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, IdTokenError, IdTokenValidation, OpenIdBuilder, OpenIdError,
+};
+use openidconnect::core::{CoreIdToken, CoreJwsSigningAlgorithm};
+use serde_json::{json, Value};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Claims of an ID token for the client with the nonce `nonce`, valid for five minutes.
+fn claims(idp: &MockIdp) -> Value {
+    json!({
+        "iss": idp.issuer_url(),
+        "sub": "alice",
+        "aud": ["client"],
+        "iat": now(),
+        "exp": now() + 300,
+        "nonce": "nonce",
+    })
+}
+
+/// Verifies `claims` signed by the provider, with `changes` applied to them.
+async fn verify(
+    openid: &ActixWebOpenId,
+    idp: &MockIdp,
+    changes: Value,
+) -> Result<(), IdTokenError> {
+    let mut claims = claims(idp);
+    claims
+        .as_object_mut()
+        .unwrap()
+        .extend(changes.as_object().unwrap().clone());
+    let id_token: CoreIdToken = idp.sign(&claims).parse().unwrap();
+    match openid
+        .openid_client()
+        .verify_id_token(&id_token, "nonce".to_string())
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(OpenIdError::IdToken(err)) => Err(err),
+        Err(err) => panic!("unexpected error: {}", err),
+    }
+}
+
+#[actix_web::test]
+async fn failed_checks_are_named() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+
+    assert_eq!(verify(&openid, &idp, json!({})).await, Ok(()));
+    assert_eq!(
+        verify(&openid, &idp, json!({ "aud": ["other"] })).await,
+        Err(IdTokenError::Audience(vec!["other".to_string()]))
+    );
+    assert_eq!(
+        verify(
+            &openid,
+            &idp,
+            json!({ "aud": ["client", "api"], "azp": "client" })
+        )
+        .await,
+        Err(IdTokenError::UntrustedAudience("api".to_string()))
+    );
+    assert_eq!(
+        verify(&openid, &idp, json!({ "iss": "https://evil.example.com" })).await,
+        Err(IdTokenError::Issuer("https://evil.example.com".to_string()))
+    );
+    assert!(matches!(
+        verify(&openid, &idp, json!({ "exp": now() - 120 })).await,
+        Err(IdTokenError::Expired { .. })
+    ));
+    assert!(matches!(
+        verify(&openid, &idp, json!({ "iat": now() + 600 })).await,
+        Err(IdTokenError::IssuedInFuture { .. })
+    ));
+    assert!(matches!(
+        verify(&openid, &idp, json!({ "nbf": now() + 600 })).await,
+        Err(IdTokenError::NotYetValid { .. })
+    ));
+    assert!(matches!(
+        verify(&openid, &idp, json!({ "nonce": "other" })).await,
+        Err(IdTokenError::Nonce(_))
+    ));
+}
+
+#[actix_web::test]
+async fn clocks_may_be_off_by_the_clock_skew() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+
+    assert_eq!(
+        verify(
+            &openid,
+            &idp,
+            json!({ "exp": now() - 30, "iat": now() + 30 })
+        )
+        .await,
+        Ok(())
+    );
+
+    let openid = builder(&idp)
+        .id_token_validation(IdTokenValidation::default().clock_skew(Duration::ZERO))
+        .build()
+        .await
+        .unwrap();
+    assert!(matches!(
+        verify(&openid, &idp, json!({ "exp": now() - 30 })).await,
+        Err(IdTokenError::Expired { .. })
+    ));
+}
+
+#[actix_web::test]
+async fn multiple_audiences_need_the_client_as_authorized_party() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .id_token_validation(IdTokenValidation::default().multiple_audiences(true))
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        verify(
+            &openid,
+            &idp,
+            json!({ "aud": ["client", "api"], "azp": "client" })
+        )
+        .await,
+        Ok(())
+    );
+    assert_eq!(
+        verify(&openid, &idp, json!({ "aud": ["client", "api"] })).await,
+        Err(IdTokenError::AuthorizedParty(None))
+    );
+    assert_eq!(
+        verify(
+            &openid,
+            &idp,
+            json!({ "aud": ["client", "api"], "azp": "api" })
+        )
+        .await,
+        Err(IdTokenError::AuthorizedParty(Some("api".to_string())))
+    );
+}
+
+#[actix_web::test]
+async fn auth_time_can_be_required() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .id_token_validation(IdTokenValidation::default().require_auth_time(true))
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        verify(&openid, &idp, json!({})).await,
+        Err(IdTokenError::MissingAuthTime)
+    );
+    assert_eq!(
+        verify(&openid, &idp, json!({ "auth_time": now() })).await,
+        Ok(())
+    );
+}
+
+#[actix_web::test]
+async fn only_the_allowed_algorithms_are_accepted() {
+    let idp = MockIdp::start();
+    idp.set_id_token_alg(CoreJwsSigningAlgorithm::RsaSsaPssSha256);
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 500);
+    driver.assert_unauthenticated();
+
+    let openid = builder(&idp)
+        .id_token_validation(
+            IdTokenValidation::default().signing_algs([CoreJwsSigningAlgorithm::RsaSsaPssSha256]),
+        )
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.assert_authenticated();
+
+    assert_eq!(
+        verify(&openid, &idp, json!({})).await,
+        Err(IdTokenError::DisallowedAlgorithm {
+            alg: "RS256".to_string(),
+            allowed: vec!["PS256".to_string()],
+        })
+    );
+}
+
+#[actix_web::test]
+async fn algorithms_the_provider_does_not_sign_with_fail_the_build() {
+    let idp = MockIdp::start();
+
+    let result = builder(&idp)
+        .id_token_validation(
+            IdTokenValidation::default().signing_algs([CoreJwsSigningAlgorithm::EcdsaP256Sha256]),
+        )
+        .build()
+        .await;
+
+    assert!(result.is_err());
+}
@@ -0,0 +1,199 @@
+use std::time::Duration;
+
+use actix_web::http::header::HeaderName;
+use actix_web::{get, test, App, HttpRequest, HttpResponse};
+use actix_web_openidconnect::openid_middleware::AuthenticatedUser;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, ApiKeyValidator, ForwardedIdentity, IdentityClaim, IdentityHeaders,
+};
+
+/// Echoes the identity headers the handler received.
+#[get("/no_auth/headers")]
+async fn headers(req: HttpRequest) -> HttpResponse {
+    let mut names: Vec<_> = req
+        .headers()
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-auth-"))
+        .map(|(name, value)| format!("{}={}", name, value.to_str().unwrap()))
+        .collect();
+    names.sort();
+    HttpResponse::Ok().body(names.join(";"))
+}
+
+#[get("/no_auth/forwarded")]
+async fn forwarded(identity: ForwardedIdentity) -> HttpResponse {
+    let names: Vec<_> = identity.headers().map(|(name, _)| name.as_str()).collect();
+    HttpResponse::Ok().body(names.join(";"))
+}
+
+struct Partners;
+
+#[async_trait::async_trait]
+impl ApiKeyValidator for Partners {
+    async fn validate(&self, key: &str) -> Option<AuthenticatedUser> {
+        (key == "partner-x-key").then(|| {
+            AuthenticatedUserBuilder::new("partner-x")
+                .email("ops@partner-x.example")
+                .name("Partner X")
+                .build()
+        })
+    }
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+fn spoofed(uri: &str) -> test::TestRequest {
+    test::TestRequest::get()
+        .uri(uri)
+        .insert_header(("X-Auth-Subject", "admin"))
+        .insert_header(("X-Auth-Email", "admin@example.com"))
+        .insert_header(("X-Auth-Roles", "admin"))
+        .insert_header(("X-Auth-Signature", "t=0,v1=00"))
+}
+
+#[actix_web::test]
+async fn spoofed_headers_are_removed() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .api_key_validator(Partners)
+                    .identity_headers(IdentityHeaders::default()),
+            )
+            .service(headers),
+    )
+    .await;
+
+    let resp = test::call_service(&app, spoofed("/no_auth/headers").to_request()).await;
+    assert_eq!(test::read_body(resp).await, "");
+
+    let resp = test::call_service(
+        &app,
+        spoofed("/no_auth/headers")
+            .insert_header(("X-Api-Key", "partner-x-key"))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(
+        test::read_body(resp).await,
+        "x-auth-email=ops@partner-x.example;x-auth-subject=partner-x"
+    );
+}
+
+#[actix_web::test]
+async fn headers_and_claims_are_configurable() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .api_key_validator(Partners)
+                    .identity_headers(
+                        IdentityHeaders::new()
+                            .header(
+                                HeaderName::from_static("x-auth-user"),
+                                IdentityClaim::Subject,
+                            )
+                            .header(
+                                HeaderName::from_static("x-auth-name"),
+                                IdentityClaim::Custom(|user| {
+                                    Some(user.access.name()?.get(None)?.to_string())
+                                }),
+                            ),
+                    ),
+            )
+            .service(headers)
+            .service(forwarded),
+    )
+    .await;
+
+    // Only the configured names are stripped.
+    let resp = test::call_service(
+        &app,
+        spoofed("/no_auth/headers")
+            .insert_header(("X-Auth-User", "admin"))
+            .insert_header(("X-Api-Key", "partner-x-key"))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(
+        test::read_body(resp).await,
+        "x-auth-email=admin@example.com;x-auth-name=Partner X;x-auth-roles=admin;\
+         x-auth-subject=admin;x-auth-user=partner-x"
+    );
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/no_auth/forwarded")
+            .insert_header(("X-Api-Key", "partner-x-key"))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(test::read_body(resp).await, "x-auth-user;x-auth-name");
+}
+
+#[actix_web::test]
+async fn signed_headers_can_be_verified() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let identity = IdentityHeaders::default().sign("downstream-key");
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .api_key_validator(Partners)
+                    .identity_headers(identity.clone()),
+            )
+            .service(forwarded),
+    )
+    .await;
+    let req = test::TestRequest::get()
+        .uri("/no_auth/forwarded")
+        .insert_header(("X-Api-Key", "partner-x-key"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let sent = resp.request().headers().clone();
+
+    assert!(sent
+        .get("x-auth-signature")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("t="));
+    assert!(identity.verify(&sent, Duration::from_secs(60)));
+    assert!(!IdentityHeaders::default()
+        .sign("other-key")
+        .verify(&sent, Duration::from_secs(60)));
+
+    let mut tampered = sent.clone();
+    tampered.insert(
+        HeaderName::from_static("x-auth-subject"),
+        "admin".parse().unwrap(),
+    );
+    assert!(!identity.verify(&tampered, Duration::from_secs(60)));
+
+    let mut added = sent;
+    added.insert(
+        HeaderName::from_static("x-auth-roles"),
+        "admin".parse().unwrap(),
+    );
+    assert!(!identity.verify(&added, Duration::from_secs(60)));
+}
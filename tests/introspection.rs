@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, CredentialChain, IntrospectionResult, OpenIdBuilder, ValidationMode,
+};
+
+mod mock_auth_api;
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth") || req.path() == "/me")
+        .validation_mode(ValidationMode::Introspection)
+}
+
+#[actix_web::test]
+async fn sessions_are_checked_by_introspection() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+    assert_eq!(idp.introspection_count(), 1);
+}
+
+#[actix_web::test]
+async fn introspection_responses_are_cached() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .userinfo_cache(Duration::from_secs(60), 100)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(idp.introspection_count(), 1);
+}
+
+#[actix_web::test]
+async fn inactive_tokens_end_the_session() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    idp.revoke_refresh_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn introspect_reports_the_token() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    let result = openid
+        .openid_client()
+        .introspect(&access_token)
+        .await
+        .unwrap();
+    assert!(result.active);
+    assert_eq!(result.sub.as_deref(), Some("alice"));
+    assert!(result.scopes().any(|scope| scope == "openid"));
+    assert!(result.expires_at().is_some());
+
+    let result = openid.openid_client().introspect("unknown").await.unwrap();
+    assert!(!result.active);
+}
+
+#[::core::prelude::v1::test]
+fn expiries_past_the_clock_are_no_expiry() {
+    let result = IntrospectionResult {
+        active: true,
+        exp: Some(u64::MAX),
+        ..Default::default()
+    };
+
+    assert_eq!(result.expires_at(), None);
+}
+
+#[actix_web::test]
+async fn bearer_tokens_are_introspected() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .credentials(CredentialChain::bearer_only()),
+            )
+            .service(me),
+    )
+    .await;
+
+    for (token, status) in [(access_token.as_str(), 200), ("unknown", 401)] {
+        let request = test::TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+        let status_code = match test::try_call_service(&app, request.to_request()).await {
+            Ok(resp) => resp.status().as_u16(),
+            Err(err) => err.error_response().status().as_u16(),
+        };
+        assert_eq!(status_code, status, "{}", token);
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, CredentialChain, OpenIdBuilder, ValidationMode};
+use serde_json::{json, Value};
+
+mod mock_auth_api;
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    idp.set_jwt_access_tokens(true);
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth") || req.path() == "/me")
+        .validation_mode(ValidationMode::Local)
+}
+
+/// Claims of an access token for `client` expiring in `expires_in` seconds.
+fn claims(idp: &MockIdp, expires_in: i64) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    json!({
+        "iss": idp.issuer_url(),
+        "sub": "alice",
+        "aud": "client",
+        "iat": now,
+        "exp": now + expires_in,
+    })
+}
+
+async fn bearer_status<S>(app: &S, token: &str) -> u16
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    let request = test::TestRequest::get()
+        .uri("/me")
+        .insert_header((AUTHORIZATION, format!("Bearer {}", token)));
+    match test::try_call_service(app, request.to_request()).await {
+        Ok(resp) => resp.status().as_u16(),
+        Err(err) => err.error_response().status().as_u16(),
+    }
+}
+
+#[actix_web::test]
+async fn sessions_are_checked_without_the_userinfo_endpoint() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn expired_and_foreign_tokens_are_rejected() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .credentials(CredentialChain::bearer_only()),
+            )
+            .service(me),
+    )
+    .await;
+
+    assert_eq!(
+        bearer_status(&app, &idp.sign(&claims(&idp, 300))).await,
+        200
+    );
+    assert_eq!(bearer_status(&app, &idp.sign(&claims(&idp, -1))).await, 401);
+    let mut other_client = claims(&idp, 300);
+    other_client["aud"] = json!("other-client");
+    assert_eq!(bearer_status(&app, &idp.sign(&other_client)).await, 401);
+    let mut other_issuer = claims(&idp, 300);
+    other_issuer["iss"] = json!("https://evil.example.com");
+    assert_eq!(bearer_status(&app, &idp.sign(&other_issuer)).await, 401);
+}
+
+#[actix_web::test]
+async fn rotated_keys_are_fetched_for_unknown_key_ids() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp).clock(clock.clone()).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .credentials(CredentialChain::bearer_only()),
+            )
+            .service(me),
+    )
+    .await;
+
+    idp.rotate_signing_key();
+    let token = idp.sign(&claims(&idp, 300));
+    // The keys were fetched moments ago, the provider is not asked again yet.
+    assert_eq!(bearer_status(&app, &token).await, 401);
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(bearer_status(&app, &token).await, 200);
+}
@@ -0,0 +1,235 @@
+use std::sync::{Mutex, OnceLock};
+use std::thread::{self, ThreadId};
+
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp, MockIdpFailure};
+use actix_web_openidconnect::{ActixWebOpenId, LogCategory, LogPolicy, OpenIdBuilder};
+use log::{Level, Log, Metadata, Record};
+
+mod mock_auth_api;
+
+struct Captured {
+    thread: ThreadId,
+    target: String,
+    level: Level,
+    message: String,
+}
+
+/// Keeps every record, tests only look at the ones logged on their own thread.
+struct CapturingLogger {
+    records: Mutex<Vec<Captured>>,
+}
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.records.lock().unwrap().push(Captured {
+            thread: thread::current().id(),
+            target: record.target().to_string(),
+            level: record.level(),
+            message: record.args().to_string(),
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+fn logger() -> &'static CapturingLogger {
+    static LOGGER: OnceLock<&'static CapturingLogger> = OnceLock::new();
+    LOGGER.get_or_init(|| {
+        let logger = Box::leak(Box::new(CapturingLogger {
+            records: Mutex::new(Vec::new()),
+        }));
+        log::set_logger(logger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        logger
+    })
+}
+
+/// The crate's records logged on this thread so far, as `(target, level, message)`.
+fn records() -> Vec<(String, Level, String)> {
+    let current = thread::current().id();
+    logger()
+        .records
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| r.thread == current && r.target.starts_with("actix_web_openidconnect::"))
+        .map(|r| (r.target.clone(), r.level, r.message.clone()))
+        .collect()
+}
+
+fn records_of(category: LogCategory) -> Vec<(Level, String)> {
+    records()
+        .into_iter()
+        .filter(|(target, _, _)| target == category.target())
+        .map(|(_, level, message)| (level, message))
+        .collect()
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    logger();
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+}
+
+#[actix_web::test]
+async fn login_is_logged_without_secrets() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").send().await;
+    let nonce = resp
+        .cookies()
+        .find(|c| c.name().starts_with("nonce."))
+        .unwrap()
+        .value()
+        .to_string();
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.assert_authenticated();
+
+    assert_eq!(
+        records_of(LogCategory::UnauthenticatedRequest)[0].0,
+        Level::Debug
+    );
+    let success = records_of(LogCategory::LoginSuccess);
+    assert_eq!(success.len(), 1);
+    assert_eq!(success[0].0, Level::Info);
+    let secrets = [
+        driver.cookie("access_token").unwrap(),
+        driver.cookie("id_token").unwrap(),
+        nonce,
+    ];
+    for (target, _, message) in records() {
+        for secret in &secrets {
+            assert!(
+                !message.contains(secret.as_str()),
+                "{}: {}",
+                target,
+                message
+            );
+        }
+        assert!(!message.contains("code="), "{}: {}", target, message);
+    }
+}
+
+#[actix_web::test]
+async fn categories_can_be_turned_off_or_raised() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .log_policy(
+            LogPolicy::default()
+                .level(LogCategory::UnauthenticatedRequest, None)
+                .level(LogCategory::LoginSuccess, Some(Level::Warn)),
+        )
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    driver.get("/is_auth/hello").follow_login().await;
+
+    assert!(records_of(LogCategory::UnauthenticatedRequest).is_empty());
+    assert_eq!(records_of(LogCategory::LoginSuccess)[0].0, Level::Warn);
+}
+
+#[actix_web::test]
+async fn unavailable_provider_is_an_idp_error() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    idp.set_failure(Some(MockIdpFailure::TokenError {
+        status: 503,
+        error: "temporarily_unavailable",
+    }));
+
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let errors = records_of(LogCategory::IdpError);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, Level::Error);
+    assert!(records_of(LogCategory::LoginFailure).is_empty());
+}
+
+#[actix_web::test]
+async fn refused_code_is_a_login_failure() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    idp.set_failure(Some(MockIdpFailure::TokenError {
+        status: 400,
+        error: "invalid_scope",
+    }));
+
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let failures = records_of(LogCategory::LoginFailure);
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, Level::Warn);
+}
+
+#[actix_web::test]
+async fn basic_credentials_are_never_logged() {
+    use actix_web::{test, App};
+    use actix_web_openidconnect::BasicAuth;
+    use base64::Engine;
+
+    let idp = MockIdp::start();
+    idp.set_password("monitoring", "hunter2");
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new().wrap(
+            openid
+                .get_middleware()
+                .basic_auth(|_| true, BasicAuth::PasswordGrant),
+        ),
+    )
+    .await;
+
+    for credentials in ["monitoring:hunter2", "monitoring:hunter3"] {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+        let _ = test::try_call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/metrics")
+                .insert_header(("Authorization", format!("Basic {}", encoded)))
+                .to_request(),
+        )
+        .await;
+    }
+
+    assert!(!records_of(LogCategory::UnauthenticatedRequest).is_empty());
+    for (target, _, message) in records() {
+        assert!(!message.contains("hunter"), "{}: {}", target, message);
+        assert!(
+            !message.contains("bW9uaXRvcmluZz"),
+            "{}: {}",
+            target,
+            message
+        );
+    }
+}
+
+#[actix_web::test]
+async fn cancelled_logins_are_not_logged_as_failures() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    idp.set_failure(Some(MockIdpFailure::AuthorizationError {
+        error: "access_denied",
+    }));
+
+    driver.get("/is_auth/hello").follow_login().await;
+
+    assert!(records_of(LogCategory::LoginFailure).is_empty());
+    assert!(records_of(LogCategory::UnauthenticatedRequest)
+        .iter()
+        .any(|(level, message)| *level == Level::Debug && message.contains("access_denied")));
+}
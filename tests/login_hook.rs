@@ -0,0 +1,140 @@
+use std::sync::{Arc, Mutex};
+
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid::{OpenIDTokens, OtherClaims};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, InMemorySessionStore, LoginDecision, LoginHook, OpenIdBuilder,
+};
+use openidconnect::core::CoreGenderClaim;
+use openidconnect::IdTokenClaims;
+use serde_json::json;
+
+/// Answers with `decision`, remembering the subjects it was asked about.
+struct Hook {
+    decision: LoginDecision,
+    subjects: Arc<Mutex<Vec<String>>>,
+}
+
+#[async_trait::async_trait]
+impl LoginHook for Hook {
+    async fn on_login(
+        &self,
+        claims: &IdTokenClaims<OtherClaims, CoreGenderClaim>,
+        tokens: &OpenIDTokens,
+    ) -> LoginDecision {
+        assert!(!tokens.access_token.secret().is_empty());
+        self.subjects
+            .lock()
+            .unwrap()
+            .push(claims.subject().to_string());
+        self.decision.clone()
+    }
+}
+
+#[get("/is_auth/data")]
+async fn data(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().json(user.session_data())
+}
+
+fn builder(idp: &MockIdp, decision: LoginDecision) -> (OpenIdBuilder, Arc<Mutex<Vec<String>>>) {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    let subjects = Arc::new(Mutex::new(Vec::new()));
+    let builder = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .on_login(Hook {
+            decision,
+            subjects: subjects.clone(),
+        });
+    (builder, subjects)
+}
+
+async fn driver(
+    openid: &ActixWebOpenId,
+    idp: &MockIdp,
+) -> FlowDriver<
+    impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+> {
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .service(data)
+            .configure(openid.configure_open_id()),
+    )
+    .await;
+    FlowDriver::new(app, idp)
+}
+
+#[actix_web::test]
+async fn the_hook_sees_every_login() {
+    let idp = MockIdp::start();
+    let (builder, subjects) = builder(&idp, LoginDecision::Allow);
+    let openid = builder.build().await.unwrap();
+    let mut driver = driver(&openid, &idp).await;
+
+    let resp = driver.get("/is_auth/data").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), b"null");
+    assert_eq!(*subjects.lock().unwrap(), vec!["alice".to_string()]);
+}
+
+#[actix_web::test]
+async fn denied_logins_get_no_session() {
+    let idp = MockIdp::start();
+    let (builder, _) = builder(
+        &idp,
+        LoginDecision::Deny("Your account is deactivated".to_string()),
+    );
+    let openid = builder.build().await.unwrap();
+    let mut driver = driver(&openid, &idp).await;
+
+    let resp = driver.get("/is_auth/data").follow_login().await;
+
+    assert_eq!(resp.status(), 403);
+    assert!(String::from_utf8_lossy(resp.body()).contains("Your account is deactivated"));
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn session_data_is_kept_in_a_cookie() {
+    let idp = MockIdp::start();
+    let (builder, _) = builder(&idp, LoginDecision::AllowWith(json!({ "user_id": 42 })));
+    let openid = builder.build().await.unwrap();
+    let mut driver = driver(&openid, &idp).await;
+    driver.get("/is_auth/data").follow_login().await;
+
+    let resp = driver.get("/is_auth/data").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), br#"{"user_id":42}"#);
+    assert!(driver.cookie("session_data").is_some());
+}
+
+#[actix_web::test]
+async fn session_data_is_kept_in_the_session_store() {
+    let idp = MockIdp::start();
+    let (builder, _) = builder(&idp, LoginDecision::AllowWith(json!({ "user_id": 42 })));
+    let openid = builder
+        .session_store(InMemorySessionStore::default())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = driver(&openid, &idp).await;
+    driver.get("/is_auth/data").follow_login().await;
+
+    let resp = driver.get("/is_auth/data").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body().as_ref(), br#"{"user_id":42}"#);
+    assert!(driver.cookie("session_data").is_none());
+}
@@ -0,0 +1,207 @@
+use actix_web::dev::Service;
+use actix_web::{get, test, App, HttpMessage, HttpRequest, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{Authenticated, AuthenticationRequired};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, LoginParams, OpenIdBuilder};
+use url::Url;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .scopes(["profile"])
+        .extra_auth_param("prompt", "login")
+        .extra_auth_param("ui_locales", "de")
+        .should_auth(|req| req.path() != "/auth_callback")
+}
+
+fn query_params(location: &str, name: &str) -> Vec<String> {
+    Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .filter(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string())
+        .collect()
+}
+
+#[get("/hello")]
+async fn hello() -> HttpResponse {
+    HttpResponse::Ok().body("hello")
+}
+
+#[get("/payments/hello")]
+async fn payments() -> HttpResponse {
+    HttpResponse::Ok().body("payments")
+}
+
+#[get("/transfer")]
+async fn transfer(req: HttpRequest, _user: Authenticated) -> actix_web::Result<HttpResponse> {
+    Err(AuthenticationRequired::step_up(&req, LoginParams::new().scope("transfers"))?.into())
+}
+
+#[actix_web::test]
+async fn client_scopes_and_parameters_are_requested() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+
+    let url = openid
+        .openid_client()
+        .get_authorization_url("/".to_string())
+        .url;
+
+    assert_eq!(query_params(url.as_str(), "scope"), ["openid profile"]);
+    assert_eq!(query_params(url.as_str(), "prompt"), ["login"]);
+}
+
+#[actix_web::test]
+async fn routes_add_their_login_params() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().login_params(|req| {
+                req.path().starts_with("/payments").then(|| {
+                    LoginParams::new()
+                        .scope("payments")
+                        .param("prompt", "consent")
+                })
+            }))
+            .configure(openid.configure_open_id())
+            .service(hello)
+            .service(payments),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/payments/hello").send().await;
+    let location = resp.location().unwrap();
+    assert_eq!(query_params(location, "scope"), ["openid profile payments"]);
+    assert_eq!(query_params(location, "prompt"), ["consent"]);
+    assert_eq!(query_params(location, "ui_locales"), ["de"]);
+
+    let resp = driver.get("/hello").send().await;
+    let location = resp.location().unwrap();
+    assert_eq!(query_params(location, "scope"), ["openid profile"]);
+    assert_eq!(query_params(location, "prompt"), ["login"]);
+}
+
+#[actix_web::test]
+async fn handlers_step_up_logged_in_users() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(hello)
+            .service(transfer),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/hello").follow_login().await;
+
+    let resp = driver.get("/transfer").send().await;
+
+    assert_eq!(resp.status(), 302);
+    let location = resp.location().unwrap();
+    assert!(location.starts_with(&idp.issuer_url()));
+    assert_eq!(
+        query_params(location, "scope"),
+        ["openid profile transfers"]
+    );
+    assert!(driver.login_cookie("nonce").is_some());
+}
+
+#[actix_web::test]
+async fn login_links_pass_the_hint_and_the_languages() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .should_auth(|req| req.path() == "/hello")
+        .build()
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(hello),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver
+        .get("/login?hint=alice%40example.com&next=/hello&prompt=none")
+        .header("accept-language", "fr;q=0.9, fr-CH, *;q=0.5, en;q=0.8")
+        .send()
+        .await;
+
+    let location = resp.location().unwrap();
+    assert!(location.starts_with(&idp.issuer_url()));
+    assert_eq!(query_params(location, "login_hint"), ["alice@example.com"]);
+    assert_eq!(query_params(location, "ui_locales"), ["fr-CH fr en"]);
+    assert_eq!(query_params(location, "prompt"), ["login"]);
+}
+
+#[actix_web::test]
+async fn login_links_can_switch_accounts() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .should_auth(|req| req.path() == "/hello")
+        .build()
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(hello),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/hello").follow_login().await;
+
+    let logged_in = driver.get("/login?next=/hello").send().await;
+    let switch = driver
+        .get("/login?next=/hello&prompt=select_account")
+        .send()
+        .await;
+
+    assert_eq!(logged_in.location(), Some("/hello"));
+    let location = switch.location().unwrap();
+    assert!(location.starts_with(&idp.issuer_url()));
+    assert_eq!(query_params(location, "prompt"), ["select_account"]);
+}
+
+#[actix_web::test]
+async fn middlewares_insert_login_params_into_the_request() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .wrap_fn(|req, srv| {
+                let params = LoginParams::new()
+                    .login_hint("alice")
+                    .ui_locales_from(req.headers());
+                req.extensions_mut().insert(params);
+                srv.call(req)
+            })
+            .configure(openid.configure_open_id())
+            .service(hello),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver
+        .get("/hello")
+        .header("accept-language", "en-GB")
+        .send()
+        .await;
+
+    let location = resp.location().unwrap();
+    assert_eq!(query_params(location, "login_hint"), ["alice"]);
+    assert_eq!(query_params(location, "ui_locales"), ["en-GB"]);
+}
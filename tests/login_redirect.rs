@@ -0,0 +1,109 @@
+use actix_web::http::header::{CONTENT_TYPE, LOCATION};
+use actix_web::HttpResponse;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, LoginRedirect, OpenIdBuilder};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+fn branded_page(redirect: &LoginRedirect<'_>) -> HttpResponse {
+    let url = redirect.url.replace('&', "&amp;").replace('"', "&quot;");
+    HttpResponse::Ok().content_type("text/html").body(format!(
+        r#"<meta http-equiv="refresh" content="0; url={url}"><p>Redirecting to sign-in for {}…</p>"#,
+        redirect.path
+    ))
+}
+
+fn see_other(redirect: &LoginRedirect<'_>) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, redirect.url))
+        .finish()
+}
+
+#[actix_web::test]
+async fn login_redirects_default_to_a_found_with_the_message() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+    assert_eq!(resp.body(), "Not authenticated");
+}
+
+#[actix_web::test]
+async fn login_redirects_can_render_a_page() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .login_redirect(branded_page)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello?lang=en").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), "text/html");
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(body.contains(r#"http-equiv="refresh""#), "{}", body);
+    assert!(
+        body.contains(&format!("url={}/authorize?", idp.issuer_url())),
+        "{}",
+        body
+    );
+    assert!(body.contains("for /is_auth/hello?lang=en"), "{}", body);
+    assert!(driver.login_cookie("nonce").is_some());
+    assert!(driver.login_cookie("oauth_state").is_some());
+}
+
+#[actix_web::test]
+async fn logins_complete_through_custom_redirects() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .login_redirect(see_other)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    assert_eq!(login.status(), 303);
+    assert!(login.body().is_empty());
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn script_requests_still_get_the_login_url_as_json() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .login_redirect(branded_page)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("x-requested-with", "XMLHttpRequest")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 401);
+    let body: serde_json::Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(body["error"], "unauthenticated");
+}
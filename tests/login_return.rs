@@ -0,0 +1,100 @@
+use actix_web::http::{Method, StatusCode};
+use actix_web::{get, post, test, App, HttpRequest, HttpResponse};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use serde_json::Value;
+
+#[get("/items")]
+async fn items(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().body(req.uri().to_string())
+}
+
+#[post("/items")]
+async fn create_item() -> HttpResponse {
+    HttpResponse::Created().finish()
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn logins_return_to_the_path_and_query() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(items),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/items?page=2&sort=name").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "/items?page=2&sort=name");
+}
+
+#[actix_web::test]
+async fn unsafe_methods_are_not_redirected_to_the_provider() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(items)
+            .service(create_item),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver
+        .request(Method::POST, "/items")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("name=pizza")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 401);
+    assert!(resp.location().is_none());
+    let body: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert!(body["login_url"]
+        .as_str()
+        .unwrap()
+        .starts_with(&idp.issuer_url()));
+    let resp = driver.request(Method::HEAD, "/items").send().await;
+    assert_eq!(resp.status(), 302);
+}
+
+#[actix_web::test]
+async fn the_status_of_unsafe_methods_is_configurable() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .unsafe_method_status(StatusCode::CONFLICT),
+            )
+            .configure(openid.configure_open_id())
+            .service(create_item),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.request(Method::DELETE, "/items").send().await;
+
+    assert_eq!(resp.status(), 409);
+}
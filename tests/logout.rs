@@ -0,0 +1,209 @@
+use actix_web::cookie::Cookie;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+use url::Url;
+
+mod mock_auth_api;
+
+const SESSION_COOKIES: [&str; 5] = [
+    "access_token",
+    "id_token",
+    "refresh_token",
+    "user_info",
+    "access_token_expires_at",
+];
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    builder(idp).build().await.unwrap()
+}
+
+fn query_param(location: &str, name: &str) -> Option<String> {
+    Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+#[actix_web::test]
+async fn logout_removes_the_session_before_the_provider_is_asked() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+    for name in SESSION_COOKIES {
+        assert!(driver.cookie(name).is_none(), "{}", name);
+    }
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn local_logout_keeps_the_provider_session() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout/local").send().await;
+
+    assert_eq!(resp.status(), 200);
+    for name in SESSION_COOKIES {
+        assert!(driver.cookie(name).is_none(), "{}", name);
+    }
+}
+
+#[actix_web::test]
+async fn the_post_logout_callback_removes_what_is_left_of_the_session() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.set_cookie(Cookie::new("access_token", "stale"));
+    driver.set_cookie(Cookie::new("user_info", "stale"));
+
+    let resp = driver.get("/logout/callback").send().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_unauthenticated();
+    assert!(driver.cookie("user_info").is_none());
+}
+
+#[actix_web::test]
+async fn logout_without_id_token_still_removes_the_session() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.set_cookie(Cookie::new("refresh_token", "refresh"));
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 400);
+    assert!(driver.cookie("refresh_token").is_none());
+}
+
+#[actix_web::test]
+async fn logout_with_a_malformed_id_token_is_a_bad_request() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.set_cookie(Cookie::new("id_token", "not.a.jwt"));
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 400);
+    for name in SESSION_COOKIES {
+        assert!(driver.cookie(name).is_none(), "{}", name);
+    }
+}
+
+#[actix_web::test]
+async fn logout_redirects_travel_in_the_state() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .post_logout_redirect_url("http://localhost/logout/callback")
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout?redirect=/no_auth/hello").send().await;
+
+    let location = resp.location().unwrap();
+    assert_eq!(
+        query_param(location, "post_logout_redirect_uri").unwrap(),
+        "http://localhost/logout/callback"
+    );
+    assert_eq!(query_param(location, "state").unwrap(), "/no_auth/hello");
+    let resp = driver
+        .get("/logout/callback?state=%2Fno_auth%2Fhello")
+        .send()
+        .await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.location(), Some("/no_auth/hello"));
+    let resp = driver
+        .get("/logout/callback?state=https%3A%2F%2Fevil.example.com")
+        .send()
+        .await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn logout_redirects_become_the_post_logout_redirect_uri() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout?redirect=/no_auth/hello").send().await;
+
+    assert_eq!(
+        query_param(resp.location().unwrap(), "post_logout_redirect_uri").unwrap(),
+        "http://localhost/no_auth/hello"
+    );
+}
+
+#[actix_web::test]
+async fn logout_redirects_to_other_sites_are_ignored() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver
+        .get("/logout?redirect=https%3A%2F%2Fevil.example.com")
+        .send()
+        .await;
+
+    let location = resp.location().unwrap();
+    assert!(location.starts_with(&idp.issuer_url()));
+    assert_eq!(query_param(location, "post_logout_redirect_uri"), None);
+}
+
+#[actix_web::test]
+async fn logout_redirects_hiding_another_site_behind_whitespace_are_ignored() {
+    let idp = MockIdp::start();
+    idp.set_end_session_endpoint(false);
+    let openid = builder(&idp)
+        .post_logout_redirect_url("http://localhost/no_auth/hello")
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    for target in [
+        "/%09/evil.example.com",
+        "/%0A/evil.example.com",
+        "/%20/evil.example.com",
+    ] {
+        driver.get("/is_auth/hello").follow_login().await;
+        let resp = driver
+            .get(&format!("/logout?redirect={}", target))
+            .send()
+            .await;
+
+        assert_eq!(resp.status(), 302);
+        assert_eq!(
+            resp.location(),
+            Some("http://localhost/no_auth/hello"),
+            "{}",
+            target
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::MaybeAuthenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use serde_json::{json, Value};
+
+#[get("/home")]
+async fn home(user: MaybeAuthenticated) -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "authenticated": user.is_authenticated(),
+        "subject": user.user().map(|user| user.access.subject().as_str()),
+        "login_url": user.login_url(),
+        "shared": user.as_ref().map(|user| user.access.subject().as_str()),
+    }))
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+fn body(resp: &actix_web_openidconnect::test_util::FlowResponse) -> Value {
+    assert_eq!(resp.status(), 200);
+    serde_json::from_slice(resp.body()).unwrap()
+}
+
+#[actix_web::test]
+async fn anonymous_users_get_a_login_link() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(home),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let anonymous = body(&driver.get("/home?tab=news").send().await);
+    assert_eq!(anonymous["authenticated"], false);
+    assert!(anonymous["subject"].is_null());
+    assert_eq!(anonymous["login_url"], "/login?next=%2Fhome%3Ftab%3Dnews");
+
+    let user = body(
+        &driver
+            .get(anonymous["login_url"].as_str().unwrap())
+            .follow_login()
+            .await,
+    );
+    assert_eq!(user["authenticated"], true);
+    assert_eq!(user["subject"], "alice");
+    assert_eq!(user["shared"], "alice");
+    assert!(user["login_url"].is_null());
+    let resp = driver.get("/login?next=/home").send().await;
+    assert_eq!(resp.location(), Some("/home"));
+}
+
+#[actix_web::test]
+async fn routes_without_the_middleware_are_anonymous() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .configure(openid.configure_open_id())
+            .service(home),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let anonymous = body(&driver.get("/home").send().await);
+
+    assert_eq!(anonymous["authenticated"], false);
+    assert_eq!(anonymous["login_url"], "/login?next=%2Fhome");
+}
+
+#[actix_web::test]
+async fn apps_without_a_client_have_no_login_link() {
+    let app = test::init_service(App::new().service(home)).await;
+
+    let anonymous: Value =
+        test::call_and_read_body_json(&app, test::TestRequest::get().uri("/home").to_request())
+            .await;
+
+    assert_eq!(anonymous["authenticated"], false);
+    assert!(anonymous["login_url"].is_null());
+}
@@ -0,0 +1,145 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{test, App};
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{
+    ActixWebOpenId, EnglishMessages, MessageKey, Messages, OpenIdBuilder,
+};
+
+mod mock_auth_api;
+
+/// Translations are keyed on these ids, renaming one breaks them.
+#[::core::prelude::v1::test]
+fn message_keys_are_stable() {
+    let ids: Vec<_> = MessageKey::ALL.iter().map(MessageKey::id).collect();
+
+    assert_eq!(
+        ids,
+        [
+            "not-authenticated",
+            "internal-error",
+            "missing-id-token",
+            "invalid-id-token",
+            "missing-nonce",
+            "idp-unavailable",
+            "misconfigured",
+            "authentication-failed",
+            "unauthorized",
+            "no-access-token",
+            "client-not-registered",
+            "logged-out",
+            "choose-provider",
+            "missing-claims",
+            "invalid-state",
+            "forbidden",
+            "login-cancelled",
+            "login-expired",
+            "stale-login",
+        ]
+    );
+}
+
+#[::core::prelude::v1::test]
+fn english_has_every_message() {
+    for key in MessageKey::ALL {
+        assert!(!EnglishMessages.message(*key, &[]).is_empty(), "{:?}", key);
+    }
+}
+
+struct German;
+
+impl Messages for German {
+    fn message(&self, key: MessageKey, args: &[(&str, String)]) -> String {
+        match key {
+            MessageKey::NotAuthenticated => "Nicht angemeldet".to_string(),
+            MessageKey::MissingNonce => "Nonce fehlt".to_string(),
+            MessageKey::IdpUnavailable => format!(
+                "Anmeldung nicht erreichbar, bitte in {} Sekunden erneut versuchen",
+                args.iter()
+                    .find(|(name, _)| *name == "retry_after")
+                    .unwrap()
+                    .1
+            ),
+            _ => EnglishMessages.message(key, args),
+        }
+    }
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .messages(German)
+}
+
+#[actix_web::test]
+async fn responses_use_the_configured_messages() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+
+    let redirect = test::try_call_service(
+        &app,
+        test::TestRequest::get().uri("/is_auth/hello").to_request(),
+    )
+    .await
+    .err()
+    .unwrap()
+    .error_response();
+    let callback = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/auth_callback?code=code&state=/")
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(
+        actix_web::body::to_bytes(redirect.into_body())
+            .await
+            .unwrap(),
+        "Nicht angemeldet"
+    );
+    assert_eq!(callback.status(), 400);
+    let body = test::read_body(callback).await;
+    assert!(std::str::from_utf8(&body).unwrap().contains("Nonce fehlt"));
+}
+
+#[actix_web::test]
+async fn messages_receive_their_arguments() {
+    let idp = MockIdp::start();
+    idp.set_token_endpoint("http://127.0.0.1:1/token");
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id()),
+    )
+    .await;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/auth_callback?code=code&state=csrf%7C%2F")
+            .cookie(actix_web::cookie::Cookie::new(
+                "nonce",
+                format!("{}.nonce", now),
+            ))
+            .cookie(actix_web::cookie::Cookie::new("oauth_state", "csrf"))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 503);
+    let body = test::read_body(resp).await;
+    assert!(std::str::from_utf8(&body)
+        .unwrap()
+        .contains("Anmeldung nicht erreichbar, bitte in 30 Sekunden erneut versuchen"));
+}
@@ -0,0 +1,240 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_http::Request;
+use actix_service::Service;
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::{test, Error, HttpResponse};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockIdp, MockIdpFailure, SeededRandom,
+};
+use actix_web_openidconnect::ActixWebOpenId;
+
+mod mock_auth_api;
+
+/// The value of the nonce cookie of a login started now.
+fn nonce_cookie(nonce: &str) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!("{}.{}", now.as_secs(), nonce)
+}
+
+async fn init_openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::init(
+        "client".to_string(),
+        "secret".to_string(),
+        "http://localhost/auth_callback".to_string(),
+        idp.issuer_url(),
+        |req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback",
+        None,
+        vec!["openid".to_string()],
+    )
+    .await
+}
+
+async fn driver(
+    idp: &MockIdp,
+) -> FlowDriver<impl Service<Request, Response = ServiceResponse, Error = Error>> {
+    let openid = init_openid(idp).await;
+    FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, idp)
+}
+
+async fn call(
+    app: &impl Service<Request, Response = ServiceResponse, Error = Error>,
+    req: Request,
+) -> HttpResponse {
+    match test::try_call_service(app, req).await {
+        Ok(resp) => resp.into_parts().1,
+        Err(err) => err.error_response(),
+    }
+}
+
+fn location(resp: &HttpResponse) -> String {
+    resp.headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+/// Follows the redirect to the mock IdP and returns the callback path and query it sends back.
+async fn authorize(authorization_url: &str) -> String {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let resp = client.get(authorization_url).send().await.unwrap();
+    assert_eq!(resp.status(), 302);
+    let callback = resp.headers()["location"].to_str().unwrap();
+    callback
+        .strip_prefix("http://localhost")
+        .unwrap()
+        .to_string()
+}
+
+#[actix_web::test]
+async fn full_login_flow() {
+    let idp = MockIdp::start();
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .preferred_username("alice")
+            .email("alice@example.com"),
+    );
+    let mut driver = driver(&idp).await;
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.body(),
+        "hello auth_user EndUserUsername(\"alice\"). email: Some(EndUserEmail(\"alice@example.com\"))"
+    );
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn session_cookies_are_reused() {
+    let idp = MockIdp::start();
+    let mut driver = driver(&idp).await;
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn logout_redirects_to_the_provider() {
+    let idp = MockIdp::start();
+    let mut driver = driver(&idp).await;
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 302);
+    let location = resp.location().unwrap();
+    assert!(location.starts_with(&format!("{}/logout?", idp.issuer_url())));
+    assert!(location.contains("id_token_hint="));
+    let resp = driver.get("/logout").follow_login().await;
+    assert_eq!(resp.body(), "logged out");
+}
+
+#[actix_web::test]
+async fn anonymous_logout_asks_for_login() {
+    let idp = MockIdp::start();
+    let mut driver = driver(&idp).await;
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 302);
+    let location = resp.location().unwrap();
+    assert!(location.starts_with(&format!("{}/authorize?", idp.issuer_url())));
+}
+
+#[actix_web::test]
+async fn expired_id_token_is_rejected() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::ExpiredIdToken));
+    let mut driver = driver(&idp).await;
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 500);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn wrong_nonce_is_rejected() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::WrongNonce));
+    let mut driver = driver(&idp).await;
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 500);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn token_endpoint_error_fails_the_callback() {
+    let idp = MockIdp::start();
+    let mut driver = driver(&idp).await;
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "30");
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn callback_without_nonce_is_rejected() {
+    let idp = MockIdp::start();
+    let mut driver = driver(&idp).await;
+
+    let resp = driver
+        .get("/auth_callback?code=code&state=/is_auth/hello")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn seeded_random_source_makes_nonces_reproducible() {
+    let idp = MockIdp::start();
+    let build = || {
+        ActixWebOpenId::builder()
+            .client_id("client")
+            .client_secret("secret")
+            .redirect_url("http://localhost/auth_callback")
+            .issuer_url(idp.issuer_url())
+            .random_source(SeededRandom::new(7))
+            .build()
+    };
+    let first = build().await.unwrap();
+    let second = build().await.unwrap();
+
+    let first_url = first.openid_client().get_authorization_url("/".to_string());
+    let second_url = second
+        .openid_client()
+        .get_authorization_url("/".to_string());
+
+    assert_eq!(first_url.nonce.secret(), second_url.nonce.secret());
+    assert_eq!(first_url.url, second_url.url);
+}
+
+#[actix_web::test]
+async fn callback_completes_with_the_returned_state_and_nonce() {
+    let idp = MockIdp::start();
+    let openid = init_openid(&idp).await;
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+
+    let authorization_url = openid
+        .openid_client()
+        .get_authorization_url("/is_auth/hello".to_string());
+    assert_eq!(
+        authorization_url.state.secret(),
+        &format!("{}|/is_auth/hello", authorization_url.csrf_token.secret())
+    );
+    let callback = authorize(authorization_url.url.as_str()).await;
+    let resp = call(
+        &app,
+        test::TestRequest::get()
+            .uri(&callback)
+            .cookie(Cookie::new(
+                "nonce",
+                nonce_cookie(authorization_url.nonce.secret()),
+            ))
+            .cookie(Cookie::new(
+                "oauth_state",
+                authorization_url.csrf_token.secret().to_string(),
+            ))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 302);
+    assert_eq!(location(&resp), "/is_auth/hello");
+}
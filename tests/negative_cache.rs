@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{
+    ActixWebOpenId, AuthEvents, ClaimsSource, CredentialChain, EventContext, OpenIdBuilder,
+};
+use futures_util::future::join_all;
+use openidconnect::AccessToken;
+
+mod mock_auth_api;
+
+/// Records the source of every token validation as `source:ok`.
+#[derive(Clone, Default)]
+struct Validations(Arc<Mutex<Vec<String>>>);
+
+impl Validations {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl AuthEvents for Validations {
+    fn on_token_validation(&self, _: &EventContext<'_>, source: ClaimsSource, ok: bool) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("{}:{}", source.as_str(), ok));
+    }
+}
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp, validations: &Validations, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .auth_events(validations.clone())
+        .clock(clock.clone())
+}
+
+/// The access token of a login at the provider.
+async fn access_token(openid: &ActixWebOpenId, idp: &MockIdp) -> AccessToken {
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(openid).await, idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    AccessToken::new(driver.cookie("access_token").unwrap())
+}
+
+#[actix_web::test]
+async fn replayed_rejected_tokens_are_turned_away_without_the_provider() {
+    let idp = MockIdp::start();
+    let validations = Validations::default();
+    let openid = builder(&idp, &validations, &MockClock::new())
+        .negative_cache(Duration::from_secs(30), 100)
+        .build()
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .credentials(CredentialChain::bearer_only()),
+            )
+            .service(me),
+    )
+    .await;
+
+    for _ in 0..3 {
+        let request = test::TestRequest::get()
+            .uri("/me")
+            .insert_header((AUTHORIZATION, "Bearer forged"));
+        let status = match test::try_call_service(&app, request.to_request()).await {
+            Ok(resp) => resp.status(),
+            Err(err) => err.as_response_error().status_code(),
+        };
+        assert_eq!(status, 401);
+    }
+
+    assert_eq!(
+        validations.take(),
+        [
+            "userinfo:false",
+            "negative_cache:false",
+            "negative_cache:false"
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn rejections_are_forgotten_after_the_ttl() {
+    let idp = MockIdp::start();
+    let validations = Validations::default();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &validations, &clock)
+        .negative_cache(Duration::from_secs(30), 100)
+        .build()
+        .await
+        .unwrap();
+    let client = openid.openid_client();
+    let forged = AccessToken::new("forged".to_string());
+
+    assert!(client.user_claims(forged.clone(), None).await.is_err());
+    clock.advance(Duration::from_secs(31));
+    assert!(client.user_claims(forged, None).await.is_err());
+
+    assert_eq!(validations.take(), ["userinfo:false", "userinfo:false"]);
+}
+
+#[actix_web::test]
+async fn provider_outages_are_not_remembered() {
+    let idp = MockIdp::start();
+    let validations = Validations::default();
+    let openid = builder(&idp, &validations, &MockClock::new())
+        .negative_cache(Duration::from_secs(30), 100)
+        .build()
+        .await
+        .unwrap();
+    let client = openid.openid_client();
+    let access_token = access_token(&openid, &idp).await;
+    validations.take();
+
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+    assert!(client
+        .user_claims(access_token.clone(), None)
+        .await
+        .is_err());
+    idp.set_failure(None);
+    let claims = client.user_claims(access_token, None).await.unwrap();
+
+    assert_eq!(claims.subject().as_str(), "alice");
+    assert_eq!(validations.take(), ["userinfo:false", "userinfo:true"]);
+}
+
+#[actix_web::test]
+async fn the_oldest_rejections_make_room() {
+    let idp = MockIdp::start();
+    let validations = Validations::default();
+    let openid = builder(&idp, &validations, &MockClock::new())
+        .negative_cache(Duration::from_secs(30), 1)
+        .build()
+        .await
+        .unwrap();
+    let client = openid.openid_client();
+    let (first, second) = (
+        AccessToken::new("first".to_string()),
+        AccessToken::new("second".to_string()),
+    );
+
+    for token in [&first, &second, &second, &first] {
+        assert!(client.user_claims(token.clone(), None).await.is_err());
+    }
+
+    assert_eq!(
+        validations.take(),
+        [
+            "userinfo:false",
+            "userinfo:false",
+            "negative_cache:false",
+            "userinfo:false"
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn concurrent_validations_of_a_token_share_one_request() {
+    let idp = MockIdp::start();
+    let validations = Validations::default();
+    let openid = builder(&idp, &validations, &MockClock::new())
+        .negative_cache(Duration::from_secs(30), 100)
+        .userinfo_concurrency(4)
+        .build()
+        .await
+        .unwrap();
+    let client = openid.openid_client();
+    idp.set_userinfo_delay(Duration::from_millis(100));
+    let forged = AccessToken::new("forged".to_string());
+
+    let results = join_all((0..5).map(|_| client.user_claims(forged.clone(), None))).await;
+
+    assert!(results.iter().all(Result::is_err));
+    let mut validations = validations.take();
+    validations.sort();
+    assert_eq!(
+        validations,
+        [
+            "negative_cache:false",
+            "negative_cache:false",
+            "negative_cache:false",
+            "negative_cache:false",
+            "userinfo:false"
+        ]
+    );
+}
+
+#[actix_web::test]
+async fn userinfo_requests_beyond_the_limit_wait_their_turn() {
+    let idp = MockIdp::start();
+    let validations = Validations::default();
+    let openid = builder(&idp, &validations, &MockClock::new())
+        .userinfo_concurrency(1)
+        .build()
+        .await
+        .unwrap();
+    let client = openid.openid_client();
+    idp.set_userinfo_delay(Duration::from_millis(100));
+    let tokens = ["first", "second", "third"].map(|token| AccessToken::new(token.to_string()));
+
+    let started = std::time::Instant::now();
+    join_all(
+        tokens
+            .iter()
+            .map(|token| client.user_claims(token.clone(), None)),
+    )
+    .await;
+
+    // Each request waited for the previous one to be answered.
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert_eq!(validations.take().len(), 3);
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, IssuerValidation, OpenIdBuilder, OpenIdError};
+use serde_json::json;
+
+mod mock_auth_api;
+
+const ISSUER: &str = "https://contoso.b2clogin.com/9f8e7d6c/v2.0/";
+
+fn builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+}
+
+/// The settings of a provider only serving requests for the `B2C_1_signin` policy.
+fn policy_builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.set_policy("B2C_1_signin");
+    builder(idp, clock).discovery_url(format!(
+        "{}/.well-known/openid-configuration?p=B2C_1_signin",
+        idp.issuer_url()
+    ))
+}
+
+#[actix_web::test]
+async fn discovery_documents_are_fetched_from_the_discovery_url() {
+    let idp = MockIdp::start();
+    idp.set_policy("B2C_1_signin");
+
+    let err = builder(&idp, &MockClock::new()).build().await.unwrap_err();
+    assert!(
+        matches!(err, OpenIdError::Http { status: 404 }),
+        "{:?}",
+        err
+    );
+
+    policy_builder(&idp, &MockClock::new())
+        .build()
+        .await
+        .unwrap();
+}
+
+#[actix_web::test]
+async fn token_requests_carry_the_extra_token_params() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = policy_builder(&idp, &clock)
+        .extra_token_param("p", "B2C_1_signin")
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+
+    // Past the lifetime of the access token, the session is refreshed.
+    clock.advance(Duration::from_secs(6 * 60));
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert_eq!(resp.status(), 200);
+    assert!(idp
+        .token_requests()
+        .iter()
+        .any(|request| request.grant_type == "refresh_token"));
+}
+
+#[actix_web::test]
+async fn logins_fail_without_the_extra_token_params() {
+    let idp = MockIdp::start();
+    let openid = policy_builder(&idp, &MockClock::new())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_ne!(resp.status(), 200);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn expected_issuers_replace_the_issuer_url() {
+    let idp = MockIdp::start();
+    idp.set_issuer(ISSUER);
+
+    let err = builder(&idp, &MockClock::new()).build().await.unwrap_err();
+    assert!(matches!(err, OpenIdError::Discovery(_)), "{:?}", err);
+
+    let openid = builder(&idp, &MockClock::new())
+        .issuer_validation(IssuerValidation::Expected(ISSUER.to_string()))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn expected_issuers_are_required_of_the_tokens() {
+    let idp = MockIdp::start();
+    idp.set_issuer(ISSUER);
+    idp.set_id_token_claim("iss", json!("https://contoso.b2clogin.com/other/v2.0/"));
+    let openid = builder(&idp, &MockClock::new())
+        .issuer_validation(IssuerValidation::Expected(ISSUER.to_string()))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 500);
+    driver.assert_unauthenticated();
+}
@@ -0,0 +1,205 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, FlowResponse, MockClock, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+}
+
+/// Logs in at the provider from `login`, and returns the response of the callback.
+async fn callback<S>(driver: &mut FlowDriver<S>, login: &FlowResponse) -> FlowResponse
+where
+    S: actix_web::dev::Service<
+        actix_http::Request,
+        Response = actix_web::dev::ServiceResponse,
+        Error = actix_web::Error,
+    >,
+{
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let resp = client.get(login.location().unwrap()).send().await.unwrap();
+    let callback = resp.headers()["location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("http://localhost")
+        .unwrap()
+        .to_string();
+    driver.get(&callback).send().await
+}
+
+fn nonce_cookie(resp: &FlowResponse) -> Cookie<'static> {
+    resp.cookies()
+        .find(|cookie| cookie.name().starts_with("nonce."))
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn nonce_cookies_are_short_lived_and_hidden_from_scripts() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock)
+        .login_window(Duration::from_secs(120))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+
+    let nonce = nonce_cookie(&login);
+    assert_eq!(nonce.http_only(), Some(true));
+    assert_eq!(nonce.secure(), Some(true));
+    assert_eq!(nonce.same_site(), Some(SameSite::Lax));
+    assert_eq!(nonce.max_age().map(|age| age.whole_seconds()), Some(120));
+}
+
+#[actix_web::test]
+async fn nonce_cookies_are_removed_after_the_login() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let login = driver.get("/is_auth/hello").send().await;
+
+    let resp = callback(&mut driver, &login).await;
+
+    assert_eq!(resp.status(), 302);
+    let removal = nonce_cookie(&resp);
+    assert_eq!(removal.name(), nonce_cookie(&login).name());
+    assert_eq!(removal.max_age().map(|age| age.whole_seconds()), Some(0));
+    assert!(driver.login_cookie("nonce").is_none());
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn nonce_cookies_are_removed_after_failed_logins() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    idp.set_failure(Some(MockIdpFailure::AuthorizationError {
+        error: "access_denied",
+    }));
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 400);
+    assert!(driver.login_cookie("nonce").is_none());
+    assert!(driver.login_cookie("oauth_state").is_none());
+}
+
+#[actix_web::test]
+async fn login_cookies_are_removed_when_the_provider_refuses_the_code() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let login = driver.get("/is_auth/hello").send().await;
+    let attempt = nonce_cookie(&login).name().to_string();
+
+    // Refused codes start the login again.
+    idp.set_failure(Some(MockIdpFailure::TokenError {
+        status: 400,
+        error: "invalid_grant",
+    }));
+    let resp = callback(&mut driver, &login).await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+    let attempt_cookies: Vec<_> = resp
+        .cookies()
+        .filter(|cookie| {
+            cookie
+                .name()
+                .ends_with(attempt.strip_prefix("nonce").unwrap())
+        })
+        .collect();
+    for name in ["nonce", "oauth_state"] {
+        let removal = attempt_cookies
+            .iter()
+            .find(|cookie| cookie.name().starts_with(&format!("{}.", name)))
+            .unwrap_or_else(|| panic!("{} of the attempt is not removed", name));
+        assert_eq!(removal.max_age().map(|age| age.whole_seconds()), Some(0));
+    }
+    assert!(resp
+        .cookies()
+        .any(|cookie| cookie.name().starts_with("nonce.") && cookie.name() != attempt));
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn callbacks_after_the_login_window_are_refused() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let login = driver.get("/is_auth/hello").send().await;
+
+    clock.advance(Duration::from_secs(11 * 60));
+    let resp = callback(&mut driver, &login).await;
+
+    assert_eq!(resp.status(), 400);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(
+        body.contains("the login took too long, please log in again"),
+        "{}",
+        body
+    );
+    assert!(driver.login_cookie("nonce").is_none());
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn empty_nonces_are_refused() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let login = driver.get("/is_auth/hello").send().await;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    driver.set_cookie(Cookie::new(
+        nonce_cookie(&login).name().to_string(),
+        format!("{}.", now),
+    ));
+    let resp = callback(&mut driver, &login).await;
+
+    assert_eq!(resp.status(), 400);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn nonces_without_their_start_are_refused() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let login = driver.get("/is_auth/hello").send().await;
+    let nonce = nonce_cookie(&login);
+
+    // Unsealed, the start could be dropped to escape the login window.
+    let (_, value) = nonce.value().split_once('.').unwrap();
+    driver.set_cookie(Cookie::new(nonce.name().to_string(), value.to_string()));
+    let resp = callback(&mut driver, &login).await;
+
+    assert_eq!(resp.status(), 400);
+    driver.assert_unauthenticated();
+}
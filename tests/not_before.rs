@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, NotBeforePolicy, OpenIdBuilder};
+use serde_json::json;
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() == "/me")
+}
+
+/// Password resets, counting the lookups.
+#[derive(Clone, Default)]
+struct PasswordResets {
+    reset_at: Arc<Mutex<Option<SystemTime>>>,
+    lookups: Arc<AtomicUsize>,
+}
+
+#[async_trait::async_trait]
+impl NotBeforePolicy for PasswordResets {
+    async fn not_before(&self, _subject: &str) -> Option<SystemTime> {
+        self.lookups.fetch_add(1, Ordering::SeqCst);
+        *self.reset_at.lock().unwrap()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+async fn app_with(
+    openid: &ActixWebOpenId,
+) -> impl actix_web::dev::Service<
+    actix_http::Request,
+    Response = actix_web::dev::ServiceResponse,
+    Error = actix_web::Error,
+> {
+    test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await
+}
+
+#[actix_web::test]
+async fn sessions_older_than_the_not_before_time_log_in_again() {
+    let idp = MockIdp::start();
+    let resets = PasswordResets::default();
+    let openid = builder(&idp)
+        .not_before_policy(resets.clone())
+        .build()
+        .await
+        .unwrap();
+    let app = app_with(&openid).await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/me").follow_login().await;
+    assert_eq!(driver.get("/me").send().await.status(), 200);
+
+    *resets.reset_at.lock().unwrap() = Some(SystemTime::now() + Duration::from_secs(1));
+    // A new client, the answer of the policy is reused for a while.
+    let openid = builder(&idp)
+        .not_before_policy(resets.clone())
+        .build()
+        .await
+        .unwrap();
+    let app = app_with(&openid).await;
+    let mut driver = driver.with_app(&app);
+
+    let resp = driver.get("/me").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn the_policy_is_not_asked_on_every_request() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let resets = PasswordResets::default();
+    let openid = builder(&idp)
+        .not_before_policy(resets.clone())
+        .build()
+        .await
+        .unwrap();
+    let app = app_with(&openid).await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/me").follow_login().await;
+
+    for _ in 0..3 {
+        assert_eq!(driver.get("/me").send().await.body(), "alice");
+    }
+
+    assert_eq!(resets.lookups.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn keycloak_pushes_apply_to_every_session() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .keycloak_push_not_before(true)
+        .build()
+        .await
+        .unwrap();
+    let app = app_with(&openid).await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/me").follow_login().await;
+    assert_eq!(driver.get("/me").send().await.status(), 200);
+
+    let push = |payload| {
+        test::TestRequest::post()
+            .uri("/k_push_not_before")
+            .set_payload(idp.sign(&payload))
+            .to_request()
+    };
+    let for_other_client = json!({
+        "action": "PUSH_NOT_BEFORE",
+        "resource": "other",
+        "expiration": unix_now() + 60,
+        "notBefore": unix_now() + 1,
+    });
+    let resp = test::call_service(&app, push(for_other_client)).await;
+    assert_eq!(resp.status(), 401);
+    assert_eq!(driver.get("/me").send().await.status(), 200);
+
+    let event = json!({
+        "action": "PUSH_NOT_BEFORE",
+        "resource": "client",
+        "expiration": unix_now() + 60,
+        "notBefore": unix_now() + 1,
+    });
+    let resp = test::call_service(&app, push(event)).await;
+    assert_eq!(resp.status(), 204);
+
+    assert_eq!(driver.get("/me").send().await.status(), 302);
+}
+
+#[actix_web::test]
+async fn unsigned_pushes_are_rejected() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .keycloak_push_not_before(true)
+        .build()
+        .await
+        .unwrap();
+    let app = app_with(&openid).await;
+    let signed = idp.sign(&json!({
+        "action": "PUSH_NOT_BEFORE",
+        "resource": "client",
+        "expiration": unix_now() + 60,
+        "notBefore": unix_now(),
+    }));
+    let (unsigned, _) = signed.rsplit_once('.').unwrap();
+
+    for body in [format!("{}.", unsigned), "not a jws".to_string()] {
+        let resp = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/k_push_not_before")
+                .set_payload(body)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), 401);
+    }
+}
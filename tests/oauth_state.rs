@@ -0,0 +1,136 @@
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+use url::Url;
+
+mod mock_auth_api;
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+/// Logs in at the provider with the state of `authorization_url` replaced by `state`, and
+/// returns the callback path and query it sends back.
+async fn authorize_with_state(authorization_url: &str, state: &str) -> String {
+    let mut url = Url::parse(authorization_url).unwrap();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| match &*name {
+            "state" => (name.into_owned(), state.to_string()),
+            _ => (name.into_owned(), value.into_owned()),
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let resp = client.get(url.as_str()).send().await.unwrap();
+    resp.headers()["location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("http://localhost")
+        .unwrap()
+        .to_string()
+}
+
+/// The `state` parameter of `authorization_url`.
+fn state(authorization_url: &str) -> String {
+    Url::parse(authorization_url)
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "state")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[actix_web::test]
+async fn callbacks_with_a_forged_state_are_rejected() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver.get("/is_auth/hello").send().await;
+    let csrf_token = driver.login_cookie("oauth_state").unwrap();
+
+    // The forged token names the same login attempt, whose cookies are checked.
+    let forged = format!("{}forged|/is_auth/hello", &csrf_token[..8]);
+    let callback = authorize_with_state(resp.location().unwrap(), &forged).await;
+    let resp = driver.get(&callback).send().await;
+
+    assert_eq!(resp.status(), 400);
+    assert!(driver.login_cookie("oauth_state").is_none());
+    assert!(driver.login_cookie("nonce").is_none());
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn callbacks_without_the_state_cookie_are_rejected() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver.get("/is_auth/hello").send().await;
+    let csrf_token = driver.login_cookie("oauth_state").unwrap();
+    let state = format!("{}|/is_auth/hello", csrf_token);
+    let callback = authorize_with_state(resp.location().unwrap(), &state).await;
+
+    let mut other_browser = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    for cookie in driver.cookies() {
+        if cookie.name().starts_with("nonce.") {
+            other_browser.set_cookie(cookie);
+        }
+    }
+    let resp = other_browser.get(&callback).send().await;
+
+    assert_eq!(resp.status(), 400);
+    other_browser.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn logins_only_return_to_local_paths() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver.get("/is_auth/hello").send().await;
+    let csrf_token = driver.login_cookie("oauth_state").unwrap();
+
+    let state = format!("{}|https://evil.example.com/", csrf_token);
+    let callback = authorize_with_state(resp.location().unwrap(), &state).await;
+    let resp = driver.get(&callback).send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.location(), Some("/"));
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn logins_started_in_parallel_tabs_all_complete() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let first = driver.get("/is_auth/hello").send().await;
+    let first = first.location().unwrap().to_string();
+    let second = driver.get("/is_auth/hello?tab=2").send().await;
+    let second = second.location().unwrap().to_string();
+
+    let callback = authorize_with_state(&first, &state(&first)).await;
+    let resp = driver.get(&callback).send().await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.location(), Some("/is_auth/hello"));
+    driver.assert_authenticated();
+
+    // The second tab's login cookies survived the first callback.
+    let callback = authorize_with_state(&second, &state(&second)).await;
+    let resp = driver.get(&callback).send().await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.location(), Some("/is_auth/hello?tab=2"));
+    assert!(driver.login_cookie("nonce").is_none());
+}
@@ -0,0 +1,122 @@
+use actix_web::http::header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE};
+use actix_web::{test, App};
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, EnglishMessages, MessageKey, Messages, OpenIdBuilder, Page, PageContext,
+    PageKind, PageRenderer,
+};
+
+mod mock_auth_api;
+
+struct Branded;
+
+impl PageRenderer for Branded {
+    fn render(&self, kind: PageKind, context: &PageContext<'_>) -> Page {
+        let mut page = Page::html(format!(
+            "<main class=\"brand\">{:?} {} {} {:?}</main>",
+            kind,
+            context.status.as_u16(),
+            context.message,
+            context.target_url
+        ));
+        if kind == PageKind::LoggedOut {
+            page.content_security_policy = Some("default-src 'self'".to_string());
+        }
+        page
+    }
+}
+
+struct Markup;
+
+impl Messages for Markup {
+    fn message(&self, key: MessageKey, args: &[(&str, String)]) -> String {
+        match key {
+            MessageKey::MissingNonce => "<script>alert(1)</script>".to_string(),
+            _ => EnglishMessages.message(key, args),
+        }
+    }
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+}
+
+#[actix_web::test]
+async fn callback_errors_use_the_configured_renderer() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).page_renderer(Branded).build().await.unwrap();
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/auth_callback?code=code&state=/is_auth/hello")
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 400);
+    assert_eq!(
+        resp.headers().get(CONTENT_TYPE).unwrap(),
+        "text/html; charset=utf-8"
+    );
+    let csp = resp.headers().get(CONTENT_SECURITY_POLICY).unwrap();
+    assert!(csp.to_str().unwrap().contains("script-src 'nonce-"));
+    assert_eq!(
+        test::read_body(resp).await,
+        "<main class=\"brand\">CallbackError 400 No nonce Some(\"/is_auth/hello\")</main>"
+    );
+}
+
+#[actix_web::test]
+async fn default_pages_escape_and_only_link_locally() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).messages(Markup).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id()),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/auth_callback?code=code&state=https://evil.example")
+            .to_request(),
+    )
+    .await;
+
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(!body.contains("<script>"));
+    assert!(!body.contains("evil.example"));
+}
+
+#[actix_web::test]
+async fn logout_without_a_page_to_return_to_renders_the_logged_out_page() {
+    let idp = MockIdp::start();
+    idp.set_end_session_endpoint(false);
+    let openid = builder(&idp).page_renderer(Branded).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get(CONTENT_SECURITY_POLICY).unwrap(),
+        "default-src 'self'"
+    );
+    assert_eq!(
+        resp.body(),
+        "<main class=\"brand\">LoggedOut 200 You are logged out Some(\"/\")</main>"
+    );
+    driver.assert_unauthenticated();
+}
@@ -0,0 +1,109 @@
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, PayloadCodec, UserInfoCookie};
+use serde_json::{json, Value};
+
+mod mock_auth_api;
+
+/// Claims the size of an Azure AD ID token of a user in many groups.
+fn large_claims() -> Value {
+    let mut seed: u64 = 42;
+    let groups: Vec<String> = (0..150)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let hex = format!("{:016x}{:016x}", seed, seed.rotate_left(29));
+            format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            )
+        })
+        .collect();
+    json!({
+        "aud": "6e74172b-be56-4843-9ff4-e66a39bb12e3",
+        "iss": "https://login.microsoftonline.com/72f988bf-86f1-41af-91ab-2d7cd011db47/v2.0",
+        "iat": 1_700_000_000,
+        "exp": 1_700_003_600,
+        "sub": "AAAAAAAAAAAAAAAAAAAAAIkzqFVrSaSaFHy782bbtaQ",
+        "name": "Zoë Müller",
+        "preferred_username": "zoe@contoso.onmicrosoft.com",
+        "roles": ["orders.read", "orders.write", "admin"],
+        "groups": groups,
+        "email_verified": true,
+    })
+}
+
+#[test]
+fn every_codec_round_trips() {
+    let claims = large_claims();
+    for codec in [
+        PayloadCodec::Json,
+        PayloadCodec::MessagePack,
+        PayloadCodec::DeflateJson,
+    ] {
+        let payload = codec.encode(&claims).unwrap();
+        assert!(payload.is_ascii(), "{:?}", codec);
+        let decoded: Value = PayloadCodec::decode(&payload).unwrap();
+        assert_eq!(decoded, claims, "{:?}", codec);
+    }
+}
+
+#[test]
+fn binary_codecs_are_smaller() {
+    let claims = large_claims();
+    let json = PayloadCodec::Json.encode(&claims).unwrap().len();
+    let message_pack = PayloadCodec::MessagePack.encode(&claims).unwrap().len();
+    let deflate = PayloadCodec::DeflateJson.encode(&claims).unwrap().len();
+
+    // The random group ids hardly compress, claims with more text shrink further.
+    assert!(json > 6000, "{}", json);
+    assert!(
+        message_pack < json * 4 / 5,
+        "{} >= 4/5 of {}",
+        message_pack,
+        json
+    );
+    assert!(deflate < json * 4 / 5, "{} >= 4/5 of {}", deflate, json);
+}
+
+#[test]
+fn unknown_payloads_are_rejected() {
+    assert!(PayloadCodec::decode::<Value>("m1.not base64").is_err());
+    assert!(PayloadCodec::decode::<Value>("d1.AAAA").is_err());
+    assert!(PayloadCodec::decode::<Value>("{").is_err());
+}
+
+#[actix_web::test]
+async fn the_user_info_cookie_uses_the_codec() {
+    let idp = MockIdp::start();
+    idp.login_as(
+        AuthenticatedUserBuilder::new("zoe")
+            .preferred_username("zoe")
+            .name("Zoë"),
+    );
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+        .payload_codec(PayloadCodec::MessagePack)
+        .user_info_cookie(UserInfoCookie::Plain)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let user_info = driver.cookie("user_info").unwrap();
+    assert!(user_info.starts_with("m1."));
+    let user_info: Value = PayloadCodec::decode(&user_info).unwrap();
+    assert_eq!(user_info["sub"], "zoe");
+    assert_eq!(user_info["name"], "Zoë");
+}
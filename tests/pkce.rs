@@ -0,0 +1,69 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+fn query_parameter<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+    url.split(['?', '&'])
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+#[actix_web::test]
+async fn the_challenge_is_derived_from_the_verifier_cookie() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).pkce(true).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    let location = resp.location().unwrap();
+    let verifier = driver.login_cookie("pkce_verifier").unwrap();
+    assert!((43..=128).contains(&verifier.len()), "{}", verifier);
+    assert_eq!(
+        query_parameter(location, "code_challenge").unwrap(),
+        URL_SAFE_NO_PAD.encode(Sha256::digest(&verifier))
+    );
+    assert_eq!(
+        query_parameter(location, "code_challenge_method"),
+        Some("S256")
+    );
+}
+
+#[actix_web::test]
+async fn logins_prove_the_challenge_and_clear_the_verifier() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    let openid = builder(&idp).pkce(true).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+    assert!(driver.login_cookie("pkce_verifier").is_none());
+}
+
+#[actix_web::test]
+async fn pkce_is_off_by_default() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert!(query_parameter(resp.location().unwrap(), "code_challenge").is_none());
+    assert!(driver.login_cookie("pkce_verifier").is_none());
+}
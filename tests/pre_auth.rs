@@ -0,0 +1,118 @@
+use actix_web::dev::ServiceRequest;
+use actix_web::http::StatusCode;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::{
+    Authenticated, AuthenticatedUser, MaybeAuthenticated,
+};
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{ActixWebOpenId, PreAuthDecision};
+
+#[get("/status")]
+async fn status(user: MaybeAuthenticated) -> HttpResponse {
+    let user = user.into_inner();
+    HttpResponse::Ok().body(match user {
+        Some(user) => user.access.subject().as_str().to_string(),
+        None => "anonymous".to_string(),
+    })
+}
+
+#[get("/admin")]
+async fn admin(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn office_vpn(req: &ServiceRequest) -> PreAuthDecision {
+    match req.headers().get("x-network").map(|value| value.as_bytes()) {
+        Some(b"office") => {
+            PreAuthDecision::Allow(Some(Box::new(AuthenticatedUser::synthetic("vpn"))))
+        }
+        Some(b"probe") => PreAuthDecision::Allow(None),
+        Some(b"blocked") => PreAuthDecision::Deny(StatusCode::FORBIDDEN),
+        _ => PreAuthDecision::Continue,
+    }
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+fn request(path: &str, network: &str) -> actix_http::Request {
+    test::TestRequest::get()
+        .uri(path)
+        .insert_header(("X-Network", network))
+        .to_request()
+}
+
+#[actix_web::test]
+async fn allowed_requests_skip_the_login() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().pre_auth(office_vpn))
+            .service(status),
+    )
+    .await;
+
+    let resp = test::call_service(&app, request("/status", "office")).await;
+    assert_eq!(test::read_body(resp).await, "vpn");
+
+    let resp = test::call_service(&app, request("/status", "probe")).await;
+    assert_eq!(test::read_body(resp).await, "anonymous");
+
+    let err = test::try_call_service(&app, request("/status", "elsewhere"))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.error_response().status(), 302);
+}
+
+#[actix_web::test]
+async fn denied_requests_answer_the_status() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware().pre_auth(office_vpn))
+            .service(status),
+    )
+    .await;
+
+    let err = test::try_call_service(&app, request("/status", "blocked"))
+        .await
+        .err()
+        .unwrap();
+
+    assert_eq!(err.error_response().status(), 403);
+}
+
+#[actix_web::test]
+async fn strict_routes_cannot_be_bypassed() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .pre_auth(office_vpn)
+                    .always_authenticate(|req| req.path() == "/admin"),
+            )
+            .service(admin),
+    )
+    .await;
+
+    let err = test::try_call_service(&app, request("/admin", "office"))
+        .await
+        .err()
+        .unwrap();
+
+    assert_eq!(err.error_response().status(), 302);
+}
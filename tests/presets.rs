@@ -0,0 +1,230 @@
+use actix_web_openidconnect::openid::OpenID;
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, IssuerValidation, OpenIdBuilder};
+use httpmock::Method::GET;
+use httpmock::MockServer;
+use serde_json::json;
+use url::Url;
+
+mod mock_auth_api;
+
+/// Serves a minimal discovery document advertising `issuer` under `path`.
+fn serve_discovery(server: &MockServer, path: &str, issuer: &str) {
+    let base_url = server.url(path);
+    server.mock(|when, then| {
+        when.method(GET)
+            .path(format!("{}/.well-known/openid-configuration", path));
+        then.status(200)
+            .header("content-type", "application/json")
+            .json_body(json!({
+                "issuer": issuer,
+                "authorization_endpoint": format!("{}/authorize", base_url),
+                "token_endpoint": format!("{}/token", base_url),
+                "jwks_uri": format!("{}/jwks", base_url),
+                "response_types_supported": ["code"],
+                "subject_types_supported": ["public"],
+                "id_token_signing_alg_values_supported": ["RS256"],
+            }));
+    });
+    server.mock(|when, then| {
+        when.method(GET).path(format!("{}/jwks", path));
+        then.status(200)
+            .header("content-type", "application/json")
+            .json_body(json!({ "keys": [] }));
+    });
+}
+
+async fn build(builder: OpenIdBuilder) -> ActixWebOpenId {
+    builder
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap()
+}
+
+fn authorization_url(openid: &ActixWebOpenId) -> Url {
+    openid
+        .openid_client()
+        .get_authorization_url("/".to_string())
+        .url
+}
+
+fn query_param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.to_string())
+}
+
+#[actix_web::test]
+async fn keycloak_discovers_the_realm() {
+    let server = MockServer::start();
+    serve_discovery(&server, "/realms/my_realm", &server.url("/realms/my_realm"));
+
+    let openid = build(OpenID::keycloak(server.base_url(), "my_realm", "client")).await;
+
+    let url = authorization_url(&openid);
+    assert_eq!(url.path(), "/realms/my_realm/authorize");
+    assert_eq!(query_param(&url, "client_id").unwrap(), "client");
+    assert_eq!(
+        openid.openid_client().roles_claim(),
+        Some("realm_access.roles")
+    );
+}
+
+#[actix_web::test]
+async fn auth0_requests_the_audience_and_a_refresh_token() {
+    let idp = MockIdp::start();
+
+    let openid = build(
+        OpenID::auth0("example.eu.auth0.com", "client", "https://api.example.com")
+            .issuer_url(idp.issuer_url()),
+    )
+    .await;
+
+    let url = authorization_url(&openid);
+    assert_eq!(
+        query_param(&url, "audience").unwrap(),
+        "https://api.example.com"
+    );
+    assert_eq!(
+        query_param(&url, "scope").unwrap(),
+        "openid profile email offline_access"
+    );
+}
+
+#[actix_web::test]
+async fn azure_ad_common_endpoint_accepts_the_templated_issuer() {
+    let server = MockServer::start();
+    serve_discovery(
+        &server,
+        "/common/v2.0",
+        "https://login.microsoftonline.com/{tenantid}/v2.0",
+    );
+
+    let openid =
+        build(OpenID::azure_ad("common", "client").issuer_url(server.url("/common/v2.0"))).await;
+
+    assert_eq!(
+        openid.openid_client().issuer_validation(),
+        &IssuerValidation::Skip
+    );
+    assert_eq!(openid.openid_client().roles_claim(), Some("roles"));
+}
+
+#[actix_web::test]
+async fn azure_ad_single_tenant_checks_the_issuer() {
+    let builder = OpenID::azure_ad("contoso.onmicrosoft.com", "client");
+
+    let idp = MockIdp::start();
+    let openid = build(builder.issuer_url(idp.issuer_url())).await;
+
+    assert_eq!(
+        openid.openid_client().issuer_validation(),
+        &IssuerValidation::Exact
+    );
+}
+
+#[actix_web::test]
+async fn azure_ad_b2c_sends_the_policy_and_expects_the_tenant_issuer() {
+    let idp = MockIdp::start();
+    idp.set_policy("B2C_1_signin");
+    idp.set_issuer("https://contoso.b2clogin.com/9f8e7d6c/v2.0/");
+
+    let openid = build(
+        OpenID::azure_ad_b2c("contoso", "9f8e7d6c", "B2C_1_signin", "client")
+            .issuer_url(idp.issuer_url())
+            .discovery_url(format!(
+                "{}/.well-known/openid-configuration?p=B2C_1_signin",
+                idp.issuer_url()
+            )),
+    )
+    .await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let url = authorization_url(&openid);
+    assert_eq!(query_param(&url, "p").unwrap(), "B2C_1_signin");
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn google_requests_offline_access() {
+    let idp = MockIdp::start();
+
+    let openid = build(OpenID::google("client").issuer_url(idp.issuer_url())).await;
+
+    let url = authorization_url(&openid);
+    assert_eq!(query_param(&url, "access_type").unwrap(), "offline");
+    assert_eq!(query_param(&url, "prompt").unwrap(), "consent");
+    assert_eq!(
+        openid.openid_client().issuer_validation(),
+        &IssuerValidation::OneOf(vec![
+            "https://accounts.google.com".to_string(),
+            "accounts.google.com".to_string(),
+        ])
+    );
+}
+
+#[actix_web::test]
+async fn one_of_issuer_validation_accepts_a_listed_issuer() {
+    let idp = MockIdp::start();
+    let openid = build(
+        ActixWebOpenId::builder()
+            .client_id("client")
+            .issuer_url(idp.issuer_url())
+            .issuer_validation(IssuerValidation::OneOf(vec![
+                "https://other.example.com".to_string(),
+                idp.issuer_url(),
+            ])),
+    )
+    .await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn one_of_issuer_validation_rejects_other_issuers() {
+    let idp = MockIdp::start();
+    let openid = build(
+        ActixWebOpenId::builder()
+            .client_id("client")
+            .issuer_url(idp.issuer_url())
+            .issuer_validation(IssuerValidation::OneOf(vec![
+                "https://other.example.com".to_string()
+            ])),
+    )
+    .await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 500);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn logout_without_end_session_endpoint_redirects_locally() {
+    let idp = MockIdp::start();
+    idp.set_end_session_endpoint(false);
+    let openid = build(
+        ActixWebOpenId::builder()
+            .client_id("client")
+            .issuer_url(idp.issuer_url())
+            .post_logout_redirect_url("http://localhost/no_auth/hello"),
+    )
+    .await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.location(), Some("http://localhost/no_auth/hello"));
+}
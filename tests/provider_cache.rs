@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::ActixWebOpenId;
+use httpmock::Method::GET;
+use httpmock::{Mock, MockServer};
+use serde_json::{json, Value};
+
+mod mock_auth_api;
+
+fn discovery(server: &MockServer, end_session_endpoint: bool) -> Value {
+    let mut metadata = json!({
+        "issuer": server.base_url(),
+        "authorization_endpoint": server.url("/authorize"),
+        "token_endpoint": server.url("/token"),
+        "jwks_uri": server.url("/jwks"),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    });
+    if end_session_endpoint {
+        metadata["end_session_endpoint"] = json!(server.url("/logout"));
+    }
+    metadata
+}
+
+/// Serves both documents with `cache_control` and the `ETag` of their `version`.
+fn serve<'a>(
+    server: &'a MockServer,
+    cache_control: &str,
+    version: &str,
+    end_session_endpoint: bool,
+) -> [Mock<'a>; 2] {
+    let metadata = discovery(server, end_session_endpoint);
+    [
+        server.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200)
+                .header("cache-control", cache_control)
+                .header("etag", format!("\"discovery-{}\"", version))
+                .json_body(metadata);
+        }),
+        server.mock(|when, then| {
+            when.method(GET).path("/jwks");
+            then.status(200)
+                .header("cache-control", cache_control)
+                .header("last-modified", "Wed, 21 Oct 2026 07:28:00 GMT")
+                .json_body(json!({ "keys": [] }));
+        }),
+    ]
+}
+
+async fn build(server: &MockServer) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(server.base_url())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn not_modified_documents_are_kept() {
+    let server = MockServer::start();
+    let served = serve(&server, "max-age=0", "1", true);
+    let openid = build(&server).await;
+    for mut mock in served {
+        mock.delete();
+    }
+    let discovery_revalidated = server.mock(|when, then| {
+        when.method(GET)
+            .path("/.well-known/openid-configuration")
+            .header("if-none-match", "\"discovery-1\"");
+        then.status(304);
+    });
+    let jwks_revalidated = server.mock(|when, then| {
+        when.method(GET)
+            .path("/jwks")
+            .header("if-modified-since", "Wed, 21 Oct 2026 07:28:00 GMT");
+        then.status(304);
+    });
+
+    openid.openid_client().refresh_provider().await.unwrap();
+    openid.openid_client().refresh_provider().await.unwrap();
+
+    discovery_revalidated.assert_hits(2);
+    jwks_revalidated.assert_hits(2);
+    assert!(openid.validate().is_empty());
+}
+
+#[actix_web::test]
+async fn fresh_documents_are_not_refetched() {
+    let server = MockServer::start();
+    let [discovery, jwks] = serve(&server, "public, max-age=300", "1", true);
+    let openid = build(&server).await;
+
+    openid.openid_client().refresh_provider().await.unwrap();
+
+    discovery.assert_hits(1);
+    jwks.assert_hits(1);
+}
+
+#[actix_web::test]
+async fn modified_documents_replace_the_cached_ones() {
+    let server = MockServer::start();
+    let served = serve(&server, "max-age=0", "1", false);
+    let openid = build(&server).await;
+    assert_eq!(openid.validate().len(), 1);
+    for mut mock in served {
+        mock.delete();
+    }
+    serve(&server, "max-age=0", "2", true);
+
+    openid.openid_client().refresh_provider().await.unwrap();
+
+    assert!(openid.validate().is_empty());
+}
+
+#[actix_web::test]
+async fn documents_are_refetched_past_the_max_staleness() {
+    let server = MockServer::start();
+    let [discovery, jwks] = serve(&server, "max-age=86400", "1", true);
+    let clock = MockClock::new();
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(server.base_url())
+        .clock(clock.clone())
+        .max_provider_staleness(Duration::from_secs(600))
+        .build()
+        .await
+        .unwrap();
+
+    openid.openid_client().refresh_provider().await.unwrap();
+    discovery.assert_hits(1);
+
+    clock.advance(Duration::from_secs(601));
+    openid.openid_client().refresh_provider().await.unwrap();
+    discovery.assert_hits(2);
+    jwks.assert_hits(2);
+}
+
+#[actix_web::test]
+async fn id_tokens_signed_with_a_rotated_key_refetch_the_jwks() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    let clock = MockClock::new();
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    idp.rotate_signing_key();
+    clock.advance(Duration::from_secs(60));
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
@@ -0,0 +1,164 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_http::Request;
+use actix_service::Service;
+use actix_web::cookie::Cookie;
+use actix_web::dev::ServiceResponse;
+use actix_web::{test, Error, HttpResponse};
+use actix_web_openidconnect::test_util::{MockIdp, MockIdpFailure};
+use actix_web_openidconnect::{ActixWebOpenId, ErrorAction, OpenIdBuilder};
+
+mod mock_auth_api;
+
+/// The value of the nonce cookie of a login started now.
+fn nonce_cookie(nonce: &str) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!("{}.{}", now.as_secs(), nonce)
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+}
+
+async fn call(
+    app: &impl Service<Request, Response = ServiceResponse, Error = Error>,
+    req: Request,
+) -> HttpResponse {
+    match test::try_call_service(app, req).await {
+        Ok(resp) => resp.into_parts().1,
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Logs in at the provider and returns the callback it redirects to, with the login cookies.
+async fn authorize(openid: &ActixWebOpenId) -> Request {
+    let authorization_url = openid
+        .openid_client()
+        .get_authorization_url("/is_auth/hello".to_string());
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let resp = client
+        .get(authorization_url.url.as_str())
+        .send()
+        .await
+        .unwrap();
+    let callback = resp.headers()["location"]
+        .to_str()
+        .unwrap()
+        .strip_prefix("http://localhost")
+        .unwrap()
+        .to_string();
+    test::TestRequest::get()
+        .uri(&callback)
+        .cookie(Cookie::new(
+            "nonce",
+            nonce_cookie(authorization_url.nonce.secret()),
+        ))
+        .cookie(Cookie::new(
+            "oauth_state",
+            authorization_url.csrf_token.secret().to_string(),
+        ))
+        .to_request()
+}
+
+async fn token_error(
+    builder: OpenIdBuilder,
+    idp: &MockIdp,
+    status: u16,
+    error: &'static str,
+) -> HttpResponse {
+    let openid = builder.build().await.unwrap();
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+    let callback = authorize(&openid).await;
+    idp.set_failure(Some(MockIdpFailure::TokenError { status, error }));
+
+    call(&app, callback).await
+}
+
+async fn default_token_error(status: u16, error: &'static str) -> HttpResponse {
+    let idp = MockIdp::start();
+    token_error(builder(&idp), &idp, status, error).await
+}
+
+fn location(resp: &HttpResponse) -> &str {
+    resp.headers().get("location").unwrap().to_str().unwrap()
+}
+
+#[actix_web::test]
+async fn invalid_grant_sends_the_user_back_to_the_provider() {
+    let resp = default_token_error(400, "invalid_grant").await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(location(&resp).contains("/authorize?"));
+    assert!(location(&resp).contains("%7C%2Fis_auth%2Fhello"));
+    assert!(resp
+        .cookies()
+        .any(|cookie| cookie.name().starts_with("nonce.")));
+}
+
+#[actix_web::test]
+async fn invalid_token_sends_the_user_back_to_the_provider() {
+    let resp = default_token_error(401, "invalid_token").await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(location(&resp).contains("/authorize?"));
+}
+
+#[actix_web::test]
+async fn temporarily_unavailable_asks_to_retry_later() {
+    let resp = default_token_error(503, "temporarily_unavailable").await;
+
+    assert_eq!(resp.status(), 503);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "30");
+}
+
+#[actix_web::test]
+async fn provider_server_error_asks_to_retry_later() {
+    let resp = default_token_error(500, "server_error").await;
+
+    assert_eq!(resp.status(), 503);
+}
+
+#[actix_web::test]
+async fn invalid_client_is_an_internal_error() {
+    let resp = default_token_error(401, "invalid_client").await;
+
+    assert_eq!(resp.status(), 500);
+}
+
+#[actix_web::test]
+async fn other_provider_errors_are_bad_requests() {
+    let resp = default_token_error(400, "invalid_request").await;
+
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn unreachable_token_endpoint_asks_to_retry_later() {
+    let idp = MockIdp::start();
+    idp.set_token_endpoint("http://127.0.0.1:1/token");
+    let openid = builder(&idp).build().await.unwrap();
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+    let callback = authorize(&openid).await;
+
+    let resp = call(&app, callback).await;
+
+    assert_eq!(resp.status(), 503);
+}
+
+#[actix_web::test]
+async fn error_action_can_be_overridden() {
+    let idp = MockIdp::start();
+    let builder = builder(&idp).error_action(|_| ErrorAction::InternalError);
+
+    let resp = token_error(builder, &idp, 400, "invalid_grant").await;
+
+    assert_eq!(resp.status(), 500);
+}
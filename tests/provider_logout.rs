@@ -0,0 +1,172 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::Method;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, InMemorySessionStore, OpenIdBuilder, SessionStore};
+use serde_json::{json, Value};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .preferred_username("alice")
+            .claim("sid", "provider-session"),
+    );
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .front_channel_logout("/logout/frontchannel")
+        .back_channel_logout("/logout/backchannel")
+}
+
+/// Claims of a logout token of `sid` for the client.
+fn logout_claims(idp: &MockIdp, sid: &str) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    json!({
+        "iss": idp.issuer_url(),
+        "aud": "client",
+        "iat": now,
+        "exp": now + 120,
+        "jti": "logout-1",
+        "sid": sid,
+        "events": { "http://schemas.openid.net/event/backchannel-logout": {} },
+    })
+}
+
+fn form(logout_token: &str) -> String {
+    format!("logout_token={}", logout_token)
+}
+
+#[actix_web::test]
+async fn front_channel_logouts_end_the_provider_session() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .session_store(InMemorySessionStore::default())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let path = format!(
+        "/logout/frontchannel?iss={}&sid=other-session",
+        idp.issuer_url()
+    );
+    assert_eq!(driver.get(&path).send().await.status(), 200);
+    driver.assert_authenticated();
+
+    let path = format!(
+        "/logout/frontchannel?iss={}&sid=provider-session",
+        idp.issuer_url()
+    );
+    let resp = driver.get(&path).send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("cache-control").unwrap(),
+        "no-cache, no-store"
+    );
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn front_channel_logouts_of_other_issuers_are_rejected() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .session_store(InMemorySessionStore::default())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver
+        .get("/logout/frontchannel?iss=https://evil.example.com&sid=provider-session")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 400);
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn back_channel_logouts_remove_the_stored_sessions() {
+    let idp = MockIdp::start();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp)
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session_id = driver.cookie("session_id").unwrap();
+
+    let logout_token = idp.sign(&logout_claims(&idp, "provider-session"));
+    let resp = driver
+        .request(Method::POST, "/logout/backchannel")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(form(&logout_token))
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 200);
+    assert!(store.get(&session_id).await.unwrap().is_none());
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn invalid_logout_tokens_are_rejected() {
+    let idp = MockIdp::start();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp)
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session_id = driver.cookie("session_id").unwrap();
+
+    let mut with_nonce = logout_claims(&idp, "provider-session");
+    with_nonce["nonce"] = json!("n-0S6_WzA2Mj");
+    let mut without_event = logout_claims(&idp, "provider-session");
+    without_event["events"] = json!({});
+    let mut other_client = logout_claims(&idp, "provider-session");
+    other_client["aud"] = json!("other-client");
+    let mut without_session = logout_claims(&idp, "provider-session");
+    without_session.as_object_mut().unwrap().remove("sid");
+    for claims in [with_nonce, without_event, other_client, without_session] {
+        let resp = driver
+            .request(Method::POST, "/logout/backchannel")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(form(&idp.sign(&claims)))
+            .send()
+            .await;
+
+        assert_eq!(resp.status(), 400, "{}", claims);
+        assert_eq!(
+            serde_json::from_slice::<Value>(resp.body()).unwrap()["error"],
+            "invalid_request"
+        );
+    }
+    assert!(store.get(&session_id).await.unwrap().is_some());
+}
+
+#[actix_web::test]
+async fn back_channel_logout_requires_a_session_store() {
+    let idp = MockIdp::start();
+
+    let err = builder(&idp).build().await.err().unwrap();
+
+    assert!(err.to_string().contains("session_store"), "{}", err);
+}
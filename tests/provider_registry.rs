@@ -0,0 +1,97 @@
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder, ProviderKey, ProviderRegistry};
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp, tenant: &str) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new(format!("{}-user", tenant)));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost")
+        .issuer_url(idp.issuer_url())
+        .callback_path(format!("/{}/auth_callback", tenant))
+        .logout_path(format!("/{}/logout", tenant))
+        .namespace_cookies(true)
+}
+
+#[actix_web::test]
+async fn each_tenant_logs_in_with_its_provider() {
+    let acme_idp = MockIdp::start();
+    let globex_idp = MockIdp::start();
+    let acme = builder(&acme_idp, "acme").build().await.unwrap();
+    let globex = builder(&globex_idp, "globex").build().await.unwrap();
+    let registry = ProviderRegistry::new(
+        ProviderKey::path_prefix,
+        [("acme".into(), acme), ("globex".into(), globex)],
+    )
+    .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(registry.get_middleware())
+            .configure(registry.configure_open_id())
+            .service(web::scope("/acme").service(me))
+            .service(web::scope("/globex").service(me)),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &acme_idp).with_idp(&globex_idp);
+
+    let acme_user = driver.get("/acme/me").follow_login().await;
+    assert_eq!(acme_user.body(), "acme-user");
+    let resp = driver.get("/globex/me").send().await;
+    assert!(resp
+        .location()
+        .unwrap()
+        .starts_with(&globex_idp.issuer_url()));
+    let globex_user = driver.get("/globex/me").follow_login().await;
+    assert_eq!(globex_user.body(), "globex-user");
+    assert_eq!(driver.get("/acme/me").send().await.body(), "acme-user");
+
+    driver.get("/acme/logout/local").send().await;
+    assert_eq!(driver.get("/globex/me").send().await.body(), "globex-user");
+    assert_eq!(driver.get("/other/me").send().await.status(), 404);
+}
+
+#[actix_web::test]
+async fn tenants_sharing_cookies_are_rejected() {
+    let acme_idp = MockIdp::start();
+    let globex_idp = MockIdp::start();
+    let acme = builder(&acme_idp, "acme")
+        .namespace_cookies(false)
+        .build()
+        .await
+        .unwrap();
+    let globex = builder(&globex_idp, "globex")
+        .namespace_cookies(false)
+        .build()
+        .await
+        .unwrap();
+
+    let err = ProviderRegistry::new(
+        ProviderKey::host,
+        [("acme".into(), acme), ("globex".into(), globex)],
+    )
+    .err()
+    .unwrap();
+
+    assert!(err.to_string().contains("share their cookies"), "{}", err);
+}
+
+#[actix_web::test]
+async fn host_keys_leave_out_the_port() {
+    let req = test::TestRequest::get()
+        .uri("/me")
+        .insert_header(("host", "Acme.example.com:8443"))
+        .to_srv_request();
+
+    assert_eq!(
+        ProviderKey::host(&req),
+        ProviderKey::new("acme.example.com")
+    );
+    assert_eq!(ProviderKey::path_prefix(&req).as_str(), "me");
+}
@@ -0,0 +1,222 @@
+use actix_web::cookie::Cookie;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, LoginProviders, ProviderEntry, Realm};
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+async fn openid(idp: &MockIdp, id: &str) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url(format!("http://localhost/auth_callback?provider={}", id))
+        .issuer_url(idp.issuer_url())
+        .realm(Realm::new(id, "/"))
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn providers(corporate: &MockIdp, social: &MockIdp) -> LoginProviders {
+    LoginProviders::new(
+        |req| req.path() != "/auth_callback",
+        [
+            (
+                ProviderEntry::new("social", "Social <login>").sort_order(2),
+                openid(social, "social").await,
+            ),
+            (
+                ProviderEntry::new("corporate", "Corporate")
+                    .icon_url("https://cdn.example/corporate.svg")
+                    .sort_order(1),
+                openid(corporate, "corporate").await,
+            ),
+        ],
+    )
+    .unwrap()
+}
+
+#[actix_web::test]
+async fn chooser_lists_the_providers_without_requiring_a_login() {
+    let corporate = MockIdp::start();
+    let social = MockIdp::start();
+    let providers = providers(&corporate, &social).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(providers.get_middleware())
+            .configure(providers.configure_open_id())
+            .service(me),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get().uri("/login?next=/me").to_request(),
+    )
+    .await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.response().cookies().count(), 0);
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    let corporate_link = body
+        .find("<a href=\"/login?provider=corporate&amp;next=%2Fme\"><img src=\"https://cdn.example/corporate.svg\" alt=\"\"")
+        .unwrap();
+    let social_link = body
+        .find("<a href=\"/login?provider=social&amp;next=%2Fme\">Social &lt;login&gt;</a>")
+        .unwrap();
+    assert!(corporate_link < social_link);
+}
+
+#[actix_web::test]
+async fn users_log_in_with_the_provider_they_pick() {
+    let corporate = MockIdp::start();
+    corporate.login_as(AuthenticatedUserBuilder::new("employee"));
+    let social = MockIdp::start();
+    social.login_as(AuthenticatedUserBuilder::new("customer"));
+    let providers = providers(&corporate, &social).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(providers.get_middleware())
+            .configure(providers.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &corporate).with_idp(&social);
+
+    let resp = driver.get("/me").send().await;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.location(), Some("/login?next=%2Fme"));
+    assert_eq!(resp.cookies().count(), 0);
+
+    let resp = driver
+        .get("/login?provider=social&next=/me")
+        .follow_login()
+        .await;
+
+    assert_eq!(resp.body(), "customer");
+    assert!(driver.cookie("social_access_token").is_some());
+    assert!(driver.cookie("corporate_access_token").is_none());
+    assert_eq!(driver.get("/me").send().await.body(), "customer");
+}
+
+#[actix_web::test]
+async fn next_must_stay_on_the_site() {
+    let corporate = MockIdp::start();
+    let social = MockIdp::start();
+    let providers = providers(&corporate, &social).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(providers.get_middleware())
+            .configure(providers.configure_open_id()),
+    )
+    .await;
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/login?next=https://evil.example")
+            .to_request(),
+    )
+    .await;
+
+    let body = test::read_body(resp).await;
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("/login?provider=corporate&amp;next=%2F\""));
+    assert!(!body.contains("evil.example"));
+}
+
+#[actix_web::test]
+async fn providers_must_be_told_apart() {
+    let idp = MockIdp::start();
+    let shared_realm = |id: &str| {
+        ActixWebOpenId::builder()
+            .client_id("client")
+            .client_secret("secret")
+            .redirect_url(format!("http://localhost/auth_callback?provider={}", id))
+            .issuer_url(idp.issuer_url())
+            .build()
+    };
+    let result = LoginProviders::new(
+        |_| true,
+        [
+            (
+                ProviderEntry::new("a", "A"),
+                shared_realm("a").await.unwrap(),
+            ),
+            (
+                ProviderEntry::new("b", "B"),
+                shared_realm("b").await.unwrap(),
+            ),
+        ],
+    );
+    assert!(result
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("share the realm"));
+
+    let result = LoginProviders::new(
+        |_| true,
+        [(ProviderEntry::new("a", "A"), openid(&idp, "b").await)],
+    );
+    assert!(result
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("does not end with ?provider=a"));
+}
+
+#[actix_web::test]
+async fn later_logins_go_to_the_preferred_provider_until_switching() {
+    let corporate = MockIdp::start();
+    let social = MockIdp::start();
+    let providers = providers(&corporate, &social).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(providers.get_middleware())
+            .configure(providers.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &corporate).with_idp(&social);
+    driver
+        .get("/login?provider=social&next=/me")
+        .follow_login()
+        .await;
+    assert_eq!(driver.cookie("preferred_provider").unwrap(), "social");
+    driver.get("/logout").send().await;
+
+    let resp = driver.get("/me").send().await;
+    assert!(resp.location().unwrap().starts_with(&social.issuer_url()));
+
+    let resp = driver.get("/login?choose=1").send().await;
+    assert_eq!(resp.status(), 200);
+    assert!(driver.cookie("preferred_provider").is_none());
+    let resp = driver.get("/me").send().await;
+    assert_eq!(resp.location(), Some("/login?next=%2Fme"));
+}
+
+#[actix_web::test]
+async fn unknown_preferred_providers_are_ignored() {
+    let corporate = MockIdp::start();
+    let social = MockIdp::start();
+    let providers = providers(&corporate, &social).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(providers.get_middleware())
+            .configure(providers.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &corporate).with_idp(&social);
+    driver.set_cookie(Cookie::new("preferred_provider", "https://evil.example"));
+
+    let resp = driver.get("/me").send().await;
+
+    assert_eq!(resp.location(), Some("/login?next=%2Fme"));
+}
@@ -0,0 +1,78 @@
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::ActixWebOpenId;
+
+mod mock_auth_api;
+
+async fn public_client(idp: &MockIdp) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("browser-app")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn codes_are_exchanged_with_pkce_and_without_a_secret() {
+    let idp = MockIdp::start();
+    let openid = public_client(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert!(openid.openid_client().is_public_client());
+    assert!(openid.openid_client().pkce());
+    let requests = idp.token_requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].grant_type, "authorization_code");
+    assert_eq!(requests[0].client_id.as_deref(), Some("browser-app"));
+    assert_eq!(requests[0].client_secret, None);
+}
+
+#[actix_web::test]
+async fn refreshes_omit_the_secret() {
+    let idp = MockIdp::start();
+    let openid = public_client(&idp).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    let refresh = idp
+        .token_requests()
+        .into_iter()
+        .find(|request| request.grant_type == "refresh_token")
+        .unwrap();
+    assert_eq!(refresh.client_id.as_deref(), Some("browser-app"));
+    assert_eq!(refresh.client_secret, None);
+}
+
+#[actix_web::test]
+async fn confidential_clients_send_their_secret() {
+    let idp = MockIdp::start();
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    driver.get("/is_auth/hello").follow_login().await;
+
+    assert!(!openid.openid_client().pkce());
+    assert_eq!(
+        idp.token_requests()[0].client_secret.as_deref(),
+        Some("secret")
+    );
+}
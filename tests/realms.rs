@@ -0,0 +1,158 @@
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, Realm, SessionToken};
+
+/// Answers with the subject and the access token of the realm the route belongs to.
+#[get("/me")]
+async fn me(user: Authenticated, token: SessionToken) -> HttpResponse {
+    HttpResponse::Ok().body(format!(
+        "{} {}",
+        user.access.subject().as_str(),
+        token.access_token().await.secret()
+    ))
+}
+
+async fn openid(idp: &MockIdp, realm: Realm) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url(format!("http://localhost{}", realm.callback_path()))
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().ends_with("/auth_callback"))
+        .realm(realm)
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn realms_are_isolated() {
+    let corporate_idp = MockIdp::start();
+    corporate_idp.login_as(AuthenticatedUserBuilder::new("employee"));
+    let customer_idp = MockIdp::start();
+    customer_idp.login_as(AuthenticatedUserBuilder::new("customer"));
+    let internal = openid(&corporate_idp, Realm::new("internal", "/internal")).await;
+    let portal = openid(&customer_idp, Realm::new("portal", "/portal")).await;
+    let app = test::init_service(
+        App::new()
+            .service(
+                web::scope("/internal")
+                    .wrap(internal.get_middleware())
+                    .configure(internal.configure_open_id())
+                    .service(me),
+            )
+            .service(
+                web::scope("/portal")
+                    .wrap(portal.get_middleware())
+                    .configure(portal.configure_open_id())
+                    .service(me),
+            ),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &corporate_idp).with_idp(&customer_idp);
+
+    let employee = driver.get("/internal/me").follow_login().await;
+    let internal_token = driver.cookie("internal_access_token").unwrap();
+    assert_eq!(employee.body(), &format!("employee {}", internal_token));
+    assert!(driver.cookie("portal_access_token").is_none());
+
+    // The internal session is not a portal session.
+    let resp = driver.get("/portal/me").send().await;
+    assert_eq!(resp.status(), 302);
+    assert!(resp
+        .location()
+        .unwrap()
+        .starts_with(&format!("{}/authorize?", customer_idp.issuer_url())));
+    let nonce = resp
+        .cookies()
+        .find(|c| c.name().starts_with("portal_nonce."))
+        .unwrap();
+    assert_eq!(nonce.path(), Some("/portal"));
+
+    let customer = driver.get("/portal/me").follow_login().await;
+    let portal_token = driver.cookie("portal_access_token").unwrap();
+    assert_eq!(customer.body(), &format!("customer {}", portal_token));
+    let employee = driver.get("/internal/me").send().await;
+    assert_eq!(employee.body(), &format!("employee {}", internal_token));
+}
+
+/// A service at the root of the domain, namespacing its cookies.
+async fn service(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .namespace_cookies(true)
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn namespaced_services_share_a_cookie_jar() {
+    let orders_idp = MockIdp::start();
+    orders_idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    let billing_idp = MockIdp::start();
+    billing_idp.login_as(AuthenticatedUserBuilder::new("bob"));
+    let orders = service(&orders_idp).await;
+    let billing = service(&billing_idp).await;
+    let orders_cookie = format!("{}_access_token", orders.openid_client().realm().id());
+    let billing_cookie = format!("{}_access_token", billing.openid_client().realm().id());
+    assert_ne!(orders_cookie, billing_cookie);
+    let orders_app = test::init_service(
+        App::new()
+            .wrap(orders.get_middleware())
+            .configure(orders.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let billing_app = test::init_service(
+        App::new()
+            .wrap(billing.get_middleware())
+            .configure(billing.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&orders_app, &orders_idp).with_idp(&billing_idp);
+
+    driver.get("/me").follow_login().await;
+    let mut driver = driver.with_app(&billing_app);
+    let bob = driver.get("/me").follow_login().await;
+    let billing_token = driver.cookie(&billing_cookie).unwrap();
+    assert_eq!(bob.body(), &format!("bob {}", billing_token));
+    let mut driver = driver.with_app(&orders_app);
+    let orders_token = driver.cookie(&orders_cookie).unwrap();
+    let alice = driver.get("/me").send().await;
+    assert_eq!(alice.body(), &format!("alice {}", orders_token));
+
+    // Logging out of billing keeps the orders session.
+    let mut driver = driver.with_app(&billing_app);
+    driver.get("/logout").send().await;
+    assert!(driver.cookie(&billing_cookie).is_none());
+    let mut driver = driver.with_app(&orders_app);
+    let alice = driver.get("/me").send().await;
+    assert_eq!(alice.body(), &format!("alice {}", orders_token));
+}
+
+#[actix_web::test]
+async fn redirect_url_must_point_to_the_realm_callback() {
+    let idp = MockIdp::start();
+
+    let result = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .realm(Realm::new("portal", "/portal"))
+        .build()
+        .await;
+
+    assert!(result
+        .err()
+        .unwrap()
+        .to_string()
+        .contains("/portal/auth_callback"));
+}
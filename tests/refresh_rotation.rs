@@ -0,0 +1,158 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{
+    ActixWebOpenId, InMemorySessionStore, OpenIdBuilder, OpenIdError, SessionStore,
+};
+use futures_util::future::join_all;
+use openidconnect::RefreshToken;
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+}
+
+fn refreshes(idp: &MockIdp) -> usize {
+    idp.token_requests()
+        .iter()
+        .filter(|request| request.grant_type == "refresh_token")
+        .count()
+}
+
+#[actix_web::test]
+async fn concurrent_refreshes_share_one_exchange() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let client = openid.openid_client();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let refresh_token = RefreshToken::new(driver.cookie("refresh_token").unwrap());
+
+    let results = join_all((0..5).map(|_| client.refresh(&refresh_token))).await;
+
+    let access_tokens: Vec<_> = results
+        .into_iter()
+        .map(|tokens| tokens.unwrap().access_token.secret().clone())
+        .collect();
+    assert!(access_tokens.iter().all(|token| *token == access_tokens[0]));
+    assert_eq!(refreshes(&idp), 1);
+}
+
+#[actix_web::test]
+async fn replaced_refresh_tokens_get_the_tokens_replacing_them_for_a_moment() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let client = openid.openid_client();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let refresh_token = RefreshToken::new(driver.cookie("refresh_token").unwrap());
+
+    let first = client.refresh(&refresh_token).await.unwrap();
+    clock.advance(Duration::from_secs(10));
+    let second = client.refresh(&refresh_token).await.unwrap();
+    clock.advance(Duration::from_secs(30));
+    let replayed = client.refresh(&refresh_token).await;
+
+    assert_eq!(first.access_token.secret(), second.access_token.secret());
+    assert!(
+        matches!(replayed, Err(OpenIdError::RefreshTokenRevoked(ref err)) if err.error == "invalid_grant"),
+        "{:?}",
+        replayed.err()
+    );
+    assert_eq!(refreshes(&idp), 2);
+}
+
+#[actix_web::test]
+async fn revoked_refresh_tokens_end_the_session() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    idp.revoke_refresh_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    for name in ["access_token", "id_token", "refresh_token", "user_info"] {
+        let removal = resp.cookies().find(|cookie| cookie.name() == name).unwrap();
+        assert_eq!(removal.max_age().map(|age| age.whole_seconds()), Some(0));
+        assert_eq!(driver.cookie(name), None);
+    }
+    assert_eq!(refreshes(&idp), 1);
+}
+
+#[actix_web::test]
+async fn revoked_refresh_tokens_end_the_session_on_public_routes() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    idp.revoke_refresh_tokens();
+    let resp = driver.get("/no_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(driver.cookie("refresh_token"), None);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn revoked_refresh_tokens_remove_the_stored_session() {
+    let idp = MockIdp::start();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp, &MockClock::new())
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session_id = driver.cookie("session_id").unwrap();
+
+    idp.revoke_access_tokens();
+    idp.revoke_refresh_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(store.get(&session_id).await.unwrap().is_none());
+    assert_eq!(driver.cookie("session_id"), None);
+}
+
+#[actix_web::test]
+async fn refresh_tokens_are_kept_when_no_new_one_is_issued() {
+    let idp = MockIdp::start();
+    idp.set_rotate_refresh_tokens(false);
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let refresh_token = driver.cookie("refresh_token").unwrap();
+
+    idp.revoke_access_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert!(resp
+        .cookies()
+        .any(|cookie| cookie.name() == "refresh_token"));
+    assert_ne!(driver.cookie("access_token").unwrap(), access_token);
+    assert_eq!(driver.cookie("refresh_token").unwrap(), refresh_token);
+    clock.advance(Duration::from_secs(60));
+    idp.revoke_access_tokens();
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+}
@@ -0,0 +1,102 @@
+use actix_web::cookie::Cookie;
+use actix_web::test;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, OpenIdBuilder, OpenIdError, RandomSource, UserInfoCookie,
+};
+use serde_json::Value;
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| !req.path().starts_with("/no_auth") && req.path() != "/auth_callback")
+}
+
+/// Produces nonces no cookie can carry.
+struct HeaderBreakingRandom;
+
+impl RandomSource for HeaderBreakingRandom {
+    fn random_token(&self) -> String {
+        "bad\r\nnonce".to_string()
+    }
+}
+
+#[actix_web::test]
+async fn invalid_nonce_is_an_internal_error() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .random_source(HeaderBreakingRandom)
+        .build()
+        .await
+        .unwrap();
+    let app = mock_auth_api::get_mock_auth_api(&openid).await;
+
+    let err = test::try_call_service(
+        &app,
+        test::TestRequest::get().uri("/is_auth/hello").to_request(),
+    )
+    .await
+    .err()
+    .unwrap();
+
+    let resp = err.error_response();
+    assert_eq!(resp.status(), 500);
+    assert!(resp.headers().get("location").is_none());
+}
+
+#[actix_web::test]
+async fn tampered_id_token_cookie_is_rejected_on_logout() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.set_cookie(Cookie::new("id_token", "not.a.jwt"));
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 400);
+}
+
+#[actix_web::test]
+async fn non_ascii_claims_are_escaped_in_the_user_info_cookie() {
+    let idp = MockIdp::start();
+    idp.login_as(
+        AuthenticatedUserBuilder::new("zoe")
+            .preferred_username("zoe")
+            .name("Zoë 😀"),
+    );
+    let openid = builder(&idp)
+        .user_info_cookie(UserInfoCookie::Plain)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let user_info = driver.cookie("user_info").unwrap();
+    assert!(user_info.is_ascii());
+    assert!(user_info.contains(r"Zo\u00eb \ud83d\ude00"));
+    let user_info: Value = serde_json::from_str(&user_info).unwrap();
+    assert_eq!(user_info["name"], "Zoë 😀");
+}
+
+#[actix_web::test]
+async fn invalid_post_logout_redirect_url_fails_the_build() {
+    let idp = MockIdp::start();
+
+    let err = builder(&idp)
+        .post_logout_redirect_url("not a url")
+        .build()
+        .await
+        .err()
+        .unwrap();
+
+    assert!(matches!(err, OpenIdError::Config(_)), "{:?}", err);
+}
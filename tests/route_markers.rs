@@ -0,0 +1,109 @@
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::MaybeAuthenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, Public, RequireAuth};
+
+async fn greeting(user: MaybeAuthenticated) -> HttpResponse {
+    match user.user() {
+        Some(user) => HttpResponse::Ok().body(user.access.subject().to_string()),
+        None => HttpResponse::Ok().body("anonymous"),
+    }
+}
+
+#[get("/faq", name = "openid:public:faq")]
+async fn faq() -> HttpResponse {
+    HttpResponse::Ok().body("faq")
+}
+
+async fn openid(idp: &MockIdp, should_auth: bool) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(move |_| should_auth)
+        .build()
+        .await
+        .unwrap()
+}
+
+macro_rules! app {
+    ($openid:expr) => {
+        test::init_service(
+            App::new()
+                .wrap($openid.get_middleware())
+                .configure($openid.configure_open_id())
+                .service(Public::resource("/pricing").route(web::get().to(greeting)))
+                .service(RequireAuth::resource("/account").route(web::get().to(greeting)))
+                .service(
+                    web::scope("/docs")
+                        .service(Public::resource("/intro").route(web::get().to(greeting))),
+                )
+                .service(faq)
+                .route("/home", web::get().to(greeting)),
+        )
+        .await
+    };
+}
+
+#[actix_web::test]
+async fn public_resources_let_anonymous_requests_through() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, true).await;
+    let app = app!(openid);
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    assert_eq!(driver.get("/home").send().await.status(), 302);
+    let resp = driver.get("/pricing").send().await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "anonymous");
+    assert_eq!(driver.get("/docs/intro").send().await.status(), 200);
+    assert_eq!(driver.get("/faq").send().await.status(), 200);
+}
+
+#[actix_web::test]
+async fn public_resources_still_see_the_session() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, true).await;
+    let app = app!(openid);
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/home").follow_login().await;
+
+    let resp = driver.get("/pricing").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "alice");
+}
+
+#[actix_web::test]
+async fn marked_resources_require_authentication_where_it_is_optional() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, false).await;
+    let app = app!(openid);
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    assert_eq!(driver.get("/home").send().await.body(), "anonymous");
+    let resp = driver.get("/account").send().await;
+    assert_eq!(resp.status(), 302);
+
+    let resp = driver.get("/account").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "alice");
+}
+
+#[actix_web::test]
+async fn marker_names_keep_the_resources_reachable_by_name() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, true).await;
+    let app = app!(openid);
+    let req = test::TestRequest::get().uri("/pricing").to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    let url = resp
+        .request()
+        .url_for::<[&str; 0], _>(&Public::name("/pricing"), [])
+        .unwrap();
+    assert_eq!(url.path(), "/pricing");
+}
@@ -0,0 +1,79 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost")
+        .issuer_url(idp.issuer_url())
+        .callback_path("/oidc/callback")
+        .logout_path("/signout")
+}
+
+#[actix_web::test]
+async fn endpoints_are_mounted_at_the_configured_paths() {
+    let idp = MockIdp::start();
+    // Every path requires a login, but the callback.
+    let openid = builder(&idp).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let login = driver.get("/me").send().await;
+    let redirect_uri = url::Url::parse(login.location().unwrap())
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "redirect_uri")
+        .map(|(_, value)| value.into_owned());
+    assert_eq!(
+        redirect_uri.as_deref(),
+        Some("http://localhost/oidc/callback")
+    );
+    let resp = driver.get("/me").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "alice");
+    assert_eq!(driver.get("/auth_callback").send().await.status(), 404);
+    driver.get("/signout/local").send().await;
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn redirect_urls_must_point_to_the_configured_callback() {
+    let idp = MockIdp::start();
+
+    let err = builder(&idp)
+        .redirect_url("http://localhost/auth_callback")
+        .build()
+        .await
+        .err()
+        .unwrap();
+
+    assert!(err.to_string().contains("/oidc/callback"), "{}", err);
+}
+
+#[actix_web::test]
+async fn paths_must_be_absolute() {
+    let idp = MockIdp::start();
+
+    for builder in [
+        builder(&idp).callback_path("oidc/callback"),
+        builder(&idp).logout_path("/"),
+    ] {
+        assert!(builder.build().await.is_err());
+    }
+}
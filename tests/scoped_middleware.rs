@@ -0,0 +1,120 @@
+use actix_web::{test, web, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::MaybeAuthenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, HasRole};
+
+async fn hello() -> HttpResponse {
+    HttpResponse::Ok().body("hello")
+}
+
+async fn home(user: MaybeAuthenticated) -> HttpResponse {
+    match user.user() {
+        Some(user) => HttpResponse::Ok().body(user.access.subject().to_string()),
+        None => HttpResponse::Ok().body("anonymous"),
+    }
+}
+
+async fn openid(idp: &MockIdp, user: AuthenticatedUserBuilder) -> ActixWebOpenId {
+    idp.login_as(user);
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .roles_claim("roles")
+        .build()
+        .await
+        .unwrap()
+}
+
+macro_rules! scoped_app {
+    ($openid:expr) => {{
+        let auth = $openid.get_middleware();
+        test::init_service(
+            App::new()
+                .configure($openid.configure_open_id())
+                .service(
+                    web::scope("/admin")
+                        .wrap(auth.scoped().require(HasRole::new("admin")))
+                        .route("/hello", web::get().to(hello)),
+                )
+                .service(
+                    web::scope("/app")
+                        .wrap(auth.scoped())
+                        .route("/hello", web::get().to(hello)),
+                )
+                .service(
+                    web::scope("")
+                        .wrap(auth.scoped().should_auth(|_| false))
+                        .route("/", web::get().to(home)),
+                ),
+        )
+        .await
+    }};
+}
+
+#[actix_web::test]
+async fn scopes_authenticate_with_their_own_requirements() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, AuthenticatedUserBuilder::new("alice")).await;
+    let app = scoped_app!(openid);
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/").send().await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "anonymous");
+
+    let resp = driver.get("/app/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+
+    let resp = driver.get("/admin/hello").send().await;
+    assert_eq!(resp.status(), 403);
+    let resp = driver.get("/").send().await;
+    assert_eq!(resp.body(), "alice");
+}
+
+#[actix_web::test]
+async fn logins_started_in_a_scope_complete_at_the_app_callback() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, AuthenticatedUserBuilder::new("alice").role("admin")).await;
+    let app = scoped_app!(openid);
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/admin/hello").send().await;
+    assert_eq!(resp.status(), 302);
+    assert!(resp
+        .location()
+        .unwrap()
+        .starts_with(&format!("{}/authorize", idp.issuer_url())));
+
+    let resp = driver.get("/admin/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "hello");
+}
+
+#[actix_web::test]
+async fn required_policies_send_anonymous_requests_to_the_login() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, AuthenticatedUserBuilder::new("alice").role("auditor")).await;
+    let auth = openid.get_middleware();
+    let app = test::init_service(
+        App::new().configure(openid.configure_open_id()).service(
+            web::scope("/reports")
+                .wrap(
+                    auth.scoped()
+                        .should_auth(|_| false)
+                        .require(HasRole::new("auditor")),
+                )
+                .route("/hello", web::get().to(hello)),
+        ),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let resp = driver.get("/reports/hello").send().await;
+    assert_eq!(resp.status(), 302);
+
+    let resp = driver.get("/reports/hello").follow_login().await;
+    assert_eq!(resp.status(), 200);
+}
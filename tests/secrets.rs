@@ -0,0 +1,64 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::test_util::{FlowDriver, MockIdp};
+use actix_web_openidconnect::{
+    ActixWebOpenId, ForwardAuth, IdentityHeaders, SessionToken, TokenSource,
+};
+use openidconnect::RefreshToken;
+
+const CLIENT_SECRET: &str = "client-secret-value";
+const SIGNING_KEY: &str = "signing-key-value";
+const REFRESH_TOKEN: &str = "refresh-token-value";
+
+#[get("/is_auth/token")]
+async fn token(token: SessionToken) -> HttpResponse {
+    let access_token = token.access_token().await;
+    HttpResponse::Ok().body(format!("{:?}\n{}", token, access_token.secret()))
+}
+
+fn assert_redacted(debug: &str, secrets: &[&str]) {
+    for secret in secrets {
+        assert!(!debug.contains(secret), "{} leaks {}", debug, secret);
+    }
+}
+
+#[actix_web::test]
+async fn debug_output_contains_no_secrets() {
+    let idp = MockIdp::start();
+    let builder = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret(CLIENT_SECRET)
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback");
+    let debug = format!("{:?}", builder);
+    assert!(debug.contains("client"));
+    assert_redacted(&debug, &[CLIENT_SECRET]);
+
+    let openid = builder.build().await.unwrap();
+    assert_redacted(&format!("{:?}", openid), &[CLIENT_SECRET]);
+    assert_redacted(&format!("{:?}", openid.openid_client()), &[CLIENT_SECRET]);
+
+    let identity = IdentityHeaders::default().sign(SIGNING_KEY);
+    assert_redacted(&format!("{:?}", identity), &[SIGNING_KEY]);
+    let forward_auth = ForwardAuth::new(["app.example.com"]).identity_headers(identity);
+    assert_redacted(&format!("{:?}", forward_auth), &[SIGNING_KEY]);
+
+    let source = TokenSource::RefreshToken(RefreshToken::new(REFRESH_TOKEN.to_string()));
+    assert_redacted(&format!("{:?}", source), &[REFRESH_TOKEN]);
+    let provider = openid.openid_client().token_provider(source);
+    assert_redacted(&format!("{:?}", provider), &[REFRESH_TOKEN]);
+
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(token),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+    let resp = driver.get("/is_auth/token").follow_login().await;
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    let (debug, access_token) = body.split_once('\n').unwrap();
+    let refresh_token = driver.cookie("refresh_token").unwrap();
+    assert_redacted(debug, &[access_token, &refresh_token]);
+}
@@ -0,0 +1,81 @@
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{ActixWebOpenId, IssuerValidation, OpenIdBuilder, SecurityCheck};
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+}
+
+fn checks(openid: &ActixWebOpenId) -> Vec<SecurityCheck> {
+    openid
+        .security_report()
+        .into_iter()
+        .map(|finding| finding.check)
+        .collect()
+}
+
+#[actix_web::test]
+async fn http_issuer_is_reported() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp).build().await.unwrap();
+
+    assert_eq!(checks(&openid), vec![SecurityCheck::HttpIssuer]);
+}
+
+#[actix_web::test]
+async fn skipped_issuer_validation_is_reported() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp)
+        .issuer_validation(IssuerValidation::Skip)
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        checks(&openid),
+        vec![SecurityCheck::HttpIssuer, SecurityCheck::IssuerNotValidated]
+    );
+}
+
+#[actix_web::test]
+async fn public_clients_without_pkce_are_reported() {
+    let idp = MockIdp::start();
+    let public = || {
+        ActixWebOpenId::builder()
+            .client_id("client")
+            .redirect_url("http://localhost/auth_callback")
+            .issuer_url(idp.issuer_url())
+    };
+
+    let without_pkce = public().pkce(false).build().await.unwrap();
+    let with_pkce = public().build().await.unwrap();
+
+    assert!(without_pkce.openid_client().is_public_client());
+    assert_eq!(
+        checks(&without_pkce),
+        vec![
+            SecurityCheck::HttpIssuer,
+            SecurityCheck::PublicClientWithoutPkce
+        ]
+    );
+    assert_eq!(checks(&with_pkce), vec![SecurityCheck::HttpIssuer]);
+}
+
+#[cfg(debug_assertions)]
+#[actix_web::test]
+async fn findings_do_not_fail_debug_builds_in_strict_mode() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp)
+        .scopes(["offline_access"])
+        .strict(true)
+        .build()
+        .await;
+
+    assert!(openid.is_ok());
+}
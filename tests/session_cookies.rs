@@ -0,0 +1,94 @@
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, InMemorySessionStore, OpenIdBuilder};
+
+#[get("/orders")]
+async fn orders(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().to_string())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/orders"))
+}
+
+async fn call_orders(openid: &ActixWebOpenId, request: test::TestRequest) -> (u16, String) {
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(orders),
+    )
+    .await;
+    match test::try_call_service(&app, request.to_request()).await {
+        Ok(resp) => {
+            let status = resp.status().as_u16();
+            let body = test::read_body(resp).await;
+            (status, String::from_utf8(body.to_vec()).unwrap())
+        }
+        Err(err) => (err.error_response().status().as_u16(), String::new()),
+    }
+}
+
+#[actix_web::test]
+async fn issued_cookies_authenticate_test_requests() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+
+    let cookies = idp
+        .issue_session_cookies(&openid, AuthenticatedUserBuilder::new("alice"))
+        .await;
+    let request = cookies
+        .into_iter()
+        .fold(test::TestRequest::get().uri("/orders"), |req, cookie| {
+            req.cookie(cookie)
+        });
+
+    assert_eq!(
+        call_orders(&openid, request).await,
+        (200, "alice".to_string())
+    );
+}
+
+#[actix_web::test]
+async fn issued_cookies_name_stored_sessions() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .session_store(InMemorySessionStore::default())
+        .build()
+        .await
+        .unwrap();
+
+    let cookies = idp
+        .issue_session_cookies(&openid, AuthenticatedUserBuilder::new("bob"))
+        .await;
+
+    assert!(cookies.iter().any(|cookie| cookie.name() == "session_id"));
+    assert!(cookies.iter().all(|cookie| cookie.name() != "access_token"));
+    let request = cookies
+        .into_iter()
+        .fold(test::TestRequest::get().uri("/orders"), |req, cookie| {
+            req.cookie(cookie)
+        });
+    assert_eq!(
+        call_orders(&openid, request).await,
+        (200, "bob".to_string())
+    );
+}
+
+#[actix_web::test]
+async fn requests_without_the_cookies_are_sent_to_the_provider() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    idp.issue_session_cookies(&openid, AuthenticatedUserBuilder::new("alice"))
+        .await;
+
+    let (status, _) = call_orders(&openid, test::TestRequest::get().uri("/orders")).await;
+
+    assert_eq!(status, 302);
+}
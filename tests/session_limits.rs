@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{
+    ActixWebOpenId, AuthEvents, CallbackFailure, EventContext, InMemorySessionStore, OpenIdBuilder,
+    OpenIdError,
+};
+use serde_json::json;
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+}
+
+/// Sessions kept in sealed cookies.
+fn cookie_builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    builder(idp, clock).cookie_key(Key::derive_from(&[7; 32]))
+}
+
+/// Records the reasons of failed callbacks.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<CallbackFailure>>>);
+
+impl Recorder {
+    fn failures(&self) -> Vec<CallbackFailure> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl AuthEvents for Recorder {
+    fn on_callback_failure(&self, _: &EventContext<'_>, reason: CallbackFailure) {
+        self.0.lock().unwrap().push(reason);
+    }
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[actix_web::test]
+async fn idle_sessions_end() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = cookie_builder(&idp, &clock)
+        .session_idle_timeout(10 * MINUTE)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    driver.assert_authenticated();
+
+    clock.advance(11 * MINUTE);
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+    driver.assert_unauthenticated();
+    assert_eq!(driver.cookie("session_times"), None);
+}
+
+#[actix_web::test]
+async fn requests_keep_sessions_from_idling() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = cookie_builder(&idp, &clock)
+        .session_idle_timeout(10 * MINUTE)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    for _ in 0..4 {
+        clock.advance(8 * MINUTE);
+        let resp = driver.get("/is_auth/hello").send().await;
+        assert_eq!(resp.status(), 200);
+    }
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn sessions_end_after_the_absolute_lifetime_whatever_the_refreshes() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = cookie_builder(&idp, &clock)
+        .session_absolute_lifetime(20 * MINUTE)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    // The access tokens live 5 minutes, each request refreshes the session.
+    for _ in 0..3 {
+        clock.advance(6 * MINUTE);
+        let resp = driver.get("/is_auth/hello").send().await;
+        assert_eq!(resp.status(), 200);
+    }
+    clock.advance(3 * MINUTE);
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn logins_the_provider_remembered_for_too_long_are_refused() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let recorder = Recorder::default();
+    let openid = cookie_builder(&idp, &clock)
+        .session_absolute_lifetime(60 * MINUTE)
+        .auth_events(recorder.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let login = driver.get("/is_auth/hello").send().await;
+    assert!(login.location().unwrap().contains("max_age=3600"));
+
+    let two_hours_ago = SystemTime::now() - 120 * MINUTE;
+    let auth_time = two_hours_ago.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    idp.set_id_token_claim("auth_time", json!(auth_time));
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 401);
+    assert!(
+        String::from_utf8_lossy(resp.body()).contains("login at the identity provider is too old")
+    );
+    assert_eq!(recorder.failures(), [CallbackFailure::StaleLogin]);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn session_times_past_the_clock_end_the_session() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = cookie_builder(&idp, &clock)
+        .session_idle_timeout(10 * MINUTE)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let times = format!("{}.{}", u64::MAX, u64::MAX);
+    let mut jar = CookieJar::new();
+    jar.private_mut(&Key::derive_from(&[7; 32]))
+        .add(Cookie::new("session_times", times));
+    driver.set_cookie(jar.get("session_times").unwrap().clone());
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn stored_sessions_need_no_cookie_key() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let store = Arc::new(InMemorySessionStore::default());
+
+    let err = builder(&idp, &clock)
+        .session_idle_timeout(10 * MINUTE)
+        .build()
+        .await
+        .unwrap_err();
+    assert!(matches!(err, OpenIdError::Config(_)), "{:?}", err);
+
+    let openid = builder(&idp, &clock)
+        .session_store(store)
+        .session_idle_timeout(10 * MINUTE)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    clock.advance(8 * MINUTE);
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+    clock.advance(8 * MINUTE);
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+    clock.advance(11 * MINUTE);
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 302);
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn optional_routes_serve_ended_sessions_anonymously() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = cookie_builder(&idp, &clock)
+        .session_idle_timeout(10 * MINUTE)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    clock.advance(11 * MINUTE);
+    let resp = driver.get("/no_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_unauthenticated();
+}
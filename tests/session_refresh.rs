@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use actix_web::cookie::Cookie;
+use actix_web::http::Method;
+use actix_web::{post, test, App, HttpResponse};
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, SessionToken};
+
+mod mock_auth_api;
+
+#[post("/is_auth/orders")]
+async fn create_order(token: SessionToken, body: String) -> HttpResponse {
+    let access_token = token.access_token().await;
+    HttpResponse::Ok().body(format!("{}\n{}", access_token.secret(), body))
+}
+
+async fn openid(idp: &MockIdp, clock: &MockClock) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap()
+}
+
+#[actix_web::test]
+async fn rejected_access_tokens_are_renewed() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, &MockClock::new()).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let refresh_token = driver.cookie("refresh_token").unwrap();
+
+    idp.revoke_access_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_ne!(driver.cookie("access_token").unwrap(), access_token);
+    assert_ne!(driver.cookie("refresh_token").unwrap(), refresh_token);
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert_eq!(resp.status(), 200);
+}
+
+#[actix_web::test]
+async fn sessions_are_renewed_shortly_before_the_token_expires() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(&idp, &clock).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+    assert_eq!(driver.cookie("access_token").unwrap(), access_token);
+
+    // The provider's tokens live 5 minutes.
+    clock.advance(Duration::from_secs(3 * 60 + 30));
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+    assert_ne!(driver.cookie("access_token").unwrap(), access_token);
+}
+
+#[actix_web::test]
+async fn expired_sessions_are_renewed_for_the_current_request() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(&idp, &clock).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(create_order),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+    // Logins return to pages, not to forms.
+    driver.get("/is_auth/orders").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    clock.advance(Duration::from_secs(10 * 60));
+    let resp = driver
+        .request(Method::POST, "/is_auth/orders")
+        .header("content-type", "text/plain")
+        .body("two pizzas")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 200);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    let (used_token, order) = body.split_once('\n').unwrap();
+    assert_eq!(order, "two pizzas");
+    assert_ne!(used_token, access_token);
+    assert_eq!(driver.cookie("access_token").unwrap(), used_token);
+}
+
+#[actix_web::test]
+async fn sessions_failing_to_renew_log_in_again() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, &MockClock::new()).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    idp.revoke_refresh_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn expiry_times_past_the_clock_count_as_expired() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, &MockClock::new()).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    driver.set_cookie(Cookie::new("access_token_expires_at", u64::MAX.to_string()));
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_ne!(driver.cookie("access_token").unwrap(), access_token);
+}
+
+#[actix_web::test]
+async fn logins_without_a_refresh_token_drop_the_earlier_one() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(&idp, &clock).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    assert!(driver.cookie("refresh_token").is_some());
+
+    // Switching to an account the provider issues no refresh token for.
+    idp.login_as(AuthenticatedUserBuilder::new("bob").preferred_username("bob"));
+    idp.set_login_refresh_tokens(false);
+    let resp = driver
+        .get("/login?next=/is_auth/hello&prompt=select_account")
+        .follow_login()
+        .await;
+    assert_eq!(resp.status(), 200);
+    assert!(String::from_utf8_lossy(resp.body()).contains("\"bob\""));
+    assert_eq!(driver.cookie("refresh_token"), None);
+
+    // Once the access token expires, the session does not turn back into alice's.
+    clock.advance(Duration::from_secs(6 * 60));
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
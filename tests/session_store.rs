@@ -0,0 +1,161 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{
+    ActixWebOpenId, InMemorySessionStore, OpenIdBuilder, OpenIdError, SessionStore, StoredSession,
+};
+use serde_json::Value;
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+async fn stored(store: &InMemorySessionStore, session_id: &str) -> Option<Value> {
+    let session = store.get(session_id).await.unwrap()?;
+    Some(serde_json::to_value(&session).unwrap())
+}
+
+#[actix_web::test]
+async fn sessions_are_kept_in_the_store() {
+    let idp = MockIdp::start();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp)
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+    for token_cookie in ["access_token", "id_token", "refresh_token", "user_info"] {
+        assert_eq!(driver.cookie(token_cookie), None);
+    }
+    let session_id = driver.cookie("session_id").unwrap();
+    let session = stored(&store, &session_id).await.unwrap();
+    assert_eq!(session["claims"]["sub"], "alice");
+    assert!(session["refresh_token"].is_string());
+    let debug = format!("{:?}", store.get(&session_id).await.unwrap());
+    assert!(!debug.contains(session["access_token"].as_str().unwrap()));
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+}
+
+#[actix_web::test]
+async fn logout_removes_the_stored_session() {
+    let idp = MockIdp::start();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp)
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session_id = driver.cookie("session_id").unwrap();
+
+    driver.get("/logout/local").send().await;
+
+    assert!(stored(&store, &session_id).await.is_none());
+    driver.assert_unauthenticated();
+}
+
+#[actix_web::test]
+async fn refreshed_tokens_replace_the_stored_ones() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp)
+        .clock(clock.clone())
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session_id = driver.cookie("session_id").unwrap();
+    let before = stored(&store, &session_id).await.unwrap();
+
+    clock.advance(Duration::from_secs(10 * 60));
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(driver.cookie("session_id").unwrap(), session_id);
+    let after = stored(&store, &session_id).await.unwrap();
+    assert_ne!(after["access_token"], before["access_token"]);
+    assert_eq!(after["claims"], before["claims"]);
+}
+
+/// Keeps the sessions it is given but cannot read them back, like a store gone down.
+struct Unreachable(InMemorySessionStore);
+
+#[async_trait::async_trait]
+impl SessionStore for Unreachable {
+    async fn get(
+        &self,
+        _session_id: &str,
+    ) -> actix_web_openidconnect::Result<Option<StoredSession>> {
+        Err(OpenIdError::SessionStore("connection refused".into()))
+    }
+
+    async fn insert(
+        &self,
+        session_id: &str,
+        session: StoredSession,
+    ) -> actix_web_openidconnect::Result<()> {
+        self.0.insert(session_id, session).await
+    }
+
+    async fn remove(&self, session_id: &str) -> actix_web_openidconnect::Result<()> {
+        self.0.remove(session_id).await
+    }
+}
+
+#[actix_web::test]
+async fn unreachable_stores_answer_service_unavailable() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .session_store(Unreachable(InMemorySessionStore::default()))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 503);
+    assert!(resp.headers().contains_key("retry-after"));
+}
+
+#[actix_web::test]
+async fn sessions_expiring_past_the_clock_are_not_read() {
+    let idp = MockIdp::start();
+    let store = Arc::new(InMemorySessionStore::default());
+    let openid = builder(&idp)
+        .session_store(store.clone())
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session_id = driver.cookie("session_id").unwrap();
+    let mut session = stored(&store, &session_id).await.unwrap();
+    assert!(serde_json::from_value::<StoredSession>(session.clone()).is_ok());
+
+    session["access_token_expires_at"] = u64::MAX.into();
+    let err = serde_json::from_value::<StoredSession>(session).unwrap_err();
+
+    assert!(err.to_string().contains("out of range"), "{}", err);
+}
@@ -0,0 +1,77 @@
+use actix_web::{test, web, App, HttpResponse};
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder, ShouldAuth};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+}
+
+async fn hello() -> HttpResponse {
+    HttpResponse::Ok().body("hello")
+}
+
+async fn status(openid: &ActixWebOpenId, idp: &MockIdp, path: &str) -> u16 {
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .default_service(web::to(hello)),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, idp);
+    driver.get(path).send().await.status().as_u16()
+}
+
+#[actix_web::test]
+async fn closures_capture_their_configuration() {
+    let idp = MockIdp::start();
+    // As if read from a configuration file.
+    let public_paths: Vec<String> = "/no_auth/hello,/health"
+        .split(',')
+        .map(String::from)
+        .collect();
+    let openid = builder(&idp)
+        .should_auth(move |req| !public_paths.iter().any(|path| path == req.path()))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    assert_eq!(driver.get("/no_auth/hello").send().await.status(), 200);
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 302);
+}
+
+#[actix_web::test]
+async fn prefixes_match_whole_segments() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .should_auth(ShouldAuth::except_prefixes(["/health", "/public/"]))
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(status(&openid, &idp, "/health").await, 200);
+    assert_eq!(status(&openid, &idp, "/public/logo.png").await, 200);
+    assert_eq!(status(&openid, &idp, "/public").await, 200);
+    assert_eq!(status(&openid, &idp, "/publications").await, 302);
+    assert_eq!(status(&openid, &idp, "/healthz").await, 302);
+}
+
+#[actix_web::test]
+async fn always_requires_a_login() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .should_auth(ShouldAuth::always())
+        .build()
+        .await
+        .unwrap();
+
+    assert_eq!(status(&openid, &idp, "/health").await, 302);
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+use openidconnect::{AccessToken, EmptyAdditionalClaims};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .preferred_username("alice")
+            .email("alice@example.com"),
+    );
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+#[actix_web::test]
+async fn signed_userinfo_responses_authenticate_users() {
+    let idp = MockIdp::start();
+    idp.set_signed_userinfo(true);
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let body = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert!(body.contains("\"alice\""), "{}", body);
+    assert!(body.contains("alice@example.com"), "{}", body);
+}
+
+#[actix_web::test]
+async fn plain_userinfo_is_refused_when_signed_responses_are_required() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = AccessToken::new(driver.cookie("access_token").unwrap());
+    let strict = builder(&idp)
+        .require_signed_userinfo(true)
+        .build()
+        .await
+        .unwrap();
+
+    let plain = openid
+        .openid_client()
+        .user_info::<EmptyAdditionalClaims>(access_token.clone())
+        .await;
+    let refused = strict
+        .openid_client()
+        .user_info::<EmptyAdditionalClaims>(access_token)
+        .await;
+
+    assert_eq!(plain.unwrap().subject().as_str(), "alice");
+    assert!(refused.is_err());
+}
+
+#[actix_web::test]
+async fn signed_userinfo_passes_when_required() {
+    let idp = MockIdp::start();
+    idp.set_signed_userinfo(true);
+    let openid = builder(&idp)
+        .require_signed_userinfo(true)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let claims = openid
+        .openid_client()
+        .user_info::<EmptyAdditionalClaims>(AccessToken::new(
+            driver.cookie("access_token").unwrap(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(claims.subject().as_str(), "alice");
+}
+
+#[actix_web::test]
+async fn signed_userinfo_with_a_rotated_key_refetches_the_keys() {
+    let idp = MockIdp::start();
+    idp.set_signed_userinfo(true);
+    let clock = MockClock::new();
+    let openid = builder(&idp).clock(clock.clone()).build().await.unwrap();
+    let client = openid.openid_client();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    clock.advance(Duration::from_secs(60));
+    idp.rotate_signing_key();
+    let claims = client
+        .user_info::<EmptyAdditionalClaims>(AccessToken::new(access_token))
+        .await
+        .unwrap();
+
+    assert_eq!(claims.subject().as_str(), "alice");
+}
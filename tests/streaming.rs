@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use actix_web::cookie::Key;
+use actix_web::dev::Service;
+use actix_web::error::{ErrorBadRequest, ErrorForbidden};
+use actix_web::http::header::CONTENT_LENGTH;
+use actix_web::web::Bytes;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+use futures_util::future::{ready, Either};
+use futures_util::stream;
+
+fn builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+}
+
+#[get("/is_auth/stream")]
+async fn streamed(_user: Authenticated) -> HttpResponse {
+    let chunks = ["first,", "second,", "third"]
+        .map(|chunk| Ok::<_, actix_web::Error>(Bytes::from_static(chunk.as_bytes())));
+    HttpResponse::Ok().streaming(stream::iter(chunks))
+}
+
+#[get("/is_auth/failing")]
+async fn failing(_user: Authenticated) -> actix_web::Result<HttpResponse> {
+    Err(ErrorBadRequest("failing"))
+}
+
+#[get("/is_auth/refused")]
+async fn refused() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+async fn app_with(
+    openid: &ActixWebOpenId,
+) -> impl Service<
+    actix_http::Request,
+    Response = actix_web::dev::ServiceResponse,
+    Error = actix_web::Error,
+> {
+    test::init_service(
+        App::new()
+            // Inside the OpenID middleware, failing the requests it lets through.
+            .wrap_fn(|req, srv| match req.path() {
+                "/is_auth/refused" => Either::Left(ready(Err(ErrorForbidden("refused")))),
+                _ => Either::Right(srv.call(req)),
+            })
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(streamed)
+            .service(failing)
+            .service(refused),
+    )
+    .await
+}
+
+#[actix_web::test]
+async fn refreshed_cookies_arrive_with_streamed_bodies() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(app_with(&openid).await, &idp);
+    driver.get("/is_auth/stream").follow_login().await;
+    let access_token = driver.cookie("access_token");
+
+    // Past the lifetime of the access token, the session is refreshed.
+    clock.advance(Duration::from_secs(6 * 60));
+    let resp = driver.get("/is_auth/stream").send().await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.body(), "first,second,third");
+    assert!(resp.headers().get(CONTENT_LENGTH).is_none());
+    assert!(resp.cookies().any(|cookie| cookie.name() == "access_token"));
+    assert_ne!(driver.cookie("access_token"), access_token);
+}
+
+#[actix_web::test]
+async fn streamed_requests_keep_sessions_from_idling() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock)
+        .cookie_key(Key::derive_from(&[7; 32]))
+        .session_idle_timeout(Duration::from_secs(10 * 60))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(app_with(&openid).await, &idp);
+    driver.get("/is_auth/stream").follow_login().await;
+
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(4 * 60));
+        let resp = driver.get("/is_auth/stream").send().await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.body(), "first,second,third");
+        assert!(resp
+            .cookies()
+            .any(|cookie| cookie.name() == "session_times"));
+    }
+    driver.assert_authenticated();
+}
+
+#[actix_web::test]
+async fn failing_handlers_still_store_refreshed_tokens() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(app_with(&openid).await, &idp);
+    driver.get("/is_auth/stream").follow_login().await;
+    let access_token = driver.cookie("access_token");
+
+    clock.advance(Duration::from_secs(6 * 60));
+    let resp = driver.get("/is_auth/failing").send().await;
+
+    assert_eq!(resp.status(), 400);
+    assert_ne!(driver.cookie("access_token"), access_token);
+}
+
+#[actix_web::test]
+async fn errors_of_inner_services_still_store_refreshed_tokens() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let mut driver = FlowDriver::new(app_with(&openid).await, &idp);
+    driver.get("/is_auth/stream").follow_login().await;
+    let access_token = driver.cookie("access_token");
+
+    clock.advance(Duration::from_secs(6 * 60));
+    let resp = driver.get("/is_auth/refused").send().await;
+
+    assert_eq!(resp.status(), 403);
+    assert_eq!(resp.body(), "refused");
+    assert_ne!(driver.cookie("access_token"), access_token);
+
+    // The refreshed session goes on.
+    let resp = driver.get("/is_auth/stream").send().await;
+    assert_eq!(resp.status(), 200);
+    driver.assert_authenticated();
+}
@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::rt::time::sleep;
+use actix_web::{test, App, HttpResponse};
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{ActixWebOpenId, HealthThresholds, TaskSet};
+
+#[actix_web::test]
+async fn tasks_are_stopped_within_the_grace_period() {
+    let tasks = TaskSet::new();
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let counter = stopped.clone();
+    tasks.add("cooperative", move |mut shutdown| async move {
+        shutdown.requested().await;
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    let counter = stopped.clone();
+    tasks.add("stubborn", move |_| async move {
+        sleep(Duration::from_secs(3600)).await;
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    assert!(tasks.start());
+    sleep(Duration::from_millis(10)).await;
+
+    let started = Instant::now();
+    tasks.shutdown_within(Duration::from_millis(200)).await;
+
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert_eq!(stopped.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn in_flight_writes_complete_before_the_shutdown_returns() {
+    let tasks = TaskSet::new();
+    let store = Arc::new(Mutex::new(Vec::new()));
+    let writing = Arc::new(AtomicUsize::new(0));
+    let (task_store, task_writing) = (store.clone(), writing.clone());
+    tasks.add("writer", move |shutdown| async move {
+        while !shutdown.is_requested() {
+            task_writing.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(100)).await;
+            task_store.lock().unwrap().push("session");
+        }
+    });
+    tasks.start();
+    while writing.load(Ordering::SeqCst) == 0 {
+        sleep(Duration::from_millis(5)).await;
+    }
+
+    tasks.shutdown().await;
+
+    let writes = writing.load(Ordering::SeqCst);
+    assert_eq!(store.lock().unwrap().len(), writes);
+}
+
+#[actix_web::test]
+async fn tasks_are_only_added_and_started_once() {
+    let tasks = TaskSet::new();
+    let runs = Arc::new(AtomicUsize::new(0));
+    for _ in 0..2 {
+        let runs = runs.clone();
+        tasks.add("once", move |_| async move {
+            runs.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    assert!(tasks.start());
+    assert!(tasks.start());
+    tasks.shutdown().await;
+
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    // Nothing starts after the shutdown.
+    let late = runs.clone();
+    tasks.add("late", move |_| async move {
+        late.fetch_add(1, Ordering::SeqCst);
+    });
+    tasks.start();
+    assert!(tasks.futures().is_empty());
+    sleep(Duration::from_millis(10)).await;
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn the_middleware_refreshes_the_provider_in_the_background() {
+    let idp = MockIdp::start();
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|_| false)
+        .health_thresholds(HealthThresholds::default().max_jwks_age(Duration::from_millis(300)))
+        .build()
+        .await
+        .unwrap();
+    let middleware = openid
+        .get_middleware()
+        .refresh_provider_every(Duration::from_millis(50));
+    let tasks = middleware.tasks().clone();
+    let _app = test::init_service(
+        App::new()
+            .wrap(middleware)
+            .default_service(actix_web::web::to(HttpResponse::Ok)),
+    )
+    .await;
+
+    sleep(Duration::from_millis(500)).await;
+    assert!(openid.openid_client().health().healthy);
+
+    tasks.shutdown().await;
+    sleep(Duration::from_millis(500)).await;
+    assert!(!openid.openid_client().health().healthy);
+}
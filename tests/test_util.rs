@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use actix_web::{get, test, web, App, HttpResponse, Responder};
+use actix_web_openidconnect::openid_middleware::{
+    Authenticated, AuthenticatedUser, MaybeAuthenticated,
+};
+use actix_web_openidconnect::test_util::{authenticate_request, AuthenticatedUserBuilder};
+use actix_web_openidconnect::{HasRole, RequireClaims};
+
+#[get("/orders")]
+async fn orders(user: Authenticated) -> impl Responder {
+    HttpResponse::Ok().body(format!(
+        "orders of {} ({})",
+        user.access.subject().as_str(),
+        user.access.email().unwrap().as_str()
+    ))
+}
+
+#[get("/maybe")]
+async fn maybe(user: MaybeAuthenticated) -> impl Responder {
+    let user: Option<&AuthenticatedUser> = (&user).into();
+    HttpResponse::Ok().body(match user {
+        Some(user) => user.access.subject().to_string(),
+        None => "anonymous".to_string(),
+    })
+}
+
+#[get("/shared")]
+async fn shared(first: Authenticated, second: MaybeAuthenticated) -> impl Responder {
+    let second = second.into_inner().unwrap();
+    HttpResponse::Ok().body(Arc::ptr_eq(&first.into_inner(), &second).to_string())
+}
+
+#[actix_web::test]
+async fn handler_sees_fabricated_user() {
+    let app = test::init_service(App::new().service(orders)).await;
+    let req = authenticate_request(
+        test::TestRequest::get().uri("/orders").to_request(),
+        AuthenticatedUserBuilder::new("sub")
+            .email("a@b.c")
+            .role("admin"),
+    );
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 200);
+    assert_eq!(test::read_body(resp).await, "orders of sub (a@b.c)");
+}
+
+#[actix_web::test]
+async fn maybe_authenticated_sees_fabricated_user() {
+    let app = test::init_service(App::new().service(maybe)).await;
+    let req = authenticate_request(
+        test::TestRequest::get().uri("/maybe").to_request(),
+        AuthenticatedUserBuilder::new("alice"),
+    );
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(test::read_body(resp).await, "alice");
+}
+
+#[actix_web::test]
+async fn extractors_share_the_user() {
+    let app = test::init_service(App::new().service(shared)).await;
+    let req = authenticate_request(
+        test::TestRequest::get().uri("/shared").to_request(),
+        AuthenticatedUserBuilder::new("alice"),
+    );
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(test::read_body(resp).await, "true");
+}
+
+#[actix_web::test]
+async fn unauthenticated_request_is_rejected() {
+    let app = test::init_service(App::new().service(orders)).await;
+    let req = test::TestRequest::get().uri("/orders").to_request();
+
+    let resp = test::call_service(&app, req).await;
+
+    assert_eq!(resp.status(), 401);
+}
+
+#[actix_web::test]
+async fn builder_sets_standard_claims() {
+    let user = AuthenticatedUserBuilder::new("bob")
+        .name("Bob")
+        .preferred_username("bobby")
+        .email("bob@example.com")
+        .build();
+
+    assert_eq!(user.access.subject().as_str(), "bob");
+    assert_eq!(user.access.preferred_username().unwrap().as_str(), "bobby");
+    assert_eq!(user.access.email().unwrap().as_str(), "bob@example.com");
+    assert_eq!(user.access.email_verified(), Some(true));
+    assert_eq!(
+        user.access.name().unwrap().get(None).unwrap().as_str(),
+        "Bob"
+    );
+}
+
+#[actix_web::test]
+async fn built_users_keep_their_roles() {
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/admin")
+                .wrap(RequireClaims::new(HasRole::new("admin").claim("roles")))
+                .service(orders),
+        ),
+    )
+    .await;
+    let admin: AuthenticatedUser = AuthenticatedUserBuilder::new("sub")
+        .email("a@b.c")
+        .role("admin")
+        .build();
+    let viewer: AuthenticatedUser = AuthenticatedUserBuilder::new("sub")
+        .email("a@b.c")
+        .role("viewer")
+        .build();
+
+    let req = test::TestRequest::get().uri("/admin/orders").to_request();
+    let resp = test::call_service(&app, authenticate_request(req, admin)).await;
+    assert_eq!(resp.status(), 200);
+
+    let req = test::TestRequest::get().uri("/admin/orders").to_request();
+    let err = test::try_call_service(&app, authenticate_request(req, viewer))
+        .await
+        .unwrap_err();
+    assert_eq!(err.as_response_error().status_code(), 403);
+}
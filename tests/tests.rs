@@ -21,7 +21,7 @@ struct OidcEndpointsMock<'a> {
     keys_endpoint_mock: Mock<'a>,
 }
 
-fn build_oidc_mock_server(server: &MockServer) -> OidcEndpointsMock {
+fn build_oidc_mock_server(server: &MockServer) -> OidcEndpointsMock<'_> {
     let metadata_endpoint_mock = server.mock(|when, then| {
         when.method(GET)
             .path("/realms/my_realm/.well-known/openid-configuration");
@@ -110,9 +110,18 @@ async fn test_add() {
             .await;
     assert_eq!(resp.status, 302);
     resp.headers.get("location").unwrap();
-    let query = Url::parse(resp.headers.get("location").unwrap().to_str().unwrap())
-        .unwrap()
-        .query_pairs();
+    let location = Url::parse(resp.headers.get("location").unwrap().to_str().unwrap()).unwrap();
+    let state = location
+        .query_pairs()
+        .find(|(key, _)| key == "state")
+        .map(|(_, value)| value.to_string());
+    assert_eq!(
+        state
+            .as_deref()
+            .and_then(|state| state.split_once('|'))
+            .map(|(_, path)| path),
+        Some("/is_auth/hello")
+    );
     let body = actix_web::body::to_bytes(resp.body).await.unwrap();
     println!("body: {:?}", body);
 }
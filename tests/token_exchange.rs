@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use actix_web::{get, test, web, App, HttpResponse};
+use actix_web_openidconnect::openid::OpenID;
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder, OpenIdError};
+use openidconnect::{AccessToken, EmptyAdditionalClaims, Scope};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp, clock: &MockClock) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+}
+
+/// The access token of a login at the provider.
+async fn access_token(openid: &ActixWebOpenId, idp: &MockIdp) -> AccessToken {
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(openid).await, idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    AccessToken::new(driver.cookie("access_token").unwrap())
+}
+
+fn exchanges(idp: &MockIdp) -> usize {
+    idp.token_requests()
+        .iter()
+        .filter(|request| request.grant_type.ends_with(":token-exchange"))
+        .count()
+}
+
+#[get("/is_auth/stock")]
+async fn stock(user: Authenticated, openid: web::Data<OpenID>) -> actix_web::Result<HttpResponse> {
+    let token = user.token_for("api://inventory", &openid).await?;
+    Ok(HttpResponse::Ok().body(token.access_token.secret().clone()))
+}
+
+#[actix_web::test]
+async fn tokens_are_exchanged_for_other_audiences() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let client = openid.openid_client();
+    let access_token = access_token(&openid, &idp).await;
+
+    let token = client
+        .exchange_token(
+            &access_token,
+            "api://inventory",
+            &[Scope::new("stock:read".to_string())],
+        )
+        .await
+        .unwrap();
+
+    assert_ne!(token.access_token.secret(), access_token.secret());
+    assert_eq!(
+        token.issued_token_type,
+        "urn:ietf:params:oauth:token-type:access_token"
+    );
+    assert_eq!(
+        token.scopes,
+        Some(vec![Scope::new("stock:read".to_string())])
+    );
+    assert!(!format!("{:?}", token).contains(token.access_token.secret()));
+    let claims = client
+        .user_info::<EmptyAdditionalClaims>(token.access_token)
+        .await
+        .unwrap();
+    assert_eq!(claims.subject().as_str(), "alice");
+}
+
+#[actix_web::test]
+async fn exchanged_tokens_are_cached_until_shortly_before_they_expire() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock).build().await.unwrap();
+    let client = openid.openid_client();
+    let access_token = access_token(&openid, &idp).await;
+
+    let first = client
+        .exchange_token(&access_token, "api://inventory", &[])
+        .await
+        .unwrap();
+    let second = client
+        .exchange_token(&access_token, "api://inventory", &[])
+        .await
+        .unwrap();
+    client
+        .exchange_token(&access_token, "api://billing", &[])
+        .await
+        .unwrap();
+    assert_eq!(first.access_token.secret(), second.access_token.secret());
+    assert_eq!(exchanges(&idp), 2);
+
+    // The provider's tokens live 5 minutes.
+    clock.advance(Duration::from_secs(4 * 60 + 1));
+    let renewed = client
+        .exchange_token(&access_token, "api://inventory", &[])
+        .await
+        .unwrap();
+    assert_ne!(renewed.access_token.secret(), first.access_token.secret());
+    assert_eq!(exchanges(&idp), 3);
+}
+
+#[actix_web::test]
+async fn providers_without_token_exchange_are_told_apart() {
+    let idp = MockIdp::start();
+    idp.set_token_exchange(false);
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let access_token = access_token(&openid, &idp).await;
+
+    let result = openid
+        .openid_client()
+        .exchange_token(&access_token, "api://inventory", &[])
+        .await;
+
+    assert!(
+        matches!(result, Err(OpenIdError::UnsupportedGrant(ref grant)) if grant.ends_with(":token-exchange")),
+        "{:?}",
+        result
+    );
+    assert_eq!(exchanges(&idp), 0);
+}
+
+#[actix_web::test]
+async fn unsupported_grant_type_answers_are_told_apart() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let access_token = access_token(&openid, &idp).await;
+
+    // Advertised in the discovery document read before.
+    idp.set_token_exchange(false);
+    let result = openid
+        .openid_client()
+        .exchange_token(&access_token, "api://inventory", &[])
+        .await;
+
+    assert!(
+        matches!(result, Err(OpenIdError::UnsupportedGrant(_))),
+        "{:?}",
+        result
+    );
+    assert_eq!(exchanges(&idp), 1);
+}
+
+#[actix_web::test]
+async fn refused_exchanges_carry_the_provider_error() {
+    let idp = MockIdp::start();
+    idp.set_token_exchange_audiences(&["api://inventory"]);
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let access_token = access_token(&openid, &idp).await;
+
+    let result = openid
+        .openid_client()
+        .exchange_token(&access_token, "api://billing", &[])
+        .await;
+
+    match result {
+        Err(OpenIdError::TokenExchange {
+            provider_error: Some(provider_error),
+            status: Some(400),
+            ..
+        }) => assert_eq!(provider_error.error, "invalid_target"),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[actix_web::test]
+async fn handlers_exchange_the_users_token() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp, &MockClock::new()).build().await.unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(stock),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+
+    let resp = driver.get("/is_auth/stock").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let token = String::from_utf8(resp.body().to_vec()).unwrap();
+    assert_ne!(Some(token), driver.cookie("access_token"));
+    assert_eq!(exchanges(&idp), 1);
+}
@@ -0,0 +1,60 @@
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockIdp, MockIdpFailure,
+};
+use actix_web_openidconnect::{ActixWebOpenId, TokenHashValidation};
+
+mod mock_auth_api;
+
+async fn openid(idp: &MockIdp, token_hash_validation: TokenHashValidation) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .token_hash_validation(token_hash_validation)
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn login_status(idp: &MockIdp, token_hash_validation: TokenHashValidation) -> u16 {
+    let openid = openid(idp, token_hash_validation).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, idp);
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+    if resp.status() != 200 {
+        driver.assert_unauthenticated();
+    }
+    resp.status().as_u16()
+}
+
+#[actix_web::test]
+async fn matching_token_hashes_are_accepted() {
+    let idp = MockIdp::start();
+
+    assert_eq!(login_status(&idp, TokenHashValidation::Strict).await, 200);
+}
+
+#[actix_web::test]
+async fn substituted_access_tokens_are_rejected() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::WrongTokenHashes));
+
+    assert_eq!(
+        login_status(&idp, TokenHashValidation::IfPresent).await,
+        400
+    );
+}
+
+#[actix_web::test]
+async fn missing_token_hashes_are_only_rejected_when_strict() {
+    let idp = MockIdp::start();
+    idp.set_failure(Some(MockIdpFailure::NoTokenHashes));
+
+    assert_eq!(
+        login_status(&idp, TokenHashValidation::IfPresent).await,
+        200
+    );
+    assert_eq!(login_status(&idp, TokenHashValidation::Strict).await, 400);
+}
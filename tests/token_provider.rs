@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::test_util::{FlowDriver, MockClock, MockIdp, MockIdpFailure};
+use actix_web_openidconnect::{ActixWebOpenId, TokenSource, TokenStatus};
+use openidconnect::RefreshToken;
+
+#[get("/is_auth/hello")]
+async fn hello() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+async fn openid(idp: &MockIdp) -> ActixWebOpenId {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .build()
+        .await
+        .unwrap()
+}
+
+fn client_credentials() -> TokenSource {
+    TokenSource::ClientCredentials(vec!["jobs".to_string()])
+}
+
+#[actix_web::test]
+async fn caches_client_credentials_tokens() {
+    let idp = MockIdp::start();
+    let provider = openid(&idp)
+        .await
+        .openid_client()
+        .token_provider(client_credentials());
+
+    let first = provider.get_token().await.unwrap();
+    let second = provider.clone().get_token().await.unwrap();
+
+    assert_eq!(first.secret(), second.secret());
+}
+
+#[actix_web::test]
+async fn renews_tokens_about_to_expire() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let provider = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap()
+        .openid_client()
+        .token_provider(client_credentials());
+
+    let first = provider.get_token().await.unwrap();
+    clock.advance(Duration::from_secs(3 * 60));
+    let second = provider.get_token().await.unwrap();
+    assert_eq!(first.secret(), second.secret());
+
+    // The mock provider's tokens expire after 5 minutes, they are renewed a minute before.
+    clock.advance(Duration::from_secs(90));
+    let third = provider.get_token().await.unwrap();
+    assert_ne!(second.secret(), third.secret());
+}
+
+#[actix_web::test]
+async fn follows_rotated_refresh_tokens() {
+    let idp = MockIdp::start();
+    idp.set_token_lifetime(Duration::from_secs(10));
+    let openid = openid(&idp).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(hello),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let refresh_token = RefreshToken::new(driver.cookie("refresh_token").unwrap());
+    let provider = openid
+        .openid_client()
+        .token_provider(TokenSource::RefreshToken(refresh_token));
+
+    // The provider invalidates each refresh token once used.
+    let first = provider.get_token().await.unwrap();
+    let second = provider.get_token().await.unwrap();
+
+    assert_ne!(first.secret(), second.secret());
+}
+
+#[actix_web::test]
+async fn retries_while_the_provider_is_unavailable() {
+    let idp = MockIdp::start();
+    let provider = openid(&idp)
+        .await
+        .openid_client()
+        .token_provider(client_credentials());
+    let mut status = provider.status();
+    idp.set_failure(Some(MockIdpFailure::ServerError));
+
+    let job = tokio::spawn({
+        let provider = provider.clone();
+        async move { provider.get_token().await }
+    });
+    status.changed().await.unwrap();
+    assert!(matches!(
+        *status.borrow_and_update(),
+        TokenStatus::Failing { attempts: 1, .. }
+    ));
+    idp.set_failure(None);
+
+    assert!(job.await.unwrap().is_ok());
+    assert_eq!(*status.borrow(), TokenStatus::Healthy);
+}
+
+#[actix_web::test]
+async fn fails_when_the_provider_refuses() {
+    let idp = MockIdp::start();
+    let provider = openid(&idp)
+        .await
+        .openid_client()
+        .token_provider(client_credentials());
+    idp.set_failure(Some(MockIdpFailure::TokenError {
+        status: 401,
+        error: "invalid_client",
+    }));
+
+    assert!(provider.get_token().await.is_err());
+    assert!(matches!(
+        *provider.status().borrow(),
+        TokenStatus::Failing { attempts: 1, .. }
+    ));
+}
@@ -0,0 +1,104 @@
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder, TokenTypeHint};
+use openidconnect::{AccessToken, EmptyAdditionalClaims, RefreshToken};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+async fn refreshes(openid: &ActixWebOpenId, refresh_token: String) -> bool {
+    openid
+        .openid_client()
+        .refresh(&RefreshToken::new(refresh_token))
+        .await
+        .is_ok()
+}
+
+async fn access_token_valid(openid: &ActixWebOpenId, access_token: String) -> bool {
+    openid
+        .openid_client()
+        .user_info::<EmptyAdditionalClaims>(AccessToken::new(access_token))
+        .await
+        .is_ok()
+}
+
+#[actix_web::test]
+async fn logouts_revoke_the_refresh_token() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let refresh_token = driver.cookie("refresh_token").unwrap();
+
+    let resp = driver.get("/logout").send().await;
+
+    assert_eq!(resp.status(), 302);
+    assert!(!refreshes(&openid, refresh_token).await);
+    assert!(access_token_valid(&openid, access_token).await);
+}
+
+#[actix_web::test]
+async fn logouts_can_revoke_the_access_token() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .revoke_access_token_on_logout(true)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    driver.get("/logout").send().await;
+
+    assert!(!access_token_valid(&openid, access_token).await);
+}
+
+#[actix_web::test]
+async fn providers_without_a_revocation_endpoint_are_not_asked() {
+    let idp = MockIdp::start();
+    idp.set_revocation_endpoint(false);
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let refresh_token = driver.cookie("refresh_token").unwrap();
+
+    let revoked = openid
+        .openid_client()
+        .revoke_token(&refresh_token, TokenTypeHint::RefreshToken)
+        .await
+        .unwrap();
+    let resp = driver.get("/logout").send().await;
+
+    assert!(!revoked);
+    assert_eq!(resp.status(), 302);
+    driver.assert_unauthenticated();
+    assert!(refreshes(&openid, refresh_token).await);
+}
+
+#[actix_web::test]
+async fn applications_revoke_tokens_themselves() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let refresh_token = driver.cookie("refresh_token").unwrap();
+
+    let revoked = openid
+        .openid_client()
+        .revoke_token(&refresh_token, TokenTypeHint::RefreshToken)
+        .await
+        .unwrap();
+
+    assert!(revoked);
+    assert!(!refreshes(&openid, refresh_token).await);
+}
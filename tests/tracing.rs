@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, CredentialChain, OpenIdBuilder};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::DefaultGuard;
+use tracing::{Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+mod mock_auth_api;
+
+#[derive(Debug)]
+struct Span {
+    metadata: &'static Metadata<'static>,
+    fields: HashMap<String, String>,
+}
+
+/// Keeps the spans and the event messages recorded while it is the thread's default subscriber,
+/// tracking the entered spans for `Span::current`.
+#[derive(Clone, Default)]
+struct Recorder {
+    next_id: Arc<AtomicU64>,
+    spans: Arc<Mutex<HashMap<u64, Span>>>,
+    entered: Arc<Mutex<Vec<u64>>>,
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+struct Fields<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut fields = HashMap::new();
+        span.record(&mut Fields(&mut fields));
+        self.spans.lock().unwrap().insert(
+            id,
+            Span {
+                metadata: span.metadata(),
+                fields,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut Fields(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = HashMap::new();
+        event.record(&mut Fields(&mut fields));
+        if let Some(message) = fields.remove("message") {
+            self.events.lock().unwrap().push(message);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut entered = self.entered.lock().unwrap();
+        if let Some(at) = entered.iter().rposition(|id| *id == span.into_u64()) {
+            entered.remove(at);
+        }
+    }
+
+    fn current_span(&self) -> Current {
+        let Some(id) = self.entered.lock().unwrap().last().copied() else {
+            return Current::none();
+        };
+        let metadata = self.spans.lock().unwrap()[&id].metadata;
+        Current::new(Id::from_u64(id), metadata)
+    }
+}
+
+impl Recorder {
+    fn start() -> (Recorder, DefaultGuard) {
+        let recorder = Recorder::default();
+        let guard = tracing::subscriber::set_default(recorder.clone());
+        (recorder, guard)
+    }
+
+    /// The fields of the spans named `name`, in the order they were created.
+    fn spans(&self, name: &str) -> Vec<HashMap<String, String>> {
+        let spans = self.spans.lock().unwrap();
+        let mut ids: Vec<_> = spans
+            .iter()
+            .filter(|(_, span)| span.metadata.name() == name)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids.iter().map(|id| spans[id].fields.clone()).collect()
+    }
+
+    fn events(&self) -> Vec<String> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+#[get("/me")]
+async fn me(user: Authenticated) -> HttpResponse {
+    HttpResponse::Ok().body(user.access.subject().as_str().to_string())
+}
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth") || req.path() == "/me")
+}
+
+#[actix_web::test]
+async fn requests_run_in_a_span_naming_the_credential_and_the_validation() {
+    let (recorder, _guard) = Recorder::start();
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    let spans = recorder.spans("openid_auth");
+    let last = spans.last().unwrap();
+    assert_eq!(last["path"], "/is_auth/hello");
+    assert_eq!(last["auth_source"], "cookie");
+    assert_eq!(last["validation"], "userinfo");
+    assert!(last.contains_key("realm"));
+}
+
+#[actix_web::test]
+async fn bearer_tokens_are_named_as_the_auth_source() {
+    let (recorder, _guard) = Recorder::start();
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid
+                    .get_middleware()
+                    .credentials(CredentialChain::bearer_only()),
+            )
+            .service(me),
+    )
+    .await;
+
+    let request = test::TestRequest::get()
+        .uri("/me")
+        .insert_header((AUTHORIZATION, format!("Bearer {}", access_token)));
+    let resp = test::call_service(&app, request.to_request()).await;
+
+    assert_eq!(resp.status(), 200);
+    let spans = recorder.spans("openid_auth");
+    let last = spans.last().unwrap();
+    assert_eq!(last["path"], "/me");
+    assert_eq!(last["auth_source"], "bearer");
+}
+
+#[actix_web::test]
+async fn provider_requests_record_their_status_and_latency() {
+    let (recorder, _guard) = Recorder::start();
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    driver.get("/is_auth/hello").follow_login().await;
+
+    let requests = recorder.spans("provider_request");
+    assert!(requests.len() >= 3, "{:?}", requests);
+    for request in &requests {
+        assert_eq!(request["status"], "200", "{:?}", request);
+        assert_eq!(request["attempts"], "1", "{:?}", request);
+        assert!(request.contains_key("latency_ms"), "{:?}", request);
+        assert!(
+            request["url"].starts_with(&idp.issuer_url()),
+            "{:?}",
+            request
+        );
+    }
+    assert!(requests.iter().any(|request| request["method"] == "POST"));
+}
+
+#[actix_web::test]
+async fn renewals_are_reported_as_events() {
+    let (recorder, _guard) = Recorder::start();
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    idp.revoke_access_tokens();
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 200);
+    let events = recorder.events();
+    assert!(
+        events
+            .iter()
+            .any(|event| event.starts_with("Renewing the session, its access token was rejected")),
+        "{:?}",
+        events
+    );
+    assert!(events
+        .iter()
+        .any(|event| event == "Renewed the session of alice"));
+}
+
+#[actix_web::test]
+async fn token_values_are_never_recorded() {
+    let (recorder, _guard) = Recorder::start();
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    idp.revoke_access_tokens();
+    driver.get("/is_auth/hello").send().await;
+
+    let tokens: Vec<String> = ["access_token", "refresh_token", "id_token"]
+        .iter()
+        .filter_map(|name| driver.cookie(name))
+        .collect();
+    assert!(!tokens.is_empty());
+    let spans = recorder.spans.lock().unwrap();
+    let recorded = spans
+        .values()
+        .flat_map(|span| span.fields.values().cloned())
+        .chain(recorder.events());
+    for value in recorded {
+        for token in &tokens {
+            assert!(!value.contains(token.as_str()), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, CookieConfig, OpenIdBuilder, TrustedProxies};
+use url::Url;
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+}
+
+/// The `redirect_uri` parameter of the authorization url.
+fn redirect_uri(authorization_url: &str) -> String {
+    Url::parse(authorization_url)
+        .unwrap()
+        .query_pairs()
+        .find(|(name, _)| name == "redirect_uri")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+#[actix_web::test]
+async fn logins_return_to_the_forwarded_host() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .trusted_proxies(TrustedProxies::Any)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", "app.example.com")
+        .send()
+        .await;
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "https://app.example.com/auth_callback"
+    );
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header(
+            "Forwarded",
+            "proto=http;host=evil.example.com, for=192.0.2.60;proto=https;host=\"portal.example.com:8443\"",
+        )
+        .send()
+        .await;
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "https://portal.example.com:8443/auth_callback"
+    );
+}
+
+#[actix_web::test]
+async fn entries_added_before_the_trusted_proxy_are_ignored() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .trusted_proxies(TrustedProxies::Any)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Proto", "http, https")
+        .header("X-Forwarded-Host", "evil.example.com, app.example.com")
+        .send()
+        .await;
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "https://app.example.com/auth_callback"
+    );
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("Forwarded", "proto=https;host=evil.example.com, proto=http")
+        .send()
+        .await;
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "http://localhost/auth_callback"
+    );
+}
+
+#[actix_web::test]
+async fn cookies_of_requests_forwarded_over_https_are_secure() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .trusted_proxies(TrustedProxies::Any)
+        .cookie_config(CookieConfig::default().secure(false))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert!(resp.cookies().all(|cookie| cookie.secure() != Some(true)));
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Proto", "https")
+        .send()
+        .await;
+    assert!(resp.cookies().count() > 0);
+    assert!(resp.cookies().all(|cookie| cookie.secure() == Some(true)));
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let idp_resp = client.get(resp.location().unwrap()).send().await.unwrap();
+    let callback = idp_resp.headers()["location"].to_str().unwrap().to_string();
+    let callback = callback
+        .strip_prefix("https://localhost")
+        .unwrap()
+        .to_string();
+    let resp = driver
+        .get(&callback)
+        .header("X-Forwarded-Proto", "https")
+        .send()
+        .await;
+
+    driver.assert_authenticated();
+    assert!(resp.cookies().count() > 0);
+    assert!(resp.cookies().all(|cookie| cookie.secure() == Some(true)));
+}
+
+#[actix_web::test]
+async fn forwarded_headers_are_ignored_by_default() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", "evil.example.com")
+        .send()
+        .await;
+
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "http://localhost/auth_callback"
+    );
+}
+
+#[actix_web::test]
+async fn untrusted_peers_and_smuggled_paths_are_ignored() {
+    let idp = MockIdp::start();
+    let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    let openid = builder(&idp)
+        .trusted_proxies(TrustedProxies::Peers(vec![proxy]))
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    // Test requests have no peer address, it is not the proxy's.
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Host", "app.example.com")
+        .send()
+        .await;
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "http://localhost/auth_callback"
+    );
+
+    let openid = builder(&idp)
+        .trusted_proxies(TrustedProxies::Any)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Host", "evil.example.com/steal?")
+        .send()
+        .await;
+    assert_eq!(
+        redirect_uri(resp.location().unwrap()),
+        "http://localhost/auth_callback"
+    );
+}
+
+#[actix_web::test]
+async fn the_code_is_exchanged_for_the_forwarded_redirect_url() {
+    let idp = MockIdp::start();
+    let openid = builder(&idp)
+        .trusted_proxies(TrustedProxies::Any)
+        .build()
+        .await
+        .unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    let resp = driver
+        .get("/is_auth/hello")
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", "app.example.com")
+        .send()
+        .await;
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let idp_resp = client.get(resp.location().unwrap()).send().await.unwrap();
+    let callback = idp_resp.headers()["location"].to_str().unwrap().to_string();
+    let callback = callback
+        .strip_prefix("https://app.example.com")
+        .unwrap()
+        .to_string();
+    let resp = driver
+        .get(&callback)
+        .header("X-Forwarded-Proto", "https")
+        .header("X-Forwarded-Host", "app.example.com")
+        .send()
+        .await;
+
+    assert_eq!(resp.status(), 302);
+    driver.assert_authenticated();
+    assert_eq!(
+        idp.token_requests().last().unwrap().redirect_uri.as_deref(),
+        Some("https://app.example.com/auth_callback")
+    );
+}
@@ -0,0 +1,97 @@
+use actix_web::test;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use actix_web_openidconnect::{ActixWebOpenId, UserInfoCookie};
+
+mod mock_auth_api;
+
+/// A user with claims too large for one cookie, like the long group lists of Azure AD.
+fn with_large_claims() -> AuthenticatedUserBuilder {
+    let mut seed: u64 = 42;
+    let profile: String = (0..400)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            format!("{:016x}", seed)
+        })
+        .collect();
+    AuthenticatedUserBuilder::new("alice")
+        .preferred_username("alice")
+        .claim("profile", profile)
+}
+
+async fn openid(idp: &MockIdp, user_info_cookie: Option<UserInfoCookie>) -> ActixWebOpenId {
+    let mut builder = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"));
+    if let Some(user_info_cookie) = user_info_cookie {
+        builder = builder.user_info_cookie(user_info_cookie);
+    }
+    builder.build().await.unwrap()
+}
+
+#[actix_web::test]
+async fn the_user_info_cookie_is_not_set_by_default() {
+    let idp = MockIdp::start();
+    idp.login_as(with_large_claims());
+    let openid = openid(&idp, None).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    assert!(driver
+        .cookies()
+        .iter()
+        .all(|cookie| !cookie.name().starts_with("user_info")));
+}
+
+#[actix_web::test]
+async fn large_user_infos_are_split_into_chunks() {
+    let idp = MockIdp::start();
+    idp.login_as(with_large_claims());
+    let openid = openid(&idp, Some(UserInfoCookie::Chunked)).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+
+    let resp = driver.get("/is_auth/hello").follow_login().await;
+
+    assert_eq!(resp.status(), 200);
+    let chunks: Vec<_> = driver
+        .cookies()
+        .into_iter()
+        .filter(|cookie| cookie.name().starts_with("user_info"))
+        .collect();
+    assert!(chunks.len() > 1, "{:?}", chunks);
+    assert!(chunks.iter().all(|chunk| chunk.value().len() < 4000));
+    assert!(driver.cookie("user_info").is_none());
+    let req = chunks
+        .into_iter()
+        .fold(test::TestRequest::get(), |req, chunk| req.cookie(chunk))
+        .to_http_request();
+    let user_info = openid.openid_client().cookie_user_info(&req).unwrap();
+    assert_eq!(user_info.get("sub").unwrap(), "alice");
+    assert_eq!(
+        user_info.get("profile").unwrap().as_str().unwrap().len(),
+        6400
+    );
+}
+
+#[actix_web::test]
+async fn logouts_remove_every_chunk() {
+    let idp = MockIdp::start();
+    idp.login_as(with_large_claims());
+    let openid = openid(&idp, Some(UserInfoCookie::Chunked)).await;
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    assert!(driver.cookie("user_info.1").is_some());
+
+    driver.get("/logout/local").send().await;
+
+    assert!(driver
+        .cookies()
+        .iter()
+        .all(|cookie| !cookie.name().starts_with("user_info")));
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::prelude::*;
+use actix_web_openidconnect::test_util::{AuthenticatedUserBuilder, FlowDriver, MockIdp};
+use serde_json::json;
+
+#[get("/me")]
+async fn me(user: Authenticated<OtherClaims>) -> HttpResponse {
+    HttpResponse::Ok().json(&*user.into_inner())
+}
+
+async fn logged_in_user(
+    idp: &MockIdp,
+) -> (
+    AuthenticatedUser<OtherClaims>,
+    FlowDriver<
+        impl actix_web::dev::Service<
+            actix_http::Request,
+            Response = actix_web::dev::ServiceResponse,
+            Error = actix_web::Error,
+        >,
+    >,
+) {
+    idp.login_as(
+        AuthenticatedUserBuilder::new("alice")
+            .email("alice@example.com")
+            .name("Alice Liddell")
+            .preferred_username("alice")
+            .role("admin")
+            .claim("sid", "session-1"),
+    );
+    let openid = ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() == "/me")
+        .build()
+        .await
+        .unwrap();
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(me),
+    )
+    .await;
+    let mut driver = FlowDriver::new(app, idp);
+    let resp = driver.get("/me").follow_login().await;
+    assert_eq!(resp.status(), 200);
+    (serde_json::from_slice(resp.body()).unwrap(), driver)
+}
+
+#[actix_web::test]
+async fn users_are_serialized_with_their_claims_and_tokens() {
+    let idp = MockIdp::start();
+    let (user, driver) = logged_in_user(&idp).await;
+
+    assert_eq!(user.subject(), "alice");
+    assert_eq!(user.email(), Some("alice@example.com"));
+    assert_eq!(user.name(), Some("Alice Liddell"));
+    assert_eq!(user.preferred_username(), Some("alice"));
+    assert_eq!(
+        user.access.additional_claims().get("roles"),
+        Some(&json!(["admin"]))
+    );
+    assert_eq!(
+        user.access_token().map(|token| token.secret().clone()),
+        driver.cookie("access_token")
+    );
+    assert_eq!(
+        user.id_token().map(|token| token.to_string()),
+        driver.cookie("id_token")
+    );
+    let id_token_claims = user.id_token_claims().unwrap();
+    assert_eq!(
+        id_token_claims.additional_claims().get("sid"),
+        Some(&json!("session-1"))
+    );
+    assert!(user.expires_at().is_some());
+}
+
+#[actix_web::test]
+async fn serialized_users_read_back_the_same() {
+    let idp = MockIdp::start();
+    let (user, _) = logged_in_user(&idp).await;
+
+    let serialized = serde_json::to_value(&user).unwrap();
+    let read: AuthenticatedUser<OtherClaims> = serde_json::from_value(serialized.clone()).unwrap();
+
+    assert_eq!(serde_json::to_value(&read).unwrap(), serialized);
+    assert_eq!(serialized["access"]["sub"], "alice");
+    // Read back with other claims types too.
+    let read: AuthenticatedUser = serde_json::from_value(serialized).unwrap();
+    assert_eq!(read.subject(), "alice");
+    assert_eq!(
+        read.access_token().map(|token| token.secret()),
+        user.access_token().map(|token| token.secret())
+    );
+}
+
+#[actix_web::test]
+async fn debug_output_leaves_the_tokens_out() {
+    let idp = MockIdp::start();
+    let (user, _) = logged_in_user(&idp).await;
+
+    let debug = format!("{:?}", user);
+
+    assert!(debug.contains("alice@example.com"), "{}", debug);
+    assert!(
+        !debug.contains(user.access_token().unwrap().secret()),
+        "{}",
+        debug
+    );
+    assert!(
+        !debug.contains(&user.id_token().unwrap().to_string()),
+        "{}",
+        debug
+    );
+
+    let tokens = OpenIDTokens {
+        access_token: user.access_token().unwrap().clone(),
+        id_token: user.id_token().unwrap().clone(),
+        refresh_token: Some(RefreshToken::new("refresh-secret".to_string())),
+        expires_in: Some(Duration::from_secs(300)),
+        refresh_expires_in: None,
+    };
+    let debug = format!("{:?}", tokens);
+    assert!(
+        !debug.contains(user.access_token().unwrap().secret()),
+        "{}",
+        debug
+    );
+    assert!(!debug.contains("refresh-secret"), "{}", debug);
+    let read: OpenIDTokens =
+        serde_json::from_str(&serde_json::to_string(&tokens).unwrap()).unwrap();
+    assert_eq!(read.refresh_token.unwrap().secret(), "refresh-secret");
+}
+
+#[actix_web::test]
+async fn users_without_tokens_are_serialized_without_them() {
+    let user: AuthenticatedUser = AuthenticatedUserBuilder::new("svc-reports")
+        .name("Reports")
+        .build();
+
+    let serialized = serde_json::to_value(&user).unwrap();
+
+    assert_eq!(
+        serialized,
+        json!({ "access": { "sub": "svc-reports", "name": "Reports" } })
+    );
+    let read: AuthenticatedUser = serde_json::from_value(serialized).unwrap();
+    assert_eq!(read.subject(), "svc-reports");
+    assert_eq!(read.name(), Some("Reports"));
+    assert_eq!(read.email(), None);
+    assert!(read.access_token().is_none());
+}
+
+#[actix_web::test]
+async fn localized_names_are_read_without_a_language_tag_first() {
+    let user: AuthenticatedUser = AuthenticatedUserBuilder::new("bob")
+        .claim("name#de", "Robert")
+        .build();
+    assert_eq!(user.name(), Some("Robert"));
+
+    let user: AuthenticatedUser = AuthenticatedUserBuilder::new("bob")
+        .claim("name#de", "Robert")
+        .name("Bob")
+        .build();
+    assert_eq!(user.name(), Some("Bob"));
+}
+
+#[actix_web::test]
+async fn users_expiring_past_the_clock_are_not_read() {
+    let idp = MockIdp::start();
+    let (user, _) = logged_in_user(&idp).await;
+    let mut serialized = serde_json::to_value(&user).unwrap();
+
+    serialized["expires_at"] = u64::MAX.into();
+    let err = serde_json::from_value::<AuthenticatedUser>(serialized).unwrap_err();
+
+    assert!(err.to_string().contains("out of range"), "{}", err);
+}
@@ -0,0 +1,147 @@
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{get, test, App, HttpResponse};
+use actix_web_openidconnect::openid_middleware::Authenticated;
+use actix_web_openidconnect::test_util::{
+    self, AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, BearerHeader, CredentialChain, SessionCookie};
+use serde_json::json;
+
+#[get("/tokens")]
+async fn user_tokens(user: Authenticated) -> HttpResponse {
+    let id_token_claims = user.id_token_claims();
+    HttpResponse::Ok().json(json!({
+        "access_token": user.access_token().map(|token| token.secret()),
+        "id_token": user.id_token().map(|token| token.to_string()),
+        "sid": id_token_claims.and_then(|claims| claims.additional_claims().get("sid")),
+        "issued_at": id_token_claims.map(|claims| claims.issue_time().timestamp()),
+        "expires_in": user.expires_at().map(|expires_at| {
+            expires_at
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+                .as_secs()
+        }),
+    }))
+}
+
+async fn openid(idp: &MockIdp, clock: &MockClock) -> ActixWebOpenId {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").claim("sid", "session-1"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path() != "/auth_callback")
+        .clock(clock.clone())
+        .build()
+        .await
+        .unwrap()
+}
+
+async fn body(resp: test_util::FlowResponse) -> serde_json::Value {
+    assert_eq!(resp.status(), 200);
+    serde_json::from_slice(resp.body()).unwrap()
+}
+
+#[actix_web::test]
+async fn handlers_get_the_session_tokens() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, &MockClock::new()).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(user_tokens),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+
+    let tokens = body(driver.get("/tokens").follow_login().await).await;
+
+    assert_eq!(
+        tokens["access_token"],
+        driver.cookie("access_token").unwrap()
+    );
+    assert_eq!(tokens["id_token"], driver.cookie("id_token").unwrap());
+    assert_eq!(tokens["sid"], "session-1");
+    assert!(tokens["issued_at"].is_i64());
+    assert!(tokens["expires_in"].as_u64().unwrap() > 0);
+}
+
+#[actix_web::test]
+async fn renewed_sessions_hand_out_the_new_tokens() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = openid(&idp, &clock).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(openid.get_middleware())
+            .configure(openid.configure_open_id())
+            .service(user_tokens),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    let before = body(driver.get("/tokens").follow_login().await).await;
+
+    clock.advance(Duration::from_secs(10 * 60));
+    let after = body(driver.get("/tokens").send().await).await;
+
+    assert_ne!(after["access_token"], before["access_token"]);
+    assert_eq!(
+        after["access_token"],
+        driver.cookie("access_token").unwrap()
+    );
+    assert_eq!(after["sid"], "session-1");
+}
+
+#[actix_web::test]
+async fn bearer_tokens_come_without_an_id_token() {
+    let idp = MockIdp::start();
+    let openid = openid(&idp, &MockClock::new()).await;
+    let app = test::init_service(
+        App::new()
+            .wrap(
+                openid.get_middleware().credentials(
+                    CredentialChain::new()
+                        .with(BearerHeader)
+                        .with(SessionCookie),
+                ),
+            )
+            .configure(openid.configure_open_id())
+            .service(user_tokens),
+    )
+    .await;
+    let mut driver = FlowDriver::new(&app, &idp);
+    driver.get("/tokens").follow_login().await;
+    let access_token = driver.cookie("access_token").unwrap();
+
+    let resp = test::call_service(
+        &app,
+        test::TestRequest::get()
+            .uri("/tokens")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", access_token)))
+            .to_request(),
+    )
+    .await;
+    let tokens: serde_json::Value = test::read_body_json(resp).await;
+
+    assert_eq!(tokens["access_token"], access_token);
+    assert!(tokens["id_token"].is_null());
+    assert!(tokens["sid"].is_null());
+}
+
+#[actix_web::test]
+async fn test_users_can_carry_an_access_token() {
+    let app = test::init_service(App::new().service(user_tokens)).await;
+    let req = test_util::authenticate_request(
+        test::TestRequest::get().uri("/tokens").to_request(),
+        AuthenticatedUserBuilder::new("bob").access_token("test-token"),
+    );
+
+    let tokens: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+    assert_eq!(tokens["access_token"], "test-token");
+    assert!(tokens["expires_in"].is_null());
+}
@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use actix_web::cookie::Cookie;
+use actix_web_openidconnect::test_util::{
+    AuthenticatedUserBuilder, FlowDriver, MockClock, MockIdp,
+};
+use actix_web_openidconnect::{ActixWebOpenId, OpenIdBuilder};
+
+mod mock_auth_api;
+
+fn builder(idp: &MockIdp, clock: &MockClock, max_entries: usize) -> OpenIdBuilder {
+    idp.login_as(AuthenticatedUserBuilder::new("alice").preferred_username("alice"));
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .should_auth(|req| req.path().starts_with("/is_auth"))
+        .clock(clock.clone())
+        .userinfo_cache(Duration::from_secs(60), max_entries)
+}
+
+/// Makes the provider reject the tokens issued so far.
+fn revoke_tokens(idp: &MockIdp) {
+    idp.revoke_access_tokens();
+    idp.revoke_refresh_tokens();
+}
+
+#[actix_web::test]
+async fn responses_are_reused_until_the_ttl_elapsed() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock, 100).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+
+    revoke_tokens(&idp);
+    assert_eq!(driver.get("/is_auth/hello").send().await.status(), 200);
+
+    clock.advance(Duration::from_secs(60));
+    let resp = driver.get("/is_auth/hello").send().await;
+    assert_eq!(resp.status(), 302);
+    assert!(resp.location().unwrap().starts_with(&idp.issuer_url()));
+}
+
+#[actix_web::test]
+async fn logouts_forget_the_cached_claims() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock, 100).build().await.unwrap();
+    let mut driver = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    driver.get("/is_auth/hello").follow_login().await;
+    let session: Vec<_> = ["access_token", "id_token", "refresh_token"]
+        .into_iter()
+        .map(|name| Cookie::new(name, driver.cookie(name).unwrap()))
+        .collect();
+
+    driver.get("/logout/local").send().await;
+    revoke_tokens(&idp);
+    for cookie in session {
+        driver.set_cookie(cookie);
+    }
+    let resp = driver.get("/is_auth/hello").send().await;
+
+    assert_eq!(resp.status(), 302);
+}
+
+#[actix_web::test]
+async fn the_oldest_entries_make_room() {
+    let idp = MockIdp::start();
+    let clock = MockClock::new();
+    let openid = builder(&idp, &clock, 1).build().await.unwrap();
+    let mut first = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    first.get("/is_auth/hello").follow_login().await;
+    clock.advance(Duration::from_secs(1));
+    let mut second = FlowDriver::new(mock_auth_api::get_mock_auth_api(&openid).await, &idp);
+    second.get("/is_auth/hello").follow_login().await;
+
+    revoke_tokens(&idp);
+
+    assert_eq!(second.get("/is_auth/hello").send().await.status(), 200);
+    assert_eq!(first.get("/is_auth/hello").send().await.status(), 302);
+}
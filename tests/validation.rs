@@ -0,0 +1,151 @@
+use actix_web_openidconnect::test_util::MockIdp;
+use actix_web_openidconnect::{ActixWebOpenId, CookieConfig, OpenIdBuilder, OpenIdError, Severity};
+
+fn builder(idp: &MockIdp) -> OpenIdBuilder {
+    ActixWebOpenId::builder()
+        .client_id("client")
+        .client_secret("secret")
+        .redirect_url("http://localhost/auth_callback")
+        .issuer_url(idp.issuer_url())
+        .scopes(["offline_access"])
+}
+
+fn build_error(result: Result<ActixWebOpenId, OpenIdError>) -> String {
+    match result {
+        Ok(_) => panic!("expected the build to fail"),
+        Err(err) => err.to_string(),
+    }
+}
+
+#[actix_web::test]
+async fn valid_configuration_has_no_issues() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp).strict(true).build().await.unwrap();
+
+    assert!(openid.validate().is_empty());
+}
+
+#[actix_web::test]
+async fn redirect_url_must_point_to_the_callback() {
+    let idp = MockIdp::start();
+
+    let err = build_error(
+        builder(&idp)
+            .redirect_url("http://localhost/callback")
+            .build()
+            .await,
+    );
+
+    assert!(
+        err.contains("does not point to the /auth_callback endpoint"),
+        "{}",
+        err
+    );
+}
+
+#[actix_web::test]
+async fn redirect_url_may_be_mounted_under_a_scope() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp)
+        .redirect_url("http://localhost/api/auth_callback")
+        .strict(true)
+        .build()
+        .await;
+
+    assert!(openid.is_ok());
+}
+
+#[actix_web::test]
+async fn http_redirect_url_loses_the_secure_cookies() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp)
+        .redirect_url("http://app.example.com/auth_callback")
+        .build()
+        .await
+        .unwrap();
+    let issues = openid.validate();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].severity, Severity::Warning);
+
+    let err = build_error(
+        builder(&idp)
+            .redirect_url("http://app.example.com/auth_callback")
+            .strict(true)
+            .build()
+            .await,
+    );
+    assert!(err.contains("uses http"), "{}", err);
+}
+
+#[actix_web::test]
+async fn http_redirect_url_is_fine_without_secure_cookies() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp)
+        .redirect_url("http://app.example.com/auth_callback")
+        .cookie_config(CookieConfig::default().secure(false))
+        .strict(true)
+        .build()
+        .await;
+
+    assert!(openid.is_ok());
+}
+
+#[actix_web::test]
+async fn missing_end_session_endpoint_is_a_warning() {
+    let idp = MockIdp::start();
+    idp.set_end_session_endpoint(false);
+
+    let openid = builder(&idp).build().await.unwrap();
+
+    let issues = openid.validate();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].severity, Severity::Warning);
+    assert!(issues[0].message.contains("no end_session_endpoint"));
+}
+
+#[actix_web::test]
+async fn unsupported_scope_is_a_warning() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp)
+        .scopes(["offline_access", "groups"])
+        .build()
+        .await
+        .unwrap();
+
+    let issues = openid.validate();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].severity, Severity::Warning);
+    assert!(issues[0].message.contains("scope groups"));
+}
+
+#[actix_web::test]
+async fn offline_access_left_unrequested_is_a_warning() {
+    let idp = MockIdp::start();
+
+    let openid = builder(&idp).scopes(["profile"]).build().await.unwrap();
+
+    let issues = openid.validate();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].severity, Severity::Warning);
+    assert!(issues[0].message.contains("offline_access"));
+}
+
+#[actix_web::test]
+async fn strict_mode_fails_on_warnings() {
+    let idp = MockIdp::start();
+
+    let err = build_error(
+        builder(&idp)
+            .scopes(["offline_access", "groups"])
+            .strict(true)
+            .build()
+            .await,
+    );
+
+    assert!(err.contains("warning: scope groups"), "{}", err);
+}